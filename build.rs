@@ -0,0 +1,9 @@
+fn main() {
+    #[cfg(feature = "grpc")]
+    {
+        println!("cargo:rerun-if-changed=proto/rustaigent.proto");
+        // protox compiles the contract in Rust, so building needs no protoc
+        let descriptors = protox::compile(["proto/rustaigent.proto"], ["proto"]).expect("invalid proto/rustaigent.proto");
+        tonic_prost_build::configure().build_client(false).compile_fds(descriptors).expect("gRPC code generation failed");
+    }
+}
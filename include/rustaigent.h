@@ -0,0 +1,44 @@
+/*
+ * C API of the RustAIgent library. Build with
+ *   cargo rustc --release --lib --features ffi --crate-type cdylib
+ * and link against target/release/librustaigent.so (.dylib, .dll).
+ *
+ * Strings are NUL-terminated UTF-8. Results are JSON strings owned by the caller and freed with
+ * rustaigent_free_string. A handle may be used from several threads: one thread blocks in
+ * rustaigent_send_message_json while another polls events and replies to prompts.
+ */
+#ifndef RUSTAIGENT_H
+#define RUSTAIGENT_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct RustAigent RustAigent;
+
+/* Settings as JSON: provider, model, api_key, system_prompt, events (bool), yolo (bool); NULL or
+ * "" for defaults. Returns NULL on failure and stores the reason in *error when error is not NULL. */
+RustAigent *rustaigent_create_agent(const char *settings_json, char **error);
+
+/* Run one turn; returns {"answer": "..."}, {"answer": null} when held back, or {"error": "..."}. */
+char *rustaigent_send_message_json(const RustAigent *agent, const char *message);
+
+/* The next event as JSON, waiting up to timeout_ms; NULL when none arrived or events are off. */
+char *rustaigent_poll_event(const RustAigent *agent, uint32_t timeout_ms);
+
+/* {"type": "approval", "id": 1, "approve": true} or {"type": "answer", "id": 2, "text": "..."};
+ * returns {} or {"error": "..."}. */
+char *rustaigent_reply(const RustAigent *agent, const char *reply_json);
+
+void rustaigent_free_string(char *text);
+
+/* Ends the session; prompts still waiting are declined. */
+void rustaigent_free_agent(RustAigent *agent);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
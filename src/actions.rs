@@ -0,0 +1,128 @@
+//! What a turn did, for wrappers that should not have to reconstruct it from prose: the files its
+//! tool calls changed with their hashes afterwards, the commands they ran with exit codes, and
+//! the turn's cost. After each turn that changed something the summary is appended to the
+//! conversation as a compact JSON note, and `-p --json` reports it as `actions`.
+
+use std::{fs, path::Path};
+use serde::Serialize;
+use crate::session;
+
+/// `name` of the system messages carrying a turn's summary
+pub const NOTE: &str = "turn_actions";
+
+/// A file a tool call wrote, patched, deleted or otherwise changed
+#[derive(Serialize, Clone)]
+pub struct FileChange {
+    pub path: String,
+    /// Tool of the last call that changed it
+    pub tool: String,
+    /// SHA-256 of the content at the end of the turn; `None` once deleted or for a directory
+    pub sha256: Option<String>,
+}
+
+/// A command a tool call ran
+#[derive(Serialize, Clone)]
+pub struct CommandRun {
+    pub command: String,
+    /// `None` when the tool does not report one, or the command was killed
+    pub exit_code: Option<i32>,
+    pub ok: bool,
+}
+
+/// Summary of one turn's side effects
+#[derive(Serialize, Clone)]
+pub struct Actions {
+    pub files: Vec<FileChange>,
+    pub commands: Vec<CommandRun>,
+    /// Calls of the turn, read-only ones included
+    pub tool_calls: usize,
+    pub failed_calls: usize,
+    pub cost_usd: f64,
+}
+
+/// The exit code `run_command` and `eval_code` start their output with
+fn exit_code(output: &str) -> Option<i32> {
+    output.strip_prefix("Exit code ")?.split_whitespace().next()?.parse().ok()
+}
+
+/// Summary of `calls`, made in `dir` in one turn that cost `cost_usd`; `None` when none of them
+/// changed anything or ran a command that might have
+pub fn summarize(calls: &[session::ToolCall], dir: &Path, package: Option<&str>, cost_usd: f64) -> Option<Actions> {
+    let mut files: Vec<FileChange> = Vec::new();
+    let mut commands = Vec::new();
+    for entry in calls {
+        let sim = entry.call.simulate(dir, package);
+        if sim.read_only {
+            continue;
+        }
+        // The reported exit code is the last command's, as with `eval_code` building then running
+        let last = sim.commands.len().saturating_sub(1);
+        for (i, command) in sim.commands.into_iter().enumerate() {
+            let exit_code = if i == last { exit_code(&entry.output) } else { None };
+            commands.push(CommandRun { command, exit_code, ok: entry.ok });
+        }
+        if !entry.ok {
+            continue;
+        }
+        for path in sim.files {
+            files.retain(|f| f.path != path);
+            files.push(FileChange { path, tool: entry.call.name().into(), sha256: None });
+        }
+    }
+    if files.is_empty() && commands.is_empty() {
+        return None;
+    }
+    for file in &mut files {
+        let path = dir.join(&file.path);
+        file.sha256 = fs::metadata(&path).ok().filter(|m| m.is_file())
+            .and_then(|_| fs::read(&path).ok())
+            .map(|bytes| session::sha256_hex(&bytes));
+    }
+    Some(Actions {
+        files,
+        commands,
+        tool_calls: calls.len(),
+        failed_calls: calls.iter().filter(|c| !c.ok).count(),
+        cost_usd,
+    })
+}
+
+impl Actions {
+    /// Content of the conversation note
+    pub fn note(&self) -> String {
+        format!("Actions taken this turn: {}", serde_json::to_string(self).unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use crate::mock::MockProvider;
+    use super::NOTE;
+
+    #[tokio::test]
+    async fn turns_end_with_a_summary_of_their_actions() {
+        let mock = MockProvider::start().await;
+        let dir = std::env::temp_dir().join(format!("rustaigent-actions-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        mock.tool_call("write_file", json!({"path": "@out/out.txt", "content": "hello\n"})).await;
+        mock.tool_call("run_command", json!({"command": "exit 3"})).await;
+        mock.reply("done").await;
+        let mut agent = mock.tool_agent();
+        agent.enable_yolo();
+        agent.add_root("out", &dir).unwrap();
+        agent.turn("Write the file").await.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let actions = agent.last_actions().unwrap();
+        assert_eq!(actions.files.len(), 1);
+        assert!(actions.files[0].path.ends_with("out.txt"));
+        assert_eq!(actions.files[0].sha256.as_deref(), Some(crate::session::sha256_hex(b"hello\n").as_str()));
+        assert_eq!(actions.commands[0].exit_code, Some(3));
+        assert_eq!((actions.tool_calls, actions.failed_calls), (2, 0));
+        let note = agent.conversation.iter().last().unwrap();
+        assert_eq!(note.name.as_deref(), Some(NOTE));
+        assert!(note.content.contains("\"exit_code\":3"));
+        agent.turn("Thanks").await.unwrap();
+        assert!(agent.last_actions().is_none());
+    }
+}
@@ -0,0 +1,75 @@
+//! Model names that no longer work as written. Providers answer a retired model with a bare 404,
+//! so names from old configs and scripts are looked up here first: `[model_aliases]` from the
+//! config, then the built-in table of retired models with their successors. A retired model is
+//! replaced, with a warning naming the new one; one retiring later only draws the warning.
+
+use std::collections::BTreeMap;
+use crate::capabilities;
+
+/// A model leaving a provider's API
+struct Retirement {
+    provider: &'static str,
+    /// Name prefix, so dated snapshots of the model match too
+    prefix: &'static str,
+    successor: &'static str,
+    /// Day the provider stops serving it
+    date: &'static str,
+}
+
+const fn retires(provider: &'static str, prefix: &'static str, successor: &'static str, date: &'static str) -> Retirement {
+    Retirement { provider, prefix, successor, date }
+}
+
+/// Known retirements; the first match wins, so specific prefixes come first
+const RETIRED: &[Retirement] = &[
+    retires("openai", "text-davinci-", "gpt-4o-mini", "2024-01-04"),
+    retires("openai", "gpt-3.5-turbo-0613", "gpt-4o-mini", "2024-09-13"),
+    retires("openai", "gpt-4-vision-preview", "gpt-4o", "2024-12-06"),
+    retires("openai", "gpt-4-32k", "gpt-4o", "2025-06-06"),
+    retires("openai", "gpt-4.5-preview", "gpt-4.1", "2025-07-14"),
+    retires("openai", "o1-preview", "o3", "2025-07-28"),
+    retires("openai", "o1-mini", "o4-mini", "2025-10-27"),
+    retires("claude", "claude-instant-1", "claude-haiku-4-5", "2024-11-06"),
+    retires("claude", "claude-2", "claude-sonnet-4-5", "2025-07-21"),
+    retires("claude", "claude-3-sonnet-20240229", "claude-sonnet-4-5", "2025-07-21"),
+    retires("claude", "claude-3-5-sonnet", "claude-sonnet-4-5", "2025-10-22"),
+    retires("claude", "claude-3-opus", "claude-opus-4-1", "2026-01-05"),
+    retires("google", "chat-bison-001", "gemini-2.5-flash", "2024-10-09"),
+    retires("google", "text-bison-001", "gemini-2.5-flash", "2024-10-09"),
+    retires("google", "gemini-pro", "gemini-2.5-flash", "2025-04-09"),
+    retires("google", "gemini-1.0-pro", "gemini-2.5-flash", "2025-04-09"),
+    retires("google", "gemini-1.5-pro", "gemini-2.5-pro", "2025-09-24"),
+    retires("google", "gemini-1.5-flash", "gemini-2.5-flash", "2025-09-24"),
+];
+
+/// Whether a table entry for `provider` covers `backend`; Vertex AI serves Google's models
+fn covers(provider: &str, backend: &str) -> bool {
+    provider == backend || (provider == "google" && backend == "vertex")
+}
+
+/// What `model` stands for: its `[model_aliases]` target, else the successor of a model
+/// `provider` has retired, unless `migrate` is off
+pub fn resolve(provider: &str, model: &str, aliases: Option<&BTreeMap<String, String>>, migrate: bool) -> String {
+    let model = aliases.and_then(|a| a.get(model)).map_or(model, String::as_str);
+    let Some(entry) = RETIRED.iter().find(|r| covers(r.provider, provider) && model.starts_with(r.prefix)) else {
+        return model.to_string();
+    };
+    let today = chrono::Utc::now().format("%Y-%m-%d").to_string();
+    if today.as_str() < entry.date {
+        capabilities::warn_once(format!("{} is deprecated and {} retires it on {}; switch to {}", model, provider, entry.date, entry.successor));
+        return model.to_string();
+    }
+    if !migrate {
+        capabilities::warn_once(format!("{} was retired on {} and requests for it will fail; switch to {}", model, entry.date, entry.successor));
+        return model.to_string();
+    }
+    capabilities::warn_once(format!("{} was retired on {}; using {} instead (set `model` to it to silence this)", model, entry.date, entry.successor));
+    entry.successor.to_string()
+}
+
+/// The retirement notice for `model`, for config validation and 404s: `None` for models in service
+pub fn notice(provider: &str, model: &str) -> Option<String> {
+    let entry = RETIRED.iter().find(|r| covers(r.provider, provider) && model.starts_with(r.prefix))?;
+    let retires = if chrono::Utc::now().format("%Y-%m-%d").to_string().as_str() < entry.date { "retires" } else { "retired" };
+    Some(format!("{} {} {} on {}; its successor is {}", provider, retires, model, entry.date, entry.successor))
+}
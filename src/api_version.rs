@@ -0,0 +1,44 @@
+use std::env;
+
+/// API revisions pinned per provider; each can be overridden from the environment
+#[derive(Clone)]
+pub struct ApiVersions {
+    /// Path segment for the OpenAI REST API (`OPENAI_API_VERSION`)
+    pub openai: String,
+    /// Value of the `anthropic-version` header (`ANTHROPIC_VERSION`)
+    pub anthropic: String,
+    /// Path segment for the Generative Language API (`GOOGLE_API_VERSION`)
+    pub google: String,
+}
+
+impl Default for ApiVersions {
+    fn default() -> Self {
+        ApiVersions { openai: "v1".into(), anthropic: "2023-06-01".into(), google: "v1beta".into() }
+    }
+}
+
+impl ApiVersions {
+    pub fn from_env() -> Self {
+        let defaults = ApiVersions::default();
+        ApiVersions {
+            openai: env::var("OPENAI_API_VERSION").unwrap_or(defaults.openai),
+            anthropic: env::var("ANTHROPIC_VERSION").unwrap_or(defaults.anthropic),
+            google: env::var("GOOGLE_API_VERSION").unwrap_or(defaults.google),
+        }
+    }
+
+    /// The pinned version for a provider, for error messages
+    pub fn for_provider(&self, provider: &str) -> String {
+        match provider {
+            "claude" => format!("anthropic-version {}", self.anthropic),
+            "google" => format!("Generative Language API {}", self.google),
+            "vertex" => "Vertex AI v1".into(),
+            "huggingface" => "Hugging Face Messages API v1".into(),
+            "xai" => "xAI API v1".into(),
+            "deepseek" => "DeepSeek API".into(),
+            "ollama" => "Ollama /api/chat".into(),
+            _ => format!("OpenAI API {}", self.openai),
+        }
+    }
+
+}
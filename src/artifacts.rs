@@ -0,0 +1,114 @@
+//! Large tool outputs (build logs, fetched pages, datasets) kept out of the conversation. Each is
+//! stored once under its content digest; the model sees its start and end and pages through the
+//! rest with `read_artifact`. Artifacts no saved session refers to are collected after a grace
+//! period.
+
+use std::{collections::HashSet, fs, path::PathBuf, sync::Once, time::{Duration, SystemTime}};
+use anyhow::{Result, Context, bail};
+use tracing::{debug, warn};
+use crate::{paths, session, vault};
+
+/// Output size in bytes above which a tool result is stored when `artifact_threshold` is unset
+pub const DEFAULT_THRESHOLD: usize = 32 * 1024;
+
+/// Bytes of the start, and of the end, shown in place of a stored output
+const PREVIEW: usize = 2 * 1024;
+
+/// Most bytes one `read_artifact` call returns
+pub const MAX_READ: usize = 16 * 1024;
+
+/// Unreferenced artifacts are kept this long after their last use, for sessions still running
+const GRACE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+fn dir() -> PathBuf {
+    paths::data_dir().join("artifacts")
+}
+
+/// Store `content`, sealed when `encryption` is on, and return its id: the start of its SHA-256
+pub fn store(content: &str) -> Result<String> {
+    static COLLECT: Once = Once::new();
+    COLLECT.call_once(|| {
+        std::thread::spawn(|| match collect() {
+            Ok(removed) => debug!("collected {} unreferenced artifact(s)", removed),
+            Err(err) => warn!("keeping unreferenced artifacts: {:#}", err),
+        });
+    });
+    let id = session::sha256_hex(content.as_bytes())[..16].to_string();
+    let path = dir().join(&id);
+    if path.is_file() {
+        // Stored earlier; in use again, so the grace period starts over
+        fs::File::options().append(true).open(&path).and_then(|f| f.set_modified(SystemTime::now())).ok();
+    } else {
+        fs::create_dir_all(dir())?;
+        vault::write(&path, content.as_bytes().to_vec())?;
+    }
+    Ok(id)
+}
+
+/// What the model gets instead of a stored output: its size, start and end
+pub fn preview(id: &str, content: &str) -> String {
+    let head = &content[..content.floor_char_boundary(PREVIEW)];
+    let tail_from = content.ceil_char_boundary(content.len().saturating_sub(PREVIEW).max(head.len()));
+    let mut out = format!(
+        "[artifact {}: {} bytes, {} lines; the middle is left out here, read it with read_artifact(id, offset, len)]\n{}",
+        id, content.len(), content.lines().count(), head,
+    );
+    if tail_from > head.len() {
+        out.push_str(&format!("\n… bytes {}..{} left out …\n", head.len(), tail_from));
+    }
+    out.push_str(&content[tail_from..]);
+    out
+}
+
+/// Up to `len` bytes of an artifact from `offset`, widened to whole characters
+pub fn read(id: &str, offset: usize, len: usize) -> Result<String> {
+    if id.len() != 16 || !id.bytes().all(|b| b.is_ascii_hexdigit()) {
+        bail!("`{}` is not an artifact id; use the 16 hex digits after `[artifact`", id);
+    }
+    let Some(bytes) = vault::read(&dir().join(id))? else {
+        bail!("Artifact {} is no longer stored", id);
+    };
+    let content = String::from_utf8(bytes).with_context(|| format!("Artifact {} is not text", id))?;
+    if offset >= content.len() && offset > 0 {
+        bail!("Offset {} is past the end of artifact {} ({} bytes)", offset, id, content.len());
+    }
+    let start = content.floor_char_boundary(offset);
+    let end = content.ceil_char_boundary(offset.saturating_add(len.min(MAX_READ)).min(content.len()));
+    Ok(format!("[artifact {}, bytes {}..{} of {}]\n{}", id, start, end, content.len(), &content[start..end]))
+}
+
+/// Delete artifacts unused for the grace period that no saved session mentions; returns how many
+pub fn collect() -> Result<usize> {
+    let Ok(entries) = fs::read_dir(dir()) else { return Ok(0) };
+    let now = SystemTime::now();
+    let stale: Vec<PathBuf> = entries.filter_map(|e| e.ok()).map(|e| e.path())
+        .filter(|p| fs::metadata(p).and_then(|m| m.modified()).is_ok_and(|t| now.duration_since(t).is_ok_and(|age| age > GRACE)))
+        .collect();
+    if stale.is_empty() {
+        return Ok(0);
+    }
+    let mut referenced = HashSet::new();
+    let sessions = fs::read_dir(paths::data_dir().join("sessions")).into_iter().flatten().filter_map(|e| e.ok()).map(|e| e.path());
+    for path in sessions.filter(|p| p.to_string_lossy().ends_with(".json.zst")) {
+        // One unreadable session must not stop collection for good; its artifacts may go
+        let bundle = match session::Bundle::load(&path) {
+            Ok(bundle) => bundle,
+            Err(err) => {
+                warn!("skipping {} while collecting artifacts: {:#}", path.display(), err);
+                continue;
+            }
+        };
+        for message in &bundle.messages {
+            for id in message.content.match_indices("[artifact ").filter_map(|(at, _)| message.content.get(at + 10..at + 26)) {
+                referenced.insert(id.to_string());
+            }
+        }
+    }
+    let mut removed = 0;
+    for path in stale {
+        if path.file_name().is_some_and(|name| !referenced.contains(&*name.to_string_lossy())) && fs::remove_file(&path).is_ok() {
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
@@ -0,0 +1,210 @@
+//! Credentials minted per request, for gateways that take more than a static bearer key: a
+//! command that prints a short-lived token (from a metadata service, `vault` or `gcloud`),
+//! cached until it expires, and HMAC signatures over each request. `[auth.<provider>]` sets them
+//! up from the config; applications embedding the agent can supply their own [`Authenticator`].
+
+use std::{collections::BTreeMap, env, sync::Arc, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
+use futures::future::BoxFuture;
+use hmac::{Hmac, KeyInit, Mac};
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use anyhow::{Result, Context, bail};
+
+/// Supplies the credential headers of each request to the provider. They replace headers of the
+/// same name, the static key's `Authorization` included, and are fetched again on every retry.
+pub trait Authenticator: Send + Sync {
+    /// Headers for `request`, whose method, URL and body are final
+    fn headers<'a>(&'a self, request: &'a reqwest::Request) -> BoxFuture<'a, Result<Vec<(String, String)>>>;
+
+    /// The provider answered 401: forget cached credentials, since the request is sent once more
+    fn invalidate(&self) {}
+}
+
+/// One `[auth.<provider>]` table
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct AuthConfig {
+    /// Shell command printing a token, or JSON `{"token": ..., "expires_in": secs}` or
+    /// `{"headers": {...}, "expires_in": secs}`
+    pub command: Option<String>,
+    /// Header carrying the command's token (default `Authorization`)
+    pub header: Option<String>,
+    /// Put before the token (default `Bearer`; empty for none)
+    pub scheme: Option<String>,
+    /// Seconds a token is reused when the command gives no `expires_in` (default 300)
+    pub refresh_secs: Option<u64>,
+    /// Variable holding the secret that signs each request with HMAC-SHA256
+    pub hmac_secret_env: Option<String>,
+    /// Sent as `X-Key-Id` next to the signature
+    pub hmac_key_id: Option<String>,
+    /// Header carrying the signature (default `X-Signature`)
+    pub hmac_header: Option<String>,
+}
+
+/// Keys accepted in an `[auth.<provider>]` table, for config validation
+pub const KEYS: &[&str] = &["command", "header", "scheme", "refresh_secs", "hmac_secret_env", "hmac_key_id", "hmac_header"];
+
+/// Per-provider tables
+pub type Auth = BTreeMap<String, AuthConfig>;
+
+/// Tokens are fetched again this long before they expire, so none runs out in flight
+const MARGIN: Duration = Duration::from_secs(30);
+
+impl AuthConfig {
+    /// Errors in a table that would fail every request
+    pub fn check(&self) -> Result<()> {
+        if self.command.is_none() && self.hmac_secret_env.is_none() {
+            bail!("set `command` for tokens, `hmac_secret_env` for signatures, or both");
+        }
+        Ok(())
+    }
+}
+
+/// Headers from the command, with when to fetch them again
+struct Token {
+    headers: Vec<(String, String)>,
+    until: Instant,
+}
+
+#[derive(Deserialize)]
+struct Minted {
+    token: Option<String>,
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    expires_in: Option<u64>,
+}
+
+/// [`Authenticator`] for an `[auth.<provider>]` table
+pub struct Configured {
+    cfg: AuthConfig,
+    token: tokio::sync::Mutex<Option<Token>>,
+}
+
+impl Configured {
+    pub fn new(cfg: AuthConfig) -> Self {
+        Configured { cfg, token: tokio::sync::Mutex::new(None) }
+    }
+
+    /// Run the command, or reuse what it printed last while that is still valid
+    async fn token(&self, command: &str) -> Result<Vec<(String, String)>> {
+        let mut cached = self.token.lock().await;
+        if let Some(token) = cached.as_ref().filter(|t| Instant::now() < t.until) {
+            return Ok(token.headers.clone());
+        }
+        let output = tokio::process::Command::new("sh").arg("-c").arg(command)
+            .stdin(std::process::Stdio::null())
+            .kill_on_drop(true)
+            .output().await
+            .with_context(|| format!("Failed to run the auth command `{}`", command))?;
+        if !output.status.success() {
+            bail!("The auth command `{}` failed ({}): {}", command, output.status, String::from_utf8_lossy(&output.stderr).trim());
+        }
+        let text = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        let minted = match text.starts_with('{') {
+            true => serde_json::from_str(&text).with_context(|| format!("The auth command `{}` printed JSON without `token` or `headers`", command))?,
+            false => Minted { token: Some(text), headers: BTreeMap::new(), expires_in: None },
+        };
+        let mut headers: Vec<(String, String)> = minted.headers.into_iter().collect();
+        if let Some(token) = minted.token.filter(|t| !t.is_empty()) {
+            let scheme = self.cfg.scheme.as_deref().unwrap_or("Bearer");
+            let value = if scheme.is_empty() { token } else { format!("{} {}", scheme, token) };
+            headers.push((self.cfg.header.clone().unwrap_or_else(|| "Authorization".into()), value));
+        }
+        if headers.is_empty() {
+            bail!("The auth command `{}` printed no token", command);
+        }
+        let lifetime = minted.expires_in.map_or(Duration::from_secs(self.cfg.refresh_secs.unwrap_or(300)), |secs| Duration::from_secs(secs).saturating_sub(MARGIN));
+        *cached = Some(Token { headers: headers.clone(), until: Instant::now() + lifetime });
+        Ok(headers)
+    }
+
+    /// `X-Timestamp`, the signature over method, path, timestamp and body hash, and `X-Key-Id`
+    fn sign(&self, var: &str, request: &reqwest::Request) -> Result<Vec<(String, String)>> {
+        let secret = env::var(var).with_context(|| format!("Set {} to the secret that signs requests", var))?;
+        let stamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0).to_string();
+        let body = request.body().and_then(|b| b.as_bytes()).unwrap_or_default();
+        let url = request.url();
+        let path = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        };
+        let payload = format!("{}\n{}\n{}\n{}", request.method(), path, stamp, hex(&Sha256::digest(body)));
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any length");
+        mac.update(payload.as_bytes());
+        let mut headers = vec![
+            ("X-Timestamp".to_string(), stamp),
+            (self.cfg.hmac_header.clone().unwrap_or_else(|| "X-Signature".into()), hex(&mac.finalize().into_bytes())),
+        ];
+        if let Some(id) = &self.cfg.hmac_key_id {
+            headers.push(("X-Key-Id".into(), id.clone()));
+        }
+        Ok(headers)
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+impl Authenticator for Configured {
+    fn headers<'a>(&'a self, request: &'a reqwest::Request) -> BoxFuture<'a, Result<Vec<(String, String)>>> {
+        Box::pin(async move {
+            let mut headers = match &self.cfg.command {
+                Some(command) => self.token(command).await?,
+                None => Vec::new(),
+            };
+            if let Some(var) = &self.cfg.hmac_secret_env {
+                headers.extend(self.sign(var, request)?);
+            }
+            Ok(headers)
+        })
+    }
+
+    fn invalidate(&self) {
+        if let Ok(mut token) = self.token.try_lock() {
+            *token = None;
+        }
+    }
+}
+
+/// The authenticator `[auth.<provider>]` sets up, else for Vertex AI the Application Default
+/// Credentials; `None` for providers that take their static key
+pub fn for_provider(config: &crate::config::Config, provider: &str, client: &reqwest::Client) -> Option<Arc<dyn Authenticator>> {
+    match config.auth.as_ref().and_then(|auth| auth.get(provider)) {
+        Some(cfg) => Some(Arc::new(Configured::new(cfg.clone()))),
+        None if provider == "vertex" => Some(Arc::new(crate::gcp::Adc::new(client.clone(), config.vertex.clone().unwrap_or_default()))),
+        None => None,
+    }
+}
+
+/// Set `auth`'s headers on `request`, replacing those of the same name
+pub async fn apply(auth: &dyn Authenticator, request: &mut reqwest::Request) -> Result<()> {
+    for (name, value) in auth.headers(request).await? {
+        let name = reqwest::header::HeaderName::from_bytes(name.as_bytes()).with_context(|| format!("`{}` is not a header name", name))?;
+        let value = reqwest::header::HeaderValue::from_str(&value).with_context(|| format!("The {} header holds characters headers cannot carry", name))?;
+        request.headers_mut().insert(name, value);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use crate::{mock::MockProvider, turns::Turn};
+    use super::{AuthConfig, Configured};
+
+    #[tokio::test]
+    async fn minted_credentials_are_renewed_after_a_401() {
+        let mock = MockProvider::start().await;
+        mock.unauthorized().await;
+        mock.reply("pong").await;
+        let mut agent = mock.agent_with_keys("");
+        let cfg = AuthConfig { command: Some(r#"echo "{\"token\": \"t$$\", \"expires_in\": 900}""#.into()), ..Default::default() };
+        agent.auth = Some(Arc::new(Configured::new(cfg)));
+        let reply = agent.send_request(Turn::Tools, None).await.unwrap();
+        assert_eq!(reply.content, "pong");
+        let sent = mock.headers("authorization").await;
+        assert_eq!(sent.len(), 2);
+        assert!(sent.iter().all(|h| h.starts_with("Bearer t")));
+        assert_ne!(sent[0], sent[1]);
+    }
+}
@@ -0,0 +1,141 @@
+use std::{path::PathBuf, sync::{Arc, Mutex}};
+use anyhow::Result;
+use crate::{cancel, history::History, paths, session, stats::{self, SessionStats}};
+
+/// Last known state of the interactive session, refreshed after every turn and tool call
+pub struct Checkpoint {
+    pub provider: String,
+    pub model: Option<String>,
+    pub config_digest: String,
+    pub history: History,
+    pub tool_log: Vec<session::ToolCall>,
+    pub plan: Option<crate::plan::Plan>,
+    pub stats: Arc<Mutex<SessionStats>>,
+}
+
+static CHECKPOINT: Mutex<Option<Checkpoint>> = Mutex::new(None);
+
+pub fn update(checkpoint: Checkpoint) {
+    if let Ok(mut slot) = CHECKPOINT.lock() {
+        *slot = Some(checkpoint);
+    }
+}
+
+/// Write the checkpoint as a compressed bundle and append the usage stats; returns the bundle path
+fn flush() -> Result<Option<PathBuf>> {
+    // A panic may hit while the slot is being updated; never block on it
+    let Ok(slot) = CHECKPOINT.try_lock() else { return Ok(None) };
+    let Some(cp) = slot.as_ref() else { return Ok(None) };
+    let id = match cp.stats.try_lock() {
+        Ok(mut stats) => {
+            stats.save()?;
+            stats.id.clone()
+        }
+        Err(_) => format!("{:x}-{:x}", stats::now_secs(), std::process::id()),
+    };
+    write_bundle(cp, &id)
+}
+
+/// Write the checkpoint as a compressed bundle without touching the usage stats, for a normal exit
+pub fn save() -> Result<Option<PathBuf>> {
+    let slot = CHECKPOINT.lock().map_err(|_| anyhow::anyhow!("The session checkpoint is poisoned"))?;
+    let Some(cp) = slot.as_ref() else { return Ok(None) };
+    let id = cp.stats.lock().unwrap().id.clone();
+    write_bundle(cp, &id)
+}
+
+/// Where sessions are saved
+fn dir() -> PathBuf {
+    paths::data_dir().join("sessions")
+}
+
+/// Saved sessions, newest first; bundles that cannot be read are left out
+pub fn list() -> Vec<(PathBuf, session::Bundle)> {
+    let Ok(entries) = std::fs::read_dir(dir()) else { return Vec::new() };
+    let mut saved: Vec<_> = entries.flatten()
+        .map(|entry| entry.path())
+        // Plans are kept next to the bundles
+        .filter(|path| path.to_string_lossy().contains(".json"))
+        .filter_map(|path| match session::Bundle::load(&path) {
+            Ok(bundle) => Some((path, bundle)),
+            Err(err) => {
+                tracing::debug!("skipping {}: {:#}", path.display(), err);
+                None
+            }
+        })
+        .collect();
+    saved.sort_by_key(|(_, bundle)| std::cmp::Reverse(bundle.exported));
+    saved
+}
+
+fn write_bundle(cp: &Checkpoint, id: &str) -> Result<Option<PathBuf>> {
+    if cp.history.len() <= 1 {
+        return Ok(None);
+    }
+    let bundle = session::Bundle {
+        format: session::FORMAT.into(),
+        id: id.to_string(),
+        exported: stats::now_secs(),
+        provider: cp.provider.clone(),
+        model: cp.model.clone(),
+        messages: cp.history.load_all()?,
+        tool_calls: cp.tool_log.clone(),
+        files: session::snapshot_files(&cp.tool_log),
+        config_digest: cp.config_digest.clone(),
+        plan: cp.plan.clone(),
+        tags: cp.stats.try_lock().map(|stats| stats.tags.clone()).unwrap_or_default(),
+    };
+    let dir = dir();
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join(format!("{}.json.zst", id));
+    bundle.save_private(&path)?;
+    Ok(Some(path))
+}
+
+fn flush_and_report() {
+    match flush() {
+        Ok(Some(path)) => eprintln!("Session saved to {}; continue it with `rustaigent session import {}`", path.display(), path.display()),
+        Ok(None) => {}
+        Err(err) => eprintln!("Failed to save the session: {:#}", err),
+    }
+}
+
+/// Save the session when the process panics or is interrupted (Ctrl-C between turns, SIGTERM, SIGHUP)
+pub fn install() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        flush_and_report();
+    }));
+    tokio::spawn(async {
+        // Ctrl-C first cancels the turn running in the terminal; with none running it exits
+        while signal().await && cancel::interrupt() {
+            eprintln!();
+        }
+        eprintln!();
+        flush_and_report();
+        crate::tools::remove_scratch_dirs();
+        std::process::exit(130);
+    });
+}
+
+/// Wait for Ctrl-C (true), SIGTERM or SIGHUP
+#[cfg(unix)]
+async fn signal() -> bool {
+    use tokio::signal::unix::{signal, SignalKind};
+    let (Ok(mut term), Ok(mut hup)) = (signal(SignalKind::terminate()), signal(SignalKind::hangup())) else {
+        let _ = tokio::signal::ctrl_c().await;
+        return true;
+    };
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => true,
+        _ = term.recv() => false,
+        _ = hup.recv() => false,
+    }
+}
+
+#[cfg(not(unix))]
+async fn signal() -> bool {
+    let _ = tokio::signal::ctrl_c().await;
+    true
+}
@@ -0,0 +1,213 @@
+use std::{fs, path::Path, time::Duration};
+use serde_json::{json, Value};
+use anyhow::{Result, Context, bail};
+use crate::{postprocess, progress, turns::Turn, Agent};
+
+/// Results are due within this window; OpenAI only offers 24h
+const COMPLETION_WINDOW: &str = "24h";
+
+/// The Batch API is OpenAI's; other providers only take live requests
+pub fn require_openai(agent: &Agent) -> Result<()> {
+    if agent.provider != "openai" {
+        bail!("The Batch API is only available with provider `openai` (current: `{}`)", agent.provider);
+    }
+    Ok(())
+}
+
+/// Provider endpoint for the Batch and Files APIs, honoring `base_url`
+fn endpoint(agent: &Agent, path: &str) -> String {
+    let base = agent.base_url.as_deref().unwrap_or("https://api.openai.com").trim_end_matches('/');
+    format!("{}/{}/{}", base, agent.api_versions.openai, path)
+}
+
+/// Turn an error status into an error carrying the provider's message
+async fn checked(response: reqwest::Response) -> Result<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body: Value = response.json().await.unwrap_or_default();
+    bail!("Batch API returned {}: {}", status, body["error"]["message"].as_str().unwrap_or("no details"));
+}
+
+async fn get(agent: &Agent, path: &str) -> Result<reqwest::Response> {
+    let (_, key) = agent.keys.select();
+    checked(agent.client.get(endpoint(agent, path)).bearer_auth(key).send().await?).await
+}
+
+/// The chat request the prompt would have sent interactively, as an answer turn since nobody is there to run tools
+fn request_body(agent: &Agent, prompt: &str, model: &str) -> Result<Value> {
+    let mut single = agent.clone_for_batch(prompt.to_string());
+    // The batch input names the Chat Completions endpoint whatever `openai_api` says
+    single.backend = crate::providers::for_name("openai");
+    if let Some(reason) = single.privacy.as_ref().and_then(|r| r.private_reason(&single.conversation)) {
+        bail!("The prompt has to stay local ({}), but batch jobs run at {}; send it interactively instead", reason, agent.provider);
+    }
+    let (_, body) = single.wire_request(model, Turn::Answer, None)?;
+    Ok(serde_json::from_slice(&body)?)
+}
+
+/// Upload `(custom_id, prompt)` pairs as one JSONL file and start a batch over it; returns the batch object
+pub async fn submit(agent: &Agent, requests: &[(String, String)], model: &str) -> Result<Value> {
+    let url = format!("/{}/chat/completions", agent.api_versions.openai);
+    let mut lines = String::new();
+    for (custom_id, prompt) in requests {
+        let line = json!({"custom_id": custom_id, "method": "POST", "url": url, "body": request_body(agent, prompt, model)?});
+        lines.push_str(&line.to_string());
+        lines.push('\n');
+    }
+    let (_, key) = agent.keys.select();
+    let file = reqwest::multipart::Part::bytes(lines.into_bytes()).file_name("rustaigent-batch.jsonl").mime_str("application/jsonl")?;
+    let form = reqwest::multipart::Form::new().text("purpose", "batch").part("file", file);
+    let upload: Value = checked(agent.client.post(endpoint(agent, "files")).bearer_auth(&key).multipart(form).send().await?).await?.json().await?;
+    let file_id = upload["id"].as_str().context("The file upload returned no id")?;
+    let body = json!({"input_file_id": file_id, "endpoint": url, "completion_window": COMPLETION_WINDOW});
+    let batch: Value = checked(agent.client.post(endpoint(agent, "batches")).bearer_auth(&key).json(&body).send().await?).await?.json().await?;
+    batch["id"].as_str().context("The batch request returned no id")?;
+    Ok(batch)
+}
+
+/// Current state of a batch
+pub async fn poll(agent: &Agent, id: &str) -> Result<Value> {
+    Ok(get(agent, &format!("batches/{}", id)).await?.json().await?)
+}
+
+/// Whether a batch status is final
+pub fn finished(status: &str) -> bool {
+    matches!(status, "completed" | "failed" | "expired" | "cancelled")
+}
+
+/// Why a finished batch left requests without a result
+pub fn failure(batch: &Value) -> String {
+    batch["errors"]["data"][0]["message"].as_str()
+        .map_or_else(|| format!("batch {} without a result", batch["status"].as_str().unwrap_or("ended")), String::from)
+}
+
+/// Lines of a finished batch's output and error files, recording token usage per model
+pub async fn results(agent: &Agent, batch: &Value) -> Result<Vec<Value>> {
+    let mut lines = Vec::new();
+    for file in ["output_file_id", "error_file_id"] {
+        if let Some(id) = batch[file].as_str() {
+            let text = get(agent, &format!("files/{}/content", id)).await?.text().await?;
+            lines.extend(text.lines().filter_map(|l| serde_json::from_str::<Value>(l).ok()));
+        }
+    }
+    let mut stats = agent.stats.lock().unwrap();
+    for line in &lines {
+        let body = &line["response"]["body"];
+        if let Some(model) = body["model"].as_str() {
+            stats.record_response(model, body);
+        }
+    }
+    Ok(lines)
+}
+
+/// Answer or error of one result line
+pub fn outcome(line: &Value) -> std::result::Result<String, String> {
+    let body = &line["response"]["body"];
+    match body["choices"][0]["message"]["content"].as_str() {
+        Some(answer) => Ok(answer.to_string()),
+        None => Err(line["error"]["message"].as_str().or_else(|| body["error"]["message"].as_str()).unwrap_or("no answer in the batch output").to_string()),
+    }
+}
+
+/// `(id, prompt)` pairs from a JSONL file whose lines are strings or `{"id": ..., "prompt": ...}` objects;
+/// lines without an id are numbered
+fn read_input(path: &Path) -> Result<Vec<(String, String)>> {
+    let text = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let mut requests = Vec::new();
+    for (n, line) in text.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+        let value: Value = serde_json::from_str(line).with_context(|| format!("{}:{}: not JSON", path.display(), n + 1))?;
+        let (id, prompt) = match &value {
+            Value::String(prompt) => (None, prompt.as_str()),
+            _ => (value.get("id"), value["prompt"].as_str().with_context(|| format!("{}:{}: no `prompt` string", path.display(), n + 1))?),
+        };
+        let id = match id {
+            Some(Value::String(id)) => id.clone(),
+            Some(id) => id.to_string(),
+            None => (n + 1).to_string(),
+        };
+        if requests.iter().any(|(seen, _): &(String, String)| *seen == id) {
+            bail!("{}:{}: duplicate id `{}`", path.display(), n + 1, id);
+        }
+        requests.push((id, prompt.to_string()));
+    }
+    Ok(requests)
+}
+
+/// Settings of `rustaigent batch`
+pub struct Options {
+    pub output: Option<std::path::PathBuf>,
+    /// Go through the asynchronous Batch API instead of live requests
+    pub batch_api: bool,
+    /// Batch to keep polling instead of submitting the input again
+    pub resume: Option<String>,
+    pub every: Duration,
+    /// Live requests in flight at once
+    pub concurrency: usize,
+}
+
+/// Answer every prompt of a JSONL file and write `{"id", "answer"}` or `{"id", "error"}` lines in input order
+pub async fn run(agent: &Agent, input: &Path, options: Options) -> Result<()> {
+    let requests = read_input(input)?;
+    let outcomes: Vec<std::result::Result<String, String>> = if options.batch_api || options.resume.is_some() {
+        require_openai(agent)?;
+        let id = match options.resume {
+            Some(id) => id,
+            None => {
+                let batch = submit(agent, &requests, &agent.turn_model(Turn::Answer)).await?;
+                let id = batch["id"].as_str().unwrap_or_default().to_string();
+                eprintln!("Submitted {} request(s) as batch {}; if interrupted, continue with `rustaigent batch {} --resume {}`", requests.len(), id, input.display(), id);
+                id
+            }
+        };
+        let spinner = progress::spinner(&format!("batch {}", id), agent.show_progress());
+        let batch = loop {
+            let batch = poll(agent, &id).await?;
+            let status = batch["status"].as_str().unwrap_or("unknown");
+            let counts = &batch["request_counts"];
+            spinner.set_message(format!("batch {}: {} ({}/{} done, {} failed)", id, status, counts["completed"], counts["total"], counts["failed"]));
+            if finished(status) {
+                break batch;
+            }
+            tokio::time::sleep(options.every).await;
+        };
+        spinner.finish_and_clear();
+        let lines = results(agent, &batch).await?;
+        requests.iter().map(|(id, _)| {
+            lines.iter().find(|l| l["custom_id"].as_str() == Some(id)).map_or_else(|| Err(failure(&batch)), outcome)
+        }).collect()
+    } else {
+        let prompts = requests.iter().map(|(_, prompt)| prompt.clone()).collect();
+        agent.send_batch(prompts, options.concurrency).await.into_iter()
+            .map(|result| result.map(|m| m.content).map_err(|err| format!("{:#}", err)))
+            .collect()
+    };
+
+    let steps = agent.config.post_process.clone().unwrap_or_default();
+    let mut out = String::new();
+    let mut failed = 0;
+    for ((id, _), outcome) in requests.iter().zip(outcomes) {
+        let outcome = match outcome {
+            Ok(answer) if !steps.is_empty() => postprocess::apply(agent, &steps, answer).await.map_err(|err| format!("{:#}", err)),
+            other => other,
+        };
+        let line = match outcome {
+            Ok(answer) => json!({"id": id, "answer": answer}),
+            Err(error) => {
+                failed += 1;
+                json!({"id": id, "error": error})
+            }
+        };
+        out.push_str(&line.to_string());
+        out.push('\n');
+    }
+    match &options.output {
+        Some(path) => {
+            fs::write(path, out).with_context(|| format!("Failed to write {}", path.display()))?;
+            eprintln!("Wrote {} result(s) to {} ({} failed)", requests.len(), path.display(), failed);
+        }
+        None => print!("{}", out),
+    }
+    Ok(())
+}
@@ -0,0 +1,132 @@
+use std::{env, sync::Arc};
+use anyhow::{Result, Context, bail};
+use crate::{auth::Authenticator, config, credentials, providers, tools::Tool, Agent};
+
+/// Sets up an [`Agent`] for embedding. Anything left unset is resolved the way the CLI does it:
+/// environment variables, then the config files, then the built-in defaults.
+///
+/// ```no_run
+/// # async fn demo() -> anyhow::Result<()> {
+/// let mut agent = rustaigent::AgentBuilder::new().provider("claude").model("claude-sonnet-4-20250514").build()?;
+/// if let Some(reply) = agent.turn("List the files in src").await? {
+///     println!("{}", reply.content);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Default)]
+pub struct AgentBuilder {
+    provider: Option<String>,
+    model: Option<String>,
+    api_key: Option<String>,
+    system_prompt: Option<String>,
+    tools: Vec<Arc<dyn Tool>>,
+    auth: Option<Arc<dyn Authenticator>>,
+    offline: bool,
+}
+
+impl AgentBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `openai`, `claude`, `google` or `ollama`; otherwise API_PROVIDER, then the config
+    pub fn provider(mut self, provider: impl Into<String>) -> Self {
+        self.provider = Some(provider.into());
+        self
+    }
+
+    /// Model name; otherwise MODEL_NAME, the config, then the provider's default
+    pub fn model(mut self, model: impl Into<String>) -> Self {
+        self.model = Some(model.into());
+        self
+    }
+
+    /// Key for the provider; otherwise its variable (e.g. ANTHROPIC_API_KEY, or OPENAI_API_KEY for
+    /// OpenAI), then the credentials stored by `rustaigent init`
+    pub fn api_key(mut self, key: impl Into<String>) -> Self {
+        self.api_key = Some(key.into());
+        self
+    }
+
+    /// Replace the system prompt, including the project and language notes the CLI adds
+    pub fn system_prompt(mut self, prompt: impl Into<String>) -> Self {
+        self.system_prompt = Some(prompt.into());
+        self
+    }
+
+    /// Offer an application tool to the model next to the built-in ones
+    pub fn tool(mut self, tool: impl Tool + 'static) -> Self {
+        self.tools.push(Arc::new(tool));
+        self
+    }
+
+    /// Mint the credentials of each request, e.g. by signing it for a gateway or fetching a
+    /// short-lived token; takes the place of `[auth.<provider>]` and makes the key optional
+    pub fn auth(mut self, auth: impl Authenticator + 'static) -> Self {
+        self.auth = Some(Arc::new(auth));
+        self
+    }
+
+    /// Without a key, build an agent that works offline instead of failing: tools still run when
+    /// invoked directly, and prompts fail with how to set a provider up
+    pub fn or_offline(mut self) -> Self {
+        self.offline = true;
+        self
+    }
+
+    pub fn build(self) -> Result<Agent> {
+        let provider = self.provider
+            .or_else(|| env::var("API_PROVIDER").ok())
+            .or_else(|| config::Config::load().provider)
+            .unwrap_or_else(|| "openai".into());
+        let minted = self.auth.is_some() || config::Config::load().auth.is_some_and(|auth| auth.contains_key(&provider));
+        let api_key = self.api_key.or_else(|| api_key(&provider))
+            .or_else(|| minted.then(String::new));
+        let offline = api_key.is_none() && self.offline;
+        let api_key = api_key.or_else(|| offline.then(String::new))
+            .context("Missing API key; set OPENAI_API_KEY or run `rustaigent init`")?;
+        let mut agent = Agent::new(api_key, provider);
+        agent.offline = offline;
+        crate::experiment::assign(&mut agent)?;
+        if self.auth.is_some() {
+            agent.auth = self.auth;
+        }
+        if let Some(model) = self.model {
+            agent.model = Some(model);
+        }
+        if let Some(prompt) = self.system_prompt {
+            agent.conversation.make_mut()[0].content = prompt;
+        }
+        if !self.tools.is_empty() {
+            let mut functions = agent.functions.to_vec();
+            for tool in &self.tools {
+                let definition = tool.definition();
+                if functions.iter().any(|f| f.name == definition.name) {
+                    bail!("Tool `{}` is already registered", definition.name);
+                }
+                functions.push(definition);
+            }
+            agent.functions = functions.into();
+            agent.custom_tools = self.tools;
+        }
+        Ok(agent)
+    }
+}
+
+/// The provider's key from its own variable or the stored credentials; Ollama and Vertex AI need
+/// none. OPENAI_API_KEY is read only for providers that speak to OpenAI, so it is never sent to
+/// another vendor.
+pub(crate) fn api_key(provider: &str) -> Option<String> {
+    if provider == "vertex" {
+        return Some(String::new());
+    }
+    let var = match providers::for_name(provider).key_var() {
+        Some(var) => Some(var),
+        None if provider != "ollama" => Some("OPENAI_API_KEY"),
+        None => None,
+    };
+    var.and_then(|var| env::var(var).ok())
+        .or_else(|| credentials::load(provider))
+        .or_else(|| (provider == "ollama").then(String::new))
+}
@@ -0,0 +1,134 @@
+//! Files that only cost tokens: binaries, minified bundles, build output and large lockfiles.
+//! `read_file`, prefetch, uploads, `replace_in_files` and the codebase index leave them out, so
+//! a stray `bundle.min.js` or `Cargo.lock` cannot put megabytes into a prompt. Globs in
+//! `include_files` are let through whatever they look like.
+
+use std::{fmt, fs, io::Read, path::{Component, Path}};
+use crate::config::Config;
+
+/// Largest file read whole unless `max_file_bytes` says otherwise
+pub const DEFAULT_MAX_BYTES: u64 = 256 * 1024;
+/// Lockfiles above this are left out; small ones are worth reading for versions
+const LOCKFILE_BYTES: u64 = 64 * 1024;
+/// Bytes looked at to tell text from binary and minified code
+const HEAD: u64 = 8192;
+
+/// Directories holding build output and installed dependencies
+const GENERATED_DIRS: &[&str] = &["target", "node_modules", "dist", "__pycache__", ".next", ".venv", ".gradle"];
+const LOCKFILES: &[&str] = &["Cargo.lock", "package-lock.json", "yarn.lock", "pnpm-lock.yaml", "poetry.lock", "Gemfile.lock", "composer.lock", "go.sum", "uv.lock"];
+
+/// Why a file is left out
+#[derive(Debug, PartialEq)]
+pub enum Skip {
+    Binary,
+    Minified,
+    /// Under a build or dependency directory, named
+    Generated(String),
+    Lockfile(u64),
+    Large(u64),
+}
+
+impl fmt::Display for Skip {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Skip::Binary => write!(f, "a binary file"),
+            Skip::Minified => write!(f, "minified code"),
+            Skip::Generated(dir) => write!(f, "generated output under `{}/`", dir),
+            Skip::Lockfile(len) => write!(f, "a lockfile of {} KiB", len / 1024),
+            Skip::Large(len) => write!(f, "{} KiB, more than `max_file_bytes`", len / 1024),
+        }
+    }
+}
+
+/// Whether `head`, the start of a file, is binary: it has a NUL byte
+pub fn looks_binary(head: &[u8]) -> bool {
+    head.contains(&0)
+}
+
+/// Whether `head` is minified code: a few lines of thousands of characters
+fn looks_minified(head: &[u8]) -> bool {
+    let longest = head.split(|b| *b == b'\n').map(<[u8]>::len).max().unwrap_or(0);
+    head.len() as u64 >= HEAD / 2 && longest > 1000
+}
+
+/// `max_file_bytes` and `include_files` from the config
+#[derive(Clone, Default)]
+pub struct Guard {
+    max_bytes: Option<u64>,
+    include: Vec<glob::Pattern>,
+}
+
+impl Guard {
+    /// Invalid globs are reported by config validation and ignored here
+    pub fn from_config(config: &Config) -> Self {
+        let include = config.include_files.iter().flatten().filter_map(|g| glob::Pattern::new(g).ok()).collect();
+        Guard { max_bytes: config.max_file_bytes, include }
+    }
+
+    /// Why the file at `path`, relative to `root` or absolute, should stay out of prompts, or
+    /// `None` to read it. Files that cannot be read are let through for the reader to report.
+    pub fn skip(&self, root: &Path, path: &Path) -> Option<Skip> {
+        let full = root.join(path);
+        let rel = full.strip_prefix(root).unwrap_or(path);
+        let rel = rel.strip_prefix(".").unwrap_or(rel);
+        if self.include.iter().any(|g| g.matches_path(rel)) {
+            return None;
+        }
+        // Only directories inside the project count; the project itself may sit under `dist/`
+        let generated = rel.components().rev().skip(1).find_map(|c| match c {
+            Component::Normal(dir) => dir.to_str().filter(|d| GENERATED_DIRS.contains(d)),
+            _ => None,
+        }).filter(|_| rel.is_relative());
+        if let Some(dir) = generated {
+            return Some(Skip::Generated(dir.into()));
+        }
+        let len = fs::metadata(&full).ok()?.len();
+        let name = full.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+        if LOCKFILES.contains(&name) && len > LOCKFILE_BYTES {
+            return Some(Skip::Lockfile(len));
+        }
+        let mut head = Vec::new();
+        fs::File::open(&full).ok()?.take(HEAD).read_to_end(&mut head).ok()?;
+        if looks_binary(&head) {
+            return Some(Skip::Binary);
+        }
+        if name.contains(".min.") || looks_minified(&head) {
+            return Some(Skip::Minified);
+        }
+        (len > self.max_bytes.unwrap_or(DEFAULT_MAX_BYTES)).then_some(Skip::Large(len))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use crate::mock::{last_content, MockProvider};
+    use super::Guard;
+
+    #[tokio::test]
+    async fn binaries_and_bundles_are_not_read_unless_included() {
+        let mock = MockProvider::start().await;
+        let dir = std::env::temp_dir().join(format!("rustaigent-bulk-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("app.min.js"), "var a=1").unwrap();
+        std::fs::write(dir.join("vendor.js"), "x".repeat(6000)).unwrap();
+        std::fs::write(dir.join("logo.png"), b"\x89PNG\r\n\x1a\n\0\0").unwrap();
+        std::fs::write(dir.join("keep.min.js"), "var keep=1").unwrap();
+        for name in ["app.min.js", "vendor.js", "logo.png", "keep.min.js"] {
+            mock.tool_call("read_file", json!({"path": format!("@assets/{}", name)})).await;
+        }
+        mock.reply("done").await;
+        let mut agent = mock.tool_agent();
+        let config = crate::config::Config { include_files: Some(vec!["*/keep.min.js".into()]), ..Default::default() };
+        agent.tool_ctx.bulk = Guard::from_config(&config);
+        agent.add_root("assets", &dir).unwrap();
+        agent.turn("Look at the assets").await.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let requests = mock.requests().await;
+        let output = |n: usize| last_content(&requests[n]);
+        assert!(output(1).contains("it is minified code"), "{}", output(1));
+        assert!(output(2).contains("it is minified code"), "{}", output(2));
+        assert!(output(3).contains("it is a binary file"), "{}", output(3));
+        assert!(output(4).contains("var keep=1"), "{}", output(4));
+    }
+}
@@ -0,0 +1,172 @@
+//! Cancelling a turn: the user interrupting it, `turn_timeout_secs` running out, or its cost
+//! passing the turn budget. Each turn runs under a `CancellationToken`; cancelling it drops the
+//! provider request in flight, kills the process group of a running command, and rolls the
+//! conversation and the files the turn's tools changed back to where the turn started.
+
+use std::{fmt, fs, io, path::PathBuf, sync::{Arc, Mutex}, time::Duration};
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+/// Largest file kept for rolling back a cancelled turn; bigger ones stay as the tools left them
+const MAX_JOURNAL_FILE: u64 = 16 * 1024 * 1024;
+
+/// Why a turn was cancelled
+#[derive(Clone, Copy, Debug)]
+pub enum Reason {
+    User,
+    Timeout(Duration),
+    /// The turn spent this many USD
+    Budget(f64),
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reason::User => write!(f, "cancelled"),
+            Reason::Timeout(limit) => write!(f, "stopped at the {} turn limit", humantime::format_duration(*limit)),
+            Reason::Budget(usd) => write!(f, "stopped after spending ${:.4}, its budget", usd),
+        }
+    }
+}
+
+/// Error of a turn that was cancelled and rolled back
+#[derive(Debug)]
+pub struct Cancelled(pub Reason);
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Turn {}; its messages and file changes were rolled back", self.0)
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+#[derive(Default)]
+struct Current {
+    token: CancellationToken,
+    reason: Option<Reason>,
+}
+
+/// Cancels an agent's running turn from another task or thread
+#[derive(Clone, Default)]
+pub struct Canceller(Arc<Mutex<Current>>);
+
+impl Canceller {
+    /// Cancel the running turn; between turns this does nothing
+    pub fn cancel(&self) {
+        self.cancel_for(Reason::User);
+    }
+
+    pub(crate) fn cancel_for(&self, reason: Reason) {
+        let mut current = self.0.lock().unwrap();
+        current.reason.get_or_insert(reason);
+        current.token.cancel();
+    }
+
+    /// The token of a turn that is starting; a cancellation that came before it does not carry over
+    pub(crate) fn begin(&self) -> CancellationToken {
+        let mut current = self.0.lock().unwrap();
+        if current.token.is_cancelled() {
+            *current = Current::default();
+        }
+        current.token.clone()
+    }
+
+    pub(crate) fn reason(&self) -> Reason {
+        self.0.lock().unwrap().reason.unwrap_or(Reason::User)
+    }
+}
+
+/// The turn Ctrl-C cancels, while one is running in the terminal
+static INTERRUPTIBLE: Mutex<Option<Canceller>> = Mutex::new(None);
+
+/// Routes Ctrl-C to a turn until dropped
+pub(crate) struct Interruptible;
+
+impl Interruptible {
+    pub(crate) fn new(canceller: &Canceller) -> Self {
+        *INTERRUPTIBLE.lock().unwrap() = Some(canceller.clone());
+        Interruptible
+    }
+}
+
+impl Drop for Interruptible {
+    fn drop(&mut self) {
+        INTERRUPTIBLE.lock().unwrap().take();
+    }
+}
+
+/// Cancel the turn running in the terminal; false when there is none, or it was already
+/// interrupted, and Ctrl-C should end the process instead
+pub fn interrupt() -> bool {
+    INTERRUPTIBLE.lock().unwrap().take().map(|canceller| canceller.cancel()).is_some()
+}
+
+/// Files as they were before the running turn's tools first changed them
+#[derive(Default)]
+pub(crate) struct Journal {
+    /// Path and content; `None` for files the turn created
+    saved: Vec<(PathBuf, Option<Vec<u8>>)>,
+}
+
+impl Journal {
+    /// Keep `path` as it is now, unless the turn already did
+    pub fn save(&mut self, path: PathBuf) {
+        if self.saved.iter().any(|(saved, _)| *saved == path) {
+            return;
+        }
+        match fs::metadata(&path) {
+            Ok(meta) if meta.is_file() && meta.len() <= MAX_JOURNAL_FILE => match fs::read(&path) {
+                Ok(content) => self.saved.push((path, Some(content))),
+                Err(err) => warn!("{} cannot be rolled back: {}", path.display(), err),
+            },
+            Ok(meta) if meta.is_file() => warn!("{} is too large to roll back", path.display()),
+            // Directories are left alone; what a tool creates in them is not tracked
+            Ok(_) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => self.saved.push((path, None)),
+            Err(err) => warn!("{} cannot be rolled back: {}", path.display(), err),
+        }
+    }
+
+    /// Put every kept file back, deleting those the turn created; returns how many changed
+    pub fn restore(&mut self) -> usize {
+        let mut restored = 0;
+        for (path, content) in self.saved.drain(..).rev() {
+            let result = match &content {
+                Some(content) if fs::read(&path).ok().as_ref() == Some(content) => continue,
+                Some(content) => fs::write(&path, content),
+                None if !path.exists() => continue,
+                None => fs::remove_file(&path),
+            };
+            match result {
+                Ok(()) => restored += 1,
+                Err(err) => warn!("failed to roll back {}: {}", path.display(), err),
+            }
+        }
+        restored
+    }
+
+    pub fn clear(&mut self) {
+        self.saved.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mock::MockProvider;
+    use super::{Cancelled, Reason};
+
+    #[tokio::test]
+    async fn timed_out_turns_are_rolled_back() {
+        let mock = MockProvider::start().await;
+        mock.slow_reply("late", std::time::Duration::from_secs(10)).await;
+        let mut agent = mock.agent();
+        agent.enable_quiet();
+        agent.config.turn_timeout_secs = Some(1);
+        let before = agent.conversation.len();
+        let Err(err) = agent.turn("hello").await else { panic!("the turn finished") };
+        let cancelled = err.downcast_ref::<Cancelled>().unwrap();
+        assert!(matches!(cancelled.0, Reason::Timeout(_)));
+        assert_eq!(agent.conversation.len(), before);
+    }
+}
@@ -0,0 +1,172 @@
+use std::{collections::{BTreeMap, BTreeSet}, fmt, sync::Mutex};
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+use crate::{builder, config, providers, Agent};
+
+/// What a provider/model pair accepts, so requests leave out parameters it would reject
+#[derive(Clone, Copy, Serialize)]
+pub struct Capabilities {
+    /// Function calling
+    pub tools: bool,
+    /// Image inputs
+    pub vision: bool,
+    /// `response_format: json_object`
+    pub json_mode: bool,
+    pub streaming: bool,
+    /// `reasoning_effort` and `max_completion_tokens`; such models reject `temperature`
+    pub reasoning: bool,
+    /// Context window in tokens, prompt and completion together
+    pub max_context: u32,
+}
+
+const fn caps(tools: bool, vision: bool, json_mode: bool, streaming: bool, reasoning: bool, max_context: u32) -> Capabilities {
+    Capabilities { tools, vision, json_mode, streaming, reasoning, max_context }
+}
+
+/// Unknown models and providers are treated like a current OpenAI chat model
+const DEFAULT: Capabilities = caps(true, false, true, true, false, 128_000);
+
+/// Known models by provider and name prefix; the first match wins, so specific prefixes come first
+const KNOWN: &[(&str, &str, Capabilities)] = &[
+    ("openai", "o1-mini", caps(false, false, false, true, true, 128_000)),
+    ("openai", "o1", caps(true, true, true, true, true, 200_000)),
+    ("openai", "o3-mini", caps(true, false, true, true, true, 200_000)),
+    ("openai", "o3", caps(true, true, true, true, true, 200_000)),
+    ("openai", "o4-mini", caps(true, true, true, true, true, 200_000)),
+    ("openai", "gpt-4.1", caps(true, true, true, true, false, 1_047_576)),
+    ("openai", "gpt-4o", caps(true, true, true, true, false, 128_000)),
+    ("openai", "gpt-4-turbo", caps(true, true, true, true, false, 128_000)),
+    ("openai", "gpt-4", caps(true, false, false, true, false, 8_192)),
+    ("openai", "gpt-3.5-turbo", caps(true, false, true, true, false, 16_385)),
+    ("openai", "", DEFAULT),
+    ("claude", "", caps(true, true, false, true, false, 200_000)),
+    // 4096 is Ollama's default `num_ctx`; models without tool support reject the `tools` parameter
+    ("ollama", "llava", caps(false, true, true, true, false, 4_096)),
+    ("ollama", "llama3.2-vision", caps(false, true, true, true, false, 4_096)),
+    ("ollama", "", caps(true, false, true, true, false, 4_096)),
+    ("google", "", caps(true, true, false, true, false, 1_048_576)),
+    ("vertex", "", caps(true, true, false, true, false, 1_048_576)),
+    // TGI takes `response_format` as a grammar, not `json_object`
+    ("huggingface", "", caps(true, false, false, true, false, 32_768)),
+    // Only grok-3-mini takes `reasoning_effort`; grok-4 reasons without one and rejects it
+    ("xai", "grok-3-mini", caps(true, false, true, true, true, 131_072)),
+    ("xai", "grok-4", caps(true, true, true, true, false, 256_000)),
+    ("xai", "", caps(true, false, true, true, false, 131_072)),
+    ("deepseek", "", caps(true, false, true, true, false, 128_000)),
+];
+
+/// `[capabilities."<model prefix>"]` tables in the config file, correcting or extending the built-in map
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct Override {
+    pub tools: Option<bool>,
+    pub vision: Option<bool>,
+    pub json_mode: Option<bool>,
+    pub streaming: Option<bool>,
+    pub reasoning: Option<bool>,
+    pub max_context: Option<u32>,
+}
+
+/// Keys accepted in a `[capabilities.*]` table, for config validation
+pub const KEYS: &[&str] = &["tools", "vision", "json_mode", "streaming", "reasoning", "max_context"];
+
+/// Capabilities of `model` on `provider`, with the longest matching config override applied
+pub fn lookup(provider: &str, model: &str, overrides: Option<&BTreeMap<String, Override>>) -> Capabilities {
+    let mut found = KNOWN.iter()
+        .find(|(p, prefix, _)| *p == provider && model.starts_with(prefix))
+        .map(|(_, _, caps)| *caps)
+        .unwrap_or(DEFAULT);
+    let Some(o) = overrides.and_then(|map| map.iter().filter(|(prefix, _)| model.starts_with(prefix.as_str())).max_by_key(|(prefix, _)| prefix.len())).map(|(_, o)| o) else {
+        return found;
+    };
+    found.tools = o.tools.unwrap_or(found.tools);
+    found.vision = o.vision.unwrap_or(found.vision);
+    found.json_mode = o.json_mode.unwrap_or(found.json_mode);
+    found.streaming = o.streaming.unwrap_or(found.streaming);
+    found.reasoning = o.reasoning.unwrap_or(found.reasoning);
+    found.max_context = o.max_context.unwrap_or(found.max_context);
+    found
+}
+
+/// The provider refused a prompt as longer than the model's context window
+#[derive(Debug)]
+pub struct ContextOverflow(pub String);
+
+impl fmt::Display for ContextOverflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for ContextOverflow {}
+
+/// Whether a provider's `error` object reports an over-long prompt; each provider words it differently
+pub fn is_context_overflow(error: &serde_json::Value) -> bool {
+    const PHRASES: &[&str] = &["context length", "context window", "prompt is too long", "too many tokens", "maximum number of tokens", "input is too long"];
+    let message = error["message"].as_str().unwrap_or_default().to_lowercase();
+    error["code"] == "context_length_exceeded" || PHRASES.iter().any(|p| message.contains(p))
+}
+
+static WARNED: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+/// Warn about a degraded feature once per process rather than on every request
+pub fn warn_once(message: String) {
+    if WARNED.lock().map(|mut seen| seen.insert(message.clone())).unwrap_or(true) {
+        tracing::warn!("{}", message);
+    }
+}
+
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mark = |on: bool| if on { "yes" } else { "no" };
+        writeln!(f, "tools:       {}", mark(self.tools))?;
+        writeln!(f, "vision:      {}", mark(self.vision))?;
+        writeln!(f, "json mode:   {}", mark(self.json_mode))?;
+        writeln!(f, "streaming:   {}", mark(self.streaming))?;
+        writeln!(f, "reasoning:   {}", mark(self.reasoning))?;
+        write!(f, "max context: {} tokens", self.max_context)
+    }
+}
+
+/// Version of the handshake's layout; raised only by incompatible changes
+pub const HANDSHAKE: u32 = 1;
+
+/// Cargo features this binary was built with
+fn features() -> Value {
+    json!({
+        "grpc": cfg!(feature = "grpc"),
+        "python": cfg!(feature = "python"),
+        "ffi": cfg!(feature = "ffi"),
+        "mock_server": cfg!(feature = "mock-server"),
+        "daemon": cfg!(unix),
+    })
+}
+
+/// What this build offers and what `agent` runs with, for frontends and scripts to adapt to:
+/// build features, each provider with its default model, the model families known here and
+/// whether a key is at hand, the session's model and what it accepts, and the tools the model
+/// may call
+pub(crate) fn handshake(agent: &Agent) -> Value {
+    let providers: Vec<Value> = config::PROVIDERS.iter().map(|&provider| {
+        let families: Vec<&str> = KNOWN.iter().filter(|(p, prefix, _)| *p == provider && !prefix.is_empty()).map(|(_, prefix, _)| *prefix).collect();
+        json!({
+            "name": provider,
+            "default_model": providers::for_name(provider).default_model(),
+            "model_families": families,
+            "configured": builder::api_key(provider).is_some() || agent.config.auth.as_ref().is_some_and(|auth| auth.contains_key(provider)),
+        })
+    }).collect();
+    let model = agent.model_name();
+    let tools: Vec<Value> = agent.functions.iter().map(|f| json!({ "name": f.name, "description": f.description, "parameters": f.parameters })).collect();
+    json!({
+        "handshake": HANDSHAKE,
+        "version": env!("CARGO_PKG_VERSION"),
+        "features": features(),
+        "editor_protocol": crate::stdio::PROTOCOL,
+        "provider": agent.provider,
+        "model": model,
+        "model_capabilities": agent.capabilities(&model),
+        "offline": agent.offline,
+        "providers": providers,
+        "tools": tools,
+    })
+}
@@ -0,0 +1,31 @@
+//! One request to a provider with nothing around it: no retries, tools or local state. This is
+//! the part of the crate that also builds for wasm32, where reqwest sends through `fetch`, so
+//! browser playgrounds and edge functions share the agent's request and response handling.
+
+use anyhow::{Result, bail};
+use reqwest::{header::CONTENT_TYPE, Client};
+use serde_json::Value;
+use crate::{http, providers::{self, Reply, Request}};
+
+/// Send `request` to `provider` and parse the whole answer; `request.stream` must be off. Error
+/// statuses fail with the provider's own message.
+pub async fn complete(client: &Client, provider: &str, key: &str, request: &Request<'_>) -> Result<Reply> {
+    if request.stream {
+        bail!("chat::complete reads whole responses; turn `stream` off");
+    }
+    let backend = providers::for_name(provider);
+    let (url, body) = backend.build_request(request)?;
+    let resp = backend.authorize(client.post(&url), key, request.versions)
+        .header(CONTENT_TYPE, "application/json")
+        .body(body)
+        .send()
+        .await?;
+    let status = resp.status();
+    if !status.is_success() {
+        let text = resp.text().await.unwrap_or_default();
+        let body = serde_json::from_str(&text).unwrap_or_else(|_| Value::String(text.chars().take(200).collect()));
+        let detail = http::error_message(&body).unwrap_or_else(|| "no error details".into());
+        bail!("{} rejected the request with {} (pinned {}): {}", provider, status, request.versions.for_provider(backend.name()), detail);
+    }
+    backend.parse_response(resp.json().await?)
+}
@@ -0,0 +1,138 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+use serde::Serialize;
+use crate::tools::ToolCall;
+
+/// Retrieved material the model may cite: a file span or a URL
+#[derive(Serialize, Clone)]
+pub struct Citation {
+    pub id: usize,
+    pub location: String,
+    /// Start of the retrieved content, for reports that show what was cited
+    pub excerpt: String,
+    /// Hash of the retrieved content, to spot identical re-reads
+    #[serde(skip)]
+    digest: u64,
+}
+
+/// Characters of a source kept as its excerpt
+const EXCERPT_CHARS: usize = 200;
+
+/// The first lines of `content`, up to `EXCERPT_CHARS`
+fn excerpt(content: &str) -> String {
+    let trimmed = content.trim();
+    match trimmed.char_indices().nth(EXCERPT_CHARS) {
+        Some((end, _)) => format!("{}…", &trimmed[..end]),
+        None => trimmed.to_string(),
+    }
+}
+
+fn digest(content: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Sources handed to the model this session, numbered from 1
+#[derive(Default)]
+pub struct Sources {
+    all: Vec<Citation>,
+    /// Sources added since the last answer
+    fresh: usize,
+}
+
+/// The citable pieces of a retrieval tool's output, each with where it came from as
+/// `file:start-end` or a URL
+pub fn retrieved<'a>(call: &ToolCall, output: &'a str) -> Vec<(String, &'a str)> {
+    match call {
+        ToolCall::ReadFile { path } => vec![(format!("{}:1-{}", path, output.lines().count().max(1)), output)],
+        ToolCall::FetchUrl { url } => vec![(url.clone(), output)],
+        ToolCall::SearchCodebase { .. } => crate::index::matches(output),
+        _ => Vec::new(),
+    }
+}
+
+impl Sources {
+    /// Register a source, returning the context message that introduces it to the model
+    pub fn add(&mut self, location: String, content: &str) -> String {
+        let id = self.all.len() + 1;
+        self.all.push(Citation { id, location: location.clone(), excerpt: excerpt(content), digest: digest(content) });
+        self.fresh += 1;
+        format!("[source {}: {}]\n{}\n[end of source {}]\nWhen your answer relies on this source, cite it inline as [{}].", id, location, content, id, id)
+    }
+
+    /// Register a source the model was given without asking for it; answers need not cite it
+    pub fn offer(&mut self, location: String, content: &str) -> String {
+        let fresh = self.fresh;
+        let message = self.add(location, content);
+        self.fresh = fresh;
+        message
+    }
+
+    /// Earlier sources with the same location and identical content, which a new copy makes redundant
+    pub fn unchanged(&self, location: &str, content: &str) -> Vec<usize> {
+        let digest = digest(content);
+        self.all.iter().filter(|c| c.location == location && c.digest == digest).map(|c| c.id).collect()
+    }
+
+    /// Number of the most recently added source
+    pub fn latest(&self) -> usize {
+        self.all.len()
+    }
+
+    /// A context message with its block for source `id` replaced by `stub`; `None` when the
+    /// message does not carry that source. Other sources in the message are kept.
+    pub fn replace_block(message: &str, id: usize, stub: &str) -> Option<String> {
+        let open = format!("[source {}: ", id);
+        let start = if message.starts_with(&open) { 0 } else { message.find(&format!("\n{}", open))? + 1 };
+        let close = format!("[end of source {}]", id);
+        let end = start + message[start..].find(&close)? + close.len();
+        Some(format!("{}{}{}", &message[..start], stub, &message[end..]))
+    }
+
+    /// Short context message replacing source `id` once `latest` carries the same content, so the
+    /// number stays citable without sending the content twice
+    pub fn stub(&self, id: usize, latest: usize) -> String {
+        let location = self.all.get(id.wrapping_sub(1)).map_or("", |c| c.location.as_str());
+        format!("[source {}: {}]
+Content unchanged; see the latest read, source {}.
+[end of source {}]", id, location, latest, id)
+    }
+
+    /// True when sources were retrieved since the last answer
+    pub fn has_fresh(&self) -> bool {
+        self.fresh > 0
+    }
+
+    /// Mark the current answer as done
+    pub fn settle(&mut self) {
+        self.fresh = 0;
+    }
+
+    /// Sources referenced as `[n]` in an answer, in order of first mention
+    pub fn cited(&self, answer: &str) -> Vec<Citation> {
+        let mut found: Vec<Citation> = Vec::new();
+        let mut rest = answer;
+        while let Some(open) = rest.find('[') {
+            rest = &rest[open + 1..];
+            let Some(close) = rest.find(']') else { break };
+            for part in rest[..close].split(',') {
+                let Ok(id) = part.trim().parse::<usize>() else { continue };
+                if let Some(source) = self.all.get(id.wrapping_sub(1)) {
+                    if !found.iter().any(|c| c.id == id) {
+                        found.push(source.clone());
+                    }
+                }
+            }
+        }
+        found
+    }
+}
+
+/// Citation list printed beneath an answer
+pub fn render(citations: &[Citation]) -> String {
+    let mut out = String::from("Sources:");
+    for c in citations {
+        out.push_str(&format!("\n  [{}] {}", c.id, c.location));
+    }
+    out
+}
@@ -0,0 +1,102 @@
+//! Request and response shapes of OpenAI's `/v1/chat/completions`, so editors and other clients
+//! of that API can put `rustaigent serve` in front of any configured provider.
+
+use anyhow::{Result, Context, bail};
+use serde_json::{json, Value};
+use crate::ChatMessage;
+
+/// Model name that stands for the server's configured model
+pub const MODEL: &str = "rustaigent";
+
+/// What a client asked for: the conversation before its last user message, that message, and
+/// the sampling options the agent honors
+pub struct Completion {
+    pub history: Vec<ChatMessage>,
+    pub prompt: String,
+    /// Model to use instead of the configured one; `None` for `rustaigent` or when unset
+    pub model: Option<String>,
+    pub temperature: Option<f32>,
+    pub max_tokens: Option<u16>,
+    pub stream: bool,
+    /// Send a last chunk with token counts, as `stream_options.include_usage` asks
+    pub include_usage: bool,
+}
+
+/// Text of a message's `content`: a string, or the text parts of an array of parts
+fn text(content: &Value) -> String {
+    match content {
+        Value::String(text) => text.clone(),
+        Value::Array(parts) => parts.iter().filter_map(|p| p["text"].as_str()).collect::<Vec<_>>().join("\n"),
+        _ => String::new(),
+    }
+}
+
+impl Completion {
+    /// Read a request body. Tool definitions and tool messages of the client are left out, since
+    /// the agent runs its own tools.
+    pub fn parse(body: &Value) -> Result<Self> {
+        let messages = body["messages"].as_array().context("expected a `messages` array")?;
+        let mut history: Vec<ChatMessage> = messages.iter()
+            .filter_map(|m| {
+                let role = m["role"].as_str()?;
+                let role = if role == "developer" { "system" } else { role };
+                ["system", "user", "assistant"].contains(&role).then(|| ChatMessage {
+                    role: role.into(),
+                    content: text(&m["content"]),
+                    name: None,
+                    reasoning: None,
+                    function_call: None,
+                    files: Vec::new(),
+                    response_id: None,
+                })
+            })
+            .collect();
+        let prompt = match history.pop() {
+            Some(last) if last.role == "user" => last.content,
+            _ => bail!("the last message must come from the user"),
+        };
+        let max_tokens = body["max_completion_tokens"].as_u64().or_else(|| body["max_tokens"].as_u64());
+        Ok(Completion {
+            history,
+            prompt,
+            model: body["model"].as_str().filter(|m| !m.is_empty() && *m != MODEL).map(String::from),
+            temperature: body["temperature"].as_f64().map(|t| t as f32),
+            max_tokens: max_tokens.map(|n| n.min(u16::MAX as u64) as u16),
+            stream: body["stream"].as_bool().unwrap_or(false),
+            include_usage: body["stream_options"]["include_usage"].as_bool().unwrap_or(false),
+        })
+    }
+}
+
+/// `usage` object from prompt and completion token counts
+pub fn usage(prompt: u64, completion: u64) -> Value {
+    json!({ "prompt_tokens": prompt, "completion_tokens": completion, "total_tokens": prompt + completion })
+}
+
+/// A whole `chat.completion` answer
+pub fn completion(id: &str, created: u64, model: &str, content: &str, usage: Value) -> Value {
+    json!({
+        "id": id,
+        "object": "chat.completion",
+        "created": created,
+        "model": model,
+        "choices": [{ "index": 0, "message": { "role": "assistant", "content": content }, "finish_reason": "stop" }],
+        "usage": usage,
+    })
+}
+
+/// One `chat.completion.chunk` of a streamed answer; `finish_reason` is set on the last one
+pub fn chunk(id: &str, created: u64, model: &str, delta: Value, finish_reason: Option<&str>) -> Value {
+    json!({
+        "id": id,
+        "object": "chat.completion.chunk",
+        "created": created,
+        "model": model,
+        "choices": [{ "index": 0, "delta": delta, "finish_reason": finish_reason }],
+    })
+}
+
+/// Error body in OpenAI's shape, which its client libraries know how to report
+pub fn error(message: &str, kind: &str) -> Value {
+    json!({ "error": { "message": message, "type": kind } })
+}
@@ -0,0 +1,32 @@
+use std::{fs, path::Path};
+use anyhow::{Result, Context};
+
+/// First bytes of every zstd frame
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Read a stored file, decrypting it when sealed and decompressing it when it is zstd regardless of its name
+pub fn read(path: &Path) -> Result<Vec<u8>> {
+    let bytes = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let bytes = crate::vault::open(path, bytes)?;
+    if bytes.starts_with(&ZSTD_MAGIC) {
+        return zstd::decode_all(bytes.as_slice()).with_context(|| format!("Failed to decompress {}", path.display()));
+    }
+    Ok(bytes)
+}
+
+/// Write a stored file, zstd-compressed when the path ends in `.zst`
+pub fn write(path: &Path, bytes: &[u8]) -> Result<()> {
+    fs::write(path, encode(path, bytes)?).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Like [`write`], then encrypted when `encryption` is on, for files only the agent reads back
+pub fn write_private(path: &Path, bytes: &[u8]) -> Result<()> {
+    crate::vault::write(path, encode(path, bytes)?)
+}
+
+fn encode(path: &Path, bytes: &[u8]) -> Result<Vec<u8>> {
+    if path.extension().is_some_and(|e| e == "zst") {
+        return Ok(zstd::encode_all(bytes, zstd::DEFAULT_COMPRESSION_LEVEL)?);
+    }
+    Ok(bytes.to_vec())
+}
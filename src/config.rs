@@ -0,0 +1,1162 @@
+use std::{collections::BTreeMap, env, fmt, fs, path::{Path, PathBuf}, sync::OnceLock};
+use serde::{Serialize, Deserialize};
+use anyhow::{Result, Context, bail};
+use crate::paths;
+
+/// Settings readable from the team, user and project config files
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct Config {
+    pub provider: Option<String>,
+    pub model: Option<String>,
+    pub max_tokens: Option<u16>,
+    pub temperature: Option<f32>,
+    /// Attempts per request before giving up on transient errors
+    pub retry_count: Option<u8>,
+    /// `[profiles.<name>]` table applied when no `--profile` is given
+    pub profile: Option<String>,
+    /// Reply and interface language, e.g. `de` or `pt-BR`
+    pub language: Option<String>,
+    pub system_prompt: Option<String>,
+    pub system_prompt_file: Option<PathBuf>,
+    /// Tools the model may call; all tools when unset
+    pub allowed_tools: Option<Vec<String>>,
+    /// Models the team permits; any model when unset
+    pub allowed_models: Option<Vec<String>>,
+    /// Labels the session's spend is attributed to in the usage stats, e.g. `client = "acme"`
+    pub tags: Option<BTreeMap<String, String>>,
+    /// Model names standing for others, e.g. `fast = "gpt-4o-mini"`
+    pub model_aliases: Option<BTreeMap<String, String>>,
+    /// Replace retired models with their successors (default true); off, they only draw a warning
+    pub migrate_models: Option<bool>,
+    /// Output colors
+    pub theme: Option<crate::theme::ThemeConfig>,
+    /// `low`, `medium` or `high` for reasoning models
+    pub reasoning_effort: Option<String>,
+    /// Token budget for extended thinking on Claude and Gemini
+    pub thinking_budget: Option<u32>,
+    /// Print model reasoning before the answer
+    pub show_thinking: Option<bool>,
+    /// Keep model reasoning in the conversation transcript
+    pub persist_thinking: Option<bool>,
+    /// Outbound prompt filters for cloud providers
+    pub guardrails: Option<crate::guardrails::GuardrailConfig>,
+    /// Content that only a local provider may see
+    pub privacy: Option<crate::privacy::PrivacyConfig>,
+    /// Second provider each request is raced against
+    pub race: Option<crate::race::RaceConfig>,
+    /// How commands the model proposes are scored for risk before they run
+    pub command_safety: Option<crate::risk::SafetyConfig>,
+    /// Who owns the files the model changes, per CODEOWNERS, and which owners are this team
+    pub codeowners: Option<crate::owners::OwnersConfig>,
+    /// Two variants of the prompt and settings, one assigned to each session at random
+    pub experiment: Option<crate::experiment::ExperimentConfig>,
+    /// Session role matched by `[[policy]]` rules
+    pub role: Option<String>,
+    /// Ordered tool-call rules; the first match decides
+    pub policy: Option<Vec<crate::policy::Rule>>,
+    /// `allow`, `ask` or `deny` per tool name
+    pub permissions: Option<BTreeMap<String, crate::policy::Action>>,
+    /// `ask` (default), `deny` or `off`: what calls that may act on fetched pages, issues or MCP output need
+    pub injection_guard: Option<String>,
+    /// Put a canary token in the system prompt and refuse tool calls that carry it
+    pub canary: Option<bool>,
+    /// Pick up config and system prompt file changes before each turn (default true)
+    pub hot_reload: Option<bool>,
+    /// Run likely read-only tool calls while the model is still writing (default true)
+    pub prefetch: Option<bool>,
+    /// Note the files changed and commands run in the conversation after each turn that had
+    /// tools act (default true)
+    pub actions_summary: Option<bool>,
+    /// Follow-up requests offered after each answer in the REPL
+    pub suggestions: Option<crate::suggest::SuggestConfig>,
+    /// Where an editor plugin writes the cursor position for `--context editor` (default
+    /// `.rustaigent/focus.json` in the project)
+    pub focus_file: Option<PathBuf>,
+    /// Program that approves or declines actions in place of the prompt, by exit status
+    pub approval_command: Option<String>,
+    /// Directories besides the working directory that file tools may touch
+    pub allowed_paths: Option<Vec<String>>,
+    /// Other project roots of the session by name, reached as `@name/path` in tool calls
+    pub roots: Option<BTreeMap<String, String>>,
+    /// Hosts `fetch_url` and `download_file` may reach, `*.example.com` for any subdomain
+    pub allowed_hosts: Option<Vec<String>>,
+    /// Let `fetch_url` and `download_file` reach loopback, private and link-local addresses
+    pub allow_private_network: Option<bool>,
+    /// Connection pool and keep-alive tuning
+    pub http: Option<crate::http::HttpConfig>,
+    /// Messages kept in memory before older turns are paged out to disk
+    pub memory_messages: Option<usize>,
+    /// Scheme and host replacing the provider's endpoint, e.g. a proxy
+    pub base_url: Option<String>,
+    /// `chat` for Chat Completions, or `responses` for the Responses API with server-side state
+    pub openai_api: Option<String>,
+    /// Provider-run tools offered through the Responses API: `code_interpreter`, `file_search`
+    pub hosted_tools: Option<Vec<String>>,
+    /// Vector store ids `file_search` searches
+    pub vector_stores: Option<Vec<String>>,
+    /// Per-model corrections to the provider capability map, keyed by model name prefix
+    pub capabilities: Option<BTreeMap<String, crate::capabilities::Override>>,
+    /// Have `fix` loops write a plan before the first attempt
+    pub plan: Option<bool>,
+    /// Local time window in which queued batch jobs are submitted, e.g. `22:00-06:00`
+    pub off_peak: Option<String>,
+    /// Generation settings for tool-choosing and answer-writing requests
+    pub turns: Option<crate::turns::Turns>,
+    /// Tool calls the model may chain in one turn before it must answer
+    pub max_tool_rounds: Option<usize>,
+    /// Seconds `run_command`, unless the call sets a limit, and `terraform_plan` may take before their process group is killed
+    pub command_timeout_secs: Option<u64>,
+    /// Bytes of `run_command` output returned to the model, split between its start and end
+    pub command_output_limit: Option<usize>,
+    /// Seal the request and response bodies logged at `-vv`; `false` logs them as plain text
+    pub encrypt_request_logs: Option<bool>,
+    /// Bytes above which files are left out of `read_file`, prefetch and the index
+    pub max_file_bytes: Option<u64>,
+    /// Globs of files read, uploaded and indexed even when binary, minified, generated or large
+    pub include_files: Option<Vec<String>>,
+    /// Bytes of tool output above which it is stored as an artifact the model pages through; 0 keeps every output whole
+    pub artifact_threshold: Option<usize>,
+    /// Seconds a turn may run, tools included, before it is cancelled and rolled back
+    pub turn_timeout_secs: Option<u64>,
+    /// USD a turn may spend before it is cancelled and rolled back
+    pub turn_budget_usd: Option<f64>,
+    /// Retries all requests of a turn may make together
+    pub turn_retries: Option<u32>,
+    /// Seconds from the start of a turn, tools included, after which failed requests are not retried
+    pub retry_deadline_secs: Option<u64>,
+    /// Print answers as they are generated
+    pub streaming: Option<bool>,
+    /// Encrypted mirror of prompt history and sessions
+    pub sync: Option<crate::sync::SyncConfig>,
+    /// `keyring` or `passphrase` to encrypt stored sessions and prompt history
+    pub encryption: Option<String>,
+    /// Steps applied to final answers, in order
+    pub post_process: Option<Vec<crate::postprocess::Step>>,
+    /// Prompt token budget and how older turns make room
+    pub context: Option<crate::context::ContextConfig>,
+    /// Model preparation for the Ollama backend
+    pub ollama: Option<crate::models::OllamaConfig>,
+    /// Embedding and retrieval of the codebase index
+    pub index: Option<crate::index::IndexConfig>,
+    /// Checking of final answers against a rubric
+    pub verify: Option<crate::verify::VerifyConfig>,
+    /// Project, region and credentials of the Vertex AI provider
+    pub vertex: Option<crate::gcp::VertexConfig>,
+    /// Dedicated endpoint of the Hugging Face provider
+    pub huggingface: Option<crate::providers::HuggingFaceConfig>,
+    /// Credentials minted per request by provider, for gateways that want more than a static key
+    pub auth: Option<crate::auth::Auth>,
+    /// External tool servers by name
+    pub mcp: Option<BTreeMap<String, crate::mcp::ServerConfig>>,
+    /// Registry and trusted keys of tool plugins
+    pub plugins: Option<crate::plugins::PluginsConfig>,
+    /// Users, workspaces and listening address of `rustaigent serve`
+    pub serve: Option<crate::tenants::ServeConfig>,
+    /// USD per million prompt and completion tokens by model name prefix, for cost tracking
+    pub pricing: Option<crate::stats::Pricing>,
+}
+
+/// Where an effective setting came from
+#[derive(Clone)]
+pub enum Source {
+    Team(PathBuf),
+    User(PathBuf),
+    Project(PathBuf),
+    /// `[profiles.<name>]` in this file
+    Profile(String, PathBuf),
+    /// A profile that comes with the binary
+    Builtin(&'static str),
+    Env(&'static str),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Team(p) => write!(f, "team ({})", p.display()),
+            Source::User(p) => write!(f, "user ({})", p.display()),
+            Source::Project(p) => write!(f, "project ({})", p.display()),
+            Source::Profile(name, p) => write!(f, "profile {} ({})", name, p.display()),
+            Source::Builtin(name) => write!(f, "built-in profile {}", name),
+            Source::Env(var) => write!(f, "env ({})", var),
+        }
+    }
+}
+
+/// Environment variables that override config keys
+const ENV_OVERRIDES: &[(&str, &str)] = &[
+    ("API_PROVIDER", "provider"),
+    ("MODEL_NAME", "model"),
+    ("MAX_TOKENS", "max_tokens"),
+    ("TEMPERATURE", "temperature"),
+    ("RETRY_COUNT", "retry_count"),
+    ("RESPONSE_LANGUAGE", "language"),
+    ("REASONING_EFFORT", "reasoning_effort"),
+    ("THINKING_BUDGET", "thinking_budget"),
+    ("SHOW_THINKING", "show_thinking"),
+    ("RUSTAIGENT_ROLE", "role"),
+    ("MEMORY_MESSAGES", "memory_messages"),
+    ("API_BASE_URL", "base_url"),
+    ("OPENAI_API", "openai_api"),
+    ("STREAMING", "streaming"),
+    ("RUSTAIGENT_ENCRYPTION", "encryption"),
+];
+
+/// Profile for small machines and CI containers: a short conversation in memory with older
+/// turns on disk, no codebase index, large tool results stored as artifacts and tight output caps
+const LOW_MEMORY: &str = r#"
+memory_messages = 16
+artifact_threshold = 4096
+command_output_limit = 8192
+max_file_bytes = 131072
+prefetch = false
+
+[index]
+enabled = false
+"#;
+
+/// Profiles every installation has; a config file's `[profiles.<name>]` of the same name is
+/// applied on top
+const BUILTIN_PROFILES: &[(&str, &str)] = &[("low-memory", LOW_MEMORY)];
+
+/// Profile chosen with `--profile`, taking precedence over `RUSTAIGENT_PROFILE` and the `profile` key
+static PROFILE: OnceLock<String> = OnceLock::new();
+
+/// Select the profile every later load applies
+pub fn use_profile(name: &str) {
+    PROFILE.set(name.to_string()).ok();
+}
+
+/// Team, user and project layers merged key by key, remembering each key's origin, with the
+/// selected profile applied on top
+pub struct Layered {
+    pub values: BTreeMap<String, (toml::Value, Source)>,
+    /// Every profile defined in any layer or built in, with the files that define it
+    pub profiles: BTreeMap<String, Vec<PathBuf>>,
+    /// The profile that was applied
+    pub profile: Option<String>,
+}
+
+/// Lists the team config restricts: the user and project layers and profiles may narrow them,
+/// but anything the team leaves out stays out
+const TEAM_LIMITS: &[&str] = &["allowed_tools", "allowed_models"];
+
+/// `value` without the strings missing from `limit`
+fn narrow(value: &toml::Value, limit: &toml::Value) -> toml::Value {
+    let allowed = limit.as_array().map(Vec::as_slice).unwrap_or_default();
+    match value.as_array() {
+        Some(items) => items.iter().filter(|item| allowed.contains(item)).cloned().collect::<Vec<_>>().into(),
+        None => limit.clone(),
+    }
+}
+
+/// Make the file paths a layer sets relative to the directory of the file, instead of wherever
+/// the agent happens to be started
+fn anchor_paths(table: &mut toml::Table, dir: &Path) {
+    let anchor = |table: &mut toml::Table| {
+        if let Some(value) = table.get_mut("system_prompt_file") {
+            if let Some(path) = value.as_str().filter(|p| Path::new(p).is_relative()) {
+                *value = dir.join(path).display().to_string().into();
+            }
+        }
+    };
+    anchor(table);
+    if let Some(experiment) = table.get_mut("experiment").and_then(|v| v.as_table_mut()) {
+        for name in ["a", "b"] {
+            if let Some(variant) = experiment.get_mut(name).and_then(|v| v.as_table_mut()) {
+                anchor(variant);
+            }
+        }
+    }
+    for (_, profile) in table.get_mut("profiles").and_then(|v| v.as_table_mut()).into_iter().flat_map(|p| p.iter_mut()) {
+        if let Some(profile) = profile.as_table_mut() {
+            anchor_paths(profile, dir);
+        }
+    }
+}
+
+fn read_layer(path: &PathBuf) -> Result<toml::Table> {
+    let text = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    toml::from_str(&text).with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+impl Layered {
+    /// Load the team config, apply personal overrides on top, then the project's, then the
+    /// selected profile's values from all three
+    pub fn load() -> Result<Self> {
+        Self::merge([
+            paths::team_config_file().map(|p| (p.clone(), Source::Team(p))),
+            Some(paths::user_config_file()).filter(|p| p.is_file()).map(|p| (p.clone(), Source::User(p))),
+            paths::project_config_file().map(|p| (p.clone(), Source::Project(p))),
+        ].into_iter().flatten())
+    }
+
+    /// Merge the files of `layers`, lowest precedence first
+    fn merge(layers: impl IntoIterator<Item = (PathBuf, Source)>) -> Result<Self> {
+        let mut values = BTreeMap::new();
+        let mut profiles: BTreeMap<String, Vec<(toml::Table, PathBuf)>> = BTreeMap::new();
+        let mut limits = Vec::new();
+        for (path, source) in layers {
+            let mut table = read_layer(&path)?;
+            anchor_paths(&mut table, path.parent().unwrap_or(Path::new(".")));
+            if let Source::Team(_) = source {
+                limits = TEAM_LIMITS.iter().filter_map(|k| table.get(*k).map(|v| (*k, v.clone()))).collect();
+            }
+            if let Some(defined) = table.remove("profiles") {
+                let toml::Value::Table(defined) = defined else { bail!("`profiles` in {} must be a table", path.display()) };
+                for (name, profile) in defined {
+                    let toml::Value::Table(profile) = profile else { bail!("`profiles.{}` in {} must be a table", name, path.display()) };
+                    profiles.entry(name).or_default().push((profile, path.clone()));
+                }
+            }
+            for (k, v) in table {
+                values.insert(k, (v, source.clone()));
+            }
+        }
+        let profile = PROFILE.get().cloned()
+            .or_else(|| env::var("RUSTAIGENT_PROFILE").ok().filter(|p| !p.is_empty()))
+            .or_else(|| values.get("profile").and_then(|(v, _)| v.as_str()).map(String::from));
+        for (name, _) in BUILTIN_PROFILES {
+            profiles.entry(name.to_string()).or_default();
+        }
+        if let Some(name) = &profile {
+            let Some(layers) = profiles.get(name) else {
+                let known: Vec<&str> = profiles.keys().map(String::as_str).collect();
+                bail!("Unknown profile `{}`; defined: {}", name, known.join(", "));
+            };
+            if let Some((builtin, text)) = BUILTIN_PROFILES.iter().find(|(builtin, _)| builtin == name) {
+                let table: toml::Table = toml::from_str(text).expect("built-in profiles parse");
+                for (k, v) in table {
+                    // Tables are merged, so the profile only changes the keys it names
+                    let v = match (values.remove(&k), v) {
+                        (Some((toml::Value::Table(mut configured), _)), toml::Value::Table(set)) => {
+                            configured.extend(set);
+                            toml::Value::Table(configured)
+                        }
+                        (_, v) => v,
+                    };
+                    values.insert(k, (v, Source::Builtin(builtin)));
+                }
+            }
+            for (table, path) in layers {
+                for (k, v) in table {
+                    values.insert(k.clone(), (v.clone(), Source::Profile(name.clone(), path.clone())));
+                }
+            }
+        }
+        for (key, limit) in limits {
+            if let Some((value, source)) = values.get_mut(key).filter(|(_, source)| !matches!(source, Source::Team(_))) {
+                *value = narrow(value, &limit);
+                tracing::debug!("{} from {} narrowed to the team's list", key, source);
+            }
+        }
+        let profiles = profiles.into_iter().map(|(name, layers)| (name, layers.into_iter().map(|(_, p)| p).collect())).collect();
+        Ok(Layered { values, profiles, profile })
+    }
+
+    /// Effective values including environment overrides, for `config show --merged`
+    pub fn with_env(mut self) -> Self {
+        for (var, key) in ENV_OVERRIDES {
+            if let Ok(raw) = env::var(var) {
+                let value = raw.parse::<i64>().map(toml::Value::Integer)
+                    .or_else(|_| raw.parse::<f64>().map(toml::Value::Float))
+                    .or_else(|_| raw.parse::<bool>().map(toml::Value::Boolean))
+                    .unwrap_or(toml::Value::String(raw));
+                self.values.insert(key.to_string(), (value, Source::Env(var)));
+            }
+        }
+        self
+    }
+
+    pub fn config(&self) -> Result<Config> {
+        let table: toml::Table = self.values.iter().map(|(k, (v, _))| (k.clone(), v.clone())).collect();
+        Config::deserialize(toml::Value::Table(table)).context("Invalid configuration")
+    }
+
+    /// Render as TOML lines annotated with their source
+    pub fn render(&self) -> String {
+        self.values.iter()
+            .map(|(k, (v, src))| format!("{} = {}  # {}\n", k, v, src))
+            .collect()
+    }
+}
+
+impl Config {
+    /// Merged team, user and project configuration with the selected profile; a broken file is
+    /// reported and ignored
+    pub fn load() -> Self {
+        match Layered::load().and_then(|l| l.config()) {
+            Ok(cfg) => cfg,
+            Err(err) => {
+                tracing::warn!("ignoring configuration: {:#}", err);
+                Config::default()
+            }
+        }
+    }
+
+    /// The system prompt from inline text or a referenced file, if configured; a relative file is
+    /// found from the config file that names it
+    pub fn system_prompt(&self) -> Result<Option<String>> {
+        if let Some(p) = &self.system_prompt {
+            return Ok(Some(p.clone()));
+        }
+        match &self.system_prompt_file {
+            Some(path) => Ok(Some(fs::read_to_string(path).with_context(|| format!("Failed to read system prompt {}", path.display()))?)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    String,
+    Integer,
+    Number,
+    Bool,
+    StringList,
+    /// A string naming a file that must exist
+    File,
+    /// The `[theme]` table of color specs
+    Theme,
+    /// The `[guardrails]` table
+    Guardrails,
+    /// The `[privacy]` table
+    Privacy,
+    /// The `[race]` table
+    Race,
+    /// The `[command_safety]` table
+    CommandSafety,
+    /// The `[codeowners]` table
+    Codeowners,
+    /// The `[experiment]` table
+    Experiment,
+    /// The `[suggestions]` table
+    Suggestions,
+    /// The `[[policy]]` rule list
+    Policy,
+    /// The `[permissions]` table of tool levels
+    Permissions,
+    /// The `[[post_process]]` step list
+    PostProcess,
+    /// The `[context]` table
+    Context,
+    /// The `[http]` table
+    Http,
+    /// The `[ollama]` table
+    Ollama,
+    /// The `[index]` table
+    Index,
+    /// The `[verify]` table
+    Verify,
+    /// `[capabilities."<model prefix>"]` tables
+    Capabilities,
+    /// `[pricing."<model prefix>"]` tables
+    Pricing,
+    /// `[mcp.<server>]` tables
+    Mcp,
+    /// `[auth.<provider>]` tables
+    Auth,
+    /// The `[vertex]` table
+    Vertex,
+    /// The `[plugins]` table
+    Plugins,
+    /// The `[huggingface]` table
+    HuggingFace,
+    /// The `[serve]` table and its `[serve.users.<name>]` tables
+    Serve,
+    /// `[turns.tools]` and `[turns.answer]` tables
+    Turns,
+    /// The `[sync]` table
+    Sync,
+    /// `[profiles.<name>]` tables of config keys
+    Profiles,
+    /// The `[roots]` table of named directories
+    Roots,
+    /// The `[model_aliases]` table of model names
+    ModelAliases,
+    /// The `[tags]` table of spend labels
+    Tags,
+}
+
+/// Every key the config understands, with its expected type
+const SCHEMA: &[(&str, Kind)] = &[
+    ("provider", Kind::String),
+    ("model", Kind::String),
+    ("max_tokens", Kind::Integer),
+    ("temperature", Kind::Number),
+    ("retry_count", Kind::Integer),
+    ("profile", Kind::String),
+    ("profiles", Kind::Profiles),
+    ("language", Kind::String),
+    ("system_prompt", Kind::String),
+    ("system_prompt_file", Kind::File),
+    ("allowed_tools", Kind::StringList),
+    ("allowed_models", Kind::StringList),
+    ("model_aliases", Kind::ModelAliases),
+    ("tags", Kind::Tags),
+    ("migrate_models", Kind::Bool),
+    ("theme", Kind::Theme),
+    ("reasoning_effort", Kind::String),
+    ("thinking_budget", Kind::Integer),
+    ("show_thinking", Kind::Bool),
+    ("persist_thinking", Kind::Bool),
+    ("guardrails", Kind::Guardrails),
+    ("privacy", Kind::Privacy),
+    ("race", Kind::Race),
+    ("command_safety", Kind::CommandSafety),
+    ("codeowners", Kind::Codeowners),
+    ("experiment", Kind::Experiment),
+    ("role", Kind::String),
+    ("policy", Kind::Policy),
+    ("permissions", Kind::Permissions),
+    ("approval_command", Kind::String),
+    ("injection_guard", Kind::String),
+    ("canary", Kind::Bool),
+    ("hot_reload", Kind::Bool),
+    ("prefetch", Kind::Bool),
+    ("actions_summary", Kind::Bool),
+    ("suggestions", Kind::Suggestions),
+    ("focus_file", Kind::String),
+    ("allowed_paths", Kind::StringList),
+    ("roots", Kind::Roots),
+    ("allowed_hosts", Kind::StringList),
+    ("allow_private_network", Kind::Bool),
+    ("http", Kind::Http),
+    ("memory_messages", Kind::Integer),
+    ("base_url", Kind::String),
+    ("openai_api", Kind::String),
+    ("hosted_tools", Kind::StringList),
+    ("vector_stores", Kind::StringList),
+    ("capabilities", Kind::Capabilities),
+    ("plan", Kind::Bool),
+    ("off_peak", Kind::String),
+    ("turns", Kind::Turns),
+    ("max_tool_rounds", Kind::Integer),
+    ("command_timeout_secs", Kind::Integer),
+    ("command_output_limit", Kind::Integer),
+    ("encrypt_request_logs", Kind::Bool),
+    ("max_file_bytes", Kind::Integer),
+    ("include_files", Kind::StringList),
+    ("artifact_threshold", Kind::Integer),
+    ("turn_timeout_secs", Kind::Integer),
+    ("turn_budget_usd", Kind::Number),
+    ("turn_retries", Kind::Integer),
+    ("retry_deadline_secs", Kind::Integer),
+    ("streaming", Kind::Bool),
+    ("sync", Kind::Sync),
+    ("encryption", Kind::String),
+    ("post_process", Kind::PostProcess),
+    ("context", Kind::Context),
+    ("ollama", Kind::Ollama),
+    ("index", Kind::Index),
+    ("verify", Kind::Verify),
+    ("pricing", Kind::Pricing),
+    ("mcp", Kind::Mcp),
+    ("plugins", Kind::Plugins),
+    ("auth", Kind::Auth),
+    ("vertex", Kind::Vertex),
+    ("huggingface", Kind::HuggingFace),
+    ("serve", Kind::Serve),
+];
+
+/// Old key names still seen in the wild, with their replacements
+const DEPRECATED: &[(&str, &str)] = &[
+    ("model_name", "model"),
+    ("api_provider", "provider"),
+    ("prompt_file", "system_prompt_file"),
+];
+
+pub(crate) const PROVIDERS: &[&str] = &["openai", "claude", "ollama", "google", "vertex", "huggingface", "xai", "deepseek"];
+
+/// Problems found in one config file
+#[derive(Default)]
+pub struct Report {
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+/// Check a parsed config layer against the schema; relative file paths are checked from the
+/// working directory, so layers read from a file are anchored to its directory first
+pub fn validate(table: &toml::Table) -> Report {
+    let mut report = Report::default();
+    for (key, value) in table {
+        if let Some((_, replacement)) = DEPRECATED.iter().find(|(old, _)| old == key) {
+            report.warnings.push(format!("`{}` is deprecated; use `{}`", key, replacement));
+            continue;
+        }
+        let Some((_, kind)) = SCHEMA.iter().find(|(name, _)| name == key) else {
+            report.warnings.push(format!("unknown key `{}`", key));
+            continue;
+        };
+        let ok = match kind {
+            Kind::String | Kind::File => value.is_str(),
+            Kind::Integer => value.is_integer(),
+            Kind::Number => value.is_integer() || value.is_float(),
+            Kind::Bool => value.is_bool(),
+            Kind::StringList => value.as_array().is_some_and(|a| a.iter().all(|v| v.is_str())),
+            Kind::Theme | Kind::Guardrails | Kind::Privacy | Kind::Race | Kind::CommandSafety | Kind::Codeowners | Kind::Experiment | Kind::Suggestions | Kind::Http | Kind::Sync | Kind::Permissions | Kind::Context | Kind::Ollama | Kind::Index | Kind::Verify | Kind::Vertex | Kind::Plugins | Kind::HuggingFace | Kind::Serve => value.is_table(),
+            Kind::Capabilities | Kind::Pricing | Kind::Mcp | Kind::Auth | Kind::Turns | Kind::Profiles => value.as_table().is_some_and(|t| t.values().all(|v| v.is_table())),
+            Kind::Policy | Kind::PostProcess => value.as_array().is_some_and(|a| a.iter().all(|v| v.is_table())),
+            Kind::Roots | Kind::ModelAliases | Kind::Tags => value.as_table().is_some_and(|t| t.values().all(|v| v.is_str())),
+        };
+        if !ok {
+            report.errors.push(format!("`{}` has the wrong type (found {})", key, value.type_str()));
+            continue;
+        }
+        if let (Kind::File, Some(path)) = (kind, value.as_str()) {
+            if !Path::new(path).is_file() {
+                report.errors.push(format!("`{}` references missing file {}", key, path));
+            }
+        }
+    }
+
+    if let Some(theme) = table.get("theme").and_then(|v| v.as_table()) {
+        for (key, value) in theme {
+            if !crate::theme::KEYS.contains(&key.as_str()) {
+                report.warnings.push(format!("unknown theme key `{}`", key));
+                continue;
+            }
+            match value.as_str() {
+                Some(spec) => if let Err(err) = crate::theme::parse_spec(spec, key == "code_bg") {
+                    report.errors.push(format!("`theme.{}`: {}", key, err));
+                },
+                None => report.errors.push(format!("`theme.{}` must be a string (found {})", key, value.type_str())),
+            }
+        }
+    }
+    if let Some(guardrails) = table.get("guardrails").and_then(|v| v.as_table()) {
+        for key in guardrails.keys().filter(|k| !crate::guardrails::KEYS.contains(&k.as_str())) {
+            report.warnings.push(format!("unknown guardrails key `{}`", key));
+        }
+        match crate::guardrails::GuardrailConfig::deserialize(toml::Value::Table(guardrails.clone())) {
+            Ok(cfg) => {
+                if let Some(mode) = cfg.mode.as_deref().filter(|m| !crate::guardrails::MODES.contains(m)) {
+                    report.errors.push(format!("`guardrails.mode` must be one of {} (found `{}`)", crate::guardrails::MODES.join(", "), mode));
+                }
+                if let Err(err) = crate::guardrails::Guardrails::from_config(Some(&cfg)) {
+                    report.errors.push(format!("`guardrails`: {:#}", err));
+                }
+            }
+            Err(err) => report.errors.push(format!("`guardrails`: {}", err)),
+        }
+    }
+    if let Some(privacy) = table.get("privacy").and_then(|v| v.as_table()) {
+        for key in privacy.keys().filter(|k| !crate::privacy::KEYS.contains(&k.as_str())) {
+            report.warnings.push(format!("unknown privacy key `{}`", key));
+        }
+        match crate::privacy::PrivacyConfig::deserialize(toml::Value::Table(privacy.clone())) {
+            Ok(cfg) => {
+                if let Some(provider) = cfg.local_provider.as_deref().filter(|p| !PROVIDERS.contains(p)) {
+                    report.errors.push(format!("`privacy.local_provider` must be one of {} (found `{}`)", PROVIDERS.join(", "), provider));
+                }
+                if let Some(url) = cfg.local_base_url.as_deref().filter(|u| !u.starts_with("http://") && !u.starts_with("https://")) {
+                    report.errors.push(format!("`privacy.local_base_url` must start with http:// or https:// (found `{}`)", url));
+                }
+                if let Err(err) = crate::privacy::Router::from_config(Some(&cfg), "") {
+                    report.errors.push(format!("`privacy`: {:#}", err));
+                }
+            }
+            Err(err) => report.errors.push(format!("`privacy`: {}", err)),
+        }
+    }
+    if let Some(race) = table.get("race").and_then(|v| v.as_table()) {
+        for key in race.keys().filter(|k| !crate::race::KEYS.contains(&k.as_str())) {
+            report.warnings.push(format!("unknown race key `{}`", key));
+        }
+        match crate::race::RaceConfig::deserialize(toml::Value::Table(race.clone())) {
+            Ok(cfg) => {
+                if !PROVIDERS.contains(&cfg.provider.as_str()) {
+                    report.errors.push(format!("`race.provider` must be one of {} (found `{}`)", PROVIDERS.join(", "), cfg.provider));
+                }
+                if let Some(url) = cfg.base_url.as_deref().filter(|u| !u.starts_with("http://") && !u.starts_with("https://")) {
+                    report.errors.push(format!("`race.base_url` must start with http:// or https:// (found `{}`)", url));
+                }
+            }
+            Err(err) => report.errors.push(format!("`race`: {}", err)),
+        }
+    }
+    if let Some(safety) = table.get("command_safety").and_then(|v| v.as_table()) {
+        for key in safety.keys().filter(|k| !crate::risk::KEYS.contains(&k.as_str())) {
+            report.warnings.push(format!("unknown command_safety key `{}`", key));
+        }
+        match crate::risk::SafetyConfig::deserialize(toml::Value::Table(safety.clone())) {
+            Ok(cfg) => {
+                if let Some(score) = cfg.ask_score.filter(|s| !(1..=10).contains(s)) {
+                    report.errors.push(format!("`command_safety.ask_score` must be between 1 and 10 (found {})", score));
+                }
+            }
+            Err(err) => report.errors.push(format!("`command_safety`: {}", err)),
+        }
+    }
+    if let Some(owners) = table.get("codeowners").and_then(|v| v.as_table()) {
+        for key in owners.keys().filter(|k| !crate::owners::KEYS.contains(&k.as_str())) {
+            report.warnings.push(format!("unknown codeowners key `{}`", key));
+        }
+        if let Err(err) = crate::owners::OwnersConfig::deserialize(toml::Value::Table(owners.clone())) {
+            report.errors.push(format!("`codeowners`: {}", err));
+        }
+    }
+    if let Some(suggestions) = table.get("suggestions").and_then(|v| v.as_table()) {
+        for key in suggestions.keys().filter(|k| !crate::suggest::KEYS.contains(&k.as_str())) {
+            report.warnings.push(format!("unknown suggestions key `{}`", key));
+        }
+        if let Err(err) = crate::suggest::SuggestConfig::deserialize(toml::Value::Table(suggestions.clone())) {
+            report.errors.push(format!("`suggestions`: {}", err));
+        }
+    }
+    if let Some(experiment) = table.get("experiment").and_then(|v| v.as_table()) {
+        for key in experiment.keys().filter(|k| !crate::experiment::KEYS.contains(&k.as_str())) {
+            report.warnings.push(format!("unknown experiment key `{}`", key));
+        }
+        for (name, variant) in ["a", "b"].iter().filter_map(|name| experiment.get(*name).and_then(|v| v.as_table()).map(|v| (name, v))) {
+            for key in variant.keys().filter(|k| !crate::experiment::VARIANT_KEYS.contains(&k.as_str())) {
+                report.warnings.push(format!("unknown experiment.{} key `{}`", name, key));
+            }
+            if let Some(path) = variant.get("system_prompt_file").and_then(|v| v.as_str()).filter(|p| !Path::new(p).is_file()) {
+                report.errors.push(format!("`experiment.{}.system_prompt_file` references missing file {}", name, path));
+            }
+        }
+        match crate::experiment::ExperimentConfig::deserialize(toml::Value::Table(experiment.clone())) {
+            Ok(cfg) => {
+                if cfg.name.trim().is_empty() {
+                    report.errors.push("`experiment.name` must not be empty".into());
+                }
+                if let Some(split) = cfg.split.filter(|s| !(0.0..=1.0).contains(s)) {
+                    report.errors.push(format!("`experiment.split` must be between 0 and 1 (found {})", split));
+                }
+            }
+            Err(err) => report.errors.push(format!("`experiment`: {}", err)),
+        }
+    }
+    if let Some(rules) = table.get("policy").and_then(|v| v.as_array()) {
+        for (i, rule) in rules.iter().enumerate() {
+            let checked = crate::policy::Rule::deserialize(rule.clone()).map_err(anyhow::Error::from).and_then(|r| r.check());
+            if let Err(err) = checked {
+                report.errors.push(format!("policy rule #{}: {:#}", i + 1, err));
+            }
+        }
+    }
+    if let Some(steps) = table.get("post_process").and_then(|v| v.as_array()) {
+        for (i, step) in steps.iter().enumerate() {
+            let checked = crate::postprocess::Step::deserialize(step.clone()).map_err(anyhow::Error::from).and_then(|s| s.check());
+            if let Err(err) = checked {
+                report.errors.push(format!("post_process step #{}: {:#}", i + 1, err));
+            }
+        }
+    }
+    if let Some(roots) = table.get("roots").and_then(|v| v.as_table()) {
+        for name in roots.keys().filter(|n| n.is_empty() || !n.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')) {
+            report.errors.push(format!("`roots.{}`: root names may only hold letters, digits, - and _", name));
+        }
+    }
+    if let Some(tags) = table.get("tags").and_then(|v| v.as_table()) {
+        for (name, value) in tags {
+            if let Err(err) = crate::stats::parse_tag(&format!("{}={}", name, value.as_str().unwrap_or_default())) {
+                report.errors.push(format!("`tags.{}`: {:#}", name, err));
+            }
+        }
+    }
+    if let Some(model) = table.get("model").and_then(|v| v.as_str()) {
+        let provider = table.get("provider").and_then(|v| v.as_str()).unwrap_or("openai");
+        let aliases = table.get("model_aliases").and_then(|v| v.as_table());
+        let model = aliases.and_then(|a| a.get(model)).and_then(|v| v.as_str()).unwrap_or(model);
+        if let Some(notice) = crate::aliases::notice(provider, model) {
+            report.warnings.push(format!("`model`: {}", notice));
+        }
+    }
+    if let Some(permissions) = table.get("permissions").and_then(|v| v.as_table()) {
+        let tools = crate::tools::ToolCall::definitions();
+        for (tool, level) in permissions {
+            // MCP tools, `<server>__<tool>`, are only known once their servers run
+            if !tools.iter().any(|f| &f.name == tool) && !tool.contains("__") {
+                report.warnings.push(format!("unknown tool `permissions.{}`", tool));
+            }
+            if !level.as_str().is_some_and(|l| crate::policy::LEVELS.contains(&l)) {
+                report.errors.push(format!("`permissions.{}` must be one of {} (found {})", tool, crate::policy::LEVELS.join(", "), level));
+            }
+        }
+    }
+    if let Some(http) = table.get("http").and_then(|v| v.as_table()) {
+        for (key, value) in http {
+            if !crate::http::KEYS.contains(&key.as_str()) {
+                report.warnings.push(format!("unknown http key `{}`", key));
+                continue;
+            }
+            let ok = if key == "http2_adaptive_window" { value.is_bool() } else { value.as_integer().is_some_and(|n| n >= 0) };
+            if !ok {
+                report.errors.push(format!("`http.{}` must be {} (found {})", key, if key == "http2_adaptive_window" { "a boolean" } else { "a non-negative integer" }, value));
+            }
+        }
+    }
+    if let Some(context) = table.get("context").and_then(|v| v.as_table()) {
+        for key in context.keys().filter(|k| !crate::context::KEYS.contains(&k.as_str())) {
+            report.warnings.push(format!("unknown context key `{}`", key));
+        }
+        match crate::context::ContextConfig::deserialize(toml::Value::Table(context.clone())) {
+            Ok(cfg) => {
+                if let Some(strategy) = cfg.strategy.as_deref().filter(|s| !crate::context::STRATEGIES.contains(s)) {
+                    report.errors.push(format!("`context.strategy` must be one of {} (found `{}`)", crate::context::STRATEGIES.join(", "), strategy));
+                }
+            }
+            Err(err) => report.errors.push(format!("`context`: {}", err)),
+        }
+    }
+    if let Some(ollama) = table.get("ollama").and_then(|v| v.as_table()) {
+        for key in ollama.keys().filter(|k| !crate::models::KEYS.contains(&k.as_str())) {
+            report.warnings.push(format!("unknown ollama key `{}`", key));
+        }
+        match crate::models::OllamaConfig::deserialize(toml::Value::Table(ollama.clone())) {
+            Ok(cfg) => {
+                if let Some(gb) = cfg.vram_gb.filter(|&gb| gb <= 0.0) {
+                    report.errors.push(format!("`ollama.vram_gb` must be positive (found {})", gb));
+                }
+            }
+            Err(err) => report.errors.push(format!("`ollama`: {}", err)),
+        }
+    }
+    if let Some(verify) = table.get("verify").and_then(|v| v.as_table()) {
+        for key in verify.keys().filter(|k| !crate::verify::KEYS.contains(&k.as_str())) {
+            report.warnings.push(format!("unknown verify key `{}`", key));
+        }
+        if let Err(err) = crate::verify::VerifyConfig::deserialize(toml::Value::Table(verify.clone())) {
+            report.errors.push(format!("`verify`: {}", err));
+        }
+    }
+    if let Some(sync) = table.get("sync").and_then(|v| v.as_table()) {
+        for key in sync.keys().filter(|k| !crate::sync::KEYS.contains(&k.as_str())) {
+            report.warnings.push(format!("unknown sync key `{}`", key));
+        }
+        match sync.get("backend").map(|v| v.as_str()) {
+            Some(Some(b)) if !crate::sync::BACKENDS.contains(&b) => {
+                report.errors.push(format!("`sync.backend` must be one of {} (found `{}`)", crate::sync::BACKENDS.join(", "), b));
+            }
+            Some(None) => report.errors.push("`sync.backend` must be a string".into()),
+            _ => {}
+        }
+        if !sync.get("url").is_some_and(|v| v.is_str()) {
+            report.errors.push("`sync.url` must be set to the git remote, s3:// URL or WebDAV collection".into());
+        }
+    }
+    if let Some(models) = table.get("capabilities").and_then(|v| v.as_table()) {
+        for (model, caps) in models.iter().filter_map(|(m, v)| v.as_table().map(|t| (m, t))) {
+            for (key, value) in caps {
+                if !crate::capabilities::KEYS.contains(&key.as_str()) {
+                    report.warnings.push(format!("unknown capabilities key `{}`", key));
+                    continue;
+                }
+                let ok = if key == "max_context" { value.as_integer().is_some_and(|n| (1..=u32::MAX as i64).contains(&n)) } else { value.is_bool() };
+                if !ok {
+                    report.errors.push(format!("`capabilities.\"{}\".{}` must be {} (found {})", model, key, if key == "max_context" { "a positive integer" } else { "a boolean" }, value));
+                }
+            }
+        }
+    }
+    if let Some(models) = table.get("pricing").and_then(|v| v.as_table()) {
+        for (model, price) in models.iter().filter_map(|(m, v)| v.as_table().map(|t| (m, t))) {
+            for (key, value) in price {
+                if !crate::stats::KEYS.contains(&key.as_str()) {
+                    report.warnings.push(format!("unknown pricing key `{}`", key));
+                    continue;
+                }
+                if !value.as_float().or_else(|| value.as_integer().map(|i| i as f64)).is_some_and(|p| p >= 0.0) {
+                    report.errors.push(format!("`pricing.\"{}\".{}` must be a non-negative number of USD per million tokens (found {})", model, key, value));
+                }
+            }
+        }
+    }
+    if let Some(plugins) = table.get("plugins").and_then(|v| v.as_table()) {
+        for key in plugins.keys().filter(|k| !crate::plugins::KEYS.contains(&k.as_str())) {
+            report.warnings.push(format!("unknown plugins key `{}`", key));
+        }
+        match crate::plugins::PluginsConfig::deserialize(toml::Value::Table(plugins.clone())) {
+            Ok(cfg) => {
+                if let Some(url) = cfg.registry.filter(|u| !u.starts_with("https://") && !u.starts_with("http://")) {
+                    report.errors.push(format!("`plugins.registry` must be an http(s) URL (found `{}`)", url));
+                }
+                for key in cfg.trusted_keys.iter().flatten().filter(|k| minisign_verify::PublicKey::from_base64(k.trim()).is_err()) {
+                    report.errors.push(format!("`plugins.trusted_keys` has an invalid minisign public key `{}`", key));
+                }
+            }
+            Err(err) => report.errors.push(format!("`plugins`: {}", err)),
+        }
+    }
+    if let Some(vertex) = table.get("vertex").and_then(|v| v.as_table()) {
+        for key in vertex.keys().filter(|k| !crate::gcp::KEYS.contains(&k.as_str())) {
+            report.warnings.push(format!("unknown vertex key `{}`", key));
+        }
+        match crate::gcp::VertexConfig::deserialize(toml::Value::Table(vertex.clone())) {
+            Ok(cfg) => {
+                if let Some(path) = cfg.credentials.filter(|p| !p.is_file()) {
+                    report.errors.push(format!("`vertex.credentials` references missing file {}", path.display()));
+                }
+                if let Some(location) = cfg.location.filter(|l| l.is_empty() || !l.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')) {
+                    report.errors.push(format!("`vertex.location` must be a region such as us-central1, or global (found `{}`)", location));
+                }
+            }
+            Err(err) => report.errors.push(format!("`vertex`: {}", err)),
+        }
+    }
+    if let Some(hf) = table.get("huggingface").and_then(|v| v.as_table()) {
+        for key in hf.keys().filter(|k| !crate::providers::HUGGINGFACE_KEYS.contains(&k.as_str())) {
+            report.warnings.push(format!("unknown huggingface key `{}`", key));
+        }
+        match crate::providers::HuggingFaceConfig::deserialize(toml::Value::Table(hf.clone())) {
+            Ok(cfg) => {
+                if let Some(endpoint) = cfg.endpoint.filter(|e| !e.starts_with("https://") && !e.starts_with("http://")) {
+                    report.errors.push(format!("`huggingface.endpoint` must be an http(s) URL (found `{}`)", endpoint));
+                }
+            }
+            Err(err) => report.errors.push(format!("`huggingface`: {}", err)),
+        }
+    }
+    if let Some(providers) = table.get("auth").and_then(|v| v.as_table()) {
+        for (provider, cfg) in providers.iter().filter_map(|(p, v)| v.as_table().map(|t| (p, t))) {
+            if !PROVIDERS.contains(&provider.as_str()) {
+                report.errors.push(format!("`auth.{}` must name one of {}", provider, PROVIDERS.join(", ")));
+            }
+            for key in cfg.keys().filter(|k| !crate::auth::KEYS.contains(&k.as_str())) {
+                report.warnings.push(format!("unknown auth key `{}`", key));
+            }
+            let checked = crate::auth::AuthConfig::deserialize(toml::Value::Table(cfg.clone())).map_err(anyhow::Error::from).and_then(|c| c.check());
+            if let Err(err) = checked {
+                report.errors.push(format!("`auth.{}`: {:#}", provider, err));
+            }
+        }
+    }
+    if let Some(servers) = table.get("mcp").and_then(|v| v.as_table()) {
+        for (server, cfg) in servers.iter().filter_map(|(s, v)| v.as_table().map(|t| (s, t))) {
+            for key in cfg.keys().filter(|k| !crate::mcp::KEYS.contains(&k.as_str())) {
+                report.warnings.push(format!("unknown mcp key `{}`", key));
+            }
+            let checked = crate::mcp::ServerConfig::deserialize(toml::Value::Table(cfg.clone())).map_err(anyhow::Error::from).and_then(|c| c.check());
+            if let Err(err) = checked {
+                report.errors.push(format!("`mcp.{}`: {:#}", server, err));
+            }
+        }
+    }
+    if let Some(index) = table.get("index").and_then(|v| v.as_table()) {
+        for key in index.keys().filter(|k| !crate::index::KEYS.contains(&k.as_str())) {
+            report.warnings.push(format!("unknown index key `{}`", key));
+        }
+        match crate::index::IndexConfig::deserialize(toml::Value::Table(index.clone())) {
+            Ok(cfg) => {
+                if let Some(provider) = cfg.provider.filter(|p| !crate::index::PROVIDERS.contains(&p.as_str())) {
+                    report.errors.push(format!("`index.provider` must be one of {} (found `{}`)", crate::index::PROVIDERS.join(", "), provider));
+                }
+                if cfg.chunk_lines == Some(0) {
+                    report.errors.push("`index.chunk_lines` must be at least 1".into());
+                }
+                for glob in cfg.exclude.iter().flatten().filter(|g| glob::Pattern::new(g).is_err()) {
+                    report.errors.push(format!("`index.exclude` has an invalid glob `{}`", glob));
+                }
+                if let Some(share) = cfg.share.filter(|s| !s.starts_with("s3://") && !s.starts_with("gs://")) {
+                    report.errors.push(format!("`index.share` must look like s3://bucket/prefix or gs://bucket/prefix (found `{}`)", share));
+                }
+            }
+            Err(err) => report.errors.push(format!("`index`: {}", err)),
+        }
+    }
+    if let Some(serve) = table.get("serve").and_then(|v| v.as_table()) {
+        for key in serve.keys().filter(|k| !crate::tenants::KEYS.contains(&k.as_str())) {
+            report.warnings.push(format!("unknown serve key `{}`", key));
+        }
+        let users = serve.get("users").and_then(|v| v.as_table()).into_iter().flatten();
+        for (user, cfg) in users.filter_map(|(u, v)| v.as_table().map(|t| (u, t))) {
+            for key in cfg.keys().filter(|k| !crate::tenants::USER_KEYS.contains(&k.as_str())) {
+                report.warnings.push(format!("unknown key `serve.users.{}.{}`", user, key));
+            }
+        }
+        let checked = crate::tenants::ServeConfig::deserialize(toml::Value::Table(serve.clone())).map_err(anyhow::Error::from).and_then(|c| c.check());
+        if let Err(err) = checked {
+            report.errors.push(format!("`serve`: {:#}", err));
+        }
+    }
+    if let Some(turns) = table.get("turns").and_then(|v| v.as_table()) {
+        for (kind, params) in turns.iter().filter_map(|(k, v)| v.as_table().map(|t| (k, t))) {
+            if !crate::turns::KINDS.contains(&kind.as_str()) {
+                report.warnings.push(format!("unknown turns table `{}`", kind));
+                continue;
+            }
+            for (key, value) in params {
+                let ok = match key.as_str() {
+                    "model" => value.is_str(),
+                    "temperature" => value.as_float().or_else(|| value.as_integer().map(|i| i as f64)).is_some_and(|t| (0.0..=2.0).contains(&t)),
+                    "max_tokens" => value.as_integer().is_some_and(|n| (1..=u16::MAX as i64).contains(&n)),
+                    _ => {
+                        report.warnings.push(format!("unknown turns key `{}`", key));
+                        continue;
+                    }
+                };
+                if !ok {
+                    let expected = match key.as_str() { "model" => "a string", "temperature" => "a number between 0.0 and 2.0", _ => "a positive integer" };
+                    report.errors.push(format!("`turns.{}.{}` must be {} (found {})", kind, key, expected, value));
+                }
+            }
+        }
+    }
+    if let Some(profiles) = table.get("profiles").and_then(|v| v.as_table()) {
+        for (name, profile) in profiles.iter().filter_map(|(n, v)| v.as_table().map(|t| (n, t))) {
+            if profile.contains_key("profiles") || profile.contains_key("profile") {
+                report.errors.push(format!("`profiles.{}` cannot select or define profiles", name));
+            }
+            let nested = validate(profile);
+            report.errors.extend(nested.errors.into_iter().map(|e| format!("profiles.{}: {}", name, e)));
+            report.warnings.extend(nested.warnings.into_iter().map(|w| format!("profiles.{}: {}", name, w)));
+        }
+    }
+    if let Some(spec) = table.get("off_peak").and_then(|v| v.as_str()) {
+        if let Err(err) = crate::queue::Window::parse(spec) {
+            report.errors.push(format!("{:#}", err));
+        }
+    }
+    if let Some(p) = table.get("provider").and_then(|v| v.as_str()) {
+        if !PROVIDERS.contains(&p) {
+            report.errors.push(format!("`provider` must be one of {} (found `{}`)", PROVIDERS.join(", "), p));
+        }
+    }
+    if let Some(t) = table.get("temperature").and_then(|v| v.as_float().or_else(|| v.as_integer().map(|i| i as f64))) {
+        if !(0.0..=2.0).contains(&t) {
+            report.errors.push(format!("`temperature` must be between 0.0 and 2.0 (found {})", t));
+        }
+    }
+    if let Some(n) = table.get("max_tokens").and_then(|v| v.as_integer()) {
+        if !(1..=u16::MAX as i64).contains(&n) {
+            report.errors.push(format!("`max_tokens` must be between 1 and {} (found {})", u16::MAX, n));
+        }
+    }
+    if let Some(n) = table.get("retry_count").and_then(|v| v.as_integer()) {
+        if !(0..=u8::MAX as i64).contains(&n) {
+            report.errors.push(format!("`retry_count` must be between 0 and {} (found {})", u8::MAX, n));
+        }
+    }
+    if let Some(url) = table.get("base_url").and_then(|v| v.as_str()) {
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            report.errors.push(format!("`base_url` must start with http:// or https:// (found `{}`)", url));
+        }
+    }
+    if table.get("approval_command").and_then(|v| v.as_str()).is_some_and(|c| c.trim().is_empty()) {
+        report.errors.push("`approval_command` is empty; remove it to approve on the terminal".into());
+    }
+    for host in table.get("allowed_hosts").and_then(|v| v.as_array()).into_iter().flatten().filter_map(|v| v.as_str()) {
+        let name = host.strip_prefix("*.").unwrap_or(host);
+        if host != "*" && (name.is_empty() || name.contains(['*', '/', ':', ' '])) {
+            report.errors.push(format!("`allowed_hosts` entries must be host names such as `docs.rs` or `*.github.com` (found `{}`)", host));
+        }
+    }
+    if let Some(mode) = table.get("injection_guard").and_then(|v| v.as_str()) {
+        if !crate::untrusted::MODES.contains(&mode) {
+            report.errors.push(format!("`injection_guard` must be one of {} (found `{}`)", crate::untrusted::MODES.join(", "), mode));
+        }
+    }
+    if let Some(api) = table.get("openai_api").and_then(|v| v.as_str()) {
+        if !crate::providers::OPENAI_APIS.contains(&api) {
+            report.errors.push(format!("`openai_api` must be one of {} (found `{}`)", crate::providers::OPENAI_APIS.join(", "), api));
+        }
+    }
+    if let Some(tools) = table.get("hosted_tools").and_then(|v| v.as_array()) {
+        for tool in tools.iter().filter_map(|t| t.as_str()).filter(|t| !crate::providers::HOSTED_TOOLS.contains(t)) {
+            report.errors.push(format!("`hosted_tools` entries must be one of {} (found `{}`)", crate::providers::HOSTED_TOOLS.join(", "), tool));
+        }
+        if tools.iter().any(|t| t.as_str() == Some("file_search")) && !table.contains_key("vector_stores") {
+            report.warnings.push("`file_search` in `hosted_tools` has nothing to search without `vector_stores`".into());
+        }
+    }
+    if let Some(n) = table.get("memory_messages").and_then(|v| v.as_integer()) {
+        if n < 2 {
+            report.errors.push(format!("`memory_messages` must be at least 2 (found {})", n));
+        }
+    }
+    if let Some(n) = table.get("max_tool_rounds").and_then(|v| v.as_integer()) {
+        if n < 1 {
+            report.errors.push(format!("`max_tool_rounds` must be at least 1 (found {})", n));
+        }
+    }
+    if let Some(n) = table.get("turn_retries").and_then(|v| v.as_integer()).filter(|n| u32::try_from(*n).is_err()) {
+        report.errors.push(format!("`turn_retries` must be between 0 and {} (found {})", u32::MAX, n));
+    }
+    for key in ["command_timeout_secs", "command_output_limit", "turn_timeout_secs", "retry_deadline_secs", "max_file_bytes"] {
+        if let Some(n) = table.get(key).and_then(|v| v.as_integer()).filter(|n| *n < 1) {
+            report.errors.push(format!("`{}` must be at least 1 (found {})", key, n));
+        }
+    }
+    for glob in table.get("include_files").and_then(|v| v.as_array()).into_iter().flatten().filter_map(|v| v.as_str()) {
+        if glob::Pattern::new(glob).is_err() {
+            report.errors.push(format!("`include_files` has an invalid glob `{}`", glob));
+        }
+    }
+    if let Some(n) = table.get("artifact_threshold").and_then(|v| v.as_integer()).filter(|n| *n < 0) {
+        report.errors.push(format!("`artifact_threshold` must not be negative (found {})", n));
+    }
+    if let Some(usd) = table.get("turn_budget_usd").and_then(|v| v.as_float().or_else(|| v.as_integer().map(|n| n as f64))) {
+        if usd <= 0.0 {
+            report.errors.push(format!("`turn_budget_usd` must be positive (found {})", usd));
+        }
+    }
+    if let Some(e) = table.get("reasoning_effort").and_then(|v| v.as_str()) {
+        if !crate::reasoning::EFFORTS.contains(&e) {
+            report.errors.push(format!("`reasoning_effort` must be one of {} (found `{}`)", crate::reasoning::EFFORTS.join(", "), e));
+        }
+    }
+    if let Some(m) = table.get("encryption").and_then(|v| v.as_str()) {
+        if !crate::vault::MODES.contains(&m) {
+            report.errors.push(format!("`encryption` must be one of {} (found `{}`)", crate::vault::MODES.join(", "), m));
+        }
+    }
+    if table.contains_key("system_prompt") && table.contains_key("system_prompt_file") {
+        report.warnings.push("both `system_prompt` and `system_prompt_file` are set; the inline prompt wins".into());
+    }
+    report
+}
+
+/// Validate every config file that exists, returning `(path, report)` pairs; parse failures count as errors
+pub fn validate_files() -> Vec<(PathBuf, Report)> {
+    let candidates = [paths::team_config_file(), Some(paths::user_config_file()), paths::project_config_file()];
+    candidates.into_iter().flatten().filter(|p| p.is_file()).map(|path| {
+        let report = validate_file(&path);
+        (path, report)
+    }).collect()
+}
+
+/// Validate one config file, with the files it references found from its directory
+fn validate_file(path: &PathBuf) -> Report {
+    match read_layer(path) {
+        Ok(mut table) => {
+            anchor_paths(&mut table, path.parent().unwrap_or(Path::new(".")));
+            validate(&table)
+        }
+        Err(err) => Report { errors: vec![format!("{:#}", err)], warnings: Vec::new() },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use super::{validate_file, Layered, Source};
+
+    #[test]
+    fn layers_resolve_prompt_files_from_their_directory_and_keep_team_limits() {
+        let dir = std::env::temp_dir().join(format!("rustaigent-layers-{}", std::process::id()));
+        let team = dir.join(".rustaigent").join("config.toml");
+        let user = dir.join("user.toml");
+        fs::create_dir_all(team.parent().unwrap()).unwrap();
+        fs::write(&team, "system_prompt_file = \"prompt.md\"\nallowed_tools = [\"read_file\", \"list_dir\"]\n").unwrap();
+        fs::write(&user, "allowed_tools = [\"list_dir\", \"run_command\"]\nallowed_models = [\"gpt-4o\"]\n").unwrap();
+        let merged = Layered::merge([(team.clone(), Source::Team(team.clone())), (user.clone(), Source::User(user))]).unwrap().config().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(merged.system_prompt_file, Some(dir.join(".rustaigent").join("prompt.md")));
+        assert_eq!(merged.allowed_tools, Some(vec!["list_dir".to_string()]));
+        assert_eq!(merged.allowed_models, Some(vec!["gpt-4o".to_string()]));
+    }
+
+    #[test]
+    fn referenced_files_are_checked_from_the_config_files_directory() {
+        let dir = std::env::temp_dir().join(format!("rustaigent-validate-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("prompt.md"), "Be brief.").unwrap();
+        let config = dir.join("config.toml");
+        fs::write(&config, "system_prompt_file = \"prompt.md\"\n\n[experiment]\nname = \"tone\"\n[experiment.a]\nsystem_prompt_file = \"prompt.md\"\n[experiment.b]\nsystem_prompt_file = \"missing.md\"\n").unwrap();
+        let report = validate_file(&config);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(report.errors.len(), 1, "{:?}", report.errors);
+        assert!(report.errors[0].starts_with("`experiment.b.system_prompt_file` references missing file") && report.errors[0].ends_with("missing.md"), "{}", report.errors[0]);
+    }
+}
@@ -0,0 +1,206 @@
+//! Token estimates and the context budget: how much of the model's window the prompt may use
+//! before older turns are dropped or summarized.
+
+use std::collections::BTreeMap;
+use serde::{Serialize, Deserialize};
+use crate::{ChatMessage, FileRef, FunctionDefinition};
+
+/// `[context]` table in the config file
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct ContextConfig {
+    /// `drop` (default) or `summarize`
+    pub strategy: Option<String>,
+    /// Prompt tokens allowed for every model (default: its context window minus `max_tokens`)
+    pub budget: Option<u32>,
+    /// Budgets by model name prefix; the longest match wins over `budget`
+    pub models: Option<BTreeMap<String, u32>>,
+}
+
+/// Keys accepted in `[context]`, for config validation
+pub const KEYS: &[&str] = &["strategy", "budget", "models"];
+
+pub const STRATEGIES: &[&str] = &["drop", "summarize"];
+
+/// Name marking the system note that stands in for summarized turns
+pub const SUMMARY: &str = "context_summary";
+
+/// Framing every chat message costs on top of its text
+const MESSAGE_OVERHEAD: u32 = 4;
+
+/// Width of the largest item's bar in `/context`
+const BAR: usize = 16;
+
+/// Characters of each item's content shown in `/context`
+const PREVIEW: usize = 48;
+
+/// Rough BPE count: about four characters per token for ASCII text and one per token for other
+/// scripts, where tokenizers split much finer
+pub fn estimate_text(text: &str) -> u32 {
+    let (ascii, other) = text.chars().fold((0u32, 0u32), |(a, o), c| if c.is_ascii() { (a + 1, o) } else { (a, o + 1) });
+    ascii.div_ceil(4) + other
+}
+
+pub fn estimate(message: &ChatMessage) -> u32 {
+    let call = message.function_call.as_ref().map_or(0, |c| estimate_text(&c.name) + estimate_text(&c.arguments));
+    MESSAGE_OVERHEAD + estimate_text(&message.content) + message.name.as_deref().map_or(0, estimate_text) + call
+}
+
+/// Messages plus, when they are sent along, the tool definitions
+pub fn estimate_prompt(messages: &[ChatMessage], functions: Option<&[FunctionDefinition]>) -> u32 {
+    let tools = functions.map_or(0, |f| estimate_text(&serde_json::to_string(f).unwrap_or_default()));
+    messages.iter().map(estimate).sum::<u32>() + tools
+}
+
+/// What a message is, as `/context` lists it
+pub fn kind(index: usize, message: &ChatMessage) -> String {
+    match (message.role.as_str(), message.name.as_deref()) {
+        ("system", _) if index == 0 => "system prompt".into(),
+        ("system", Some(SUMMARY)) => "summary".into(),
+        ("system", Some(crate::index::NOTE)) => "retrieval".into(),
+        ("system", Some(crate::gitdiff::NOTE)) => "git diff".into(),
+        ("system", Some(crate::focus::NOTE)) => "editor focus".into(),
+        ("system", Some(crate::reload::NOTE)) => "config reload".into(),
+        ("system", Some(crate::actions::NOTE)) => "actions".into(),
+        ("system", _) => "note".into(),
+        ("user", _) if message.content.starts_with("[source ") => "sources".into(),
+        ("user", _) if message.content.starts_with("Output of `") => "shell output".into(),
+        ("user", _) => "prompt".into(),
+        ("assistant", _) => message.function_call.as_ref().map_or_else(|| "answer".into(), |call| format!("call {}", call.name)),
+        ("function", name) => format!("output {}", name.unwrap_or("tool")),
+        (role, _) => role.into(),
+    }
+}
+
+/// First line of what a message says, shortened for one row
+fn preview(message: &ChatMessage) -> String {
+    let text = message.function_call.as_ref().map_or(message.content.as_str(), |call| call.arguments.as_str());
+    let line = text.lines().map(str::trim).find(|l| !l.is_empty()).unwrap_or_default();
+    match line.char_indices().nth(PREVIEW) {
+        Some((end, _)) => format!("{}…", &line[..end]),
+        None => line.to_string(),
+    }
+}
+
+/// `/context` table: every message with its estimated tokens, its share of the prompt and, unless
+/// `bars` is off, a bar scaled to the largest item; then the tool definitions and the uploads
+/// waiting for the next prompt. Uploaded files are held by the provider and not counted.
+pub fn inspect(messages: &[ChatMessage], tools: u32, pending: &[FileRef], bars: bool) -> String {
+    let mut rows: Vec<(String, String, u32, String)> = messages.iter().enumerate()
+        .map(|(i, m)| (i.to_string(), kind(i, m), estimate(m), preview(m)))
+        .collect();
+    if tools > 0 {
+        rows.push((String::new(), "tool definitions".into(), tools, String::new()));
+    }
+    let total = rows.iter().map(|r| r.2).sum::<u32>().max(1);
+    let largest = rows.iter().map(|r| r.2).max().unwrap_or(1).max(1);
+    let header = format!("{:>4}  {:<22} {:>7} {:>6}  {}", "#", "item", "tokens", "share", if bars { "" } else { "content" });
+    let mut out = format!("{}\n", header.trim_end());
+    for (i, (index, kind, tokens, preview)) in rows.iter().enumerate() {
+        let share = *tokens as f64 * 100.0 / total as f64;
+        let bar = if bars {
+            let filled = (*tokens as usize * BAR).div_ceil(largest as usize);
+            format!("{}{}  ", "█".repeat(filled), "░".repeat(BAR - filled))
+        } else {
+            String::new()
+        };
+        let row = format!("{:>4}  {:<22} {:>7} {:>5.1}%  {}{}", index, kind, tokens, share, bar, preview);
+        out.push_str(row.trim_end());
+        out.push('\n');
+        for file in messages.get(i).into_iter().flat_map(|m| &m.files) {
+            out.push_str(&format!("{:>4}  └ file {} ({}, held by {})\n", "", file.name, file.mime, file.provider));
+        }
+    }
+    for (n, file) in pending.iter().enumerate() {
+        out.push_str(&format!("{:>4}  next prompt: file {} ({}, held by {})\n", format!("u{}", n + 1), file.name, file.mime, file.provider));
+    }
+    out
+}
+
+impl ContextConfig {
+    pub fn summarize(&self) -> bool {
+        self.strategy.as_deref() == Some("summarize")
+    }
+
+    /// Prompt budget for `model`, given its window and the tokens kept for the answer
+    pub fn budget(&self, model: &str, max_context: u32, max_tokens: u16) -> u32 {
+        self.models.iter().flatten()
+            .filter(|(prefix, _)| model.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, &budget)| budget)
+            .or(self.budget)
+            .unwrap_or_else(|| max_context.saturating_sub(max_tokens as u32))
+    }
+}
+
+/// Turns rendered as a transcript for the summarization request
+pub fn transcript(messages: &[ChatMessage]) -> String {
+    messages.iter().map(|m| match &m.function_call {
+        Some(call) => format!("{} called {}({})", m.role, call.name, call.arguments),
+        None => format!("{}: {}", m.role, m.content),
+    }).collect::<Vec<_>>().join("\n\n")
+}
+
+/// Request asking the model to condense earlier turns
+pub fn summary_prompt(transcript: &str) -> String {
+    format!(
+        "Summarize the conversation below for your own later reference. Keep decisions, facts learned, file names, open questions and what the user asked for; drop pleasantries and tool output that no longer matters. Reply with the summary only.\n\n{}",
+        transcript
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{mock::MockProvider, turns::Turn};
+    use super::{estimate, ContextConfig};
+
+    #[tokio::test]
+    async fn context_overflow_pages_out_older_turns_and_retries() {
+        let mock = MockProvider::start().await;
+        mock.context_overflow().await;
+        mock.reply("pong").await;
+        let mut agent = mock.agent();
+        let reply = agent.send_fitted(Turn::Tools).await.unwrap();
+        assert_eq!(reply.content, "pong");
+        let requests = mock.requests().await;
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1]["messages"].as_array().unwrap().len(), 2);
+        assert_eq!(agent.conversation.total_len(), 4);
+    }
+
+    /// The fixed agent with a context budget that leaves room for the latest prompt only
+    async fn over_budget(mock: &MockProvider, strategy: &str) -> crate::Agent {
+        let mut agent = mock.agent();
+        let (used, _) = agent.context_usage(Turn::Tools);
+        let budget = used - estimate(&agent.conversation[1]) - estimate(&agent.conversation[2]);
+        agent.config.context = Some(ContextConfig { strategy: Some(strategy.into()), budget: Some(budget), models: None });
+        agent
+    }
+
+    #[tokio::test]
+    async fn turns_over_the_context_budget_are_dropped() {
+        let mock = MockProvider::start().await;
+        mock.reply("pong").await;
+        let mut agent = over_budget(&mock, "drop").await;
+        agent.send_fitted(Turn::Tools).await.unwrap();
+        let requests = mock.requests().await;
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0]["messages"].as_array().unwrap().len(), 2);
+        assert_eq!(agent.conversation.total_len(), 4);
+    }
+
+    #[tokio::test]
+    async fn turns_over_the_context_budget_are_summarized() {
+        let mock = MockProvider::start().await;
+        mock.reply("The user asked for the files in src: main.rs and lib.rs.").await;
+        let mut agent = over_budget(&mock, "summarize").await;
+        agent.send_fitted(Turn::Tools).await.unwrap();
+        let requests = mock.requests().await;
+        assert_eq!(requests.len(), 2);
+        assert!(requests[0]["messages"][1]["content"].as_str().unwrap().contains("assistant: main.rs and lib.rs."));
+        let messages = requests[1]["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[1]["name"], "context_summary");
+        assert!(messages[1]["content"].as_str().unwrap().ends_with("main.rs and lib.rs."));
+        assert_eq!(messages[2]["content"], "Which one is the entry point?");
+    }
+}
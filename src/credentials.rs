@@ -0,0 +1,28 @@
+use std::{fs, io::{self, Write}};
+use anyhow::{Result, Context};
+use crate::{paths, vault};
+
+/// Look up a stored API key for a provider
+pub fn load(provider: &str) -> Option<String> {
+    let text = fs::read_to_string(paths::credentials_file()).ok()?;
+    let table: toml::Table = toml::from_str(&text).ok()?;
+    table.get(provider)?.as_str().map(String::from)
+}
+
+/// Store an API key, keeping the file readable by the owner only. The new file is written beside
+/// the old one and renamed over it, so a failed write leaves the stored keys as they were
+pub fn store(provider: &str, key: &str) -> Result<()> {
+    let path = paths::credentials_file();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut table: toml::Table = match fs::read_to_string(&path) {
+        Ok(text) => toml::from_str(&text).with_context(|| format!("Corrupt credentials file {}; fix or remove it first", path.display()))?,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => toml::Table::new(),
+        Err(err) => return Err(err).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    table.insert(provider.into(), toml::Value::String(key.into()));
+    let tmp = path.with_extension("tmp");
+    vault::create_private(&tmp)?.write_all(toml::to_string(&table)?.as_bytes())?;
+    fs::rename(&tmp, &path).with_context(|| format!("Failed to write {}", path.display()))
+}
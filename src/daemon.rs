@@ -0,0 +1,254 @@
+//! `rustaigent daemon`: a resident process on a Unix socket, started by hand or by systemd socket
+//! activation. Each connection speaks the `--stdio-editor` protocol, opening its session in a
+//! project root. What is slow to set up for a project stays loaded between connections: MCP
+//! servers, plugins, the codebase index and the provider's warm connections. `rustaigent -p` hands
+//! its prompt to the daemon when one is listening, so it answers without starting any of that.
+
+use std::{collections::HashMap, env, fs, io::{self, Write}, os::unix::{fs::{DirBuilderExt, FileTypeExt, PermissionsExt}, io::FromRawFd}, path::{Path, PathBuf}, sync::Arc};
+use serde_json::{json, Value};
+use anyhow::{Result, Context, bail};
+use tokio::{io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines}, net::{unix::{OwnedReadHalf, OwnedWriteHalf}, UnixListener, UnixStream}, signal::unix::{signal, SignalKind}, sync::Mutex};
+use tracing::{info, warn};
+use crate::{i18n, index, mcp, models, oneshot, paths, plugins, stdio, tools, Agent, AgentBuilder, FunctionDefinition};
+
+/// First descriptor systemd passes, per sd_listen_fds(3)
+const SD_LISTEN_FDS_START: i32 = 3;
+
+/// Where the daemon listens: `RUSTAIGENT_SOCKET`, else `rustaigent.sock` in the runtime
+/// directory, else `daemon.sock` in the data directory
+pub fn socket_path() -> PathBuf {
+    env::var_os("RUSTAIGENT_SOCKET").map(PathBuf::from)
+        .or_else(|| dirs::runtime_dir().map(|dir| dir.join("rustaigent.sock")))
+        .unwrap_or_else(|| paths::data_dir().join("daemon.sock"))
+}
+
+/// The socket systemd passed in, when it started this process for it
+fn activated() -> Result<Option<UnixListener>> {
+    let ours = env::var("LISTEN_PID").ok().and_then(|pid| pid.parse::<u32>().ok()) == Some(std::process::id());
+    let count = env::var("LISTEN_FDS").ok().and_then(|n| n.parse::<u32>().ok()).unwrap_or(0);
+    if !ours || count == 0 {
+        return Ok(None);
+    }
+    // Not meant for the commands and servers the tools start
+    for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        env::remove_var(var);
+    }
+    // SAFETY: systemd hands the listening socket over as the first passed descriptor, owned by
+    // nothing else in this process
+    let listener = unsafe { std::os::unix::net::UnixListener::from_raw_fd(SD_LISTEN_FDS_START) };
+    listener.set_nonblocking(true)?;
+    Ok(Some(UnixListener::from_std(listener).context("The socket systemd passed is not a Unix socket")?))
+}
+
+async fn bind(path: &Path) -> Result<UnixListener> {
+    if UnixStream::connect(path).await.is_ok() {
+        bail!("A daemon is already listening on {}", path.display());
+    }
+    // Left behind by a daemon that did not exit cleanly
+    if fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+        fs::remove_file(path)?;
+    }
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    fs::create_dir_all(dir)?;
+    // Whoever can connect runs tools as this user, so the socket is bound inside a directory only
+    // this user can enter, tightened there, and only then moved into place
+    let private = dir.join(format!(".rustaigent-bind-{}", std::process::id()));
+    fs::DirBuilder::new().mode(0o700).create(&private)
+        .with_context(|| format!("Cannot create {}", private.display()))?;
+    let staged = private.join("daemon.sock");
+    let bound = UnixListener::bind(&staged)
+        .and_then(|listener| fs::set_permissions(&staged, fs::Permissions::from_mode(0o600)).map(|_| listener))
+        .and_then(|listener| fs::rename(&staged, path).map(|_| listener));
+    fs::remove_file(&staged).ok();
+    fs::remove_dir(&private).ok();
+    bound.with_context(|| format!("Cannot listen on {}", path.display()))
+}
+
+/// What stays loaded for a project between connections
+struct Resident {
+    client: reqwest::Client,
+    model: Option<String>,
+    functions: Arc<[FunctionDefinition]>,
+    custom_tools: Vec<Arc<dyn tools::Tool>>,
+    index: Option<Arc<index::Retriever>>,
+}
+
+/// Projects by root, loaded by their first connection
+#[derive(Default)]
+struct Projects(Mutex<HashMap<PathBuf, Arc<Resident>>>);
+
+impl Projects {
+    /// A new session in `root`, built from the project's config with what is resident attached
+    async fn session(&self, root: &Path) -> Result<Agent> {
+        // Config files, MCP servers and plugins are found from the project's directory
+        let mut agent = paths::in_dir(root, || AgentBuilder::new().build())?;
+        agent.tool_ctx.set_workdir(root.to_path_buf());
+        let mut projects = self.0.lock().await;
+        let resident = match projects.get(root) {
+            Some(resident) => Arc::clone(resident),
+            None => {
+                info!("loading {}", root.display());
+                models::prepare(&mut agent).await;
+                mcp::connect(&mut agent).await;
+                plugins::load(&mut agent);
+                let resident = Arc::new(Resident {
+                    client: agent.client.clone(),
+                    model: agent.model.clone(),
+                    functions: Arc::clone(&agent.functions),
+                    custom_tools: agent.custom_tools.clone(),
+                    index: agent.tool_ctx.index.clone(),
+                });
+                projects.insert(root.to_path_buf(), Arc::clone(&resident));
+                resident
+            }
+        };
+        agent.client = resident.client.clone();
+        agent.model = resident.model.clone();
+        agent.functions = Arc::clone(&resident.functions);
+        agent.custom_tools = resident.custom_tools.clone();
+        agent.tool_ctx.index = resident.index.clone();
+        Ok(agent)
+    }
+}
+
+/// Listen until interrupted, serving each connection in a session of its own
+pub async fn run(socket: Option<PathBuf>) -> Result<()> {
+    let (listener, bound) = match activated()? {
+        Some(listener) => (listener, None),
+        None => {
+            let path = socket.unwrap_or_else(socket_path);
+            (bind(&path).await?, Some(path))
+        }
+    };
+    match &bound {
+        Some(path) => info!("listening on {}", path.display()),
+        None => info!("listening on the socket systemd passed"),
+    }
+    let projects = Arc::new(Projects::default());
+    let mut terminate = signal(SignalKind::terminate())?;
+    loop {
+        let stream = tokio::select! {
+            accepted = listener.accept() => accepted?.0,
+            _ = tokio::signal::ctrl_c() => break,
+            _ = terminate.recv() => break,
+        };
+        let projects = Arc::clone(&projects);
+        tokio::spawn(async move {
+            if let Err(err) = connection(&projects, stream).await {
+                warn!("connection failed: {:#}", err);
+            }
+        });
+    }
+    // A socket systemd passed belongs to systemd
+    if let Some(path) = bound {
+        fs::remove_file(path).ok();
+    }
+    Ok(())
+}
+
+/// Serve one connection; its first request has to be `open_session` with the project's `root`
+async fn connection(projects: &Projects, stream: UnixStream) -> Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+    let Some(first) = lines.next_line().await? else { return Ok(()) };
+    let request: Value = serde_json::from_str(&first).unwrap_or_default();
+    let root = match request["params"]["root"].as_str().filter(|_| request["method"] == "open_session") {
+        Some(root) => Path::new(root).canonicalize().with_context(|| format!("No such directory {}", root)),
+        None => Err(anyhow::anyhow!("Open the session first, with `open_session` and the project's `root`")),
+    };
+    let agent = match root {
+        Ok(root) => projects.session(&root).await,
+        Err(err) => Err(err),
+    };
+    let mut agent = match agent {
+        Ok(agent) => agent,
+        Err(err) => {
+            let line = json!({ "id": request["id"], "error": { "message": format!("{:#}", err) } });
+            write.write_all(format!("{}\n", line).as_bytes()).await?;
+            return Ok(());
+        }
+    };
+    let result = stdio::serve(&mut agent, stdio::incoming(lines, Some(first)), write).await;
+    agent.save_stats();
+    result
+}
+
+/// The client's side of a connection
+struct Client {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    write: OwnedWriteHalf,
+    next: u64,
+}
+
+impl Client {
+    async fn request(&mut self, method: &str, params: Value) -> Result<u64> {
+        self.next += 1;
+        let line = json!({ "id": self.next, "method": method, "params": params });
+        self.write.write_all(format!("{}\n", line).as_bytes()).await?;
+        Ok(self.next)
+    }
+
+    async fn line(&mut self) -> Result<Value> {
+        let line = self.lines.next_line().await?.context("The daemon closed the connection")?;
+        serde_json::from_str(&line).context("The daemon sent a line that is not JSON")
+    }
+}
+
+/// Answer `prompt` in the running daemon and return the part `output` asks for; `None` when no
+/// daemon is listening. Approvals and questions are put to the user on the terminal, or declined
+/// when stdin carried the input.
+pub async fn prompt(prompt: &str, input: Option<&str>, output: oneshot::Output) -> Result<Option<String>> {
+    let Ok(stream) = UnixStream::connect(socket_path()).await else { return Ok(None) };
+    let (read, write) = stream.into_split();
+    let mut client = Client { lines: BufReader::new(read).lines(), write, next: 0 };
+    client.request("open_session", json!({ "root": env::current_dir()? })).await?;
+    let opened = client.line().await?;
+    if let Some(message) = opened["error"]["message"].as_str() {
+        bail!("The daemon could not open a session: {}", message);
+    }
+    let lang = i18n::Lang::new(opened["result"]["language"].as_str().unwrap_or("en"));
+    let output = match output {
+        oneshot::Output::Code => "code",
+        oneshot::Output::Patch => "patch",
+        oneshot::Output::Text | oneshot::Output::Ndjson => "text",
+    };
+    let id = client.request("prompt", json!({ "prompt": prompt, "input": input, "output": output })).await?;
+    loop {
+        let line = client.line().await?;
+        if line["id"] == id {
+            if let Some(message) = line["error"]["message"].as_str() {
+                bail!("{}", message);
+            }
+            return Ok(Some(line["result"]["output"].as_str().unwrap_or_default().to_string()));
+        }
+        let frame = &line["params"];
+        let reply = match frame["type"].as_str().filter(|_| line["method"] == "event") {
+            Some("approval_needed") => {
+                if let Some(diff) = frame["diff"].as_str() {
+                    println!("{}", diff);
+                }
+                let question = frame["question"].as_str().unwrap_or_default();
+                let approve = input.is_none() && tools::confirm(&lang, question)?;
+                json!({ "type": "approval", "id": frame["id"], "approve": approve })
+            }
+            Some("question_asked") => {
+                let answer = match input {
+                    Some(_) => String::new(),
+                    None => ask(frame["question"].as_str().unwrap_or_default())?,
+                };
+                json!({ "type": "answer", "id": frame["id"], "text": answer })
+            }
+            _ => continue,
+        };
+        client.request("reply", reply).await?;
+    }
+}
+
+fn ask(question: &str) -> Result<String> {
+    println!("RustAIgent asks: {}", question);
+    print!("> ");
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(answer.trim().to_string())
+}
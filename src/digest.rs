@@ -0,0 +1,153 @@
+//! Stacked summaries for diffs too large for one prompt. The diff is cut at file, then hunk, then
+//! line boundaries, always the same way for the same diff; each chunk is summarized on its own and
+//! the summaries are merged in rounds until they fit next to the conversation.
+
+use anyhow::{Result, Context};
+use tracing::info;
+use crate::{context::estimate_text, turns::Turn, Agent};
+
+/// Summary requests in flight at once
+const CONCURRENCY: usize = 4;
+
+/// Smallest chunk worth a request, whatever the context budget says
+const MIN_CHUNK: u32 = 1_000;
+
+/// A diff the way a prompt carries it
+pub enum Digest {
+    /// Small enough to send as is
+    Full(String),
+    /// Notes written from the diff part by part
+    Stacked { notes: String, parts: usize, files: usize },
+}
+
+impl Digest {
+    /// The diff in a fence, or the notes and where they came from
+    pub fn render(&self) -> String {
+        match self {
+            Digest::Full(diff) => format!("Diff:\n{}", fence(diff)),
+            Digest::Stacked { notes, parts, files } => format!(
+                "The diff is too large to show in full. These notes on its {} changed files were written from it in {} parts:\n\n{}",
+                files, parts, notes.trim()),
+        }
+    }
+}
+
+fn fence(diff: &str) -> String {
+    let fence = if diff.contains("```") { "````" } else { "```" };
+    format!("{}diff\n{}\n{}", fence, diff.trim_end(), fence)
+}
+
+/// `text` cut before every line `starts` accepts; the pieces join back into `text`
+fn split_before(text: &str, starts: impl Fn(&str) -> bool) -> Vec<&str> {
+    let (mut pieces, mut start, mut offset) = (Vec::new(), 0, 0);
+    for line in text.split_inclusive('\n') {
+        if starts(line) && offset > start {
+            pieces.push(&text[start..offset]);
+            start = offset;
+        }
+        offset += line.len();
+    }
+    if offset > start {
+        pieces.push(&text[start..]);
+    }
+    pieces
+}
+
+/// Join consecutive pieces while they stay within `budget` tokens
+fn pack(pieces: impl IntoIterator<Item = String>, budget: u32) -> Vec<String> {
+    let (mut chunks, mut current, mut size) = (Vec::new(), String::new(), 0);
+    for piece in pieces {
+        let tokens = estimate_text(&piece);
+        if size + tokens > budget && !current.is_empty() {
+            chunks.push(std::mem::take(&mut current));
+            size = 0;
+        }
+        current.push_str(&piece);
+        size += tokens;
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+/// A line too long for any chunk, cut so each piece fits; no character is more than one token
+fn cut_line(line: &str, budget: u32) -> Vec<String> {
+    line.chars().collect::<Vec<_>>().chunks(budget as usize).map(|piece| piece.iter().collect()).collect()
+}
+
+/// One file's diff in pieces of at most `budget` tokens, each repeating the file header
+fn split_file(file: &str, budget: u32) -> Vec<String> {
+    if estimate_text(file) <= budget {
+        return vec![file.to_string()];
+    }
+    let (header, body) = match file.find("\n@@") {
+        Some(i) if estimate_text(&file[..=i]) <= budget / 2 => file.split_at(i + 1),
+        _ => ("", file),
+    };
+    let room = budget - estimate_text(header);
+    let units = split_before(body, |line| line.starts_with("@@")).into_iter().flat_map(|hunk| match estimate_text(hunk) <= room {
+        true => vec![hunk.to_string()],
+        false => split_before(hunk, |_| true).into_iter().flat_map(|line| cut_line(line, room)).collect(),
+    });
+    pack(units, room).into_iter().map(|part| format!("{}{}", header, part)).collect()
+}
+
+/// Chunks of at most `budget` tokens in diff order: whole files packed together, a file over the
+/// budget split at its hunks, a hunk over it at its lines
+pub fn chunks(diff: &str, budget: u32) -> Vec<String> {
+    let budget = budget.max(1);
+    let files = split_before(diff, |line| line.starts_with("diff --git "));
+    pack(files.into_iter().flat_map(|file| split_file(file, budget)), budget)
+}
+
+fn map_prompt(focus: &str, part: usize, parts: usize, chunk: &str) -> String {
+    format!(
+        "This is part {} of {} of a diff too large to read at once. {} Start the notes on each file with its path. \
+         Be specific and brief: later steps see only your notes, not the diff.\n\n{}",
+        part, parts, focus, fence(chunk))
+}
+
+fn reduce_prompt(focus: &str, notes: &str) -> String {
+    format!(
+        "Merge these notes, written from consecutive parts of one large diff, into a single set of notes. {} \
+         Keep the file paths and every concrete finding; drop only repetition.\n\n{}",
+        focus, notes.trim())
+}
+
+/// Send the prompts as separate requests; any failure fails the whole digest
+async fn summarize(agent: &Agent, prompts: Vec<String>) -> Result<Vec<String>> {
+    let total = prompts.len();
+    agent.send_batch(prompts, CONCURRENCY).await.into_iter().enumerate()
+        .map(|(i, reply)| reply.map(|r| r.content).with_context(|| format!("Failed to summarize part {} of {} of the diff", i + 1, total)))
+        .collect()
+}
+
+/// The diff as is when it fits next to the conversation, else notes stacked from its chunks;
+/// `focus` tells the model what the notes are for, e.g. a review or a description
+pub async fn condense(agent: &Agent, diff: &str, focus: &str) -> Result<Digest> {
+    let (used, budget) = agent.context_usage(Turn::Answer);
+    // Half of what is left, so the instructions and the answer still fit
+    let room = budget.saturating_sub(used) / 2;
+    let size = estimate_text(diff);
+    if size <= room {
+        return Ok(Digest::Full(diff.to_string()));
+    }
+    // A summary request carries only the system prompt and its chunk
+    let chunk = (budget / 2).max(MIN_CHUNK);
+    let parts = chunks(diff, chunk);
+    let files = diff.lines().filter(|line| line.starts_with("diff --git ")).count();
+    info!("the diff (~{} tokens) is over the {}-token room; summarizing it in {} parts", size, room, parts.len());
+    let prompts = parts.iter().enumerate().map(|(i, part)| map_prompt(focus, i + 1, parts.len(), part)).collect();
+    let mut notes = summarize(agent, prompts).await?;
+    while notes.len() > 1 && estimate_text(&notes.join("\n\n")) > room {
+        let groups = pack(notes.iter().map(|n| format!("{}\n\n", n.trim())), chunk);
+        // Every note fills a request on its own; merging cannot shrink them further
+        if groups.len() == notes.len() {
+            break;
+        }
+        info!("merging {} summaries into {}", notes.len(), groups.len());
+        notes = summarize(agent, groups.iter().map(|group| reduce_prompt(focus, group)).collect()).await?;
+    }
+    Ok(Digest::Stacked { notes: notes.join("\n\n"), parts: parts.len(), files })
+}
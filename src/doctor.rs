@@ -0,0 +1,95 @@
+//! `rustaigent doctor`: what the session would run with and how much memory it takes, with the
+//! limits that bound its growth, so the agent can be sized for small machines and CI runners.
+
+use std::{fmt::Write, fs};
+use crate::{artifacts, bulk, config, tools, Agent};
+
+/// Memory below which `doctor` recommends the `low-memory` profile
+const SMALL_MACHINE: u64 = 2 << 30;
+
+/// `1.5 MiB`
+fn bytes(n: u64) -> String {
+    match n {
+        n if n >= 1 << 30 => format!("{:.1} GiB", n as f64 / (1u64 << 30) as f64),
+        n if n >= 1 << 20 => format!("{:.1} MiB", n as f64 / (1u64 << 20) as f64),
+        n if n >= 1 << 10 => format!("{:.1} KiB", n as f64 / 1024.0),
+        n => format!("{} B", n),
+    }
+}
+
+/// Resident and peak memory of this process, from `/proc/self/status`
+fn resident() -> Option<(u64, u64)> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let field = |name: &str| -> Option<u64> {
+        let line = status.lines().find(|line| line.starts_with(name))?;
+        Some(line[name.len()..].trim().trim_end_matches("kB").trim().parse::<u64>().ok()? * 1024)
+    };
+    Some((field("VmRSS:")?, field("VmHWM:")?))
+}
+
+/// The container's memory limit, from cgroup v2 or v1; `None` when unlimited or unknown
+fn limit() -> Option<u64> {
+    let raw = fs::read_to_string("/sys/fs/cgroup/memory.max")
+        .or_else(|_| fs::read_to_string("/sys/fs/cgroup/memory/memory.limit_in_bytes"))
+        .ok()?;
+    // cgroup v1 reports no limit as a number close to the largest it can hold
+    raw.trim().parse::<u64>().ok().filter(|&n| n < 1 << 60)
+}
+
+/// The report, one `key: value` line per check, grouped under `memory:` and `bounds:`
+pub(crate) fn report(agent: &Agent) -> String {
+    let mut out = String::new();
+    let config = &agent.config;
+    writeln!(out, "rustaigent {}", env!("CARGO_PKG_VERSION")).ok();
+    let profile = config::Layered::load().ok().and_then(|layered| layered.profile);
+    writeln!(out, "profile: {}", profile.as_deref().unwrap_or("none")).ok();
+    let reports = config::validate_files();
+    let errors: usize = reports.iter().map(|(_, report)| report.errors.len()).sum();
+    let warnings: usize = reports.iter().map(|(_, report)| report.warnings.len()).sum();
+    match (reports.len(), errors + warnings) {
+        (0, _) => writeln!(out, "config: no files found"),
+        (files, 0) => writeln!(out, "config: {} file(s), valid", files),
+        (files, _) => writeln!(out, "config: {} file(s), {} error(s) and {} warning(s); `rustaigent config validate` lists them", files, errors, warnings),
+    }.ok();
+    match agent.offline {
+        true => writeln!(out, "provider: {}, no API key; prompts are not sent", agent.provider),
+        false => writeln!(out, "provider: {}, model {}", agent.provider, agent.model_name()),
+    }.ok();
+    writeln!(out, "tools: {} ({} from MCP servers and plugins)", agent.functions.len(), agent.custom_tools.len()).ok();
+
+    writeln!(out, "\nmemory:").ok();
+    match resident() {
+        Some((now, peak)) => writeln!(out, "  process: {} resident, {} at most", bytes(now), bytes(peak)),
+        None => writeln!(out, "  process: not measured on this platform"),
+    }.ok();
+    let limit = limit();
+    match limit {
+        Some(limit) => writeln!(out, "  limit: {} (cgroup)", bytes(limit)),
+        None => writeln!(out, "  limit: none set"),
+    }.ok();
+    let held: usize = agent.conversation.iter().map(|m| m.content.len() + m.reasoning.as_ref().map_or(0, String::len)).sum();
+    writeln!(out, "  conversation: {} message(s) in memory ({}), {} on disk", agent.conversation.len(), bytes(held as u64), agent.conversation.total_len() - agent.conversation.len()).ok();
+    match (&agent.tool_ctx.index, config.index.as_ref().and_then(|index| index.enabled)) {
+        (_, Some(false)) => writeln!(out, "  index: off"),
+        (Some(index), _) => {
+            let (path, loaded) = index.file();
+            let size = fs::metadata(path).map(|meta| meta.len()).unwrap_or(0);
+            writeln!(out, "  index: {} on disk, {}", bytes(size), if loaded { "loaded" } else { "read on the first search" })
+        }
+        (None, _) => writeln!(out, "  index: not built"),
+    }.ok();
+
+    writeln!(out, "\nbounds:").ok();
+    match agent.memory_messages {
+        Some(keep) => writeln!(out, "  memory_messages: {}; older turns go to disk", keep),
+        None => writeln!(out, "  memory_messages: unset; the whole conversation stays in memory"),
+    }.ok();
+    writeln!(out, "  artifact_threshold: {}; larger tool results are stored on disk", bytes(config.artifact_threshold.unwrap_or(artifacts::DEFAULT_THRESHOLD) as u64)).ok();
+    writeln!(out, "  command_output_limit: {}", bytes(config.command_output_limit.unwrap_or(tools::DEFAULT_OUTPUT_LIMIT) as u64)).ok();
+    writeln!(out, "  max_file_bytes: {}", bytes(config.max_file_bytes.unwrap_or(bulk::DEFAULT_MAX_BYTES))).ok();
+    writeln!(out, "  prefetch: {}", if config.prefetch == Some(false) { "off" } else { "on" }).ok();
+    if let Some(limit) = limit.filter(|&limit| limit < SMALL_MACHINE && profile.as_deref() != Some("low-memory")) {
+        writeln!(out, "\nWith {} available, `--profile low-memory` keeps the agent small.", bytes(limit)).ok();
+    }
+    out
+}
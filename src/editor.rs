@@ -0,0 +1,30 @@
+use std::{env, fs, io::Write, process::Command};
+use anyhow::{Result, Context, bail};
+
+/// Editor command from `$VISUAL` or `$EDITOR`, defaulting to `vi`
+fn editor_command() -> String {
+    env::var("VISUAL").or_else(|_| env::var("EDITOR")).ok()
+        .filter(|e| !e.trim().is_empty())
+        .unwrap_or_else(|| "vi".into())
+}
+
+/// Let the user write a prompt in their editor, starting from `draft`; `None` when it is saved empty
+pub fn compose(draft: &str) -> Result<Option<String>> {
+    // A new file only we can open, so nobody can plant a link at the path or edit the draft
+    let mut file = tempfile::Builder::new().prefix("rustaigent-prompt-").suffix(".md").tempfile()
+        .context("Failed to create the draft file")?;
+    file.write_all(draft.as_bytes()).and_then(|_| file.flush()).context("Failed to write the draft")?;
+    let path = file.path();
+    let editor = editor_command();
+    // The path is passed as a positional argument so it is never re-parsed by the shell
+    let status = Command::new("sh").arg("-c").arg(format!("{} \"$1\"", editor)).arg("sh").arg(path)
+        .status()
+        .with_context(|| format!("Failed to start editor `{}`", editor));
+    let text = fs::read_to_string(path);
+    let status = status?;
+    if !status.success() {
+        bail!("Editor `{}` {}; nothing sent", editor, status);
+    }
+    let text = text.with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(Some(text.trim().to_string()).filter(|t| !t.is_empty()))
+}
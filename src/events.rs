@@ -0,0 +1,190 @@
+use std::{collections::VecDeque, sync::{Arc, Mutex}};
+use tokio::sync::Notify;
+
+/// Progress notifications for embedding frontends, so GUIs can render activity without scraping stdout
+pub enum AgentEvent {
+    /// Answer text as it arrives: one chunk per answer, or the pieces when STREAMING is on. Pieces a
+    /// slow subscriber has not taken yet are merged into one event.
+    Token(String),
+    ToolStarted { call: crate::tools::ToolCall },
+    ToolFinished { name: String, ok: bool, output: String },
+    /// A tool wants confirmation; the agent waits until `respond` is answered
+    ApprovalNeeded { question: String, respond: Approval },
+    /// The model asked the user a clarifying question; the agent waits for `respond.answer`
+    QuestionAsked { question: String, respond: Answer },
+    /// The turn is complete
+    Done { content: String },
+}
+
+/// Answer to an `ApprovalNeeded` event; dropping it counts as a denial
+pub struct Approval(std::sync::mpsc::Sender<bool>);
+
+impl Approval {
+    pub fn approve(self) {
+        self.0.send(true).ok();
+    }
+
+    pub fn deny(self) {
+        self.0.send(false).ok();
+    }
+}
+
+/// Answer to a `QuestionAsked` event; dropping it sends an empty answer
+pub struct Answer(std::sync::mpsc::Sender<String>);
+
+impl Answer {
+    pub fn answer(self, text: impl Into<String>) {
+        self.0.send(text.into()).ok();
+    }
+}
+
+/// A prompt handed to a remote frontend that has yet to reply; dropping it declines
+pub enum Pending {
+    Approval(Approval),
+    Answer(Answer),
+}
+
+/// Events on their way to the subscriber. Only tool events and prompts queue up one by one; answer
+/// text joins the token event waiting at the back, so a subscriber that falls behind during a long
+/// generation costs at most the unread text, and gets it in bigger pieces once it catches up.
+#[derive(Default)]
+struct Queue {
+    events: VecDeque<AgentEvent>,
+    /// The agent and its tools are done with the channel
+    finished: bool,
+    /// The subscriber is gone
+    closed: bool,
+}
+
+#[derive(Default)]
+struct Shared {
+    queue: Mutex<Queue>,
+    ready: Notify,
+}
+
+/// Ends the subscriber's stream once the last `Sink` is dropped
+struct Sender(Arc<Shared>);
+
+impl Drop for Sender {
+    fn drop(&mut self) {
+        self.0.queue.lock().unwrap().finished = true;
+        self.0.ready.notify_one();
+    }
+}
+
+/// Sending half held by the agent and tool context
+#[derive(Clone)]
+pub struct Sink(Arc<Sender>);
+
+/// Receiving half handed to the subscriber
+pub struct Receiver(Arc<Shared>);
+
+impl Receiver {
+    /// The next event; `None` once the agent is dropped and everything sent has been taken
+    pub async fn recv(&mut self) -> Option<AgentEvent> {
+        loop {
+            let ready = self.0.ready.notified();
+            {
+                let mut queue = self.0.queue.lock().unwrap();
+                if let Some(event) = queue.events.pop_front() {
+                    return Some(event);
+                }
+                if queue.finished {
+                    return None;
+                }
+            }
+            ready.await;
+        }
+    }
+
+    /// The next event if one is waiting, without waiting for one
+    pub fn try_recv(&mut self) -> Option<AgentEvent> {
+        self.0.queue.lock().unwrap().events.pop_front()
+    }
+}
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        let mut queue = self.0.queue.lock().unwrap();
+        queue.closed = true;
+        // Prompts nobody will see are declined
+        queue.events.clear();
+    }
+}
+
+pub fn channel() -> (Sink, Receiver) {
+    let shared = Arc::new(Shared::default());
+    (Sink(Arc::new(Sender(Arc::clone(&shared)))), Receiver(shared))
+}
+
+impl Sink {
+    /// Deliver an event; a closed receiver is ignored
+    pub fn emit(&self, event: AgentEvent) {
+        self.send(event);
+    }
+
+    /// Queue an event for the subscriber; false when it is gone
+    fn send(&self, event: AgentEvent) -> bool {
+        let shared = &(self.0).0;
+        let mut queue = shared.queue.lock().unwrap();
+        if queue.closed {
+            return false;
+        }
+        match (event, queue.events.back_mut()) {
+            (AgentEvent::Token(piece), Some(AgentEvent::Token(text))) => text.push_str(&piece),
+            (event, _) => queue.events.push_back(event),
+        }
+        drop(queue);
+        shared.ready.notify_one();
+        true
+    }
+
+    /// Ask the subscriber to approve and block until it answers; `None` when nobody is listening
+    pub fn ask(&self, question: &str) -> Option<bool> {
+        let (tx, rx) = std::sync::mpsc::channel();
+        if !self.send(AgentEvent::ApprovalNeeded { question: question.into(), respond: Approval(tx) }) {
+            return None;
+        }
+        Some(wait(rx).unwrap_or(false))
+    }
+
+    /// Ask the subscriber a free-form question and block until it answers; `None` when nobody is listening
+    pub fn question(&self, question: &str) -> Option<String> {
+        let (tx, rx) = std::sync::mpsc::channel();
+        if !self.send(AgentEvent::QuestionAsked { question: question.into(), respond: Answer(tx) }) {
+            return None;
+        }
+        Some(wait(rx).unwrap_or_default())
+    }
+}
+
+/// Block until the subscriber answers. On a multi-threaded runtime the worker's queued tasks move
+/// to another thread first, since the subscriber may be one of them.
+fn wait<T>(rx: std::sync::mpsc::Receiver<T>) -> Result<T, std::sync::mpsc::RecvError> {
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == tokio::runtime::RuntimeFlavor::MultiThread => tokio::task::block_in_place(|| rx.recv()),
+        _ => rx.recv(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mock::MockProvider;
+    use super::AgentEvent;
+
+    #[tokio::test]
+    async fn pieces_a_slow_subscriber_missed_are_merged() {
+        let mock = MockProvider::start().await;
+        mock.stream(&["Entry point: main", ".rs, caf", "é included"]).await;
+        let mut agent = mock.agent();
+        agent.enable_quiet();
+        agent.streaming = true;
+        let mut events = agent.events();
+        agent.turn("where does it start?").await.unwrap();
+        drop(agent);
+        let Some(AgentEvent::Token(text)) = events.recv().await else { panic!("expected the answer text") };
+        assert_eq!(text, "Entry point: main.rs, café included");
+        assert!(matches!(events.recv().await, Some(AgentEvent::Done { .. })));
+        assert!(events.recv().await.is_none());
+    }
+}
@@ -0,0 +1,131 @@
+//! A/B experiments on prompts and settings: `[experiment]` defines two variants, each session is
+//! assigned one at random, and `rustaigent stats` compares the variants by approval rate,
+//! iterations to green in fix loops, and cost per session.
+
+use std::{env, path::PathBuf};
+use serde::{Serialize, Deserialize};
+use anyhow::{Result, Context, bail};
+use crate::Agent;
+
+/// `[experiment]` table
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct ExperimentConfig {
+    /// Name the outcomes are grouped under; a new name starts a new comparison
+    pub name: String,
+    /// Share of sessions assigned variant `b`; 0.5 by default
+    pub split: Option<f64>,
+    /// The control; settings it leaves unset stay as configured
+    pub a: Option<Variant>,
+    pub b: Option<Variant>,
+}
+
+/// Settings a variant changes for the sessions assigned to it
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct Variant {
+    /// Replaces the configured system prompt; project and language notes are still added
+    pub system_prompt: Option<String>,
+    pub system_prompt_file: Option<PathBuf>,
+    pub model: Option<String>,
+    pub temperature: Option<f32>,
+}
+
+/// Keys accepted in `[experiment]`, for config validation
+pub const KEYS: &[&str] = &["name", "split", "a", "b"];
+/// Keys accepted in `[experiment.a]` and `[experiment.b]`
+pub const VARIANT_KEYS: &[&str] = &["system_prompt", "system_prompt_file", "model", "temperature"];
+
+/// The variant a session runs, kept with its usage stats
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Assignment {
+    pub name: String,
+    pub variant: String,
+}
+
+impl Variant {
+    fn system_prompt(&self) -> Result<Option<String>> {
+        if let Some(prompt) = &self.system_prompt {
+            return Ok(Some(prompt.clone()));
+        }
+        self.system_prompt_file.as_ref()
+            .map(|path| std::fs::read_to_string(path).with_context(|| format!("Cannot read the variant's system prompt {}", path.display())))
+            .transpose()
+    }
+}
+
+/// `a` or `b`: `RUSTAIGENT_VARIANT` when it pins one, otherwise drawn with the `split` odds
+fn draw(cfg: &ExperimentConfig) -> Result<&'static str> {
+    match env::var("RUSTAIGENT_VARIANT").ok().as_deref().map(str::trim) {
+        Some("a") => return Ok("a"),
+        Some("b") => return Ok("b"),
+        Some("") | None => {}
+        Some(other) => bail!("RUSTAIGENT_VARIANT must be `a` or `b` (found `{}`)", other),
+    }
+    let mut bytes = [0u8; 4];
+    getrandom::fill(&mut bytes).ok();
+    let roll = u32::from_le_bytes(bytes) as f64 / (u32::MAX as f64 + 1.0);
+    Ok(if roll < cfg.split.unwrap_or(0.5) { "b" } else { "a" })
+}
+
+/// Assign the session a variant of the configured experiment and apply its settings. Settings
+/// given to [`crate::AgentBuilder`] are applied after, so they win.
+pub(crate) fn assign(agent: &mut Agent) -> Result<()> {
+    let Some(cfg) = agent.config.experiment.clone().filter(|cfg| !cfg.name.is_empty()) else { return Ok(()) };
+    let variant_name = draw(&cfg)?;
+    let variant = match variant_name {
+        "a" => cfg.a.clone(),
+        _ => cfg.b.clone(),
+    }.unwrap_or_default();
+    if let Some(prompt) = variant.system_prompt()? {
+        let configured = agent.config.system_prompt().ok().flatten().unwrap_or_else(|| crate::DEFAULT_PROMPT.into());
+        let system = &mut agent.conversation.make_mut()[0].content;
+        // The notes added after the configured prompt stay
+        *system = match system.strip_prefix(configured.as_str()) {
+            Some(notes) => format!("{}{}", prompt, notes),
+            None => prompt,
+        };
+    }
+    if let Some(model) = variant.model {
+        agent.model = Some(model);
+    }
+    if let Some(temperature) = variant.temperature {
+        agent.temperature = temperature;
+    }
+    tracing::info!("experiment `{}`: this session runs variant {}", cfg.name, variant_name);
+    agent.stats.lock().unwrap().experiment = Some(Assignment { name: cfg.name, variant: variant_name.into() });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{mock::MockProvider, turns::Turn};
+    use super::{assign, Assignment, ExperimentConfig, Variant};
+
+    #[tokio::test]
+    async fn experiment_variants_change_the_session_and_are_compared_in_stats() {
+        let mock = MockProvider::start().await;
+        mock.reply("done").await;
+        let mut session = mock.agent();
+        session.config.system_prompt = Some("You are a test assistant.".into());
+        session.conversation.make_mut()[0].content = "You are a test assistant.\n\nThe project is a Rust crate.".into();
+        let terse = Variant { system_prompt: Some("Answer in one line.".into()), model: Some("gpt-terse".into()), ..Default::default() };
+        session.config.experiment = Some(ExperimentConfig { name: "terse".into(), split: Some(1.0), a: None, b: Some(terse) });
+        assign(&mut session).unwrap();
+        session.send_request(Turn::Answer, None).await.unwrap();
+        let request = &mock.requests().await[0];
+        assert_eq!(request["model"], "gpt-terse");
+        assert_eq!(request["messages"][0]["content"], "Answer in one line.\n\nThe project is a Rust crate.");
+        let mut b = session.stats.lock().unwrap().clone();
+        assert_eq!(b.experiment, Some(Assignment { name: "terse".into(), variant: "b".into() }));
+        b.approvals = 3;
+        b.denials = 1;
+        b.record_fix(2, true);
+        b.record_fix(5, false);
+        let mut a = crate::stats::SessionStats::new("openai", None);
+        a.experiment = Some(Assignment { name: "terse".into(), variant: "a".into() });
+        a.record_fix(4, true);
+        let sum = crate::stats::summarize(&[a, b.clone(), b], None, &Default::default());
+        let (a, b) = (&sum.experiments["terse"]["a"], &sum.experiments["terse"]["b"]);
+        assert_eq!((a.sessions, a.fix_green, a.avg_iterations_to_green), (1, 1, 4.0));
+        assert_eq!((b.sessions, b.fix_runs, b.fix_green, b.avg_iterations_to_green, b.approval_rate), (2, 4, 2, 2.0, 0.75));
+    }
+}
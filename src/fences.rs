@@ -0,0 +1,174 @@
+//! Languages of fenced code blocks, detected from the target path or the code itself, so blocks
+//! the model left untagged still get a fence tag, and file contents the syntax check that fits.
+
+use std::path::Path;
+use regex::Regex;
+
+/// Fence tag for a file, by its extension or well-known name
+pub fn from_path(path: &str) -> Option<&'static str> {
+    let name = Path::new(path).file_name()?.to_str()?;
+    match name {
+        "Dockerfile" => return Some("dockerfile"),
+        "Makefile" | "makefile" | "GNUmakefile" => return Some("makefile"),
+        "Cargo.lock" => return Some("toml"),
+        _ => {}
+    }
+    let ext = Path::new(name).extension()?.to_str()?.to_ascii_lowercase();
+    Some(match ext.as_str() {
+        "rs" => "rust",
+        "py" | "pyi" => "python",
+        "js" | "mjs" | "cjs" => "javascript",
+        "jsx" => "jsx",
+        "ts" | "mts" => "typescript",
+        "tsx" => "tsx",
+        "go" => "go",
+        "java" => "java",
+        "kt" | "kts" => "kotlin",
+        "c" | "h" => "c",
+        "cc" | "cpp" | "cxx" | "hpp" => "cpp",
+        "cs" => "csharp",
+        "rb" => "ruby",
+        "php" => "php",
+        "swift" => "swift",
+        "lua" => "lua",
+        "sh" | "bash" => "bash",
+        "zsh" => "zsh",
+        "ps1" => "powershell",
+        "toml" => "toml",
+        "json" => "json",
+        "yaml" | "yml" => "yaml",
+        "xml" => "xml",
+        "html" | "htm" => "html",
+        "css" => "css",
+        "scss" => "scss",
+        "sql" => "sql",
+        "md" => "markdown",
+        "tf" => "hcl",
+        "proto" => "protobuf",
+        "diff" | "patch" => "diff",
+        _ => return None,
+    })
+}
+
+/// Fence tag guessed from the code alone; `None` when nothing in it gives the language away
+pub fn from_content(code: &str) -> Option<&'static str> {
+    let text = code.trim();
+    let first = text.lines().next()?;
+    if let Some(interpreter) = first.strip_prefix("#!") {
+        return match interpreter {
+            i if i.contains("python") => Some("python"),
+            i if i.contains("node") => Some("javascript"),
+            i if ["sh", "bash", "zsh"].iter().any(|shell| i.ends_with(&format!("/{}", shell)) || i.ends_with(&format!(" {}", shell))) => Some("bash"),
+            _ => None,
+        };
+    }
+    if first.starts_with("diff --git ") || (first.starts_with("--- ") && text.contains("\n+++ ")) {
+        return Some("diff");
+    }
+    if text.starts_with(['{', '[']) && serde_json::from_str::<serde_json::Value>(text).is_ok() {
+        return Some("json");
+    }
+    for (prefix, tag) in [("<!DOCTYPE html", "html"), ("<!doctype html", "html"), ("<html", "html"), ("<?xml", "xml"), ("<?php", "php")] {
+        if text.starts_with(prefix) {
+            return Some(tag);
+        }
+    }
+    let has = |pattern: &str| Regex::new(pattern).unwrap().is_match(text);
+    if has(r"(?m)^package \w+$") && has(r"(?m)^func ") {
+        return Some("go");
+    }
+    if has(r"(?m)^\s*((pub(\(crate\))? )?(async )?fn \w+|use \w+::|impl\b|#\[derive\(|let mut \w+)") && crate::lint::syntax_error(code).is_none() {
+        return Some("rust");
+    }
+    if has(r"(?m)^\s*(def \w+\(.*\)( -> .+)?:$|class \w+(\(.*\))?:$|from [\w.]+ import |import [\w.]+$|if __name__ == )") {
+        return Some("python");
+    }
+    // `let x = 5;` and the like are Rust as much as script, so leave them untagged
+    let script = |tag| crate::lint::syntax_error(code).is_some().then_some(tag);
+    if has(r"(?m)^\s*(interface \w+ \{|type \w+ = |(export )?(const|let) \w+: \w+)") {
+        return script("typescript");
+    }
+    if has(r#"(?m)^\s*((const|let|var) \w+ = |(export )?(async )?function \w*\()|=> \{|require\(['"]|console\.log\("#) {
+        return script("javascript");
+    }
+    if has(r"(?im)^\s*(select .+ from |insert into |create table |update \w+ set |delete from )") {
+        return Some("sql");
+    }
+    if has(r#"(?m)^\[[\w."-]+\]$"#) && toml::from_str::<toml::Table>(text).is_ok() {
+        return Some("toml");
+    }
+    if has(r"(?m)^(\$ \S|(cd|ls|echo|export|sudo|apt|apt-get|brew|cargo|npm|pnpm|pip|git|mkdir|curl|docker) )") {
+        return Some("bash");
+    }
+    if has(r"(?m)^[\w-]+:( |$)") && serde_yaml::from_str::<serde_yaml::Mapping>(text).is_ok() {
+        return Some("yaml");
+    }
+    None
+}
+
+/// Language of a block: from the path it is written to when that tells, else from the code
+pub fn detect(path: Option<&str>, code: &str) -> Option<&'static str> {
+    path.and_then(from_path).or_else(|| from_content(code))
+}
+
+/// Why `code` is not valid `lang`, for the languages the agent can check itself
+pub fn syntax_error(lang: &str, code: &str) -> Option<String> {
+    match lang {
+        "rust" => crate::lint::syntax_error(code),
+        "json" => serde_json::from_str::<serde_json::Value>(code).err().map(|err| err.to_string()),
+        "toml" => toml::from_str::<toml::Table>(code).err().map(|err| err.message().to_string()),
+        "yaml" => serde_yaml::from_str::<serde_yaml::Value>(code).err().map(|err| err.to_string()),
+        _ => None,
+    }
+}
+
+/// Info string of a fence that writes a file, `file=<path>` after an optional language: the
+/// language and the path
+pub fn file_info(info: &str) -> Option<(Option<&str>, &str)> {
+    let info = info.trim();
+    match info.split_once(char::is_whitespace) {
+        Some((lang, rest)) => Some((Some(lang), rest.trim().strip_prefix("file=")?.trim())),
+        None => Some((None, info.strip_prefix("file=")?.trim())),
+    }
+}
+
+/// Put the detected language on fenced blocks that have none, including `file=<path>` blocks;
+/// everything else is left as it was, byte for byte
+pub fn tag(text: &str) -> String {
+    let lines: Vec<&str> = text.split_inclusive('\n').collect();
+    let mut out = String::with_capacity(text.len() + 16);
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        let trimmed = line.trim();
+        let ticks = trimmed.len() - trimmed.trim_start_matches('`').len();
+        i += 1;
+        if ticks < 3 {
+            out.push_str(line);
+            continue;
+        }
+        let fence = &trimmed[..ticks];
+        let close = lines[i..].iter().position(|l| l.trim().starts_with(fence) && l.trim().chars().all(|c| c == '`')).map(|p| i + p);
+        let end = close.unwrap_or(lines.len());
+        let body = lines[i..end].concat();
+        let info = trimmed[ticks..].trim();
+        let tag = match file_info(info) {
+            Some((None, path)) => detect(Some(path), &body),
+            _ if info.is_empty() => from_content(&body),
+            _ => None,
+        };
+        match tag {
+            Some(tag) => {
+                let indent = &line[..line.len() - line.trim_start().len()];
+                let ending = &line[line.trim_end().len()..];
+                let info = if info.is_empty() { String::new() } else { format!(" {}", info) };
+                out.push_str(&format!("{}{}{}{}{}", indent, fence, tag, info, ending));
+            }
+            None => out.push_str(line),
+        }
+        let next = close.map_or(end, |c| c + 1);
+        out.push_str(&lines[i..next].concat());
+        i = next;
+    }
+    out
+}
@@ -0,0 +1,201 @@
+//! C ABI for embedding the agent in editors and GUI apps, declared in `include/rustaigent.h`.
+//! Strings cross the boundary as NUL-terminated UTF-8, results as JSON; every string the library
+//! returns is freed with `rustaigent_free_string`. Panics are caught at the boundary and reported
+//! like errors.
+
+use std::{ffi::{c_char, CStr, CString}, future::Future, panic::{self, AssertUnwindSafe}, ptr, sync::Mutex, time::Duration};
+use anyhow::{Result, Context};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use crate::{events, handle::{runtime, AgentHandle}, serve::Stream, AgentBuilder};
+
+/// Settings accepted by `rustaigent_create_agent`; unset ones resolve as in [`AgentBuilder`]
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct Settings {
+    provider: Option<String>,
+    model: Option<String>,
+    api_key: Option<String>,
+    system_prompt: Option<String>,
+    /// Queue events for `rustaigent_poll_event`, and hold approval prompts for `rustaigent_reply`
+    events: bool,
+    /// Run every tool without asking
+    yolo: bool,
+}
+
+/// Opaque agent session owned by the host
+pub struct RustAigent {
+    handle: AgentHandle,
+    events: Option<Mutex<events::Receiver>>,
+    stream: Mutex<Stream>,
+}
+
+fn to_c(text: String) -> *mut c_char {
+    // JSON escapes NUL, so this only fails for messages that were never JSON
+    CString::new(text).unwrap_or_default().into_raw()
+}
+
+fn error_json(err: anyhow::Error) -> *mut c_char {
+    to_c(json!({ "error": format!("{:#}", err) }).to_string())
+}
+
+/// Run an export's body, turning a panic into `fallback` so it never unwinds into the host
+fn guarded<T>(fallback: impl FnOnce(anyhow::Error) -> T, body: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(body)).unwrap_or_else(|payload| {
+        let reason = payload.downcast_ref::<&str>().map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown cause".into());
+        fallback(anyhow::anyhow!("rustaigent panicked: {}", reason))
+    })
+}
+
+/// Wait for `future` on the library's runtime. Blocking inside another Tokio runtime panics, so a
+/// host calling from one of its tasks waits on a helper thread instead
+fn block_on<F: Future + Send>(future: F) -> F::Output where F::Output: Send {
+    if tokio::runtime::Handle::try_current().is_err() {
+        return runtime().block_on(future);
+    }
+    std::thread::scope(|scope| scope.spawn(|| runtime().block_on(future)).join())
+        .unwrap_or_else(|payload| panic::resume_unwind(payload))
+}
+
+unsafe fn from_c<'a>(text: *const c_char) -> Result<&'a str> {
+    anyhow::ensure!(!text.is_null(), "NULL string");
+    CStr::from_ptr(text).to_str().context("strings must be UTF-8")
+}
+
+fn create(settings: &str) -> Result<RustAigent> {
+    let settings: Settings = if settings.trim().is_empty() { Settings::default() } else { serde_json::from_str(settings).context("Invalid settings")? };
+    let mut builder = AgentBuilder::new();
+    if let Some(provider) = settings.provider {
+        builder = builder.provider(provider);
+    }
+    if let Some(model) = settings.model {
+        builder = builder.model(model);
+    }
+    if let Some(key) = settings.api_key {
+        builder = builder.api_key(key);
+    }
+    if let Some(prompt) = settings.system_prompt {
+        builder = builder.system_prompt(prompt);
+    }
+    let mut agent = builder.build()?;
+    agent.enable_quiet();
+    if settings.yolo {
+        agent.enable_yolo();
+    }
+    let events = if settings.events {
+        Some(Mutex::new(agent.events()))
+    } else {
+        // Nobody would see the prompts; calls that would ask are declined
+        agent.enable_unattended();
+        None
+    };
+    let workspace = std::env::current_dir()?;
+    Ok(RustAigent { handle: AgentHandle::new(agent), events, stream: Mutex::new(Stream::new(workspace)) })
+}
+
+/// Create an agent from JSON settings (`provider`, `model`, `api_key`, `system_prompt`, `events`,
+/// `yolo`; NULL or empty for defaults). Returns NULL on failure and, when `error` is not NULL,
+/// stores the reason there.
+///
+/// # Safety
+/// `settings_json` must be NULL or a NUL-terminated string, and `error` NULL or writable.
+#[no_mangle]
+pub unsafe extern "C" fn rustaigent_create_agent(settings_json: *const c_char, error: *mut *mut c_char) -> *mut RustAigent {
+    let created = guarded(Err, || {
+        let settings = if settings_json.is_null() { Ok("") } else { from_c(settings_json) };
+        settings.and_then(create)
+    });
+    match created {
+        Ok(agent) => Box::into_raw(Box::new(agent)),
+        Err(err) => {
+            if !error.is_null() {
+                *error = to_c(format!("{:#}", err));
+            }
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Run one turn and block until it ends. Returns `{"answer": "..."}`, `{"answer": null}` when the
+/// guardrails held the message back, or `{"error": "..."}`. With `events` on, another thread
+/// polls events and replies to prompts meanwhile.
+///
+/// # Safety
+/// `agent` must come from `rustaigent_create_agent` and not be freed yet; `message` must be a
+/// NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn rustaigent_send_message_json(agent: *const RustAigent, message: *const c_char) -> *mut c_char {
+    guarded(error_json, || {
+        let Some(agent) = agent.as_ref() else { return error_json(anyhow::anyhow!("NULL agent")) };
+        let message = match from_c(message) {
+            Ok(message) => message,
+            Err(err) => return error_json(err),
+        };
+        match block_on(agent.handle.send(message)) {
+            Ok(answer) => to_c(json!({ "answer": answer }).to_string()),
+            Err(err) => error_json(err),
+        }
+    })
+}
+
+/// The next event as JSON, waiting up to `timeout_ms` for one; NULL when none arrived or events
+/// are off. Events are framed like the team server's WebSocket: `token`, `tool_started`,
+/// `tool_finished`, `approval_needed`, `question_asked` and `done`.
+///
+/// # Safety
+/// `agent` must come from `rustaigent_create_agent` and not be freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn rustaigent_poll_event(agent: *const RustAigent, timeout_ms: u32) -> *mut c_char {
+    guarded(|_| ptr::null_mut(), || {
+        let Some(agent) = agent.as_ref() else { return ptr::null_mut() };
+        let Some(events) = &agent.events else { return ptr::null_mut() };
+        let mut guard = events.lock().unwrap();
+        let events = &mut *guard;
+        let event = block_on(async { tokio::time::timeout(Duration::from_millis(timeout_ms.into()), events.recv()).await });
+        match event {
+            Ok(Some(event)) => to_c(agent.stream.lock().unwrap().frame(event).to_string()),
+            _ => ptr::null_mut(),
+        }
+    })
+}
+
+/// Answer a prompt from `rustaigent_poll_event`: `{"type": "approval", "id": 1, "approve": true}`
+/// or `{"type": "answer", "id": 2, "text": "..."}`. Returns `{}` or `{"error": "..."}`.
+///
+/// # Safety
+/// `agent` must come from `rustaigent_create_agent` and not be freed yet; `reply_json` must be a
+/// NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn rustaigent_reply(agent: *const RustAigent, reply_json: *const c_char) -> *mut c_char {
+    guarded(error_json, || {
+        let Some(agent) = agent.as_ref() else { return error_json(anyhow::anyhow!("NULL agent")) };
+        match from_c(reply_json).and_then(|reply| agent.stream.lock().unwrap().settle(reply)) {
+            Ok(()) => to_c(Value::Object(Default::default()).to_string()),
+            Err(err) => error_json(err),
+        }
+    })
+}
+
+/// Free a string returned by this library; NULL is ignored.
+///
+/// # Safety
+/// `text` must be NULL or a string from this library, freed only once.
+#[no_mangle]
+pub unsafe extern "C" fn rustaigent_free_string(text: *mut c_char) {
+    if !text.is_null() {
+        guarded(drop, || drop(CString::from_raw(text)));
+    }
+}
+
+/// End the session; prompts still waiting for a reply are declined. NULL is ignored.
+///
+/// # Safety
+/// `agent` must be NULL or come from `rustaigent_create_agent`, with no call on it still running.
+#[no_mangle]
+pub unsafe extern "C" fn rustaigent_free_agent(agent: *mut RustAigent) {
+    if !agent.is_null() {
+        guarded(drop, || drop(Box::from_raw(agent)));
+    }
+}
@@ -0,0 +1,326 @@
+use std::{fs, path::{Component, Path, PathBuf}, process::Command, time::{Duration, Instant}};
+use serde::{Serialize, Deserialize};
+use anyhow::{Result, Context, bail};
+use crate::{fences, paths, plan::Plan, project, session, stats, toolchain::Toolchain, tools::{self, ToolCall}, workspace, Agent, ChatMessage};
+
+/// Diagnostic lines kept per iteration; the end of the output usually names the failure
+const MAX_DIAGNOSTIC_LINES: usize = 150;
+
+/// A file the model rewrote, with content hashes before and after
+#[derive(Serialize, Deserialize)]
+pub struct Patch {
+    pub iteration: usize,
+    pub path: String,
+    /// `None` when the file was created
+    pub before: Option<String>,
+    pub after: String,
+}
+
+/// Bounds beyond the iteration count, checked after every iteration
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct StopConditions {
+    /// Give up once the loop has run this long, counted across resumes
+    pub max_time: Option<Duration>,
+    /// Give up once the loop's requests have cost this many USD
+    pub max_cost: Option<f64>,
+    /// Stop successfully once this command passes too, e.g. the full test suite
+    pub tests_pass: Option<String>,
+    /// Stop successfully once this file exists
+    pub file_exists: Option<PathBuf>,
+}
+
+impl StopConditions {
+    /// Conditions given on the command line replace the checkpointed ones
+    fn merge(&mut self, other: StopConditions) {
+        self.max_time = other.max_time.or(self.max_time);
+        self.max_cost = other.max_cost.or(self.max_cost);
+        self.tests_pass = other.tests_pass.or(self.tests_pass.take());
+        self.file_exists = other.file_exists.or(self.file_exists.take());
+    }
+
+    /// A limit the loop has used up
+    fn exhausted(&self, state: &FixState) -> Option<String> {
+        if let Some(max) = self.max_time.filter(|max| state.elapsed >= max.as_secs()) {
+            return Some(format!("the time limit of {} is used up", humantime::format_duration(max)));
+        }
+        self.max_cost.filter(|&max| state.spent >= max)
+            .map(|max| format!("the cost limit of ${:.2} is used up (${:.4} spent)", max, state.spent))
+    }
+
+    /// A goal other than the check command that has been met
+    fn reached(&self) -> Result<Option<String>> {
+        if let Some(path) = self.file_exists.as_ref().filter(|path| path.exists()) {
+            return Ok(Some(format!("{} exists", path.display())));
+        }
+        match &self.tests_pass {
+            Some(command) if check(command)?.is_none() => Ok(Some(format!("`{}` passes", command))),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Loop state, checkpointed after every step so an interrupted loop can resume
+#[derive(Serialize, Deserialize)]
+pub struct FixState {
+    pub command: String,
+    pub max_iterations: usize,
+    /// Iterations started so far
+    pub iteration: usize,
+    pub patches: Vec<Patch>,
+    pub last_diagnostics: String,
+    pub messages: Vec<ChatMessage>,
+    #[serde(default)]
+    pub plan: Option<Plan>,
+    #[serde(default)]
+    pub stop: StopConditions,
+    /// Seconds spent in the loop so far
+    #[serde(default)]
+    pub elapsed: u64,
+    /// USD spent on requests so far
+    #[serde(default)]
+    pub spent: f64,
+    pub updated: u64,
+}
+
+/// One state file per project directory, outside the working tree
+fn state_file(dir: &Path) -> PathBuf {
+    let key = session::sha256_hex(dir.to_string_lossy().as_bytes());
+    paths::data_dir().join("fix").join(format!("{}.json", &key[..16]))
+}
+
+impl FixState {
+    /// Write to a temporary file and rename it over the old state, so a crash mid-write keeps the previous checkpoint
+    fn save(&mut self, dir: &Path) -> Result<()> {
+        self.updated = stats::now_secs();
+        let path = state_file(dir);
+        fs::create_dir_all(path.parent().unwrap())?;
+        let tmp = path.with_extension("tmp");
+        crate::vault::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        fs::rename(&tmp, &path).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    fn load(dir: &Path) -> Result<Option<Self>> {
+        let path = state_file(dir);
+        let Some(bytes) = crate::vault::read(&path)? else { return Ok(None) };
+        Ok(Some(serde_json::from_slice(&bytes).with_context(|| format!("Corrupt fix state {}", path.display()))?))
+    }
+}
+
+/// Run the check command; `None` when it passes, otherwise the tail of its output
+fn check(command: &str) -> Result<Option<String>> {
+    let out = Command::new("sh").arg("-c").arg(command).output().with_context(|| format!("Failed to run `{}`", command))?;
+    if out.status.success() {
+        return Ok(None);
+    }
+    let text = format!("{}{}", String::from_utf8_lossy(&out.stdout), String::from_utf8_lossy(&out.stderr));
+    let lines: Vec<&str> = text.lines().collect();
+    let tail = &lines[lines.len().saturating_sub(MAX_DIAGNOSTIC_LINES)..];
+    Ok(Some(format!("{}\n{}", out.status, tail.join("\n"))))
+}
+
+/// Whole files in a reply, from fenced blocks whose info string is `file=<path>`, with or without
+/// a language before it
+fn file_blocks(reply: &str) -> Vec<(String, String)> {
+    let mut files = Vec::new();
+    let mut lines = reply.lines();
+    while let Some(line) = lines.next() {
+        let trimmed = line.trim_start();
+        let ticks = trimmed.len() - trimmed.trim_start_matches('`').len();
+        let Some((_, path)) = (ticks >= 3).then(|| fences::file_info(&trimmed[ticks..])).flatten() else {
+            continue;
+        };
+        let fence = &trimmed[..ticks];
+        let mut content = String::new();
+        for body in lines.by_ref() {
+            if body.trim() == fence {
+                break;
+            }
+            content.push_str(body);
+            content.push('\n');
+        }
+        files.push((path.trim().to_string(), content));
+    }
+    files
+}
+
+/// Only relative paths that stay inside the project
+fn inside_project(path: &str) -> bool {
+    Path::new(path).components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+}
+
+fn prompt(command: &str, diagnostics: &str) -> String {
+    format!(
+        "`{}` fails:\n```\n{}\n```\nFix the cause. For every file you change, reply with its complete new content in a fenced block \
+         whose info string is `file=<relative path>`, for example ```file=src/lib.rs. Keep explanations short.",
+        command, diagnostics)
+}
+
+/// The detected project's test command, scoped to the selected workspace package
+pub fn test_command(agent: &Agent, dir: &Path) -> Option<String> {
+    let test = project::detect(dir).into_iter().next()?.test;
+    Some(match &agent.tool_ctx.package {
+        Some(package) => workspace::scope_command(&test, package),
+        None => test,
+    })
+}
+
+/// `cargo +<msrv> check` for a cargo check command in a project that names its minimum Rust
+/// version, so fixes do not need a newer Rust than the project supports
+fn msrv_command(agent: &Agent, dir: &Path, command: &str) -> Option<String> {
+    if !command.trim_start().starts_with("cargo ") {
+        return None;
+    }
+    let check = Toolchain::detect(dir)?.msrv_check()?;
+    Some(match &agent.tool_ctx.package {
+        Some(package) => workspace::scope_command(&check, package),
+        None => check,
+    })
+}
+
+/// Time and money used by this process, added to what earlier runs of the loop used
+struct Usage {
+    started: Instant,
+    cost: f64,
+    elapsed: u64,
+    spent: f64,
+}
+
+impl Usage {
+    fn start(agent: &Agent, state: &FixState) -> Self {
+        Usage { started: Instant::now(), cost: agent.stats.lock().unwrap().cost(), elapsed: state.elapsed, spent: state.spent }
+    }
+
+    fn update(&self, agent: &Agent, state: &mut FixState) {
+        state.elapsed = self.elapsed + self.started.elapsed().as_secs();
+        state.spent = self.spent + agent.stats.lock().unwrap().cost() - self.cost;
+    }
+}
+
+/// Run the check, ask for fixes and apply them until the check passes, a stop condition is met
+/// or the iterations run out
+pub async fn run(agent: &mut Agent, command: Option<String>, max_iterations: Option<usize>, stop: StopConditions, resume: bool, plan: bool) -> Result<()> {
+    let dir = std::env::current_dir()?;
+    let mut state = if resume {
+        let mut state = FixState::load(&dir)?.context("No interrupted fix loop for this directory")?;
+        if let Some(max) = max_iterations {
+            state.max_iterations = max;
+        }
+        state.stop.merge(stop);
+        println!("Resuming `{}` after iteration {}/{} ({} files patched)", state.command, state.iteration, state.max_iterations, state.patches.len());
+        agent.conversation = std::mem::take(&mut state.messages).into();
+        agent.guard_checked = agent.conversation.len();
+        agent.plan = state.plan.take();
+        state
+    } else {
+        let command = match command {
+            Some(command) => command,
+            None => test_command(agent, &dir).context("No project manifest found; pass --command")?,
+        };
+        FixState {
+            command, max_iterations: max_iterations.unwrap_or(5), iteration: 0, patches: Vec::new(), last_diagnostics: String::new(),
+            messages: Vec::new(), plan: None, stop, elapsed: 0, spent: 0.0, updated: 0,
+        }
+    };
+    let usage = Usage::start(agent, &state);
+    let msrv = msrv_command(agent, &dir, &state.command);
+
+    loop {
+        let failing = match check(&state.command)? {
+            Some(diagnostics) => Some((state.command.clone(), diagnostics)),
+            None => match &msrv {
+                Some(msrv) => check(msrv)?.map(|diagnostics| (msrv.clone(), diagnostics)),
+                None => None,
+            },
+        };
+        let Some((failed, diagnostics)) = failing else {
+            match &msrv {
+                Some(msrv) => println!("`{}` and `{}` pass after {} iteration(s)", state.command, msrv, state.iteration),
+                None => println!("`{}` passes after {} iteration(s)", state.command, state.iteration),
+            }
+            fs::remove_file(state_file(&dir)).ok();
+            // A check that passed from the start says nothing about how well fixes go
+            if state.iteration > 0 {
+                agent.stats.lock().unwrap().record_fix(state.iteration, true);
+            }
+            return Ok(());
+        };
+        state.last_diagnostics = diagnostics;
+        if state.iteration >= state.max_iterations {
+            state.messages = agent.conversation.load_all()?;
+            state.save(&dir)?;
+            agent.stats.lock().unwrap().record_fix(state.iteration, false);
+            bail!("`{}` still fails after {} iterations; `rustaigent fix --resume --max-iterations <n>` continues", failed, state.iteration);
+        }
+        usage.update(agent, &mut state);
+        if let Some(reason) = state.stop.exhausted(&state) {
+            state.messages = agent.conversation.load_all()?;
+            state.save(&dir)?;
+            agent.stats.lock().unwrap().record_fix(state.iteration, false);
+            bail!("`{}` still fails after {} iterations and {}; `rustaigent fix --resume` with a higher limit continues", failed, state.iteration, reason);
+        }
+        if plan && agent.plan.is_none() {
+            let task = format!("Make `{}` pass. It currently fails with:\n```\n{}\n```", failed, state.last_diagnostics);
+            match agent.make_plan(&task).await {
+                Ok(Some(plan)) => println!("{}", plan.render()),
+                Ok(None) => {}
+                Err(err) => println!("Continuing without a plan: {:#}", err),
+            }
+        }
+        state.iteration += 1;
+        println!("Iteration {}/{}: `{}` fails; asking for a fix", state.iteration, state.max_iterations, failed);
+        state.messages = agent.conversation.load_all()?;
+        state.plan = agent.plan.clone();
+        state.save(&dir)?;
+
+        let Some(reply) = agent.turn(&prompt(&failed, &state.last_diagnostics)).await? else {
+            bail!("The guardrails held the diagnostics back; fix loop stopped");
+        };
+        let files = file_blocks(&reply.content);
+        if files.is_empty() {
+            println!("  the reply changed no files");
+        }
+        for (path, content) in files {
+            if !inside_project(&path) {
+                println!("  skipped {}: outside the project", path);
+                continue;
+            }
+            if let Some((lang, err)) = fences::detect(Some(&path), &content).and_then(|lang| fences::syntax_error(lang, &content).map(|err| (lang, err))) {
+                println!("  skipped {}: not valid {} ({})", path, lang, err);
+                continue;
+            }
+            let before = fs::read(&path).ok().map(|b| session::sha256_hex(&b));
+            let after = session::sha256_hex(content.as_bytes());
+            let call = ToolCall::WriteFile { path: path.clone(), content };
+            let result = tools::execute(&mut agent.tool_ctx, &call).await;
+            if agent.tool_ctx.dry_run {
+                println!("  would patch {}", path);
+                continue;
+            }
+            if result.is_ok() {
+                println!("  patched {}", path);
+                agent.plan_progress(&call);
+            }
+            agent.tool_log.push(session::ToolCall {
+                call,
+                ok: result.is_ok(),
+                output: match &result { Ok(out) => out.clone(), Err(err) => format!("{:#}", err) },
+                at: stats::now_secs(),
+                turn: agent.conversation.total_len(),
+            });
+            match result {
+                Ok(_) => state.patches.push(Patch { iteration: state.iteration, path, before, after }),
+                Err(err) => println!("  skipped {}: {:#}", path, err),
+            }
+        }
+        state.messages = agent.conversation.load_all()?;
+        state.plan = agent.plan.clone();
+        usage.update(agent, &mut state);
+        state.save(&dir)?;
+        if let Some(reason) = state.stop.reached()? {
+            println!("Stopping after {} iteration(s): {}", state.iteration, reason);
+            fs::remove_file(state_file(&dir)).ok();
+            agent.stats.lock().unwrap().record_fix(state.iteration, true);
+            return Ok(());
+        }
+    }
+}
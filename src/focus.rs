@@ -0,0 +1,147 @@
+//! `--context editor`: the file and selection open in the user's editor, kept in one focus note
+//! so "fix this function" needs no path. An editor plugin writes the position as JSON to
+//! `.rustaigent/focus.json` (the nearest one up from the working directory) or to `focus_file`
+//! whenever the cursor settles:
+//!
+//! ```json
+//! {"path": "src/lib.rs", "line": 42, "column": 5, "selection": {"start": 40, "end": 55}}
+//! ```
+//!
+//! `line`, `column` and `selection` (1-based, inclusive lines) are optional, and `text` may carry
+//! the selected text of an unsaved buffer. Relative paths are taken from the directory holding
+//! `.rustaigent`, or the one holding `focus_file`. Before each prompt the note is replaced when the focus moved, and taken out
+//! once the file is gone or has not been written for an hour.
+
+use std::{fs, path::{Path, PathBuf}, time::Duration};
+use serde::Deserialize;
+
+/// Name marking the system note that carries the focus
+pub const NOTE: &str = "editor_focus";
+
+/// Age after which the editor is taken to be closed
+const MAX_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// Lines shown on each side of the cursor when nothing is selected
+const AROUND: usize = 15;
+
+/// Excerpt kept; the model can read the file for the rest
+const MAX_CHARS: usize = 8000;
+
+#[derive(Deserialize)]
+struct Selection {
+    start: usize,
+    end: usize,
+}
+
+#[derive(Deserialize)]
+struct Position {
+    path: PathBuf,
+    line: Option<usize>,
+    column: Option<usize>,
+    selection: Option<Selection>,
+    text: Option<String>,
+}
+
+/// Where the focus is read from
+pub struct Focus {
+    file: PathBuf,
+}
+
+impl Focus {
+    /// Watch `file`, else the nearest `.rustaigent/focus.json`
+    pub fn new(file: Option<PathBuf>) -> Self {
+        let file = file.unwrap_or_else(|| {
+            let cwd = std::env::current_dir().unwrap_or_default();
+            let dir = cwd.ancestors().find(|d| d.join(".rustaigent").is_dir()).unwrap_or(&cwd);
+            dir.join(".rustaigent").join("focus.json")
+        });
+        Focus { file }
+    }
+
+    /// The current focus as a note for the model; `None` when no editor reported one lately
+    pub fn collect(&self) -> Option<String> {
+        let age = fs::metadata(&self.file).and_then(|m| m.modified()).ok()?.elapsed().unwrap_or_default();
+        if age > MAX_AGE {
+            return None;
+        }
+        let position: Position = match serde_json::from_slice(&fs::read(&self.file).ok()?) {
+            Ok(position) => position,
+            Err(err) => {
+                tracing::warn!("ignoring {}: {}", self.file.display(), err);
+                return None;
+            }
+        };
+        let dir = self.file.parent().unwrap_or(Path::new("."));
+        let root = if dir.ends_with(".rustaigent") { dir.parent().unwrap_or(dir) } else { dir };
+        let full = root.join(&position.path);
+        let cwd = std::env::current_dir().unwrap_or_default();
+        let shown = full.strip_prefix(&cwd).unwrap_or(&full).display().to_string();
+        let mut note = format!("The user's editor has `{}` open", shown);
+        if let Some(line) = position.line {
+            note.push_str(&format!(" with the cursor at line {}", line));
+            if let Some(column) = position.column {
+                note.push_str(&format!(", column {}", column));
+            }
+        }
+        note.push_str(". When the user says \"this\", \"here\" or \"this function\" without naming a file, they mean this spot; read the file for more.\n");
+        let selected = position.selection.is_some();
+        let (start, end, excerpt) = match (position.selection, position.text) {
+            (Some(sel), Some(text)) => (sel.start, sel.end, text),
+            (sel, _) => {
+                let lines: Vec<String> = fs::read_to_string(&full).unwrap_or_default().lines().map(String::from).collect();
+                let (start, end) = match sel {
+                    Some(sel) => (sel.start.max(1), sel.end.min(lines.len())),
+                    None => {
+                        let line = position.line.unwrap_or(1);
+                        (line.saturating_sub(AROUND).max(1), (line + AROUND).min(lines.len()))
+                    }
+                };
+                let excerpt = if start <= end { lines[start - 1..end].join("\n") } else { String::new() };
+                (start, end, excerpt)
+            }
+        };
+        if excerpt.trim().is_empty() {
+            return Some(note.trim_end().to_string());
+        }
+        let excerpt = match excerpt.char_indices().nth(MAX_CHARS) {
+            Some((cut, _)) => format!("{}\n…", &excerpt[..cut]),
+            None => excerpt,
+        };
+        let what = if selected { "Selected" } else { "Around the cursor" };
+        note.push_str(&format!("\n{}:\n[focus: {}:{}-{}]\n```\n{}\n```", what, shown, start, end, excerpt));
+        Some(note.trim_end().to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use crate::mock::MockProvider;
+    use super::NOTE;
+
+    #[tokio::test]
+    async fn the_editor_focus_note_follows_the_cursor() {
+        let mock = MockProvider::start().await;
+        mock.reply("first").await;
+        mock.reply("second").await;
+        let file = std::env::temp_dir().join(format!("rustaigent-focus-{}.json", std::process::id()));
+        let manifest = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml");
+        std::fs::write(&file, json!({"path": manifest, "line": 3}).to_string()).unwrap();
+        let mut agent = mock.agent();
+        agent.config.focus_file = Some(file.clone());
+        agent.enable_focus();
+        agent.enable_quiet();
+        agent.turn("What does this line do?").await.unwrap();
+        std::fs::write(&file, json!({"path": manifest, "selection": {"start": 1, "end": 2}}).to_string()).unwrap();
+        agent.turn("And this?").await.unwrap();
+        std::fs::remove_file(&file).unwrap();
+        let notes: Vec<&str> = agent.conversation.iter()
+            .filter(|m| m.name.as_deref() == Some(NOTE))
+            .map(|m| m.content.as_str())
+            .collect();
+        assert_eq!(notes.len(), 1);
+        assert!(notes[0].contains("Selected:\n[focus: ") && notes[0].contains(":1-2]\n```\n[package]"));
+        let requests = mock.requests().await;
+        assert!(requests[0]["messages"].to_string().contains("with the cursor at line 3"));
+    }
+}
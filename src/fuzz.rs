@@ -0,0 +1,246 @@
+//! Property tests for the parsers that read untrusted model output: tool arguments,
+//! structured-file patches and fenced code blocks. Malformed input must come back as an
+//! error or an empty result, never as a panic.
+
+use std::{fs, path::{Path, PathBuf}};
+use proptest::prelude::*;
+use serde_json::{json, Value};
+use crate::{citations::Sources, context, digest, fences, golden, index, lint, notebook, oneshot, reasoning, tools};
+
+/// Arbitrary JSON up to a few levels deep
+fn json_value() -> impl Strategy<Value = Value> {
+    let leaf = prop_oneof![
+        Just(Value::Null),
+        any::<bool>().prop_map(Value::Bool),
+        any::<i64>().prop_map(|n| json!(n)),
+        // Quarter steps print and parse back exactly
+        any::<i32>().prop_map(|n| json!(n as f64 / 4.0)),
+        ".{0,12}".prop_map(Value::String),
+    ];
+    leaf.prop_recursive(3, 24, 4, |inner| prop_oneof![
+        prop::collection::vec(inner.clone(), 0..4).prop_map(Value::Array),
+        prop::collection::btree_map("[a-z~/]{0,6}", inner, 0..4).prop_map(|m| Value::Object(m.into_iter().collect())),
+    ])
+}
+
+/// JSON that is anything but a string at the top level
+fn non_string() -> impl Strategy<Value = Value> {
+    json_value().prop_filter("not a string", |v| !v.is_string())
+}
+
+/// Escape a key as one JSON pointer token
+fn escape(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+/// Run `edit_structured`, which never awaits, on the current thread
+fn edit(args: Value) -> anyhow::Result<String> {
+    let call = tools::ToolCall::parse("edit_structured", args)?;
+    futures::executor::block_on(tools::execute(&mut tools::ToolContext::default(), &call))
+}
+
+fn scratch_file(name: &str, content: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rustaigent-fuzz-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    fs::write(&path, content).unwrap();
+    path
+}
+
+#[test]
+fn tool_invocations() {
+    let agent = golden::agent("openai");
+    proptest!(|(input in ".*")| {
+        let _ = agent.parse_tool_invocation(&input);
+    });
+    proptest!(|(path in ".*")| {
+        let input = format!("list_dir({})", serde_json::to_string(&path).unwrap());
+        let (name, args) = agent.parse_tool_invocation(&input).unwrap();
+        prop_assert_eq!(name, "list_dir");
+        prop_assert_eq!(args["path"].as_str(), Some(path.as_str()));
+    });
+}
+
+/// Wrongly typed arguments never become a `ToolCall`, so no tool touches files, processes or the network
+#[test]
+fn tool_argument_types() {
+    let calls: &[(&str, &[&str])] = &[
+        ("read_file", &["path"]),
+        ("write_file", &["path", "content"]),
+        ("apply_patch", &["patch"]),
+        ("delete_file", &["path"]),
+        ("list_dir", &["path"]),
+        ("run_command", &["command"]),
+        ("fetch_url", &["url"]),
+        ("eval_code", &["code"]),
+        ("extract_archive", &["archive", "destination"]),
+        ("create_archive", &["archive", "paths"]),
+        ("download_file", &["url", "path"]),
+        ("diff", &["left"]),
+        ("replace_in_files", &["pattern", "replacement", "scope"]),
+        ("edit_structured", &["path", "pointer", "op"]),
+        ("ask_user", &["question"]),
+        ("calculate", &["expression"]),
+        ("generate_random", &["kind"]),
+        ("test_regex", &["pattern", "inputs"]),
+        ("search_codebase", &["query"]),
+        ("read_artifact", &["id"]),
+        ("write_note", &["note"]),
+        ("git_commit", &["message"]),
+        ("git_create_branch", &["name"]),
+    ];
+    for (tool, keys) in calls {
+        proptest!(|(values in prop::collection::vec(non_string(), keys.len()))| {
+            let args = Value::Object(keys.iter().map(|k| k.to_string()).zip(values).collect());
+            prop_assert!(tools::ToolCall::parse(tool, args).is_err());
+        });
+    }
+}
+
+#[test]
+fn json_patches() {
+    proptest!(|(doc in json_value(), pointer in "(/[a-z0-9~\\-]{0,4}){0,4}", value in json_value(), remove in any::<bool>())| {
+        let path = scratch_file("doc.json", &serde_json::to_string_pretty(&doc).unwrap());
+        let op = if remove { "remove" } else { "set" };
+        if edit(json!({"path": path, "pointer": pointer, "op": op, "value": value})).is_ok() {
+            prop_assert!(serde_json::from_str::<Value>(&fs::read_to_string(&path).unwrap()).is_ok());
+        }
+    });
+    proptest!(|(key in ".{1,8}", value in json_value())| {
+        let path = scratch_file("set.json", "{\n  \"keep\": 1\n}\n");
+        let args = json!({"path": path, "pointer": format!("/{}", escape(&key)), "op": "set", "value": value});
+        edit(args).unwrap();
+        let written: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        prop_assert_eq!(&written[key.as_str()], &value);
+        prop_assert_eq!(&written["keep"], &json!(1));
+    });
+}
+
+#[test]
+fn toml_patches() {
+    let doc = "# settings\n[package]\nname = \"demo\"\nkeywords = [\"a\", \"b\"]\n\n[[bin]]\nname = \"x\"\n";
+    proptest!(|(pointer in "(/(package|bin|name|keywords|0|1|-|[a-z]{1,3})){1,4}", value in json_value(), remove in any::<bool>())| {
+        let path = scratch_file("doc.toml", doc);
+        let op = if remove { "remove" } else { "set" };
+        let args = json!({"path": path, "pointer": pointer, "op": op, "value": value});
+        if edit(args).is_ok() {
+            let text = fs::read_to_string(&path).unwrap();
+            prop_assert!(text.parse::<toml_edit::DocumentMut>().is_ok(), "edit produced invalid TOML:\n{}", text);
+        }
+    });
+}
+
+#[test]
+fn unified_diffs() {
+    // Few distinct lines, so hunks have repeated context to match against
+    let text = prop::collection::vec("[ab ]{0,2}", 0..12).prop_map(|lines| lines.join("\n"));
+    proptest!(|(old in text.clone(), new in text, newline in any::<bool>())| {
+        let (old, new) = if newline { (format!("{}\n", old), format!("{}\n", new)) } else { (old, new) };
+        let path = scratch_file("patched.txt", &old);
+        let path = path.to_string_lossy();
+        let diff = tools::diff::unified(&old, &new, &path, &path);
+        if !diff.is_empty() {
+            let changes = tools::patch::prepare(Path::new(""), &diff, None).unwrap();
+            prop_assert_eq!(changes[0].new.as_deref(), Some(new.as_str()), "diff:\n{}", diff);
+        }
+    });
+    proptest!(|(patch in "[-+ @,0-9a<=>\n]{0,120}")| {
+        let path = scratch_file("garbage.txt", "a\nb\n");
+        let _ = tools::patch::prepare(Path::new(""), &patch, Some(&path.to_string_lossy()));
+    });
+}
+
+#[test]
+fn code_blocks() {
+    proptest!(|(text in "(.|\n){0,200}")| {
+        let _ = lint::extract_rust_blocks(&text);
+        let _ = reasoning::split_think_tags(&text);
+        let _ = oneshot::select(&text, oneshot::Output::Code);
+        let _ = oneshot::select(&text, oneshot::Output::Patch);
+        // Tagging only adds to fence lines, and a tagged text is left alone
+        let tagged = fences::tag(&text);
+        prop_assert_eq!(tagged.lines().count(), text.lines().count());
+        prop_assert_eq!(fences::tag(&tagged), tagged);
+    });
+    assert_eq!(fences::tag("Run:
+```
+fn main() {
+    println!(\"hi\");
+}
+```
+"), "Run:
+```rust
+fn main() {
+    println!(\"hi\");
+}
+```
+");
+    assert_eq!(fences::tag("````file=conf/app.yml\nkey: 1\n````"), "````yaml file=conf/app.yml\nkey: 1\n````");
+    // Lines that can't be mistaken for a fence
+    let line = "[^`\r\n]{0,20}";
+    proptest!(|(before in prop::collection::vec(line, 0..3), body in prop::collection::vec(line, 0..5), after in prop::collection::vec(line, 0..3))| {
+        let body = body.iter().map(|l| format!("{}\n", l)).collect::<String>();
+        let text = format!("{}\n```rust\n{}```\n{}", before.join("\n"), body, after.join("\n"));
+        prop_assert_eq!(lint::extract_rust_blocks(&text), vec![body.clone()]);
+        let diff = text.replacen("```rust", "```diff", 1);
+        prop_assert_eq!(oneshot::select(&diff, oneshot::Output::Patch).unwrap(), body);
+    });
+    proptest!(|(thinking in "[^<]{0,30}", answer in "[^<]{0,30}")| {
+        let (found, rest) = reasoning::split_think_tags(&format!("<think>{}</think>{}", thinking, answer));
+        prop_assert_eq!(found.as_deref(), Some(thinking.trim()));
+        prop_assert_eq!(rest, answer.trim_start());
+    });
+}
+
+#[test]
+fn diff_chunks() {
+    let hunk = prop::collection::vec("[-+ ][a-z ]{0,30}", 1..6).prop_map(|lines| format!("@@ -1 +1 @@\n{}\n", lines.join("\n")));
+    let file = ("[a-z]{1,8}", prop::collection::vec(hunk, 0..4))
+        .prop_map(|(name, hunks)| format!("diff --git a/{0} b/{0}\n--- a/{0}\n+++ b/{0}\n{1}", name, hunks.concat()));
+    proptest!(|(files in prop::collection::vec(file, 0..5), budget in 0u32..80)| {
+        let diff = files.concat();
+        let chunks = digest::chunks(&diff, budget);
+        for chunk in &chunks {
+            prop_assert!(context::estimate_text(chunk) <= budget.max(1), "chunk over {}:\n{}", budget, chunk);
+        }
+        // Nothing is cut when everything fits
+        if context::estimate_text(&diff) <= budget {
+            prop_assert_eq!(chunks.concat(), diff);
+        }
+    });
+}
+
+#[test]
+fn notebook_cells() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    // Fences, `output` blocks and unknown cells, but no runnable languages
+    proptest!(|(text in "[`\n otupl{}]{0,120}")| {
+        let mut ctx = tools::ToolContext::default();
+        let _ = runtime.block_on(notebook::rerun(&text, &mut ctx));
+    });
+}
+
+#[test]
+fn citations() {
+    proptest!(|(answer in "[\\[\\]0-9, a-z]{0,60}", count in 0usize..4)| {
+        let mut sources = Sources::default();
+        for i in 0..count {
+            sources.add(format!("file{}.rs:1-1", i), "");
+        }
+        for citation in sources.cited(&answer) {
+            prop_assert!((1..=count).contains(&citation.id));
+        }
+    });
+    // `search_codebase` matches become sources with the location and text they were found with
+    let hit = ("[a-z/]{1,12}\\.rs:[0-9]{1,3}-[0-9]{1,3}", "[^\\[]{1,80}");
+    proptest!(|(hits in prop::collection::vec(hit, 0..4))| {
+        let hits: Vec<index::Hit> = hits.into_iter().map(|(location, text)| index::Hit { location, text, score: 0.5 }).collect();
+        let output = index::render(&hits);
+        let found = index::matches(&output);
+        prop_assert_eq!(found.len(), hits.len());
+        for ((location, text), hit) in found.iter().zip(&hits) {
+            prop_assert_eq!(location, &hit.location);
+            prop_assert_eq!(*text, hit.text.as_str());
+        }
+    });
+}
@@ -0,0 +1,179 @@
+//! Google Cloud credentials for Vertex AI, found the way Google's client libraries look for the
+//! Application Default Credentials: the key file `vertex.credentials` or
+//! GOOGLE_APPLICATION_CREDENTIALS names, the one `gcloud auth application-default login`
+//! writes, then the metadata server of Compute Engine, Cloud Run and GKE. Access tokens are
+//! minted from them and reused until shortly before they expire.
+
+use std::{env, fs, path::PathBuf, time::{Duration, Instant}};
+use futures::future::BoxFuture;
+use reqwest::Client;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use anyhow::{Result, Context, bail};
+use crate::auth::Authenticator;
+
+/// `[vertex]` table: where requests go and whose credentials they carry
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct VertexConfig {
+    /// Google Cloud project (default: GOOGLE_CLOUD_PROJECT, then the credentials' project)
+    pub project: Option<String>,
+    /// Region of the endpoint, e.g. `europe-west4`, or `global` (default: GOOGLE_CLOUD_LOCATION,
+    /// then `us-central1`)
+    pub location: Option<String>,
+    /// Service account key or ADC file, in place of GOOGLE_APPLICATION_CREDENTIALS
+    pub credentials: Option<PathBuf>,
+}
+
+/// Keys accepted in `[vertex]`, for config validation
+pub const KEYS: &[&str] = &["project", "location", "credentials"];
+
+const SCOPE: &str = "https://www.googleapis.com/auth/cloud-platform";
+const TOKEN_URI: &str = "https://oauth2.googleapis.com/token";
+const METADATA_TOKEN: &str = "http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token";
+
+/// Tokens are minted again this long before they expire
+const MARGIN: Duration = Duration::from_secs(60);
+
+/// Fields of a credentials file, of either type
+#[derive(Deserialize)]
+struct KeyFile {
+    #[serde(rename = "type")]
+    kind: String,
+    client_email: Option<String>,
+    private_key: Option<String>,
+    token_uri: Option<String>,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+    refresh_token: Option<String>,
+    project_id: Option<String>,
+    quota_project_id: Option<String>,
+}
+
+/// The credentials file in effect: the configured one, GOOGLE_APPLICATION_CREDENTIALS, else
+/// gcloud's if it exists
+fn key_path(cfg: &VertexConfig) -> Option<PathBuf> {
+    if let Some(path) = cfg.credentials.clone().or_else(|| env::var_os("GOOGLE_APPLICATION_CREDENTIALS").map(PathBuf::from)) {
+        return Some(path);
+    }
+    let gcloud = match env::var_os("CLOUDSDK_CONFIG") {
+        Some(dir) => PathBuf::from(dir),
+        None if cfg!(windows) => dirs::config_dir()?.join("gcloud"),
+        None => dirs::home_dir()?.join(".config").join("gcloud"),
+    };
+    Some(gcloud.join("application_default_credentials.json")).filter(|p| p.is_file())
+}
+
+fn read_key(path: &PathBuf) -> Result<KeyFile> {
+    let text = fs::read_to_string(path).with_context(|| format!("Failed to read Google credentials {}", path.display()))?;
+    serde_json::from_str(&text).with_context(|| format!("{} is not a Google credentials file", path.display()))
+}
+
+/// The project requests are billed to: the configured one, GOOGLE_CLOUD_PROJECT, else the one
+/// the credentials file names
+pub fn project(cfg: &VertexConfig) -> Option<String> {
+    cfg.project.clone()
+        .or_else(|| env::var("GOOGLE_CLOUD_PROJECT").ok())
+        .or_else(|| key_path(cfg).and_then(|path| read_key(&path).ok()).and_then(|key| key.project_id.or(key.quota_project_id)))
+}
+
+/// RS256 signature of `message` with a PEM private key
+#[cfg(all(unix, not(target_vendor = "apple")))]
+fn sign(pem: &str, message: &[u8]) -> Result<Vec<u8>> {
+    use openssl::{hash::MessageDigest, pkey::PKey, sign::Signer};
+    let key = PKey::private_key_from_pem(pem.as_bytes()).context("The service account's private key is not a PEM key")?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+    signer.update(message)?;
+    Ok(signer.sign_to_vec()?)
+}
+
+#[cfg(not(all(unix, not(target_vendor = "apple"))))]
+fn sign(_: &str, _: &[u8]) -> Result<Vec<u8>> {
+    bail!("Service account keys are not supported on this platform; sign in with `gcloud auth application-default login` or mint tokens with an [auth.vertex] command")
+}
+
+/// Access tokens for Vertex AI from the Application Default Credentials
+pub struct Adc {
+    client: Client,
+    cfg: VertexConfig,
+    token: tokio::sync::Mutex<Option<(String, Instant)>>,
+}
+
+impl Adc {
+    pub fn new(client: Client, cfg: VertexConfig) -> Self {
+        Adc { client, cfg, token: tokio::sync::Mutex::new(None) }
+    }
+
+    /// A token and its lifetime, fresh from the credentials
+    async fn mint(&self) -> Result<(String, u64)> {
+        let response: Value = match key_path(&self.cfg) {
+            Some(path) => {
+                let key = read_key(&path)?;
+                let form: Vec<(&str, String)> = match key.kind.as_str() {
+                    "service_account" => {
+                        let (email, pem) = key.client_email.zip(key.private_key).with_context(|| format!("{} lacks `client_email` or `private_key`", path.display()))?;
+                        vec![("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer".into()), ("assertion", assertion(&email, &pem, key.token_uri.as_deref().unwrap_or(TOKEN_URI))?)]
+                    }
+                    "authorized_user" => vec![
+                        ("grant_type", "refresh_token".into()),
+                        ("client_id", key.client_id.unwrap_or_default()),
+                        ("client_secret", key.client_secret.unwrap_or_default()),
+                        ("refresh_token", key.refresh_token.with_context(|| format!("{} lacks `refresh_token`", path.display()))?),
+                    ],
+                    other => bail!("{} holds `{}` credentials; use a service account key or `gcloud auth application-default login`", path.display(), other),
+                };
+                let uri = key.token_uri.as_deref().filter(|_| key.kind == "service_account").unwrap_or(TOKEN_URI);
+                let response = self.client.post(uri).form(&form).send().await.context("Could not reach Google's token endpoint")?;
+                let status = response.status();
+                let body: Value = response.json().await.unwrap_or_default();
+                if !status.is_success() {
+                    bail!("Google refused the credentials in {} ({}): {}", path.display(), status, body["error_description"].as_str().or(body["error"].as_str()).unwrap_or("no details"));
+                }
+                body
+            }
+            None => {
+                let response = self.client.get(METADATA_TOKEN).header("Metadata-Flavor", "Google").timeout(Duration::from_secs(3)).send().await
+                    .context("No Google Cloud credentials: set GOOGLE_APPLICATION_CREDENTIALS or `vertex.credentials`, or run `gcloud auth application-default login`")?;
+                if !response.status().is_success() {
+                    bail!("The metadata server returned {} for an access token", response.status());
+                }
+                response.json().await?
+            }
+        };
+        let token = response["access_token"].as_str().context("Google's token response has no access_token")?;
+        Ok((token.to_string(), response["expires_in"].as_u64().unwrap_or(3600)))
+    }
+}
+
+/// JWT asserting the service account's identity for the cloud-platform scope
+fn assertion(email: &str, pem: &str, audience: &str) -> Result<String> {
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    let now = crate::stats::now_secs();
+    let header = URL_SAFE_NO_PAD.encode(r#"{"alg":"RS256","typ":"JWT"}"#);
+    let claims = URL_SAFE_NO_PAD.encode(serde_json::json!({"iss": email, "scope": SCOPE, "aud": audience, "iat": now, "exp": now + 3600}).to_string());
+    let unsigned = format!("{}.{}", header, claims);
+    let signature = URL_SAFE_NO_PAD.encode(sign(pem, unsigned.as_bytes())?);
+    Ok(format!("{}.{}", unsigned, signature))
+}
+
+impl Authenticator for Adc {
+    fn headers<'a>(&'a self, _: &'a reqwest::Request) -> BoxFuture<'a, Result<Vec<(String, String)>>> {
+        Box::pin(async move {
+            let mut cached = self.token.lock().await;
+            let token = match cached.as_ref().filter(|(_, until)| Instant::now() < *until) {
+                Some((token, _)) => token.clone(),
+                None => {
+                    let (token, lifetime) = self.mint().await?;
+                    *cached = Some((token.clone(), Instant::now() + Duration::from_secs(lifetime).saturating_sub(MARGIN)));
+                    token
+                }
+            };
+            Ok(vec![("Authorization".into(), format!("Bearer {}", token))])
+        })
+    }
+
+    fn invalidate(&self) {
+        if let Ok(mut token) = self.token.try_lock() {
+            *token = None;
+        }
+    }
+}
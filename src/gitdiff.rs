@@ -0,0 +1,87 @@
+//! `--context git-diff`: the working tree's uncommitted changes, and optionally the branch's
+//! commits since a base, sent along with each prompt so half-done work can be picked up.
+
+use std::{path::Path, process::Command};
+use anyhow::{Result, Context, bail};
+
+/// Diff text kept per section; the model can read the files for the rest
+const MAX_DIFF_CHARS: usize = 24_000;
+
+/// Untracked files listed by name
+const MAX_UNTRACKED: usize = 50;
+
+/// Name marking the system note that carries the diff
+pub const NOTE: &str = "git_diff";
+
+/// What to inject and what was injected last
+pub struct DiffContext {
+    /// Also show what the branch committed since it left this ref, e.g. `main`
+    pub base: Option<String>,
+    /// The note sent with the latest prompt, so an unchanged tree is not sent again
+    pub last: Option<String>,
+}
+
+/// Run git in the current directory, returning its output or its error message
+pub fn git(args: &[&str]) -> Result<String> {
+    git_in(Path::new("."), args)
+}
+
+/// Run git in `dir`, returning its output or its error message
+pub fn git_in(dir: &Path, args: &[&str]) -> Result<String> {
+    let out = Command::new("git").args(args).current_dir(dir).output().context("Failed to run git")?;
+    if !out.status.success() {
+        bail!("`git {}` failed: {}", args.join(" "), String::from_utf8_lossy(&out.stderr).trim());
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+/// Cut at a line boundary, saying how much was left out
+pub fn truncate(diff: &str) -> String {
+    if diff.len() <= MAX_DIFF_CHARS {
+        return diff.trim_end().to_string();
+    }
+    let end = (0..=MAX_DIFF_CHARS).rev().find(|&i| diff.is_char_boundary(i)).unwrap_or(0);
+    let cut = diff[..end].rfind('\n').unwrap_or(0);
+    let left = diff[cut..].lines().count();
+    format!("{}\n… {} more lines not shown; read the files for the rest", &diff[..cut], left)
+}
+
+impl DiffContext {
+    pub fn new(base: Option<String>) -> Self {
+        DiffContext { base, last: None }
+    }
+
+    /// The current changes as a note for the model; `None` outside a git repository
+    pub fn collect(&self) -> Result<Option<String>> {
+        if git(&["rev-parse", "--is-inside-work-tree"]).is_err() {
+            return Ok(None);
+        }
+        let mut note = String::from("Current state of the user's git working tree. Treat it as the work in progress the user refers to.\n");
+        // A repository without commits has no HEAD to diff against
+        let uncommitted = match git(&["rev-parse", "--verify", "-q", "HEAD"]) {
+            Ok(_) => git(&["diff", "HEAD"])?,
+            Err(_) => git(&["diff", "--cached"])? + &git(&["diff"])?,
+        };
+        if uncommitted.trim().is_empty() {
+            note.push_str("\nNo uncommitted changes to tracked files.\n");
+        } else {
+            note.push_str(&format!("\nUncommitted changes (`git diff HEAD`):\n```diff\n{}\n```\n", truncate(&uncommitted)));
+        }
+        let untracked: Vec<String> = git(&["ls-files", "--others", "--exclude-standard"])?.lines().map(String::from).collect();
+        if !untracked.is_empty() {
+            let more = untracked.len().saturating_sub(MAX_UNTRACKED);
+            let shown = untracked.into_iter().take(MAX_UNTRACKED).collect::<Vec<_>>().join(", ");
+            note.push_str(&format!("\nUntracked files: {}{}\n", shown, if more > 0 { format!(" and {} more", more) } else { String::new() }));
+        }
+        if let Some(base) = &self.base {
+            let range = format!("{}...HEAD", base);
+            let committed = git(&["diff", &range]).with_context(|| format!("Cannot diff against `{}`", base))?;
+            if committed.trim().is_empty() {
+                note.push_str(&format!("\nNo commits on this branch since `{}`.\n", base));
+            } else {
+                note.push_str(&format!("\nCommitted on this branch since `{}` (`git diff {}`):\n```diff\n{}\n```\n", base, range, truncate(&committed)));
+            }
+        }
+        Ok(Some(note.trim_end().to_string()))
+    }
+}
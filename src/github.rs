@@ -0,0 +1,110 @@
+//! GitHub REST API access for `work-on` and `pr-describe`: repositories found from web URLs and
+//! git remotes, authenticated with `GITHUB_TOKEN` when it is set.
+
+use reqwest::{Method, RequestBuilder, Url};
+use serde_json::Value;
+use anyhow::{Result, Context, bail};
+use crate::{http, Agent};
+
+/// A repository and the API serving it
+pub struct Repo {
+    /// `https://api.github.com`, or `https://<host>/api/v3` for GitHub Enterprise
+    api: String,
+    pub owner: String,
+    pub name: String,
+}
+
+impl Repo {
+    fn new(origin: &Url, owner: &str, name: &str) -> Self {
+        let api = match origin.host_str() {
+            Some("github.com" | "www.github.com") => "https://api.github.com".to_string(),
+            _ => format!("{}/api/v3", origin.origin().ascii_serialization()),
+        };
+        Repo { api, owner: owner.into(), name: name.trim_end_matches(".git").into() }
+    }
+
+    /// `https://github.com/<owner>/<repo>/<rest...>`, returning the path segments after the repository
+    pub fn from_web_url(url: &str) -> Result<(Self, Vec<String>)> {
+        let parsed = Url::parse(url).with_context(|| format!("`{}` is not a URL", url))?;
+        let segments: Vec<String> = parsed.path_segments().into_iter().flatten().filter(|s| !s.is_empty()).map(String::from).collect();
+        let [owner, name, rest @ ..] = &segments[..] else {
+            bail!("`{}` does not name a repository", url);
+        };
+        Ok((Repo::new(&parsed, owner, name), rest.to_vec()))
+    }
+
+    /// The repository a git remote points at: `https://host/o/r.git`, `ssh://git@host/o/r.git`
+    /// or `git@host:o/r.git`
+    pub fn from_remote(remote: &str) -> Result<Self> {
+        let remote = remote.trim();
+        let url = match Url::parse(remote) {
+            Ok(url) => url,
+            // scp-like syntax has no scheme
+            Err(_) => {
+                let (host, path) = remote.split_once(':').with_context(|| format!("Cannot read the git remote `{}`", remote))?;
+                let host = host.rsplit('@').next().unwrap_or(host);
+                Url::parse(&format!("https://{}/{}", host, path)).with_context(|| format!("Cannot read the git remote `{}`", remote))?
+            }
+        };
+        let origin = match url.scheme() {
+            "https" | "http" => url.clone(),
+            _ => Url::parse(&format!("https://{}", url.host_str().context("Git remote has no host")?))?,
+        };
+        let segments: Vec<&str> = url.path_segments().into_iter().flatten().filter(|s| !s.is_empty()).collect();
+        let [owner, name] = segments[..] else {
+            bail!("The git remote `{}` is not a GitHub repository", remote);
+        };
+        Ok(Repo::new(&origin, owner, name))
+    }
+
+    /// The repository of the `origin` remote of the current checkout
+    pub fn origin() -> Result<Self> {
+        let remote = crate::gitdiff::git(&["remote", "get-url", "origin"]).context("The repository has no `origin` remote")?;
+        Repo::from_remote(&remote)
+    }
+
+    /// API URL of `path` under the repository, e.g. `issues/7`
+    pub fn url(&self, path: &str) -> String {
+        format!("{}/repos/{}/{}/{}", self.api, self.owner, self.name, path)
+    }
+}
+
+impl std::fmt::Display for Repo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.owner, self.name)
+    }
+}
+
+pub fn token() -> Option<String> {
+    std::env::var("GITHUB_TOKEN").ok().filter(|t| !t.is_empty())
+}
+
+/// A request with GitHub's media type, a user agent and the token if there is one
+pub fn request(agent: &Agent, method: Method, url: &str) -> RequestBuilder {
+    let request = agent.client().request(method, url)
+        .header("Accept", "application/vnd.github+json")
+        .header("User-Agent", concat!("rustaigent/", env!("CARGO_PKG_VERSION")));
+    match token() {
+        Some(token) => request.bearer_auth(token),
+        None => request,
+    }
+}
+
+/// Send and read the JSON answer; error statuses carry GitHub's message
+pub async fn send(request: RequestBuilder) -> Result<Value> {
+    let response = request.send().await.context("Could not reach the GitHub API")?;
+    let status = response.status();
+    let body: Value = response.json().await.unwrap_or_default();
+    if !status.is_success() {
+        let mut message = http::error_message(&body).unwrap_or_else(|| "no error details".into());
+        // Validation failures explain themselves in `errors`
+        if let Some(errors) = body["errors"].as_array() {
+            let details: Vec<String> = errors.iter().filter_map(|e| e["message"].as_str().map(String::from)).collect();
+            if !details.is_empty() {
+                message = format!("{} ({})", message, details.join("; "));
+            }
+        }
+        bail!("GitHub API returned {}: {}", status, message);
+    }
+    Ok(body)
+}
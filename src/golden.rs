@@ -0,0 +1,220 @@
+//! Golden-file harness for provider wire formats. Each provider has a directory under
+//! `tests/golden/` holding the request the agent must send for a fixed conversation, a
+//! recorded response, and the reply parsed from it, so serialization can be checked
+//! without calling real APIs.
+//!
+//! After an intended format change, rewrite the fixtures with
+//! `UPDATE_GOLDEN=1 cargo test golden` and review the diff. A new provider needs a case
+//! in the tests below and a hand-written `response.json` taken from its documentation.
+
+use std::{env, fs, path::PathBuf, sync::Arc};
+use serde_json::{json, Value};
+use crate::{api_version::ApiVersions, reasoning::Reasoning, turns::Turn, Agent, ChatMessage, FunctionCall, FunctionDefinition};
+
+pub fn path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden").join(name)
+}
+
+/// Load a JSON fixture
+pub fn fixture(name: &str) -> Value {
+    let path = path(name);
+    let text = fs::read_to_string(&path).unwrap_or_else(|err| panic!("missing fixture {}: {}", path.display(), err));
+    serde_json::from_str(&text).unwrap_or_else(|err| panic!("fixture {} is not JSON: {}", path.display(), err))
+}
+
+/// Compare against a golden file, or (re)write it when `UPDATE_GOLDEN` is set
+pub fn assert_golden(name: &str, actual: &Value) {
+    let path = path(name);
+    let rendered = serde_json::to_string_pretty(actual).unwrap() + "\n";
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, rendered).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&path)
+        .unwrap_or_else(|_| panic!("missing golden file {}; run with UPDATE_GOLDEN=1 to create it", path.display()));
+    assert!(expected == rendered, "{} differs from the golden file; run with UPDATE_GOLDEN=1 if the change is intended\n--- expected\n{}\n--- actual\n{}", name, expected, rendered);
+}
+
+fn message(role: &str, content: &str) -> ChatMessage {
+    ChatMessage { role: role.into(), content: content.into(), name: None, reasoning: None, function_call: None, files: Vec::new(), response_id: None }
+}
+
+/// An agent with a fixed conversation and tool list, independent of the environment and config files
+pub fn agent(provider: &str) -> Agent {
+    let mut agent = Agent::new("test-key".into(), provider.into());
+    agent.backend = crate::providers::for_name(provider);
+    agent.model = None;
+    agent.base_url = None;
+    agent.config.allowed_models = None;
+    agent.config.turns = None;
+    agent.privacy = None;
+    agent.streaming = false;
+    agent.api_versions = ApiVersions::default();
+    agent.max_tokens = 256;
+    agent.temperature = 0.2;
+    agent.reasoning = Reasoning::default();
+    agent.conversation = vec![
+        message("system", "You are a test assistant."),
+        message("user", "List the files in src."),
+        message("assistant", "main.rs and lib.rs."),
+        message("user", "Which one is the entry point?"),
+    ].into();
+    agent.functions = Arc::from([FunctionDefinition {
+        name: "list_dir".into(),
+        description: "List files in a directory".into(),
+        parameters: json!({"type":"object","properties":{"path":{"type":"string"}},"required":["path"]}),
+    }]);
+    agent
+}
+
+/// The fixed agent after one tool round: the model called `list_dir` and got its output back
+pub fn agent_after_tool(provider: &str) -> Agent {
+    let mut agent = agent(provider);
+    agent.conversation.push(ChatMessage {
+        function_call: Some(FunctionCall { name: "list_dir".into(), arguments: r#"{"path":"src"}"#.into(), call_id: None }),
+        ..message("assistant", "")
+    });
+    agent.conversation.push(ChatMessage { name: Some("list_dir".into()), ..message("function", "main.rs") });
+    agent
+}
+
+/// Check the request for the fixed conversation and the reply parsed from the recorded response
+pub fn check_provider(dir: &str, agent: &Agent) {
+    let model = agent.model_name();
+    let (url, body) = agent.wire_request(&model, Turn::Tools, None).unwrap();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert_golden(&format!("{}/request.json", dir), &json!({ "url": url, "body": body }));
+
+    let reply = agent.parse_reply(&model, fixture(&format!("{}/response.json", dir))).unwrap();
+    assert_golden(&format!("{}/reply.json", dir), &serde_json::to_value(&reply).unwrap());
+}
+
+#[test]
+fn openai() {
+    check_provider("openai", &agent("openai"));
+}
+
+#[test]
+fn openai_reasoning() {
+    let mut agent = agent("openai");
+    agent.model = Some("o3-mini".into());
+    agent.reasoning.effort = Some("high".into());
+    check_provider("openai_reasoning", &agent);
+}
+
+#[test]
+fn openai_responses() {
+    let mut agent = agent("openai");
+    agent.backend = crate::providers::for_settings("openai", Some("responses"));
+    agent.config.hosted_tools = Some(vec!["code_interpreter".into(), "file_search".into()]);
+    agent.config.vector_stores = Some(vec!["vs_docs".into()]);
+    check_provider("openai_responses", &agent);
+}
+
+#[test]
+fn claude() {
+    let mut agent = agent("claude");
+    agent.reasoning.budget = Some(1024);
+    check_provider("claude", &agent);
+}
+
+#[test]
+fn ollama() {
+    check_provider("ollama", &agent("ollama"));
+}
+
+#[test]
+fn google() {
+    check_provider("google", &agent("google"));
+}
+
+#[test]
+fn vertex() {
+    let mut agent = agent("vertex");
+    agent.backend = crate::providers::vertex(Some("demo-project".into()), Some("europe-west4".into()));
+    check_provider("vertex", &agent);
+}
+
+#[test]
+fn huggingface() {
+    check_provider("huggingface", &agent("huggingface"));
+}
+
+/// A reasoning model: `max_completion_tokens`, no temperature, reasoning tokens billed apart
+#[test]
+fn xai() {
+    let mut agent = agent("xai");
+    agent.reasoning.effort = Some("low".into());
+    check_provider("xai", &agent);
+}
+
+#[test]
+fn claude_tool_result() {
+    let mut agent = agent_after_tool("claude");
+    agent.reasoning.budget = Some(1024);
+    check_provider("claude_tool_result", &agent);
+}
+
+#[test]
+fn google_tool_result() {
+    check_provider("google_tool_result", &agent_after_tool("google"));
+}
+
+#[test]
+fn ollama_tool_result() {
+    check_provider("ollama_tool_result", &agent_after_tool("ollama"));
+}
+
+/// A dedicated TGI endpoint, which returns call arguments as an object
+#[test]
+fn huggingface_tool_result() {
+    let mut agent = agent_after_tool("huggingface");
+    agent.backend = crate::providers::huggingface(Some("https://demo.us-east-1.aws.endpoints.huggingface.cloud/".into()));
+    check_provider("huggingface_tool_result", &agent);
+}
+
+/// The reasoning behind the call goes back with it, as the turn is still running
+#[test]
+fn deepseek_tool_result() {
+    let mut agent = agent_after_tool("deepseek");
+    agent.model = Some("deepseek-reasoner".into());
+    let call = agent.conversation.len() - 2;
+    let message = &mut agent.conversation.make_mut()[call];
+    message.reasoning = Some("The user wants the entry point; listing src shows the candidates.".into());
+    message.function_call.as_mut().unwrap().call_id = Some("call_00_kQ7bX2".into());
+    check_provider("deepseek_tool_result", &agent);
+}
+
+/// Only the function output goes out, after the stored response that asked for it
+#[test]
+fn openai_responses_tool_result() {
+    let mut agent = agent_after_tool("openai");
+    agent.backend = crate::providers::for_settings("openai", Some("responses"));
+    let call = agent.conversation.len() - 2;
+    let message = &mut agent.conversation.make_mut()[call];
+    message.response_id = Some("resp_67ccd2bed1ec8190b14f964abc054267".into());
+    message.function_call.as_mut().unwrap().call_id = Some("call_Xk3n0dlhDFkl".into());
+    check_provider("openai_responses_tool_result", &agent);
+}
+
+#[test]
+fn provider_error() {
+    let agent = agent("openai");
+    let Err(err) = agent.parse_reply("gpt-4o-mini", json!({"error": {"message": "Invalid API key"}})) else {
+        panic!("an error response must not parse as a reply");
+    };
+    assert!(err.to_string().contains("Invalid API key"));
+}
+
+#[test]
+fn handshake() {
+    let mut agent = agent("claude");
+    agent.model = Some("claude-opus-4-1".into());
+    let handshake = agent.handshake();
+    assert_eq!(handshake["model"], "claude-opus-4-1");
+    assert_eq!(handshake["model_capabilities"]["max_context"], 200_000);
+    assert!(handshake["providers"].as_array().unwrap().iter().any(|p| p["name"] == "ollama" && p["configured"] == true));
+    let tools = handshake["tools"].as_array().unwrap();
+    assert!(tools.iter().any(|t| t["name"] == "list_dir" && t["parameters"]["type"] == "object"));
+}
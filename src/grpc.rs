@@ -0,0 +1,187 @@
+//! `rustaigent grpc`: the library API as a gRPC service for backends in other languages. The
+//! contract is `proto/rustaigent.proto`; build with `--features grpc`.
+
+use std::{collections::HashMap, pin::Pin, sync::{atomic::{AtomicU64, Ordering}, Arc}};
+use anyhow::{Result, Context};
+use tokio::sync::{mpsc, Mutex};
+use tokio_stream::{wrappers::ReceiverStream, Stream};
+use tonic::{Request, Response, Status};
+use tracing::debug;
+use crate::{events::{AgentEvent, Pending}, handle::AgentHandle, AgentBuilder};
+
+/// Code generated from `proto/rustaigent.proto`
+pub mod proto {
+    tonic::include_proto!("rustaigent.v1");
+}
+
+use proto::{agent_server::{Agent, AgentServer}, event::Kind, reply_request::Reply};
+
+const DEFAULT_BIND: &str = "127.0.0.1:50051";
+
+/// Bearer token clients must send when set
+const TOKEN_VAR: &str = "RUSTAIGENT_GRPC_TOKEN";
+
+/// Events buffered for a `StreamEvents` call before the relay waits for the client
+const EVENT_BUFFER: usize = 16;
+
+struct Session {
+    handle: AgentHandle,
+    /// Prompts announced on the event stream, by id
+    pending: Arc<std::sync::Mutex<HashMap<u64, Pending>>>,
+}
+
+#[derive(Default)]
+struct Service {
+    sessions: Mutex<HashMap<String, Session>>,
+    next_prompt: Arc<AtomicU64>,
+}
+
+/// Listen on `bind` until interrupted
+pub async fn run(bind: Option<String>) -> Result<()> {
+    let bind = bind.unwrap_or_else(|| DEFAULT_BIND.into());
+    let addr = bind.parse().with_context(|| format!("Invalid address `{}`", bind))?;
+    let token = std::env::var(TOKEN_VAR).ok().filter(|t| !t.is_empty());
+    if token.is_none() && !bind.starts_with("127.") && !bind.starts_with("[::1]") {
+        tracing::warn!("serving gRPC on {} without {}; anyone who reaches it can run tools", bind, TOKEN_VAR);
+    }
+    let check = move |req: Request<()>| -> Result<Request<()>, Status> {
+        let Some(token) = &token else { return Ok(req) };
+        let sent = req.metadata().get("authorization").and_then(|v| v.to_str().ok()).and_then(|v| v.strip_prefix("Bearer "));
+        match sent {
+            Some(sent) if sent.trim() == token => Ok(req),
+            _ => Err(Status::unauthenticated(format!("send `authorization: Bearer <{}>`", TOKEN_VAR))),
+        }
+    };
+    eprintln!("Serving gRPC on {}", addr);
+    tonic::transport::Server::builder()
+        .add_service(AgentServer::with_interceptor(Service::default(), check))
+        .serve(addr)
+        .await
+        .context("gRPC server failed")
+}
+
+fn session_id() -> Result<String, Status> {
+    let mut bytes = [0u8; 12];
+    getrandom::fill(&mut bytes).map_err(|err| Status::internal(format!("No OS randomness: {}", err)))?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+impl Service {
+    async fn session(&self, id: &str) -> Result<(AgentHandle, Arc<std::sync::Mutex<HashMap<u64, Pending>>>), Status> {
+        let sessions = self.sessions.lock().await;
+        let session = sessions.get(id).ok_or_else(|| Status::not_found(format!("no session `{}`", id)))?;
+        Ok((session.handle.clone(), Arc::clone(&session.pending)))
+    }
+}
+
+/// The event as a message; prompts are kept in `pending` under the id the client replies to
+fn convert(event: AgentEvent, pending: &std::sync::Mutex<HashMap<u64, Pending>>, next: &AtomicU64) -> proto::Event {
+    let kind = match event {
+        AgentEvent::Token(text) => Kind::Token(proto::Token { text }),
+        AgentEvent::ToolStarted { call } => Kind::ToolStarted(proto::ToolStarted { name: call.name().into(), args_json: call.args().to_string() }),
+        AgentEvent::ToolFinished { name, ok, output } => Kind::ToolFinished(proto::ToolFinished { name, ok, output }),
+        AgentEvent::ApprovalNeeded { question, respond } => {
+            let prompt_id = next.fetch_add(1, Ordering::Relaxed) + 1;
+            pending.lock().unwrap().insert(prompt_id, Pending::Approval(respond));
+            Kind::ApprovalNeeded(proto::ApprovalNeeded { prompt_id, question })
+        }
+        AgentEvent::QuestionAsked { question, respond } => {
+            let prompt_id = next.fetch_add(1, Ordering::Relaxed) + 1;
+            pending.lock().unwrap().insert(prompt_id, Pending::Answer(respond));
+            Kind::QuestionAsked(proto::QuestionAsked { prompt_id, question })
+        }
+        AgentEvent::Done { content } => Kind::Done(proto::Done { content }),
+    };
+    proto::Event { kind: Some(kind) }
+}
+
+#[tonic::async_trait]
+impl Agent for Service {
+    async fn create_session(&self, request: Request<proto::CreateSessionRequest>) -> Result<Response<proto::Session>, Status> {
+        let request = request.into_inner();
+        let mut builder = AgentBuilder::new();
+        if !request.provider.is_empty() {
+            builder = builder.provider(request.provider);
+        }
+        if !request.model.is_empty() {
+            builder = builder.model(request.model);
+        }
+        if !request.system_prompt.is_empty() {
+            builder = builder.system_prompt(request.system_prompt);
+        }
+        let mut agent = builder.build().map_err(|err| Status::failed_precondition(format!("{:#}", err)))?;
+        // Nobody is at the terminal; prompts wait for a subscriber's reply or are declined
+        agent.enable_unattended();
+        agent.enable_quiet();
+        let id = session_id()?;
+        let session = Session { handle: AgentHandle::new(agent), pending: Arc::default() };
+        self.sessions.lock().await.insert(id.clone(), session);
+        Ok(Response::new(proto::Session { id }))
+    }
+
+    async fn close_session(&self, request: Request<proto::SessionRef>) -> Result<Response<proto::CloseSessionResponse>, Status> {
+        let id = request.into_inner().session_id;
+        match self.sessions.lock().await.remove(&id) {
+            Some(_) => Ok(Response::new(proto::CloseSessionResponse {})),
+            None => Err(Status::not_found(format!("no session `{}`", id))),
+        }
+    }
+
+    async fn send_message(&self, request: Request<proto::SendMessageRequest>) -> Result<Response<proto::SendMessageResponse>, Status> {
+        let request = request.into_inner();
+        let (handle, _) = self.session(&request.session_id).await?;
+        match handle.send(&request.content).await {
+            Ok(Some(answer)) => Ok(Response::new(proto::SendMessageResponse { answer, held_back: false })),
+            Ok(None) => Ok(Response::new(proto::SendMessageResponse { answer: String::new(), held_back: true })),
+            Err(err) => Err(Status::unavailable(format!("{:#}", err))),
+        }
+    }
+
+    type StreamEventsStream = Pin<Box<dyn Stream<Item = Result<proto::Event, Status>> + Send>>;
+
+    async fn stream_events(&self, request: Request<proto::SessionRef>) -> Result<Response<Self::StreamEventsStream>, Status> {
+        let (handle, pending) = self.session(&request.into_inner().session_id).await?;
+        let mut events = handle.events().await;
+        // A slow client holds the relay up, so answer text waits merged in the agent's event queue
+        let (tx, rx) = mpsc::channel(EVENT_BUFFER);
+        let next = Arc::clone(&self.next_prompt);
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    event = events.recv() => {
+                        let Some(event) = event else { break };
+                        if tx.send(Ok(convert(event, &pending, &next))).await.is_err() {
+                            break;
+                        }
+                    }
+                    _ = tx.closed() => break,
+                }
+            }
+            // Unanswered prompts of a stream that ended are declined
+            pending.lock().unwrap().clear();
+            debug!("gRPC event stream ended");
+        });
+        Ok(Response::new(Box::pin(ReceiverStream::new(rx))))
+    }
+
+    async fn reply(&self, request: Request<proto::ReplyRequest>) -> Result<Response<proto::ReplyResponse>, Status> {
+        let request = request.into_inner();
+        let (_, pending) = self.session(&request.session_id).await?;
+        let mut pending = pending.lock().unwrap();
+        let prompt = pending.remove(&request.prompt_id).ok_or_else(|| Status::not_found(format!("nothing is waiting for prompt {}", request.prompt_id)))?;
+        match (prompt, request.reply) {
+            (Pending::Approval(respond), Some(Reply::Approve(true))) => respond.approve(),
+            (Pending::Approval(respond), Some(Reply::Approve(false))) => respond.deny(),
+            (Pending::Answer(respond), Some(Reply::Answer(text))) => respond.answer(text),
+            (prompt, _) => {
+                let expected = match prompt {
+                    Pending::Approval(_) => "approve",
+                    Pending::Answer(_) => "answer",
+                };
+                pending.insert(request.prompt_id, prompt);
+                return Err(Status::invalid_argument(format!("prompt {} expects `{}`", request.prompt_id, expected)));
+            }
+        }
+        Ok(Response::new(proto::ReplyResponse {}))
+    }
+}
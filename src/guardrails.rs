@@ -0,0 +1,88 @@
+use regex::Regex;
+use serde::{Serialize, Deserialize};
+use anyhow::{Result, Context};
+
+/// `[guardrails]` table: outbound checks run before anything is sent to a cloud provider
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct GuardrailConfig {
+    /// `block` asks before sending a flagged prompt; `flag` only warns
+    pub mode: Option<String>,
+    /// Built-in detectors for keys, tokens and private keys; on by default
+    pub secrets: Option<bool>,
+    /// Domains whose hosts must not leave the network, e.g. `corp.example.com`
+    pub internal_domains: Option<Vec<String>>,
+    /// Case-insensitive phrases such as `CONFIDENTIAL`
+    pub markers: Option<Vec<String>>,
+    /// Extra regexes to flag
+    pub patterns: Option<Vec<String>>,
+}
+
+/// Keys accepted in `[guardrails]`, for config validation
+pub const KEYS: &[&str] = &["mode", "secrets", "internal_domains", "markers", "patterns"];
+pub const MODES: &[&str] = &["block", "flag"];
+
+pub const SECRET_PATTERNS: &[(&str, &str)] = &[
+    ("AWS access key", r"\b(AKIA|ASIA)[0-9A-Z]{16}\b"),
+    ("private key", r"-----BEGIN [A-Z ]*PRIVATE KEY-----"),
+    ("GitHub token", r"\bgh[pousr]_[A-Za-z0-9]{36,}\b"),
+    ("API secret key", r"\bsk-[A-Za-z0-9_-]{20,}"),
+    ("Slack token", r"\bxox[abprs]-[A-Za-z0-9-]{10,}"),
+    ("credential assignment", r#"(?i)\b(api[_-]?key|secret|passw(or)?d|token)\s*[:=]\s*["']?[^\s"']{8,}"#),
+];
+
+/// Private top-level domains that never resolve publicly
+const INTERNAL_TLDS: &[&str] = &["internal", "corp", "intranet"];
+
+pub struct Finding {
+    pub rule: String,
+    /// Truncated match, safe to print
+    pub excerpt: String,
+}
+
+pub struct Guardrails {
+    pub block: bool,
+    rules: Vec<(String, Regex)>,
+}
+
+fn redact(matched: &str) -> String {
+    let head: String = matched.chars().take(6).collect();
+    if matched.chars().count() > 6 { format!("{}…", head) } else { head }
+}
+
+impl Guardrails {
+    /// Compile the configured rules; `None` when guardrails are not configured
+    pub fn from_config(cfg: Option<&GuardrailConfig>) -> Result<Option<Self>> {
+        let Some(cfg) = cfg else {
+            return Ok(None);
+        };
+        let mut rules = Vec::new();
+        if cfg.secrets.unwrap_or(true) {
+            for (name, pattern) in SECRET_PATTERNS {
+                rules.push((name.to_string(), Regex::new(pattern)?));
+            }
+        }
+        let host = r"\b(?:[A-Za-z0-9-]+\.)+";
+        for tld in INTERNAL_TLDS {
+            rules.push(("internal hostname".into(), Regex::new(&format!(r"(?i){}{}(?:$|[^\w.-])", host, tld))?));
+        }
+        for domain in cfg.internal_domains.iter().flatten() {
+            let re = format!(r"(?i)\b(?:[A-Za-z0-9-]+\.)*{}\b", regex::escape(domain.trim_start_matches('.')));
+            rules.push((format!("internal host ({})", domain), Regex::new(&re)?));
+        }
+        for marker in cfg.markers.iter().flatten() {
+            rules.push((format!("marker \"{}\"", marker), Regex::new(&format!("(?i){}", regex::escape(marker)))?));
+        }
+        for pattern in cfg.patterns.iter().flatten() {
+            let re = Regex::new(pattern).with_context(|| format!("Invalid guardrail pattern `{}`", pattern))?;
+            rules.push((format!("pattern /{}/", pattern), re));
+        }
+        Ok(Some(Guardrails { block: cfg.mode.as_deref() != Some("flag"), rules }))
+    }
+
+    /// Every rule that matches `text`
+    pub fn scan(&self, text: &str) -> Vec<Finding> {
+        self.rules.iter()
+            .filter_map(|(rule, re)| re.find(text).map(|m| Finding { rule: rule.clone(), excerpt: redact(m.as_str()) }))
+            .collect()
+    }
+}
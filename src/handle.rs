@@ -0,0 +1,61 @@
+use std::sync::Arc;
+use anyhow::Result;
+use tokio::sync::Mutex;
+use crate::{cancel::Canceller, events, history::History, Agent};
+
+/// Cloneable, thread-safe handle to one agent session. Turns on the same handle are serialized;
+/// separate handles are independent sessions and run concurrently.
+#[derive(Clone)]
+pub struct AgentHandle {
+    inner: Arc<Mutex<Agent>>,
+    /// Reaches the running turn without waiting for the lock it holds
+    cancel: Canceller,
+}
+
+impl AgentHandle {
+    pub fn new(agent: Agent) -> Self {
+        let cancel = agent.canceller();
+        AgentHandle { inner: Arc::new(Mutex::new(agent)), cancel }
+    }
+
+    /// Run one turn and return the answer; `None` when the guardrails held the prompt back
+    pub async fn send(&self, input: &str) -> Result<Option<String>> {
+        Ok(self.inner.lock().await.turn(input).await?.map(|reply| reply.content))
+    }
+
+    /// Snapshot of the conversation so far; cheap, later turns copy on write
+    pub async fn history(&self) -> History {
+        self.inner.lock().await.conversation.clone()
+    }
+
+    /// Cancel the running turn, whose `send` then fails with `cancel::Cancelled`
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    /// USD each following turn may spend before it is cancelled
+    pub async fn set_turn_budget(&self, usd: Option<f64>) {
+        self.inner.lock().await.set_turn_budget(usd);
+    }
+
+    /// Subscribe to this session's events; approvals are routed to the subscriber
+    pub async fn events(&self) -> events::Receiver {
+        self.inner.lock().await.events()
+    }
+}
+
+/// Runtime for hosts that call in from synchronous code (Python, C); turns of every agent in the
+/// process run on it
+#[cfg(any(feature = "python", feature = "ffi"))]
+pub(crate) fn runtime() -> &'static tokio::runtime::Runtime {
+    static RUNTIME: std::sync::OnceLock<tokio::runtime::Runtime> = std::sync::OnceLock::new();
+    RUNTIME.get_or_init(|| tokio::runtime::Builder::new_multi_thread().enable_all().build().expect("failed to start the tokio runtime"))
+}
+
+// Servers hold handles across threads and spawn turns as tasks
+fn _assert_thread_safe(handle: &AgentHandle) {
+    fn send_sync<T: Send + Sync>(_: &T) {}
+    fn send<T: Send>(_: T) {}
+    send_sync(handle);
+    send(handle.send(""));
+}
@@ -0,0 +1,135 @@
+use std::{fs::{self, File}, io::{Read, Seek, SeekFrom, Write}, ops::Deref, path::{Path, PathBuf}, sync::{atomic::{AtomicUsize, Ordering}, Arc, Mutex}};
+use anyhow::{Result, Context};
+use crate::ChatMessage;
+
+/// Append-only file holding messages paged out of memory, as JSON records sealed one by one
+/// when `encryption` is on; deleted with the session
+struct Spill {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl Spill {
+    fn create(dir: &Path) -> Result<Self> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!("{}-{}.jsonl", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed)));
+        let file = File::options().create(true).truncate(true).read(true).write(true).open(&path)
+            .with_context(|| format!("Failed to create {}", path.display()))?;
+        Ok(Spill { path, file: Mutex::new(file) })
+    }
+
+    /// Append one message, returning where it landed as `(offset, len)`
+    fn write(&self, message: &ChatMessage) -> Result<(u64, u64)> {
+        let mut line = crate::vault::seal(serde_json::to_vec(message)?)?;
+        line.push(b'\n');
+        let mut file = self.file.lock().unwrap();
+        let offset = file.seek(SeekFrom::End(0))?;
+        file.write_all(&line)?;
+        Ok((offset, line.len() as u64))
+    }
+
+    fn read(&self, (offset, len): (u64, u64)) -> Result<ChatMessage> {
+        let mut buf = vec![0; len as usize];
+        let mut file = self.file.lock().unwrap();
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut buf)?;
+        drop(file);
+        buf.pop();
+        Ok(serde_json::from_slice(&crate::vault::open(&self.path, buf)?)?)
+    }
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        fs::remove_file(&self.path).ok();
+    }
+}
+
+/// Conversation transcript shared copy-on-write: cloning it (for exports, snapshots or
+/// batch requests) only bumps a reference count, and the messages are copied once, on
+/// the first change made while another clone is still alive.
+///
+/// Dereferences to the messages held in memory, which are what the model sees: the system
+/// prompt plus recent turns. Older turns paged out with [`History::page_out`] stay on disk
+/// until [`History::load_all`] reads them back.
+#[derive(Clone, Default)]
+pub struct History {
+    messages: Arc<Vec<ChatMessage>>,
+    spill: Option<Arc<Spill>>,
+    /// Locations of paged-out messages in the spill file, oldest first
+    archived: Arc<Vec<(u64, u64)>>,
+}
+
+impl History {
+    pub fn push(&mut self, message: ChatMessage) {
+        Arc::make_mut(&mut self.messages).push(message);
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        if len < self.messages.len() {
+            Arc::make_mut(&mut self.messages).truncate(len);
+        }
+    }
+
+    /// Mutable access to the in-memory messages, copying them first if they are shared
+    pub fn make_mut(&mut self) -> &mut Vec<ChatMessage> {
+        Arc::make_mut(&mut self.messages)
+    }
+
+    /// Messages in the whole session, including those paged out
+    pub fn total_len(&self) -> usize {
+        self.archived.len() + self.messages.len()
+    }
+
+    /// Move turns older than the last `keep` messages to a spill file under `dir`, keeping the
+    /// system prompt and starting the kept window at a user message. Returns how many moved.
+    pub fn page_out(&mut self, keep: usize, dir: &Path) -> Result<usize> {
+        let len = self.messages.len();
+        if len <= keep + 1 {
+            return Ok(0);
+        }
+        let Some(split) = (len - keep..len).find(|&i| self.messages[i].role == "user") else {
+            return Ok(0);
+        };
+        let spill = match &self.spill {
+            Some(spill) => Arc::clone(spill),
+            None => Arc::clone(self.spill.insert(Arc::new(Spill::create(dir)?))),
+        };
+        let mut locations = Vec::with_capacity(split - 1);
+        for message in &self.messages[1..split] {
+            locations.push(spill.write(message)?);
+        }
+        Arc::make_mut(&mut self.archived).extend(locations);
+        Arc::make_mut(&mut self.messages).drain(1..split);
+        tracing::debug!("paged {} messages out to {}", split - 1, spill.path.display());
+        Ok(split - 1)
+    }
+
+    /// The full transcript in order, reading paged-out messages back from disk
+    pub fn load_all(&self) -> Result<Vec<ChatMessage>> {
+        let mut all = Vec::with_capacity(self.total_len());
+        all.extend(self.messages.first().cloned());
+        if let Some(spill) = &self.spill {
+            for location in self.archived.iter() {
+                all.push(spill.read(*location).context("Failed to read paged-out conversation")?);
+            }
+        }
+        all.extend(self.messages.iter().skip(1).cloned());
+        Ok(all)
+    }
+}
+
+impl Deref for History {
+    type Target = [ChatMessage];
+
+    fn deref(&self) -> &[ChatMessage] {
+        &self.messages
+    }
+}
+
+impl From<Vec<ChatMessage>> for History {
+    fn from(messages: Vec<ChatMessage>) -> Self {
+        History { messages: Arc::new(messages), ..History::default() }
+    }
+}
@@ -0,0 +1,124 @@
+//! `/howdoi <thing>`: answers about the agent itself from a copy of its README built into the
+//! binary. The sections are ranked by the question's words, weighted by how rare they are and
+//! whether the heading has them, so it works offline and costs no request.
+
+use std::collections::HashMap;
+use crate::repl;
+
+const DOCS: &str = include_str!("../README.md");
+
+/// Lines of a section shown before it is cut
+const SHOWN_LINES: usize = 30;
+/// Other sections named as further reading
+const SEE_ALSO: usize = 3;
+
+/// Words too common to tell sections apart
+const STOPWORDS: &[&str] = &[
+    "a", "an", "and", "are", "as", "at", "be", "by", "can", "do", "does", "for", "from", "get", "how", "i", "in", "is",
+    "it", "make", "me", "my", "of", "on", "or", "set", "so", "the", "to", "use", "what", "when", "where", "which", "with",
+    "without", "you", "rustaigent",
+];
+
+struct Section {
+    heading: String,
+    body: String,
+}
+
+/// Lowercase words, with config keys such as `turn_budget_usd` kept whole and split, and a
+/// plural `s` dropped so "tags" finds "tag"
+fn words(text: &str) -> Vec<String> {
+    let mut out = Vec::new();
+    for word in text.split(|c: char| !(c.is_alphanumeric() || c == '_')).filter(|w| !w.is_empty()) {
+        let word = word.to_lowercase();
+        if word.contains('_') {
+            out.extend(word.split('_').filter(|p| !p.is_empty()).map(String::from));
+        }
+        let word = match word.strip_suffix('s') {
+            Some(stem) if stem.len() > 2 && !stem.ends_with('s') => stem.to_string(),
+            _ => word,
+        };
+        if !STOPWORDS.contains(&word.as_str()) {
+            out.push(word);
+        }
+    }
+    out
+}
+
+/// The README cut at its headings, code blocks kept with their section, and the REPL commands
+fn sections() -> Vec<Section> {
+    let mut out = Vec::new();
+    let (mut heading, mut body, mut fenced) = (String::from("RustAIgent"), String::new(), false);
+    for line in DOCS.lines() {
+        if line.trim_start().starts_with("```") {
+            fenced = !fenced;
+        }
+        if !fenced && line.starts_with('#') {
+            if !body.trim().is_empty() {
+                out.push(Section { heading: heading.clone(), body: body.trim().to_string() });
+            }
+            heading = line.trim_start_matches('#').trim().to_string();
+            body.clear();
+        } else {
+            body.push_str(line);
+            body.push('\n');
+        }
+    }
+    if !body.trim().is_empty() {
+        out.push(Section { heading, body: body.trim().to_string() });
+    }
+    let commands: Vec<String> = repl::COMMANDS.iter().map(|(name, args, about)| format!("{} {} — {}", name, args, about)).collect();
+    out.push(Section { heading: "REPL commands".into(), body: commands.join("\n") });
+    out
+}
+
+/// The sections that best answer `question`, best first, with their scores: BM25 over the
+/// bodies, so long sections do not win on length, with bonuses for words in the heading and
+/// for commands and keys quoted in the body
+fn rank(question: &str, sections: &[Section]) -> Vec<(f64, usize)> {
+    let (k, b) = (1.2, 0.75);
+    let asked = words(question);
+    // Slash commands and config keys asked about by name count most where they are quoted
+    let literals: Vec<String> = question.split_whitespace().filter(|w| w.starts_with('/') || w.contains('_')).map(|w| format!("`{}", w)).collect();
+    let indexed: Vec<(Vec<String>, HashMap<String, usize>, usize)> = sections.iter().map(|s| {
+        let body = words(&s.body);
+        let mut counts = HashMap::new();
+        for word in &body {
+            *counts.entry(word.clone()).or_insert(0) += 1;
+        }
+        (words(&s.heading), counts, body.len())
+    }).collect();
+    let average = indexed.iter().map(|(_, _, len)| *len as f64).sum::<f64>() / indexed.len().max(1) as f64;
+    let mut ranked: Vec<(f64, usize)> = indexed.iter().enumerate().map(|(i, (heading, counts, len))| {
+        let score: f64 = asked.iter().map(|word| {
+            let holding = indexed.iter().filter(|(h, c, _)| h.contains(word) || c.contains_key(word)).count() as f64;
+            let rarity = ((sections.len() as f64 - holding + 0.5) / (holding + 0.5) + 1.0).ln();
+            let tf = counts.get(word).map_or(0.0, |n| *n as f64);
+            let weight = tf * (k + 1.0) / (tf + k * (1.0 - b + b * *len as f64 / average));
+            let titled = if heading.contains(word) { 1.5 } else { 0.0 };
+            rarity * (weight + titled)
+        }).sum::<f64>() + literals.iter().map(|l| (1.0 + sections[i].body.matches(l.as_str()).count() as f64).ln() * 3.0).sum::<f64>();
+        (score, i)
+    }).filter(|(score, _)| *score > 0.0).collect();
+    ranked.sort_by(|a, b| b.0.total_cmp(&a.0));
+    ranked
+}
+
+/// The section of the documentation answering `question`, and where else to look
+pub fn answer(question: &str) -> String {
+    let sections = sections();
+    let ranked = rank(question, &sections);
+    let Some(&(_, best)) = ranked.first() else {
+        return format!("Nothing in the documentation matches `{}`; try other words, or /help for the commands.", question.trim());
+    };
+    let section = &sections[best];
+    let lines: Vec<&str> = section.body.lines().collect();
+    let mut out = format!("## {}\n\n{}", section.heading, lines[..lines.len().min(SHOWN_LINES)].join("\n"));
+    if lines.len() > SHOWN_LINES {
+        out.push_str(&format!("\n… {} more lines in the README section \"{}\"", lines.len() - SHOWN_LINES, section.heading));
+    }
+    let also: Vec<&str> = ranked.iter().skip(1).take(SEE_ALSO).map(|&(_, i)| sections[i].heading.as_str()).collect();
+    if !also.is_empty() {
+        out.push_str(&format!("\n\nSee also: {}", also.join(" · ")));
+    }
+    out
+}
@@ -0,0 +1,235 @@
+use std::time::Duration;
+use serde::{Serialize, Deserialize};
+use reqwest::header::HeaderMap;
+use serde_json::Value;
+
+/// `[http]` table in the config file: connection pool tuning for the shared client
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct HttpConfig {
+    /// Idle connections kept open per host (default: unlimited)
+    pub pool_max_idle_per_host: Option<usize>,
+    /// Seconds an idle pooled connection is kept before closing (default 90)
+    pub pool_idle_timeout_secs: Option<u64>,
+    /// Let HTTP/2 grow its flow-control window with measured bandwidth
+    pub http2_adaptive_window: Option<bool>,
+    /// Seconds between HTTP/2 pings that keep a quiet connection alive
+    pub http2_keep_alive_secs: Option<u64>,
+    /// TCP keepalive probe interval in seconds
+    pub tcp_keepalive_secs: Option<u64>,
+}
+
+/// Keys accepted in `[http]`, for config validation
+pub const KEYS: &[&str] = &["pool_max_idle_per_host", "pool_idle_timeout_secs", "http2_adaptive_window", "http2_keep_alive_secs", "tcp_keepalive_secs"];
+
+/// Build the client shared by every request of a session, so retries and
+/// follow-up turns reuse warm connections instead of repeating TLS handshakes
+#[cfg(not(target_arch = "wasm32"))]
+pub fn client(cfg: Option<&HttpConfig>) -> reqwest::Client {
+    builder(cfg).build().unwrap_or_else(|err| {
+        tracing::warn!("ignoring [http] settings: {}", err);
+        reqwest::Client::new()
+    })
+}
+
+/// A client builder with the `[http]` tuning applied, for clients that need more settings
+#[cfg(not(target_arch = "wasm32"))]
+pub fn builder(cfg: Option<&HttpConfig>) -> reqwest::ClientBuilder {
+    let cfg = cfg.cloned().unwrap_or_default();
+    let mut builder = reqwest::Client::builder()
+        .pool_idle_timeout(Duration::from_secs(cfg.pool_idle_timeout_secs.unwrap_or(90)))
+        .http2_adaptive_window(cfg.http2_adaptive_window.unwrap_or(false));
+    if let Some(max) = cfg.pool_max_idle_per_host {
+        builder = builder.pool_max_idle_per_host(max);
+    }
+    if let Some(secs) = cfg.http2_keep_alive_secs {
+        builder = builder
+            .http2_keep_alive_interval(Duration::from_secs(secs))
+            .http2_keep_alive_while_idle(true);
+    }
+    if let Some(secs) = cfg.tcp_keepalive_secs {
+        builder = builder.tcp_keepalive(Duration::from_secs(secs));
+    }
+    builder
+}
+
+/// Longest server-requested wait sat out before a retry; a later retry time, such as a spent
+/// daily quota, is reported instead
+pub const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+/// The wait a 429 or 503 response asks for: OpenAI's `retry-after-ms`, else `Retry-After` as
+/// seconds or an HTTP date
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok()).map(str::trim);
+    if let Some(ms) = header("retry-after-ms").and_then(|v| v.parse::<f64>().ok()).filter(|ms| ms.is_finite() && *ms >= 0.0) {
+        return Some(Duration::from_secs_f64(ms / 1000.0));
+    }
+    let value = header("retry-after")?;
+    if let Ok(secs) = value.parse() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    Some((at.with_timezone(&chrono::Utc) - chrono::Utc::now()).to_std().unwrap_or_default())
+}
+
+/// The message of a provider error body: `error.message` (OpenAI, Claude, Gemini), a bare
+/// `error` string (Ollama), a top-level `message`, or the body itself when it is not JSON
+pub fn error_message(body: &Value) -> Option<String> {
+    let error = &body["error"];
+    error["message"].as_str()
+        .or_else(|| error.as_str())
+        .or_else(|| body["message"].as_str())
+        .or_else(|| body.as_str())
+        .map(|m| m.trim().to_string())
+        .filter(|m| !m.is_empty())
+        .or_else(|| (!error.is_null()).then(|| error.to_string()))
+}
+
+/// What failed when a request got no response: the stage (DNS lookup, TCP connect, TLS
+/// handshake, timeout), the host with the addresses it resolves to, and the proxy in between.
+/// reqwest's own message names only the URL, so every network problem used to read the same.
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn diagnose(err: &reqwest::Error) -> String {
+    let mut causes = Vec::new();
+    let mut source: Option<&dyn std::error::Error> = Some(err);
+    while let Some(cause) = source {
+        causes.push(cause.to_string().to_lowercase());
+        source = cause.source();
+    }
+    let text = causes.join(": ");
+    let has = |needles: &[&str]| needles.iter().any(|n| text.contains(n));
+    let Some(url) = err.url() else { return "the request failed before it was sent".into() };
+    let dns = has(&["dns error", "failed to lookup address", "name or service not known", "nodename nor servname", "no such host"]);
+    let tls = !dns && has(&["certificate", "ssl", "tls", "handshake"]);
+    let stage = if dns {
+        "DNS lookup of"
+    } else if tls {
+        "TLS handshake with"
+    } else if err.is_timeout() {
+        "timed out waiting for"
+    } else if err.is_connect() || has(&["tcp connect error", "connection refused"]) {
+        "TCP connection to"
+    } else if err.is_body() || err.is_decode() {
+        "reading the response of"
+    } else {
+        "HTTP exchange with"
+    };
+    let proxy = proxy_for(url);
+    // Lookups and connections go to the proxy when there is one; TLS is tunneled to the host
+    let target = match &proxy {
+        Some(proxy) if err.is_connect() && !tls => proxy,
+        _ => url,
+    };
+    let host = target.host_str().unwrap_or_default().trim_start_matches('[').trim_end_matches(']').to_string();
+    let port = target.port_or_known_default().unwrap_or(443);
+    let mut notes = Vec::new();
+    if host.parse::<std::net::IpAddr>().is_err() && !dns {
+        let lookup = tokio::time::timeout(Duration::from_secs(2), tokio::net::lookup_host((host.as_str(), port))).await;
+        notes.push(match lookup {
+            Ok(Ok(addrs)) => {
+                let mut ips: Vec<String> = addrs.map(|a| a.ip().to_string()).collect();
+                ips.dedup();
+                ips.truncate(4);
+                format!("resolves to {}", ips.join(", "))
+            }
+            _ => "no longer resolves".into(),
+        });
+    }
+    notes.push(match &proxy {
+        Some(proxy) if target == proxy => format!("proxy for {}", url.host_str().unwrap_or_default()),
+        Some(proxy) => format!("via proxy {}", proxy.as_str().trim_end_matches('/')),
+        None => "no proxy".into(),
+    });
+    let failed = if stage.starts_with("timed out") { "" } else { " failed" };
+    format!("{} {}:{}{} ({})", stage, host, port, failed, notes.join("; "))
+}
+
+/// The proxy reqwest takes from the environment for `url`, without its credentials
+#[cfg(not(target_arch = "wasm32"))]
+fn proxy_for(url: &reqwest::Url) -> Option<reqwest::Url> {
+    let var = |names: &[&str]| names.iter().find_map(|n| std::env::var(n).ok()).filter(|v| !v.trim().is_empty());
+    let host = url.host_str()?;
+    let bypassed = var(&["NO_PROXY", "no_proxy"]).is_some_and(|list| list.split(',').map(str::trim).any(|entry| {
+        let entry = entry.trim_start_matches('.');
+        entry == "*" || (!entry.is_empty() && (host == entry || host.ends_with(&format!(".{}", entry))))
+    }));
+    if bypassed {
+        return None;
+    }
+    let proxy = match url.scheme() {
+        "https" => var(&["HTTPS_PROXY", "https_proxy"]),
+        _ => var(&["HTTP_PROXY", "http_proxy"]),
+    }.or_else(|| var(&["ALL_PROXY", "all_proxy"]))?;
+    // `host:port` parses as a URL with `host` for its scheme
+    let mut proxy = reqwest::Url::parse(&proxy).ok().filter(|p| p.has_host())
+        .or_else(|| reqwest::Url::parse(&format!("http://{}", proxy)).ok())?;
+    proxy.set_password(None).ok();
+    proxy.set_username("").ok();
+    Some(proxy)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{mock::MockProvider, turns::Turn};
+
+    #[tokio::test]
+    async fn unreachable_servers_name_the_failed_stage() {
+        let mock = MockProvider::start().await;
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let mut agent = mock.agent();
+        agent.base_url = Some(format!("http://localhost:{}", port));
+        agent.retry_count = 1;
+        let Err(err) = agent.send_request(Turn::Tools, None).await else {
+            panic!("a closed port must not answer");
+        };
+        let message = err.to_string();
+        assert!(message.contains(&format!("TCP connection to localhost:{} failed", port)) && message.contains("resolves to"), "{}", message);
+    }
+
+    #[tokio::test]
+    async fn rate_limit_with_one_key_waits_and_retries() {
+        let mock = MockProvider::start().await;
+        mock.rate_limited(1).await;
+        mock.reply("pong").await;
+        let started = std::time::Instant::now();
+        let reply = mock.agent().send_request(Turn::Tools, None).await.unwrap();
+        assert_eq!(reply.content, "pong");
+        assert!(started.elapsed() >= std::time::Duration::from_secs(1), "retry-after must be honored");
+    }
+
+    #[tokio::test]
+    async fn rate_limit_past_the_retries_is_an_error() {
+        let mock = MockProvider::start().await;
+        mock.rate_limited(2).await;
+        let mut agent = mock.agent();
+        agent.retry_count = 2;
+        let Err(err) = agent.send_request(Turn::Tools, None).await else {
+            panic!("a 429 must not parse as a reply");
+        };
+        assert!(err.to_string().contains("Rate limit reached"));
+        assert_eq!(mock.requests().await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_retried_and_reported() {
+        let mock = MockProvider::start().await;
+        mock.unavailable(3).await;
+        let Err(err) = mock.agent().send_request(Turn::Tools, None).await else {
+            panic!("a 503 must not parse as a reply");
+        };
+        let message = err.to_string();
+        assert!(message.contains("503 Service Unavailable") && message.contains("Service Temporarily Unavailable"), "{}", message);
+        assert_eq!(mock.requests().await.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn unauthorized_fails_without_retrying() {
+        let mock = MockProvider::start().await;
+        mock.unauthorized().await;
+        mock.reply("pong").await;
+        let Err(err) = mock.agent_with_keys("k1,k2").send_request(Turn::Tools, None).await else {
+            panic!("a 401 must not be retried");
+        };
+        assert!(err.to_string().contains("Incorrect API key provided"));
+        assert_eq!(mock.requests().await.len(), 1);
+    }
+}
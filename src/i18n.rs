@@ -0,0 +1,124 @@
+/// Messages shown by the CLI itself
+#[derive(Clone, Copy)]
+pub enum Msg {
+    You,
+    ToolOutput,
+    Error,
+    ResponseTruncated,
+    LintFixing,
+    LintNotFormatted,
+    ConfirmSuffix,
+    Generating,
+}
+
+/// Response and interface language, from `RESPONSE_LANGUAGE` or the `language` config key
+#[derive(Clone)]
+pub struct Lang {
+    code: String,
+}
+
+impl Default for Lang {
+    fn default() -> Self {
+        Lang { code: "en".into() }
+    }
+}
+
+impl Lang {
+    pub fn new(code: &str) -> Self {
+        Lang { code: code.trim().to_lowercase() }
+    }
+
+    /// Primary subtag, so `de-AT` uses the German catalog
+    fn base(&self) -> &str {
+        self.code.split(['-', '_']).next().unwrap_or("en")
+    }
+
+    /// The code it was made from, such as `de-AT`
+    pub fn code(&self) -> &str {
+        &self.code
+    }
+
+    /// English name of the language, or the code when it has none here
+    pub fn name(&self) -> &str {
+        match self.base() {
+            "en" => "English",
+            "de" => "German",
+            "fr" => "French",
+            "es" => "Spanish",
+            "tr" => "Turkish",
+            "ja" => "Japanese",
+            "pt" => "Portuguese",
+            "it" => "Italian",
+            "zh" => "Chinese",
+            _ => &self.code,
+        }
+    }
+
+    /// Sentence appended to the system prompt; none for English, the models' default
+    pub fn reply_instruction(&self) -> Option<String> {
+        if self.base() == "en" {
+            return None;
+        }
+        Some(format!("Always reply in {} ({}), including explanations and code comments, unless the user explicitly asks for another language.", self.name(), self.code))
+    }
+
+    /// Localized CLI text, falling back to English for languages without a catalog
+    pub fn msg(&self, msg: Msg) -> &'static str {
+        use Msg::*;
+        match (self.base(), msg) {
+            ("de", You) => "Du: ",
+            ("de", ToolOutput) => "WERKZEUG",
+            ("de", Error) => "Fehler",
+            ("de", ResponseTruncated) => "Antwort wurde bei MAX_TOKENS abgeschnitten",
+            ("de", LintFixing) => "[lint] generierter Rust-Code ist syntaktisch fehlerhaft, das Modell wird um Korrektur gebeten",
+            ("de", LintNotFormatted) => "[lint] ein Rust-Block in dieser Antwort ist nicht rustfmt-konform",
+            ("de", Generating) => "generiere Antwort",
+            ("de", ConfirmSuffix) => "[j/N]",
+            ("fr", You) => "Vous : ",
+            ("fr", ToolOutput) => "OUTIL",
+            ("fr", Error) => "erreur",
+            ("fr", ResponseTruncated) => "réponse tronquée à MAX_TOKENS",
+            ("fr", LintFixing) => "[lint] le code Rust généré ne compile pas syntaxiquement, correction demandée au modèle",
+            ("fr", LintNotFormatted) => "[lint] un bloc Rust de cette réponse n'est pas conforme à rustfmt",
+            ("fr", Generating) => "génération",
+            ("fr", ConfirmSuffix) => "[o/N]",
+            ("es", You) => "Tú: ",
+            ("es", ToolOutput) => "HERRAMIENTA",
+            ("es", Error) => "error",
+            ("es", ResponseTruncated) => "respuesta truncada en MAX_TOKENS",
+            ("es", LintFixing) => "[lint] el código Rust generado no se puede analizar, se pide al modelo que lo corrija",
+            ("es", LintNotFormatted) => "[lint] un bloque Rust de esta respuesta no cumple rustfmt",
+            ("es", Generating) => "generando",
+            ("es", ConfirmSuffix) => "[s/N]",
+            ("tr", You) => "Sen: ",
+            ("tr", ToolOutput) => "ARAÇ",
+            ("tr", Error) => "hata",
+            ("tr", ResponseTruncated) => "yanıt MAX_TOKENS sınırında kesildi",
+            ("tr", LintFixing) => "[lint] üretilen Rust kodu ayrıştırılamadı, modelden düzeltmesi isteniyor",
+            ("tr", LintNotFormatted) => "[lint] bu yanıttaki bir Rust bloğu rustfmt ile uyumlu değil",
+            ("tr", Generating) => "yanıt üretiliyor",
+            ("tr", ConfirmSuffix) => "[e/H]",
+            (_, You) => "You: ",
+            (_, ToolOutput) => "TOOL",
+            (_, Error) => "error",
+            (_, ResponseTruncated) => "response truncated at MAX_TOKENS",
+            (_, LintFixing) => "[lint] generated Rust does not parse, asking the model to fix it",
+            (_, LintNotFormatted) => "[lint] a Rust block in this answer is not rustfmt-clean",
+            (_, ConfirmSuffix) => "[y/N]",
+            (_, Generating) => "generating",
+        }
+    }
+
+    /// Accept English yes plus the local equivalent
+    pub fn is_yes(&self, answer: &str) -> bool {
+        let a = answer.trim().to_lowercase();
+        let local: &[&str] = match self.base() {
+            "de" => &["j", "ja"],
+            "fr" => &["o", "oui"],
+            "es" => &["s", "si", "sí"],
+            "tr" => &["e", "evet"],
+            _ => &[],
+        };
+        a == "y" || a == "yes" || local.contains(&a.as_str())
+    }
+}
@@ -0,0 +1,613 @@
+//! Codebase index: `rustaigent index` splits the repository's text files into overlapping chunks
+//! and stores their embeddings locally; `search_codebase` and the retrieval before each prompt
+//! rank the chunks by similarity to a query.
+
+use std::{collections::BTreeMap, env, fs, path::{Path, PathBuf}, process::Command};
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use anyhow::{Result, Context, bail};
+use tokio::sync::OnceCell;
+use futures::{StreamExt, TryStreamExt};
+use crate::{api_version, bulk, compress, config::Config, credentials, http, paths, privacy, progress, s3};
+
+/// Name of the context message carrying retrieved chunks
+pub const NOTE: &str = "codebase";
+
+/// Texts per embedding request
+const BATCH: usize = 64;
+
+/// Characters of a chunk sent for embedding, well inside the models' input limits
+const MAX_EMBED_CHARS: usize = 8000;
+
+/// Commits whose file versions are kept after switching away from them
+const KEPT_COMMITS: usize = 16;
+
+/// Commits back from HEAD `index --pull` looks for a shared index at
+const PULL_DEPTH: usize = 50;
+
+/// Objects transferred at once when sharing the index
+const TRANSFERS: usize = 16;
+
+/// `[index]` table: how the codebase is chunked, embedded and retrieved
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct IndexConfig {
+    /// Load the built index for `search_codebase` and retrieval (default true); `rustaigent
+    /// index` builds it either way
+    pub enabled: Option<bool>,
+    /// `openai` or `ollama`; `ollama` when it is the session's provider, else `openai`
+    pub provider: Option<String>,
+    /// Embedding model; `text-embedding-3-small` for OpenAI, `nomic-embed-text` for Ollama
+    pub model: Option<String>,
+    /// Scheme and host of the embedding endpoint when it is not the provider's default
+    pub base_url: Option<String>,
+    /// Lines per chunk (default 60); neighbouring chunks overlap by a sixth
+    pub chunk_lines: Option<usize>,
+    /// Globs of files to leave out, on top of what git ignores
+    pub exclude: Option<Vec<String>>,
+    /// Chunks added to each prompt; 0 turns automatic retrieval off (default 4)
+    pub top_k: Option<usize>,
+    /// Least cosine similarity for a chunk to be added to a prompt (default 0.3)
+    pub min_score: Option<f32>,
+    /// `s3://bucket/prefix` or `gs://bucket/prefix` that `index --push` and `--pull` share the
+    /// index through
+    pub share: Option<String>,
+    /// S3-compatible endpoint of `share` such as MinIO (default: AWS, or Google for gs://)
+    pub share_endpoint: Option<String>,
+    /// Region of `share` (default: AWS_REGION, then us-east-1)
+    pub share_region: Option<String>,
+}
+
+/// Keys accepted in `[index]`, for config validation
+pub const KEYS: &[&str] = &["enabled", "provider", "model", "base_url", "chunk_lines", "exclude", "top_k", "min_score", "share", "share_endpoint", "share_region"];
+
+pub const PROVIDERS: &[&str] = &["openai", "ollama"];
+
+#[derive(Serialize, Deserialize)]
+struct Chunk {
+    start: usize,
+    end: usize,
+    text: String,
+    /// Unit length, so a dot product is the cosine similarity
+    vector: Vec<f32>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct IndexedFile {
+    digest: String,
+    chunks: Vec<Chunk>,
+}
+
+/// Everything stored for one repository
+#[derive(Serialize, Deserialize)]
+struct Index {
+    provider: String,
+    model: String,
+    chunk_lines: usize,
+    /// By path relative to the repository root
+    files: BTreeMap<String, IndexedFile>,
+    /// Commits indexed, latest last
+    #[serde(default)]
+    commits: Vec<String>,
+    /// Versions of files no longer checked out, by `shelf_key`, so switching back to a branch
+    /// embeds only what changed since
+    #[serde(default)]
+    shelf: BTreeMap<String, Shelved>,
+}
+
+/// A file version from an earlier checkout, and the commit it was last indexed at
+#[derive(Serialize, Deserialize)]
+struct Shelved {
+    commit: String,
+    file: IndexedFile,
+}
+
+/// Chunks embed their path, so a version is reused only at the same path
+fn shelf_key(rel: &str, digest: &str) -> String {
+    format!("{}:{}", digest, rel)
+}
+
+/// A chunk ranked against a query
+pub struct Hit {
+    /// `path:start-end`, relative to the working directory
+    pub location: String,
+    pub text: String,
+    pub score: f32,
+}
+
+/// The embedding endpoint in use
+#[derive(Clone)]
+struct Embedder {
+    client: reqwest::Client,
+    provider: String,
+    model: String,
+    base_url: String,
+    key: Option<String>,
+}
+
+impl Embedder {
+    fn from_config(config: &Config, client: reqwest::Client) -> Self {
+        let cfg = config.index.clone().unwrap_or_default();
+        let session = env::var("API_PROVIDER").ok().or_else(|| config.provider.clone()).unwrap_or_else(|| "openai".into());
+        let provider = cfg.provider.unwrap_or_else(|| if session == "ollama" { session.clone() } else { "openai".into() });
+        // The session's endpoint is reused when the index embeds with the same provider
+        let session_url = env::var("API_BASE_URL").ok().or_else(|| config.base_url.clone()).filter(|_| provider == session);
+        let (model, default_url) = match provider.as_str() {
+            "ollama" => ("nomic-embed-text", "http://localhost:11434"),
+            _ => ("text-embedding-3-small", "https://api.openai.com"),
+        };
+        let key = (provider == "openai").then(|| env::var("OPENAI_API_KEY").ok().or_else(|| credentials::load("openai"))).flatten();
+        Embedder {
+            client,
+            model: cfg.model.unwrap_or_else(|| model.into()),
+            base_url: cfg.base_url.or(session_url).unwrap_or_else(|| default_url.into()).trim_end_matches('/').to_string(),
+            provider,
+            key,
+        }
+    }
+
+    /// One vector per text, in order
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let (url, body) = match self.provider.as_str() {
+            "ollama" => (format!("{}/api/embed", self.base_url), json!({ "model": self.model, "input": texts })),
+            _ => (format!("{}/{}/embeddings", self.base_url, api_version::ApiVersions::from_env().openai), json!({ "model": self.model, "input": texts })),
+        };
+        let mut req = self.client.post(&url).json(&body);
+        if let Some(key) = &self.key {
+            req = req.bearer_auth(key);
+        } else if self.provider == "openai" {
+            bail!("Embedding with OpenAI needs OPENAI_API_KEY; set `[index] provider = \"ollama\"` to embed locally");
+        }
+        let resp = req.send().await.with_context(|| format!("Embedding request to {} failed", url))?;
+        let status = resp.status();
+        let body: Value = resp.json().await.unwrap_or_default();
+        if !status.is_success() {
+            bail!("{} rejected the embedding request with {}: {}", self.provider, status, http::error_message(&body).unwrap_or_else(|| "no error details".into()));
+        }
+        let vectors: Vec<Vec<f32>> = match self.provider.as_str() {
+            "ollama" => serde_json::from_value(body["embeddings"].clone()),
+            _ => serde_json::from_value(body["data"].as_array().into_iter().flatten().map(|d| d["embedding"].clone()).collect()),
+        }.context("Unexpected embedding response")?;
+        if vectors.len() != texts.len() {
+            bail!("Asked for {} embeddings, got {}", texts.len(), vectors.len());
+        }
+        Ok(vectors.into_iter().map(normalized).collect())
+    }
+}
+
+fn normalized(mut vector: Vec<f32>) -> Vec<f32> {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|x| *x /= norm);
+    }
+    vector
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// The enclosing git repository's root, else the working directory
+fn repo_root() -> Result<PathBuf> {
+    let cwd = crate::paths::current_dir()?;
+    Ok(cwd.ancestors().find(|dir| dir.join(".git").exists()).unwrap_or(&cwd).to_path_buf())
+}
+
+/// The commit checked out at `root`, `None` outside git or before the first commit
+fn head(root: &Path) -> Option<String> {
+    let out = Command::new("git").args(["rev-parse", "-q", "--verify", "HEAD"]).current_dir(root).output().ok()?;
+    out.status.success().then(|| String::from_utf8_lossy(&out.stdout).trim().to_string())
+}
+
+/// Where the index of the repository at `root` is stored
+fn index_file(root: &Path) -> PathBuf {
+    let id = sha256_hex(root.to_string_lossy().as_bytes());
+    paths::data_dir().join("index").join(format!("{}.json.zst", &id[..16]))
+}
+
+/// Files to index, relative to `root`: what git tracks or would track, else everything outside
+/// hidden and build directories
+pub(crate) fn candidates(root: &Path) -> Result<Vec<String>> {
+    if root.join(".git").exists() {
+        let out = Command::new("git").args(["ls-files", "-z", "--cached", "--others", "--exclude-standard"]).current_dir(root).output()
+            .context("Failed to run `git ls-files`")?;
+        if out.status.success() {
+            return Ok(out.stdout.split(|b| *b == 0).filter(|p| !p.is_empty()).map(|p| String::from_utf8_lossy(p).into_owned()).collect());
+        }
+    }
+    let mut files = Vec::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir)?.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let path = entry.path();
+            if name.starts_with('.') || name == "target" || name == "node_modules" {
+                continue;
+            }
+            if path.is_dir() {
+                dirs.push(path);
+            } else if let Ok(rel) = path.strip_prefix(root) {
+                files.push(rel.to_string_lossy().into_owned());
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// The text of `rel` under `root`, or `None` for non-UTF-8 files; binaries, bundles, build
+/// output and oversized files are left to `bulk` to turn away
+fn read_text(root: &Path, rel: &str) -> Option<String> {
+    String::from_utf8(fs::read(root.join(rel)).ok()?).ok()
+}
+
+/// Windows of `size` lines overlapping by a sixth, as 1-based inclusive line ranges
+fn chunks(text: &str, size: usize) -> Vec<(usize, usize, String)> {
+    let lines: Vec<&str> = text.lines().collect();
+    let step = (size - size / 6).max(1);
+    let mut out = Vec::new();
+    let mut start = 0;
+    while start < lines.len() {
+        let end = (start + size).min(lines.len());
+        let body = lines[start..end].join("\n");
+        if !body.trim().is_empty() {
+            out.push((start + 1, end, body));
+        }
+        if end == lines.len() {
+            break;
+        }
+        start += step;
+    }
+    out
+}
+
+/// What is embedded for a chunk: its path, so file names count, and the start of its text
+fn embed_input(path: &str, text: &str) -> String {
+    let input = format!("{}\n{}", path, text);
+    match input.char_indices().nth(MAX_EMBED_CHARS) {
+        Some((cut, _)) => input[..cut].to_string(),
+        None => input,
+    }
+}
+
+fn load(path: &Path) -> Result<Option<Index>> {
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let bytes = compress::read(path)?;
+    Ok(Some(serde_json::from_slice(&bytes).with_context(|| format!("Corrupt index {}; rebuild it with `rustaigent index --rebuild`", path.display()))?))
+}
+
+/// `rustaigent index`: chunk and embed the repository's files, re-embedding only files that
+/// changed since the last run unless `rebuild` is set. Versions replaced by a checkout are
+/// shelved under the commit they came from and taken back when it is checked out again.
+pub async fn build(config: &Config, rebuild: bool) -> Result<()> {
+    let cfg = config.index.clone().unwrap_or_default();
+    let embedder = Embedder::from_config(config, http::client(config.http.as_ref()));
+    let chunk_lines = cfg.chunk_lines.unwrap_or(60).max(1);
+    let root = repo_root()?;
+    let path = index_file(&root);
+    let previous = if rebuild { None } else { load(&path)? }
+        .filter(|i| i.provider == embedder.provider && i.model == embedder.model && i.chunk_lines == chunk_lines);
+    let exclude = cfg.exclude.iter().flatten()
+        .map(|g| glob::Pattern::new(g).with_context(|| format!("Invalid `index.exclude` glob `{}`", g)))
+        .collect::<Result<Vec<_>>>()?;
+    // Content of private paths must not reach a cloud embedding endpoint
+    let privacy = match embedder.provider.as_str() {
+        "ollama" => None,
+        _ => privacy::Router::from_config(config.privacy.as_ref(), "")?,
+    };
+    let commit = head(&root);
+    let (mut previous_files, mut commits, mut shelf) = match previous {
+        Some(index) => (index.files, index.commits, index.shelf),
+        None => Default::default(),
+    };
+    // Versions being replaced came from the checkout indexed last; edits on the same commit
+    // are not worth keeping
+    let last = commits.last().filter(|c| commit.as_ref() != Some(*c)).cloned();
+    let shelve = |shelf: &mut BTreeMap<String, Shelved>, rel: &str, file: IndexedFile| {
+        if let Some(commit) = &last {
+            shelf.insert(shelf_key(rel, &file.digest), Shelved { commit: commit.clone(), file });
+        }
+    };
+    let mut files = BTreeMap::new();
+    let mut pending: Vec<(String, usize, usize, String)> = Vec::new();
+    let mut reused = 0;
+    let mut skipped_private = 0;
+    let mut skipped_bulky = 0;
+    let bulk = bulk::Guard::from_config(config);
+    for rel in candidates(&root)? {
+        if exclude.iter().any(|g| g.matches(&rel)) {
+            continue;
+        }
+        if privacy.as_ref().is_some_and(|r| r.private_path(&rel).is_some()) {
+            skipped_private += 1;
+            continue;
+        }
+        if bulk.skip(&root, Path::new(&rel)).is_some() {
+            skipped_bulky += 1;
+            continue;
+        }
+        let Some(text) = read_text(&root, &rel) else { continue };
+        let digest = sha256_hex(text.as_bytes());
+        let current = match previous_files.remove(&rel) {
+            Some(file) if file.digest == digest => Some(file),
+            Some(file) => {
+                shelve(&mut shelf, &rel, file);
+                None
+            }
+            None => None,
+        };
+        let current = current.or_else(|| shelf.remove(&shelf_key(&rel, &digest)).map(|s| s.file).inspect(|_| reused += 1));
+        match current {
+            Some(file) => {
+                files.insert(rel, file);
+            }
+            None => {
+                pending.extend(chunks(&text, chunk_lines).into_iter().map(|(start, end, body)| (rel.clone(), start, end, body)));
+                files.insert(rel, IndexedFile { digest, chunks: Vec::new() });
+            }
+        }
+    }
+    // Files this checkout lacks, and versions of commits that dropped out of the kept ones
+    for (rel, file) in std::mem::take(&mut previous_files) {
+        shelve(&mut shelf, &rel, file);
+    }
+    if let Some(commit) = commit {
+        commits.retain(|c| *c != commit);
+        commits.push(commit);
+        commits.drain(..commits.len().saturating_sub(KEPT_COMMITS));
+    }
+    shelf.retain(|_, s| commits.contains(&s.commit));
+    let bar = progress::batch_bar(pending.len() as u64, true);
+    for batch in pending.chunks(BATCH) {
+        let inputs: Vec<String> = batch.iter().map(|(rel, _, _, text)| embed_input(rel, text)).collect();
+        let vectors = embedder.embed(&inputs).await?;
+        for ((rel, start, end, text), vector) in batch.iter().zip(vectors) {
+            if let Some(file) = files.get_mut(rel) {
+                file.chunks.push(Chunk { start: *start, end: *end, text: text.clone(), vector });
+            }
+        }
+        bar.inc(batch.len() as u64);
+    }
+    bar.finish_and_clear();
+    let index = Index { provider: embedder.provider.clone(), model: embedder.model.clone(), chunk_lines, files, commits, shelf };
+    fs::create_dir_all(path.parent().unwrap())?;
+    compress::write_private(&path, &serde_json::to_vec(&index)?)?;
+    let total: usize = index.files.values().map(|f| f.chunks.len()).sum();
+    println!("Indexed {} files of {} as {} chunks ({} embedded now with {} {})", index.files.len(), root.display(), total, pending.len(), embedder.provider, embedder.model);
+    if reused > 0 {
+        println!("Reused the chunks of {} file(s) indexed at an earlier commit or pulled from the shared index", reused);
+    }
+    if skipped_private > 0 {
+        println!("Left out {} file(s) under private paths; embed with `[index] provider = \"ollama\"` to include them", skipped_private);
+    }
+    if skipped_bulky > 0 {
+        println!("Left out {} binary, minified, generated or oversized file(s); list them in `include_files` to index them", skipped_bulky);
+    }
+    Ok(())
+}
+
+/// The bucket `[index] share` names
+fn shared(cfg: &IndexConfig, client: &reqwest::Client) -> Result<s3::Bucket> {
+    let url = cfg.share.as_deref().context("Set `[index] share` to an s3:// or gs:// URL to share the index")?;
+    s3::Bucket::open(url, cfg.share_endpoint.as_deref(), cfg.share_region.as_deref(), client, "index.share")
+}
+
+/// Shared objects of indexes built with the same provider, model and chunk size, under
+/// `<space>/files/` by version and `<space>/commits/` by commit
+fn space(provider: &str, model: &str, chunk_lines: usize) -> String {
+    sha256_hex(format!("{}\n{}\n{}", provider, model, chunk_lines).as_bytes())[..16].to_string()
+}
+
+/// The shared object holding the chunks of one file version; content-addressed, so it never
+/// changes once uploaded
+fn file_object(space: &str, rel: &str, digest: &str) -> String {
+    format!("{}/files/{}.json.zst", space, sha256_hex(shelf_key(rel, digest).as_bytes()))
+}
+
+fn commit_object(space: &str, commit: &str) -> String {
+    format!("{}/commits/{}.json.zst", space, commit)
+}
+
+/// `rustaigent index --pull`: fetch the chunks of the shared index of HEAD, or of its nearest
+/// ancestor that has one, that the local index lacks, so the build that follows embeds only
+/// what differs from it
+pub async fn pull(config: &Config) -> Result<()> {
+    let cfg = config.index.clone().unwrap_or_default();
+    let client = http::client(config.http.as_ref());
+    let bucket = shared(&cfg, &client)?;
+    let embedder = Embedder::from_config(config, client);
+    let chunk_lines = cfg.chunk_lines.unwrap_or(60).max(1);
+    let space = space(&embedder.provider, &embedder.model, chunk_lines);
+    let root = repo_root()?;
+    let path = index_file(&root);
+    let out = Command::new("git").args(["rev-list", &format!("--max-count={}", PULL_DEPTH), "HEAD"]).current_dir(&root).output()
+        .context("Failed to run `git rev-list`")?;
+    if !out.status.success() {
+        bail!("Pulling a shared index needs a git repository with a commit checked out");
+    }
+    let mut found = None;
+    for commit in String::from_utf8_lossy(&out.stdout).lines() {
+        if let Some(bytes) = bucket.get(&commit_object(&space, commit)).await? {
+            found = Some((commit.to_string(), bytes));
+            break;
+        }
+    }
+    let Some((commit, bytes)) = found else {
+        println!("No index of HEAD or its last {} commits is shared for {} {}; build one and share it with `rustaigent index --push`", PULL_DEPTH, embedder.provider, embedder.model);
+        return Ok(());
+    };
+    let manifest: BTreeMap<String, String> = serde_json::from_slice(&zstd::decode_all(bytes.as_slice())?)
+        .with_context(|| format!("Corrupt shared index of commit {}", commit))?;
+    let mut index = load(&path)?
+        .filter(|i| i.provider == embedder.provider && i.model == embedder.model && i.chunk_lines == chunk_lines)
+        .unwrap_or_else(|| Index { provider: embedder.provider.clone(), model: embedder.model.clone(), chunk_lines, files: BTreeMap::new(), commits: Vec::new(), shelf: BTreeMap::new() });
+    let missing: Vec<(&String, &String)> = manifest.iter()
+        .filter(|(rel, digest)| index.files.get(*rel).is_none_or(|f| f.digest != **digest) && !index.shelf.contains_key(&shelf_key(rel, digest)))
+        .collect();
+    let bar = progress::batch_bar(missing.len() as u64, true);
+    let fetched: Vec<(String, IndexedFile)> = futures::stream::iter(missing)
+        .map(|(rel, digest)| {
+            let (bucket, bar, name) = (&bucket, &bar, file_object(&space, rel, digest));
+            async move {
+                let file = bucket.get(&name).await?
+                    .map(|bytes| Ok::<IndexedFile, anyhow::Error>(serde_json::from_slice(&zstd::decode_all(bytes.as_slice())?)?))
+                    .transpose()
+                    .with_context(|| format!("Corrupt shared chunks of {}", rel))?
+                    .filter(|file| file.digest == *digest);
+                bar.inc(1);
+                Ok::<_, anyhow::Error>(file.map(|file| (rel.clone(), file)))
+            }
+        })
+        .buffer_unordered(TRANSFERS)
+        .try_filter_map(|file| async move { Ok(file) })
+        .try_collect()
+        .await?;
+    bar.finish_and_clear();
+    // Filed under the commit they were shared at, oldest of the kept ones, so the build takes
+    // them where they match and forgets the rest first
+    let pulled = fetched.len();
+    for (rel, file) in fetched {
+        index.shelf.insert(shelf_key(&rel, &file.digest), Shelved { commit: commit.clone(), file });
+    }
+    if !index.commits.contains(&commit) {
+        index.commits.insert(0, commit.clone());
+    }
+    fs::create_dir_all(path.parent().unwrap())?;
+    compress::write_private(&path, &serde_json::to_vec(&index)?)?;
+    println!("Pulled {} file version(s) of the index shared at commit {}", pulled, &commit[..commit.len().min(12)]);
+    Ok(())
+}
+
+/// `rustaigent index --push`: upload the chunks of the local index that the bucket lacks, and
+/// file the index under the commit checked out. Files under `[privacy] private_paths` stay local.
+pub async fn push(config: &Config) -> Result<()> {
+    let cfg = config.index.clone().unwrap_or_default();
+    let bucket = shared(&cfg, &http::client(config.http.as_ref()))?;
+    let root = repo_root()?;
+    let index = load(&index_file(&root))?.context("There is no index to share; run `rustaigent index` first")?;
+    let commit = head(&root).context("Sharing the index needs a git repository with a commit checked out")?;
+    let privacy = privacy::Router::from_config(config.privacy.as_ref(), "")?;
+    let space = space(&index.provider, &index.model, index.chunk_lines);
+    let files: Vec<(&String, &IndexedFile)> = index.files.iter()
+        .filter(|(rel, _)| privacy.as_ref().is_none_or(|r| r.private_path(rel).is_none()))
+        .collect();
+    let bar = progress::batch_bar(files.len() as u64, true);
+    let uploaded: usize = futures::stream::iter(&files)
+        .map(|(rel, file)| {
+            let (bucket, bar, name) = (&bucket, &bar, file_object(&space, rel, &file.digest));
+            async move {
+                let new = !bucket.exists(&name).await?;
+                if new {
+                    bucket.put(&name, zstd::encode_all(serde_json::to_vec(file)?.as_slice(), zstd::DEFAULT_COMPRESSION_LEVEL)?).await?;
+                }
+                bar.inc(1);
+                Ok::<_, anyhow::Error>(new as usize)
+            }
+        })
+        .buffer_unordered(TRANSFERS)
+        .try_fold(0, |sum, new| async move { Ok(sum + new) })
+        .await?;
+    bar.finish_and_clear();
+    let manifest: BTreeMap<&String, &String> = files.iter().map(|(rel, file)| (*rel, &file.digest)).collect();
+    bucket.put(&commit_object(&space, &commit), zstd::encode_all(serde_json::to_vec(&manifest)?.as_slice(), zstd::DEFAULT_COMPRESSION_LEVEL)?).await?;
+    println!("Shared the index of {} files at commit {} ({} uploaded now)", files.len(), &commit[..commit.len().min(12)], uploaded);
+    if files.len() < index.files.len() {
+        println!("Kept {} file(s) under private paths out of the shared index", index.files.len() - files.len());
+    }
+    Ok(())
+}
+
+/// The stored index of the working directory's repository, loaded on first search
+pub struct Retriever {
+    root: PathBuf,
+    path: PathBuf,
+    embedder: Embedder,
+    /// Chunks added to each prompt
+    pub top_k: usize,
+    pub min_score: f32,
+    index: OnceCell<Index>,
+}
+
+impl Retriever {
+    /// `None` when the repository has not been indexed or `[index] enabled = false`
+    pub fn open(config: &Config, client: reqwest::Client) -> Option<Self> {
+        let cfg = config.index.clone().unwrap_or_default();
+        if cfg.enabled == Some(false) {
+            return None;
+        }
+        let root = repo_root().ok()?;
+        let path = index_file(&root);
+        if !path.is_file() {
+            return None;
+        }
+        Some(Retriever {
+            root,
+            path,
+            embedder: Embedder::from_config(config, client),
+            top_k: cfg.top_k.unwrap_or(4),
+            min_score: cfg.min_score.unwrap_or(0.3),
+            index: OnceCell::new(),
+        })
+    }
+
+    /// Where the index is kept, and whether it has been read into memory yet
+    pub(crate) fn file(&self) -> (&Path, bool) {
+        (&self.path, self.index.initialized())
+    }
+
+    /// The `k` chunks most similar to `query`, best first
+    pub async fn search(&self, query: &str, k: usize) -> Result<Vec<Hit>> {
+        let index = self.index.get_or_try_init(|| async {
+            load(&self.path)?.context("The codebase index is gone; run `rustaigent index`")
+        }).await?;
+        if index.provider != self.embedder.provider || index.model != self.embedder.model {
+            bail!("The codebase index was built with {} {}; run `rustaigent index` again for {} {}", index.provider, index.model, self.embedder.provider, self.embedder.model);
+        }
+        let query = self.embedder.embed(&[query.to_string()]).await?.pop().unwrap_or_default();
+        let query = &query;
+        let mut scored: Vec<(f32, &str, &Chunk)> = index.files.iter()
+            .flat_map(|(rel, file)| file.chunks.iter().map(move |c| (c.vector.iter().zip(query).map(|(a, b)| a * b).sum(), rel.as_str(), c)))
+            .collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        let cwd = env::current_dir().unwrap_or_default();
+        Ok(scored.into_iter().take(k).map(|(score, rel, chunk)| {
+            let path = self.root.join(rel);
+            let shown = path.strip_prefix(&cwd).unwrap_or(&path).display().to_string();
+            Hit { location: format!("{}:{}-{}", shown, chunk.start, chunk.end), text: chunk.text.clone(), score }
+        }).collect())
+    }
+}
+
+/// `search_codebase` output: each hit between `[match n: location]` and `[end of match n]` lines
+pub fn render(hits: &[Hit]) -> String {
+    if hits.is_empty() {
+        return "The index holds no chunks".into();
+    }
+    hits.iter().enumerate()
+        .map(|(i, hit)| format!("[match {}: {}] similarity {:.2}\n{}\n[end of match {}]", i + 1, hit.location, hit.score, hit.text, i + 1))
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// Locations and text of the hits in `search_codebase` output
+pub fn matches(output: &str) -> Vec<(String, &str)> {
+    let mut found = Vec::new();
+    let mut rest = output;
+    for n in 1.. {
+        let open = format!("[match {}: ", n);
+        let Some(start) = rest.find(&open) else { break };
+        let after = &rest[start + open.len()..];
+        let Some((location, body)) = after.split_once("] ") else { break };
+        let Some(body_start) = body.find('\n') else { break };
+        let close = format!("\n[end of match {}]", n);
+        let Some(end) = body.find(&close) else { break };
+        if end < body_start {
+            break;
+        }
+        found.push((location.to_string(), &body[body_start + 1..end]));
+        rest = &body[end + close.len()..];
+    }
+    found
+}
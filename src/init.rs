@@ -0,0 +1,120 @@
+use std::{fs, io::{self, Write}};
+use anyhow::{Result, Context, bail};
+use reqwest::Client;
+use serde_json::Value;
+use crate::{api_version::ApiVersions, credentials, http, paths};
+
+const PROVIDERS: &[(&str, &str)] = &[
+    ("openai", "OpenAI"),
+    ("claude", "Anthropic Claude"),
+    ("ollama", "Ollama (local)"),
+    ("google", "Google Generative Language API"),
+    ("huggingface", "Hugging Face Inference"),
+    ("xai", "xAI"),
+    ("deepseek", "DeepSeek"),
+];
+
+fn prompt(question: &str) -> Result<String> {
+    print!("{}", question);
+    io::stdout().flush()?;
+    let mut line = String::new();
+    io::stdin().read_line(&mut line)?;
+    Ok(line.trim().to_string())
+}
+
+/// Pick from a numbered list; an empty answer takes the first entry
+fn choose(question: &str, options: &[String]) -> Result<usize> {
+    for (i, opt) in options.iter().enumerate() {
+        println!("  {}) {}", i + 1, opt);
+    }
+    loop {
+        let answer = prompt(&format!("{} [1]: ", question))?;
+        if answer.is_empty() {
+            return Ok(0);
+        }
+        match answer.parse::<usize>() {
+            Ok(n) if (1..=options.len()).contains(&n) => return Ok(n - 1),
+            _ => println!("Please enter a number between 1 and {}", options.len()),
+        }
+    }
+}
+
+/// List the provider's models; doubles as the connectivity and credential check
+async fn list_models(client: &Client, provider: &str, key: &str) -> Result<Vec<String>> {
+    let versions = ApiVersions::from_env();
+    let req = match provider {
+        "openai" => client.get(format!("https://api.openai.com/{}/models", versions.openai)).bearer_auth(key),
+        "claude" => client.get("https://api.anthropic.com/v1/models")
+            .header("x-api-key", key)
+            .header("anthropic-version", &versions.anthropic),
+        "ollama" => client.get("http://localhost:11434/api/tags"),
+        "google" => client.get(format!("https://generativelanguage.googleapis.com/{}/models?key={}", versions.google, key)),
+        "huggingface" => client.get("https://router.huggingface.co/v1/models").bearer_auth(key),
+        "xai" => client.get("https://api.x.ai/v1/models").bearer_auth(key),
+        "deepseek" => client.get("https://api.deepseek.com/models").bearer_auth(key),
+        other => bail!("Unknown provider `{}`", other),
+    };
+    let resp = req.send().await.with_context(|| format!("Could not reach the {} API", provider))?;
+    let status = resp.status();
+    let body: Value = resp.json().await.unwrap_or(Value::Null);
+    if !status.is_success() {
+        let msg = http::error_message(&body).unwrap_or_else(|| "no error details".into());
+        bail!("{} API returned {}: {}", provider, status, msg);
+    }
+    let entries = body["data"].as_array().or_else(|| body["models"].as_array()).cloned().unwrap_or_default();
+    let mut models: Vec<String> = entries.iter()
+        .filter_map(|m| m["id"].as_str().or_else(|| m["name"].as_str()))
+        .map(|name| name.trim_start_matches("models/").to_string())
+        .collect();
+    models.sort();
+    Ok(models)
+}
+
+/// Interactive first-run setup: provider, key, connectivity check, default model, config file
+pub async fn run() -> Result<()> {
+    println!("Welcome to RustAIgent! Let's set up a provider.\n");
+    let labels: Vec<String> = PROVIDERS.iter().map(|(_, label)| label.to_string()).collect();
+    let provider = PROVIDERS[choose("Provider", &labels)?].0;
+
+    let key = if provider == "ollama" {
+        String::new()
+    } else {
+        let key = rpassword::prompt_password(format!("{} API key (input hidden): ", provider))?;
+        if key.trim().is_empty() {
+            bail!("An API key is required for {}", provider);
+        }
+        key.trim().to_string()
+    };
+
+    println!("Testing connectivity...");
+    let client = Client::new();
+    let models = list_models(&client, provider, &key).await?;
+    println!("Connected; {} models available.", models.len());
+
+    let model = if models.is_empty() {
+        prompt("Model name: ")?
+    } else {
+        // Long lists are trimmed for readability; any name can still be typed in later via config
+        let shown: Vec<String> = models.iter().take(30).cloned().collect();
+        shown[choose("Default model", &shown)?].clone()
+    };
+
+    if !key.is_empty() {
+        credentials::store(provider, &key)?;
+        println!("Stored API key in {} (owner read/write only)", paths::credentials_file().display());
+    }
+
+    // Edit in place so existing comments and settings survive
+    let path = paths::user_config_file();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut doc: toml_edit::DocumentMut = fs::read_to_string(&path).unwrap_or_default().parse()
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+    doc["provider"] = toml_edit::value(provider);
+    doc["model"] = toml_edit::value(model.as_str());
+    fs::write(&path, doc.to_string())?;
+    println!("Wrote {}", path.display());
+    println!("\nAll set. Run `rustaigent` to start a session.");
+    Ok(())
+}
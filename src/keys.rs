@@ -0,0 +1,72 @@
+use std::{sync::Mutex, time::Instant};
+
+/// How the next key is chosen from a pool
+#[derive(Clone, Copy, PartialEq)]
+pub enum KeyStrategy {
+    RoundRobin,
+    LeastRecentlyLimited,
+}
+
+impl KeyStrategy {
+    pub fn parse(s: &str) -> Self {
+        match s {
+            "least-limited" | "least-recently-limited" => KeyStrategy::LeastRecentlyLimited,
+            _ => KeyStrategy::RoundRobin,
+        }
+    }
+}
+
+struct KeyState {
+    key: String,
+    limited_at: Option<Instant>,
+}
+
+/// A set of API keys for one provider, shared by every request the agent makes
+pub struct KeyPool {
+    strategy: KeyStrategy,
+    state: Mutex<(usize, Vec<KeyState>)>,
+}
+
+impl KeyPool {
+    /// Build a pool from a comma-separated key list
+    pub fn new(keys: &str, strategy: KeyStrategy) -> Self {
+        let keys = keys.split(',')
+            .map(str::trim)
+            .filter(|k| !k.is_empty())
+            .map(|k| KeyState { key: k.into(), limited_at: None })
+            .collect();
+        KeyPool { strategy, state: Mutex::new((0, keys)) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().1.len()
+    }
+
+    /// Pick the key for the next request, returning its index for later failure reports
+    pub fn select(&self) -> (usize, String) {
+        let mut guard = self.state.lock().unwrap();
+        let (next, keys) = &mut *guard;
+        if keys.is_empty() {
+            return (0, String::new());
+        }
+        let idx = match self.strategy {
+            KeyStrategy::RoundRobin => *next % keys.len(),
+            // Never-limited keys sort first, then the one whose limit is oldest; ties keep rotation order
+            KeyStrategy::LeastRecentlyLimited => (0..keys.len())
+                .map(|i| (*next + i) % keys.len())
+                .min_by_key(|&i| keys[i].limited_at)
+                .unwrap_or(0),
+        };
+        *next = idx + 1;
+        (idx, keys[idx].key.clone())
+    }
+
+    /// Record that a key hit a rate limit or quota so selection moves away from it
+    pub fn mark_limited(&self, idx: usize) {
+        let mut guard = self.state.lock().unwrap();
+        if let Some(k) = guard.1.get_mut(idx) {
+            k.limited_at = Some(Instant::now());
+        }
+        guard.0 = idx + 1;
+    }
+}
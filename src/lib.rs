@@ -0,0 +1,2513 @@
+//! RustAIgent as a library: an [`Agent`] session with provider dispatch, the tool registry and
+//! the policy they run under. Build one with [`AgentBuilder`], run turns with [`Agent::turn`],
+//! and follow them through [`Agent::events`]; the `rustaigent` binary is a CLI on top of this.
+
+use serde::{Serialize, Deserialize, Deserializer};
+
+/// Items beyond a single provider request: the agent, its tools and the CLI need the filesystem,
+/// processes and a native runtime, so wasm32 builds leave them out
+macro_rules! native {
+    ($($item:item)*) => { $( #[cfg(not(target_arch = "wasm32"))] $item )* };
+}
+
+native! {
+    use std::{env, io::{self, Write}, sync::{Arc, Mutex}, time::{Duration, Instant}};
+    use reqwest::Client;
+    use anyhow::{Result, Context, bail};
+    use dotenvy::dotenv;
+    use tokio::time::sleep;
+    use futures::stream::{self, StreamExt};
+    use tracing::{debug, info, warn};
+}
+
+mod api_version;
+pub mod chat;
+pub mod http;
+pub mod providers;
+#[cfg(target_arch = "wasm32")]
+mod wasm;
+
+pub use api_version::ApiVersions;
+
+native! {
+    pub mod actions;
+    mod aliases;
+    mod artifacts;
+    pub mod auth;
+    pub mod autosave;
+    pub mod batch;
+    mod builder;
+    mod bulk;
+    pub mod cancel;
+    mod canary;
+    mod capabilities;
+    mod citations;
+    mod compat;
+    mod compress;
+    pub mod config;
+    mod context;
+    mod credentials;
+    #[cfg(unix)]
+    pub mod daemon;
+    mod digest;
+    mod doctor;
+    mod editor;
+    pub mod events;
+    pub mod experiment;
+    mod fences;
+    #[cfg(feature = "ffi")]
+    pub mod ffi;
+    pub mod fix;
+    mod focus;
+    #[cfg(test)]
+    mod fuzz;
+    mod gcp;
+    mod gitdiff;
+    mod github;
+    #[cfg(test)]
+    mod golden;
+    #[cfg(feature = "grpc")]
+    pub mod grpc;
+    mod guardrails;
+    pub mod handle;
+    pub mod history;
+    mod howdoi;
+    mod i18n;
+    pub mod index;
+    pub mod init;
+    mod keys;
+    mod lint;
+    pub mod logging;
+    pub mod logs;
+    pub mod mcp;
+    #[cfg(any(test, feature = "mock-server"))]
+    pub mod mock;
+    pub mod models;
+    pub mod notebook;
+    pub mod oneshot;
+    pub mod owners;
+    pub mod pager;
+    pub mod paths;
+    mod plan;
+    pub mod plugins;
+    pub mod policy;
+    mod postprocess;
+    mod prefetch;
+    pub mod pr;
+    mod privacy;
+    mod progress;
+    mod project;
+    pub mod purge;
+    #[cfg(feature = "python")]
+    mod python;
+    pub mod queue;
+    mod race;
+    mod reasoning;
+    mod reload;
+    mod retry;
+    pub mod risk;
+    mod roots;
+    mod repl;
+    pub mod replay;
+    pub mod report;
+    pub mod review;
+    mod s3;
+    pub mod self_update;
+    pub mod serve;
+    pub mod session;
+    pub mod stats;
+    pub mod stdio;
+    mod suggest;
+    pub mod sync;
+    pub mod templates;
+    pub mod tenants;
+    pub mod theme;
+    mod toolchain;
+    pub mod tools;
+    mod turns;
+    mod untrusted;
+    mod uploads;
+    mod verify;
+    pub mod vault;
+    pub mod workon;
+    pub mod workspace;
+
+    pub use builder::AgentBuilder;
+    pub use tools::Tool;
+}
+
+/// One turn of the conversation as the providers see it
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ChatMessage {
+    pub role: String,
+    /// Empty when the model answered with a function call, for which the API sends `null`
+    #[serde(default, deserialize_with = "null_as_empty")]
+    pub content: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Reasoning returned alongside the answer; only DeepSeek gets it back, within the turn
+    #[serde(default, alias = "reasoning_content", skip_serializing_if = "Option::is_none")]
+    pub reasoning: Option<String>,
+    /// Tool the model wants run before it answers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub function_call: Option<FunctionCall>,
+    /// Files uploaded to the provider that go with this user message
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<FileRef>,
+    /// Stored response this answer came from, for backends that keep the conversation server-side
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_id: Option<String>,
+}
+
+/// A file held by a provider's files API, referred to from a message instead of inlined
+#[derive(Serialize, Deserialize, Clone)]
+pub struct FileRef {
+    /// Provider the file was uploaded to; the others never see it
+    pub provider: String,
+    /// OpenAI file id, or the Gemini file URI
+    pub id: String,
+    /// File name it was uploaded from
+    pub name: String,
+    pub mime: String,
+}
+
+fn null_as_empty<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<String, D::Error> {
+    Ok(Option::<String>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+/// A function call from the model, with the arguments as the JSON text it sent
+#[derive(Serialize, Deserialize, Clone)]
+pub struct FunctionCall {
+    pub name: String,
+    pub arguments: String,
+    /// Provider's id for the call, which its result has to quote back
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub call_id: Option<String>,
+}
+
+/// A tool as offered to the model: its name, what it does and a JSON Schema for its arguments
+#[derive(Serialize, Clone)]
+pub struct FunctionDefinition {
+    pub name: String,
+    pub description: String,
+    pub parameters: serde_json::Value,
+}
+
+/// One agent session: the conversation, the provider it talks to and the tools it may run
+#[cfg(not(target_arch = "wasm32"))]
+pub struct Agent {
+    client: Client,
+    keys: Arc<keys::KeyPool>,
+    /// Mints credentials for each request, per `[auth.<provider>]` or the embedding application
+    auth: Option<Arc<dyn auth::Authenticator>>,
+    provider: String,
+    /// Native request and response format of `provider`
+    backend: Arc<dyn providers::Provider>,
+    model: Option<String>,
+    config: config::Config,
+    api_versions: api_version::ApiVersions,
+    conversation: history::History,
+    /// Tool definitions, shared with batch clones
+    functions: Arc<[FunctionDefinition]>,
+    /// Tools registered by the embedding application
+    custom_tools: Vec<Arc<dyn tools::Tool>>,
+    max_tokens: u16,
+    temperature: f32,
+    /// Attempts per request outside a turn
+    retry_count: u8,
+    backoff_base: u64,
+    /// Retries left to the running turn, shared with the copies of the session it sends through
+    retries: Arc<retry::Budget>,
+    lint_rust: bool,
+    /// Request answers as server-sent events where the provider supports it
+    streaming: bool,
+    /// Answer text printed to the terminal while it streamed in; `None` when nothing should be printed
+    live: Option<Mutex<String>>,
+    /// Messages kept in memory before older turns are paged out to disk; unlimited when unset
+    memory_messages: Option<usize>,
+    /// Scheme and host replacing the provider's endpoint, for proxies and mock servers
+    base_url: Option<String>,
+    reasoning: reasoning::Reasoning,
+    lang: i18n::Lang,
+    a11y: bool,
+    quiet: bool,
+    tool_ctx: tools::ToolContext,
+    sources: citations::Sources,
+    tool_log: Vec<session::ToolCall>,
+    /// Active plan, checked off as tools complete
+    plan: Option<plan::Plan>,
+    guardrails: Option<guardrails::Guardrails>,
+    /// Local provider for requests carrying private content
+    privacy: Option<Arc<privacy::Router>>,
+    /// Provider each request is raced against, per `[race]` or `/race`
+    race: Option<Arc<race::RaceConfig>>,
+    /// Conversation messages already passed through the guardrails
+    guard_checked: usize,
+    stats: Arc<Mutex<stats::SessionStats>>,
+    /// Spinner of the request in flight, paused while a tool the model called runs
+    waiting: Option<indicatif::ProgressBar>,
+    /// Working-tree changes sent along with each prompt
+    diff_context: Option<gitdiff::DiffContext>,
+    focus: Option<focus::Focus>,
+    /// `@name` directories besides the working directory
+    roots: roots::Roots,
+    /// The latest REPL prompt and how long the conversation was before it, for `/retry`
+    last_prompt: Option<(usize, String)>,
+    /// Cancels the running turn
+    cancel: cancel::Canceller,
+    /// Files the running turn's tools changed, restored if it is cancelled
+    journal: cancel::Journal,
+    /// USD one turn may spend, set by the embedding application below `turn_budget_usd`
+    turn_budget: Option<f64>,
+    /// Session cost when the running turn started
+    turn_spent_from: f64,
+    /// Uploaded files that go with the next prompt
+    attachments: Vec<FileRef>,
+    /// Frames and checks material from outside the conversation, per `injection_guard`
+    untrusted: untrusted::Guard,
+    /// Config and prompt files checked for changes before each turn; `None` with `hot_reload = false`
+    reload: Option<reload::Watcher>,
+    /// Read-only calls run ahead of the model asking for them
+    prefetch: prefetch::Prefetch,
+    /// What the latest turn changed and ran; `None` when it only read
+    actions: Option<actions::Actions>,
+    /// No provider is set up, so prompts are not sent; see [`AgentBuilder::or_offline`]
+    offline: bool,
+    /// Follow-ups offered under the latest answer, sent by typing their number
+    suggestions: Vec<String>,
+}
+
+/// System prompt used when the config sets none
+#[cfg(not(target_arch = "wasm32"))]
+const DEFAULT_PROMPT: &str = "You are RustAIgent, a versatile Rust coding assistant with tools for file I/O, directory ops, shell commands, HTTP fetches, and code evaluation. Switch between OpenAI, Claude, Ollama, Google. Use rich function calling. Respond concisely in Rust style.";
+
+/// Why a prompt got no answer in an offline session, and how to get one
+#[cfg(not(target_arch = "wasm32"))]
+const OFFLINE: &str = "No provider is set up, so prompts are not sent. Run /init to pick a provider and store its key, or set its key (OPENAI_API_KEY, ANTHROPIC_API_KEY, GOOGLE_API_KEY, ...) and restart; /provider ollama talks to a local Ollama server, which needs none.";
+
+/// The backend for `provider`, with the settings in the config that shape it
+#[cfg(not(target_arch = "wasm32"))]
+fn backend(provider: &str, config: &config::Config) -> Arc<dyn providers::Provider> {
+    match provider {
+        "vertex" => {
+            let cfg = config.vertex.clone().unwrap_or_default();
+            providers::vertex(gcp::project(&cfg), cfg.location)
+        }
+        "huggingface" => providers::huggingface(config.huggingface.as_ref().and_then(|cfg| cfg.endpoint.clone())),
+        _ => providers::for_settings(provider, config.openai_api.as_deref()),
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Agent {
+    fn new(api_key: String, provider: String) -> Self {
+        dotenv().ok();
+        let mut config = config::Config::load();
+        if let Ok(api) = env::var("OPENAI_API") {
+            config.openai_api = Some(api);
+        }
+        let strategy = keys::KeyStrategy::parse(&env::var("KEY_STRATEGY").unwrap_or_default());
+        let keys = Arc::new(keys::KeyPool::new(&api_key, strategy));
+        let model = env::var("MODEL_NAME").ok().or_else(|| config.model.clone());
+        let max_tokens = env::var("MAX_TOKENS").ok().and_then(|v| v.parse().ok()).or(config.max_tokens).unwrap_or(1024);
+        let temperature = env::var("TEMPERATURE").ok().and_then(|v| v.parse().ok()).or(config.temperature).unwrap_or(0.7);
+        let retry_count = env::var("RETRY_COUNT").ok().and_then(|v| v.parse().ok()).or(config.retry_count).unwrap_or(3);
+        let backoff_base = env::var("BACKOFF_BASE_MS").ok().and_then(|v| v.parse().ok()).unwrap_or(500);
+        let lint_rust = env::var("LINT_RUST").map(|v| v == "true" || v == "1").unwrap_or(false);
+        let streaming = env::var("STREAMING").map(|v| v == "true" || v == "1").ok().or(config.streaming).unwrap_or(false);
+        let base_url = env::var("API_BASE_URL").ok().or_else(|| config.base_url.clone());
+        let memory_messages = env::var("MEMORY_MESSAGES").ok().and_then(|v| v.parse().ok()).or(config.memory_messages);
+        let reasoning = reasoning::Reasoning::resolve(&config);
+        let lang = env::var("RESPONSE_LANGUAGE").ok().or_else(|| config.language.clone())
+            .map(|code| i18n::Lang::new(&code))
+            .unwrap_or_default();
+
+        let mut funcs = tools::ToolCall::definitions();
+
+        if let Some(allowed) = &config.allowed_tools {
+            funcs.retain(|f| allowed.contains(&f.name));
+        }
+
+        let prompt = config.system_prompt().unwrap_or_else(|err| {
+            warn!("{:#}; using the default system prompt", err);
+            None
+        }).unwrap_or_else(|| DEFAULT_PROMPT.into());
+        let configured_prompt = prompt.clone();
+        let prompt = match paths::current_dir().ok().and_then(|cwd| project::describe(&project::detect(&cwd))) {
+            Some(projects) => format!("{}\n\n{}", prompt, projects),
+            None => prompt,
+        };
+        let prompt = match paths::current_dir().ok().and_then(|cwd| workspace::Workspace::detect(&cwd)) {
+            Some(ws) if ws.members.len() > 1 => format!("{}\n\n{}", prompt, ws.describe()),
+            _ => prompt,
+        };
+        let prompt = match paths::current_dir().ok().and_then(|cwd| toolchain::Toolchain::detect(&cwd)) {
+            Some(toolchain) => format!("{}\n\n{}", prompt, toolchain.describe()),
+            None => prompt,
+        };
+        let prompt = match lang.reply_instruction() {
+            Some(instruction) => format!("{}\n\n{}", prompt, instruction),
+            None => prompt,
+        };
+        let prompt = match config.canary {
+            Some(true) => format!("{}\n\n{}", prompt, canary::instruction()),
+            _ => prompt,
+        };
+        let mut tool_ctx = tools::ToolContext::default();
+        tool_ctx.lint_rust = lint_rust;
+        tool_ctx.command_timeout_secs = config.command_timeout_secs;
+        tool_ctx.command_output_limit = config.command_output_limit;
+        tool_ctx.bulk = bulk::Guard::from_config(&config);
+        tool_ctx.lang = lang.clone();
+        tool_ctx.theme = theme::Theme::detect(config.theme.as_ref());
+        tool_ctx.policy = policy::Policy::from_config(&config);
+        tool_ctx.approval_command = config.approval_command.clone();
+        let client = http::client(config.http.as_ref());
+        let auth = auth::for_provider(&config, &provider, &client);
+        tool_ctx.network = tools::network::Network::new(config.allowed_hosts.clone(), config.allow_private_network.unwrap_or(false), config.http.as_ref());
+        tool_ctx.index = index::Retriever::open(&config, client.clone()).map(Arc::new);
+        tool_ctx.owners = paths::current_dir().ok().and_then(|cwd| owners::Owners::discover(config.codeowners.as_ref(), &cwd)).map(Arc::new);
+        if tool_ctx.index.is_none() {
+            funcs.retain(|f| f.name != "search_codebase");
+        }
+        let conv = vec![ChatMessage { role: "system".into(), content: prompt, name: None, reasoning: None, function_call: None, files: Vec::new(), response_id: None }];
+
+        let api_versions = api_version::ApiVersions::from_env();
+        let backend = backend(&provider, &config);
+        if !backend.supports_tools() {
+            warn!("tools are unavailable with provider `{}`", provider);
+        }
+        let guardrails = guardrails::Guardrails::from_config(config.guardrails.as_ref()).unwrap_or_else(|err| {
+            warn!("guardrails disabled: {:#}", err);
+            None
+        });
+        let privacy = privacy::Router::from_config(config.privacy.as_ref(), &provider).unwrap_or_else(|err| {
+            warn!("privacy routing disabled: {:#}", err);
+            None
+        }).map(Arc::new);
+        let race = config.race.clone().filter(|r| r.enabled != Some(false)).map(Arc::new);
+        let stats = Arc::new(Mutex::new(stats::SessionStats::new(&provider, config.pricing.clone())));
+        stats.lock().unwrap().tags = config.tags.clone().unwrap_or_default();
+        let untrusted = untrusted::Guard::new(config.injection_guard.as_deref());
+        tool_ctx.notes = tools::notes::Notes::open(&stats.lock().unwrap().id);
+        let reload = (config.hot_reload != Some(false)).then(|| reload::Watcher::new(&config, configured_prompt));
+        let roots: Vec<(String, String)> = config.roots.clone().unwrap_or_default().into_iter().collect();
+        let mut agent = Agent { client, keys, auth, provider, backend, model, config, api_versions, conversation: conv.into(), functions: funcs.into(), custom_tools: Vec::new(), max_tokens, temperature, retry_count, backoff_base, retries: Arc::default(), lint_rust, streaming, live: None, memory_messages, base_url, reasoning, lang, a11y: false, quiet: false, tool_ctx, sources: citations::Sources::default(), tool_log: Vec::new(), plan: None, guardrails, privacy, race, guard_checked: 0, stats, waiting: None, diff_context: None, focus: None, roots: roots::Roots::default(), last_prompt: None, cancel: cancel::Canceller::default(), journal: cancel::Journal::default(), turn_budget: None, turn_spent_from: 0.0, attachments: Vec::new(), untrusted, reload, prefetch: prefetch::Prefetch::default(), actions: None, offline: false, suggestions: Vec::new() };
+        for (name, dir) in roots {
+            if let Err(err) = agent.add_root(&name, std::path::Path::new(&dir)) {
+                warn!("leaving out root `{}`: {:#}", name, err);
+            }
+        }
+        agent
+    }
+
+    /// Send a single request with retries. The body is serialized once, straight from borrowed
+    /// data, and every attempt shares those bytes instead of re-encoding the conversation.
+    async fn request_with_retry(&self, url: &str, payload: bytes::Bytes) -> Result<serde_json::Value> {
+        let body = self.post_with_retry(url, payload).await?.bytes().await?;
+        if tracing::enabled!(tracing::Level::TRACE) {
+            tracing::trace!("response body {}", logging::body(&body, self.config.encrypt_request_logs != Some(false)));
+        }
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// POST with retries and key failover, returning the response before its body is read.
+    /// Rate limits and server errors are retried after the wait the provider asks for, from the
+    /// turn's retry budget, and a 401 once with fresh credentials when they are minted; other
+    /// error statuses fail at once with the provider's message.
+    async fn post_with_retry(&self, url: &str, payload: bytes::Bytes) -> Result<reqwest::Response> {
+        let mut failovers = 0;
+        let mut attempt = 0u32;
+        let mut reauthorized = false;
+        if tracing::enabled!(tracing::Level::TRACE) {
+            tracing::trace!("request body {}", logging::body(&payload, self.config.encrypt_request_logs != Some(false)));
+        }
+        loop {
+            let (key_idx, api_key) = self.keys.select();
+            let mut req = self.backend.authorize(self.client.post(url), &api_key, &self.api_versions)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(payload.clone())
+                .build()?;
+            if let Some(auth) = &self.auth {
+                auth::apply(auth.as_ref(), &mut req).await.with_context(|| format!("Could not authenticate to {}", self.provider))?;
+            }
+            debug!("POST {} ({} bytes, attempt {}, key #{})", url, payload.len(), attempt + 1, key_idx + 1);
+            let started = std::time::Instant::now();
+            let res = self.client.execute(req).await;
+            debug!("response headers after {}ms", started.elapsed().as_millis());
+            let backoff = Duration::from_millis(self.backoff_base.saturating_mul(2u64.saturating_pow(attempt)));
+            match res {
+                Ok(resp) if resp.status().is_success() => return Ok(resp),
+                // A minted token may have been revoked before it expired: mint another once
+                Ok(resp) if resp.status() == reqwest::StatusCode::UNAUTHORIZED && self.auth.is_some() && !reauthorized => {
+                    warn!("{} rejected the credentials; minting new ones", self.provider);
+                    self.auth.iter().for_each(|auth| auth.invalidate());
+                    reauthorized = true;
+                    continue;
+                }
+                // Quota or rate limit on one key: fail over to the next key without spending a retry
+                Ok(resp) if resp.status() == reqwest::StatusCode::TOO_MANY_REQUESTS && failovers + 1 < self.keys.len() => {
+                    warn!("key #{} hit a rate limit; failing over to the next key", key_idx + 1);
+                    self.keys.mark_limited(key_idx);
+                    failovers += 1;
+                    continue;
+                }
+                Ok(resp) => {
+                    let status = resp.status();
+                    if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+                        self.keys.mark_limited(key_idx);
+                    }
+                    let retryable = status == reqwest::StatusCode::TOO_MANY_REQUESTS || status.is_server_error();
+                    let wait = http::retry_after(resp.headers());
+                    let refused = match retryable && wait.is_none_or(|wait| wait <= http::MAX_RETRY_AFTER) {
+                        true => self.retry_refused(attempt, wait.unwrap_or(backoff)),
+                        false => Some(String::new()),
+                    };
+                    if let Some(why) = refused {
+                        let err = self.rejected(resp).await;
+                        return Err(match wait.filter(|_| retryable) {
+                            Some(wait) => anyhow::anyhow!("{}; retry in {}{}", err, humantime::format_duration(Duration::from_secs(wait.as_secs().max(1))), why),
+                            None if why.is_empty() => err,
+                            None => anyhow::anyhow!("{}{}", err, why),
+                        });
+                    }
+                    let wait = wait.unwrap_or(backoff);
+                    warn!("{} returned {}; retrying in {}ms", self.provider, status, wait.as_millis());
+                    sleep(wait).await;
+                }
+                Err(err) => {
+                    let stage = http::diagnose(&err).await;
+                    if let Some(why) = self.retry_refused(attempt, backoff) {
+                        return Err(anyhow::Error::from(err).context(format!("Could not reach {}: {}{}", self.provider, stage, why)));
+                    }
+                    warn!("request to {} failed: {}; retrying in {}ms", self.provider, stage, backoff.as_millis());
+                    sleep(backoff).await;
+                }
+            }
+            attempt += 1;
+        }
+    }
+
+    /// Why a request that failed `attempt + 1` times is not retried after waiting `pause`, as a
+    /// clause to append to its error (empty when the request used its own `retry_count`), or
+    /// `None` to retry it. Inside a turn the retry comes out of the turn's budget.
+    fn retry_refused(&self, attempt: u32, pause: Duration) -> Option<String> {
+        match self.retries.take(pause) {
+            Some(taken) => taken.err().map(|why| format!("; {}", why)),
+            None => (attempt + 1 >= u32::from(self.retry_count)).then(String::new),
+        }
+    }
+
+    /// The error for a response with an error status, carrying the provider's message
+    async fn rejected(&self, resp: reqwest::Response) -> anyhow::Error {
+        let status = resp.status();
+        let text = resp.text().await.unwrap_or_default();
+        // Proxies and gateways answer with HTML; a line of it beats no detail at all
+        let body = serde_json::from_str(&text).unwrap_or_else(|_| serde_json::Value::String(text.chars().take(200).collect()));
+        self.rejection(Some(status), &body)
+    }
+
+    /// The error for a provider error payload, typed when it reports an over-long prompt
+    fn rejection(&self, status: Option<reqwest::StatusCode>, body: &serde_json::Value) -> anyhow::Error {
+        let pinned = self.api_versions.for_provider(self.backend.name());
+        let detail = http::error_message(body).unwrap_or_else(|| "no error details".into());
+        let message = match status {
+            Some(status) => format!("{} rejected the request with {} (pinned {}): {}", self.provider, status, pinned, detail),
+            None => format!("{} rejected the request (pinned {}): {}", self.provider, pinned, detail),
+        };
+        if capabilities::is_context_overflow(&body["error"]) {
+            return capabilities::ContextOverflow(message).into();
+        }
+        match status {
+            Some(reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN) => anyhow::anyhow!("{}; check the API key and its permissions", message),
+            Some(reqwest::StatusCode::NOT_FOUND) => match aliases::notice(&self.provider, &self.model_name()) {
+                Some(notice) => anyhow::anyhow!("{}; {}", message, notice),
+                None => anyhow::anyhow!("{}; check that {} serves the model `{}`", message, self.provider, self.model_name()),
+            },
+            _ => anyhow::anyhow!(message),
+        }
+    }
+
+    /// Send a request to the session's provider, or to the local one when the conversation holds
+    /// private content; with privacy routing on, every request says where it went and why
+    async fn send_request(&self, turn: turns::Turn, func_call: Option<String>) -> Result<ChatMessage> {
+        if self.offline {
+            bail!(OFFLINE);
+        }
+        let Some(router) = &self.privacy else {
+            return self.send_raced(turn, func_call).await;
+        };
+        match router.private_reason(&self.conversation) {
+            Some(reason) => {
+                let local = self.local_clone(router);
+                self.report_route(&local.provider, &local.turn_model(turn), &reason);
+                local.send_to_provider(turn, func_call).await
+            }
+            None => {
+                self.report_route(&self.provider, &self.turn_model(turn), "no private content");
+                self.send_raced(turn, func_call).await
+            }
+        }
+    }
+
+    /// Say where a request goes: on stderr in the REPL, in the log when quiet
+    fn report_route(&self, provider: &str, model: &str, reason: &str) {
+        self.report(format!("[route] {} ({}): {}", provider, model, reason));
+    }
+
+    /// A line about the session on stderr, suspending the spinner; in the log when quiet
+    pub(crate) fn report(&self, line: String) {
+        if self.quiet {
+            info!("{}", line);
+            return;
+        }
+        let line = self.tool_ctx.theme.paint(theme::Role::Thinking, &line);
+        match self.waiting.as_ref().filter(|s| !s.is_finished()) {
+            Some(spinner) => spinner.suspend(|| eprintln!("{}", line)),
+            None => eprintln!("{}", line),
+        }
+    }
+
+    /// Send a request to the session's provider and, while racing, to the `[race]` provider
+    /// too; the first answer is kept and the other request cancelled
+    async fn send_raced(&self, turn: turns::Turn, func_call: Option<String>) -> Result<ChatMessage> {
+        let Some(race) = &self.race else {
+            return self.send_to_provider(turn, func_call).await;
+        };
+        let rival = self.sibling(&race.provider, race.base_url.clone(), race.model.clone());
+        let started = std::time::Instant::now();
+        let (answer, winner) = race::first_ok(self.send_to_provider(turn, func_call.clone()), rival.send_to_provider(turn, func_call)).await?;
+        let (won, lost) = match winner {
+            race::Winner::Session => ((&self.provider, self.turn_model(turn)), &rival.provider),
+            race::Winner::Rival => ((&rival.provider, rival.turn_model(turn)), &self.provider),
+        };
+        self.report(format!("[race] {} ({}) answered first in {:.1}s; {} cancelled", won.0, won.1, started.elapsed().as_secs_f64(), lost));
+        Ok(answer)
+    }
+
+    /// A copy of the session talking to the local provider of the privacy rules
+    fn local_clone(&self, router: &privacy::Router) -> Self {
+        let mut local = self.sibling(&router.provider, router.base_url.clone(), router.model.clone());
+        // Local servers speak Chat Completions whatever `openai_api` says
+        local.backend = providers::for_name(&router.provider);
+        local
+    }
+
+    /// A copy of the session's conversation and settings sent to another provider
+    fn sibling(&self, provider: &str, base_url: Option<String>, model: Option<String>) -> Self {
+        let api_key = builder::api_key(provider).unwrap_or_default();
+        let mut sibling = Agent::new(api_key, provider.to_string());
+        sibling.client = self.client.clone();
+        sibling.max_tokens = self.max_tokens;
+        sibling.temperature = self.temperature;
+        sibling.retry_count = self.retry_count;
+        sibling.backoff_base = self.backoff_base;
+        sibling.retries = Arc::clone(&self.retries);
+        // The answer is printed once it is complete
+        sibling.streaming = false;
+        sibling.reasoning = self.reasoning.clone();
+        sibling.lang = self.lang.clone();
+        sibling.api_versions = self.api_versions.clone();
+        sibling.base_url = base_url;
+        sibling.model = model;
+        // `[turns.*]` models name the session provider's models; the other turn settings still apply
+        for params in sibling.config.turns.iter_mut().flat_map(|t| [t.tools.as_mut(), t.answer.as_mut()]).flatten() {
+            params.model = None;
+        }
+        sibling.stats = Arc::clone(&self.stats);
+        sibling.conversation = self.conversation.clone();
+        sibling.functions = Arc::clone(&self.functions);
+        sibling
+    }
+
+    async fn send_to_provider(&self, turn: turns::Turn, func_call: Option<String>) -> Result<ChatMessage> {
+        let model = self.turn_model(turn);
+        if let Some(allowed) = &self.config.allowed_models {
+            if !allowed.contains(&model) {
+                anyhow::bail!("Model `{}` is not permitted by allowed_models ({})", model, allowed.join(", "));
+            }
+        }
+        let (url, body) = self.wire_request(&model, turn, func_call)?;
+        if !self.streams(&model) {
+            let response_json = self.request_with_retry(&url, body.into()).await?;
+            return self.parse_reply(&model, response_json);
+        }
+        let response = self.post_with_retry(&url, body.into()).await?;
+        let is_stream = response.headers().get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| !v.starts_with("application/json"));
+        if !is_stream {
+            // Errors, and servers that ignore `stream`, answer with a single JSON body
+            return self.parse_reply(&model, response.json().await?);
+        }
+        self.read_stream(&model, response).await
+    }
+
+    /// Whether requests for `model` are streamed: STREAMING is on, no race is run, and both the
+    /// provider and the model stream
+    fn streams(&self, model: &str) -> bool {
+        self.streaming && self.race.is_none() && self.backend.supports_streaming() && self.capabilities(model).streaming
+    }
+
+    /// Assemble a streamed completion, showing answer text as it arrives
+    async fn read_stream(&self, model: &str, mut response: reqwest::Response) -> Result<ChatMessage> {
+        let mut message = ChatMessage { role: "assistant".into(), content: String::new(), name: None, reasoning: None, function_call: None, files: Vec::new(), response_id: None };
+        let mut usage = (0, 0);
+        let mut truncated = false;
+        // Bytes are split into lines before decoding so multi-byte characters may straddle chunks
+        let mut pending = Vec::new();
+        'read: while let Some(chunk) = response.chunk().await? {
+            pending.extend_from_slice(&chunk);
+            while let Some(end) = pending.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = pending.drain(..=end).collect();
+                let Some(delta) = self.backend.parse_stream_line(&String::from_utf8_lossy(&line))? else { continue };
+                if let Some(error) = delta.error {
+                    return self.parse_reply(model, error);
+                }
+                if let Some(piece) = delta.content.as_deref() {
+                    self.show_piece(piece);
+                    message.content.push_str(piece);
+                }
+                if let Some(piece) = delta.reasoning {
+                    message.reasoning.get_or_insert_with(String::new).push_str(&piece);
+                }
+                if delta.call_name.is_some() || delta.call_arguments.is_some() {
+                    let entry = message.function_call.get_or_insert_with(|| FunctionCall { name: String::new(), arguments: String::new(), call_id: None });
+                    entry.name.push_str(delta.call_name.as_deref().unwrap_or_default());
+                    entry.arguments.push_str(delta.call_arguments.as_deref().unwrap_or_default());
+                    if delta.call_id.is_some() {
+                        entry.call_id = delta.call_id;
+                    }
+                }
+                if delta.response_id.is_some() {
+                    message.response_id = delta.response_id;
+                }
+                if let Some((prompt, completion)) = delta.usage {
+                    usage = (if prompt > 0 { prompt } else { usage.0 }, if completion > 0 { completion } else { usage.1 });
+                }
+                truncated |= delta.truncated;
+                if delta.done {
+                    break 'read;
+                }
+            }
+        }
+        self.stats.lock().unwrap().record_usage(model, usage.0, usage.1);
+        if truncated {
+            warn!("{}", self.lang.msg(i18n::Msg::ResponseTruncated));
+        }
+        if message.reasoning.is_none() {
+            let (thinking, answer) = reasoning::split_think_tags(&message.content);
+            message.reasoning = thinking;
+            message.content = answer;
+        }
+        Ok(message)
+    }
+
+    /// Hand a streamed piece of answer to event subscribers and, in the REPL, the terminal
+    fn show_piece(&self, piece: &str) {
+        self.emit(events::AgentEvent::Token(piece.into()));
+        let Some(live) = &self.live else { return };
+        let mut shown = live.lock().unwrap();
+        if let Some(spinner) = self.waiting.as_ref().filter(|s| !s.is_finished()) {
+            spinner.finish_and_clear();
+        }
+        if shown.is_empty() && !self.quiet {
+            print!("{} ", self.tool_ctx.theme.paint(theme::Role::Assistant, "RustAIgent:"));
+        }
+        print!("{}", piece);
+        io::stdout().flush().ok();
+        shown.push_str(piece);
+    }
+
+    /// The configured model, or the provider's default, with aliases and retired models resolved
+    fn model_name(&self) -> String {
+        match &self.model {
+            Some(model) => self.resolve_model(model),
+            None => self.backend.default_model().into(),
+        }
+    }
+
+    /// The model for this kind of request: its `[turns.*]` model, otherwise the session's
+    fn turn_model(&self, turn: turns::Turn) -> String {
+        match self.turn_params(turn).and_then(|p| p.model.as_deref()) {
+            Some(model) => self.resolve_model(model),
+            None => self.model_name(),
+        }
+    }
+
+    /// `model` after `[model_aliases]`, and replaced by its successor once retired
+    fn resolve_model(&self, model: &str) -> String {
+        aliases::resolve(&self.provider, model, self.config.model_aliases.as_ref(), self.config.migrate_models.unwrap_or(true))
+    }
+
+    /// `[turns.tools]` or `[turns.answer]` settings for this kind of request
+    fn turn_params(&self, turn: turns::Turn) -> Option<&turns::TurnParams> {
+        self.config.turns.as_ref().and_then(|t| t.params(turn))
+    }
+
+    /// What the provider accepts for `model`, from the built-in map and `[capabilities]` overrides
+    fn capabilities(&self, model: &str) -> capabilities::Capabilities {
+        capabilities::lookup(&self.provider, model, self.config.capabilities.as_ref())
+    }
+
+    /// What this build and session offer, as JSON: version, build features, providers and their
+    /// models, the session's model with what it accepts, and the tools the model may call.
+    /// `--capabilities`, `GET /capabilities` and the editor protocol's `capabilities` return it.
+    pub fn handshake(&self) -> serde_json::Value {
+        capabilities::handshake(self)
+    }
+
+    /// What `rustaigent doctor` prints: the configuration in effect, the memory the session
+    /// takes and the settings that bound it
+    pub fn doctor(&self) -> String {
+        doctor::report(self)
+    }
+
+    /// Endpoint and serialized body of the provider request for the current conversation
+    fn wire_request(&self, model: &str, turn: turns::Turn, func_call: Option<String>) -> Result<(String, Vec<u8>)> {
+        let caps = self.capabilities(model);
+        let tools = turn == turns::Turn::Tools && caps.tools && !self.functions.is_empty();
+        let params = self.turn_params(turn);
+        let temperature = params.and_then(|p| p.temperature).unwrap_or(self.temperature);
+        let max_tokens = params.and_then(|p| p.max_tokens).unwrap_or(self.max_tokens);
+        if turn == turns::Turn::Tools && !caps.tools {
+            match &func_call {
+                Some(name) => capabilities::warn_once(format!("{} has no function calling; `{}` is not forced and tools are left out", model, name)),
+                None => debug!("{} has no function calling; sending the request without tools", model),
+            }
+        }
+        if self.reasoning.effort.is_some() && !caps.reasoning {
+            capabilities::warn_once(format!("{} does not take reasoning_effort; ignoring it", model));
+        }
+        // Leave room for the answer inside the context window
+        let prompt_tokens = context::estimate_prompt(&self.conversation, tools.then_some(&*self.functions));
+        let room = caps.max_context.saturating_sub(prompt_tokens);
+        if room == 0 {
+            capabilities::warn_once(format!("the conversation (~{} tokens) exceeds the {}-token context of {}; lower memory_messages if the provider rejects it", prompt_tokens, caps.max_context, model));
+        }
+        let max_tokens = match u16::try_from(room) {
+            Ok(room) if room > 0 && room < max_tokens => {
+                debug!("capping max_tokens at {} to fit the context of {}", room, model);
+                room
+            }
+            _ => max_tokens,
+        };
+        // Reasoning models reject `temperature` and count reasoning against `max_completion_tokens`
+        let reasoning = caps.reasoning;
+        self.backend.build_request(&providers::Request {
+            model,
+            messages: &self.conversation,
+            functions: tools.then_some(&*self.functions),
+            force: func_call.as_deref().filter(|_| tools),
+            max_tokens,
+            temperature: (!reasoning).then_some(temperature),
+            reasoning_model: reasoning,
+            reasoning_effort: self.reasoning.effort.as_deref().filter(|_| reasoning),
+            thinking_budget: self.reasoning.budget,
+            stream: self.streams(model),
+            base_url: self.base_url.as_deref(),
+            versions: &self.api_versions,
+            hosted_tools: self.config.hosted_tools.as_deref().unwrap_or_default(),
+            vector_stores: self.config.vector_stores.as_deref().unwrap_or_default(),
+        })
+    }
+
+    /// Turn a provider response into the assistant message, recording usage
+    fn parse_reply(&self, model: &str, response_json: serde_json::Value) -> Result<ChatMessage> {
+        // Some servers report errors in a successful response
+        if response_json.get("error").is_some_and(|e| !e.is_null()) {
+            return Err(self.rejection(None, &response_json));
+        }
+        let reply = self.backend.parse_response(response_json)?;
+        self.stats.lock().unwrap().record_usage(model, reply.usage.0, reply.usage.1);
+        if reply.truncated {
+            warn!("{}", self.lang.msg(i18n::Msg::ResponseTruncated));
+        }
+        let mut message = reply.message;
+        if message.reasoning.is_none() {
+            let (thinking, answer) = reasoning::split_think_tags(&message.content);
+            message.reasoning = thinking;
+            message.content = answer;
+        }
+        Ok(message)
+    }
+
+    /// Send multiple prompts concurrently
+    pub async fn send_batch_requests(&self, prompts: Vec<String>) -> Result<Vec<ChatMessage>> {
+        let limit = prompts.len();
+        Ok(self.send_batch(prompts, limit).await.into_iter().filter_map(Result::ok).collect())
+    }
+
+    /// Send prompts as separate answer turns, at most `limit` at a time; results keep the order of the prompts
+    async fn send_batch(&self, prompts: Vec<String>, limit: usize) -> Vec<Result<ChatMessage>> {
+        let bar = progress::batch_bar(prompts.len() as u64, self.show_progress());
+        let results = stream::iter(prompts).map(|text| {
+            let agent_clone = self.clone_for_batch(text);
+            let bar = bar.clone();
+            tokio::spawn(async move {
+                let result = agent_clone.send_request(turns::Turn::Answer, None).await;
+                bar.inc(1);
+                result
+            })
+        }).buffered(limit.max(1)).map(|task| task.map_err(anyhow::Error::from).and_then(|result| result)).collect().await;
+        bar.finish_and_clear();
+        results
+    }
+
+    fn clone_for_batch(&self, user_input: String) -> Self {
+        let mut cloned = Agent::new(String::new(), self.provider.clone());
+        // Share the connection pool so parallel requests reuse warm connections
+        cloned.client = self.client.clone();
+        cloned.tool_ctx.network = self.tool_ctx.network.clone();
+        cloned.keys = Arc::clone(&self.keys);
+        cloned.auth = self.auth.clone();
+        cloned.offline = self.offline;
+        cloned.max_tokens = self.max_tokens;
+        cloned.temperature = self.temperature;
+        cloned.retry_count = self.retry_count;
+        cloned.backoff_base = self.backoff_base;
+        // Side requests of a turn, such as the risk judge, retry out of the turn's budget
+        cloned.retries = Arc::clone(&self.retries);
+        cloned.lint_rust = self.lint_rust;
+        // Batch API bodies and concurrent prompts have no terminal to stream to
+        cloned.streaming = false;
+        // Where each request went is logged rather than printed between progress bar redraws
+        cloned.quiet = true;
+        cloned.reasoning = self.reasoning.clone();
+        cloned.lang = self.lang.clone();
+        cloned.api_versions = self.api_versions.clone();
+        cloned.base_url = self.base_url.clone();
+        cloned.model = self.model.clone();
+        cloned.stats = Arc::clone(&self.stats);
+        cloned.conversation = vec![self.conversation[0].clone(), ChatMessage { role: "user".into(), content: user_input, name: None, reasoning: None, function_call: None, files: Vec::new(), response_id: None }].into();
+        cloned.functions = Arc::clone(&self.functions);
+        cloned
+    }
+
+    /// Scan outbound messages added since the last check; in block mode flagged content needs the user's approval
+    fn outbound_allowed(&mut self) -> Result<bool> {
+        let end = self.conversation.len();
+        let findings: Vec<_> = match &self.guardrails {
+            // Local models never leave the machine, and neither do prompts routed to one
+            Some(guard) if self.provider != "ollama" && self.privacy.as_ref().is_none_or(|r| r.private_reason(&self.conversation).is_none()) => self.conversation[self.guard_checked..].iter()
+                .filter(|m| m.role != "assistant")
+                .flat_map(|m| guard.scan(&m.content))
+                .collect(),
+            _ => Vec::new(),
+        };
+        if !findings.is_empty() {
+            let label = self.tool_ctx.theme.paint(theme::Role::Error, "guardrail");
+            for f in &findings {
+                eprintln!("{}: outbound prompt contains {} ({})", label, f.rule, f.excerpt);
+            }
+            let block = self.guardrails.as_ref().is_some_and(|g| g.block);
+            if block && !self.tool_ctx.confirm(&format!("Send it to {} anyway?", self.provider))? {
+                return Ok(false);
+            }
+        }
+        self.guard_checked = end;
+        Ok(true)
+    }
+
+    /// Append an assistant reply to the transcript, dropping its reasoning unless `persist_thinking` is
+    /// set or the provider needs it back with the function call
+    fn push_reply(&mut self, mut reply: ChatMessage) {
+        let replayed = reply.function_call.is_some() && self.backend.replays_reasoning();
+        if !self.reasoning.persist && !replayed {
+            reply.reasoning = None;
+        }
+        self.conversation.push(reply);
+    }
+
+    /// Request a reply; when sources were retrieved for this turn, an answer without citations is sent back once
+    async fn request_cited(&mut self) -> Result<ChatMessage> {
+        let mut reply = self.request_tools().await?;
+        if self.sources.has_fresh() && self.sources.cited(&reply.content).is_empty() {
+            info!("answer cites none of the retrieved sources; asking for citations");
+            self.push_reply(reply);
+            self.conversation.push(ChatMessage {
+                role: "user".into(),
+                content: "Resend your previous answer with inline citations like [1] for every retrieved source it relies on. If it relies on none, resend it unchanged.".into(),
+                name: None,
+                reasoning: None,
+                function_call: None,
+                files: Vec::new(),
+                response_id: None,
+            });
+            reply = self.request_linted(turns::Turn::Answer).await?;
+        }
+        self.sources.settle();
+        Ok(reply)
+    }
+
+    /// Request a reply, running the tools the model calls and sending their output back until it
+    /// answers; after `max_tool_rounds` calls it is told to answer with what it has
+    async fn request_tools(&mut self) -> Result<ChatMessage> {
+        const DEFAULT_MAX_ROUNDS: usize = 10;
+        let max_rounds = self.config.max_tool_rounds.unwrap_or(DEFAULT_MAX_ROUNDS);
+        let mut reply = self.request_linted(turns::Turn::Tools).await?;
+        for _ in 0..max_rounds {
+            let Some(call) = reply.function_call.clone() else { return Ok(reply) };
+            self.push_reply(reply);
+            let output = match serde_json::from_str(&call.arguments) {
+                Ok(args) => self.execute_tool(&call.name, args).await,
+                Err(err) => Err(anyhow::anyhow!("Arguments for `{}` are not valid JSON: {}", call.name, err)),
+            };
+            self.conversation.push(ChatMessage {
+                role: "function".into(),
+                content: output.unwrap_or_else(|err| format!("Error: {:#}", err)),
+                name: Some(call.name),
+                reasoning: None,
+                function_call: None,
+                files: Vec::new(),
+                response_id: None,
+            });
+            if !self.outbound_allowed()? {
+                let last = self.conversation.len() - 1;
+                self.conversation.make_mut()[last].content = "The output was withheld by the user's outbound guardrails.".into();
+                self.guard_checked = self.conversation.len();
+            }
+            reply = self.request_linted(turns::Turn::Tools).await?;
+        }
+        if reply.function_call.is_none() {
+            return Ok(reply);
+        }
+        warn!("the model kept calling tools after {} rounds; asking it to answer", max_rounds);
+        self.push_reply(reply);
+        self.conversation.push(ChatMessage {
+            role: "user".into(),
+            content: "Stop calling tools and answer with what you have.".into(),
+            name: None,
+            reasoning: None,
+            function_call: None,
+            files: Vec::new(),
+            response_id: None,
+        });
+        self.request_linted(turns::Turn::Answer).await
+    }
+
+    /// Run a tool the model called and return the output to send back; retrieved material comes
+    /// back as a numbered source it can cite
+    async fn execute_tool(&mut self, name: &str, args: serde_json::Value) -> Result<String> {
+        if self.tool_ctx.cancel.is_cancelled() {
+            anyhow::bail!("The turn was cancelled before `{}` ran", name);
+        }
+        if !self.functions.iter().any(|f| f.name == name) {
+            let offered = self.functions.iter().map(|f| f.name.as_str());
+            anyhow::bail!("Tool `{}` is not available in this session.{}", name, tools::hints::did_you_mean(name, offered));
+        }
+        // Canaries only appear in system prompts; one in a call means the prompt is being sent out
+        if let Some(token) = canary::find(&args.to_string()) {
+            warn!("blocked {}: its arguments carry the canary token {} from the system prompt, a sign of exfiltration", name, token);
+            anyhow::bail!("Blocked `{}`: its arguments contain the confidential session reference, which must not leave the conversation", name);
+        }
+        if let Some(tool) = self.custom_tools.iter().find(|t| t.definition().name == name).cloned() {
+            self.tool_ctx.caution = self.untrusted.check(&args, true);
+            let paused = self.pause_for_tool(&format!("{}({})", name, args));
+            let result = self.run_custom(tool.as_ref(), args).await;
+            self.resume_after_tool(paused);
+            self.tool_ctx.caution = None;
+            let output = result?;
+            report_canary(name, &output);
+            let output = match tool.untrusted() {
+                true => self.untrusted.frame(&format!("tool {}", name), &output),
+                false => output,
+            };
+            return Ok(self.offload(output));
+        }
+        let call = self.roots.expand(tools::ToolCall::parse(name, args)?)?;
+        // Following a link from a fetched page is how pages are read, not acting on them
+        self.tool_ctx.caution = self.untrusted.check(&call.args(), !matches!(call, tools::ToolCall::FetchUrl { .. }));
+        if let tools::ToolCall::RunCommand { command, .. } = &call {
+            if self.tool_ctx.caution.as_ref().is_none_or(|c| c.action != policy::Action::Deny) {
+                self.tool_ctx.caution = risk::caution(self, command).await.or(self.tool_ctx.caution.take());
+            }
+        }
+        let paused = self.pause_for_tool(&call);
+        let result = self.run_tool(&call).await;
+        self.resume_after_tool(paused);
+        self.tool_ctx.caution = None;
+        let output = result.map_err(|err| self.path_hints(&call, err))?;
+        report_canary(name, &output);
+        let output = self.cite(&call, &output).unwrap_or(output);
+        Ok(self.offload(output))
+    }
+
+    /// Add where the model may have meant its paths to point to an error about a missing file
+    fn path_hints(&self, call: &tools::ToolCall, err: anyhow::Error) -> anyhow::Error {
+        if !tools::hints::not_found(&err) {
+            return err;
+        }
+        let root = self.tool_ctx.dir();
+        let hints: Vec<String> = call.paths().into_iter().filter_map(|path| tools::hints::missing_path(&root, path)).collect();
+        if hints.is_empty() {
+            return err;
+        }
+        anyhow::anyhow!("{:#}\n{}", err, hints.join("\n"))
+    }
+
+    /// Store an output longer than `artifact_threshold` and give the model its start and end instead
+    fn offload(&self, output: String) -> String {
+        let threshold = self.config.artifact_threshold.unwrap_or(artifacts::DEFAULT_THRESHOLD);
+        if threshold == 0 || output.len() <= threshold {
+            return output;
+        }
+        match artifacts::store(&output) {
+            Ok(id) => artifacts::preview(&id, &output),
+            Err(err) => {
+                warn!("keeping a {}-byte tool output in the conversation: {:#}", output.len(), err);
+                output
+            }
+        }
+    }
+
+    /// Clear the request's spinner and any streamed text before a tool runs, so tool output and
+    /// approval prompts get lines of their own; returns whether a spinner was stopped
+    fn pause_for_tool(&mut self, call: &dyn std::fmt::Display) -> bool {
+        let paused = self.waiting.take().map(|spinner| spinner.finish_and_clear()).is_some();
+        if let Some(mut shown) = self.live.as_ref().map(|l| l.lock().unwrap()).filter(|s| !s.is_empty()) {
+            println!();
+            shown.clear();
+        }
+        if !self.quiet && !self.a11y {
+            println!("{}", self.tool_ctx.theme.paint(theme::Role::Tool, &format!("[tool] {}", call)));
+        }
+        paused
+    }
+
+    fn resume_after_tool(&mut self, paused: bool) {
+        if paused {
+            self.waiting = Some(progress::spinner(self.lang.msg(i18n::Msg::Generating), self.show_progress()));
+        }
+    }
+
+    /// Run a tool registered through `AgentBuilder::tool`; it is counted in the usage stats but,
+    /// having no `ToolCall`, left out of the session's tool log
+    async fn run_custom(&mut self, tool: &dyn tools::Tool, args: serde_json::Value) -> Result<String> {
+        let name = tool.definition().name;
+        if self.a11y {
+            println!("Running tool {} with {}.", name, describe_args(&args));
+        }
+        let started = Instant::now();
+        let result = tools::execute_custom(&mut self.tool_ctx, tool, args).await;
+        self.record_tool(&name, started, false, &result);
+        self.emit(events::AgentEvent::ToolFinished {
+            name,
+            ok: result.is_ok(),
+            output: match &result { Ok(out) => out.clone(), Err(err) => format!("{:#}", err) },
+        });
+        self.checkpoint();
+        result
+    }
+
+    /// Count a finished call in the usage stats, timing it only when no approval prompt was in
+    /// the way, and warn once when most of a tool's calls run into its time limit
+    fn record_tool(&self, name: &str, started: Instant, asked: bool, result: &Result<String>) {
+        if result.as_ref().is_err_and(|err| err.downcast_ref::<tools::Refused>().is_some()) {
+            return;
+        }
+        let outcome = match result {
+            _ if tools::timed_out(result) => stats::ToolOutcome::TimedOut,
+            Ok(_) => stats::ToolOutcome::Ok,
+            Err(_) => stats::ToolOutcome::Failed,
+        };
+        let elapsed = (!asked).then(|| started.elapsed());
+        let mut stats = self.stats.lock().unwrap();
+        let usage = stats.record_tool(name, elapsed, outcome);
+        if usage.timeouts >= 2 && usage.timeouts * 2 >= usage.calls && !usage.warned {
+            usage.warned = true;
+            let hint = if name.contains("__") {
+                "raise `timeout_secs` for its MCP server if it is just slow"
+            } else {
+                "raise `command_timeout_secs` if the work is just slow"
+            };
+            warn!("tool {} timed out in {} of {} calls; check your environment or {}", name, usage.timeouts, usage.calls, hint);
+        }
+    }
+
+    /// Execute a validated call with progress, events, plan tracking and the session's tool log
+    async fn run_tool(&mut self, call: &tools::ToolCall) -> Result<String> {
+        let name = call.name();
+        debug!("running tool {}", call);
+        if self.a11y {
+            println!("Running tool {} with {}.", name, describe_args(&call.args()));
+        }
+        let spinner = progress::spinner(name, self.show_progress() && !tools::INTERACTIVE.contains(&name) && !self.tool_ctx.will_ask(call));
+        self.emit(events::AgentEvent::ToolStarted { call: call.clone() });
+        let dir = self.tool_ctx.dir();
+        let effect = call.simulate(&dir, self.tool_ctx.package.as_deref());
+        if !effect.read_only {
+            for file in effect.files {
+                self.journal.save(dir.join(file));
+            }
+        }
+        let asked = self.tool_ctx.will_ask(call);
+        let started = Instant::now();
+        let prefetched = match effect.read_only {
+            true => self.prefetch.take(call).await,
+            false => {
+                self.prefetch.clear();
+                None
+            }
+        };
+        let result = match prefetched {
+            Some(result) => result,
+            None => tools::execute(&mut self.tool_ctx, call).await,
+        };
+        spinner.finish_and_clear();
+        self.record_tool(name, started, asked, &result);
+        self.emit(events::AgentEvent::ToolFinished {
+            name: name.into(),
+            ok: result.is_ok(),
+            output: match &result { Ok(out) => out.clone(), Err(err) => format!("{:#}", err) },
+        });
+        if result.is_ok() {
+            self.plan_progress(call);
+        }
+        self.tool_log.push(session::ToolCall {
+            call: call.clone(),
+            ok: result.is_ok(),
+            output: match &result { Ok(out) => out.clone(), Err(err) => format!("{:#}", err) },
+            at: stats::now_secs(),
+            turn: self.conversation.total_len(),
+        });
+        self.checkpoint();
+        result
+    }
+
+    /// Register retrieved material as numbered sources, stubbing earlier unchanged copies;
+    /// `None` when the call retrieved nothing citable
+    fn cite(&mut self, call: &tools::ToolCall, output: &str) -> Option<String> {
+        let mut blocks = Vec::new();
+        for (location, content) in citations::retrieved(call, output) {
+            let framed;
+            let content = match call {
+                tools::ToolCall::FetchUrl { .. } => {
+                    framed = self.untrusted.frame(&location, content);
+                    framed.as_str()
+                }
+                _ => content,
+            };
+            let unchanged = self.sources.unchanged(&location, content);
+            blocks.push(self.sources.add(location, content));
+            self.stub_sources(&unchanged);
+        }
+        (!blocks.is_empty()).then(|| blocks.join("\n\n"))
+    }
+
+    /// Request a reply with its untagged code fences tagged; with LINT_RUST enabled, Rust blocks
+    /// that fail to parse are sent back for a fix
+    async fn request_linted(&mut self, turn: turns::Turn) -> Result<ChatMessage> {
+        const MAX_FIX_ATTEMPTS: usize = 2;
+        let mut reply = self.send_fitted(turn).await?;
+        reply.content = fences::tag(&reply.content);
+        if !self.lint_rust {
+            return Ok(reply);
+        }
+        for _ in 0..MAX_FIX_ATTEMPTS {
+            let errors = lint::check_reply(&reply.content);
+            if errors.is_empty() {
+                break;
+            }
+            info!("{}", self.lang.msg(i18n::Msg::LintFixing));
+            self.push_reply(reply);
+            self.conversation.push(ChatMessage {
+                role: "user".into(),
+                content: format!("The Rust code in your previous answer does not parse:\n{}\nPlease resend the answer with the syntax fixed.", errors.join("\n")),
+                name: None,
+                reasoning: None,
+                function_call: None,
+                files: Vec::new(),
+                response_id: None,
+            });
+            reply = self.send_fitted(turns::Turn::Answer).await?;
+            reply.content = fences::tag(&reply.content);
+        }
+        let edition = toolchain::Toolchain::detect(&self.tool_ctx.dir()).and_then(|toolchain| toolchain.edition);
+        for block in lint::extract_rust_blocks(&reply.content) {
+            if lint::syntax_error(&block).is_none() && lint::rustfmt_check(&block, edition.as_deref().unwrap_or("2021")).is_some() {
+                info!("{}", self.lang.msg(i18n::Msg::LintNotFormatted));
+            }
+        }
+        Ok(reply)
+    }
+
+    /// What a tool invocation typed in the REPL would do, without running it
+    fn simulate(&self, invocation: &str) -> Result<String> {
+        let (name, args) = self.parse_tool_invocation(invocation).context("Expected a tool invocation such as write_file(\"notes.md\", \"...\")")?;
+        if let Some(tool) = self.custom_tools.iter().find(|t| t.definition().name == name) {
+            return Ok(tool.simulate(&args).unwrap_or_else(|| format!("Calls {}({})", name, args)));
+        }
+        Ok(self.tool_ctx.effect(&tools::ToolCall::parse(&name, args)?).to_string())
+    }
+
+    /// Parse an explicit invocation such as `read_file("src/main.rs")` into a tool name and arguments
+    fn parse_tool_invocation(&self, input: &str) -> Option<(String, serde_json::Value)> {
+        let (name, rest) = input.split_once('(')?;
+        let inner = rest.trim_end().strip_suffix(')')?;
+        let func = self.functions.iter().find(|f| f.name == name.trim())?;
+        let values: Vec<serde_json::Value> = serde_json::from_str(&format!("[{}]", inner)).ok()?;
+        // Positional arguments fill required parameters first, then the optional ones
+        let required: Vec<String> = func.parameters["required"].as_array()?.iter()
+            .filter_map(|p| p.as_str().map(String::from))
+            .collect();
+        let optional = func.parameters["properties"].as_object()?.keys()
+            .filter(|k| !required.contains(k))
+            .cloned()
+            .collect::<Vec<_>>();
+        let args: serde_json::Map<_, _> = required.into_iter().chain(optional).zip(values).collect();
+        Some((func.name.clone(), serde_json::Value::Object(args)))
+    }
+
+    /// `/plan` shows the active plan, `/plan done <n>` checks a step off, `/plan <task>` makes a new one
+    async fn plan_command(&mut self, arg: &str) -> Result<()> {
+        if let Some(number) = arg.strip_prefix("done ") {
+            let plan = self.plan.as_mut().context("No active plan")?;
+            plan.mark_done(number.trim().parse().context("Expected a step number")?)?;
+            self.save_plan();
+        } else if !arg.is_empty() {
+            let spinner = progress::spinner(self.lang.msg(i18n::Msg::Generating), self.show_progress());
+            let made = self.make_plan(arg).await;
+            spinner.finish_and_clear();
+            if made?.is_none() {
+                return Ok(());
+            }
+        }
+        let plan = self.plan.as_ref().context("No active plan; start one with `/plan <task>`")?;
+        pager::show(&self.tool_ctx.theme.reply(&plan.render()));
+        Ok(())
+    }
+
+    /// `/help`, `/model`, `/provider`, `/tools`, `/clear`, `/retry`, `/context`, `/upload`, `/template`, `/sessions` and `/init`; `false` when `input` is none of them
+    async fn session_command(&mut self, input: &str) -> Result<bool> {
+        let (command, arg) = input.split_once(' ').map_or((input, ""), |(c, a)| (c, a.trim()));
+        match (command, arg) {
+            ("/help", _) => {
+                let other = [
+                    ("!<command>", "", "Run a shell command; !! also adds its output to the conversation"),
+                    ("<tool>(args)", "", "Run a tool yourself, e.g. read_file(\"src/main.rs\")"),
+                ];
+                for (name, args, about) in repl::COMMANDS.iter().chain(&other) {
+                    println!("  {:<32} {}", format!("{} {}", name, args), about);
+                }
+                println!("End a first line with \\ to continue the prompt until a blank line; ``` fences continue until closed.");
+            }
+            ("/howdoi", "") => println!("Ask about a command, setting or feature, e.g. `/howdoi limit spend per turn`"),
+            ("/howdoi", question) => println!("{}", howdoi::answer(question)),
+            ("/model", "") => println!("{} on {}", self.model_name(), self.provider),
+            ("/model", model) => {
+                self.model = Some(model.to_string());
+                println!("Now using {} on {}", model, self.provider);
+            }
+            ("/tag", "") => {
+                let tags = self.tags();
+                if tags.is_empty() {
+                    println!("No tags; `/tag NAME=VALUE` attributes the spend that follows to one");
+                }
+                for (name, value) in tags {
+                    println!("  {}={}", name, value);
+                }
+            }
+            ("/tag", tag) => match tag.strip_prefix('-') {
+                Some(name) => {
+                    self.untag(name);
+                    println!("Spend is no longer tagged {}", name);
+                }
+                None => {
+                    let (name, value) = stats::parse_tag(tag)?;
+                    println!("Spend from now on is tagged {}={}", name, value);
+                    self.tag(name, value);
+                }
+            },
+            ("/provider", "") => println!("{} ({})", self.provider, self.model_name()),
+            ("/provider", provider) => {
+                self.switch_provider(provider)?;
+                println!("Now using {} on {}", self.model_name(), self.provider);
+            }
+            ("/race", "") => match &self.race {
+                Some(race) => println!("Racing {} against {}", self.provider, race.provider),
+                None => println!("Not racing; `/race on` races the `[race]` provider, `/race <provider>` another"),
+            },
+            ("/race", "off") => {
+                self.race = None;
+                println!("Requests go to {} alone", self.provider);
+            }
+            ("/race", rival) => {
+                let race = match (rival, &self.config.race) {
+                    ("on", Some(race)) => race.clone(),
+                    ("on", None) => bail!("No `[race]` table in the config; name the provider, e.g. `/race claude`"),
+                    (provider, _) if config::PROVIDERS.contains(&provider) => race::RaceConfig { provider: provider.into(), ..Default::default() },
+                    (provider, _) => bail!("Unknown provider `{}`; expected one of {}", provider, config::PROVIDERS.join(", ")),
+                };
+                println!("Racing {} against {}; answers are shown once complete", self.provider, race.provider);
+                self.race = Some(Arc::new(race));
+            }
+            ("/tools", _) => {
+                for function in self.functions.iter() {
+                    println!("  {:<24} {}", function.name, function.description.lines().next().unwrap_or_default());
+                }
+            }
+            ("/clear", _) => {
+                self.clear();
+                println!("Cleared the conversation");
+            }
+            ("/retry", _) => {
+                let prompt = self.rewind()?;
+                self.ask(&prompt).await?;
+            }
+            ("/context", "") => self.show_context()?,
+            ("/context", arg) => match arg.strip_prefix("drop ") {
+                Some(items) => self.drop_context(items)?,
+                None => bail!("Usage: /context [drop <n>|<from>-<to>|u<n> ...]"),
+            },
+            ("/template", "") => {
+                let found = templates::list();
+                if found.is_empty() {
+                    println!("No templates; add them to .rustaigent/templates/ or ~/.config/rustaigent/templates/");
+                }
+                for (name, description, _) in found {
+                    println!("  {:<24} {}", name, description.unwrap_or_default());
+                }
+            }
+            ("/template", name) => self.apply_template(name).await?,
+            ("/sessions", "") => {
+                let saved = autosave::list();
+                if saved.is_empty() {
+                    println!("No saved sessions yet");
+                }
+                for (_, bundle) in saved.iter().take(20) {
+                    let when = chrono::DateTime::from_timestamp(bundle.exported as i64, 0).map(|at| at.format("%Y-%m-%d %H:%M").to_string()).unwrap_or_default();
+                    let first = bundle.messages.iter().find(|m| m.role == "user").map(|m| m.content.lines().next().unwrap_or_default()).unwrap_or_default();
+                    let first: String = first.chars().take(48).collect();
+                    println!("  {:<24} {}  {:<10} {:>4} msgs  {}", bundle.id, when, bundle.provider, bundle.messages.len(), first);
+                }
+                if saved.len() > 20 {
+                    println!("  ... and {} older", saved.len() - 20);
+                }
+            }
+            ("/sessions", id) => {
+                let (_, bundle) = autosave::list().into_iter().find(|(_, bundle)| bundle.id.starts_with(id)).with_context(|| format!("No saved session `{}`; /sessions lists them", id))?;
+                replay::run(&bundle, &self.tool_ctx.theme)?;
+            }
+            ("/init", _) => {
+                init::run().await?;
+                let config = config::Config::load();
+                self.switch_provider(config.provider.as_deref().unwrap_or("openai"))?;
+                self.model = config.model;
+                println!("Now using {} on {}", self.model_name(), self.provider);
+            }
+            ("/upload", "") => bail!("Usage: /upload <path>"),
+            ("/upload", path) => {
+                let file = self.upload(std::path::Path::new(path)).await?;
+                println!("Uploaded {} as {}; it goes with your next prompt", file.name, file.id);
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Seed the conversation from the template called `name`: its prompt joins the system
+    /// prompt, its tools run now as if the model had called them, and its messages are added as
+    /// written. Tool runs go through the policy like any other call.
+    pub async fn apply_template(&mut self, name: &str) -> Result<()> {
+        let template = templates::load(name)?;
+        if let Some(prompt) = &template.prompt {
+            self.conversation.make_mut()[0].content.push_str(&format!("\n\n{}", prompt.trim()));
+        }
+        let start = self.conversation.len();
+        let (mut runs, mut failed) = (0, 0);
+        for seed in template.seeds {
+            match seed {
+                templates::Seed::Tool { name, args } => {
+                    let output = self.execute_tool(&name, args.clone()).await.unwrap_or_else(|err| {
+                        failed += 1;
+                        format!("Error: {:#}", err)
+                    });
+                    runs += 1;
+                    let call = FunctionCall { name: name.clone(), arguments: args.to_string(), call_id: None };
+                    self.conversation.push(ChatMessage { role: "assistant".into(), content: String::new(), name: None, reasoning: None, function_call: Some(call), files: Vec::new(), response_id: None });
+                    self.conversation.push(ChatMessage { role: "function".into(), content: output, name: Some(name), reasoning: None, function_call: None, files: Vec::new(), response_id: None });
+                }
+                templates::Seed::Message { role, content } => {
+                    self.conversation.push(ChatMessage { role, content, name: None, reasoning: None, function_call: None, files: Vec::new(), response_id: None });
+                }
+            }
+        }
+        let tokens: u32 = self.conversation.iter().skip(start).map(context::estimate).sum();
+        let messages = self.conversation.len() - start;
+        let failures = if failed > 0 { format!(", {} failed", failed) } else { String::new() };
+        self.report(format!("[template] {}: {} message(s) from {} tool run(s){}, ~{} tokens", template.name, messages, runs, failures, tokens));
+        self.checkpoint();
+        Ok(())
+    }
+
+    /// `/context`: the prompt against its budget, then each item in it with its share
+    fn show_context(&self) -> Result<()> {
+        let (used, budget) = self.context_usage(turns::Turn::Tools);
+        let strategy = self.config.context.as_ref().and_then(|c| c.strategy.clone()).unwrap_or_else(|| "drop".into());
+        let tools = used.saturating_sub(context::estimate_prompt(&self.conversation, None));
+        let mut out = format!("~{} of {} prompt tokens ({:.0}%); {} messages in the prompt, {} moved out (strategy: {})\n\n",
+            used, budget, used as f64 * 100.0 / budget.max(1) as f64, self.conversation.len(), self.conversation.total_len() - self.conversation.len(), strategy);
+        out.push_str(&context::inspect(&self.conversation, tools, &self.attachments, !self.a11y));
+        out.push_str("\nDrop items with /context drop <n>, a range like 3-5, or u<n> for an upload not sent yet.\n");
+        if self.conversation.iter().any(|m| m.response_id.is_some()) {
+            out.push_str("The provider also keeps the turns up to its last stored response; dropping them shortens only a restarted chain.\n");
+        }
+        pager::show(&out);
+        Ok(())
+    }
+
+    /// `/context drop`: take messages out of the conversation by index or range, and uploads out
+    /// of the next prompt. A tool call and its output go together, and the system prompt stays.
+    fn drop_context(&mut self, items: &str) -> Result<()> {
+        let mut indices = std::collections::BTreeSet::new();
+        let mut uploads = std::collections::BTreeSet::new();
+        for item in items.split([' ', ',']).filter(|i| !i.is_empty()) {
+            if let Some(n) = item.strip_prefix('u') {
+                let n: usize = n.parse().with_context(|| format!("`{}` is not an upload like u1", item))?;
+                if n == 0 || n > self.attachments.len() {
+                    bail!("No upload u{}; /context lists them", n);
+                }
+                uploads.insert(n - 1);
+                continue;
+            }
+            let (from, to) = item.split_once('-').unwrap_or((item, item));
+            let (from, to): (usize, usize) = (from.parse()?, to.parse()?);
+            if from == 0 {
+                bail!("The system prompt stays; /clear starts over");
+            }
+            if from > to || to >= self.conversation.len() {
+                bail!("No messages {}; /context numbers them 1 to {}", item, self.conversation.len() - 1);
+            }
+            indices.extend(from..=to);
+        }
+        let paired: Vec<usize> = indices.iter().flat_map(|&i| {
+            let messages = &self.conversation;
+            let output = (messages[i].function_call.is_some() && messages.get(i + 1).is_some_and(|m| m.role == "function")).then_some(i + 1);
+            let call = (messages[i].role == "function" && messages[i - 1].function_call.is_some()).then_some(i - 1);
+            output.into_iter().chain(call)
+        }).collect();
+        let extra = paired.iter().filter(|i| indices.insert(**i)).count();
+        let tokens: u32 = indices.iter().map(|&i| context::estimate(&self.conversation[i])).sum();
+        for &i in indices.iter().rev() {
+            self.conversation.make_mut().remove(i);
+        }
+        self.guard_checked -= indices.iter().filter(|&&i| i < self.guard_checked).count();
+        self.last_prompt = self.last_prompt.take().filter(|(start, _)| indices.iter().all(|i| i < start))
+            .map(|(start, prompt)| (start - indices.len(), prompt));
+        for &n in uploads.iter().rev() {
+            self.attachments.remove(n);
+        }
+        if !indices.is_empty() {
+            let pairs = if extra > 0 { format!(", {} of them to keep tool calls with their outputs", extra) } else { String::new() };
+            println!("Dropped {} message(s), ~{} tokens{}", indices.len(), tokens, pairs);
+        }
+        if !uploads.is_empty() {
+            println!("Dropped {} upload(s) from the next prompt", uploads.len());
+        }
+        self.checkpoint();
+        Ok(())
+    }
+
+    /// Talk to another provider for the rest of the session, with its key and default model
+    fn switch_provider(&mut self, provider: &str) -> Result<()> {
+        if !config::PROVIDERS.contains(&provider) {
+            bail!("Unknown provider `{}`; expected one of {}", provider, config::PROVIDERS.join(", "));
+        }
+        let backend = backend(provider, &self.config);
+        let auth = auth::for_provider(&self.config, provider, &self.client);
+        let key = builder::api_key(provider).or_else(|| auth.is_some().then(String::new)).with_context(|| {
+            format!("No key for {}; set {} or run `rustaigent init`", provider, backend.key_var().unwrap_or("OPENAI_API_KEY"))
+        })?;
+        self.keys = Arc::new(keys::KeyPool::new(&key, keys::KeyStrategy::parse(&env::var("KEY_STRATEGY").unwrap_or_default())));
+        self.auth = auth;
+        self.backend = backend;
+        self.provider = provider.to_string();
+        self.model = None;
+        self.privacy = privacy::Router::from_config(self.config.privacy.as_ref(), provider)?.map(Arc::new);
+        self.offline = false;
+        if !self.backend.supports_tools() {
+            warn!("tools are unavailable with provider `{}`", provider);
+        }
+        Ok(())
+    }
+
+    /// Start over with only the system prompt, forgetting sources, attachments and untrusted material
+    fn clear(&mut self) {
+        let system = self.conversation[0].clone();
+        self.conversation = vec![system].into();
+        self.guard_checked = self.guard_checked.min(1);
+        self.sources = citations::Sources::default();
+        self.last_prompt = None;
+        self.attachments.clear();
+        self.untrusted.clear();
+    }
+
+    /// Drop the latest prompt's turn from the conversation and return the prompt
+    fn rewind(&mut self) -> Result<String> {
+        let (start, prompt) = self.last_prompt.take().context("Nothing to retry yet")?;
+        let paged_out = self.conversation.total_len() - self.conversation.len();
+        match start.checked_sub(paged_out).filter(|&keep| keep >= 1) {
+            Some(keep) => {
+                self.conversation.truncate(keep);
+                self.guard_checked = self.guard_checked.min(keep);
+                Ok(prompt)
+            }
+            None => bail!("The last turn has been paged out of memory; send the prompt again instead"),
+        }
+    }
+
+    /// `!cmd` runs a shell command on the terminal without involving the model; `!!cmd` also adds
+    /// its output to the conversation for the next question
+    fn shell(&mut self, input: &str) -> Result<()> {
+        let (command, share) = match input.strip_prefix('!') {
+            Some(command) => (command.trim(), true),
+            None => (input.trim(), false),
+        };
+        if command.is_empty() {
+            anyhow::bail!("Expected a command after `!`");
+        }
+        let mut shell = std::process::Command::new("sh");
+        shell.arg("-c").arg(command);
+        if !share {
+            let status = shell.status().with_context(|| format!("Failed to run `{}`", command))?;
+            if !status.success() {
+                eprintln!("`{}` {}", command, status);
+            }
+            return Ok(());
+        }
+        let out = shell.output().with_context(|| format!("Failed to run `{}`", command))?;
+        let text = format!("{}{}", String::from_utf8_lossy(&out.stdout), String::from_utf8_lossy(&out.stderr));
+        pager::show(text.trim_end());
+        self.conversation.push(ChatMessage {
+            role: "user".into(),
+            content: format!("Output of `{}` ({}):\n```\n{}\n```", command, out.status, text.trim_end()),
+            name: None,
+            reasoning: None,
+            function_call: None,
+            files: Vec::new(),
+            response_id: None,
+        });
+        if !self.quiet {
+            println!("{}", self.tool_ctx.theme.paint(theme::Role::Tool, "Added the output to the conversation"));
+        }
+        Ok(())
+    }
+
+    /// Interactive loop: explicit tool invocations run locally, everything else goes to the model
+    pub async fn run(&mut self) -> Result<()> {
+        let mut reader = repl::LineReader::new()?;
+        if self.offline {
+            println!("Offline: no API key found. Tools you run yourself, such as read_file(\"src/main.rs\"), !commands, /howdoi and /sessions work without one.");
+            println!("{}", OFFLINE);
+        }
+        loop {
+            let (mut prompt, mut styled) = (String::new(), String::new());
+            if !self.quiet {
+                let cost = self.stats.lock().unwrap().cost();
+                // The running cost, once there is one; local models stay free and unlabeled
+                if cost > 0.0 {
+                    let label = format!("[${:.4}] ", cost);
+                    styled.push_str(&self.tool_ctx.theme.paint(theme::Role::Thinking, &label));
+                    prompt.push_str(&label);
+                }
+                let you = self.lang.msg(i18n::Msg::You);
+                styled.push_str(&self.tool_ctx.theme.paint(theme::Role::User, you));
+                prompt.push_str(you);
+            }
+            let Some(line) = reader.read(&prompt, &styled)? else {
+                break;
+            };
+            let input = line.trim();
+            match input {
+                "" => continue,
+                "exit" | "quit" => break,
+                _ => {}
+            }
+            // A number picks one of the follow-ups offered under the last answer
+            let picked = input.parse::<usize>().ok().and_then(|n| n.checked_sub(1)).and_then(|i| self.suggestions.get(i).cloned());
+            self.suggestions.clear();
+            if let Some(suggestion) = &picked {
+                println!("> {}", suggestion);
+            }
+            let input = picked.as_deref().unwrap_or(input);
+
+            if let Some(path) = input.strip_prefix("/export ") {
+                match self.export(std::path::Path::new(path.trim())) {
+                    Ok(()) => println!("Exported session to {}", path.trim()),
+                    Err(err) => eprintln!("{}: {:#}", self.tool_ctx.theme.paint(theme::Role::Error, self.lang.msg(i18n::Msg::Error)), err),
+                }
+                continue;
+            }
+
+            if let Some(rest) = input.strip_prefix("/plan").filter(|r| r.is_empty() || r.starts_with(' ')) {
+                if let Err(err) = self.plan_command(rest.trim()).await {
+                    eprintln!("{}: {:#}", self.tool_ctx.theme.paint(theme::Role::Error, self.lang.msg(i18n::Msg::Error)), err);
+                }
+                continue;
+            }
+
+            if let Some(draft) = input.strip_prefix("/editor").filter(|r| r.is_empty() || r.starts_with(' ')) {
+                match editor::compose(draft.trim()) {
+                    Ok(Some(prompt)) => self.ask(&prompt).await?,
+                    Ok(None) => println!("Empty prompt; nothing sent"),
+                    Err(err) => eprintln!("{}: {:#}", self.tool_ctx.theme.paint(theme::Role::Error, self.lang.msg(i18n::Msg::Error)), err),
+                }
+                continue;
+            }
+
+            if let Some(invocation) = input.strip_prefix("/simulate ") {
+                match self.simulate(invocation.trim()) {
+                    Ok(effect) => println!("{}", effect),
+                    Err(err) => eprintln!("{}: {:#}", self.tool_ctx.theme.paint(theme::Role::Error, self.lang.msg(i18n::Msg::Error)), err),
+                }
+                continue;
+            }
+
+            if input.starts_with('/') {
+                match self.session_command(input).await {
+                    Ok(true) => continue,
+                    Ok(false) => {}
+                    Err(err) => {
+                        eprintln!("{}: {:#}", self.tool_ctx.theme.paint(theme::Role::Error, self.lang.msg(i18n::Msg::Error)), err);
+                        continue;
+                    }
+                }
+            }
+
+            if input == "/cost" {
+                self.print_usage();
+                continue;
+            }
+
+            if input == "/capabilities" {
+                let model = self.model_name();
+                println!("{} on {}:\n{}", model, self.provider, self.capabilities(&model));
+                continue;
+            }
+
+            if let Some(command) = input.strip_prefix('!') {
+                if let Err(err) = self.shell(command) {
+                    eprintln!("{}: {:#}", self.tool_ctx.theme.paint(theme::Role::Error, self.lang.msg(i18n::Msg::Error)), err);
+                }
+                continue;
+            }
+
+            if let Some((name, args)) = self.parse_tool_invocation(input) {
+                let call = match tools::ToolCall::parse(&name, args).and_then(|call| self.roots.expand(call)) {
+                    Ok(call) => call,
+                    Err(err) => {
+                        eprintln!("{}: {:#}", self.tool_ctx.theme.paint(theme::Role::Error, self.lang.msg(i18n::Msg::Error)), err);
+                        continue;
+                    }
+                };
+                let name = call.name();
+                let result = self.run_tool(&call).await;
+                // Retrieved material becomes numbered context the next answer can cite
+                if let Some(content) = result.as_ref().ok().and_then(|out| self.cite(&call, out)) {
+                    self.conversation.push(ChatMessage { role: "user".into(), content, name: None, reasoning: None, function_call: None, files: Vec::new(), response_id: None });
+                }
+                let colors = &self.tool_ctx.theme;
+                let label = colors.paint(theme::Role::Tool, &format!("[{}]", self.lang.msg(i18n::Msg::ToolOutput)));
+                match (result, self.a11y) {
+                    (Ok(out), true) => {
+                        println!("Tool {} finished. Output follows.", name);
+                        println!("{}", if tools::diff::is_unified(&out) { tools::diff::linearize(&out) } else { out });
+                        println!("End of {} output.", name);
+                    }
+                    (Err(err), true) => println!("Tool {} failed: {:#}.", name, err),
+                    (Ok(out), false) if self.quiet => pager::show(&out),
+                    (Ok(out), false) => pager::show(&format!("{} {} -- {}", colors.paint(theme::Role::Assistant, "RustAIgent:"), label, colors.diff(&out))),
+                    (Err(err), false) => println!("{} {} -- {}: {:#}", colors.paint(theme::Role::Assistant, "RustAIgent:"), label, colors.paint(theme::Role::Error, self.lang.msg(i18n::Msg::Error)), err),
+                }
+                continue;
+            }
+
+            if self.offline {
+                println!("{}", OFFLINE);
+                continue;
+            }
+            self.ask(input).await?;
+        }
+        if !self.quiet && !self.stats.lock().unwrap().models.is_empty() {
+            println!("\nSession usage:");
+            self.print_usage();
+        }
+        self.save_stats();
+        Ok(())
+    }
+
+    /// Requests, tokens and cost of the session per model, batch requests included
+    fn print_usage(&self) {
+        let stats = self.stats.lock().unwrap();
+        if stats.models.is_empty() {
+            println!("No requests yet");
+            return;
+        }
+        print!("{}", stats.render());
+        if self.prefetch.hits > 0 {
+            println!("Prefetch: {} tool results were ready when asked for, saving {:.1}s", self.prefetch.hits, self.prefetch.saved.as_secs_f64());
+        }
+    }
+
+    /// One model turn without terminal output: guardrails, then the request with lint and citation passes.
+    /// `None` when the guardrails held the prompt back. A turn stopped through [`Agent::canceller`],
+    /// `turn_timeout_secs` or the turn budget fails with [`cancel::Cancelled`], leaving the
+    /// conversation and the files its tools changed as they were before it.
+    pub async fn turn(&mut self, input: &str) -> Result<Option<ChatMessage>> {
+        let token = self.cancel.begin();
+        self.tool_ctx.cancel = token.clone();
+        self.turn_spent_from = self.stats.lock().unwrap().cost();
+        let deadline = self.config.retry_deadline_secs.map_or(retry::DEFAULT_DEADLINE, Duration::from_secs);
+        let deadline = self.config.turn_timeout_secs.map_or(deadline, |secs| deadline.min(Duration::from_secs(secs)));
+        self.retries.begin(self.config.turn_retries.unwrap_or(retry::DEFAULT_RETRIES), deadline);
+        let timer = self.config.turn_timeout_secs.map(|secs| {
+            let (canceller, limit) = (self.cancel.clone(), Duration::from_secs(secs));
+            tokio::spawn(async move {
+                sleep(limit).await;
+                canceller.cancel_for(cancel::Reason::Timeout(limit));
+            })
+        });
+        let (messages, checked) = (self.conversation.len(), self.guard_checked);
+        let result = tokio::select! {
+            biased;
+            _ = token.cancelled() => None,
+            result = self.run_turn(input) => Some(result),
+        };
+        if let Some(timer) = timer {
+            timer.abort();
+        }
+        self.retries.end();
+        let result = match result {
+            Some(result) => result,
+            None => {
+                if let Some(spinner) = self.waiting.take() {
+                    spinner.finish_and_clear();
+                }
+                self.conversation.truncate(messages);
+                self.guard_checked = checked;
+                let restored = self.journal.restore();
+                debug!("cancelled turn rolled back; {} file(s) restored", restored);
+                Err(cancel::Cancelled(self.cancel.reason()).into())
+            }
+        };
+        self.journal.clear();
+        result
+    }
+
+    /// Summarize what the turn's tool calls from `first_call` on changed and ran, and note it in
+    /// the conversation unless `actions_summary = false`
+    fn record_actions(&mut self, first_call: usize) {
+        let cost = self.stats.lock().unwrap().cost() - self.turn_spent_from;
+        let calls = self.tool_log.get(first_call..).unwrap_or_default();
+        self.actions = actions::summarize(calls, &self.tool_ctx.dir(), self.tool_ctx.package.as_deref(), cost);
+        if let Some(summary) = self.actions.as_ref().filter(|_| self.config.actions_summary != Some(false)) {
+            self.conversation.push(ChatMessage { role: "system".into(), content: summary.note(), name: Some(actions::NOTE.into()), reasoning: None, function_call: None, files: Vec::new(), response_id: None });
+        }
+    }
+
+    /// Files changed and commands run by the latest turn's tool calls, with its cost; `None` when
+    /// it changed and ran nothing
+    pub fn last_actions(&self) -> Option<&actions::Actions> {
+        self.actions.as_ref()
+    }
+
+    /// Stop the turn when it has spent its budget
+    fn check_turn_budget(&self) {
+        let Some(budget) = self.turn_budget.into_iter().chain(self.config.turn_budget_usd).reduce(f64::min) else { return };
+        let spent = self.stats.lock().unwrap().cost() - self.turn_spent_from;
+        if spent >= budget {
+            self.cancel.cancel_for(cancel::Reason::Budget(spent));
+        }
+    }
+
+    /// Handle for cancelling the running turn from another task or thread
+    pub fn canceller(&self) -> cancel::Canceller {
+        self.cancel.clone()
+    }
+
+    /// USD each turn may spend before it is cancelled, on top of `turn_budget_usd` in the config
+    pub fn set_turn_budget(&mut self, usd: Option<f64>) {
+        self.turn_budget = usd;
+    }
+
+    /// Upload a file to the provider's files API and send it with the next prompt, for PDFs and
+    /// datasets too large to inline. Private paths and content are refused, and guardrail findings
+    /// in text files need the user's approval as a prompt would.
+    pub async fn upload(&mut self, path: &std::path::Path) -> Result<FileRef> {
+        // Binaries and large files are what uploads are for; build output and bundles are not
+        if let Some(skip) = self.tool_ctx.bulk.skip(&env::current_dir()?, path).filter(|s| !matches!(s, bulk::Skip::Binary | bulk::Skip::Large(_))) {
+            bail!("Not uploading {}: it is {}; list it in `include_files` to upload it anyway", path.display(), skip);
+        }
+        let bytes = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let text = std::str::from_utf8(&bytes).ok();
+        if let Some(router) = &self.privacy {
+            if let Some(private) = router.private_path(&path.to_string_lossy()) {
+                bail!("{} is on private path {} and stays on this machine", path.display(), private);
+            }
+            let probe = ChatMessage { role: "user".into(), content: text.unwrap_or_default().into(), name: None, reasoning: None, function_call: None, files: Vec::new(), response_id: None };
+            if let Some(reason) = router.private_reason(&[probe]) {
+                bail!("{} has to stay on this machine ({})", path.display(), reason);
+            }
+        }
+        if let (Some(guard), Some(text)) = (&self.guardrails, text) {
+            let findings = guard.scan(text);
+            let label = self.tool_ctx.theme.paint(theme::Role::Error, "guardrail");
+            for f in &findings {
+                eprintln!("{}: {} contains {} ({})", label, path.display(), f.rule, f.excerpt);
+            }
+            if !findings.is_empty() && guard.block && !self.tool_ctx.confirm(&format!("Upload it to {} anyway?", self.provider))? {
+                bail!("Upload of {} declined", path.display());
+            }
+        }
+        let name = path.file_name().map_or_else(|| path.display().to_string(), |n| n.to_string_lossy().into_owned());
+        let file = uploads::upload(self, &name, uploads::mime(path), bytes).await?;
+        self.attachments.push(file.clone());
+        Ok(file)
+    }
+
+    async fn run_turn(&mut self, input: &str) -> Result<Option<ChatMessage>> {
+        self.actions = None;
+        self.reload_config();
+        let changes = self.changed_diff();
+        if let Some(note) = &changes {
+            self.conversation.push(ChatMessage { role: "system".into(), content: note.clone(), name: Some(gitdiff::NOTE.into()), reasoning: None, function_call: None, files: Vec::new(), response_id: None });
+        }
+        self.refresh_focus();
+        if let Some(excerpts) = self.retrieve(input).await {
+            self.conversation.push(ChatMessage { role: "system".into(), content: excerpts, name: Some(index::NOTE.into()), reasoning: None, function_call: None, files: Vec::new(), response_id: None });
+        }
+        self.conversation.push(ChatMessage { role: "user".into(), content: input.into(), name: None, reasoning: None, function_call: None, files: self.attachments.clone(), response_id: None });
+        if !self.outbound_allowed()? {
+            self.conversation.truncate(self.guard_checked);
+            return Ok(None);
+        }
+        if let (Some(note), Some(diff)) = (changes, self.diff_context.as_mut()) {
+            diff.last = Some(note);
+        }
+        if self.config.prefetch != Some(false) {
+            let root = self.tool_ctx.dir();
+            let offered: Vec<&str> = self.functions.iter().map(|f| f.name.as_str()).collect();
+            let git_used = self.tool_log.iter().any(|entry| matches!(entry.call, tools::ToolCall::GitStatus {}));
+            let calls = prefetch::guesses(input, &root, &offered, git_used, &self.tool_ctx.bulk);
+            self.prefetch.start(calls, &self.tool_ctx);
+        }
+        let (started, first_call) = (Instant::now(), self.tool_log.len());
+        let result = self.request_cited().await;
+        self.prefetch.clear();
+        self.stats.lock().unwrap().record_task(started.elapsed());
+        let reply = result?;
+        self.attachments.clear();
+        if !self.streams(&self.turn_model(turns::Turn::Tools)) {
+            self.emit(events::AgentEvent::Token(reply.content.clone()));
+        }
+        self.emit(events::AgentEvent::Done { content: reply.content.clone() });
+        self.push_reply(reply.clone());
+        self.record_actions(first_call);
+        self.page_out();
+        self.checkpoint();
+        Ok(Some(reply))
+    }
+
+    /// Apply what changed in the config and system prompt files since the last turn. Keys in
+    /// [`reload::LIVE`] take effect and are recorded in the conversation; environment variables
+    /// still win, and any other changed key is reported as waiting for a restart.
+    fn reload_config(&mut self) {
+        let Some(watcher) = self.reload.as_mut() else { return };
+        if !watcher.changed(&self.config) {
+            return;
+        }
+        let mut fresh = match config::Layered::load().and_then(|layers| layers.config()) {
+            Ok(fresh) => fresh,
+            Err(err) => {
+                warn!("keeping the running configuration: {:#}", err);
+                return;
+            }
+        };
+        if let Ok(api) = env::var("OPENAI_API") {
+            fresh.openai_api = Some(api);
+        }
+        let keys = reload::changed_keys(&self.config, &fresh);
+        let changed = |key: &str| keys.iter().any(|k| k == key);
+        let mut applied = Vec::new();
+        for (key, var) in [("temperature", "TEMPERATURE"), ("max_tokens", "MAX_TOKENS")] {
+            if changed(key) && env::var(var).is_ok() {
+                warn!("`{}` changed, but {} overrides it", key, var);
+            }
+        }
+        if changed("temperature") && env::var("TEMPERATURE").is_err() {
+            let value = fresh.temperature.unwrap_or(0.7);
+            applied.push(format!("temperature {} -> {}", self.temperature, value));
+            self.temperature = value;
+        }
+        if changed("max_tokens") && env::var("MAX_TOKENS").is_err() {
+            let value = fresh.max_tokens.unwrap_or(1024);
+            applied.push(format!("max_tokens {} -> {}", self.max_tokens, value));
+            self.max_tokens = value;
+        }
+        match fresh.system_prompt() {
+            Ok(prompt) => {
+                let prompt = prompt.unwrap_or_else(|| DEFAULT_PROMPT.into());
+                let watcher = self.reload.as_mut().expect("checked above");
+                if prompt != watcher.prompt {
+                    // Additions made at startup (project, language, canary) follow the configured text
+                    let system = &mut self.conversation.make_mut()[0];
+                    match system.content.strip_prefix(watcher.prompt.as_str()) {
+                        Some(rest) => {
+                            system.content = format!("{}{}", prompt, rest);
+                            watcher.prompt = prompt;
+                            applied.push("system prompt replaced".into());
+                        }
+                        None => warn!("the system prompt was set by the embedding program; not replacing it"),
+                    }
+                }
+            }
+            Err(err) => warn!("keeping the system prompt: {:#}", err),
+        }
+        if ["policy", "permissions", "role", "allowed_paths"].iter().any(|k| changed(k)) {
+            let mut policy = policy::Policy::from_config(&fresh);
+            let old = &self.tool_ctx.policy;
+            // Scratch directories granted during the session stay reachable
+            let configured = policy::Policy::from_config(&self.config).roots.unwrap_or_default();
+            if let (Some(roots), Some(granted)) = (policy.roots.as_mut(), old.roots.as_ref()) {
+                roots.extend(granted.iter().filter(|r| !configured.contains(r)).cloned());
+            }
+            policy.yolo = old.yolo;
+            policy.ask_by_default = old.ask_by_default;
+            policy.scratch = old.scratch.clone();
+            self.tool_ctx.policy = policy;
+        }
+        if changed("allowed_hosts") || changed("allow_private_network") {
+            self.tool_ctx.network = tools::network::Network::new(fresh.allowed_hosts.clone(), fresh.allow_private_network.unwrap_or(false), fresh.http.as_ref());
+        }
+        self.tool_ctx.command_timeout_secs = fresh.command_timeout_secs;
+        self.tool_ctx.command_output_limit = fresh.command_output_limit;
+        let reloaded = ["policy", "permissions", "role", "allowed_paths", "allowed_hosts", "allow_private_network", "command_timeout_secs", "command_output_limit", "hot_reload", "prefetch", "actions_summary", "suggestions"];
+        applied.extend(reloaded.iter().filter(|k| changed(k)).map(|k| format!("`{}` reloaded", k)));
+        let waiting: Vec<&str> = keys.iter().map(String::as_str).filter(|k| !reload::LIVE.contains(k)).collect();
+        if fresh.hot_reload == Some(false) {
+            self.reload = None;
+        }
+        let config = &mut self.config;
+        config.temperature = fresh.temperature;
+        config.max_tokens = fresh.max_tokens;
+        config.system_prompt = fresh.system_prompt;
+        config.system_prompt_file = fresh.system_prompt_file;
+        config.policy = fresh.policy;
+        config.permissions = fresh.permissions;
+        config.role = fresh.role;
+        config.allowed_paths = fresh.allowed_paths;
+        config.allowed_hosts = fresh.allowed_hosts;
+        config.allow_private_network = fresh.allow_private_network;
+        config.command_timeout_secs = fresh.command_timeout_secs;
+        config.command_output_limit = fresh.command_output_limit;
+        config.hot_reload = fresh.hot_reload;
+        config.prefetch = fresh.prefetch;
+        config.actions_summary = fresh.actions_summary;
+        config.suggestions = fresh.suggestions;
+        if !waiting.is_empty() {
+            self.report(format!("[config] changed, applied after a restart: {}", waiting.join(", ")));
+        }
+        if applied.is_empty() {
+            return;
+        }
+        let summary = applied.join("; ");
+        self.report(format!("[config] reloaded: {}", summary));
+        let note = format!("The session's configuration changed: {}. Follow the current settings from here on.", summary);
+        self.conversation.push(ChatMessage { role: "system".into(), content: note, name: Some(reload::NOTE.into()), reasoning: None, function_call: None, files: Vec::new(), response_id: None });
+    }
+
+    /// Replace the editor focus note with the current one when `--context editor` is on and the
+    /// focus moved, or take it out once no editor reports one
+    fn refresh_focus(&mut self) {
+        let Some(focus) = &self.focus else { return };
+        let note = focus.collect();
+        let at = self.conversation.iter().rposition(|m| m.name.as_deref() == Some(focus::NOTE));
+        if at.map(|i| &self.conversation[i].content) == note.as_ref() {
+            return;
+        }
+        if let Some(at) = at {
+            self.conversation.make_mut().remove(at);
+            self.guard_checked -= (at < self.guard_checked) as usize;
+            if let Some((start, _)) = self.last_prompt.as_mut().filter(|(start, _)| at < *start) {
+                *start -= 1;
+            }
+        }
+        if let Some(note) = note {
+            self.conversation.push(ChatMessage { role: "system".into(), content: note, name: Some(focus::NOTE.into()), reasoning: None, function_call: None, files: Vec::new(), response_id: None });
+        }
+    }
+
+    /// The working-tree note for the next prompt, when `--context git-diff` is on and the tree
+    /// changed since the last one was sent
+    fn changed_diff(&self) -> Option<String> {
+        let diff = self.diff_context.as_ref()?;
+        match diff.collect() {
+            Ok(Some(note)) if diff.last.as_ref() != Some(&note) => Some(note),
+            Ok(_) => None,
+            Err(err) => {
+                warn!("not sending the git diff: {:#}", err);
+                None
+            }
+        }
+    }
+
+    /// Indexed chunks related to `input` as numbered sources, leaving out chunks already in the
+    /// conversation; `None` without an index, with retrieval off, or when nothing is close enough
+    async fn retrieve(&mut self, input: &str) -> Option<String> {
+        let index = self.tool_ctx.index.clone().filter(|i| i.top_k > 0)?;
+        let hits = match index.search(input, index.top_k).await {
+            Ok(hits) => hits,
+            Err(err) => {
+                warn!("no codebase excerpts for this prompt: {:#}", err);
+                return None;
+            }
+        };
+        let mut blocks = Vec::new();
+        for hit in hits.into_iter().filter(|h| h.score >= index.min_score) {
+            if self.sources.unchanged(&hit.location, &hit.text).is_empty() {
+                blocks.push(self.sources.offer(hit.location, &hit.text));
+            }
+        }
+        debug!("added {} codebase excerpt(s) to the prompt", blocks.len());
+        (!blocks.is_empty()).then(|| format!("Excerpts of this project's code that may bear on the next prompt:\n\n{}", blocks.join("\n\n")))
+    }
+
+    /// Replace earlier copies of the latest source still held in memory with a pointer to it
+    fn stub_sources(&mut self, ids: &[usize]) {
+        let latest = self.sources.latest();
+        for &id in ids {
+            let stub = self.sources.stub(id, latest);
+            let found = self.conversation.make_mut().iter_mut()
+                .filter(|m| m.role != "assistant")
+                .find_map(|m| citations::Sources::replace_block(&m.content, id, &stub).map(|content| (m, content)));
+            if let Some((message, content)) = found {
+                debug!("source {} is unchanged in source {}; stubbing the earlier copy", id, latest);
+                message.content = content;
+            }
+        }
+    }
+
+    /// Keep long sessions small by moving turns beyond `memory_messages` to disk
+    fn page_out(&mut self) {
+        let Some(keep) = self.memory_messages else { return };
+        match self.conversation.page_out(keep, &paths::data_dir().join("spill")) {
+            Ok(moved) => self.guard_checked = self.guard_checked.saturating_sub(moved).max(1),
+            Err(err) => warn!("keeping the whole conversation in memory: {:#}", err),
+        }
+    }
+
+    /// Make room after the provider rejected the prompt as too long: page out the older half of the
+    /// turns held in memory, or everything before the latest prompt if that moves nothing
+    fn shrink_context(&mut self) -> Result<usize> {
+        let dir = paths::data_dir().join("spill");
+        let mut moved = self.conversation.page_out((self.conversation.len() - 1) / 2, &dir)?;
+        if moved == 0 {
+            moved = self.conversation.page_out(1, &dir)?;
+        }
+        self.guard_checked = self.guard_checked.saturating_sub(moved).max(1);
+        Ok(moved)
+    }
+
+    /// Send a request; when it is rejected for exceeding the context window, shrink the context and retry once
+    async fn send_fitted(&mut self, turn: turns::Turn) -> Result<ChatMessage> {
+        if let Err(err) = self.fit_context(turn).await {
+            warn!("could not trim the conversation to the context budget: {:#}", err);
+        }
+        let reply = match self.send_request(turn, None).await {
+            Err(err) if err.downcast_ref::<capabilities::ContextOverflow>().is_some() => {
+                let moved = self.shrink_context()?;
+                if moved == 0 {
+                    return Err(err.context("The latest prompt alone exceeds the context window; shorten it"));
+                }
+                warn!("the conversation exceeded the context window; moved {} older messages out of the prompt and retrying", moved);
+                self.send_request(turn, None).await
+            }
+            result => result,
+        };
+        self.check_turn_budget();
+        reply
+    }
+
+    /// Prompt tokens the next request of this kind would use, and the budget for them
+    fn context_usage(&self, turn: turns::Turn) -> (u32, u32) {
+        let model = self.turn_model(turn);
+        let caps = self.capabilities(&model);
+        let max_tokens = self.turn_params(turn).and_then(|p| p.max_tokens).unwrap_or(self.max_tokens);
+        let budget = self.config.context.clone().unwrap_or_default().budget(&model, caps.max_context, max_tokens);
+        let tools = turn == turns::Turn::Tools && caps.tools && !self.functions.is_empty();
+        (context::estimate_prompt(&self.conversation, tools.then_some(&*self.functions)), budget)
+    }
+
+    /// Keep the prompt inside the context budget before a request: move the oldest turns out of
+    /// it, or with `strategy = "summarize"` replace them with a summary note
+    async fn fit_context(&mut self, turn: turns::Turn) -> Result<()> {
+        let (used, budget) = self.context_usage(turn);
+        if used <= budget {
+            return Ok(());
+        }
+        // The newest messages that fit next to the system prompt and tool definitions
+        let mut total = used - self.conversation.iter().skip(1).map(context::estimate).sum::<u32>();
+        let kept = self.conversation.iter().skip(1).rev()
+            .take_while(|m| {
+                total += context::estimate(m);
+                total <= budget
+            })
+            .count();
+        let len = self.conversation.len();
+        let Some(split) = (len - kept..len).find(|&i| i > 1 && self.conversation[i].role == "user") else {
+            capabilities::warn_once(format!("the latest turn alone exceeds the {}-token context budget; sending it whole", budget));
+            return Ok(());
+        };
+        let summary = match self.config.context.as_ref().is_some_and(|c| c.summarize()) {
+            true => match self.summarize_turns(&self.conversation[1..split]).await {
+                Ok(summary) => Some(summary),
+                Err(err) => {
+                    warn!("could not summarize the earlier conversation, dropping it instead: {:#}", err);
+                    None
+                }
+            },
+            false => None,
+        };
+        // An earlier summary is folded into the new one, or goes with the turns it stood for
+        let old_note = self.conversation[1].name.as_deref() == Some(context::SUMMARY);
+        if old_note {
+            self.conversation.make_mut().remove(1);
+        }
+        let moved = self.conversation.page_out(len - split, &paths::data_dir().join("spill"))?;
+        self.guard_checked = self.guard_checked.saturating_sub(moved + old_note as usize).max(1);
+        if let Some(summary) = summary {
+            self.conversation.make_mut().insert(1, ChatMessage {
+                role: "system".into(),
+                content: format!("Summary of the earlier conversation:\n{}", summary),
+                name: Some(context::SUMMARY.into()),
+                reasoning: None,
+                function_call: None,
+                files: Vec::new(),
+                response_id: None,
+            });
+            self.guard_checked += 1;
+        }
+        let (now, _) = self.context_usage(turn);
+        info!("the conversation (~{} tokens) exceeded the {}-token context budget; {} {} older messages (now ~{} tokens)",
+            used, budget, if self.conversation[1].name.as_deref() == Some(context::SUMMARY) { "summarized" } else { "dropped" }, moved, now);
+        Ok(())
+    }
+
+    /// Condense turns into a summary with a separate request
+    async fn summarize_turns(&self, messages: &[ChatMessage]) -> Result<String> {
+        let prompt = context::summary_prompt(&context::transcript(messages));
+        Ok(self.clone_for_batch(prompt).send_request(turns::Turn::Answer, None).await?.content)
+    }
+
+    /// Ask the model to plan `task` before anything runs, making the result the active plan
+    async fn make_plan(&mut self, task: &str) -> Result<Option<&plan::Plan>> {
+        let Some(reply) = self.turn(&plan::prompt(task)).await? else { return Ok(None) };
+        self.plan = Some(plan::Plan::parse(task, &reply.content)?);
+        self.save_plan();
+        Ok(self.plan.as_ref())
+    }
+
+    /// Write the active plan next to the session
+    fn save_plan(&self) {
+        let Some(plan) = &self.plan else { return };
+        let id = self.stats.lock().unwrap().id.clone();
+        if let Err(err) = plan.save(&id) {
+            warn!("{:#}", err);
+        }
+    }
+
+    /// Check off the plan step a completed tool call carried out
+    fn plan_progress(&mut self, call: &tools::ToolCall) {
+        let Some(plan) = self.plan.as_mut() else { return };
+        if let Some(i) = plan.complete(call) {
+            if !self.quiet {
+                println!("Plan step {} done: {} ({} left)", i + 1, plan.steps[i].description, plan.remaining());
+            }
+            self.save_plan();
+        }
+    }
+
+    /// One model turn with the answer printed to the terminal
+    pub async fn ask(&mut self, input: &str) -> Result<()> {
+        if let Err(err) = sync::record_prompt(input) {
+            debug!("prompt not added to the history: {:#}", err);
+        }
+        self.last_prompt = Some((self.conversation.total_len(), input.to_string()));
+        self.waiting = Some(progress::spinner(self.lang.msg(i18n::Msg::Generating), self.show_progress()));
+        let steps = self.config.post_process.clone().unwrap_or_default();
+        // Post-processed answers are printed once they are final
+        self.live = (self.streaming && steps.is_empty()).then(|| Mutex::new(String::new()));
+        let interruptible = cancel::Interruptible::new(&self.cancel);
+        let result = match verify::turn(self, input).await {
+            Ok(Some(mut reply)) if !steps.is_empty() => postprocess::apply(self, &steps, reply.content.clone()).await.map(|text| {
+                reply.content = text;
+                Some(reply)
+            }),
+            other => other,
+        };
+        drop(interruptible);
+        if let Some(spinner) = self.waiting.take() {
+            spinner.finish_and_clear();
+        }
+        let shown = self.live.take().map(|l| l.into_inner().unwrap()).unwrap_or_default();
+        if !shown.is_empty() {
+            println!();
+        }
+        let answer = result.as_ref().ok().and_then(|reply| reply.as_ref()).map(|reply| reply.content.clone());
+        match result {
+            // Already on screen as it streamed in; only the sources are left to list
+            Ok(Some(reply)) if !shown.is_empty() && shown.trim() == reply.content.trim() => {
+                let cited = self.sources.cited(&reply.content);
+                if !cited.is_empty() {
+                    println!("\n{}", self.tool_ctx.theme.paint(theme::Role::Tool, &citations::render(&cited)));
+                }
+            }
+            Ok(Some(reply)) => {
+                let colors = &self.tool_ctx.theme;
+                if let (Some(thinking), true, false) = (&reply.reasoning, self.reasoning.show, self.quiet) {
+                    let label = if self.a11y { "Reasoning follows." } else { "[thinking]" };
+                    println!("{}\n{}", colors.paint(theme::Role::Thinking, label), colors.paint(theme::Role::Thinking, thinking));
+                }
+                let mut text = colors.reply(&reply.content);
+                let cited = self.sources.cited(&reply.content);
+                if !cited.is_empty() {
+                    text = format!("{}\n\n{}", text, colors.paint(theme::Role::Tool, &citations::render(&cited)));
+                }
+                if self.quiet {
+                    pager::show(&text);
+                } else {
+                    pager::show(&format!("{} {}", colors.paint(theme::Role::Assistant, "RustAIgent:"), text));
+                }
+            }
+            Ok(None) => {}
+            Err(err) => eprintln!("{}: {:#}", self.tool_ctx.theme.paint(theme::Role::Error, self.lang.msg(i18n::Msg::Error)), err),
+        }
+        if let Some(answer) = answer.filter(|_| self.config.suggestions.as_ref().is_some_and(|cfg| cfg.enabled == Some(true))) {
+            self.offer_follow_ups(input, &answer).await;
+        }
+        Ok(())
+    }
+
+    /// Show follow-ups for `answer` under it; a failed request only costs the suggestions
+    async fn offer_follow_ups(&mut self, request: &str, answer: &str) {
+        let spinner = progress::spinner(self.lang.msg(i18n::Msg::Generating), self.show_progress());
+        let suggestions = suggest::follow_ups(self, request, answer).await;
+        spinner.finish_and_clear();
+        match suggestions {
+            Ok(suggestions) if !suggestions.is_empty() => {
+                println!("{}", self.tool_ctx.theme.paint(theme::Role::Thinking, &suggest::render(&suggestions, self.a11y)));
+                self.suggestions = suggestions;
+            }
+            Ok(_) => {}
+            Err(err) => debug!("no follow-up suggestions: {:#}", err),
+        }
+    }
+
+    /// Subscribe to progress events and take over approvals from the terminal
+    pub fn events(&mut self) -> events::Receiver {
+        let (sink, rx) = events::channel();
+        self.tool_ctx.events = Some(sink);
+        rx
+    }
+
+    fn emit(&self, event: events::AgentEvent) {
+        if let Some(sink) = &self.tool_ctx.events {
+            sink.emit(event);
+        }
+    }
+
+    /// Switch to screen-reader friendly output for the REPL and tools
+    pub fn enable_a11y(&mut self) {
+        self.a11y = true;
+        self.tool_ctx.a11y = true;
+    }
+
+    /// Spinners and progress bars are noise for screen readers and scripts
+    fn show_progress(&self) -> bool {
+        !self.quiet && !self.a11y
+    }
+
+    /// Limit the session to one workspace member
+    pub fn scope_to(&mut self, member: &workspace::Member) {
+        self.conversation.make_mut()[0].content.push_str(&format!("\n\n{}", member.scope_instruction()));
+        self.tool_ctx.package = Some(member.name.clone());
+    }
+
+    /// Run tools that would ask for approval without asking; denials still apply
+    pub fn enable_yolo(&mut self) {
+        self.tool_ctx.policy.yolo = true;
+    }
+
+    /// No one is at the terminal to answer: decline approvals instead of prompting for them
+    pub fn enable_unattended(&mut self) {
+        self.tool_ctx.unattended = true;
+    }
+
+    /// Send the working tree's uncommitted changes, and with `base` the branch's commits since
+    /// that ref, along with every prompt that follows a change
+    pub fn enable_diff_context(&mut self, base: Option<String>) {
+        self.diff_context = Some(gitdiff::DiffContext::new(base));
+    }
+
+    /// Let tool calls reach `dir` as `@name/path`, and tell the model about it
+    pub fn add_root(&mut self, name: &str, dir: &std::path::Path) -> Result<()> {
+        let before = (!self.roots.is_empty()).then(|| self.roots.describe());
+        let dir = self.roots.add(name, dir)?;
+        self.tool_ctx.policy.allow_path(&dir);
+        let after = self.roots.describe();
+        let prompt = &mut self.conversation.make_mut()[0].content;
+        match before.filter(|b| prompt.contains(b.as_str())) {
+            Some(before) => *prompt = prompt.replace(&before, &after),
+            None => prompt.push_str(&after),
+        }
+        Ok(())
+    }
+
+    /// Attribute the requests that follow to `name=value` in the usage stats, replacing an earlier
+    /// value of `name`
+    pub fn tag(&mut self, name: String, value: String) {
+        self.stats.lock().unwrap().tags.insert(name, value);
+    }
+
+    /// Stop attributing requests to the tag `name`
+    pub fn untag(&mut self, name: &str) {
+        self.stats.lock().unwrap().tags.remove(name);
+    }
+
+    /// Tags the next request is attributed to
+    pub fn tags(&self) -> stats::Tags {
+        self.stats.lock().unwrap().tags.clone()
+    }
+
+    /// Keep the file and selection open in the editor, as its plugin reports them, in a focus
+    /// note that moves along with it
+    pub fn enable_focus(&mut self) {
+        self.focus = Some(focus::Focus::new(self.config.focus_file.clone()));
+    }
+
+    /// Check each final answer against the `[verify]` rubric and regenerate it when it fails
+    pub fn enable_verify(&mut self) {
+        self.config.verify.get_or_insert_with(Default::default).enabled = Some(true);
+    }
+
+    /// Answer tool calls that would change something with a description of the effect instead
+    pub fn enable_dry_run(&mut self) {
+        self.tool_ctx.dry_run = true;
+    }
+
+    /// Merged configuration the session was started with
+    pub fn config(&self) -> &config::Config {
+        &self.config
+    }
+
+    /// The session's pooled HTTP client
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// Print only final answers: no prompt labels or tool chatter
+    pub fn enable_quiet(&mut self) {
+        self.quiet = true;
+        self.tool_ctx.quiet = true;
+    }
+
+    /// Write the conversation, tool log and touched files as a portable bundle, or as a notebook for `.md` paths
+    fn export(&self, path: &std::path::Path) -> Result<()> {
+        let messages = self.conversation.load_all()?;
+        let bundle = session::Bundle {
+            format: session::FORMAT.into(),
+            id: self.stats.lock().unwrap().id.clone(),
+            exported: stats::now_secs(),
+            provider: self.provider.clone(),
+            model: self.model.clone(),
+            messages,
+            tool_calls: self.tool_log.clone(),
+            files: session::snapshot_files(&self.tool_log),
+            config_digest: session::config_digest(&self.config),
+            plan: self.plan.clone(),
+            tags: self.tags(),
+        };
+        if path.extension().is_some_and(|e| e == "md") {
+            return std::fs::write(path, notebook::render(&bundle)).with_context(|| format!("Failed to write {}", path.display()));
+        }
+        bundle.save(path)
+    }
+
+    /// Continue an imported session; the model from the bundle applies unless MODEL_NAME is set
+    pub fn resume(&mut self, bundle: session::Bundle) {
+        if bundle.config_digest != session::config_digest(&self.config) {
+            warn!("this session was recorded with a different configuration");
+        }
+        if env::var("MODEL_NAME").is_err() && bundle.model.is_some() {
+            self.model = bundle.model;
+        }
+        // Keep this session's system prompt: the saved one carries the other session's canary
+        let system = self.conversation[0].clone();
+        let mut messages = bundle.messages;
+        if messages.first().is_some_and(|m| m.role == "system") {
+            messages.remove(0);
+        }
+        messages.insert(0, system);
+        self.conversation = messages.into();
+        // Imported messages weren't scanned by this session's guardrails
+        self.guard_checked = 1;
+        self.tool_log = bundle.tool_calls;
+        self.tool_ctx.notes.adopt(&bundle.id);
+        self.stats.lock().unwrap().tags.extend(bundle.tags);
+        self.plan = bundle.plan;
+        // Renumber retrieved sources in their original order so old citations stay valid
+        for call in self.tool_log.iter().filter(|c| c.ok) {
+            for (location, content) in citations::retrieved(&call.call, &call.output) {
+                self.sources.add(location, content);
+            }
+        }
+        self.sources.settle();
+    }
+
+    /// Hand the current state to the autosave that runs on panic or interrupt
+    pub fn checkpoint(&self) {
+        {
+            let mut stats = self.stats.lock().unwrap();
+            stats.approvals = self.tool_ctx.approvals;
+            stats.denials = self.tool_ctx.denials;
+        }
+        autosave::update(autosave::Checkpoint {
+            provider: self.provider.clone(),
+            model: self.model.clone(),
+            config_digest: session::config_digest(&self.config),
+            history: self.conversation.clone(),
+            tool_log: self.tool_log.clone(),
+            plan: self.plan.clone(),
+            stats: Arc::clone(&self.stats),
+        });
+    }
+
+    /// Fold approval counters into the session stats and append them to the usage log
+    pub fn save_stats(&mut self) {
+        let mut stats = self.stats.lock().unwrap();
+        stats.approvals = self.tool_ctx.approvals;
+        stats.denials = self.tool_ctx.denials;
+        if let Err(err) = stats.save() {
+            warn!("failed to save usage stats: {:#}", err);
+        }
+    }
+}
+
+/// Warn when a tool brings back a canary token: the system prompt reached what it read, such as
+/// a page or a server that was sent the conversation earlier
+#[cfg(not(target_arch = "wasm32"))]
+fn report_canary(tool: &str, output: &str) {
+    if let Some(token) = canary::find(output) {
+        warn!("the output of {} contains the canary token {} from a system prompt; the conversation has leaked to where it came from", tool, token);
+    }
+}
+
+/// Tool arguments as a spoken-style list, e.g. `path src and label tmp`
+#[cfg(not(target_arch = "wasm32"))]
+fn describe_args(args: &serde_json::Value) -> String {
+    let parts: Vec<String> = args.as_object().into_iter().flatten()
+        .map(|(k, v)| format!("{} {}", k, v.as_str().map(String::from).unwrap_or_else(|| v.to_string())))
+        .collect();
+    if parts.is_empty() { "no arguments".into() } else { parts.join(" and ") }
+}
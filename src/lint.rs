@@ -0,0 +1,66 @@
+use std::{io::Write, process::{Command, Stdio}};
+
+/// Pull the bodies of ```rust (or ```rs) fenced blocks out of a model reply
+pub fn extract_rust_blocks(text: &str) -> Vec<String> {
+    let mut blocks = Vec::new();
+    let mut current: Option<String> = None;
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        match current.as_mut() {
+            Some(block) if trimmed.starts_with("```") => {
+                blocks.push(std::mem::take(block));
+                current = None;
+            }
+            Some(block) => {
+                block.push_str(line);
+                block.push('\n');
+            }
+            None => {
+                if let Some(lang) = trimmed.strip_prefix("```") {
+                    if matches!(lang.trim(), "rust" | "rs") {
+                        current = Some(String::new());
+                    }
+                }
+            }
+        }
+    }
+    blocks
+}
+
+/// Parse as a Rust source file, falling back to a function body for snippets; returns the error if neither parses
+pub fn syntax_error(code: &str) -> Option<String> {
+    let err = match syn::parse_file(code) {
+        Ok(_) => return None,
+        Err(err) => err,
+    };
+    if syn::parse_str::<syn::Block>(&format!("{{\n{}\n}}", code)).is_ok() {
+        return None;
+    }
+    let start = err.span().start();
+    Some(format!("line {}, column {}: {}", start.line, start.column + 1, err))
+}
+
+/// Run `rustfmt --check` over the code for `edition`; returns the suggested diff when formatting differs
+pub fn rustfmt_check(code: &str, edition: &str) -> Option<String> {
+    let mut child = Command::new("rustfmt")
+        .args(["--check", "--edition", edition])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+    child.stdin.take()?.write_all(code.as_bytes()).ok()?;
+    let out = child.wait_with_output().ok()?;
+    if out.status.success() {
+        None
+    } else {
+        Some(String::from_utf8_lossy(&out.stdout).into_owned())
+    }
+}
+
+/// Collect syntax errors for every Rust block in a reply, numbered by block
+pub fn check_reply(text: &str) -> Vec<String> {
+    extract_rust_blocks(text).iter().enumerate()
+        .filter_map(|(i, block)| syntax_error(block).map(|e| format!("block {}: {}", i + 1, e)))
+        .collect()
+}
@@ -0,0 +1,112 @@
+//! Diagnostics on stderr or in `--log-file`. At `-vv` the bodies of provider requests and
+//! responses are logged too; they carry source code and prompts, so each is sealed with a key
+//! made for the session and kept in the data directory, and `rustaigent logs decrypt` reads them.
+
+use std::{fs::{self, OpenOptions}, io::Write, path::{Path, PathBuf}, sync::{Mutex, OnceLock}};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use anyhow::{Result, Context, anyhow};
+use tracing_subscriber::EnvFilter;
+use crate::{paths, vault::{self, Cipher}};
+
+const MAGIC: &[u8] = b"RAIL1";
+/// Starts a sealed body in a log line: `[sealed <key id> <base64>]`
+const SEALED: &str = "[sealed ";
+
+/// Filter for a `-q` / `-v` count; RUST_LOG, when set, takes precedence
+fn filter_for(verbosity: u8, quiet: bool) -> EnvFilter {
+    if let Ok(filter) = EnvFilter::try_from_default_env() {
+        return filter;
+    }
+    let directives = match (quiet, verbosity) {
+        (true, _) => "error",
+        (false, 0) => "warn,rustaigent=info",
+        (false, 1) => "warn,rustaigent=debug",
+        (false, 2) => "info,rustaigent=trace",
+        _ => "trace",
+    };
+    EnvFilter::new(directives)
+}
+
+/// Install the global subscriber, writing to stderr or, with `--log-file`, appending to a file
+pub fn init(verbosity: u8, quiet: bool, log_file: Option<&Path>) -> Result<()> {
+    let filter = filter_for(verbosity, quiet);
+    match log_file {
+        Some(path) => {
+            let file = OpenOptions::new().create(true).append(true).open(path)
+                .with_context(|| format!("Failed to open log file {}", path.display()))?;
+            tracing_subscriber::fmt()
+                .with_env_filter(filter)
+                .with_ansi(false)
+                .with_writer(Mutex::new(file))
+                .init();
+        }
+        None => {
+            tracing_subscriber::fmt()
+                .with_env_filter(filter)
+                .without_time()
+                .with_target(false)
+                .with_ansi(crate::theme::colors_supported_on(std::io::stderr()))
+                .with_writer(std::io::stderr)
+                .init();
+        }
+    }
+    Ok(())
+}
+
+fn keys_dir() -> PathBuf {
+    paths::data_dir().join("logkeys")
+}
+
+/// The session's log key and its id, made and stored on first use
+fn session_key() -> Result<&'static (String, Cipher)> {
+    static KEY: OnceLock<std::result::Result<(String, Cipher), String>> = OnceLock::new();
+    let key = KEY.get_or_init(|| {
+        let made = (|| -> Result<(String, Cipher)> {
+            let key: [u8; 32] = vault::random()?;
+            let id: String = vault::random::<4>()?.iter().map(|b| format!("{:02x}", b)).collect();
+            fs::create_dir_all(keys_dir())?;
+            // Sealed with the storage key too when `encryption` is on
+            let path = keys_dir().join(&id);
+            vault::create_private(&path)?.write_all(&vault::seal(key.to_vec())?)?;
+            Ok((id, Cipher::new(&key, MAGIC)))
+        })();
+        made.map_err(|err| format!("{:#}", err))
+    });
+    key.as_ref().map_err(|err| anyhow!("{}", err))
+}
+
+/// A request or response body as it goes into the log: sealed unless `encrypt` is off, and
+/// left out when no key can be made rather than logged in the clear
+pub fn body(bytes: &[u8], encrypt: bool) -> String {
+    if !encrypt {
+        return String::from_utf8_lossy(bytes).into_owned();
+    }
+    match session_key().and_then(|(id, cipher)| Ok((id, cipher.seal(bytes)?))) {
+        Ok((id, sealed)) => format!("{}{} {}]", SEALED, id, STANDARD.encode(sealed)),
+        Err(err) => format!("[{} bytes not logged: {:#}]", bytes.len(), err),
+    }
+}
+
+/// `log` with every sealed body replaced by its text, for `rustaigent logs decrypt`
+pub fn decrypt(log: &str) -> Result<String> {
+    let mut ciphers: std::collections::HashMap<String, Cipher> = std::collections::HashMap::new();
+    let mut out = String::with_capacity(log.len());
+    let mut rest = log;
+    while let Some(start) = rest.find(SEALED) {
+        out.push_str(&rest[..start]);
+        let token = &rest[start + SEALED.len()..];
+        let end = token.find(']').context("A sealed body is cut off")?;
+        let (id, data) = token[..end].split_once(' ').context("A sealed body has no key id")?;
+        if !ciphers.contains_key(id) {
+            let path = keys_dir().join(id);
+            let key = vault::read(&path)?.with_context(|| format!("No log key {} in {}; it was purged or written on another machine", id, keys_dir().display()))?;
+            let key: [u8; 32] = key.try_into().map_err(|_| anyhow!("Log key {} is malformed", id))?;
+            ciphers.insert(id.to_string(), Cipher::new(&key, MAGIC));
+        }
+        let sealed = STANDARD.decode(data).with_context(|| format!("A body sealed with log key {} is not base64", id))?;
+        out.push_str(&String::from_utf8_lossy(&ciphers[id].open(&format!("a body sealed with log key {}", id), &sealed)?));
+        rest = &token[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
@@ -0,0 +1,90 @@
+use std::{fs::File, io::{Read, Seek, SeekFrom}, path::PathBuf, time::Duration};
+use regex::Regex;
+use anyhow::{Result, Context};
+use crate::{Agent, ChatMessage};
+
+/// Follows a file like `tail -f`, coping with truncation and rotation
+struct Tail {
+    path: PathBuf,
+    offset: u64,
+    partial: String,
+}
+
+impl Tail {
+    /// Start at the current end of the file
+    fn open(path: PathBuf) -> Result<Self> {
+        let offset = std::fs::metadata(&path).with_context(|| format!("Cannot follow {}", path.display()))?.len();
+        Ok(Tail { path, offset, partial: String::new() })
+    }
+
+    /// Complete lines appended since the last poll
+    fn poll(&mut self) -> Result<Vec<String>> {
+        let Ok(mut file) = File::open(&self.path) else {
+            // Rotated away; the next poll picks up the new file
+            return Ok(Vec::new());
+        };
+        let len = file.metadata()?.len();
+        if len < self.offset {
+            self.offset = 0;
+            self.partial.clear();
+        }
+        file.seek(SeekFrom::Start(self.offset))?;
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+        self.offset += buf.len() as u64;
+        self.partial.push_str(&String::from_utf8_lossy(&buf));
+        let mut lines: Vec<String> = self.partial.split('\n').map(String::from).collect();
+        self.partial = lines.pop().unwrap_or_default();
+        Ok(lines)
+    }
+}
+
+/// Follow `path`, batching lines that match `pattern`, and ask the model for an anomaly summary every `interval`
+pub async fn follow(agent: &mut Agent, path: PathBuf, pattern: Regex, interval: Duration, max_lines: usize) -> Result<()> {
+    let mut tail = Tail::open(path.clone())?;
+    let mut batch: Vec<String> = Vec::new();
+    let mut dropped = 0usize;
+    let mut ticker = tokio::time::interval(interval);
+    ticker.tick().await;
+    let mut poll = tokio::time::interval(Duration::from_millis(500));
+    println!("Following {} for /{}/, summarizing every {}; Ctrl-C to stop", path.display(), pattern, humantime::format_duration(interval));
+
+    loop {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => break,
+            _ = poll.tick() => {
+                for line in tail.poll()?.into_iter().filter(|l| pattern.is_match(l)) {
+                    batch.push(line);
+                    if batch.len() > max_lines {
+                        batch.remove(0);
+                        dropped += 1;
+                    }
+                }
+            }
+            _ = ticker.tick() => {
+                if batch.is_empty() {
+                    continue;
+                }
+                let omitted = if dropped > 0 { format!(", {} older lines omitted", dropped) } else { String::new() };
+                let prompt = format!(
+                    "New lines matching /{}/ in {} during the last {} ({} lines{}):\n```\n{}\n```\n\
+                     Summarize the anomalies briefly: group similar errors, point out error types not seen in your earlier summary, \
+                     suggest likely causes and what to check next.",
+                    pattern, path.display(), humantime::format_duration(interval), batch.len(), omitted, batch.join("\n"));
+                // Keep only the previous summary as context so a long session doesn't grow without bound
+                let previous = agent.conversation.last().filter(|m| m.role == "assistant").cloned();
+                agent.conversation.truncate(1);
+                agent.guard_checked = agent.guard_checked.min(1);
+                if let Some(summary) = previous {
+                    agent.conversation.push(ChatMessage { role: "assistant".into(), content: summary.content, name: None, reasoning: None, function_call: None, files: Vec::new(), response_id: None });
+                }
+                println!("\n── {} ──", chrono::Local::now().format("%H:%M:%S"));
+                agent.ask(&prompt).await?;
+                batch.clear();
+                dropped = 0;
+            }
+        }
+    }
+    agent.save_stats();
+    Ok(())
+}
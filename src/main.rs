@@ -1,194 +1,916 @@
-use std::{env, io::{self, Write}, fs, process::Command, time::Duration};
-use serde::{Serialize, Deserialize};
-use serde_json::json;
-use reqwest::Client;
+use std::{env, io};
 use anyhow::{Result, Context};
 use dotenvy::dotenv;
-use tokio::time::sleep;
-use futures::future::join_all;
-
-#[derive(Serialize, Deserialize, Clone)]
-struct ChatMessage {
-    role: String,
-    content: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    name: Option<String>,
-}
-
-#[derive(Serialize, Clone)]
-struct FunctionDefinition {
-    name: String,
-    description: String,
-    parameters: serde_json::Value,
-}
-
-#[derive(Serialize)]
-struct ChatCompletionRequest {
-    model: String,
-    messages: Vec<ChatMessage>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    functions: Option<Vec<FunctionDefinition>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    function_call: Option<String>,
-    max_tokens: u16,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    temperature: Option<f32>,
-}
-
-#[derive(Deserialize)]
-struct ChatCompletionResponse {
-    choices: Vec<Choice>,
-}
-
-#[derive(Deserialize)]
-struct Choice {
-    message: ChatMessage,
-    finish_reason: Option<String>,
-}
-
-struct Agent {
-    client: Client,
-    api_key: String,
-    google_api_key: Option<String>,
-    provider: String,
-    conversation: Vec<ChatMessage>,
-    functions: Vec<FunctionDefinition>,
-    max_tokens: u16,
-    temperature: f32,
-    retry_count: u8,
-    backoff_base: u64,
-}
-
-impl Agent {
-    fn new(api_key: String, provider: String) -> Self {
-        dotenv().ok();
-        let google_api_key = env::var("GOOGLE_API_KEY").ok();
-        let max_tokens = env::var("MAX_TOKENS").ok().and_then(|v| v.parse().ok()).unwrap_or(1024);
-        let temperature = env::var("TEMPERATURE").ok().and_then(|v| v.parse().ok()).unwrap_or(0.7);
-        let retry_count = env::var("RETRY_COUNT").ok().and_then(|v| v.parse().ok()).unwrap_or(3);
-        let backoff_base = env::var("BACKOFF_BASE_MS").ok().and_then(|v| v.parse().ok()).unwrap_or(500);
-
-        // Define tools/functions
-        let funcs = vec![
-            FunctionDefinition { name: "read_file".into(), description: "Read a file from the filesystem".into(), parameters: json!({"type":"object","properties":{"path":{"type":"string"}},"required":["path"]}) },
-            FunctionDefinition { name: "write_file".into(), description: "Write content to a file".into(), parameters: json!({"type":"object","properties":{"path":{"type":"string"},"content":{"type":"string"}},"required":["path","content"]}) },
-            FunctionDefinition { name: "delete_file".into(), description: "Delete a file from the filesystem".into(), parameters: json!({"type":"object","properties":{"path":{"type":"string"}},"required":["path"]}) },
-            FunctionDefinition { name: "list_dir".into(), description: "List files in a directory".into(), parameters: json!({"type":"object","properties":{"path":{"type":"string"}},"required":["path"]}) },
-            FunctionDefinition { name: "run_command".into(), description: "Run a shell command".into(), parameters: json!({"type":"object","properties":{"command":{"type":"string"}},"required":["command"]}) },
-            FunctionDefinition { name: "fetch_url".into(), description: "Perform a GET request to a URL".into(), parameters: json!({"type":"object","properties":{"url":{"type":"string"}},"required":["url"]}) },
-            FunctionDefinition { name: "eval_code".into(), description: "Compile and run Rust code snippet".into(), parameters: json!({"type":"object","properties":{"code":{"type":"string"}},"required":["code"]}) },
-        ];
-
-        let prompt = "You are RustAIgent, a versatile Rust coding assistant with tools for file I/O, directory ops, shell commands, HTTP fetches, and code evaluation. Switch between OpenAI, Claude, Ollama, Google. Use rich function calling. Respond concisely in Rust style.";
-        let mut conv = vec![ChatMessage { role: "system".into(), content: prompt.into(), name: None }];
-
-        Agent { client: Client::new(), api_key, google_api_key, provider, conversation: conv, functions: funcs, max_tokens, temperature, retry_count, backoff_base }
-    }
-
-    /// Send a single request with retries
-    async fn request_with_retry(&self, url: &str, body: &serde_json::Value) -> Result<serde_json::Value> {
-        for attempt in 0..self.retry_count {
-            let res = self.client.post(url)
-                .bearer_auth(&self.api_key)
-                .json(body)
-                .send().await;
-            match res {
-                Ok(resp) => return Ok(resp.json().await?);
-                Err(err) if attempt < self.retry_count - 1 => {
-                    let backoff = self.backoff_base * 2u64.pow(attempt as u32);
-                    sleep(Duration::from_millis(backoff)).await;
-                }
-                Err(err) => return Err(err.into()),
-            }
-        }
-        unreachable!()
-    }
-
-    async fn send_request(&self, func_call: Option<String>) -> Result<ChatMessage> {
-        // Build common request payload
-        let req = ChatCompletionRequest {
-            model: match self.provider.as_str() {
-                "openai" => env::var("MODEL_NAME").unwrap_or_else(|_| "gpt-4o-mini".into()),
-                "claude" => "claude-2".into(),
-                "ollama" => "rust-ai-agent".into(),
-                "google" => "chat-bison-001".into(),
-                _ => "gpt-4o-mini".into(),
-            },
-            messages: self.conversation.clone(),
-            functions: Some(self.functions.clone()),
-            function_call: Some(func_call.unwrap_or_else(|| "auto".into())),
-            max_tokens: self.max_tokens,
-            temperature: Some(self.temperature),
-        };
-        let body = serde_json::to_value(&req)?;
-
-        // Dispatch based on provider
-        let response_json = match self.provider.as_str() {
-            "openai" => self.request_with_retry("https://api.openai.com/v1/chat/completions", &body).await?,
-            "claude" => {
-                let anthropic_body = json!({"model":"claude-2","prompt": self.conversation.iter().map(|m| format!"[{m.role}] {m.content}").collect::<Vec<_>>().join("\n"),"max_tokens_to_sample":self.max_tokens});
-                self.request_with_retry("https://api.anthropic.com/v1/complete", &anthropic_body).await?
-            }
-            "ollama" => self.request_with_retry("http://localhost:11434/v1/completions", &body).await?,
-            "google" => {
-                let gkey = self.google_api_key.as_ref().context("Missing GOOGLE_API_KEY")?;
-                let url = format!("https://generativelanguage.googleapis.com/v1beta2/models/chat-bison-001:generateMessage?key={}", gkey);
-                self.request_with_retry(&url, &json!({"messages": self.conversation.iter().map(|m| json!({"author": m.role, "content": m.content})).collect::<Vec<_>>() })).await?
-            }
-            _ => self.request_with_retry("https://api.openai.com/v1/chat/completions", &body).await?,
-        };
+use tracing::warn;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::engine::{ArgValueCompleter, CompletionCandidate, PathCompleter, ValueCompleter};
+use rustaigent::{autosave, batch, config, fix, http, index, init, logging, logs, mcp, models, notebook, oneshot, pager, paths, plugins, policy, purge, pr, queue, replay, report, review, risk, self_update, serve, session, stats, stdio, sync, templates, theme, tools, vault, workon, workspace, AgentBuilder};
 
-        // Extract ChatMessage
-        if let Some(choice) = response_json["choices"].as_array().and_then(|arr| arr.get(0)) {
-            let msg: ChatMessage = serde_json::from_value(choice["message"].clone())?;
-            Ok(msg)
-        } else if let Some(text) = response_json["completion"].as_str() {
-            Ok(ChatMessage { role: "assistant".into(), content: text.into(), name: None })
-        } else {
-            Err(anyhow::anyhow!("Unexpected response format"))
-        }
-    }
+#[derive(Parser)]
+#[command(name = "rustaigent", version, about = "Command-line coding assistant backed by LLMs")]
+struct Cli {
+    /// Screen-reader friendly output: plain sentences for tool activity, linear diffs, no redrawn lines
+    #[arg(long, global = true, env = "RUSTAIGENT_A11Y")]
+    a11y: bool,
+    /// Print only final answers; diagnostics are limited to errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Increase diagnostic output (-v debug, -vv trace)
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Scope the session to one cargo workspace member
+    #[arg(long, global = true, value_name = "NAME", env = "RUSTAIGENT_PACKAGE")]
+    package: Option<String>,
+    /// Run tools that would ask for approval without asking; denials still apply
+    #[arg(long, global = true, env = "RUSTAIGENT_YOLO")]
+    yolo: bool,
+    /// Describe what tools would do instead of running them; read-only tools still run
+    #[arg(long, global = true, env = "RUSTAIGENT_DRY_RUN")]
+    dry_run: bool,
+    /// Check each final answer against the `[verify]` rubric with a second model call, regenerating it when it fails
+    #[arg(long, global = true, env = "RUSTAIGENT_VERIFY")]
+    verify: bool,
+    /// Send extra context along with each prompt; repeat it for several sources
+    #[arg(long, global = true, value_enum, value_name = "SOURCE")]
+    context: Vec<ContextSource>,
+    /// With `--context git-diff`, also send the branch's commits since this ref (e.g. main)
+    #[arg(long, global = true, value_name = "REF", requires = "context")]
+    diff_base: Option<String>,
+    /// Add another project root that tool calls reach as `@NAME/path`, e.g. `--root sdk=../client-sdk`; repeatable
+    #[arg(long, global = true, value_name = "NAME=PATH")]
+    root: Vec<String>,
+    /// Attribute the session's spend to a tag in the usage stats, e.g. `--tag ticket=ENG-142`; repeatable.
+    /// With `stats`, count only the spend under it
+    #[arg(long, global = true, value_name = "NAME=VALUE", env = "RUSTAIGENT_TAGS", value_delimiter = ',')]
+    tag: Vec<String>,
+    /// Seed the session from a conversation template in .rustaigent/templates/
+    #[arg(long, global = true, value_name = "NAME", add = ArgValueCompleter::new(complete_templates))]
+    template: Option<String>,
+    /// Apply a `[profiles.<name>]` table from the config files, or the built-in `low-memory`
+    /// profile, on top of the other settings
+    #[arg(long, global = true, value_name = "NAME", env = "RUSTAIGENT_PROFILE", add = ArgValueCompleter::new(complete_profiles))]
+    profile: Option<String>,
+    /// Answer this prompt and exit instead of starting the REPL; piped stdin is attached to it
+    #[arg(short, long, value_name = "TEXT")]
+    prompt: Option<String>,
+    /// With --prompt, print only this part of the answer, or every event as a JSON line
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t = OutputFormat::Text, requires = "prompt")]
+    output: OutputFormat,
+    /// With --prompt, print a JSON object with the answer, tool calls and token usage
+    #[arg(long, requires = "prompt")]
+    json: bool,
+    /// With --prompt, upload this file to the provider's files API and send it along; repeatable
+    #[arg(long, value_name = "PATH", requires = "prompt")]
+    upload: Vec<std::path::PathBuf>,
+    /// Speak the editor plugin protocol, JSON lines on stdin and stdout, instead of starting the REPL
+    #[arg(long, conflicts_with = "prompt")]
+    stdio_editor: bool,
+    /// With --prompt, answer in this process even when `rustaigent daemon` is listening
+    #[arg(long, requires = "prompt")]
+    no_daemon: bool,
+    /// Print what this build and configuration offer as JSON: providers, models, tools and build
+    /// features
+    #[arg(long, conflicts_with_all = ["prompt", "stdio_editor"])]
+    capabilities: bool,
+    /// Append diagnostics to this file instead of stderr
+    #[arg(long, global = true, value_name = "PATH")]
+    log_file: Option<std::path::PathBuf>,
+    #[command(subcommand)]
+    command: Option<Commands>,
+}
 
-    /// Send multiple prompts concurrently
-    async fn send_batch_requests(&self, prompts: Vec<String>) -> Result<Vec<ChatMessage>> {
-        let tasks: Vec<_> = prompts.into_iter().map(|text| {
-            let mut agent_clone = self.clone_for_batch(text);
-            tokio::spawn(async move {
-                agent_clone.send_request(None).await
-            })
-        }).collect();
+#[derive(Subcommand)]
+enum Commands {
+    /// Summarize recorded usage: sessions, tokens, cost, tool calls, approvals
+    Stats {
+        /// Only include sessions started within this window (e.g. 24h, 7d)
+        #[arg(long, default_value = "30d")]
+        since: String,
+        #[arg(long, value_enum, default_value_t = StatsFormat::Table)]
+        format: StatsFormat,
+        /// Write a standalone HTML report with charts to this file instead
+        #[arg(long, value_name = "PATH")]
+        html: Option<std::path::PathBuf>,
+    },
+    /// Interactive first-run setup: provider, API key, default model
+    Init,
+    /// Check the setup and report the agent's memory footprint and the limits that bound it
+    Doctor,
+    /// Download and install the latest GitHub release after verifying its checksum
+    SelfUpdate {
+        /// Only report whether a newer release exists
+        #[arg(long)]
+        check: bool,
+    },
+    /// Print a shell completion script, e.g. `source <(rustaigent completions bash)`
+    Completions {
+        #[arg(value_parser = ["bash", "zsh", "fish", "elvish", "powershell"])]
+        shell: String,
+    },
+    /// Inspect configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Step through an exported session turn by turn, including tool calls and diffs
+    Replay {
+        /// Session bundle written with `/export`
+        #[arg(add = ArgValueCompleter::new(complete_bundles))]
+        session: std::path::PathBuf,
+    },
+    /// List the members of the enclosing cargo workspace
+    Workspace,
+    /// Follow a log file and periodically have the model summarize anomalies in matching lines
+    #[command(subcommand_negates_reqs = true)]
+    Logs {
+        #[command(subcommand)]
+        action: Option<LogsAction>,
+        /// Log file to follow
+        #[arg(long, required = true)]
+        tail: Option<std::path::PathBuf>,
+        /// Only batch lines matching this regex
+        #[arg(long, default_value = "(?i)error|warn|panic|exception|fatal")]
+        pattern: String,
+        /// How often to summarize new lines (e.g. 30s, 5m)
+        #[arg(long, default_value = "1m", value_parser = humantime::parse_duration)]
+        every: std::time::Duration,
+        /// Keep at most this many lines per batch
+        #[arg(long, default_value_t = 200)]
+        max_lines: usize,
+    },
+    /// Run `terraform plan` and have the model explain and risk-assess it, then continue interactively
+    PlanReview {
+        /// Terraform root module
+        #[arg(long, default_value = ".")]
+        dir: String,
+        /// Review a saved plan (`terraform plan -out`) instead of planning now
+        #[arg(long)]
+        plan_file: Option<String>,
+    },
+    /// List the Ollama server's models, or pull, show or remove one
+    Models {
+        #[command(subcommand)]
+        action: Option<ModelsAction>,
+    },
+    /// Have the model review the branch's changes, uncommitted work included, then continue interactively
+    Review {
+        /// Branch the changes are reviewed against (default: origin's HEAD branch, else main or master)
+        base: Option<String>,
+    },
+    /// Run the project's tests (or --command) and have the model fix failures until they pass
+    Fix {
+        /// Check to make pass; defaults to the detected project's test command
+        #[arg(long)]
+        command: Option<String>,
+        /// Give up after this many fix attempts (default 5)
+        #[arg(long)]
+        max_iterations: Option<usize>,
+        /// Give up after this much wall time across resumes, e.g. `30m`
+        #[arg(long, value_name = "DURATION")]
+        max_time: Option<String>,
+        /// Give up once requests have cost this many USD
+        #[arg(long, value_name = "USD")]
+        max_cost: Option<f64>,
+        /// Also stop once the detected project's test command passes
+        #[arg(long)]
+        until_tests_pass: bool,
+        /// Also stop once this file exists
+        #[arg(long, value_name = "PATH")]
+        until_exists: Option<std::path::PathBuf>,
+        /// Continue the interrupted loop for this directory
+        #[arg(long, conflicts_with = "command")]
+        resume: bool,
+        /// Have the model write a plan before the first attempt (config key `plan`)
+        #[arg(long)]
+        plan: bool,
+    },
+    /// Take a GitHub issue from branch to pull request description: plan, implement and test the change, asking before each stage
+    WorkOn {
+        /// https://github.com/<owner>/<repo>/issues/<number>; set GITHUB_TOKEN for private repositories
+        issue: String,
+        /// Give up fixing failing tests after this many attempts (default 5)
+        #[arg(long)]
+        max_iterations: Option<usize>,
+    },
+    /// Summarize the branch's changes into a pull request title and description
+    PrDescribe {
+        /// Branch the pull request goes into (default: origin's HEAD branch, else main or master)
+        base: Option<String>,
+        /// Create the pull request on GitHub, or update the branch's open one (needs GITHUB_TOKEN)
+        #[arg(long)]
+        push: bool,
+        /// Also write the description to this file
+        #[arg(long, short, value_name = "PATH")]
+        output: Option<std::path::PathBuf>,
+    },
+    /// Answer every prompt of a JSONL file, live or through the provider's Batch API, writing JSONL results
+    Batch {
+        /// One prompt per line: a JSON string or `{"id": ..., "prompt": ...}`
+        input: std::path::PathBuf,
+        /// Write results here instead of stdout
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<std::path::PathBuf>,
+        /// Submit through OpenAI's asynchronous Batch API: half the price, results within 24h
+        #[arg(long)]
+        batch_api: bool,
+        /// Keep polling a submitted batch instead of submitting the input again
+        #[arg(long, value_name = "BATCH_ID")]
+        resume: Option<String>,
+        /// How often to poll the Batch API (e.g. 30s, 5m)
+        #[arg(long, default_value = "1m")]
+        every: String,
+        /// Live requests in flight at once
+        #[arg(long, default_value_t = 8)]
+        concurrency: usize,
+    },
+    /// Queue prompts for OpenAI's Batch API (half the price, answers within 24h), submitted in the `off_peak` window
+    #[command(args_conflicts_with_subcommands = true)]
+    Queue {
+        #[command(subcommand)]
+        action: Option<QueueAction>,
+        /// Prompts to queue, one job each; with none, only submit jobs that are due
+        prompts: Vec<String>,
+        /// Also queue every non-empty line of this file
+        #[arg(long, value_name = "PATH")]
+        file: Option<std::path::PathBuf>,
+        /// Submit waiting jobs now instead of in the off-peak window
+        #[arg(long)]
+        now: bool,
+    },
+    /// Mirror prompt history and saved sessions with the encrypted `[sync]` backend (git, S3 or WebDAV)
+    Sync,
+    /// Chunk and embed the repository's files so answers can draw on them; only changed files are embedded again
+    Index {
+        /// Embed every file again instead of only the changed ones
+        #[arg(long, conflicts_with = "pull")]
+        rebuild: bool,
+        /// First fetch what teammates shared to `[index] share` for this commit or a recent one
+        #[arg(long)]
+        pull: bool,
+        /// Afterwards upload the index to `[index] share` for this commit
+        #[arg(long)]
+        push: bool,
+    },
+    /// Serve a JSON API, a web chat and OpenAI-compatible completions for the users in `[serve.users]`
+    Serve {
+        /// Address and port to listen on (default: `serve.bind`, else 127.0.0.1:8080)
+        #[arg(long, value_name = "ADDR")]
+        bind: Option<String>,
+        /// Port to listen on, at the host of the address
+        #[arg(long)]
+        port: Option<u16>,
+    },
+    /// Keep sessions' setup resident behind a Unix socket, for editor plugins and a faster `--prompt`
+    #[cfg(unix)]
+    Daemon {
+        /// Socket to listen on (default: `RUSTAIGENT_SOCKET`, else rustaigent.sock in the runtime directory)
+        #[arg(long, value_name = "PATH")]
+        socket: Option<std::path::PathBuf>,
+    },
+    /// Serve the library API over gRPC (sessions, turns, events and approvals), per proto/rustaigent.proto
+    #[cfg(feature = "grpc")]
+    Grpc {
+        /// Address and port to listen on (default: 127.0.0.1:50051)
+        #[arg(long, value_name = "ADDR")]
+        bind: Option<String>,
+    },
+    /// Encrypt sessions, fix checkpoints, plans and prompt history saved before `encryption` was turned on
+    Encrypt,
+    /// Securely delete stored sessions, checkpoints, prompt history, usage logs and caches
+    Purge {
+        /// Everything under the data directory
+        #[arg(long, conflicts_with_all = ["session", "older_than"])]
+        all: bool,
+        /// One autosaved session, by the id in its file name
+        #[arg(long, value_name = "ID", conflicts_with = "older_than", add = ArgValueCompleter::new(complete_sessions))]
+        session: Option<String>,
+        /// Data last written longer ago than this (e.g. 30d)
+        #[arg(long, value_name = "AGE", value_parser = humantime::parse_duration)]
+        older_than: Option<std::time::Duration>,
+    },
+    /// Share sessions as portable bundles
+    Session {
+        #[command(subcommand)]
+        action: SessionAction,
+    },
+    /// Evaluate tool-call policy rules
+    Policy {
+        #[command(subcommand)]
+        action: PolicyAction,
+    },
+    /// Install and manage tool plugins from the plugin registry
+    Plugins {
+        #[command(subcommand)]
+        action: PluginsAction,
+    },
+}
 
-        let mut results = Vec::new();
-        for task in join_all(tasks).await {
-            if let Ok(Ok(msg)) = task {
-                results.push(msg);
-            }
-        }
-        Ok(results)
+#[derive(Subcommand)]
+enum SessionAction {
+    /// Continue a session exported with `/export <file>.json`
+    Import {
+        #[arg(add = ArgValueCompleter::new(complete_bundles))]
+        bundle: std::path::PathBuf,
+        /// Print the transcript, tool calls and file list instead of continuing
+        #[arg(long)]
+        inspect: bool,
+        /// Write the bundled file snapshots into the current directory first
+        #[arg(long)]
+        restore_files: bool,
+    },
+    /// Print a bundle as a markdown notebook of prompts, answers and tool cells
+    Notebook {
+        #[arg(add = ArgValueCompleter::new(complete_bundles))]
+        bundle: std::path::PathBuf,
+    },
+    /// Re-execute the tool, rust and shell cells of a notebook in order, refreshing their outputs
+    Rerun {
+        notebook: std::path::PathBuf,
+        /// Overwrite the notebook instead of printing the result
+        #[arg(long)]
+        in_place: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum ModelsAction {
+    /// Download a model (default: the configured one) with a progress bar
+    Pull { name: Option<String> },
+    /// Show a model's family, size, quantization, context length and parameters
+    Show { name: Option<String> },
+    /// Delete a model from the server
+    Rm { name: String },
+    /// Show the loaded models, how much of each is in GPU memory, and their context size
+    Ps,
+}
+
+#[derive(Subcommand)]
+enum PluginsAction {
+    /// Fetch a plugin from the registry, verify its checksum and signature, and install it
+    Install { name: String },
+    /// List the installed plugins
+    List,
+    /// Uninstall a plugin
+    Remove { name: String },
+}
+
+#[derive(Subcommand)]
+enum LogsAction {
+    /// Print a `--log-file` written at -vv with its sealed request and response bodies decrypted
+    Decrypt { file: std::path::PathBuf },
+}
+
+#[derive(Subcommand)]
+enum QueueAction {
+    /// Submit due jobs, poll submitted batches and save finished answers
+    Status,
+}
+
+#[derive(Subcommand)]
+enum PolicyAction {
+    /// Show which rule decides a tool call, e.g. `policy test run_command '{"command":"git push"}'`
+    Test {
+        tool: String,
+        /// Arguments as a JSON object
+        #[arg(default_value = "{}")]
+        args: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Print the team, personal and project config files
+    Show {
+        /// Print the effective settings after merging, with the source of each value
+        #[arg(long)]
+        merged: bool,
+    },
+    /// Check config files for unknown keys, wrong types, deprecated options and missing files
+    Validate,
+    /// List the profiles defined in the config files and the built-in ones
+    Profiles,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ContextSource {
+    /// Uncommitted changes in the git working tree, refreshed whenever they change
+    GitDiff,
+    /// The file and selection open in the editor, as an editor plugin writes them to `.rustaigent/focus.json`
+    Editor,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum OutputFormat {
+    /// The whole answer
+    Text,
+    /// Only the first fenced code block
+    Code,
+    /// A unified diff for `git apply`
+    Patch,
+    /// Token chunks, tool calls, tool results and the final message as JSON lines, as they happen
+    Ndjson,
+}
+
+impl Cli {
+    /// Whether a running daemon can answer `--prompt`: only when nothing set on the command line
+    /// shapes the session
+    #[cfg(unix)]
+    fn daemon_answers(&self) -> bool {
+        self.prompt.is_some() && !self.no_daemon && !self.json && !matches!(self.output, OutputFormat::Ndjson)
+            && self.upload.is_empty() && self.template.is_none() && self.context.is_empty() && self.root.is_empty()
+            && self.tag.is_empty() && self.package.is_none() && self.profile.is_none()
+            && !(self.yolo || self.dry_run || self.verify)
     }
 
-    fn clone_for_batch(&self, user_input: String) -> Self {
-        let mut cloned = Agent::new(self.api_key.clone(), self.provider.clone());
-        cloned.google_api_key = self.google_api_key.clone();
-        cloned.max_tokens = self.max_tokens;
-        cloned.temperature = self.temperature;
-        cloned.retry_count = self.retry_count;
-        cloned.backoff_base = self.backoff_base;
-        cloned.conversation = vec![self.conversation[0].clone(), ChatMessage { role: "user".into(), content: user_input, name: None }];
-        cloned.functions = self.functions.clone();
-        cloned
+    /// Whether the session can start offline when no provider is set up: only the REPL has
+    /// anything to do without the model
+    fn offline_allowed(&self) -> bool {
+        self.capabilities || self.prompt.is_none() && !self.stdio_editor
+            && matches!(self.command, None | Some(Commands::Doctor) | Some(Commands::Session { action: SessionAction::Import { .. } }))
     }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum StatsFormat {
+    Table,
+    Csv,
+    Json,
+}
+
+/// Completion candidates among `names` that start with what was typed, with their descriptions
+fn candidates(current: &std::ffi::OsStr, names: impl IntoIterator<Item = (String, Option<String>)>) -> Vec<CompletionCandidate> {
+    let typed = current.to_string_lossy();
+    names.into_iter()
+        .filter(|(name, _)| name.starts_with(typed.as_ref()))
+        .map(|(name, help)| CompletionCandidate::new(name).help(help.map(Into::into)))
+        .collect()
+}
+
+/// A saved session's date and first prompt, as `/sessions` shows them
+fn describe(bundle: &session::Bundle) -> String {
+    let when = chrono::DateTime::from_timestamp(bundle.exported as i64, 0).map(|at| at.format("%Y-%m-%d %H:%M").to_string()).unwrap_or_default();
+    let first = bundle.messages.iter().find(|m| m.role == "user").map(|m| m.content.lines().next().unwrap_or_default()).unwrap_or_default();
+    format!("{} {}", when, first.chars().take(48).collect::<String>())
+}
 
-    // run() remains unchanged, routing through send_request and batch if needed
+/// `--profile`: profiles defined in the config files, and the built-in ones
+fn complete_profiles(current: &std::ffi::OsStr) -> Vec<CompletionCandidate> {
+    let profiles = config::Layered::load().map(|layered| layered.profiles).unwrap_or_default();
+    candidates(current, profiles.into_iter().map(|(name, files)| {
+        let defined = files.iter().map(|f| f.display().to_string()).collect::<Vec<_>>().join(", ");
+        (name, Some(if defined.is_empty() { "built-in".into() } else { defined }))
+    }))
+}
+
+/// `--template`: templates in the project and user template directories
+fn complete_templates(current: &std::ffi::OsStr) -> Vec<CompletionCandidate> {
+    candidates(current, templates::list().into_iter().map(|(name, description, _)| (name, description)))
+}
+
+/// `purge --session`: ids of the autosaved sessions, newest first
+fn complete_sessions(current: &std::ffi::OsStr) -> Vec<CompletionCandidate> {
+    candidates(current, autosave::list().into_iter().map(|(_, bundle)| (bundle.id.clone(), Some(describe(&bundle)))))
+}
+
+/// Session bundle arguments: the autosaved bundles, then files as usual
+fn complete_bundles(current: &std::ffi::OsStr) -> Vec<CompletionCandidate> {
+    let saved = autosave::list().into_iter().map(|(path, bundle)| (path.display().to_string(), Some(describe(&bundle))));
+    let mut found = candidates(current, saved);
+    found.extend(PathCompleter::any().complete(current));
+    found
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    // Answers completion requests from the registered shell script, then exits
+    clap_complete::CompleteEnv::with_factory(Cli::command).complete();
+    let cli = Cli::parse();
+    if cli.prompt.is_some() && cli.command.is_some() {
+        Cli::command().error(clap::error::ErrorKind::ArgumentConflict, "--prompt answers one prompt without a subcommand").exit();
+    }
+    if cli.capabilities && cli.command.is_some() {
+        Cli::command().error(clap::error::ErrorKind::ArgumentConflict, "--capabilities describes the build without a subcommand").exit();
+    }
+    logging::init(cli.verbose, cli.quiet, cli.log_file.as_deref())?;
     dotenv().ok();
-    let api_key = env::var("OPENAI_API_KEY").context("Missing API key")?;
-    let provider = env::var("API_PROVIDER").unwrap_or_else(|_| "openai".into());
-    env_logger::init();
-    let mut agent = Agent::new(api_key, provider);
+    if let Some(profile) = &cli.profile {
+        config::use_profile(profile);
+        // Fail here rather than silently running with the wrong settings
+        config::Layered::load()?;
+    }
+    let mut imported = None;
+    let mut first_prompt = None;
+    let mut follow_logs = None;
+    let mut fix_loop = None;
+    let mut work_on = None;
+    let mut pr_describe = None;
+    let mut review = None;
+    let mut models_action = None;
+    let mut queue_jobs = None;
+    let mut batch_job = None;
+    let mut doctor = false;
+    #[cfg(unix)]
+    let daemon_answers = cli.daemon_answers();
+    let offline_allowed = cli.offline_allowed();
+    match cli.command {
+        Some(Commands::Stats { since, format, html }) => {
+            let window = humantime::parse_duration(&since).with_context(|| format!("Invalid --since value `{}`", since))?;
+            let filter = cli.tag.iter().map(|tag| stats::parse_tag(tag)).collect::<Result<stats::Tags>>()?;
+            let summary = stats::summarize(&stats::load(window)?, config::Config::load().pricing.as_ref(), &filter);
+            if let Some(path) = html {
+                std::fs::write(&path, report::html(&summary, &since, &filter)).with_context(|| format!("Failed to write {}", path.display()))?;
+                println!("Wrote {}", path.display());
+                return Ok(());
+            }
+            match format {
+                StatsFormat::Table => print!("{}", stats::render_table(&summary)),
+                StatsFormat::Csv => print!("{}", stats::render_csv(&summary)),
+                StatsFormat::Json => println!("{}", serde_json::to_string_pretty(&summary)?),
+            }
+            return Ok(());
+        }
+        Some(Commands::Config { action: ConfigAction::Show { merged: true } }) => {
+            let layered = config::Layered::load()?;
+            if let Some(profile) = &layered.profile {
+                println!("# profile: {}", profile);
+            }
+            print!("{}", layered.with_env().render());
+            return Ok(());
+        }
+        Some(Commands::Config { action: ConfigAction::Show { merged: false } }) => {
+            let layers = [("team", paths::team_config_file()), ("user", Some(paths::user_config_file())), ("project", paths::project_config_file())];
+            for (label, path) in layers {
+                match path.filter(|p| p.is_file()) {
+                    Some(p) => println!("# {} ({})\n{}", label, p.display(), std::fs::read_to_string(&p)?),
+                    None => println!("# {}: not present\n", label),
+                }
+            }
+            return Ok(());
+        }
+        Some(Commands::Config { action: ConfigAction::Profiles }) => {
+            let layered = config::Layered::load()?;
+            for (name, files) in &layered.profiles {
+                let files: Vec<String> = match files.is_empty() {
+                    true => vec!["built in".into()],
+                    false => files.iter().map(|p| p.display().to_string()).collect(),
+                };
+                let mark = if layered.profile.as_ref() == Some(name) { " (active)" } else { "" };
+                println!("{}{}  {}", name, mark, files.join(", "));
+            }
+            return Ok(());
+        }
+        Some(Commands::Config { action: ConfigAction::Validate }) => {
+            let results = config::validate_files();
+            if results.is_empty() {
+                println!("No config files found");
+            }
+            let mut errors = 0;
+            for (path, report) in &results {
+                println!("{}", path.display());
+                for e in &report.errors {
+                    println!("  error: {}", e);
+                }
+                for w in &report.warnings {
+                    println!("  warning: {}", w);
+                }
+                if report.errors.is_empty() && report.warnings.is_empty() {
+                    println!("  ok");
+                }
+                errors += report.errors.len();
+            }
+            if errors > 0 {
+                anyhow::bail!("{} configuration error(s)", errors);
+            }
+            return Ok(());
+        }
+        Some(Commands::Policy { action: PolicyAction::Test { tool, args } }) => {
+            let args: serde_json::Value = serde_json::from_str(&args).context("Arguments must be a JSON object")?;
+            let call = tools::ToolCall::parse(&tool, args)?;
+            let policy = policy::Policy::from_config(&config::Config::load());
+            println!("{}", policy.evaluate(&policy::Call::now(&call, policy.role.as_deref())));
+            if let tools::ToolCall::RunCommand { command, .. } = &call {
+                let assessment = risk::classify(command);
+                match assessment.score {
+                    0 => println!("risk 0/10: no rule matches"),
+                    score => println!("risk {}/10: {}", score, assessment.findings.join(", ")),
+                }
+            }
+            return Ok(());
+        }
+        Some(Commands::Plugins { action }) => {
+            match action {
+                PluginsAction::Install { name } => plugins::install(&config::Config::load().plugins.unwrap_or_default(), &name).await?,
+                PluginsAction::List => {
+                    let installed = plugins::list()?;
+                    if installed.is_empty() {
+                        println!("No plugins installed in {}", plugins::dir().display());
+                    }
+                    installed.iter().for_each(|line| println!("{}", line));
+                }
+                PluginsAction::Remove { name } => {
+                    plugins::remove(&name)?;
+                    println!("Removed {}", name);
+                }
+            }
+            return Ok(());
+        }
+        Some(Commands::Workspace) => {
+            let cwd = env::current_dir()?;
+            let ws = workspace::Workspace::detect(&cwd).with_context(|| format!("{} is not inside a cargo project", cwd.display()))?;
+            println!("{}", ws.root.display());
+            for line in ws.listing().lines() {
+                let scoped = cli.package.as_deref().is_some_and(|p| line.starts_with(&format!("{} ", p)));
+                println!("{} {}", if scoped { "*" } else { " " }, line);
+            }
+            return Ok(());
+        }
+        Some(Commands::Logs { action: Some(LogsAction::Decrypt { file }), .. }) => {
+            let text = std::fs::read_to_string(&file).with_context(|| format!("Failed to read {}", file.display()))?;
+            print!("{}", logging::decrypt(&text)?);
+            return Ok(());
+        }
+        Some(Commands::Logs { action: None, tail, pattern, every, max_lines }) => {
+            let pattern = regex::Regex::new(&pattern).context("Invalid --pattern")?;
+            follow_logs = Some((tail.context("--tail is required")?, pattern, every, max_lines));
+        }
+        Some(Commands::PlanReview { dir, plan_file }) => {
+            let summary = tools::terraform::plan(std::path::Path::new("."), &dir, plan_file.as_deref(), config::Config::load().command_timeout_secs, &Default::default())?;
+            println!("{}", summary);
+            first_prompt = Some(tools::terraform::review_prompt(&summary));
+        }
+        Some(Commands::Models { action }) => {
+            models_action = Some(action);
+        }
+        Some(Commands::Review { base }) => {
+            review = Some(base);
+        }
+        Some(Commands::Fix { command, max_iterations, max_time, max_cost, until_tests_pass, until_exists, resume, plan }) => {
+            let max_time = max_time.map(|t| humantime::parse_duration(&t).with_context(|| format!("Invalid --max-time value `{}`", t))).transpose()?;
+            let stop = fix::StopConditions { max_time, max_cost, tests_pass: None, file_exists: until_exists };
+            fix_loop = Some((command, max_iterations, stop, until_tests_pass, resume, plan));
+        }
+        Some(Commands::PrDescribe { base, push, output }) => {
+            pr_describe = Some((base, push, output));
+        }
+        Some(Commands::WorkOn { issue, max_iterations }) => {
+            workon::IssueRef::parse(&issue)?;
+            work_on = Some((issue, max_iterations));
+        }
+        Some(Commands::Batch { input, output, batch_api, resume, every, concurrency }) => {
+            let every = humantime::parse_duration(&every).with_context(|| format!("Invalid --every value `{}`", every))?;
+            batch_job = Some((input, batch::Options { output, batch_api, resume, every, concurrency }));
+        }
+        Some(Commands::Queue { action, mut prompts, file, now }) => {
+            if let Some(path) = file {
+                let text = std::fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+                prompts.extend(text.lines().map(String::from));
+            }
+            queue_jobs = Some((action, prompts, now));
+        }
+        Some(Commands::Sync) => {
+            let config = config::Config::load();
+            return sync::run(config.sync.as_ref(), &http::client(config.http.as_ref())).await;
+        }
+        Some(Commands::Index { rebuild, pull, push }) => {
+            let config = config::Config::load();
+            if pull {
+                index::pull(&config).await?;
+            }
+            index::build(&config, rebuild).await?;
+            if push {
+                index::push(&config).await?;
+            }
+            return Ok(());
+        }
+        Some(Commands::Serve { bind, port }) => return serve::run(bind, port).await,
+        #[cfg(unix)]
+        Some(Commands::Daemon { socket }) => return rustaigent::daemon::run(socket).await,
+        #[cfg(feature = "grpc")]
+        Some(Commands::Grpc { bind }) => return rustaigent::grpc::run(bind).await,
+        Some(Commands::Purge { all, session, older_than }) => {
+            let scope = match (all, session, older_than) {
+                (true, _, _) => purge::Scope::All,
+                (_, Some(id), _) => purge::Scope::Session(id),
+                (_, _, Some(age)) => purge::Scope::OlderThan(age),
+                _ => anyhow::bail!("Choose what to purge: --all, --session <ID> or --older-than <AGE>"),
+            };
+            return purge::run(&scope, cli.dry_run);
+        }
+        Some(Commands::Encrypt) => {
+            println!("Encrypted {} stored file(s)", vault::seal_existing()?);
+            return Ok(());
+        }
+        Some(Commands::Replay { session }) => {
+            let bundle = session::Bundle::load(&session)?;
+            let theme = theme::Theme::detect(config::Config::load().theme.as_ref());
+            return replay::run(&bundle, &theme);
+        }
+        Some(Commands::Session { action: SessionAction::Notebook { bundle } }) => {
+            print!("{}", notebook::render(&session::Bundle::load(&bundle)?));
+            return Ok(());
+        }
+        Some(Commands::Session { action: SessionAction::Rerun { notebook: path, in_place } }) => {
+            let text = std::fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+            let config = config::Config::load();
+            let mut ctx = tools::ToolContext::default();
+            ctx.policy = policy::Policy::from_config(&config);
+            ctx.policy.yolo = cli.yolo;
+            ctx.dry_run = cli.dry_run;
+            ctx.theme = theme::Theme::detect(config.theme.as_ref());
+            let refreshed = notebook::rerun(&text, &mut ctx).await?;
+            if in_place {
+                std::fs::write(&path, refreshed)?;
+            } else {
+                print!("{}", refreshed);
+            }
+            return Ok(());
+        }
+        Some(Commands::Session { action: SessionAction::Import { bundle, inspect, restore_files } }) => {
+            let bundle = session::Bundle::load(&bundle)?;
+            if inspect {
+                pager::show(&bundle.render());
+                return Ok(());
+            }
+            if restore_files {
+                for path in bundle.restore_files()? {
+                    println!("Restored {}", path);
+                }
+            }
+            imported = Some(bundle);
+        }
+        Some(Commands::Init) => return init::run().await,
+        Some(Commands::Doctor) => doctor = true,
+        Some(Commands::SelfUpdate { check }) => return self_update::run(check).await,
+        Some(Commands::Completions { shell }) => {
+            // The script calls back into this binary, so completions track the CLI and local state
+            let shells = clap_complete::env::Shells::builtins();
+            let completer = shells.completer(&shell).context("Unsupported shell")?;
+            let exe = env::current_exe()?;
+            completer.write_registration("COMPLETE", "rustaigent", "rustaigent", &exe.to_string_lossy(), &mut io::stdout())?;
+            return Ok(());
+        }
+        None => {}
+    }
+    let output = match cli.output {
+        OutputFormat::Text => oneshot::Output::Text,
+        OutputFormat::Code => oneshot::Output::Code,
+        OutputFormat::Patch => oneshot::Output::Patch,
+        OutputFormat::Ndjson => oneshot::Output::Ndjson,
+    };
+    // Read before anything is set up, so a daemon can answer from what it keeps loaded
+    let input = match &cli.prompt {
+        Some(_) => oneshot::read_stdin()?,
+        None => None,
+    };
+    #[cfg(unix)]
+    if let Some(prompt) = cli.prompt.as_deref().filter(|_| daemon_answers) {
+        if let Some(selected) = rustaigent::daemon::prompt(prompt, input.as_deref(), output).await? {
+            oneshot::print(&selected, output);
+            return Ok(());
+        }
+    }
+    let scope = cli.package.as_deref().map(workspace::resolve).transpose()?;
+    let mut builder = AgentBuilder::new();
+    if offline_allowed {
+        builder = builder.or_offline();
+    }
+    // API_PROVIDER still wins over the provider an imported session was recorded with
+    if let Some(bundle) = imported.as_ref().filter(|_| env::var("API_PROVIDER").is_err()) {
+        builder = builder.provider(&bundle.provider);
+    }
+    let mut agent = builder.build()?;
+    if cli.a11y {
+        agent.enable_a11y();
+    }
+    if cli.quiet || cli.prompt.is_some() {
+        agent.enable_quiet();
+    }
+    if cli.yolo {
+        agent.enable_yolo();
+    }
+    if cli.dry_run {
+        agent.enable_dry_run();
+    }
+    if cli.verify {
+        agent.enable_verify();
+    }
+    for source in &cli.context {
+        match source {
+            ContextSource::GitDiff => agent.enable_diff_context(cli.diff_base.clone()),
+            ContextSource::Editor => agent.enable_focus(),
+        }
+    }
+    if let Some(bundle) = imported {
+        agent.resume(bundle);
+    }
+    for root in &cli.root {
+        let (name, dir) = root.split_once('=').with_context(|| format!("Expected --root NAME=PATH, not `{}`", root))?;
+        agent.add_root(name, std::path::Path::new(dir))?;
+    }
+    for tag in &cli.tag {
+        let (name, value) = stats::parse_tag(tag)?;
+        agent.tag(name, value);
+    }
+    if let Some((_, member)) = scope {
+        agent.scope_to(&member);
+    }
+    if let Some(action) = models_action {
+        return match action {
+            None => models::list(&agent).await,
+            Some(ModelsAction::Pull { name }) => models::pull(&agent, name).await,
+            Some(ModelsAction::Show { name }) => models::show(&agent, name).await,
+            Some(ModelsAction::Rm { name }) => models::rm(&agent, &name).await,
+            Some(ModelsAction::Ps) => models::ps(&agent).await,
+        };
+    }
+    models::prepare(&mut agent).await;
+    if let Some((input, options)) = batch_job {
+        let result = batch::run(&agent, &input, options).await;
+        agent.save_stats();
+        return result;
+    }
+    if let Some((action, prompts, now)) = queue_jobs {
+        let result = match action {
+            Some(QueueAction::Status) => queue::status(&agent).await,
+            None => queue::add(&agent, prompts, now).await,
+        };
+        agent.save_stats();
+        return result;
+    }
+    mcp::connect(&mut agent).await;
+    plugins::load(&mut agent);
+    if let Some(name) = &cli.template {
+        agent.apply_template(name).await?;
+    }
+    if cli.capabilities {
+        println!("{}", serde_json::to_string_pretty(&agent.handshake())?);
+        return Ok(());
+    }
+    if doctor {
+        print!("{}", agent.doctor());
+        return Ok(());
+    }
+    if cli.stdio_editor {
+        let result = stdio::run(&mut agent).await;
+        agent.save_stats();
+        return result;
+    }
+    if let Some(prompt) = &cli.prompt {
+        if input.is_some() {
+            // stdin carried the input, so nobody is there to answer approval prompts
+            agent.enable_unattended();
+        }
+        for path in &cli.upload {
+            agent.upload(path).await?;
+        }
+        let result = oneshot::run(&mut agent, prompt, input, output, cli.json).await;
+        agent.save_stats();
+        return result;
+    }
+    if let Some(base) = review {
+        first_prompt = Some(review::prompt(&agent, base).await?);
+    }
+    if follow_logs.is_none() {
+        agent.checkpoint();
+        autosave::install();
+    }
+    if let Some(prompt) = first_prompt {
+        agent.ask(&prompt).await?;
+    }
+    if let Some((path, pattern, every, max_lines)) = follow_logs {
+        return logs::follow(&mut agent, path, pattern, every, max_lines).await;
+    }
+    if let Some((command, max_iterations, mut stop, until_tests_pass, resume, plan)) = fix_loop {
+        let plan = plan || agent.config().plan.unwrap_or(false);
+        if until_tests_pass {
+            stop.tests_pass = Some(fix::test_command(&agent, &std::env::current_dir()?).context("No project manifest found for --until-tests-pass")?);
+        }
+        let result = fix::run(&mut agent, command, max_iterations, stop, resume, plan).await;
+        agent.save_stats();
+        return result;
+    }
+    if let Some((base, push, output)) = pr_describe {
+        let result = pr::run(&mut agent, base, push, output).await;
+        agent.save_stats();
+        return result;
+    }
+    if let Some((issue, max_iterations)) = work_on {
+        let result = workon::run(&mut agent, &issue, max_iterations).await;
+        agent.save_stats();
+        return result;
+    }
     agent.run().await?;
+    if let Some(cfg) = agent.config().sync.clone() {
+        // Keep the session where `rustaigent sync` picks it up
+        agent.checkpoint();
+        if let Err(err) = autosave::save() {
+            warn!("failed to save the session: {:#}", err);
+        }
+        if cfg.auto.unwrap_or(false) {
+            if let Err(err) = sync::run(Some(&cfg), agent.client()).await {
+                warn!("sync failed: {:#}", err);
+            }
+        }
+    }
     Ok(())
 }
@@ -0,0 +1,387 @@
+//! MCP (Model Context Protocol) client: the tools of external servers, reached over stdio or SSE,
+//! are offered to the model next to the built-in ones and their calls are sent back to the server
+//! as JSON-RPC requests.
+
+use std::{collections::{BTreeMap, HashMap}, process::Stdio, sync::{Arc, Mutex, atomic::{AtomicU64, Ordering}}, time::Duration};
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+use anyhow::{Result, Context, anyhow, bail};
+use futures::future::BoxFuture;
+use tokio::{io::{AsyncBufReadExt, AsyncWriteExt, BufReader}, process::{Child, Command}, sync::{mpsc, oneshot}};
+use tracing::{debug, info, warn};
+use crate::{tools::{Tool, HIDDEN_ENV}, Agent, FunctionDefinition};
+
+/// Protocol revision asked for in the handshake; servers answer with the one they speak
+const PROTOCOL_VERSION: &str = "2024-11-05";
+
+/// Time a server has to start, answer the handshake and list its tools
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Seconds a tool call may take when the server sets no `timeout_secs`
+const DEFAULT_CALL_TIMEOUT_SECS: u64 = 120;
+
+/// Longest function name the providers accept
+const MAX_NAME: usize = 64;
+
+/// One `[mcp.<name>]` table: a command to spawn, or the SSE endpoint of a running server
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct ServerConfig {
+    /// Program speaking MCP on its stdin and stdout
+    pub command: Option<String>,
+    pub args: Option<Vec<String>>,
+    /// Extra environment variables for `command`
+    pub env: Option<BTreeMap<String, String>>,
+    /// SSE endpoint of a server that is already running
+    pub url: Option<String>,
+    /// Headers sent to `url`, e.g. `Authorization`
+    pub headers: Option<BTreeMap<String, String>>,
+    /// The server's tools to offer; all of them when unset
+    pub tools: Option<Vec<String>>,
+    /// Seconds a tool call may take
+    pub timeout_secs: Option<u64>,
+}
+
+/// Keys accepted in `[mcp.<name>]`, for config validation
+pub const KEYS: &[&str] = &["command", "args", "env", "url", "headers", "tools", "timeout_secs"];
+
+impl ServerConfig {
+    /// Exactly one transport, with a URL the client can reach
+    pub fn check(&self) -> Result<()> {
+        match (&self.command, &self.url) {
+            (Some(_), Some(_)) => bail!("set either `command` or `url`, not both"),
+            (None, None) => bail!("set `command` for a stdio server or `url` for an SSE server"),
+            (None, Some(url)) if !url.starts_with("http://") && !url.starts_with("https://") => bail!("`url` must start with http:// or https:// (found `{}`)", url),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Requests waiting for their response, by JSON-RPC id
+type Pending = Arc<Mutex<HashMap<u64, oneshot::Sender<Value>>>>;
+
+/// A connection to one server, shared by its tools
+struct Client {
+    server: String,
+    outgoing: mpsc::UnboundedSender<Value>,
+    pending: Pending,
+    next_id: AtomicU64,
+    call_timeout: Duration,
+    /// A spawned server, killed when the last of its tools is dropped
+    _child: Option<Child>,
+}
+
+impl Client {
+    async fn request(&self, method: &str, params: Value, timeout: Duration) -> Result<Value> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id, tx);
+        self.outgoing.send(json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }))
+            .map_err(|_| anyhow!("The MCP server `{}` has disconnected", self.server))?;
+        let reply = match tokio::time::timeout(timeout, rx).await {
+            Ok(Ok(reply)) => reply,
+            Ok(Err(_)) => bail!("The MCP server `{}` closed the connection", self.server),
+            Err(_) => {
+                self.pending.lock().unwrap().remove(&id);
+                bail!("The MCP server `{}` did not answer `{}` within {}", self.server, method, humantime::format_duration(timeout));
+            }
+        };
+        if let Some(error) = reply.get("error") {
+            bail!("The MCP server `{}` failed `{}`: {}", self.server, method, error["message"].as_str().unwrap_or("no details"));
+        }
+        Ok(reply["result"].clone())
+    }
+
+    fn notify(&self, method: &str) {
+        self.outgoing.send(json!({ "jsonrpc": "2.0", "method": method })).ok();
+    }
+}
+
+/// Hand a response to the request waiting for it; the server's own requests get an answer too,
+/// though only pings are supported
+fn dispatch(server: &str, message: Value, pending: &Pending, outgoing: &mpsc::UnboundedSender<Value>) {
+    match (message.get("id"), message.get("method").and_then(Value::as_str)) {
+        (Some(id), Some("ping")) => {
+            outgoing.send(json!({ "jsonrpc": "2.0", "id": id, "result": {} })).ok();
+        }
+        (Some(id), Some(method)) => {
+            let error = json!({ "code": -32601, "message": format!("`{}` is not supported by this client", method) });
+            outgoing.send(json!({ "jsonrpc": "2.0", "id": id, "error": error })).ok();
+        }
+        (None, Some(method)) => debug!("MCP server `{}` sent {}", server, method),
+        (Some(id), None) => {
+            if let Some(waiting) = id.as_u64().and_then(|id| pending.lock().unwrap().remove(&id)) {
+                waiting.send(message).ok();
+            }
+        }
+        (None, None) => debug!("MCP server `{}` sent a message that is neither a request nor a response", server),
+    }
+}
+
+/// Spawn the server in `dir` with pipes for newline-delimited JSON-RPC; its stderr goes to the log
+fn stdio(dir: &std::path::Path, server: &str, command: &str, cfg: &ServerConfig, pending: Pending) -> Result<(mpsc::UnboundedSender<Value>, Child)> {
+    let mut process = Command::new(command);
+    process.args(cfg.args.iter().flatten()).current_dir(dir);
+    for var in HIDDEN_ENV {
+        process.env_remove(var);
+    }
+    let mut child = process.envs(cfg.env.iter().flatten())
+        .stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn().with_context(|| format!("Failed to start `{}`", command))?;
+    let mut stdin = child.stdin.take().context("no stdin pipe")?;
+    let stdout = child.stdout.take().context("no stdout pipe")?;
+    let stderr = child.stderr.take().context("no stderr pipe")?;
+
+    let (outgoing, mut queue) = mpsc::unbounded_channel::<Value>();
+    tokio::spawn(async move {
+        while let Some(message) = queue.recv().await {
+            let line = format!("{}\n", message);
+            if stdin.write_all(line.as_bytes()).await.is_err() || stdin.flush().await.is_err() {
+                break;
+            }
+        }
+    });
+    let (name, replies) = (server.to_string(), outgoing.clone());
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(stdout).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            match serde_json::from_str(&line) {
+                Ok(message) => dispatch(&name, message, &pending, &replies),
+                Err(_) => debug!("MCP server `{}`: {}", name, line),
+            }
+        }
+        // Dropping the waiting senders fails the requests in flight
+        pending.lock().unwrap().clear();
+    });
+    let name = server.to_string();
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(stderr).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            debug!("MCP server `{}`: {}", name, line);
+        }
+    });
+    Ok((outgoing, child))
+}
+
+/// Server-sent events read off a response as `(event, data)` pairs
+struct Events {
+    response: reqwest::Response,
+    buffer: Vec<u8>,
+    event: String,
+    data: Vec<String>,
+}
+
+impl Events {
+    async fn next(&mut self) -> Result<Option<(String, String)>> {
+        loop {
+            while let Some(end) = self.buffer.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = self.buffer.drain(..=end).collect();
+                let line = String::from_utf8_lossy(&line);
+                let line = line.trim_end_matches(['\n', '\r']);
+                if line.is_empty() {
+                    let event = std::mem::take(&mut self.event);
+                    if !self.data.is_empty() {
+                        let kind = if event.is_empty() { "message".to_string() } else { event };
+                        return Ok(Some((kind, std::mem::take(&mut self.data).join("\n"))));
+                    }
+                } else if let Some(event) = line.strip_prefix("event:") {
+                    self.event = event.trim().to_string();
+                } else if let Some(data) = line.strip_prefix("data:") {
+                    self.data.push(data.strip_prefix(' ').unwrap_or(data).to_string());
+                }
+            }
+            match self.response.chunk().await? {
+                Some(chunk) => self.buffer.extend_from_slice(&chunk),
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+/// Open the event stream, wait for the endpoint it names and POST requests there; responses
+/// arrive as `message` events
+async fn sse(server: &str, url: &str, cfg: &ServerConfig, http: &reqwest::Client, pending: Pending) -> Result<mpsc::UnboundedSender<Value>> {
+    let mut headers = reqwest::header::HeaderMap::new();
+    for (name, value) in cfg.headers.iter().flatten() {
+        headers.insert(reqwest::header::HeaderName::from_bytes(name.as_bytes())?, value.parse().with_context(|| format!("Invalid value for header `{}`", name))?);
+    }
+    let response = http.get(url).headers(headers.clone()).header(reqwest::header::ACCEPT, "text/event-stream").send().await?.error_for_status()?;
+    let mut events = Events { response, buffer: Vec::new(), event: String::new(), data: Vec::new() };
+    let endpoint = loop {
+        match events.next().await? {
+            Some((event, data)) if event == "endpoint" => break reqwest::Url::parse(url)?.join(data.trim())?,
+            Some(_) => continue,
+            None => bail!("The event stream ended before the server named its message endpoint"),
+        }
+    };
+    debug!("MCP server `{}` takes messages at {}", server, endpoint);
+
+    let (outgoing, mut queue) = mpsc::unbounded_channel::<Value>();
+    let (name, http) = (server.to_string(), http.clone());
+    tokio::spawn(async move {
+        while let Some(message) = queue.recv().await {
+            let sent = http.post(endpoint.clone()).headers(headers.clone()).json(&message).send().await.and_then(|r| r.error_for_status());
+            if let Err(err) = sent {
+                warn!("MCP server `{}` did not take a message: {}", name, err);
+            }
+        }
+    });
+    let (name, replies) = (server.to_string(), outgoing.clone());
+    tokio::spawn(async move {
+        while let Ok(Some((event, data))) = events.next().await {
+            if event != "message" {
+                continue;
+            }
+            match serde_json::from_str(&data) {
+                Ok(message) => dispatch(&name, message, &pending, &replies),
+                Err(err) => debug!("MCP server `{}` sent invalid JSON: {}", name, err),
+            }
+        }
+        pending.lock().unwrap().clear();
+    });
+    Ok(outgoing)
+}
+
+/// A tool of an MCP server, offered as `<server>__<tool>`
+struct McpTool {
+    client: Arc<Client>,
+    /// The name the server knows it by
+    name: String,
+    definition: FunctionDefinition,
+}
+
+impl Tool for McpTool {
+    fn definition(&self) -> FunctionDefinition {
+        self.definition.clone()
+    }
+
+    fn call(&self, args: Value) -> BoxFuture<'_, Result<String>> {
+        Box::pin(async move {
+            let params = json!({ "name": self.name, "arguments": args });
+            let result = self.client.request("tools/call", params, self.client.call_timeout).await?;
+            let text = render(&result);
+            if result["isError"].as_bool() == Some(true) {
+                bail!("{}", text);
+            }
+            Ok(text)
+        })
+    }
+
+    fn simulate(&self, args: &Value) -> Option<String> {
+        Some(format!("Calls `{}` on the MCP server `{}` with {}", self.name, self.client.server, args))
+    }
+
+    /// Servers search the web, read mail and wrap third-party APIs
+    fn untrusted(&self) -> bool {
+        true
+    }
+}
+
+/// The text of a tool result; content that is not text is named rather than inlined
+fn render(result: &Value) -> String {
+    let parts: Vec<String> = result["content"].as_array().into_iter().flatten().map(|part| match part["type"].as_str() {
+        Some("text") => part["text"].as_str().unwrap_or_default().to_string(),
+        Some("resource") => part["resource"]["text"].as_str().map(String::from)
+            .unwrap_or_else(|| format!("[resource {}]", part["resource"]["uri"].as_str().unwrap_or("without a URI"))),
+        Some(kind) => format!("[{} content, {}]", kind, part["mimeType"].as_str().unwrap_or("unknown type")),
+        None => part.to_string(),
+    }).collect();
+    match result.get("structuredContent") {
+        Some(structured) if parts.is_empty() => structured.to_string(),
+        _ => parts.join("\n"),
+    }
+}
+
+/// `<server>__<tool>` with the characters providers reject replaced
+fn tool_name(server: &str, tool: &str) -> String {
+    format!("{}__{}", server, tool).chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' { c } else { '_' })
+        .take(MAX_NAME)
+        .collect()
+}
+
+/// Connect, shake hands and list the server's tools, every page of them
+async fn start(agent: &Agent, server: &str, cfg: &ServerConfig) -> Result<Vec<McpTool>> {
+    cfg.check()?;
+    let pending = Pending::default();
+    let (outgoing, child) = match (&cfg.command, &cfg.url) {
+        (Some(command), _) => stdio(&agent.tool_ctx.dir(), server, command, cfg, pending.clone()).map(|(outgoing, child)| (outgoing, Some(child)))?,
+        (None, url) => (sse(server, url.as_deref().unwrap_or_default(), cfg, agent.client(), pending.clone()).await?, None),
+    };
+    let call_timeout = Duration::from_secs(cfg.timeout_secs.unwrap_or(DEFAULT_CALL_TIMEOUT_SECS));
+    let client = Arc::new(Client { server: server.to_string(), outgoing, pending, next_id: AtomicU64::new(1), call_timeout, _child: child });
+    let hello = json!({
+        "protocolVersion": PROTOCOL_VERSION,
+        "capabilities": {},
+        "clientInfo": { "name": "rustaigent", "version": env!("CARGO_PKG_VERSION") },
+    });
+    let info = client.request("initialize", hello, STARTUP_TIMEOUT).await?;
+    debug!("MCP server `{}` is {} (protocol {})", server, info["serverInfo"]["name"].as_str().unwrap_or("unnamed"), info["protocolVersion"].as_str().unwrap_or("unknown"));
+    client.notify("notifications/initialized");
+
+    let (mut listed, mut cursor) = (Vec::new(), None);
+    loop {
+        let params = match &cursor {
+            Some(cursor) => json!({ "cursor": cursor }),
+            None => json!({}),
+        };
+        let page = client.request("tools/list", params, STARTUP_TIMEOUT).await?;
+        listed.extend(page["tools"].as_array().cloned().unwrap_or_default());
+        match page["nextCursor"].as_str() {
+            Some(next) => cursor = Some(next.to_string()),
+            None => break,
+        }
+    }
+    Ok(listed.into_iter().filter_map(|tool| {
+        let name = tool["name"].as_str()?.to_string();
+        if cfg.tools.as_ref().is_some_and(|wanted| !wanted.contains(&name)) {
+            return None;
+        }
+        let definition = FunctionDefinition {
+            name: tool_name(server, &name),
+            description: tool["description"].as_str().unwrap_or_default().to_string(),
+            parameters: tool.get("inputSchema").cloned().unwrap_or_else(|| json!({ "type": "object", "properties": {} })),
+        };
+        Some(McpTool { client: client.clone(), name, definition })
+    }).collect())
+}
+
+/// Start the servers in `[mcp]` and offer their tools to the model. A server that fails to start is
+/// skipped with a warning, and tools named like one already offered are left out; `allowed_tools`
+/// applies to the `<server>__<tool>` names like to any other.
+pub async fn connect(agent: &mut Agent) {
+    let Some(servers) = agent.config.mcp.clone().filter(|s| !s.is_empty()) else { return };
+    let shared = &*agent;
+    let started = futures::future::join_all(servers.iter().map(|(server, cfg)| async move {
+        (server, tokio::time::timeout(STARTUP_TIMEOUT, start(shared, server, cfg)).await)
+    })).await;
+    let mut functions = agent.functions.to_vec();
+    for (server, result) in started {
+        let tools = match result {
+            Ok(Ok(tools)) => tools,
+            Ok(Err(err)) => {
+                warn!("MCP server `{}` is unavailable: {:#}", server, err);
+                continue;
+            }
+            Err(_) => {
+                warn!("MCP server `{}` did not start within {}", server, humantime::format_duration(STARTUP_TIMEOUT));
+                continue;
+            }
+        };
+        let mut offered = 0;
+        for tool in tools {
+            if agent.config.allowed_tools.as_ref().is_some_and(|allowed| !allowed.contains(&tool.definition.name)) {
+                continue;
+            }
+            if functions.iter().any(|f| f.name == tool.definition.name) {
+                warn!("MCP tool `{}` is named like a tool already offered; leaving it out", tool.definition.name);
+                continue;
+            }
+            functions.push(tool.definition.clone());
+            agent.custom_tools.push(Arc::new(tool));
+            offered += 1;
+        }
+        info!("MCP server `{}` offers {} tools", server, offered);
+    }
+    agent.functions = functions.into();
+}
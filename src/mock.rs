@@ -0,0 +1,202 @@
+//! Mock provider server for testing agent wiring without real APIs (feature `mock-server`).
+//! Point an agent at it with `API_BASE_URL` (or the `base_url` config key) and script the
+//! replies; every endpoint answers in the OpenAI chat-completions format unless a native
+//! stream is scripted.
+
+use serde_json::{json, Value};
+use wiremock::{matchers::{method, path}, Mock, MockServer, ResponseTemplate};
+
+pub struct MockProvider {
+    server: MockServer,
+}
+
+fn completion(message: Value, finish_reason: &str) -> Value {
+    json!({
+        "id": "chatcmpl-mock",
+        "object": "chat.completion",
+        "model": "mock",
+        "choices": [{ "index": 0, "message": message, "finish_reason": finish_reason }],
+        "usage": { "prompt_tokens": 10, "completion_tokens": 5, "total_tokens": 15 }
+    })
+}
+
+/// Content of the last message in a request body: the prompt, or the result of the last call
+#[cfg(test)]
+pub(crate) fn last_content(request: &Value) -> String {
+    request["messages"].as_array().and_then(|m| m.last()).and_then(|m| m["content"].as_str()).unwrap_or_default().to_string()
+}
+
+impl MockProvider {
+    pub async fn start() -> Self {
+        MockProvider { server: MockServer::start().await }
+    }
+
+    /// The fixed agent of the golden tests, talking to this server with one key and short backoff
+    #[cfg(test)]
+    pub(crate) fn agent(&self) -> crate::Agent {
+        self.agent_with_keys("k1")
+    }
+
+    /// `agent` with the comma-separated `keys` to rotate through; empty for none
+    #[cfg(test)]
+    pub(crate) fn agent_with_keys(&self, keys: &str) -> crate::Agent {
+        let mut agent = crate::golden::agent("openai");
+        agent.base_url = Some(self.base_url());
+        agent.keys = std::sync::Arc::new(crate::keys::KeyPool::new(keys, crate::keys::KeyStrategy::parse("")));
+        agent.backoff_base = 1;
+        agent
+    }
+
+    /// `agent` offered every built-in tool, printing nothing
+    #[cfg(test)]
+    pub(crate) fn tool_agent(&self) -> crate::Agent {
+        let mut agent = self.agent();
+        agent.functions = crate::tools::ToolCall::definitions().into();
+        agent.enable_quiet();
+        agent
+    }
+
+    /// Value for `API_BASE_URL`
+    pub fn base_url(&self) -> String {
+        self.server.uri()
+    }
+
+    async fn respond(&self, template: ResponseTemplate, times: Option<u64>, priority: u8) {
+        let mock = Mock::given(method("POST")).respond_with(template).with_priority(priority);
+        match times {
+            Some(n) => mock.up_to_n_times(n).mount(&self.server).await,
+            None => mock.mount(&self.server).await,
+        }
+    }
+
+    /// Answer every request with a plain assistant message
+    pub async fn reply(&self, content: &str) {
+        let body = completion(json!({ "role": "assistant", "content": content }), "stop");
+        self.respond(ResponseTemplate::new(200).set_body_json(body), None, 5).await;
+    }
+
+    /// Answer the next request with a plain assistant message, ahead of `reply`
+    pub async fn reply_once(&self, content: &str) {
+        let body = completion(json!({ "role": "assistant", "content": content }), "stop");
+        self.respond(ResponseTemplate::new(200).set_body_json(body), Some(1), 1).await;
+    }
+
+    /// Answer every request with a plain assistant message after `delay`
+    pub async fn slow_reply(&self, content: &str, delay: std::time::Duration) {
+        let body = completion(json!({ "role": "assistant", "content": content }), "stop");
+        self.respond(ResponseTemplate::new(200).set_body_json(body).set_delay(delay), None, 5).await;
+    }
+
+    /// Answer the next request with a function call
+    pub async fn tool_call(&self, name: &str, args: Value) {
+        let message = json!({ "role": "assistant", "content": null, "function_call": { "name": name, "arguments": args.to_string() } });
+        self.respond(ResponseTemplate::new(200).set_body_json(completion(message, "function_call")), Some(1), 1).await;
+    }
+
+    /// Serve `body` as a web page at `route`, for `fetch_url`
+    pub async fn page(&self, route: &str, body: &str) {
+        Mock::given(method("GET")).and(path(route)).respond_with(ResponseTemplate::new(200).set_body_string(body)).mount(&self.server).await;
+    }
+
+    /// Accept uploads to the Files API, returning `id` for each
+    pub async fn files(&self, id: &str) {
+        Mock::given(method("POST")).and(path("/v1/files"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "id": id, "object": "file", "purpose": "user_data" })))
+            .with_priority(1)
+            .mount(&self.server).await;
+    }
+
+    /// Reject the next `times` requests with HTTP 429 and a `retry-after` header
+    pub async fn rate_limited(&self, times: u64) {
+        let body = json!({ "error": { "message": "Rate limit reached", "type": "rate_limit_exceeded" } });
+        let template = ResponseTemplate::new(429).insert_header("retry-after", "1").set_body_json(body);
+        self.respond(template, Some(times), 1).await;
+    }
+
+    /// Fail the next `times` requests with HTTP 503 and an HTML page, as a gateway in front of the provider does
+    pub async fn unavailable(&self, times: u64) {
+        let template = ResponseTemplate::new(503).set_body_raw("<html><body>Service Temporarily Unavailable</body></html>", "text/html");
+        self.respond(template, Some(times), 1).await;
+    }
+
+    /// Reject the next request with HTTP 401 for a bad key
+    pub async fn unauthorized(&self) {
+        let body = json!({ "error": { "message": "Incorrect API key provided", "type": "invalid_request_error", "code": "invalid_api_key" } });
+        self.respond(ResponseTemplate::new(401).set_body_json(body), Some(1), 1).await;
+    }
+
+    /// Reject the next request with HTTP 400 for exceeding the context window, as OpenAI does
+    pub async fn context_overflow(&self) {
+        let body = json!({ "error": { "message": "This model's maximum context length is 8192 tokens. However, your messages resulted in 9000 tokens.", "type": "invalid_request_error", "code": "context_length_exceeded" } });
+        self.respond(ResponseTemplate::new(400).set_body_json(body), Some(1), 1).await;
+    }
+
+    /// Answer the next request with a truncated JSON body
+    pub async fn malformed_json(&self) {
+        let template = ResponseTemplate::new(200).set_body_raw(r#"{"choices":[{"message":{"role":"assist"#, "application/json");
+        self.respond(template, Some(1), 1).await;
+    }
+
+    /// Answer the next request with a server-sent event stream, one delta per piece, so pieces
+    /// can split words or multi-byte characters where a client must reassemble them
+    pub async fn stream(&self, pieces: &[&str]) {
+        let mut body = String::new();
+        for piece in pieces {
+            let chunk = json!({ "object": "chat.completion.chunk", "choices": [{ "index": 0, "delta": { "content": piece }, "finish_reason": null }] });
+            body.push_str(&format!("data: {}\n\n", chunk));
+        }
+        body.push_str("data: [DONE]\n\n");
+        self.respond(ResponseTemplate::new(200).set_body_raw(body, "text/event-stream"), Some(1), 1).await;
+    }
+
+    /// Answer the next request with a stream in another provider's native format
+    pub async fn native_stream(&self, body: &str, content_type: &str) {
+        self.respond(ResponseTemplate::new(200).set_body_raw(body.to_string(), content_type), Some(1), 1).await;
+    }
+
+    /// Values of header `name` on the requests received so far, one per request carrying it
+    pub async fn headers(&self, name: &str) -> Vec<String> {
+        self.server.received_requests().await.unwrap_or_default().iter()
+            .filter_map(|r| r.headers.get(name)?.to_str().ok().map(String::from))
+            .collect()
+    }
+
+    /// JSON bodies of the requests received so far
+    pub async fn requests(&self) -> Vec<Value> {
+        self.server.received_requests().await.unwrap_or_default().iter()
+            .filter_map(|r| serde_json::from_slice(&r.body).ok())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MockProvider;
+    use crate::turns::Turn;
+
+    #[tokio::test]
+    async fn reply() {
+        let mock = MockProvider::start().await;
+        mock.reply("pong").await;
+        let reply = mock.agent().send_request(Turn::Tools, None).await.unwrap();
+        assert_eq!(reply.content, "pong");
+        assert_eq!(mock.requests().await[0]["messages"][3]["content"], "Which one is the entry point?");
+    }
+
+    #[tokio::test]
+    async fn rate_limit_fails_over_to_the_next_key() {
+        let mock = MockProvider::start().await;
+        mock.rate_limited(1).await;
+        mock.reply("pong").await;
+        let reply = mock.agent_with_keys("k1,k2").send_request(Turn::Tools, None).await.unwrap();
+        assert_eq!(reply.content, "pong");
+        assert_eq!(mock.requests().await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn malformed_json_is_an_error() {
+        let mock = MockProvider::start().await;
+        mock.malformed_json().await;
+        assert!(mock.agent().send_request(Turn::Tools, None).await.is_err());
+    }
+}
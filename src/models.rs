@@ -0,0 +1,329 @@
+//! `rustaigent models`: list, pull, show and remove models on the Ollama server the agent talks
+//! to, and pull the configured model the first time it is used.
+
+use std::{process::Command, time::Duration};
+use indicatif::HumanBytes;
+use reqwest::Method;
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+use anyhow::{Result, Context, bail};
+use tracing::{debug, info, warn};
+use crate::{http, progress, Agent};
+
+/// Where `ollama serve` listens unless `base_url` says otherwise
+const DEFAULT_BASE_URL: &str = "http://localhost:11434";
+
+/// GPU memory a model needs per byte of weights, leaving room for the context cache
+const FIT_OVERHEAD: f64 = 1.2;
+
+/// `[ollama]` table in the config file
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct OllamaConfig {
+    /// Pull the configured model on first use when the server lacks it (default true)
+    pub auto_pull: Option<bool>,
+    /// Variants of the model, most preferred first; the first on the server that fits in GPU
+    /// memory replaces `model`
+    pub variants: Option<Vec<String>>,
+    /// GPU memory in GiB models may use (default: the local GPUs' total from `nvidia-smi`)
+    pub vram_gb: Option<f64>,
+}
+
+/// Keys accepted in `[ollama]`, for config validation
+pub const KEYS: &[&str] = &["auto_pull", "variants", "vram_gb"];
+
+/// How long the first-use check waits for the server before leaving the error to the first request
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// The Ollama server: the configured endpoint when Ollama is the provider, else the local default
+fn base(agent: &Agent) -> String {
+    let url = match agent.provider.as_str() {
+        "ollama" => agent.base_url.as_deref().unwrap_or(DEFAULT_BASE_URL),
+        _ => DEFAULT_BASE_URL,
+    };
+    url.trim_end_matches('/').to_string()
+}
+
+async fn call(agent: &Agent, method: Method, path: &str, body: Option<Value>) -> Result<reqwest::Response> {
+    let base = base(agent);
+    let mut request = agent.client().request(method, format!("{}{}", base, path));
+    if let Some(body) = body {
+        request = request.json(&body);
+    }
+    let response = request.send().await.with_context(|| format!("Could not reach Ollama at {}; is `ollama serve` running?", base))?;
+    let status = response.status();
+    if !status.is_success() {
+        let body: Value = response.json().await.unwrap_or_default();
+        bail!("Ollama returned {}: {}", status, http::error_message(&body).unwrap_or_else(|| "no error details".into()));
+    }
+    Ok(response)
+}
+
+/// The model named on the command line, else the configured one
+fn model_or_default(agent: &Agent, name: Option<String>) -> String {
+    name.unwrap_or_else(|| agent.model_name())
+}
+
+/// Whether `listed`, as `/api/tags` names it, is `model`; an untagged name means `:latest`
+fn same_model(listed: &str, model: &str) -> bool {
+    listed == model || (!model.contains(':') && listed == format!("{}:latest", model))
+}
+
+async fn installed(agent: &Agent) -> Result<Vec<Value>> {
+    let tags: Value = call(agent, Method::GET, "/api/tags", None).await?.json().await?;
+    Ok(tags["models"].as_array().cloned().unwrap_or_default())
+}
+
+/// Print the models on the server; the configured one is starred
+pub async fn list(agent: &Agent) -> Result<()> {
+    let models = installed(agent).await?;
+    if models.is_empty() {
+        println!("No models on {}; pull one with `rustaigent models pull <name>`", base(agent));
+        return Ok(());
+    }
+    let configured = agent.model_name();
+    println!("  {:<32} {:>10}  {:<8} {:<8} MODIFIED", "NAME", "SIZE", "PARAMS", "QUANT");
+    for model in &models {
+        let name = model["name"].as_str().unwrap_or_default();
+        let details = &model["details"];
+        println!("{} {:<32} {:>10}  {:<8} {:<8} {}",
+            if same_model(name, &configured) { "*" } else { " " },
+            name,
+            HumanBytes(model["size"].as_u64().unwrap_or(0)).to_string(),
+            details["parameter_size"].as_str().unwrap_or("-"),
+            details["quantization_level"].as_str().unwrap_or("-"),
+            model["modified_at"].as_str().and_then(|t| t.get(..10)).unwrap_or("-"));
+    }
+    Ok(())
+}
+
+/// Download `model`, showing each layer's progress on stderr
+async fn download(agent: &Agent, model: &str) -> Result<()> {
+    let mut response = call(agent, Method::POST, "/api/pull", Some(json!({ "model": model, "stream": true }))).await?;
+    let bar = progress::bytes_bar(agent.show_progress());
+    let (mut buffer, mut layer) = (Vec::new(), String::new());
+    while let Some(chunk) = response.chunk().await.context("The pull was interrupted")? {
+        buffer.extend_from_slice(&chunk);
+        while let Some(end) = buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=end).collect();
+            let Ok(event) = serde_json::from_slice::<Value>(&line) else { continue };
+            if let Some(error) = event["error"].as_str() {
+                bar.finish_and_clear();
+                bail!("Pulling {} failed: {}", model, error);
+            }
+            let status = event["status"].as_str().unwrap_or_default();
+            match (event["digest"].as_str(), event["total"].as_u64()) {
+                (Some(digest), Some(total)) => {
+                    if digest != layer {
+                        layer = digest.to_string();
+                        bar.reset();
+                        bar.set_length(total);
+                        bar.set_message(format!("pulling {}", digest.trim_start_matches("sha256:").get(..12).unwrap_or(digest)));
+                    }
+                    bar.set_position(event["completed"].as_u64().unwrap_or(0));
+                }
+                _ => bar.set_message(status.to_string()),
+            }
+            debug!("ollama pull: {}", status);
+        }
+    }
+    bar.finish_and_clear();
+    Ok(())
+}
+
+/// Download `name` (default: the configured model)
+pub async fn pull(agent: &Agent, name: Option<String>) -> Result<()> {
+    let model = model_or_default(agent, name);
+    download(agent, &model).await?;
+    println!("Pulled {}", model);
+    Ok(())
+}
+
+async fn details(agent: &Agent, model: &str) -> Result<Value> {
+    Ok(call(agent, Method::POST, "/api/show", Some(json!({ "model": model }))).await?.json().await?)
+}
+
+/// Print what the server knows about `name` (default: the configured model)
+pub async fn show(agent: &Agent, name: Option<String>) -> Result<()> {
+    let model = model_or_default(agent, name);
+    let info = details(agent, &model).await?;
+    let details = &info["details"];
+    println!("{}", model);
+    for (label, value) in [
+        ("family", details["family"].as_str().map(String::from)),
+        ("parameters", details["parameter_size"].as_str().map(String::from)),
+        ("quantization", details["quantization_level"].as_str().map(String::from)),
+        ("format", details["format"].as_str().map(String::from)),
+        ("context", info["model_info"].as_object().and_then(|m| m.iter().find(|(k, _)| k.ends_with(".context_length"))).map(|(_, v)| v.to_string())),
+        ("capabilities", info["capabilities"].as_array().map(|c| c.iter().filter_map(Value::as_str).collect::<Vec<_>>().join(", "))),
+    ] {
+        if let Some(value) = value.filter(|v| !v.is_empty()) {
+            println!("  {:<13} {}", label, value);
+        }
+    }
+    if let Some(parameters) = info["parameters"].as_str().filter(|p| !p.trim().is_empty()) {
+        println!("\nParameters:");
+        for line in parameters.lines() {
+            println!("  {}", line.split_whitespace().collect::<Vec<_>>().join(" "));
+        }
+    }
+    Ok(())
+}
+
+/// Delete `name` from the server
+pub async fn rm(agent: &Agent, name: &str) -> Result<()> {
+    if agent.tool_ctx.dry_run {
+        println!("Dry run: would remove {} from {}", name, base(agent));
+        return Ok(());
+    }
+    call(agent, Method::DELETE, "/api/delete", Some(json!({ "model": name }))).await?;
+    println!("Removed {}", name);
+    Ok(())
+}
+
+/// Print the models loaded in memory, how much of each sits in GPU memory, and their context
+pub async fn ps(agent: &Agent) -> Result<()> {
+    let models = loaded(agent).await?;
+    if models.is_empty() {
+        println!("No models loaded on {}", base(agent));
+        return Ok(());
+    }
+    println!("{:<32} {:>10}  {:<16} {:>8}  UNTIL", "NAME", "SIZE", "PROCESSOR", "CONTEXT");
+    for model in &models {
+        println!("{:<32} {:>10}  {:<16} {:>8}  {}",
+            model["name"].as_str().unwrap_or_default(),
+            HumanBytes(model["size"].as_u64().unwrap_or(0)).to_string(),
+            processor(model),
+            model["context_length"].as_u64().map_or("-".into(), |n| n.to_string()),
+            model["expires_at"].as_str().and_then(|t| t.get(..19)).map_or("-".into(), |t| t.replace('T', " ")));
+    }
+    Ok(())
+}
+
+async fn loaded(agent: &Agent) -> Result<Vec<Value>> {
+    let ps: Value = call(agent, Method::GET, "/api/ps", None).await?.json().await?;
+    Ok(ps["models"].as_array().cloned().unwrap_or_default())
+}
+
+/// `100% GPU`, `100% CPU` or the split, as `ollama ps` shows it
+fn processor(model: &Value) -> String {
+    let (size, vram) = (model["size"].as_u64().unwrap_or(0), model["size_vram"].as_u64().unwrap_or(0));
+    match vram * 100 / size.max(1) {
+        100.. => "100% GPU".into(),
+        0 => "100% CPU".into(),
+        gpu => format!("{}%/{}% CPU/GPU", 100 - gpu, gpu),
+    }
+}
+
+/// GPU memory models may use: `vram_gb` from the config, else the total of the local GPUs as
+/// `nvidia-smi` reports it; unknown for remote servers
+fn vram(agent: &Agent, config: &OllamaConfig) -> Option<u64> {
+    if let Some(gb) = config.vram_gb {
+        return Some((gb * (1u64 << 30) as f64) as u64);
+    }
+    let url = reqwest::Url::parse(&base(agent)).ok()?;
+    if !matches!(url.host_str(), Some("localhost" | "127.0.0.1" | "::1" | "[::1]")) {
+        return None;
+    }
+    let out = Command::new("nvidia-smi").args(["--query-gpu=memory.total", "--format=csv,noheader,nounits"]).output().ok()?;
+    let mib: u64 = String::from_utf8_lossy(&out.stdout).lines().filter_map(|l| l.trim().parse::<u64>().ok()).sum();
+    (out.status.success() && mib > 0).then_some(mib * 1024 * 1024)
+}
+
+/// The first of `variants` on the server whose weights, with room for the context, fit in `vram`
+/// bytes; else the smallest one on the server
+fn pick_variant(variants: &[String], installed: &[Value], vram: u64) -> Option<(String, bool)> {
+    let sizes: Vec<(&String, u64)> = variants.iter()
+        .filter_map(|v| installed.iter().find(|m| m["name"].as_str().is_some_and(|n| same_model(n, v))).map(|m| (v, m["size"].as_u64().unwrap_or(0))))
+        .collect();
+    let fitting = sizes.iter().find(|(_, size)| (*size as f64 * FIT_OVERHEAD) as u64 <= vram);
+    match fitting {
+        Some((variant, _)) => Some(((*variant).clone(), true)),
+        None => sizes.iter().min_by_key(|(_, size)| *size).map(|(variant, _)| ((*variant).clone(), false)),
+    }
+}
+
+/// Warn when the context the agent may fill is more than the model was trained for or than the
+/// server loaded it with, and when the model spills out of GPU memory
+async fn check_fit(agent: &Agent) {
+    let model = agent.model_name();
+    let assumed = agent.capabilities(&model).max_context;
+    if let Ok(info) = details(agent, &model).await {
+        let trained = info["model_info"].as_object()
+            .and_then(|m| m.iter().find(|(k, _)| k.ends_with(".context_length")))
+            .and_then(|(_, v)| v.as_u64());
+        if let Some(trained) = trained.filter(|&t| u64::from(assumed) > t) {
+            warn!("{} has a context window of {} tokens, but {} are assumed; set [capabilities.\"{}\"] max_context = {}", model, trained, assumed, model, trained);
+        }
+    }
+    let Ok(models) = loaded(agent).await else { return };
+    let Some(running) = models.iter().find(|m| m["name"].as_str().is_some_and(|n| same_model(n, &model))) else { return };
+    if let Some(context) = running["context_length"].as_u64().filter(|&c| u64::from(assumed) > c) {
+        warn!("Ollama loaded {} with a {}-token context, less than the {} tokens prompts may use; longer prompts are cut off. \
+               Raise OLLAMA_CONTEXT_LENGTH on the server or lower max_context", model, context, assumed);
+    }
+    if running["size_vram"].as_u64().unwrap_or(0) < running["size"].as_u64().unwrap_or(0) {
+        warn!("{} runs {}; answers will be slow. A smaller variant may fit (see [ollama] variants)", model, processor(running));
+    }
+}
+
+/// With Ollama as the provider, prepare the model before the first request: pick the configured
+/// variant that fits in GPU memory, pull the model if the server lacks it (unless `auto_pull` is
+/// off) and warn about context and memory limits. Problems are only logged; the first request
+/// reports them properly
+pub async fn prepare(agent: &mut Agent) {
+    if agent.provider != "ollama" {
+        return;
+    }
+    let config = agent.config.ollama.clone().unwrap_or_default();
+    let models = match tokio::time::timeout(PROBE_TIMEOUT, installed(agent)).await {
+        Ok(Ok(models)) => models,
+        Ok(Err(err)) => return debug!("not preparing the model: {:#}", err),
+        Err(_) => return debug!("not preparing the model: Ollama did not answer in time"),
+    };
+    let variants = config.variants.clone().unwrap_or_default();
+    if !variants.is_empty() {
+        match vram(agent, &config) {
+            Some(vram) => match pick_variant(&variants, &models, vram) {
+                Some((variant, true)) => {
+                    info!("using {}, the first variant that fits in {} of GPU memory", variant, HumanBytes(vram));
+                    agent.model = Some(variant);
+                }
+                Some((variant, false)) => {
+                    warn!("no variant fits in {} of GPU memory; using the smallest, {}", HumanBytes(vram), variant);
+                    agent.model = Some(variant);
+                }
+                None => warn!("none of the variants is on the Ollama server; pull one with `rustaigent models pull`"),
+            },
+            None => warn!("cannot tell how much GPU memory the Ollama server has; set [ollama] vram_gb to pick a variant"),
+        }
+    }
+    let model = agent.model_name();
+    if !models.iter().any(|m| m["name"].as_str().is_some_and(|name| same_model(name, &model))) && config.auto_pull != Some(false) {
+        info!("{} is not on the Ollama server yet; pulling it", model);
+        match download(agent, &model).await {
+            Ok(()) => info!("pulled {}", model),
+            Err(err) => return warn!("{:#}", err),
+        }
+    }
+    check_fit(agent).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{mock::MockProvider, turns::Turn};
+
+    #[tokio::test]
+    async fn aliases_and_retired_models_are_resolved() {
+        let mock = MockProvider::start().await;
+        mock.reply("pong").await;
+        let mut agent = mock.agent();
+        agent.config.model_aliases = Some([("fast".to_string(), "gpt-4-32k-0613".to_string())].into());
+        agent.model = Some("fast".into());
+        agent.send_request(Turn::Tools, None).await.unwrap();
+        agent.config.migrate_models = Some(false);
+        agent.send_request(Turn::Tools, None).await.unwrap();
+        let requests = mock.requests().await;
+        assert_eq!(requests[0]["model"], "gpt-4o");
+        assert_eq!(requests[1]["model"], "gpt-4-32k-0613");
+    }
+}
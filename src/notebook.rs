@@ -0,0 +1,111 @@
+use serde_json::Value;
+use anyhow::{Result, Context};
+use crate::{session::{Bundle, Step}, tools::{self, ToolCall, ToolContext}};
+
+/// Code block languages that `session rerun` executes, and the tool that runs them
+const RUNNABLE: &[(&str, &str)] = &[("rust", "eval_code"), ("sh", "run_command"), ("bash", "run_command"), ("shell", "run_command")];
+
+/// A fence longer than any backtick run in `content`, so outputs containing fences stay intact
+fn fence_for(content: &str) -> String {
+    let longest = content.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    "`".repeat(longest.max(2) + 1)
+}
+
+fn block(info: &str, content: &str) -> String {
+    let fence = fence_for(content);
+    format!("{}{}\n{}\n{}\n", fence, info, content.trim_end_matches('\n'), fence)
+}
+
+/// Markdown notebook: prompts, answers and tool cells with their outputs, in session order
+pub fn render(bundle: &Bundle) -> String {
+    let mut out = format!("# RustAIgent session {}\n\n_Provider: {}{}. Re-run with `rustaigent session rerun <file>`._\n", bundle.id, bundle.provider, bundle.model.as_deref().map(|m| format!(", model {}", m)).unwrap_or_default());
+    for step in bundle.steps() {
+        match step {
+            // Retrieved sources are already shown as the tool cell that produced them
+            Step::Message { role: "system", .. } => {}
+            Step::Message { role: "user", content } if content.starts_with("[source ") => {}
+            Step::Message { role: "user", content } => out.push_str(&format!("\n## Prompt\n\n{}\n", content)),
+            Step::Message { content, .. } => out.push_str(&format!("\n## Answer\n\n{}\n", content)),
+            Step::Tool(call) => {
+                out.push('\n');
+                out.push_str(&block(&format!("tool {}", call.call.name()), &serde_json::to_string_pretty(&call.call.args()).unwrap_or_default()));
+                out.push_str(&block("output", &call.output));
+            }
+        }
+    }
+    out
+}
+
+/// A fenced block found in a notebook, by line range
+struct Fenced {
+    info: String,
+    body: String,
+    /// Index of the line after the closing fence
+    end: usize,
+}
+
+fn fenced_at(lines: &[&str], start: usize) -> Option<Fenced> {
+    let line = lines[start];
+    let ticks = line.len() - line.trim_start_matches('`').len();
+    if ticks < 3 {
+        return None;
+    }
+    let fence = &line[..ticks];
+    let close = lines[start + 1..].iter().position(|l| l.trim_end() == fence)? + start + 1;
+    Some(Fenced {
+        info: line[ticks..].trim().to_string(),
+        body: lines[start + 1..close].join("\n"),
+        end: close + 1,
+    })
+}
+
+/// Execute every runnable block in order, replacing the `output` block that follows it.
+/// Tool cells go through the normal dispatcher, so policy rules and approvals still apply.
+pub async fn rerun(text: &str, ctx: &mut ToolContext) -> Result<String> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut out = String::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let Some(cell) = fenced_at(&lines, i) else {
+            out.push_str(lines[i]);
+            out.push('\n');
+            i += 1;
+            continue;
+        };
+        out.push_str(&lines[i..cell.end].join("\n"));
+        out.push('\n');
+        i = cell.end;
+
+        let call = match cell.info.split_once(' ') {
+            Some(("tool", name)) => {
+                let args: Value = serde_json::from_str(&cell.body).with_context(|| format!("Tool cell `{}` has invalid JSON arguments", name))?;
+                Some((name.trim().to_string(), args))
+            }
+            _ => RUNNABLE.iter().find(|(lang, _)| *lang == cell.info).map(|(_, tool)| {
+                let key = if *tool == "eval_code" { "code" } else { "command" };
+                (tool.to_string(), serde_json::json!({ key: cell.body }))
+            }),
+        };
+        let Some((tool, args)) = call else { continue };
+
+        // Drop the stale output block, keeping blank lines between
+        let mut next = i;
+        while next < lines.len() && lines[next].trim().is_empty() {
+            next += 1;
+        }
+        if let Some(old) = (next < lines.len()).then(|| fenced_at(&lines, next)).flatten().filter(|f| f.info == "output") {
+            i = old.end;
+        }
+        tracing::info!("running {} cell", tool);
+        let result = match ToolCall::parse(&tool, args) {
+            Ok(call) => tools::execute(ctx, &call).await,
+            Err(err) => Err(err),
+        };
+        let output = match result {
+            Ok(o) => o,
+            Err(err) => format!("error: {:#}", err),
+        };
+        out.push_str(&block("output", &output));
+    }
+    Ok(out)
+}
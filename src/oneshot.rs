@@ -0,0 +1,208 @@
+//! `rustaigent -p <prompt>`: one agent turn for scripts and CI. Piped stdin is attached to the
+//! prompt, only the result goes to stdout, and failures end with a nonzero exit code.
+
+use std::io::{self, IsTerminal, Read};
+use serde_json::json;
+use anyhow::{Result, Context, bail};
+use crate::{pager, postprocess, serve, verify, Agent};
+
+/// Bytes of piped input accepted; a larger input would not fit any context window anyway
+const MAX_STDIN: u64 = 4 * 1024 * 1024;
+
+/// Asks for a change as a patch the caller applies, rather than files written by the tools
+pub(crate) const PATCH_INSTRUCTIONS: &str = "\n\nReply with the change as a unified diff in a single ```diff block, with ---/+++ file headers and @@ hunks \
+    that apply with `git apply`. Do not write files yourself.";
+
+/// What part of the answer is printed
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum Output {
+    /// The whole answer
+    #[default]
+    Text,
+    /// Only the first fenced code block
+    Code,
+    /// A unified diff, asked for and printed alone, ready for `git apply`
+    Patch,
+    /// Every event as a JSON line while the turn runs, then the result as with `--json`
+    Ndjson,
+}
+
+/// The piped input, if stdin is not a terminal
+pub fn read_stdin() -> Result<Option<String>> {
+    if io::stdin().is_terminal() {
+        return Ok(None);
+    }
+    let mut bytes = Vec::new();
+    io::stdin().take(MAX_STDIN).read_to_end(&mut bytes).context("Failed to read stdin")?;
+    if crate::bulk::looks_binary(&bytes) {
+        bail!("Piped input is binary; pipe text, or pass the file with --upload");
+    }
+    let input = String::from_utf8(bytes).context("Piped input is not UTF-8 text")?;
+    Ok(Some(input).filter(|i| !i.trim().is_empty()))
+}
+
+/// The prompt with the piped input and the output instructions
+fn compose(prompt: &str, input: Option<&str>, output: Output) -> String {
+    let mut text = prompt.trim().to_string();
+    if let Some(input) = input {
+        let fence = if input.contains("```") { "````" } else { "```" };
+        text.push_str(&format!("\n\nInput:\n{}\n{}\n{}", fence, input.trim_end(), fence));
+    }
+    match output {
+        Output::Text | Output::Ndjson => {}
+        Output::Code => text.push_str("\n\nReply with the resulting code in a single fenced code block."),
+        Output::Patch => text.push_str(PATCH_INSTRUCTIONS),
+    }
+    text
+}
+
+/// Body of the first fenced block, or of the first one tagged with one of `langs`
+fn fenced(text: &str, langs: &[&str]) -> Option<String> {
+    let mut lines = text.lines();
+    while let Some(line) = lines.next() {
+        let Some(info) = line.trim_start().strip_prefix("```") else { continue };
+        let fence: String = line.trim_start().chars().take_while(|&c| c == '`').collect();
+        let lang = info.trim_start_matches('`').trim();
+        let body: Vec<&str> = lines.by_ref().take_while(|l| !l.trim_start().starts_with(&fence)).collect();
+        if langs.is_empty() || langs.contains(&lang) {
+            return Some(body.iter().map(|l| format!("{}\n", l)).collect());
+        }
+    }
+    None
+}
+
+/// The part of the answer `output` asks for
+pub fn select(answer: &str, output: Output) -> Result<String> {
+    match output {
+        Output::Text | Output::Ndjson => Ok(answer.trim().to_string()),
+        Output::Code => fenced(answer, &[]).context("The answer contains no code block"),
+        Output::Patch => fenced(answer, &["diff", "patch"])
+            .or_else(|| ["--- ", "diff --git "].iter().any(|p| answer.trim_start().starts_with(p)).then(|| answer.trim().to_string() + "\n"))
+            .context("The answer contains no unified diff"),
+    }
+}
+
+/// The answer to `prompt`, verified and post-processed, and the part of it `output` asks for
+pub(crate) async fn answer(agent: &mut Agent, prompt: &str, input: Option<&str>, output: Output) -> Result<(String, String)> {
+    let steps = agent.config.post_process.clone().unwrap_or_default();
+    let answer = match verify::turn(agent, &compose(prompt, input, output)).await? {
+        Some(reply) if !steps.is_empty() => postprocess::apply(agent, &steps, reply.content).await?,
+        Some(reply) => reply.content,
+        None => bail!("The guardrails held the prompt back"),
+    };
+    let selected = select(&answer, output)?;
+    Ok((answer, selected))
+}
+
+/// Print the selected part of an answer, ending with a newline. A whole answer goes through the
+/// pager when it would scroll past the terminal; code and patches are printed as they are.
+pub fn print(selected: &str, output: Output) {
+    if output == Output::Text {
+        pager::show(selected);
+        return;
+    }
+    print!("{}", selected);
+    if !selected.ends_with('\n') {
+        println!();
+    }
+}
+
+/// Print the agent's events as JSON lines, framed as the server's event stream, until the
+/// session stops sending them. Nobody can answer through a pipe, so prompts are declined.
+fn print_events(agent: &mut Agent) -> Result<tokio::task::JoinHandle<()>> {
+    let mut events = agent.events();
+    let mut stream = serve::Stream::new(std::env::current_dir()?);
+    Ok(tokio::spawn(async move {
+        while let Some(event) = events.recv().await {
+            let frame = stream.frame(event);
+            stream.decline_all();
+            println!("{}", frame);
+        }
+    }))
+}
+
+/// Run one turn, tool calls included, and print the result; `json` prints a JSON object with the
+/// answer, tool calls, a summary of their side effects and token usage instead
+pub async fn run(agent: &mut Agent, prompt: &str, input: Option<String>, output: Output, json: bool) -> Result<()> {
+    let first_call = agent.tool_log.len();
+    let printer = match output {
+        Output::Ndjson => Some(print_events(agent)?),
+        _ => None,
+    };
+    let result = answer(agent, prompt, input.as_deref(), output).await;
+    if let Some(printer) = printer {
+        // Dropping the subscription ends the event lines; the result comes after the last of them
+        agent.tool_ctx.events = None;
+        printer.await?;
+    }
+    if !json && output != Output::Ndjson {
+        print(&result?.1, output);
+        return Ok(());
+    }
+    let report = report(agent, first_call, &result, output);
+    match output {
+        Output::Ndjson => {
+            let mut line = serde_json::Map::from_iter([("type".to_string(), json!("result"))]);
+            line.extend(report.as_object().cloned().unwrap_or_default());
+            println!("{}", serde_json::Value::Object(line));
+        }
+        _ => println!("{}", serde_json::to_string_pretty(&report)?),
+    }
+    result.map(|_| ())
+}
+
+/// The `--json` object for a turn whose tool calls start at `first_call`; sources the answer
+/// cites are listed under `citations` with where they came from and how they begin
+fn report(agent: &Agent, first_call: usize, result: &Result<(String, String)>, output: Output) -> serde_json::Value {
+    let calls: Vec<_> = agent.tool_log[first_call..].iter()
+        .map(|c| json!({ "tool": c.call.name(), "args": c.call.args(), "ok": c.ok, "output": c.output }))
+        .collect();
+    let (prompt_tokens, completion_tokens, cost) = {
+        let stats = agent.stats.lock().unwrap();
+        let (p, c) = stats.models.values().fold((0, 0), |(p, c), m| (p + m.prompt_tokens, c + m.completion_tokens));
+        (p, c, stats.cost())
+    };
+    let mut report = json!({
+        "ok": result.is_ok(),
+        "model": agent.model_name(),
+        "tool_calls": calls,
+        "actions": agent.last_actions(),
+        "usage": { "prompt_tokens": prompt_tokens, "completion_tokens": completion_tokens, "cost_usd": cost },
+        "tags": agent.tags(),
+    });
+    match result {
+        Ok((answer, selected)) => {
+            report["answer"] = json!(answer);
+            report["citations"] = json!(agent.sources.cited(answer));
+            if !matches!(output, Output::Text | Output::Ndjson) {
+                report["output"] = json!(selected);
+            }
+        }
+        Err(err) => report["error"] = json!(format!("{:#}", err)),
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use crate::mock::MockProvider;
+    use super::{answer, report, Output};
+
+    #[tokio::test]
+    async fn json_reports_list_the_cited_sources() {
+        let mock = MockProvider::start().await;
+        mock.tool_call("read_file", json!({"path": "Cargo.toml"})).await;
+        mock.reply("The package is rustaigent [1].").await;
+        let mut agent = mock.agent();
+        agent.functions = crate::tools::ToolCall::definitions().into();
+        agent.enable_quiet();
+        let result = answer(&mut agent, "What is the package called?", None, Output::Text).await;
+        let report = report(&agent, 0, &result, Output::Text);
+        let citations = report["citations"].as_array().unwrap();
+        assert_eq!(citations.len(), 1);
+        assert_eq!(citations[0]["id"], 1);
+        assert!(citations[0]["location"].as_str().unwrap().starts_with("Cargo.toml:1-"));
+        assert!(citations[0]["excerpt"].as_str().unwrap().starts_with("[package]"));
+    }
+}
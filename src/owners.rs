@@ -0,0 +1,206 @@
+//! Which team owns each file the model proposes to change, from the repository's CODEOWNERS. The
+//! owners are shown with the change wherever it is previewed, and a change to files owned only by
+//! teams outside `[codeowners] teams` is warned about, or with `require_approval` needs approval
+//! even where the tool is allowed, so a monorepo session does not churn other teams' code.
+
+use std::{fs, path::{Component, Path, PathBuf}};
+use regex::Regex;
+use serde::{Serialize, Deserialize};
+use crate::policy::{Action, Decision, Origin};
+
+/// `[codeowners]` table
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct OwnersConfig {
+    /// Look owners up; on by default when the repository has a CODEOWNERS file
+    pub enabled: Option<bool>,
+    /// CODEOWNERS file to read instead of `.github/CODEOWNERS`, `CODEOWNERS` or `docs/CODEOWNERS`
+    pub file: Option<PathBuf>,
+    /// Owners the session works for, such as `@org/platform` or `@alice`; files owned by none of
+    /// them belong to other teams
+    pub teams: Option<Vec<String>>,
+    /// Changes to other teams' files need approval; otherwise they are only warned about
+    pub require_approval: Option<bool>,
+}
+
+/// Keys accepted in `[codeowners]`, for config validation
+pub const KEYS: &[&str] = &["enabled", "file", "teams", "require_approval"];
+
+/// Where GitHub looks for the file, in its order
+const LOCATIONS: &[&str] = &[".github/CODEOWNERS", "CODEOWNERS", "docs/CODEOWNERS"];
+
+/// One line of the file: a path pattern and who owns what it matches
+struct Rule {
+    pattern: Regex,
+    owners: Vec<String>,
+}
+
+/// A CODEOWNERS pattern as a regex over paths relative to the repository root. Patterns with a
+/// slash before their end are anchored to the root, others match at any depth; a pattern whose
+/// last segment has no wildcard also covers everything beneath it, as `docs/*` does not.
+fn pattern(glob: &str) -> Option<Regex> {
+    let dir_only = glob.ends_with('/');
+    let trimmed = glob.trim_end_matches('/');
+    let anchored = trimmed.starts_with('/') || trimmed.contains('/');
+    let trimmed = trimmed.trim_start_matches('/');
+    let mut re = String::from(if anchored { "^" } else { "^(?:.*/)?" });
+    let mut rest = trimmed;
+    while let Some(c) = rest.chars().next() {
+        if let Some(after) = rest.strip_prefix("**/") {
+            re.push_str("(?:.*/)?");
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("**") {
+            re.push_str(".*");
+            rest = after;
+        } else {
+            match c {
+                '*' => re.push_str("[^/]*"),
+                '?' => re.push_str("[^/]"),
+                c => re.push_str(&regex::escape(&c.to_string())),
+            }
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    let last = trimmed.rsplit('/').next().unwrap_or(trimmed);
+    re.push_str(match (dir_only, last.contains(['*', '?'])) {
+        (true, _) => "/.*$",
+        (false, false) => "(?:/.*)?$",
+        (false, true) => "$",
+    });
+    Regex::new(&re).ok()
+}
+
+/// The owners of a repository's files
+pub struct Owners {
+    root: PathBuf,
+    rules: Vec<Rule>,
+    teams: Vec<String>,
+    require_approval: bool,
+}
+
+impl Owners {
+    /// Rules from CODEOWNERS `text` for the repository at `root`; lines that do not parse are
+    /// left out
+    pub fn parse(root: &Path, text: &str, cfg: &OwnersConfig) -> Owners {
+        let rules = text.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace().take_while(|f| !f.starts_with('#'));
+                let glob = fields.next()?;
+                let Some(pattern) = pattern(glob) else {
+                    tracing::debug!("skipping CODEOWNERS pattern `{}`", glob);
+                    return None;
+                };
+                Some(Rule { pattern, owners: fields.map(String::from).collect() })
+            })
+            .collect();
+        Owners { root: root.to_path_buf(), rules, teams: cfg.teams.clone().unwrap_or_default(), require_approval: cfg.require_approval.unwrap_or(false) }
+    }
+
+    /// The owners of the repository around `dir`, per `[codeowners]`; `None` when it has no
+    /// CODEOWNERS file or the lookup is off
+    pub fn discover(cfg: Option<&OwnersConfig>, dir: &Path) -> Option<Owners> {
+        let cfg = cfg.cloned().unwrap_or_default();
+        if cfg.enabled == Some(false) {
+            return None;
+        }
+        let root = dir.ancestors().find(|d| d.join(".git").exists() || LOCATIONS.iter().any(|l| d.join(l).is_file()))?;
+        let file = match &cfg.file {
+            Some(file) => root.join(file),
+            None => LOCATIONS.iter().map(|l| root.join(l)).find(|p| p.is_file())?,
+        };
+        match fs::read_to_string(&file) {
+            Ok(text) => Some(Owners::parse(root, &text, &cfg)),
+            Err(err) => {
+                tracing::warn!("cannot read {}: {}", file.display(), err);
+                None
+            }
+        }
+    }
+
+    /// Who owns `path`, relative to `dir` or absolute: the owners of the last rule matching it,
+    /// none when it is unowned or outside the repository
+    pub fn of(&self, dir: &Path, path: &str) -> &[String] {
+        let full = dir.join(path);
+        let mut normal = PathBuf::new();
+        for component in full.components() {
+            match component {
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    normal.pop();
+                }
+                other => normal.push(other),
+            }
+        }
+        let Ok(relative) = normal.strip_prefix(&self.root) else { return &[] };
+        let relative = relative.to_string_lossy().replace('\\', "/");
+        self.rules.iter().rev().find(|rule| rule.pattern.is_match(&relative)).map_or(&[], |rule| &rule.owners)
+    }
+
+    /// Owned by someone, none of them in `teams`
+    fn foreign(&self, owners: &[String]) -> bool {
+        !self.teams.is_empty() && !owners.is_empty() && !owners.iter().any(|o| self.teams.iter().any(|t| t.eq_ignore_ascii_case(o)))
+    }
+
+    /// Each owned file of `files` with its owners, as shown with the change
+    pub fn describe(&self, dir: &Path, files: &[String]) -> Vec<String> {
+        files.iter()
+            .map(|file| (file, self.of(dir, file)))
+            .filter(|(_, owners)| !owners.is_empty())
+            .map(|(file, owners)| format!("{} ({})", file, owners.join(" ")))
+            .collect()
+    }
+
+    /// The approval a change to `files` needs beyond the policy when other teams own some of
+    /// them; without `require_approval` it is only warned about
+    pub fn caution(&self, dir: &Path, files: &[String]) -> Option<Decision> {
+        let foreign: Vec<String> = files.iter()
+            .filter(|file| self.foreign(self.of(dir, file)))
+            .map(|file| format!("{} is owned by {}", file, self.of(dir, file).join(" ")))
+            .collect();
+        if foreign.is_empty() {
+            return None;
+        }
+        let reason = foreign.join(", ");
+        if !self.require_approval {
+            tracing::warn!("changing files of other teams: {}", reason);
+            return None;
+        }
+        Some(Decision { action: Action::Ask, origin: Origin::Owners, reason: Some(reason) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use serde_json::json;
+    use crate::mock::{last_content, MockProvider};
+    use super::{Owners, OwnersConfig};
+
+    #[tokio::test]
+    async fn changes_to_files_of_other_teams_need_approval() {
+        let repo = std::env::temp_dir().join(format!("rustaigent-owners-{}", std::process::id()));
+        std::fs::create_dir_all(repo.join("billing")).unwrap();
+        std::fs::create_dir_all(repo.join("src")).unwrap();
+        let repo = repo.canonicalize().unwrap();
+        let cfg = OwnersConfig { teams: Some(vec!["@acme/platform".into()]), require_approval: Some(true), ..Default::default() };
+        let owners = Owners::parse(&repo, "*  @acme/platform\n/billing/  @acme/payments  # rates and invoices\n", &cfg);
+        let mock = MockProvider::start().await;
+        mock.tool_call("write_file", json!({"path": "billing/rates.rs", "content": "pub const VAT: f64 = 0.2;\n"})).await;
+        mock.tool_call("write_file", json!({"path": "src/lib.rs", "content": "pub mod rates;\n"})).await;
+        mock.reply("done").await;
+        let mut agent = mock.tool_agent();
+        agent.tool_ctx.set_workdir(repo.clone());
+        agent.tool_ctx.policy.allow_path(&repo);
+        agent.tool_ctx.policy.levels.insert("write_file".into(), crate::policy::Action::Allow);
+        agent.tool_ctx.owners = Some(Arc::new(owners));
+        agent.enable_unattended();
+        agent.turn("set the VAT rate").await.unwrap();
+        let requests = mock.requests().await;
+        let last = |i: usize| last_content(&requests[i]);
+        assert!(last(1).contains("`write_file` was not approved"), "{}", last(1));
+        assert!(!repo.join("billing/rates.rs").exists());
+        assert!(repo.join("src/lib.rs").exists(), "{}", last(2));
+        std::fs::remove_dir_all(&repo).ok();
+    }
+}
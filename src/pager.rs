@@ -0,0 +1,49 @@
+use std::{env, io::{IsTerminal, Write}, process::{Command, Stdio}};
+use anyhow::{Result, Context, bail};
+use terminal_size::{terminal_size, Height};
+
+/// Pager command from `$PAGER`, defaulting to `less -R` (keeps colors, `/` searches);
+/// an empty value or `cat` disables paging
+fn pager_command() -> Option<String> {
+    match env::var("PAGER") {
+        Ok(p) if p.trim().is_empty() || p.trim() == "cat" => None,
+        Ok(p) => Some(p),
+        Err(_) => Some("less -R".into()),
+    }
+}
+
+/// Print `text`, routing it through the pager when it would scroll past a terminal screen. A
+/// pager that cannot be started or fails, such as `less` missing, leaves the text printed plainly.
+pub fn show(text: &str) {
+    let fits = match terminal_size() {
+        Some((_, Height(rows))) => text.lines().count() < rows as usize,
+        None => true,
+    };
+    let pager = match pager_command() {
+        Some(p) if !fits && std::io::stdout().is_terminal() => p,
+        _ => {
+            println!("{}", text);
+            return;
+        }
+    };
+    if let Err(err) = page(&pager, text) {
+        tracing::debug!("{:#}", err);
+        println!("{}", text);
+    }
+}
+
+fn page(pager: &str, text: &str) -> Result<()> {
+    let mut child = Command::new("sh").arg("-c").arg(pager)
+        .stdin(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to start pager `{}`", pager))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // The user may quit the pager before reading everything
+        let _ = writeln!(stdin, "{}", text);
+    }
+    let status = child.wait()?;
+    if !status.success() {
+        bail!("Pager `{}` failed: {}", pager, status);
+    }
+    Ok(())
+}
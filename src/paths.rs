@@ -0,0 +1,49 @@
+use std::{io, path::{Path, PathBuf}};
+
+tokio::task_local! {
+    /// Project directory of the agent being set up on this task, set by [`in_dir`]
+    static DIR: PathBuf;
+}
+
+/// The directory project files are looked up from: the one [`in_dir`] set, else the process's
+/// working directory
+pub fn current_dir() -> io::Result<PathBuf> {
+    DIR.try_with(PathBuf::clone).or_else(|_| std::env::current_dir())
+}
+
+/// Run `f` with project files looked up from `dir`, without moving the process's working
+/// directory, which other sessions share
+pub fn in_dir<R>(dir: &Path, f: impl FnOnce() -> R) -> R {
+    DIR.sync_scope(dir.to_path_buf(), f)
+}
+
+/// Root directory for persisted state (usage logs, sessions, caches)
+pub fn data_dir() -> PathBuf {
+    dirs::data_dir().unwrap_or_else(|| PathBuf::from(".")).join("rustaigent")
+}
+
+/// Personal config file, overriding team settings
+pub fn user_config_file() -> PathBuf {
+    dirs::config_dir().unwrap_or_else(|| PathBuf::from(".")).join("rustaigent").join("config.toml")
+}
+
+/// Nearest `.rustaigent/config.toml` in the current directory or its ancestors
+pub fn team_config_file() -> Option<PathBuf> {
+    let cwd = current_dir().ok()?;
+    cwd.ancestors()
+        .map(|dir| dir.join(".rustaigent").join("config.toml"))
+        .find(|p| p.is_file())
+}
+
+/// Nearest `.rustaigent.toml` in the current directory or its ancestors, overriding personal settings
+pub fn project_config_file() -> Option<PathBuf> {
+    let cwd = current_dir().ok()?;
+    cwd.ancestors()
+        .map(|dir| dir.join(".rustaigent.toml"))
+        .find(|p| p.is_file())
+}
+
+/// Stored API keys, written by `rustaigent init` with owner-only permissions
+pub fn credentials_file() -> PathBuf {
+    dirs::config_dir().unwrap_or_else(|| PathBuf::from(".")).join("rustaigent").join("credentials.toml")
+}
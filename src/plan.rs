@@ -0,0 +1,112 @@
+use std::{fs, path::PathBuf};
+use serde::{Serialize, Deserialize};
+use anyhow::{Result, Context};
+use crate::{paths, stats, tools::ToolCall};
+
+/// The model's plan for a task, written before anything runs and checked off as tools complete
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Plan {
+    #[serde(default)]
+    pub task: String,
+    #[serde(default)]
+    pub goals: Vec<String>,
+    pub steps: Vec<Step>,
+    /// Files the plan expects to touch
+    #[serde(default)]
+    pub files: Vec<String>,
+    #[serde(default)]
+    pub risks: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Step {
+    pub description: String,
+    /// Tool expected to carry the step out
+    #[serde(default)]
+    pub tool: Option<String>,
+    #[serde(default)]
+    pub files: Vec<String>,
+    /// When a matching tool call completed, in seconds since the epoch
+    #[serde(default)]
+    pub done_at: Option<u64>,
+}
+
+/// Instructions sent before the task so the model answers with a plan only
+pub fn prompt(task: &str) -> String {
+    format!(
+        "Before doing anything, plan this task:\n\n{}\n\nReply with only a JSON object: \
+         {{\"goals\": [string], \"steps\": [{{\"description\": string, \"tool\": tool name or null, \"files\": [path]}}], \
+         \"files\": [path], \"risks\": [string]}}. Name the tool and files of each step where you can; keep steps small.",
+        task)
+}
+
+fn same_path(a: &str, b: &str) -> bool {
+    a.trim_start_matches("./") == b.trim_start_matches("./")
+}
+
+impl Plan {
+    /// Read the JSON object in a reply, fenced or bare
+    pub fn parse(task: &str, reply: &str) -> Result<Self> {
+        let start = reply.find('{').context("The reply contains no plan")?;
+        let end = reply.rfind('}').filter(|&end| end > start).context("The reply contains no plan")?;
+        let mut plan: Plan = serde_json::from_str(&reply[start..=end]).context("The plan is not valid JSON")?;
+        plan.task = task.to_string();
+        Ok(plan)
+    }
+
+    /// Check off the first open step this call carries out; steps naming neither a tool nor files
+    /// are left for `/plan done`. Returns the step's index.
+    pub fn complete(&mut self, call: &ToolCall) -> Option<usize> {
+        let paths = call.paths();
+        let index = self.steps.iter().position(|step| {
+            let tool = step.tool.as_deref().filter(|t| !t.is_empty());
+            step.done_at.is_none()
+                && (tool.is_some() || !step.files.is_empty())
+                && tool.is_none_or(|t| t == call.name())
+                && (step.files.is_empty() || step.files.iter().any(|f| paths.iter().any(|p| same_path(f, p))))
+        })?;
+        self.steps[index].done_at = Some(stats::now_secs());
+        Some(index)
+    }
+
+    /// Check off a step by its 1-based number
+    pub fn mark_done(&mut self, number: usize) -> Result<()> {
+        let step = number.checked_sub(1).and_then(|i| self.steps.get_mut(i)).with_context(|| format!("The plan has no step {}", number))?;
+        step.done_at.get_or_insert_with(stats::now_secs);
+        Ok(())
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.steps.iter().filter(|s| s.done_at.is_none()).count()
+    }
+
+    /// Markdown checklist for reviewers
+    pub fn render(&self) -> String {
+        let mut out = format!("# Plan\n\n{}\n", self.task);
+        let list = |title: &str, items: &[String]| if items.is_empty() {
+            String::new()
+        } else {
+            format!("\n## {}\n\n{}", title, items.iter().map(|i| format!("- {}\n", i)).collect::<String>())
+        };
+        out.push_str(&list("Goals", &self.goals));
+        out.push_str("\n## Steps\n\n");
+        for (i, step) in self.steps.iter().enumerate() {
+            let mut detail: Vec<String> = step.tool.iter().map(|t| format!("`{}`", t)).collect();
+            detail.extend(step.files.iter().cloned());
+            let detail = if detail.is_empty() { String::new() } else { format!(" ({})", detail.join(", ")) };
+            out.push_str(&format!("- [{}] {}. {}{}\n", if step.done_at.is_some() { "x" } else { " " }, i + 1, step.description, detail));
+        }
+        out.push_str(&list("Files", &self.files));
+        out.push_str(&list("Risks", &self.risks));
+        out
+    }
+
+    /// Write the checklist next to the session's autosaved bundle
+    pub fn save(&self, session_id: &str) -> Result<PathBuf> {
+        let dir = paths::data_dir().join("sessions");
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(format!("{}.plan.md", session_id));
+        crate::vault::write(&path, self.render().into_bytes())?;
+        Ok(path)
+    }
+}
@@ -0,0 +1,353 @@
+//! Tool plugins: `rustaigent plugins install <name>` fetches a plugin from the registry `[plugins]`
+//! names, checks its SHA-256 and minisign signature against `trusted_keys`, and unpacks it into
+//! the user plugin directory. Each installed plugin has a `plugin.toml` listing its tools; a tool
+//! runs a program shipped with the plugin, or a WASI module under the `wasmtime` CLI, with its
+//! arguments as JSON on stdin and its answer on stdout.
+
+use std::{fs, path::{Path, PathBuf}, process::Stdio, sync::Arc, time::Duration};
+use futures::future::BoxFuture;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use anyhow::{Result, Context, bail};
+use tokio::io::AsyncWriteExt;
+use tracing::{info, warn};
+use crate::{paths, tools::{archive, download, network, Tool, HIDDEN_ENV}, Agent, FunctionDefinition};
+
+/// Registry asked when `[plugins] registry` is unset
+const REGISTRY: &str = "https://raw.githubusercontent.com/makalin/RustAIgent/main/plugins/index.json";
+
+/// Minisign key of the default registry, baked in at build time
+const REGISTRY_PUBKEY: Option<&str> = option_env!("RUSTAIGENT_PLUGIN_PUBKEY");
+
+/// Seconds a plugin tool may run when its manifest sets no `timeout_secs`
+const DEFAULT_TIMEOUT_SECS: u64 = 120;
+
+const MANIFEST: &str = "plugin.toml";
+
+/// `[plugins]` table: where plugins come from and whose signatures are accepted
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct PluginsConfig {
+    /// URL of the registry index, a JSON list of plugins
+    pub registry: Option<String>,
+    /// Minisign public keys (the base64 line of a `.pub` file) a plugin may be signed with
+    pub trusted_keys: Option<Vec<String>>,
+    /// Installed plugins whose tools are not offered
+    pub disabled: Option<Vec<String>>,
+}
+
+/// Keys accepted in `[plugins]`, for config validation
+pub const KEYS: &[&str] = &["registry", "trusted_keys", "disabled"];
+
+/// One plugin in the registry index
+#[derive(Deserialize)]
+struct Entry {
+    name: String,
+    version: String,
+    #[serde(default)]
+    description: String,
+    /// A .tar.gz or .zip holding `plugin.toml` at its top
+    url: String,
+    sha256: String,
+    /// Minisign signature of the archive; `<url>.minisig` when absent
+    signature: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Index {
+    plugins: Vec<Entry>,
+}
+
+/// `plugin.toml`
+#[derive(Deserialize)]
+struct Manifest {
+    name: String,
+    version: String,
+    #[serde(default)]
+    tools: Vec<ToolSpec>,
+}
+
+#[derive(Deserialize, Clone)]
+struct ToolSpec {
+    name: String,
+    description: String,
+    /// JSON schema of the arguments; none when unset
+    parameters: Option<Value>,
+    /// Program in the plugin directory, or on PATH
+    command: Option<String>,
+    #[serde(default)]
+    args: Vec<String>,
+    /// WASI module in the plugin directory, run with `wasmtime` and only the working directory
+    /// visible to it
+    wasm: Option<String>,
+    timeout_secs: Option<u64>,
+    /// Output comes from outside the user's control, e.g. a web service
+    #[serde(default)]
+    untrusted: bool,
+}
+
+/// Directory plugins are installed into, one subdirectory each
+pub fn dir() -> PathBuf {
+    paths::user_config_file().with_file_name("plugins")
+}
+
+fn parse_version(v: &str) -> Vec<u64> {
+    v.trim_start_matches('v').split(['.', '-']).map_while(|p| p.parse().ok()).collect()
+}
+
+fn read_manifest(plugin: &Path) -> Result<Manifest> {
+    let path = plugin.join(MANIFEST);
+    let text = fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let manifest: Manifest = toml::from_str(&text).with_context(|| format!("Invalid {}", path.display()))?;
+    for tool in &manifest.tools {
+        if tool.command.is_some() == tool.wasm.is_some() {
+            bail!("Tool `{}` of plugin `{}` must set either `command` or `wasm`", tool.name, manifest.name);
+        }
+    }
+    Ok(manifest)
+}
+
+/// A signature by any of `keys`
+fn verify(artifact: &[u8], signature: &str, keys: &[String]) -> Result<()> {
+    let signature = minisign_verify::Signature::decode(signature).context("Invalid plugin signature")?;
+    for key in keys {
+        let key = minisign_verify::PublicKey::from_base64(key.trim()).with_context(|| format!("Invalid key `{}` in `plugins.trusted_keys`", key))?;
+        if key.verify(artifact, &signature, false).is_ok() {
+            return Ok(());
+        }
+    }
+    bail!("The plugin is not signed by any key in `plugins.trusted_keys`; refusing to install it")
+}
+
+/// Fetch `name` from the registry, verify it and install it, replacing an older version
+pub async fn install(cfg: &PluginsConfig, name: &str) -> Result<()> {
+    if name.is_empty() || name.contains(['/', '\\', '.']) {
+        bail!("`{}` is not a plugin name", name);
+    }
+    let registry = cfg.registry.as_deref().unwrap_or(REGISTRY);
+    let mut keys = cfg.trusted_keys.clone().unwrap_or_default();
+    if cfg.registry.is_none() {
+        keys.extend(REGISTRY_PUBKEY.map(String::from));
+    }
+    if keys.is_empty() {
+        bail!("No key to verify plugins with; add the registry's minisign public key to `[plugins] trusted_keys`");
+    }
+    let client = reqwest::Client::builder().user_agent(concat!("rustaigent/", env!("CARGO_PKG_VERSION"))).build()?;
+    let index: Index = client.get(registry).send().await?.error_for_status()
+        .with_context(|| format!("Failed to fetch the plugin registry {}", registry))?
+        .json().await.with_context(|| format!("{} is not a plugin registry", registry))?;
+    let entry = index.plugins.into_iter().filter(|p| p.name == name).max_by_key(|p| parse_version(&p.version))
+        .with_context(|| format!("No plugin `{}` in {}", name, registry))?;
+    let signature = match &entry.signature {
+        Some(signature) => signature.clone(),
+        None => client.get(format!("{}.minisig", entry.url)).send().await?.error_for_status()
+            .context("The plugin is not signed; refusing to install it")?.text().await?,
+    };
+
+    let file_name = archive_name(&entry.url)?;
+    // Made fresh and private, so nobody else can plant files in it; removed when dropped
+    let tmp = tempfile::Builder::new().prefix(&format!("rustaigent-plugin-{}-", name)).tempdir()
+        .context("Failed to create a staging directory")?;
+    let staging = tmp.path();
+    let archive = staging.join(file_name);
+    // The user chose the registry, so the model's network limits do not apply to it
+    let network = network::Network::new(None, true, None);
+    download::download(&network, &entry.url, staging, file_name, Some(&entry.sha256), download::Progress::Redraw).await?;
+    verify(&fs::read(&archive)?, &signature, &keys)?;
+    let unpacked = staging.join("unpacked");
+    archive::extract(staging, file_name, "unpacked")?;
+    let manifest = read_manifest(&unpacked)?;
+    if manifest.name != entry.name {
+        bail!("The archive holds plugin `{}`, not `{}`", manifest.name, entry.name);
+    }
+    let target = dir().join(name);
+    if target.exists() {
+        fs::remove_dir_all(&target).with_context(|| format!("Failed to remove the installed {}", target.display()))?;
+    }
+    fs::create_dir_all(dir())?;
+    fs::rename(&unpacked, &target).or_else(|_| copy_dir(&unpacked, &target))?;
+    println!("Installed {} {} ({} tools) into {}", manifest.name, manifest.version, manifest.tools.len(), target.display());
+    if !entry.description.is_empty() {
+        println!("{}", entry.description);
+    }
+    Ok(())
+}
+
+/// The file name at the end of an artifact URL, without its query
+fn archive_name(url: &str) -> Result<&str> {
+    let name = url.split(['?', '#']).next().unwrap_or_default().rsplit('/').next().unwrap_or_default();
+    if name.is_empty() || name == "." || name == ".." || name.contains('\\') {
+        bail!("The plugin URL {} does not end in a file name", url);
+    }
+    Ok(name)
+}
+
+/// Copy a directory across filesystems, where a rename fails
+fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)?.flatten() {
+        let target = to.join(entry.file_name());
+        if entry.path().is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Installed plugins as `name version (n tools)` lines
+pub fn list() -> Result<Vec<String>> {
+    let mut out = Vec::new();
+    for entry in fs::read_dir(dir()).into_iter().flatten().flatten().filter(|e| e.path().is_dir()) {
+        match read_manifest(&entry.path()) {
+            Ok(m) => out.push(format!("{} {} ({} tools)", m.name, m.version, m.tools.len())),
+            Err(err) => out.push(format!("{}: {:#}", entry.file_name().to_string_lossy(), err)),
+        }
+    }
+    out.sort();
+    Ok(out)
+}
+
+pub fn remove(name: &str) -> Result<()> {
+    let target = dir().join(name);
+    if name.is_empty() || name.contains(['/', '\\', '.']) || !target.is_dir() {
+        bail!("No plugin `{}` is installed", name);
+    }
+    fs::remove_dir_all(&target).with_context(|| format!("Failed to remove {}", target.display()))
+}
+
+/// A tool of an installed plugin
+struct PluginTool {
+    plugin: PathBuf,
+    spec: ToolSpec,
+    definition: FunctionDefinition,
+}
+
+impl PluginTool {
+    async fn run(&self, args: Value) -> Result<String> {
+        let mut command = match (&self.spec.command, &self.spec.wasm) {
+            (Some(program), _) => {
+                let shipped = self.plugin.join(program);
+                let mut command = tokio::process::Command::new(if shipped.is_file() { shipped } else { PathBuf::from(program) });
+                command.args(&self.spec.args);
+                command
+            }
+            (None, Some(module)) => {
+                let mut command = tokio::process::Command::new("wasmtime");
+                command.arg("run").arg("--dir=.").arg(self.plugin.join(module)).args(&self.spec.args);
+                command
+            }
+            (None, None) => bail!("Tool `{}` sets neither `command` nor `wasm`", self.spec.name),
+        };
+        for var in HIDDEN_ENV {
+            command.env_remove(var);
+        }
+        let mut child = command.env("RUSTAIGENT_PLUGIN_DIR", &self.plugin)
+            .stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| match &self.spec.wasm {
+                Some(_) => "WASM plugins run with the wasmtime CLI; install it from https://wasmtime.dev".to_string(),
+                None => format!("Failed to start the `{}` tool", self.spec.name),
+            })?;
+        let mut stdin = child.stdin.take().context("no stdin pipe")?;
+        stdin.write_all(args.to_string().as_bytes()).await?;
+        drop(stdin);
+        let timeout = Duration::from_secs(self.spec.timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS));
+        let output = tokio::time::timeout(timeout, child.wait_with_output()).await
+            .map_err(|_| anyhow::anyhow!("`{}` did not finish within {}", self.spec.name, humantime::format_duration(timeout)))??;
+        if !output.status.success() {
+            bail!("`{}` failed ({}): {}", self.spec.name, output.status, String::from_utf8_lossy(&output.stderr).trim());
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+impl Tool for PluginTool {
+    fn definition(&self) -> FunctionDefinition {
+        self.definition.clone()
+    }
+
+    fn call(&self, args: Value) -> BoxFuture<'_, Result<String>> {
+        Box::pin(self.run(args))
+    }
+
+    fn untrusted(&self) -> bool {
+        self.spec.untrusted
+    }
+}
+
+/// Offer the tools of the installed plugins as `<plugin>__<tool>`, like MCP tools; a broken
+/// plugin is skipped with a warning
+pub fn load(agent: &mut Agent) {
+    let cfg = agent.config.plugins.clone().unwrap_or_default();
+    let mut functions = agent.functions.to_vec();
+    for entry in fs::read_dir(dir()).into_iter().flatten().flatten().filter(|e| e.path().is_dir()) {
+        let plugin = entry.path();
+        let manifest = match read_manifest(&plugin) {
+            Ok(manifest) => manifest,
+            Err(err) => {
+                warn!("plugin in {} is unusable: {:#}", plugin.display(), err);
+                continue;
+            }
+        };
+        if cfg.disabled.iter().flatten().any(|d| *d == manifest.name) {
+            continue;
+        }
+        let mut offered = 0;
+        for spec in manifest.tools {
+            let definition = FunctionDefinition {
+                name: format!("{}__{}", manifest.name, spec.name),
+                description: spec.description.clone(),
+                parameters: spec.parameters.clone().unwrap_or_else(|| serde_json::json!({ "type": "object", "properties": {} })),
+            };
+            if agent.config.allowed_tools.as_ref().is_some_and(|allowed| !allowed.contains(&definition.name)) {
+                continue;
+            }
+            if functions.iter().any(|f| f.name == definition.name) {
+                warn!("plugin tool `{}` is named like a tool already offered; leaving it out", definition.name);
+                continue;
+            }
+            functions.push(definition.clone());
+            agent.custom_tools.push(Arc::new(PluginTool { plugin: plugin.clone(), spec, definition }));
+            offered += 1;
+        }
+        info!("plugin `{}` {} offers {} tools", manifest.name, manifest.version, offered);
+    }
+    agent.functions = functions.into();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{archive_name, verify};
+
+    /// Minisign key that signed `SIGNATURE` over `ARTIFACT`, and one that did not
+    const PUBLISHER: &str = "RWTJ5hRldXKymoevjRbtJkM6te4wcl5PDRkoThnLaoi+nBh2bJ5maWzZ";
+    const STRANGER: &str = "RWRNu/l9nEzTZ8CGXZBvfcH7yUeewOFx/RICCbU7YNk772WjOPOkK8va";
+    const ARTIFACT: &[u8] = b"plugin archive";
+    const SIGNATURE: &str = "untrusted comment: signature from minisign secret key\nRUTJ5hRldXKymi2b7ci4CFsiRIc59CJEQ6d5m9tP2t9jfwk3fLKV+foqHQRLC9ZUlzVtXObGXf1BEEjLXTBMYTQGzDAhv8fWZwA=\ntrusted comment: timestamp:1760000000\tfile:weather.tar.gz\nx9+o1dW0PMAQNUeFupw6Xnoubo79SN3gDLy6Ue9tlIzgeJzadyrM6oBJmQEibL9JQnqe27WZFtU91uTnHCYdCQ==\n";
+
+    #[test]
+    fn artifacts_install_only_with_a_signature_by_a_trusted_key() {
+        assert!(verify(ARTIFACT, SIGNATURE, &[PUBLISHER.into()]).is_ok());
+        assert!(verify(ARTIFACT, SIGNATURE, &[STRANGER.into(), format!(" {} ", PUBLISHER)]).is_ok());
+        let refused = |artifact: &[u8], signature: &str, keys: &[&str]| {
+            verify(artifact, signature, &keys.iter().map(|k| k.to_string()).collect::<Vec<_>>()).unwrap_err().to_string()
+        };
+        assert!(refused(ARTIFACT, SIGNATURE, &[STRANGER]).contains("not signed by any key"));
+        assert!(refused(ARTIFACT, SIGNATURE, &[]).contains("not signed by any key"));
+        assert!(refused(b"plugin archivE", SIGNATURE, &[PUBLISHER]).contains("not signed by any key"));
+        let forged = SIGNATURE.replace("timestamp:1760000000", "timestamp:1760000001");
+        assert!(refused(ARTIFACT, &forged, &[PUBLISHER]).contains("not signed by any key"));
+        assert!(refused(ARTIFACT, "not a signature", &[PUBLISHER]).contains("Invalid plugin signature"));
+        assert!(refused(ARTIFACT, SIGNATURE, &["RWQ="]).contains("Invalid key"));
+    }
+
+    #[test]
+    fn artifacts_are_staged_under_their_url_file_name() {
+        assert_eq!(archive_name("https://plugins.example/weather/1.2.0/weather.tar.gz?sig=abc").unwrap(), "weather.tar.gz");
+        for url in ["https://plugins.example/weather/", "https://plugins.example/..", "https://plugins.example/a/.?x=1", "https://plugins.example/..\\evil.zip"] {
+            assert!(archive_name(url).is_err(), "{}", url);
+        }
+    }
+}
@@ -0,0 +1,408 @@
+use std::{collections::BTreeMap, fmt, path::{Component, Path, PathBuf}};
+use chrono::Timelike;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use anyhow::{Result, Context, bail};
+use crate::tools::ToolCall;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Allow,
+    Deny,
+    /// Require interactive approval
+    Ask,
+}
+
+/// One `[[policy]]` entry; every condition that is set must match
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Rule {
+    /// Glob on the tool name, e.g. `run_*`
+    pub tool: Option<String>,
+    /// Globs on string arguments, e.g. `{ command = "git push*" }`
+    pub args: Option<BTreeMap<String, String>>,
+    /// Glob on any path argument, e.g. `/etc/**`
+    pub path: Option<String>,
+    /// Local time window in whole hours, `9-18`; may wrap past midnight (`22-6`)
+    pub hours: Option<String>,
+    /// Session role from `role` / RUSTAIGENT_ROLE
+    pub role: Option<String>,
+    pub action: Action,
+    pub reason: Option<String>,
+}
+
+fn parse_hours(spec: &str) -> Result<(u32, u32)> {
+    let (start, end) = spec.split_once('-').with_context(|| format!("hours `{}` must look like 9-18", spec))?;
+    let (start, end): (u32, u32) = (start.trim().parse()?, end.trim().parse()?);
+    if start > 24 || end > 24 {
+        bail!("hours `{}` must be between 0 and 24", spec);
+    }
+    Ok((start, end))
+}
+
+fn glob(pattern: &str) -> Result<glob::Pattern> {
+    glob::Pattern::new(pattern).with_context(|| format!("invalid glob `{}`", pattern))
+}
+
+impl Rule {
+    /// Reject malformed globs and hour ranges up front
+    pub fn check(&self) -> Result<()> {
+        for pattern in self.tool.iter().chain(self.path.iter()).chain(self.args.iter().flat_map(|a| a.values())) {
+            glob(pattern)?;
+        }
+        if let Some(hours) = &self.hours {
+            parse_hours(hours)?;
+        }
+        Ok(())
+    }
+
+    fn matches(&self, call: &Call) -> bool {
+        let glob_match = |pattern: &str, text: &str| glob(pattern).is_ok_and(|p| p.matches(text));
+        if self.tool.as_deref().is_some_and(|t| !glob_match(t, call.tool)) {
+            return false;
+        }
+        if let Some(args) = &self.args {
+            let all = args.iter().all(|(key, pattern)| call.args[key].as_str().is_some_and(|v| glob_match(pattern, v)));
+            if !all {
+                return false;
+            }
+        }
+        if let Some(pattern) = &self.path {
+            if !call.paths.iter().any(|p| glob_match(pattern, p)) {
+                return false;
+            }
+        }
+        if let Some(Ok((start, end))) = self.hours.as_deref().map(parse_hours) {
+            let inside = if start <= end { (start..end).contains(&call.hour) } else { call.hour >= start || call.hour < end };
+            if !inside {
+                return false;
+            }
+        }
+        if self.role.as_ref().is_some_and(|r| call.role != Some(r.as_str())) {
+            return false;
+        }
+        true
+    }
+}
+
+/// A tool call as seen by the policy
+pub struct Call<'a> {
+    pub tool: &'a str,
+    pub args: Value,
+    /// Every file, directory or glob argument
+    pub paths: Vec<&'a str>,
+    pub role: Option<&'a str>,
+    /// Local hour, 0-23
+    pub hour: u32,
+}
+
+impl<'a> Call<'a> {
+    pub fn now(call: &'a ToolCall, role: Option<&'a str>) -> Self {
+        Call { tool: call.name(), args: call.args(), paths: call.paths(), role, hour: chrono::Local::now().hour() }
+    }
+
+    /// A call to a tool the embedding application registered; it has no known path arguments
+    pub fn custom(tool: &'a str, args: Value, role: Option<&'a str>) -> Self {
+        Call { tool, args, paths: Vec::new(), role, hour: chrono::Local::now().hour() }
+    }
+}
+
+/// Values accepted in the `[permissions]` table
+pub const LEVELS: &[&str] = &["allow", "ask", "deny"];
+
+/// Tools that need approval unless `[permissions]` or a rule says otherwise
+const ASK_BY_DEFAULT: &[(&str, &str)] = &[
+    ("write_file", "writes a file"),
+    ("apply_patch", "edits files"),
+    ("delete_file", "deletes a file"),
+    ("run_command", "runs a shell command"),
+    ("eval_code", "compiles and runs code"),
+    ("git_commit", "creates a git commit"),
+    ("download_file", "writes a downloaded file"),
+    ("extract_archive", "writes the files of an archive"),
+    ("create_archive", "writes an archive"),
+    ("edit_structured", "edits a file"),
+    ("run_tests", "runs the project's test command"),
+    ("terraform_plan", "runs terraform"),
+];
+
+/// What decided a call
+#[derive(PartialEq)]
+pub enum Origin {
+    /// 1-based `[[policy]]` rule number
+    Rule(usize),
+    /// The tool's `[permissions]` level
+    Permission,
+    /// A path argument outside the workspace and `allowed_paths`
+    Sandbox,
+    /// Nothing configured matched
+    Default,
+    /// `injection_guard`: the call may act on untrusted content
+    Untrusted,
+    /// `[command_safety]`: the command scored this risky out of 10
+    Risk(u8),
+    /// `[codeowners]`: the call changes files other teams own
+    Owners,
+}
+
+/// Outcome of evaluating a call, naming what decided it
+pub struct Decision {
+    pub action: Action,
+    pub origin: Origin,
+    pub reason: Option<String>,
+}
+
+impl fmt::Display for Decision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let verb = match self.action {
+            Action::Allow => "allowed",
+            Action::Deny => "denied",
+            Action::Ask => "needs approval",
+        };
+        write!(f, "{}", verb)?;
+        match self.origin {
+            Origin::Rule(n) => write!(f, " by policy rule #{}", n)?,
+            Origin::Permission => write!(f, " by `permissions`")?,
+            Origin::Sandbox => write!(f, " outside the allowed paths")?,
+            Origin::Default => write!(f, " by default")?,
+            Origin::Untrusted => write!(f, " as it may act on untrusted content")?,
+            Origin::Risk(score) => write!(f, " as a risky command ({}/10)", score)?,
+            Origin::Owners => write!(f, " as it changes files other teams own")?,
+        }
+        if let Some(reason) = &self.reason {
+            write!(f, ": {}", reason)?;
+        }
+        Ok(())
+    }
+}
+
+/// Path sandbox, `[permissions]` levels and ordered rules. The sandbox is checked first, then
+/// the first matching rule decides, then the tool's level; anything left is allowed, except the
+/// tools in `ASK_BY_DEFAULT` when loaded from the config
+#[derive(Default, Clone)]
+pub struct Policy {
+    pub rules: Vec<Rule>,
+    pub role: Option<String>,
+    /// `[permissions]` level per tool name
+    pub levels: BTreeMap<String, Action>,
+    /// Ask before the `ASK_BY_DEFAULT` tools that have no level
+    pub ask_by_default: bool,
+    /// Directories path arguments must stay inside; `None` leaves paths unrestricted
+    pub roots: Option<Vec<PathBuf>>,
+    /// Directory relative path arguments resolve against; the working directory when unset
+    pub workdir: Option<PathBuf>,
+    /// `--yolo`: approve every call that would ask; denials still apply
+    pub yolo: bool,
+    /// Scratch directories made during the session; calls whose path arguments all lie inside
+    /// them run without the default approval
+    pub scratch: Vec<PathBuf>,
+}
+
+impl Policy {
+    /// Rules and levels from the config, confined to the working directory and `allowed_paths`;
+    /// the role comes from RUSTAIGENT_ROLE, then the `role` key
+    pub fn from_config(config: &crate::config::Config) -> Self {
+        let cwd = crate::paths::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+        let roots = std::iter::once(cwd.clone())
+            .chain(config.allowed_paths.iter().flatten().map(|p| cwd.join(p)))
+            .map(|root| resolve(&root))
+            .collect();
+        Policy {
+            rules: config.policy.clone().unwrap_or_default(),
+            role: std::env::var("RUSTAIGENT_ROLE").ok().or_else(|| config.role.clone()),
+            levels: config.permissions.clone().unwrap_or_default(),
+            ask_by_default: true,
+            roots: Some(roots),
+            workdir: None,
+            yolo: false,
+            scratch: Vec::new(),
+        }
+    }
+
+    /// Let path arguments reach into `dir`, e.g. a scratch directory made during the session
+    pub fn allow_path(&mut self, dir: &Path) {
+        if let Some(roots) = &mut self.roots {
+            roots.push(resolve(dir));
+        }
+    }
+
+    /// Let path arguments reach into the scratch directory `dir` and use it without approval
+    pub fn allow_scratch(&mut self, dir: &Path) {
+        self.allow_path(dir);
+        self.scratch.push(resolve(dir));
+    }
+
+    /// Where a path argument points, following symlinks as far as it exists
+    fn locate(&self, path: &str) -> Option<PathBuf> {
+        let cwd = self.workdir.clone().or_else(|| crate::paths::current_dir().ok())?;
+        // Only the literal prefix of a glob names a place on disk
+        let literal = path.find(['*', '?', '[']).map_or(path, |i| &path[..i]);
+        Some(resolve(&cwd.join(literal)))
+    }
+
+    /// The first path argument that resolves outside every root
+    fn outside<'a>(&self, call: &Call<'a>) -> Option<&'a str> {
+        let roots = self.roots.as_ref()?;
+        call.paths.iter().copied().find(|path| {
+            self.locate(path).is_some_and(|resolved| !roots.iter().any(|root| resolved.starts_with(root)))
+        })
+    }
+
+    /// Whether the call has path arguments and all of them lie in scratch directories
+    fn in_scratch(&self, call: &Call) -> bool {
+        !call.paths.is_empty() && call.paths.iter().all(|path| {
+            self.locate(path).is_some_and(|resolved| self.scratch.iter().any(|dir| resolved.starts_with(dir)))
+        })
+    }
+
+    pub fn evaluate(&self, call: &Call) -> Decision {
+        if let Some(path) = self.outside(call) {
+            return Decision { action: Action::Deny, origin: Origin::Sandbox, reason: Some(format!("{} is not under the workspace or `allowed_paths`", path)) };
+        }
+        if let Some((i, rule)) = self.rules.iter().enumerate().find(|(_, rule)| rule.matches(call)) {
+            return Decision { action: rule.action, origin: Origin::Rule(i + 1), reason: rule.reason.clone() };
+        }
+        if let Some(&action) = self.levels.get(call.tool) {
+            return Decision { action, origin: Origin::Permission, reason: None };
+        }
+        match ASK_BY_DEFAULT.iter().find(|(tool, _)| *tool == call.tool) {
+            Some((_, why)) if self.ask_by_default && !self.in_scratch(call) => Decision { action: Action::Ask, origin: Origin::Default, reason: Some(why.to_string()) },
+            _ => Decision { action: Action::Allow, origin: Origin::Default, reason: None },
+        }
+    }
+}
+
+/// Absolute path with `.` and `..` folded and symlinks resolved as far as the path exists,
+/// so neither can step outside a root. Each existing prefix is resolved before the next part
+/// applies, so a `..` after a symlink climbs out of the link's target, as the OS would.
+fn resolve(path: &Path) -> PathBuf {
+    let mut real = PathBuf::new();
+    for part in path.components() {
+        match part {
+            Component::ParentDir => { real.pop(); }
+            Component::CurDir => {}
+            other => {
+                real.push(other);
+                if let Ok(canonical) = real.canonicalize() {
+                    real = canonical;
+                }
+            }
+        }
+    }
+    real
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, fs, path::PathBuf};
+    use serde_json::json;
+    use crate::tools::{ToolCall, ToolContext};
+    use super::{resolve, Action, Call, Origin, Policy};
+
+    fn sandbox(name: &str) -> (PathBuf, Policy) {
+        let dir = resolve(&std::env::temp_dir().join(format!("rustaigent-policy-{}-{}", name, std::process::id())));
+        fs::create_dir_all(dir.join("work")).unwrap();
+        let policy = Policy { roots: Some(vec![dir.join("work")]), workdir: Some(dir.join("work")), ..Policy::default() };
+        (dir, policy)
+    }
+
+    fn read(path: &str) -> Call<'_> {
+        Call { tool: "read_file", args: json!({ "path": path }), paths: vec![path], role: None, hour: 12 }
+    }
+
+    #[test]
+    fn paths_outside_the_workspace_are_denied() {
+        let (dir, policy) = sandbox("escape");
+        let outside = dir.join("secret").to_string_lossy().into_owned();
+        let inside = dir.join("work").join("notes.md").to_string_lossy().into_owned();
+        let decisions: Vec<_> = ["src/main.rs", "src/../../secret", "../work/../secret", outside.as_str(), inside.as_str(), "/etc/passwd"]
+            .into_iter().map(|path| policy.evaluate(&read(path)).origin == Origin::Sandbox).collect();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(decisions, [false, true, true, true, false, true]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn parent_components_after_a_symlink_leave_from_its_target() {
+        let (dir, policy) = sandbox("symlink");
+        fs::create_dir_all(dir.join("elsewhere").join("deep")).unwrap();
+        std::os::unix::fs::symlink(dir.join("elsewhere").join("deep"), dir.join("work").join("link")).unwrap();
+        std::os::unix::fs::symlink(dir.join("work"), dir.join("elsewhere").join("back")).unwrap();
+        let through = policy.evaluate(&read("link/file")).origin == Origin::Sandbox;
+        let climbed = policy.evaluate(&read("link/../secret")).origin == Origin::Sandbox;
+        let missing = policy.evaluate(&read("link/new/../../x")).origin == Origin::Sandbox;
+        let returned = policy.evaluate(&read("link/../back/file")).origin == Origin::Sandbox;
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(through && climbed && missing);
+        assert!(!returned);
+    }
+
+    #[test]
+    fn permission_levels_decide_before_the_defaults() {
+        let (dir, mut policy) = sandbox("levels");
+        policy.ask_by_default = true;
+        policy.levels = BTreeMap::from([("read_file".to_string(), Action::Deny), ("delete_file".to_string(), Action::Allow)]);
+        let read_file = policy.evaluate(&read("a.txt"));
+        let delete = policy.evaluate(&Call { tool: "delete_file", ..read("a.txt") });
+        let write = policy.evaluate(&Call { tool: "write_file", ..read("a.txt") });
+        let list = policy.evaluate(&Call { tool: "list_dir", ..read("a.txt") });
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(read_file.action == Action::Deny && read_file.origin == Origin::Permission);
+        assert!(delete.action == Action::Allow && delete.origin == Origin::Permission);
+        assert!(write.action == Action::Ask && write.origin == Origin::Default);
+        assert!(list.action == Action::Allow && list.origin == Origin::Default);
+    }
+
+    #[test]
+    fn tools_that_write_files_or_run_programs_ask() {
+        let (dir, mut policy) = sandbox("writers");
+        policy.ask_by_default = true;
+        let asks: Vec<_> = ["download_file", "extract_archive", "create_archive", "edit_structured", "run_tests", "terraform_plan"]
+            .into_iter().filter(|&tool| policy.evaluate(&Call { tool, ..read("a.txt") }).action != Action::Ask).collect();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(asks.is_empty(), "{:?} run without approval", asks);
+    }
+
+    #[test]
+    fn calls_confined_to_a_scratch_directory_need_no_approval() {
+        let (dir, mut policy) = sandbox("scratch");
+        policy.ask_by_default = true;
+        fs::create_dir_all(dir.join("scratch")).unwrap();
+        policy.allow_scratch(&dir.join("scratch"));
+        let scratch = dir.join("scratch").join("a.txt").to_string_lossy().into_owned();
+        let write = |paths| policy.evaluate(&Call { tool: "write_file", paths, ..read("") }).action;
+        let (inside, workspace, mixed) = (write(vec![&scratch]), write(vec!["a.txt"]), write(vec![&scratch, "a.txt"]));
+        let command = policy.evaluate(&Call { tool: "run_command", paths: Vec::new(), ..read("") }).action;
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(inside == Action::Allow);
+        assert!(workspace == Action::Ask && mixed == Action::Ask && command == Action::Ask);
+    }
+
+    #[test]
+    fn terraform_modules_and_plan_files_stay_in_the_workspace() {
+        let (dir, policy) = sandbox("terraform");
+        let origin = |args| policy.evaluate(&Call::now(&ToolCall::parse("terraform_plan", args).unwrap(), None)).origin;
+        let inside = origin(json!({ "dir": "infra", "plan_file": "plan.out" }));
+        let module = origin(json!({ "dir": "../other" }));
+        let plan = origin(json!({ "plan_file": "/etc/plan.out" }));
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(inside != Origin::Sandbox);
+        assert!(module == Origin::Sandbox && plan == Origin::Sandbox);
+    }
+
+    #[test]
+    fn yolo_waives_approvals_but_not_denials() {
+        let (dir, mut policy) = sandbox("yolo");
+        policy.ask_by_default = true;
+        policy.yolo = true;
+        let mut ctx = ToolContext::default();
+        ctx.policy = policy;
+        let write = ToolCall::parse("write_file", json!({ "path": "a.txt", "content": "" })).unwrap();
+        let escape = ToolCall::parse("write_file", json!({ "path": "../a.txt", "content": "" })).unwrap();
+        let asks = ctx.will_ask(&write);
+        let denied = ctx.policy.evaluate(&Call::now(&escape, None)).action;
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(!asks);
+        assert!(denied == Action::Deny);
+    }
+}
@@ -0,0 +1,124 @@
+//! Post-processors for final answers, applied in `post_process` order before an answer is
+//! printed or written as a batch result. The transcript keeps the model's own text.
+
+use std::{io::Write, process::{Command, Stdio}};
+use regex::Regex;
+use serde::{Serialize, Deserialize};
+use anyhow::{Result, Context, bail};
+use crate::{turns::Turn, Agent};
+
+/// One `[[post_process]]` entry
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Step {
+    /// Plain text: no headings, emphasis, code fences, quote markers or link targets
+    StripMarkdown,
+    /// Cut at a word boundary to at most `chars` characters, ending with `ellipsis` (default `…`)
+    MaxLength { chars: usize, ellipsis: Option<String> },
+    /// Pipe the answer through a shell command, e.g. `prettier --parser markdown`
+    Command { command: String },
+    /// Have the model translate the answer, e.g. `language = "de"`
+    Translate { language: String },
+}
+
+impl Step {
+    /// Reject settings that could never produce output
+    pub fn check(&self) -> Result<()> {
+        match self {
+            Step::MaxLength { chars, ellipsis } if *chars <= ellipsis.as_deref().unwrap_or("…").chars().count() => {
+                bail!("`chars` must be longer than the ellipsis")
+            }
+            Step::Command { command } if command.trim().is_empty() => bail!("`command` is empty"),
+            Step::Translate { language } if language.trim().is_empty() => bail!("`language` is empty"),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn strip_markdown(text: &str) -> String {
+    let heading = Regex::new(r"^\s{0,3}#{1,6}\s+").unwrap();
+    let quote = Regex::new(r"^\s*>\s?").unwrap();
+    let rule = Regex::new(r"^\s{0,3}([-*_])(\s*[-*_]){2,}\s*$").unwrap();
+    let image = Regex::new(r"!\[([^\]]*)\]\([^)]*\)").unwrap();
+    let link = Regex::new(r"\[([^\]]+)\]\([^)]*\)").unwrap();
+    let strong = Regex::new(r"(\*\*|__)(\S(?:.*?\S)?)(\*\*|__)").unwrap();
+    let emphasis = Regex::new(r"(^|[^\w*])\*(\S(?:.*?\S)?)\*").unwrap();
+    let code = Regex::new(r"`([^`]+)`").unwrap();
+    let mut out = Vec::new();
+    let mut in_code = false;
+    for line in text.lines() {
+        if line.trim_start().starts_with("```") {
+            in_code = !in_code;
+            continue;
+        }
+        if in_code {
+            out.push(line.to_string());
+            continue;
+        }
+        if rule.is_match(line) {
+            continue;
+        }
+        let line = heading.replace(line, "");
+        let line = quote.replace(&line, "");
+        let line = image.replace_all(&line, "$1");
+        let line = link.replace_all(&line, "$1");
+        let line = strong.replace_all(&line, "$2");
+        let line = emphasis.replace_all(&line, "$1$2");
+        out.push(code.replace_all(&line, "$1").into_owned());
+    }
+    out.join("\n")
+}
+
+/// At most `chars` characters, cut at the last whitespace when there is one in the kept part
+fn max_length(text: &str, chars: usize, ellipsis: &str) -> String {
+    if text.chars().count() <= chars {
+        return text.to_string();
+    }
+    let keep = chars.saturating_sub(ellipsis.chars().count());
+    let cut: String = text.chars().take(keep).collect();
+    let cut = match cut.rfind(char::is_whitespace) {
+        Some(i) if i > 0 => &cut[..i],
+        _ => &cut,
+    };
+    format!("{}{}", cut.trim_end(), ellipsis)
+}
+
+fn pipe(command: &str, text: &str) -> Result<String> {
+    let mut child = Command::new("sh").arg("-c").arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run `{}`", command))?;
+    child.stdin.take().context("No stdin for the post-processor")?.write_all(text.as_bytes())?;
+    let out = child.wait_with_output()?;
+    if !out.status.success() {
+        bail!("`{}` failed ({}): {}", command, out.status, String::from_utf8_lossy(&out.stderr).trim());
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).trim_end().to_string())
+}
+
+async fn translate(agent: &Agent, text: &str, language: &str) -> Result<String> {
+    let lang = crate::i18n::Lang::new(language);
+    let prompt = format!(
+        "Translate the text below into {} ({}). Keep code blocks, identifiers and citation markers such as [1] unchanged. Reply with the translation only.\n\n{}",
+        lang.name(), language, text
+    );
+    let reply = agent.clone_for_batch(prompt).send_request(Turn::Answer, None).await?;
+    Ok(reply.content)
+}
+
+/// Run an answer through every step in order
+pub async fn apply(agent: &Agent, steps: &[Step], text: String) -> Result<String> {
+    let mut text = text;
+    for (i, step) in steps.iter().enumerate() {
+        let result = match step {
+            Step::StripMarkdown => Ok(strip_markdown(&text)),
+            Step::MaxLength { chars, ellipsis } => Ok(max_length(&text, *chars, ellipsis.as_deref().unwrap_or("…"))),
+            Step::Command { command } => pipe(command, &text),
+            Step::Translate { language } => translate(agent, &text, language).await,
+        };
+        text = result.with_context(|| format!("post_process step #{}", i + 1))?;
+    }
+    Ok(text)
+}
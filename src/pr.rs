@@ -0,0 +1,196 @@
+//! `rustaigent pr-describe [base]`: the branch's commits and diff summarized into a pull request
+//! title and description, optionally pushed to GitHub as a new or updated pull request.
+
+use std::{fs, path::PathBuf};
+use reqwest::Method;
+use serde::Deserialize;
+use serde_json::json;
+use anyhow::{Result, Context, bail};
+use crate::{digest, github, gitdiff::git, Agent};
+
+/// Commit subjects listed in the prompt; the diff carries the detail
+const MAX_COMMITS: usize = 100;
+
+/// What the notes on a diff too large for one prompt should capture
+const NOTES_FOCUS: &str = "Note what each file's change does and why it appears to be made, and anything that breaks existing users, configs or APIs.";
+
+/// What the model wrote about the change
+#[derive(Deserialize)]
+pub struct Description {
+    pub title: String,
+    pub summary: String,
+    #[serde(default)]
+    pub test_plan: String,
+    #[serde(default)]
+    pub breaking_changes: Vec<String>,
+    /// Issue the pull request resolves, referenced at the end of the body
+    #[serde(skip)]
+    pub closes: Option<u64>,
+}
+
+impl Description {
+    fn parse(reply: &str) -> Result<Self> {
+        let start = reply.find('{').context("The reply contains no description")?;
+        let end = reply.rfind('}').filter(|&end| end > start).context("The reply contains no description")?;
+        serde_json::from_str(&reply[start..=end]).context("The description is not valid JSON")
+    }
+
+    /// The pull request body in Markdown
+    pub fn body(&self) -> String {
+        let test_plan = match self.test_plan.trim() {
+            "" => "Not described.",
+            plan => plan,
+        };
+        let mut body = format!("## Summary\n\n{}\n\n## Test plan\n\n{}\n\n## Breaking changes\n\n", self.summary.trim(), test_plan);
+        if self.breaking_changes.is_empty() {
+            body.push_str("None.\n");
+        }
+        for change in &self.breaking_changes {
+            body.push_str(&format!("- {}\n", change.trim()));
+        }
+        if let Some(issue) = self.closes {
+            body.push_str(&format!("\nCloses #{}\n", issue));
+        }
+        body.trim_end().to_string()
+    }
+}
+
+fn branch_exists(name: &str) -> bool {
+    git(&["rev-parse", "--verify", "-q", name]).is_ok()
+}
+
+/// The branch pull requests target by default: the one `origin/HEAD` names, else `main` or `master`
+pub fn default_base() -> Result<String> {
+    if let Ok(head) = git(&["symbolic-ref", "--short", "refs/remotes/origin/HEAD"]) {
+        return Ok(head.trim().trim_start_matches("origin/").to_string());
+    }
+    ["main", "master"].into_iter()
+        .find(|b| branch_exists(&format!("refs/heads/{}", b)))
+        .map(String::from)
+        .context("Cannot tell the base branch; pass it, e.g. `rustaigent pr-describe main`")
+}
+
+/// The ref to diff against: the local branch, else its `origin` counterpart, else `base` as given
+fn base_ref(base: &str) -> String {
+    let remote = format!("origin/{}", base);
+    if !branch_exists(&format!("refs/heads/{}", base)) && branch_exists(&format!("refs/remotes/{}", remote)) {
+        return remote;
+    }
+    base.to_string()
+}
+
+fn prompt(base: &str, changes: &Changes, diff: &digest::Digest) -> String {
+    format!(
+        "Describe this branch as a pull request into `{}`.\n\n{}\n\n{}\n\n\
+         Reply with only a JSON object: {{\"title\": string, \"summary\": string, \"test_plan\": string, \"breaking_changes\": [string]}}. \
+         The title is imperative and under 72 characters. The summary says what changed and why in a few sentences or bullets. \
+         The test plan says how the change was or should be verified. List only changes that break existing users, configs or APIs.",
+        base, changes.overview(), diff.render())
+}
+
+/// What HEAD changed since it left a base branch
+pub struct Changes {
+    /// `- subject` per commit, newest first
+    pub log: String,
+    pub stat: String,
+    pub diff: String,
+}
+
+impl Changes {
+    /// Commit subjects and changed files, for a prompt
+    pub fn overview(&self) -> String {
+        format!("Commits:\n{}\n\nChanged files:\n```\n{}\n```",
+            if self.log.trim().is_empty() { "(none yet)" } else { self.log.trim_end() }, self.stat.trim_end())
+    }
+}
+
+/// The commits and diff since HEAD left `base`, with uncommitted changes too when `uncommitted` is set
+pub fn changes(base: &str, uncommitted: bool) -> Result<Changes> {
+    let base_ref = base_ref(base);
+    let merge_base = git(&["merge-base", &base_ref, "HEAD"]).with_context(|| format!("Cannot find where HEAD left `{}`", base))?;
+    let merge_base = merge_base.trim();
+    let log = git(&["log", "--format=- %s", &format!("{}..HEAD", merge_base)])?.lines().take(MAX_COMMITS).map(|l| format!("{}\n", l)).collect();
+    let mut range = vec![merge_base];
+    if !uncommitted {
+        range.push("HEAD");
+    }
+    let diff = git(&[&["diff"], &range[..]].concat())?;
+    if diff.trim().is_empty() {
+        bail!("No changes since `{}`", base);
+    }
+    let stat = git(&[&["diff", "--stat"], &range[..]].concat())?;
+    Ok(Changes { log, stat, diff })
+}
+
+/// Have the model describe what HEAD changed since it left `base`, with uncommitted changes
+/// too when `uncommitted` is set; a diff too large for the context is summarized in parts first
+pub async fn describe(agent: &mut Agent, base: &str, uncommitted: bool) -> Result<Description> {
+    let changes = changes(base, uncommitted)?;
+    let diff = digest::condense(agent, &changes.diff, NOTES_FOCUS).await?;
+    let reply = agent.turn(&prompt(base, &changes, &diff)).await?
+        .context("The guardrails held the diff back; no description written")?;
+    Description::parse(&reply.content)
+}
+
+/// The repository and pushed branch a pull request can be opened for
+fn pushable() -> Result<(github::Repo, String)> {
+    let repo = github::Repo::origin()?;
+    if github::token().is_none() {
+        bail!("Set GITHUB_TOKEN to create pull requests on {}", repo);
+    }
+    let branch = git(&["rev-parse", "--abbrev-ref", "HEAD"])?.trim().to_string();
+    if branch == "HEAD" {
+        bail!("HEAD is detached; check out the branch to open a pull request for");
+    }
+    if git(&["rev-parse", "--abbrev-ref", "@{upstream}"]).is_err() {
+        bail!("{} is not pushed; run `git push -u origin {}` first", branch, branch);
+    }
+    Ok((repo, branch))
+}
+
+/// Create the pull request for `branch`, or update its open one; returns its URL
+async fn push(agent: &Agent, repo: &github::Repo, branch: &str, base: &str, description: &Description) -> Result<String> {
+    let mut body = json!({ "title": description.title, "body": description.body() });
+    let open = github::send(github::request(agent, Method::GET, &repo.url(&format!("pulls?state=open&head={}:{}", repo.owner, branch)))).await?;
+    let pr = match open.as_array().and_then(|prs| prs.first()) {
+        Some(pr) => {
+            let number = pr["number"].as_u64().context("Pull request without a number")?;
+            github::send(github::request(agent, Method::PATCH, &repo.url(&format!("pulls/{}", number))).json(&body)).await?
+        }
+        None => {
+            body["head"] = json!(branch);
+            body["base"] = json!(base.trim_start_matches("origin/"));
+            github::send(github::request(agent, Method::POST, &repo.url("pulls")).json(&body)).await?
+        }
+    };
+    Ok(pr["html_url"].as_str().unwrap_or_default().to_string())
+}
+
+/// Describe the branch, save the body to `output` and, with `push_to_github`, open or update the
+/// pull request
+pub async fn run(agent: &mut Agent, base: Option<String>, push_to_github: bool, output: Option<PathBuf>) -> Result<()> {
+    let base = match base {
+        Some(base) => base,
+        None => default_base()?,
+    };
+    // Checked first so a missing token or push costs no request
+    let target = push_to_github.then(pushable).transpose()?;
+    let description = describe(agent, &base, false).await?;
+    let body = description.body();
+    println!("# {}\n\n{}", description.title, body);
+    if let Some(path) = output {
+        fs::write(&path, &body).with_context(|| format!("Cannot write {}", path.display()))?;
+        println!("\nSaved the description to {}", path.display());
+    }
+    let Some((repo, branch)) = target else { return Ok(()) };
+    if agent.tool_ctx.dry_run {
+        println!("\nDry run: would create or update the pull request into {}", base);
+        return Ok(());
+    }
+    if !agent.tool_ctx.approve(&format!("Create or update the pull request of {} into {} on {}?", branch, base, repo))? {
+        println!("Not pushed");
+        return Ok(());
+    }
+    println!("Pull request: {}", push(agent, &repo, &branch, &base, &description).await?);
+    Ok(())
+}
@@ -0,0 +1,146 @@
+//! Speculative tool prefetch: while the model writes its reply, read-only calls it is likely to
+//! make next (reading a file the prompt names, `git status` when the session works with git) run
+//! in the background. When the model makes one of them, the waiting result is served instead of
+//! running the tool again. Only calls the policy allows without asking are prefetched, and a
+//! result is dropped once its file changed or anything that writes ran.
+
+use std::{path::{Path, PathBuf}, time::{Duration, Instant, SystemTime}};
+use anyhow::Result;
+use tokio::task::JoinHandle;
+use crate::{bulk::Guard, tools::{ToolCall, ToolContext}};
+
+/// Files named in a prompt read ahead at most
+const MAX_FILES: usize = 4;
+/// Largest file read ahead
+const MAX_BYTES: u64 = 512 * 1024;
+/// Age after which a result is not served
+const MAX_AGE: Duration = Duration::from_secs(60);
+
+/// Prompt words that suggest the turn will look at the working tree
+const GIT_WORDS: &[&str] = &["git", "commit", "branch", "staged", "uncommitted", "changes", "changed", "diff"];
+
+struct Entry {
+    key: String,
+    started: Instant,
+    /// File the result was read from and its modification time then
+    file: Option<(PathBuf, Option<SystemTime>)>,
+    /// The output and how long producing it took
+    task: JoinHandle<(Result<String>, Duration)>,
+}
+
+fn key(call: &ToolCall) -> String {
+    format!("{}{}", call.name(), call.args())
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Results being fetched for the running turn
+#[derive(Default)]
+pub struct Prefetch {
+    entries: Vec<Entry>,
+    /// Results served this session, and the time they saved
+    pub hits: u64,
+    pub saved: Duration,
+}
+
+impl Prefetch {
+    /// Start each of `calls` the policy lets run without asking, on a detached context
+    pub fn start(&mut self, calls: Vec<ToolCall>, ctx: &ToolContext) {
+        for call in calls {
+            let key = key(&call);
+            if !ctx.runs_unasked(&call) || self.entries.iter().any(|e| e.key == key) {
+                continue;
+            }
+            let file = match &call {
+                ToolCall::ReadFile { path } => {
+                    let full = ctx.workdir.as_ref().map_or_else(|| PathBuf::from(path), |dir| dir.join(path));
+                    Some((full.clone(), modified(&full)))
+                }
+                _ => None,
+            };
+            let mut detached = ctx.detached();
+            tracing::debug!("prefetching {}", call);
+            let task = tokio::spawn(async move {
+                let started = Instant::now();
+                (crate::tools::execute(&mut detached, &call).await, started.elapsed())
+            });
+            self.entries.push(Entry { key, started: Instant::now(), file, task });
+        }
+    }
+
+    /// The prefetched result of `call`, if one was started and is still current
+    pub async fn take(&mut self, call: &ToolCall) -> Option<Result<String>> {
+        let key = key(call);
+        let at = self.entries.iter().position(|e| e.key == key)?;
+        let entry = self.entries.remove(at);
+        let stale = entry.started.elapsed() > MAX_AGE
+            || entry.file.as_ref().is_some_and(|(path, stamp)| modified(path) != *stamp);
+        if stale {
+            entry.task.abort();
+            return None;
+        }
+        let waited = Instant::now();
+        let (result, took) = entry.task.await.ok()?;
+        // Failures are left for the real call, whose error the model should see first-hand
+        let output = result.ok()?;
+        let saved = took.saturating_sub(waited.elapsed());
+        self.hits += 1;
+        self.saved += saved;
+        tracing::debug!("served {} from prefetch, {}ms sooner", call, saved.as_millis());
+        Some(Ok(output))
+    }
+
+    /// Drop every pending result, when something may have changed what they read
+    pub fn clear(&mut self) {
+        for entry in self.entries.drain(..) {
+            entry.task.abort();
+        }
+    }
+}
+
+/// Calls a turn with `input` is likely to make: reading the existing files it names, and `git
+/// status` when it talks about the working tree or the session already asked for it
+pub fn guesses(input: &str, root: &Path, offered: &[&str], git_used: bool, bulk: &Guard) -> Vec<ToolCall> {
+    let mut calls = Vec::new();
+    if offered.contains(&"read_file") {
+        let words = input.split(|c: char| c.is_whitespace() || matches!(c, '`' | '"' | '\'' | '(' | ')' | '[' | ']' | ',' | ';'));
+        let mut paths: Vec<&str> = Vec::new();
+        for word in words.map(|w| w.trim_end_matches(['.', ':', '!', '?'])) {
+            if paths.len() >= MAX_FILES || paths.contains(&word) || !(word.contains('.') || word.contains('/')) || word.contains("://") {
+                continue;
+            }
+            let full = root.join(word);
+            if std::fs::metadata(&full).is_ok_and(|m| m.is_file() && m.len() <= MAX_BYTES) && bulk.skip(root, Path::new(word)).is_none() {
+                paths.push(word);
+            }
+        }
+        calls.extend(paths.into_iter().map(|path| ToolCall::ReadFile { path: path.into() }));
+    }
+    let lower = input.to_lowercase();
+    let mentions_git = lower.split(|c: char| !c.is_alphanumeric()).any(|w| GIT_WORDS.contains(&w));
+    if offered.contains(&"git_status") && (git_used || mentions_git) && root.ancestors().any(|d| d.join(".git").exists()) {
+        calls.push(ToolCall::GitStatus {});
+    }
+    calls
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use crate::mock::MockProvider;
+
+    #[tokio::test]
+    async fn files_named_in_the_prompt_are_read_ahead() {
+        let mock = MockProvider::start().await;
+        mock.tool_call("read_file", json!({"path": "Cargo.toml"})).await;
+        mock.reply("done").await;
+        let mut agent = mock.tool_agent();
+        agent.turn("Which edition does Cargo.toml set?").await.unwrap();
+        assert_eq!(agent.prefetch.hits, 1);
+        let requests = mock.requests().await;
+        let messages = requests[1]["messages"].as_array().unwrap();
+        assert!(messages[messages.len() - 1]["content"].as_str().unwrap().contains("[package]"));
+    }
+}
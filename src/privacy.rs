@@ -0,0 +1,140 @@
+//! Privacy routing: a request whose messages match a sensitive pattern, or carry what was read
+//! from a private path, goes to a local provider; everything else goes to the session's provider.
+
+use std::path::Path;
+use regex::Regex;
+use serde::{Serialize, Deserialize};
+use anyhow::{Result, Context};
+use crate::{guardrails, tools::ToolCall, ChatMessage};
+
+/// `[privacy]` table: what has to stay on the machine and the provider that keeps it there
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct PrivacyConfig {
+    /// Provider for private requests; `ollama` when unset
+    pub local_provider: Option<String>,
+    /// Model for private requests; the local provider's default when unset
+    pub local_model: Option<String>,
+    /// Scheme and host of the local server when it is not on the provider's default port
+    pub local_base_url: Option<String>,
+    /// Built-in detectors for keys, tokens and private keys; on by default
+    pub secrets: Option<bool>,
+    /// Regexes marking a prompt as private, e.g. customer or ticket ids
+    pub patterns: Option<Vec<String>>,
+    /// Globs of files and directories whose content must not leave the machine
+    pub private_paths: Option<Vec<String>>,
+}
+
+/// Keys accepted in `[privacy]`, for config validation
+pub const KEYS: &[&str] = &["local_provider", "local_model", "local_base_url", "secrets", "patterns", "private_paths"];
+
+pub struct Router {
+    pub provider: String,
+    pub model: Option<String>,
+    pub base_url: Option<String>,
+    rules: Vec<(String, Regex)>,
+    paths: Vec<glob::Pattern>,
+}
+
+impl Router {
+    /// Compile the configured rules; `None` without a `[privacy]` table, or when the session's
+    /// provider is the local one already
+    pub fn from_config(cfg: Option<&PrivacyConfig>, provider: &str) -> Result<Option<Self>> {
+        let Some(cfg) = cfg else {
+            return Ok(None);
+        };
+        let local = cfg.local_provider.clone().unwrap_or_else(|| "ollama".into());
+        if local == provider && cfg.local_base_url.is_none() {
+            return Ok(None);
+        }
+        let mut rules = Vec::new();
+        if cfg.secrets.unwrap_or(true) {
+            for (name, pattern) in guardrails::SECRET_PATTERNS {
+                rules.push((name.to_string(), Regex::new(pattern)?));
+            }
+        }
+        for pattern in cfg.patterns.iter().flatten() {
+            let re = Regex::new(pattern).with_context(|| format!("Invalid privacy pattern `{}`", pattern))?;
+            rules.push((format!("pattern /{}/", pattern), re));
+        }
+        let paths = cfg.private_paths.iter().flatten()
+            .map(|p| glob::Pattern::new(&normalize(p)).with_context(|| format!("Invalid private path `{}`", p)))
+            .collect::<Result<_>>()?;
+        Ok(Some(Router { provider: local, model: cfg.local_model.clone(), base_url: cfg.local_base_url.clone(), rules, paths }))
+    }
+
+    /// The private path `path` falls under, itself or through one of its directories
+    pub(crate) fn private_path(&self, path: &str) -> Option<String> {
+        let path = normalize(path);
+        let private = Path::new(&path).ancestors().filter_map(|p| p.to_str()).filter(|p| !p.is_empty())
+            .any(|p| self.paths.iter().any(|pattern| pattern.matches(p)));
+        private.then_some(path)
+    }
+
+    /// Why `messages` have to stay local, or `None` when they may go to the cloud. Content from a
+    /// private path is recognized by the tool call that read it and by source and diff headers.
+    pub fn private_reason(&self, messages: &[ChatMessage]) -> Option<String> {
+        for message in messages {
+            if let Some(rule) = self.rules.iter().find(|(_, re)| re.is_match(&message.content)) {
+                return Some(format!("{} in a {} message", rule.0, message.role));
+            }
+            if let Some(call) = &message.function_call {
+                let parsed = serde_json::from_str(&call.arguments).ok().and_then(|args| ToolCall::parse(&call.name, args).ok());
+                if let Some(path) = parsed.iter().flat_map(|c| c.paths()).find_map(|p| self.private_path(p)) {
+                    return Some(format!("`{}` on private path {}", call.name, path));
+                }
+            }
+            if let Some(path) = message.content.lines().filter_map(quoted_path).find_map(|p| self.private_path(p)) {
+                return Some(format!("content of private path {}", path));
+            }
+        }
+        None
+    }
+}
+
+/// The file a `[source n: path:lines]`, `[focus: path:lines]` or `diff --git` header line
+/// introduces
+fn quoted_path(line: &str) -> Option<&str> {
+    if let Some(rest) = line.strip_prefix("diff --git a/") {
+        return rest.split(" b/").next();
+    }
+    let location = match line.strip_prefix("[focus: ") {
+        Some(rest) => rest,
+        None => line.strip_prefix("[source ")?.split_once(": ")?.1,
+    }.strip_suffix(']')?;
+    Some(location.rsplit_once(':').map_or(location, |(path, _)| path))
+}
+
+/// Paths as tools and diffs write them, relative to the working directory
+fn normalize(path: &str) -> String {
+    let path = path.trim_start_matches("./").trim_end_matches('/');
+    std::env::current_dir().ok()
+        .and_then(|cwd| Path::new(path).strip_prefix(cwd).ok().and_then(|p| p.to_str()).map(String::from))
+        .unwrap_or_else(|| path.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use crate::{mock::MockProvider, turns::Turn};
+    use super::{PrivacyConfig, Router};
+
+    #[tokio::test]
+    async fn private_prompts_go_to_the_local_provider() {
+        let (cloud, local) = (MockProvider::start().await, MockProvider::start().await);
+        cloud.reply("from the cloud").await;
+        local.reply("from the local model").await;
+        let mut agent = cloud.agent();
+        agent.enable_quiet();
+        let cfg = PrivacyConfig {
+            local_provider: Some("openai".into()),
+            local_base_url: Some(local.base_url()),
+            patterns: Some(vec![r"ACME-\d{4}".into()]),
+            ..Default::default()
+        };
+        agent.privacy = Router::from_config(Some(&cfg), "openai").unwrap().map(Arc::new);
+        assert_eq!(agent.send_request(Turn::Answer, None).await.unwrap().content, "from the cloud");
+        agent.conversation.push(crate::ChatMessage { role: "user".into(), content: "What is blocking ACME-1234?".into(), name: None, reasoning: None, function_call: None, files: Vec::new(), response_id: None });
+        assert_eq!(agent.send_request(Turn::Answer, None).await.unwrap().content, "from the local model");
+        assert_eq!((cloud.requests().await.len(), local.requests().await.len()), (1, 1));
+    }
+}
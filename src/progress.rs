@@ -0,0 +1,34 @@
+use std::time::Duration;
+use indicatif::{ProgressBar, ProgressStyle};
+
+/// Spinner with elapsed time on stderr; hidden when disabled or stderr is not a terminal
+pub fn spinner(label: &str, enabled: bool) -> ProgressBar {
+    if !enabled {
+        return ProgressBar::hidden();
+    }
+    let pb = ProgressBar::new_spinner();
+    pb.set_style(ProgressStyle::with_template("{spinner} {msg}: {elapsed}").unwrap());
+    pb.set_message(label.to_string());
+    pb.enable_steady_tick(Duration::from_millis(100));
+    pb
+}
+
+/// Bar for `len` batch items with an ETA
+pub fn batch_bar(len: u64, enabled: bool) -> ProgressBar {
+    if !enabled {
+        return ProgressBar::hidden();
+    }
+    let pb = ProgressBar::new(len);
+    pb.set_style(ProgressStyle::with_template("[{bar:30}] {pos}/{len} ({elapsed}, ETA {eta})").unwrap().progress_chars("=> "));
+    pb
+}
+
+/// Bar for a download of known size, reset per file; hidden when disabled
+pub fn bytes_bar(enabled: bool) -> ProgressBar {
+    if !enabled {
+        return ProgressBar::hidden();
+    }
+    let pb = ProgressBar::new(0);
+    pb.set_style(ProgressStyle::with_template("{msg} [{bar:30}] {bytes}/{total_bytes} ({bytes_per_sec}, ETA {eta})").unwrap().progress_chars("=> "));
+    pb
+}
@@ -0,0 +1,70 @@
+use std::path::Path;
+
+/// A toolchain recognized by its manifest in the project root
+pub struct Project {
+    pub language: &'static str,
+    pub manifest: &'static str,
+    pub test: String,
+    pub build: Option<String>,
+}
+
+/// Manifest file, language, test command, build command
+const MARKERS: &[(&str, &str, &str, Option<&str>)] = &[
+    ("Cargo.toml", "Rust", "cargo test", Some("cargo build")),
+    ("package.json", "JavaScript/TypeScript", "npm test", Some("npm run build")),
+    ("pyproject.toml", "Python", "pytest", None),
+    ("setup.py", "Python", "pytest", None),
+    ("go.mod", "Go", "go test ./...", Some("go build ./...")),
+    ("pom.xml", "Java", "mvn test", Some("mvn package")),
+    ("build.gradle", "Java/Kotlin", "gradle test", Some("gradle build")),
+    ("build.gradle.kts", "Kotlin", "gradle test", Some("gradle build")),
+    ("Gemfile", "Ruby", "bundle exec rake test", None),
+    ("mix.exs", "Elixir", "mix test", Some("mix compile")),
+    ("composer.json", "PHP", "composer test", None),
+    ("CMakeLists.txt", "C/C++", "ctest --test-dir build", Some("cmake --build build")),
+];
+
+/// Node package manager implied by the lockfile
+fn node_runner(dir: &Path) -> &'static str {
+    [("pnpm-lock.yaml", "pnpm"), ("yarn.lock", "yarn"), ("bun.lockb", "bun"), ("bun.lock", "bun")]
+        .iter()
+        .find(|(lock, _)| dir.join(lock).is_file())
+        .map(|(_, runner)| *runner)
+        .unwrap_or("npm")
+}
+
+/// Every project type whose manifest sits in `dir`; a repo may have several
+pub fn detect(dir: &Path) -> Vec<Project> {
+    let mut found: Vec<Project> = Vec::new();
+    for (manifest, language, test, build) in MARKERS {
+        if !dir.join(manifest).is_file() || found.iter().any(|p| p.language == *language) {
+            continue;
+        }
+        let (test, build) = if *manifest == "package.json" {
+            let runner = node_runner(dir);
+            (format!("{} test", runner), Some(format!("{} run build", runner)))
+        } else if manifest.starts_with("build.gradle") && dir.join("gradlew").is_file() {
+            (test.replace("gradle", "./gradlew"), build.map(|b| b.replace("gradle", "./gradlew")))
+        } else {
+            (test.to_string(), build.map(String::from))
+        };
+        found.push(Project { language, manifest, test, build });
+    }
+    found
+}
+
+/// System prompt paragraph describing the detected toolchains
+pub fn describe(projects: &[Project]) -> Option<String> {
+    if projects.is_empty() {
+        return None;
+    }
+    let lines: Vec<String> = projects.iter().map(|p| {
+        let build = p.build.as_deref().map(|b| format!(", build with `{}`", b)).unwrap_or_default();
+        format!("- {} ({}): test with `{}`{}", p.language, p.manifest, p.test, build)
+    }).collect();
+    let mut out = format!("Detected projects in the working directory:\n{}", lines.join("\n"));
+    if !projects.iter().any(|p| p.language == "Rust") {
+        out.push_str("\nThis is not a Rust project: follow the conventions of the languages above rather than Rust style.");
+    }
+    Some(out)
+}
@@ -0,0 +1,161 @@
+use anyhow::{Result, Context};
+use reqwest::RequestBuilder;
+use serde_json::{json, Value};
+use crate::{api_version::ApiVersions, ChatMessage, FunctionCall};
+use super::{alternate, arguments, assistant, sse_data, Delta, Provider, Reply, Request};
+
+/// Anthropic Messages API
+pub struct Anthropic;
+
+/// The system prompt, and the turns as content blocks. Tool calls get ids from their position
+/// so each result can point back at its call.
+fn messages(messages: &[ChatMessage]) -> (String, Vec<Value>) {
+    let mut system = Vec::new();
+    let mut turns = Vec::new();
+    let mut open_call = None;
+    for (i, m) in messages.iter().enumerate() {
+        let text = || (!m.content.is_empty()).then(|| json!({"type": "text", "text": m.content}));
+        match m.role.as_str() {
+            "system" => system.push(m.content.as_str()),
+            "assistant" => {
+                let mut blocks: Vec<Value> = text().into_iter().collect();
+                if let Some(call) = &m.function_call {
+                    let id = format!("call_{}", i);
+                    blocks.push(json!({"type": "tool_use", "id": id, "name": call.name, "input": arguments(call)}));
+                    open_call = Some(id);
+                }
+                turns.push(("assistant", blocks));
+            }
+            "function" => match open_call.take() {
+                Some(id) => turns.push(("user", vec![json!({"type": "tool_result", "tool_use_id": id, "content": m.content})])),
+                None => turns.push(("user", text().into_iter().collect())),
+            },
+            _ => turns.push(("user", text().into_iter().collect())),
+        }
+    }
+    let messages = alternate(turns).into_iter().map(|(role, blocks)| {
+        // A lone text block is sent as a plain string
+        let content = match blocks.as_slice() {
+            [block] if block["type"] == "text" => block["text"].clone(),
+            _ => Value::Array(blocks),
+        };
+        json!({"role": role, "content": content})
+    }).collect();
+    (system.join("\n\n"), messages)
+}
+
+impl Provider for Anthropic {
+    fn name(&self) -> &'static str {
+        "claude"
+    }
+
+    fn default_model(&self) -> &'static str {
+        "claude-sonnet-4-5"
+    }
+
+    fn key_var(&self) -> Option<&'static str> {
+        Some("ANTHROPIC_API_KEY")
+    }
+
+    fn build_request(&self, r: &Request) -> Result<(String, Vec<u8>)> {
+        let (system, messages) = messages(r.messages);
+        let mut body = json!({"model": r.model, "max_tokens": r.max_tokens, "messages": messages});
+        if !system.is_empty() {
+            body["system"] = system.into();
+        }
+        // Thinking blocks are not kept in the history, and the API wants the one that led to a
+        // tool call sent back with its result, so thinking stays off until the tool loop ends
+        let in_tool_loop = r.messages.iter().rev().find(|m| m.role == "assistant").is_some_and(|m| m.function_call.is_some());
+        match r.thinking_budget.filter(|_| !in_tool_loop) {
+            // Thinking counts against `max_tokens`, so the budget comes on top of the answer's share;
+            // the API only accepts the default temperature with thinking on
+            Some(budget) => {
+                body["max_tokens"] = (u32::from(r.max_tokens) + budget).into();
+                body["thinking"] = json!({"type": "enabled", "budget_tokens": budget});
+            }
+            None => if let Some(temperature) = r.temperature {
+                body["temperature"] = temperature.into();
+            },
+        }
+        if let Some(functions) = r.functions {
+            body["tools"] = functions.iter()
+                .map(|f| json!({"name": f.name, "description": f.description, "input_schema": f.parameters}))
+                .collect();
+            // The agent runs one call per reply, so the model must not batch them
+            body["tool_choice"] = match r.force {
+                Some(name) => json!({"type": "tool", "name": name, "disable_parallel_tool_use": true}),
+                None => json!({"type": "auto", "disable_parallel_tool_use": true}),
+            };
+        }
+        if r.stream {
+            body["stream"] = true.into();
+        }
+        Ok((format!("{}/v1/messages", r.base("https://api.anthropic.com")), serde_json::to_vec(&body)?))
+    }
+
+    fn authorize(&self, builder: RequestBuilder, key: &str, versions: &ApiVersions) -> RequestBuilder {
+        builder.header("x-api-key", key).header("anthropic-version", &versions.anthropic)
+    }
+
+    fn parse_response(&self, response: Value) -> Result<Reply> {
+        let blocks = response["content"].as_array().context("Unexpected response format")?;
+        let mut content = String::new();
+        let mut reasoning: Option<String> = None;
+        let mut call = None;
+        for block in blocks {
+            match block["type"].as_str() {
+                Some("text") => content.push_str(block["text"].as_str().unwrap_or_default()),
+                Some("thinking") => reasoning.get_or_insert_with(String::new).push_str(block["thinking"].as_str().unwrap_or_default()),
+                Some("tool_use") if call.is_none() => call = Some(FunctionCall {
+                    name: block["name"].as_str().unwrap_or_default().to_string(),
+                    arguments: block["input"].to_string(),
+                    call_id: None,
+                }),
+                _ => {}
+            }
+        }
+        let usage = &response["usage"];
+        Ok(Reply {
+            message: assistant(content, reasoning, call),
+            truncated: response["stop_reason"] == "max_tokens",
+            usage: (usage["input_tokens"].as_u64().unwrap_or(0), usage["output_tokens"].as_u64().unwrap_or(0)),
+        })
+    }
+
+    fn parse_stream_line(&self, line: &str) -> Result<Option<Delta>> {
+        let Some(data) = sse_data(line) else { return Ok(None) };
+        let event: Value = serde_json::from_str(data).context("Malformed event in the response stream")?;
+        let delta = &event["delta"];
+        Ok(match event["type"].as_str() {
+            Some("error") => Some(Delta { error: Some(event), ..Delta::default() }),
+            Some("message_start") => {
+                let usage = &event["message"]["usage"];
+                Some(Delta { usage: Some((usage["input_tokens"].as_u64().unwrap_or(0), usage["output_tokens"].as_u64().unwrap_or(0))), ..Delta::default() })
+            }
+            Some("content_block_start") if event["content_block"]["type"] == "tool_use" => {
+                Some(Delta { call_name: event["content_block"]["name"].as_str().map(String::from), ..Delta::default() })
+            }
+            Some("content_block_delta") => Some(Delta {
+                content: delta["text"].as_str().map(String::from),
+                reasoning: delta["thinking"].as_str().map(String::from),
+                call_arguments: delta["partial_json"].as_str().map(String::from),
+                ..Delta::default()
+            }),
+            Some("message_delta") => Some(Delta {
+                truncated: delta["stop_reason"] == "max_tokens",
+                usage: Some((0, event["usage"]["output_tokens"].as_u64().unwrap_or(0))),
+                ..Delta::default()
+            }),
+            Some("message_stop") => Some(Delta { done: true, ..Delta::default() }),
+            _ => None,
+        })
+    }
+
+    fn supports_tools(&self) -> bool {
+        true
+    }
+
+    fn supports_streaming(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,169 @@
+//! Chat Completions as the OpenAI-compatible APIs of other vendors speak it: tools as `tools`
+//! and `tool_calls` with ids rather than the legacy `functions`, and reasoning as
+//! `reasoning_content`. Where they part ways is a [`Quirks`] flag.
+
+use anyhow::{Result, Context, bail};
+use serde_json::{json, Value};
+use crate::{ChatMessage, FunctionCall};
+use super::{assistant, call_index, ignore_parallel, responses::call_id, sse_data, Delta, Reply, Request};
+
+/// Where one vendor's API differs from the others
+#[derive(Clone, Copy, Default)]
+pub(super) struct Quirks {
+    /// Reasoning behind the tool calls of the current turn goes back as `reasoning_content`,
+    /// which DeepSeek requires in thinking mode and rejects anywhere else
+    pub replay_reasoning: bool,
+    /// `completion_tokens` leaves out reasoning tokens, which are reported apart (xAI)
+    pub reasoning_tokens_apart: bool,
+}
+
+/// The conversation as Messages API turns, function results quoting the id of their call
+fn messages(all: &[ChatMessage], quirks: Quirks) -> Vec<Value> {
+    let turn_start = all.iter().rposition(|m| m.role == "user").unwrap_or(0);
+    let mut last_call = None;
+    let mut out = Vec::with_capacity(all.len());
+    for (at, m) in all.iter().enumerate() {
+        let message = match (m.role.as_str(), &m.function_call) {
+            ("assistant", Some(call)) => {
+                last_call = Some(at);
+                let mut message = json!({
+                    "role": "assistant",
+                    "content": m.content,
+                    "tool_calls": [{"id": call_id(all, at), "type": "function", "function": {"name": call.name, "arguments": call.arguments}}],
+                });
+                if let Some(reasoning) = m.reasoning.as_ref().filter(|_| quirks.replay_reasoning && at > turn_start) {
+                    message["reasoning_content"] = reasoning.as_str().into();
+                }
+                message
+            }
+            ("function", _) => match last_call {
+                Some(call) => json!({"role": "tool", "tool_call_id": call_id(all, call), "name": m.name, "content": m.content}),
+                None => json!({"role": "user", "content": m.content}),
+            },
+            (role, _) => json!({"role": role, "content": m.content}),
+        };
+        out.push(message);
+    }
+    out
+}
+
+/// Chat Completions body for `r`
+pub(super) fn body(r: &Request, quirks: Quirks) -> Value {
+    let mut body = json!({"model": r.model, "messages": messages(r.messages, quirks)});
+    // Reasoning models count hidden reasoning against `max_completion_tokens`
+    body[if r.reasoning_model { "max_completion_tokens" } else { "max_tokens" }] = r.max_tokens.into();
+    if let Some(temperature) = r.temperature {
+        body["temperature"] = temperature.into();
+    }
+    if let Some(effort) = r.reasoning_effort {
+        body["reasoning_effort"] = effort.into();
+    }
+    if let Some(functions) = r.functions {
+        body["tools"] = functions.iter().map(|f| json!({"type": "function", "function": f})).collect();
+        body["tool_choice"] = r.force.map_or_else(|| json!("auto"), |name| json!({"type": "function", "function": {"name": name}}));
+        // The agent runs one call per reply and answers each by id, so the model must not batch them
+        body["parallel_tool_calls"] = false.into();
+    }
+    if r.stream {
+        body["stream"] = true.into();
+        body["stream_options"] = json!({"include_usage": true});
+    }
+    body
+}
+
+/// Arguments as JSON text, whether sent as a string or, by TGI, as an object
+fn arguments_text(arguments: &Value) -> Option<String> {
+    match arguments {
+        Value::Null => None,
+        Value::String(text) => Some(text.clone()),
+        other => Some(other.to_string()),
+    }
+}
+
+fn usage(response: &Value, quirks: Quirks) -> (u64, u64) {
+    let usage = &response["usage"];
+    let mut completion = usage["completion_tokens"].as_u64().unwrap_or(0);
+    if quirks.reasoning_tokens_apart {
+        completion += usage["completion_tokens_details"]["reasoning_tokens"].as_u64().unwrap_or(0);
+    }
+    (usage["prompt_tokens"].as_u64().unwrap_or(0), completion)
+}
+
+pub(super) fn parse_response(response: Value, quirks: Quirks) -> Result<Reply> {
+    let choice = response["choices"].as_array().context("Unexpected response format")?.first().context("Empty choices in response")?;
+    let message = &choice["message"];
+    if !message.is_object() {
+        bail!("Unexpected response format");
+    }
+    let call = &message["tool_calls"][0];
+    ignore_parallel(message["tool_calls"].as_array().into_iter().flatten().skip(1));
+    let function_call = call["function"]["name"].as_str().map(|name| FunctionCall {
+        name: name.to_string(),
+        arguments: arguments_text(&call["function"]["arguments"]).unwrap_or_else(|| "{}".into()),
+        call_id: call["id"].as_str().map(String::from),
+    });
+    Ok(Reply {
+        message: assistant(
+            message["content"].as_str().unwrap_or_default().to_string(),
+            message["reasoning_content"].as_str().filter(|t| !t.is_empty()).map(String::from),
+            function_call,
+        ),
+        truncated: choice["finish_reason"] == "length",
+        usage: usage(&response, quirks),
+    })
+}
+
+pub(super) fn parse_stream_line(line: &str, quirks: Quirks) -> Result<Option<Delta>> {
+    let Some(data) = sse_data(line) else { return Ok(None) };
+    if data == "[DONE]" {
+        return Ok(Some(Delta { done: true, ..Delta::default() }));
+    }
+    let event: Value = serde_json::from_str(data).context("Malformed event in the response stream")?;
+    if event.get("error").is_some() {
+        return Ok(Some(Delta { error: Some(event), ..Delta::default() }));
+    }
+    let choice = &event["choices"][0];
+    let delta = &choice["delta"];
+    // Pieces carry the `index` of their call; only the first call is assembled and run
+    let calls = delta["tool_calls"].as_array().map(Vec::as_slice).unwrap_or_default();
+    let call = calls.iter().find(|c| call_index(c) == 0).unwrap_or(&Value::Null);
+    ignore_parallel(calls.iter().filter(|c| call_index(c) > 0));
+    Ok(Some(Delta {
+        content: delta["content"].as_str().filter(|p| !p.is_empty()).map(String::from),
+        reasoning: delta["reasoning_content"].as_str().filter(|p| !p.is_empty()).map(String::from),
+        call_name: call["function"]["name"].as_str().filter(|n| !n.is_empty()).map(String::from),
+        call_arguments: arguments_text(&call["function"]["arguments"]).filter(|a| !a.is_empty()),
+        call_id: call["id"].as_str().filter(|id| !id.is_empty()).map(String::from),
+        truncated: choice["finish_reason"] == "length",
+        usage: event["usage"].is_object().then(|| usage(&event, quirks)),
+        ..Delta::default()
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use crate::{golden, mock::MockProvider, turns::Turn};
+
+    #[tokio::test]
+    async fn parallel_calls_are_turned_off_and_only_the_first_is_assembled() {
+        let chunk = |calls: serde_json::Value| json!({"choices": [{"index": 0, "delta": {"tool_calls": calls}, "finish_reason": null}]});
+        let events = [
+            chunk(json!([{"index": 0, "id": "call_1", "type": "function", "function": {"name": "list_dir", "arguments": ""}}])),
+            chunk(json!([{"index": 0, "function": {"arguments": "{\"path\": \"src\"}"}}])),
+            chunk(json!([{"index": 1, "id": "call_2", "type": "function", "function": {"name": "list_dir", "arguments": ""}}])),
+            chunk(json!([{"index": 1, "function": {"arguments": "{\"path\": \"tests\"}"}}])),
+        ];
+        let body = events.iter().map(|e| format!("data: {}\n\n", e)).collect::<String>() + "data: [DONE]\n\n";
+        for provider in ["xai", "deepseek"] {
+            let mock = MockProvider::start().await;
+            mock.native_stream(&body, "text/event-stream").await;
+            let mut agent = golden::agent(provider);
+            agent.base_url = Some(mock.base_url());
+            agent.streaming = true;
+            let call = agent.send_request(Turn::Tools, None).await.unwrap().function_call.unwrap();
+            assert_eq!((call.name.as_str(), call.arguments.as_str(), call.call_id.as_deref()), ("list_dir", "{\"path\": \"src\"}", Some("call_1")), "{}", provider);
+            assert_eq!(mock.requests().await[0]["parallel_tool_calls"], false, "{}", provider);
+        }
+    }
+}
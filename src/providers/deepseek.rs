@@ -0,0 +1,54 @@
+use anyhow::Result;
+use reqwest::RequestBuilder;
+use serde_json::Value;
+use crate::api_version::ApiVersions;
+use super::{compat, Delta, Provider, Reply, Request};
+
+/// DeepSeek's Chat Completions. `deepseek-reasoner` thinks out loud in `reasoning_content`,
+/// which has to come back with the tool calls it led to until the turn ends, and must be left
+/// out of every earlier turn.
+pub struct DeepSeek;
+
+const QUIRKS: compat::Quirks = compat::Quirks { replay_reasoning: true, reasoning_tokens_apart: false };
+
+impl Provider for DeepSeek {
+    fn name(&self) -> &'static str {
+        "deepseek"
+    }
+
+    fn default_model(&self) -> &'static str {
+        "deepseek-chat"
+    }
+
+    fn key_var(&self) -> Option<&'static str> {
+        Some("DEEPSEEK_API_KEY")
+    }
+
+    fn build_request(&self, r: &Request) -> Result<(String, Vec<u8>)> {
+        Ok((format!("{}/chat/completions", r.base("https://api.deepseek.com")), serde_json::to_vec(&compat::body(r, QUIRKS))?))
+    }
+
+    fn authorize(&self, builder: RequestBuilder, key: &str, _: &ApiVersions) -> RequestBuilder {
+        builder.bearer_auth(key)
+    }
+
+    fn parse_response(&self, response: Value) -> Result<Reply> {
+        compat::parse_response(response, QUIRKS)
+    }
+
+    fn parse_stream_line(&self, line: &str) -> Result<Option<Delta>> {
+        compat::parse_stream_line(line, QUIRKS)
+    }
+
+    fn supports_tools(&self) -> bool {
+        true
+    }
+
+    fn supports_streaming(&self) -> bool {
+        true
+    }
+
+    fn replays_reasoning(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,155 @@
+use anyhow::{Result, Context, bail};
+use reqwest::RequestBuilder;
+use serde_json::{json, Value};
+use crate::{api_version::ApiVersions, ChatMessage, FunctionCall};
+use super::{alternate, arguments, assistant, sse_data, Delta, Provider, Reply, Request};
+
+/// Gemini `generateContent` on the Generative Language API
+pub struct Gemini;
+
+/// The system instruction, and the turns as `contents` with `user` and `model` roles
+fn contents(messages: &[ChatMessage]) -> (String, Vec<Value>) {
+    let mut system = Vec::new();
+    let mut turns = Vec::new();
+    for m in messages {
+        let text = || (!m.content.is_empty()).then(|| json!({"text": m.content}));
+        match m.role.as_str() {
+            "system" => system.push(m.content.as_str()),
+            "assistant" => {
+                let mut parts: Vec<Value> = text().into_iter().collect();
+                if let Some(call) = &m.function_call {
+                    parts.push(json!({"functionCall": {"name": call.name, "args": arguments(call)}}));
+                }
+                turns.push(("model", parts));
+            }
+            "function" => turns.push(("user", vec![json!({"functionResponse": {"name": m.name, "response": {"content": m.content}}})])),
+            _ => {
+                let files = m.files.iter()
+                    .filter(|f| f.provider == "google")
+                    .map(|f| json!({"fileData": {"mimeType": f.mime, "fileUri": f.id}}));
+                turns.push(("user", files.chain(text()).collect()));
+            }
+        }
+    }
+    let contents = alternate(turns).into_iter().map(|(role, parts)| json!({"role": role, "parts": parts})).collect();
+    (system.join("\n\n"), contents)
+}
+
+/// Answer, thoughts and the first function call among a candidate's parts
+fn parts(response: &Value) -> (String, Option<String>, Option<FunctionCall>) {
+    let mut content = String::new();
+    let mut reasoning: Option<String> = None;
+    let mut call = None;
+    for part in response["candidates"][0]["content"]["parts"].as_array().into_iter().flatten() {
+        if let Some(text) = part["text"].as_str() {
+            match part["thought"] == true {
+                true => reasoning.get_or_insert_with(String::new).push_str(text),
+                false => content.push_str(text),
+            }
+        }
+        if let Some(name) = part["functionCall"]["name"].as_str().filter(|_| call.is_none()) {
+            call = Some(FunctionCall { name: name.to_string(), arguments: part["functionCall"]["args"].to_string(), call_id: None });
+        }
+    }
+    (content, reasoning, call)
+}
+
+fn usage(response: &Value) -> (u64, u64) {
+    let usage = &response["usageMetadata"];
+    let completion = usage["candidatesTokenCount"].as_u64().unwrap_or(0) + usage["thoughtsTokenCount"].as_u64().unwrap_or(0);
+    (usage["promptTokenCount"].as_u64().unwrap_or(0), completion)
+}
+
+/// `generateContent` body for `r`, the same on the Generative Language API and Vertex AI
+pub(super) fn body(r: &Request) -> Value {
+    let (system, contents) = contents(r.messages);
+    let mut config = json!({"maxOutputTokens": r.max_tokens});
+    if let Some(temperature) = r.temperature {
+        config["temperature"] = temperature.into();
+    }
+    if let Some(budget) = r.thinking_budget {
+        config["thinkingConfig"] = json!({"thinkingBudget": budget, "includeThoughts": true});
+    }
+    let mut body = json!({"contents": contents, "generationConfig": config});
+    if !system.is_empty() {
+        body["systemInstruction"] = json!({"parts": [{"text": system}]});
+    }
+    if let Some(functions) = r.functions {
+        let declarations: Vec<Value> = functions.iter()
+            .map(|f| json!({"name": f.name, "description": f.description, "parametersJsonSchema": f.parameters}))
+            .collect();
+        body["tools"] = json!([{"functionDeclarations": declarations}]);
+        if let Some(name) = r.force {
+            body["toolConfig"] = json!({"functionCallingConfig": {"mode": "ANY", "allowedFunctionNames": [name]}});
+        }
+    }
+    body
+}
+
+/// Method path segment: whole responses, or server-sent events
+pub(super) fn method(stream: bool) -> &'static str {
+    if stream { "streamGenerateContent?alt=sse" } else { "generateContent" }
+}
+
+impl Provider for Gemini {
+    fn name(&self) -> &'static str {
+        "google"
+    }
+
+    fn default_model(&self) -> &'static str {
+        "gemini-2.5-flash"
+    }
+
+    fn key_var(&self) -> Option<&'static str> {
+        Some("GOOGLE_API_KEY")
+    }
+
+    fn build_request(&self, r: &Request) -> Result<(String, Vec<u8>)> {
+        let url = format!("{}/{}/models/{}:{}", r.base("https://generativelanguage.googleapis.com"), r.versions.google, r.model, method(r.stream));
+        Ok((url, serde_json::to_vec(&body(r))?))
+    }
+
+    fn authorize(&self, builder: RequestBuilder, key: &str, _: &ApiVersions) -> RequestBuilder {
+        builder.header("x-goog-api-key", key)
+    }
+
+    fn parse_response(&self, response: Value) -> Result<Reply> {
+        if response["candidates"][0].is_null() {
+            let reason = response["promptFeedback"]["blockReason"].as_str().context("Unexpected response format")?;
+            bail!("Gemini blocked the prompt ({})", reason);
+        }
+        let (content, reasoning, call) = parts(&response);
+        Ok(Reply {
+            message: assistant(content, reasoning, call),
+            truncated: response["candidates"][0]["finishReason"] == "MAX_TOKENS",
+            usage: usage(&response),
+        })
+    }
+
+    /// Each event is a complete response holding the next parts; the stream ends with the connection
+    fn parse_stream_line(&self, line: &str) -> Result<Option<Delta>> {
+        let Some(data) = sse_data(line) else { return Ok(None) };
+        let event: Value = serde_json::from_str(data).context("Malformed event in the response stream")?;
+        if event.get("error").is_some() {
+            return Ok(Some(Delta { error: Some(event), ..Delta::default() }));
+        }
+        let (content, reasoning, call) = parts(&event);
+        Ok(Some(Delta {
+            content: Some(content).filter(|c| !c.is_empty()),
+            reasoning,
+            call_name: call.as_ref().map(|c| c.name.clone()),
+            call_arguments: call.map(|c| c.arguments),
+            truncated: event["candidates"][0]["finishReason"] == "MAX_TOKENS",
+            usage: event["usageMetadata"].is_object().then(|| usage(&event)),
+            ..Delta::default()
+        }))
+    }
+
+    fn supports_tools(&self) -> bool {
+        true
+    }
+
+    fn supports_streaming(&self) -> bool {
+        true
+    }
+}
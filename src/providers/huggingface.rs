@@ -0,0 +1,65 @@
+use anyhow::Result;
+use reqwest::RequestBuilder;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use crate::api_version::ApiVersions;
+use super::{compat, Delta, Provider, Reply, Request};
+
+/// `[huggingface]` table: where requests go when not to the serverless router
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct HuggingFaceConfig {
+    /// URL of a dedicated Inference Endpoint, e.g. `https://xyz.us-east-1.aws.endpoints.huggingface.cloud`
+    pub endpoint: Option<String>,
+}
+
+/// Keys accepted in `[huggingface]`, for config validation
+pub const KEYS: &[&str] = &["endpoint"];
+
+/// Hugging Face Inference: the serverless router, or a dedicated endpoint running Text
+/// Generation Inference. Both take the Messages API with `tools` and `tool_calls`, and answer
+/// errors with a bare `error` string; TGI returns call arguments as an object.
+pub struct HuggingFace {
+    /// Dedicated endpoint in place of the router
+    pub endpoint: Option<String>,
+}
+
+impl Provider for HuggingFace {
+    fn name(&self) -> &'static str {
+        "huggingface"
+    }
+
+    fn default_model(&self) -> &'static str {
+        "Qwen/Qwen2.5-Coder-32B-Instruct"
+    }
+
+    fn key_var(&self) -> Option<&'static str> {
+        Some("HF_TOKEN")
+    }
+
+    fn build_request(&self, r: &Request) -> Result<(String, Vec<u8>)> {
+        // A dedicated endpoint serves one model, which it also answers to under the name `tgi`
+        let host = self.endpoint.as_deref().unwrap_or("https://router.huggingface.co");
+        Ok((format!("{}/v1/chat/completions", r.base(host)), serde_json::to_vec(&compat::body(r, compat::Quirks::default()))?))
+    }
+
+    /// Endpoints of a private network may run without a token; one is sent whenever set
+    fn authorize(&self, builder: RequestBuilder, key: &str, _: &ApiVersions) -> RequestBuilder {
+        if key.is_empty() { builder } else { builder.bearer_auth(key) }
+    }
+
+    fn parse_response(&self, response: Value) -> Result<Reply> {
+        compat::parse_response(response, compat::Quirks::default())
+    }
+
+    fn parse_stream_line(&self, line: &str) -> Result<Option<Delta>> {
+        compat::parse_stream_line(line, compat::Quirks::default())
+    }
+
+    fn supports_tools(&self) -> bool {
+        true
+    }
+
+    fn supports_streaming(&self) -> bool {
+        true
+    }
+}
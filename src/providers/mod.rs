@@ -0,0 +1,242 @@
+//! Chat backends. Each provider turns the conversation into its native request and its
+//! responses, whole or streamed, back into assistant messages, so the agent itself never
+//! branches on the provider's name. A new backend is one more `Provider` implementation
+//! and a line in `for_name`.
+
+mod anthropic;
+mod compat;
+mod deepseek;
+mod gemini;
+mod huggingface;
+mod ollama;
+mod openai;
+mod responses;
+mod vertex;
+mod xai;
+
+use std::sync::Arc;
+use anyhow::Result;
+use reqwest::RequestBuilder;
+use serde_json::Value;
+use tracing::warn;
+use crate::{api_version::ApiVersions, ChatMessage, FunctionCall, FunctionDefinition};
+
+pub use huggingface::{HuggingFaceConfig, KEYS as HUGGINGFACE_KEYS};
+
+/// A chat request in provider-neutral terms, its parameters already fitted to the model
+pub struct Request<'a> {
+    pub model: &'a str,
+    pub messages: &'a [ChatMessage],
+    /// Tools to offer; `None` when the model must answer in prose
+    pub functions: Option<&'a [FunctionDefinition]>,
+    /// Function the model must call instead of choosing freely
+    pub force: Option<&'a str>,
+    pub max_tokens: u16,
+    /// Left out for reasoning models, which reject it
+    pub temperature: Option<f32>,
+    /// OpenAI-style reasoning model: `max_completion_tokens` instead of `max_tokens`
+    pub reasoning_model: bool,
+    pub reasoning_effort: Option<&'a str>,
+    /// Extended-thinking budget in tokens, for providers that take one
+    pub thinking_budget: Option<u32>,
+    pub stream: bool,
+    /// Scheme and host replacing the provider's endpoint, for proxies and mock servers
+    pub base_url: Option<&'a str>,
+    pub versions: &'a ApiVersions,
+    /// Tools the provider runs itself, for backends that host any: `code_interpreter`, `file_search`
+    pub hosted_tools: &'a [String],
+    /// Vector stores `file_search` searches
+    pub vector_stores: &'a [String],
+}
+
+impl Request<'_> {
+    fn base(&self, default: &str) -> String {
+        self.base_url.unwrap_or(default).trim_end_matches('/').to_string()
+    }
+}
+
+/// A parsed, successful response
+pub struct Reply {
+    pub message: ChatMessage,
+    /// The answer was cut off by the token limit
+    pub truncated: bool,
+    /// Prompt and completion tokens
+    pub usage: (u64, u64),
+}
+
+/// What one line of a streamed response contributes
+#[derive(Default)]
+pub struct Delta {
+    pub content: Option<String>,
+    pub reasoning: Option<String>,
+    /// Name, or the start of the name, of a function call
+    pub call_name: Option<String>,
+    /// The next piece of the function call's JSON arguments
+    pub call_arguments: Option<String>,
+    /// Provider's id for the function call
+    pub call_id: Option<String>,
+    /// Id of the stored response, for backends that keep the conversation server-side
+    pub response_id: Option<String>,
+    pub truncated: bool,
+    /// Prompt and completion token totals reported so far; zero when the event does not say
+    pub usage: Option<(u64, u64)>,
+    /// The stream is complete
+    pub done: bool,
+    /// Error body sent in place of the next event, with the provider's `error` object
+    pub error: Option<Value>,
+}
+
+pub trait Provider: Send + Sync {
+    /// Name used by `provider` in the config
+    fn name(&self) -> &'static str;
+
+    /// Model used when none is configured
+    fn default_model(&self) -> &'static str;
+
+    /// Environment variable with this provider's own API key, checked before OPENAI_API_KEY
+    fn key_var(&self) -> Option<&'static str> {
+        None
+    }
+
+    /// Endpoint and serialized body
+    fn build_request(&self, request: &Request) -> Result<(String, Vec<u8>)>;
+
+    /// Attach credentials
+    fn authorize(&self, builder: RequestBuilder, key: &str, versions: &ApiVersions) -> RequestBuilder;
+
+    /// The assistant message in a successful response body
+    fn parse_response(&self, response: Value) -> Result<Reply>;
+
+    /// One line of a streamed response; `None` for lines that carry nothing
+    fn parse_stream_line(&self, line: &str) -> Result<Option<Delta>>;
+
+    fn supports_tools(&self) -> bool;
+
+    fn supports_streaming(&self) -> bool;
+
+    /// The reasoning behind a function call has to be sent back with it
+    fn replays_reasoning(&self) -> bool {
+        false
+    }
+}
+
+/// Tools a backend can offer that run on the provider's side
+pub const HOSTED_TOOLS: &[&str] = &["code_interpreter", "file_search"];
+
+/// Values of `openai_api`: Chat Completions, or the Responses API with server-side state
+pub const OPENAI_APIS: &[&str] = &["chat", "responses"];
+
+/// The backend for a `provider` setting; unknown names get OpenAI, which most servers imitate
+pub fn for_name(name: &str) -> Arc<dyn Provider> {
+    for_settings(name, None)
+}
+
+/// The backend for a `provider` setting, with OpenAI spoken through the API `openai_api` names
+pub fn for_settings(name: &str, openai_api: Option<&str>) -> Arc<dyn Provider> {
+    match name {
+        "claude" => Arc::new(anthropic::Anthropic),
+        "deepseek" => Arc::new(deepseek::DeepSeek),
+        "google" => Arc::new(gemini::Gemini),
+        "huggingface" => huggingface(None),
+        "ollama" => Arc::new(ollama::Ollama),
+        "vertex" => vertex(None, None),
+        "xai" => Arc::new(xai::Xai),
+        _ if openai_api == Some("responses") => Arc::new(responses::Responses),
+        _ => Arc::new(openai::OpenAi),
+    }
+}
+
+/// Vertex AI in `project` and `location`, else GOOGLE_CLOUD_PROJECT and GOOGLE_CLOUD_LOCATION
+/// (default `us-central1`)
+pub fn vertex(project: Option<String>, location: Option<String>) -> Arc<dyn Provider> {
+    Arc::new(vertex::Vertex {
+        project: project.or_else(|| std::env::var("GOOGLE_CLOUD_PROJECT").ok()),
+        location: location.or_else(|| std::env::var("GOOGLE_CLOUD_LOCATION").ok()).unwrap_or_else(|| "us-central1".into()),
+    })
+}
+
+/// Hugging Face Inference through the serverless router, or the dedicated `endpoint`
+pub fn huggingface(endpoint: Option<String>) -> Arc<dyn Provider> {
+    Arc::new(huggingface::HuggingFace { endpoint: endpoint.map(|e| e.trim_end_matches('/').to_string()) })
+}
+
+/// Payload of a server-sent event line
+fn sse_data(line: &str) -> Option<&str> {
+    line.trim().strip_prefix("data:").map(str::trim)
+}
+
+/// Warn about tool calls past the first, which the agent does not run. Requests turn parallel
+/// calls off where the API has a switch for it; this covers servers that ignore it or have none.
+fn ignore_parallel<'a>(calls: impl Iterator<Item = &'a Value>) {
+    for name in calls.filter_map(|c| c["function"]["name"].as_str()) {
+        warn!("ignoring parallel call to `{}`; only one tool call runs per reply", name);
+    }
+}
+
+/// `index` of a streamed `tool_calls` piece; servers that send one call at a time may leave it out
+fn call_index(call: &Value) -> u64 {
+    call["index"].as_u64().unwrap_or(0)
+}
+
+/// Function-call arguments as a JSON object; text the model garbled becomes an empty object
+fn arguments(call: &FunctionCall) -> Value {
+    serde_json::from_str::<Value>(&call.arguments).ok().filter(Value::is_object).unwrap_or_else(|| Value::Object(Default::default()))
+}
+
+fn assistant(content: String, reasoning: Option<String>, function_call: Option<FunctionCall>) -> ChatMessage {
+    ChatMessage { role: "assistant".into(), content, name: None, reasoning, function_call, files: Vec::new(), response_id: None }
+}
+
+/// `[role, parts]` pairs with consecutive turns of the same role folded together, for APIs
+/// that require user and model turns to alternate
+fn alternate(turns: Vec<(&'static str, Vec<Value>)>) -> Vec<(&'static str, Vec<Value>)> {
+    let mut out: Vec<(&'static str, Vec<Value>)> = Vec::new();
+    for (role, parts) in turns.into_iter().filter(|(_, parts)| !parts.is_empty()) {
+        match out.last_mut() {
+            Some((last, existing)) if *last == role => existing.extend(parts),
+            _ => out.push((role, parts)),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use crate::{golden, mock::MockProvider, turns::Turn};
+
+    #[tokio::test]
+    async fn native_streams_are_reassembled() {
+        let anthropic = [
+            json!({"type": "message_start", "message": {"usage": {"input_tokens": 20, "output_tokens": 1}}}),
+            json!({"type": "content_block_start", "index": 0, "content_block": {"type": "text", "text": ""}}),
+            json!({"type": "content_block_delta", "index": 0, "delta": {"type": "text_delta", "text": "Checking"}}),
+            json!({"type": "content_block_start", "index": 1, "content_block": {"type": "tool_use", "id": "toolu_1", "name": "list_dir", "input": {}}}),
+            json!({"type": "content_block_delta", "index": 1, "delta": {"type": "input_json_delta", "partial_json": "{\"path\": "}}),
+            json!({"type": "content_block_delta", "index": 1, "delta": {"type": "input_json_delta", "partial_json": "\"src\"}"}}),
+            json!({"type": "message_delta", "delta": {"stop_reason": "tool_use"}, "usage": {"output_tokens": 12}}),
+            json!({"type": "message_stop"}),
+        ];
+        let ollama = [
+            json!({"message": {"role": "assistant", "content": "Checking"}, "done": false}),
+            json!({"message": {"role": "assistant", "content": "", "tool_calls": [{"function": {"name": "list_dir", "arguments": {"path": "src"}}}]}, "done": false}),
+            json!({"message": {"role": "assistant", "content": ""}, "done": true, "done_reason": "stop", "prompt_eval_count": 20, "eval_count": 12}),
+        ];
+        let cases = [
+            ("claude", anthropic.iter().map(|e| format!("event: {}\ndata: {}\n\n", e["type"].as_str().unwrap(), e)).collect::<String>(), "text/event-stream"),
+            ("ollama", ollama.iter().map(|e| format!("{}\n", e)).collect(), "application/x-ndjson"),
+        ];
+        for (provider, body, content_type) in cases {
+            let mock = MockProvider::start().await;
+            mock.native_stream(&body, content_type).await;
+            let mut agent = golden::agent(provider);
+            agent.base_url = Some(mock.base_url());
+            agent.streaming = true;
+            let reply = agent.send_request(Turn::Tools, None).await.unwrap();
+            assert_eq!(reply.content, "Checking", "{}", provider);
+            let call = reply.function_call.unwrap();
+            assert_eq!((call.name.as_str(), serde_json::from_str::<serde_json::Value>(&call.arguments).unwrap()), ("list_dir", json!({"path": "src"})), "{}", provider);
+            assert_eq!(mock.requests().await[0]["stream"], true, "{}", provider);
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use anyhow::{Result, Context, bail};
+use reqwest::RequestBuilder;
+use serde_json::{json, Value};
+use crate::{api_version::ApiVersions, ChatMessage, FunctionCall};
+use super::{arguments, assistant, ignore_parallel, Delta, Provider, Reply, Request};
+
+/// Ollama's native `/api/chat`, which streams newline-delimited JSON
+pub struct Ollama;
+
+fn message(m: &ChatMessage) -> Value {
+    match (m.role.as_str(), &m.function_call) {
+        ("assistant", Some(call)) => json!({
+            "role": "assistant",
+            "content": m.content,
+            "tool_calls": [{"function": {"name": call.name, "arguments": arguments(call)}}],
+        }),
+        ("function", _) => json!({"role": "tool", "content": m.content, "tool_name": m.name}),
+        (role, _) => json!({"role": role, "content": m.content}),
+    }
+}
+
+/// The function call in a message, with its arguments object as JSON text. Ollama has no switch
+/// for parallel calls, so any past the first are reported and left out.
+fn function_call(message: &Value) -> Option<FunctionCall> {
+    ignore_parallel(message["tool_calls"].as_array().into_iter().flatten().skip(1));
+    let call = &message["tool_calls"][0]["function"];
+    Some(FunctionCall { name: call["name"].as_str()?.to_string(), arguments: call["arguments"].to_string(), call_id: None })
+}
+
+fn usage(response: &Value) -> (u64, u64) {
+    (response["prompt_eval_count"].as_u64().unwrap_or(0), response["eval_count"].as_u64().unwrap_or(0))
+}
+
+impl Provider for Ollama {
+    fn name(&self) -> &'static str {
+        "ollama"
+    }
+
+    fn default_model(&self) -> &'static str {
+        "rust-ai-agent"
+    }
+
+    fn build_request(&self, r: &Request) -> Result<(String, Vec<u8>)> {
+        let mut options = json!({"num_predict": r.max_tokens});
+        if let Some(temperature) = r.temperature {
+            options["temperature"] = temperature.into();
+        }
+        // `stream` defaults to true on this endpoint, so it is always sent
+        let mut body = json!({
+            "model": r.model,
+            "messages": r.messages.iter().map(message).collect::<Vec<_>>(),
+            "stream": r.stream,
+            "options": options,
+        });
+        if let Some(functions) = r.functions {
+            body["tools"] = functions.iter().map(|f| json!({"type": "function", "function": f})).collect();
+        }
+        Ok((format!("{}/api/chat", r.base("http://localhost:11434")), serde_json::to_vec(&body)?))
+    }
+
+    /// Local servers need no key; one is only sent for authenticating proxies
+    fn authorize(&self, builder: RequestBuilder, key: &str, _: &ApiVersions) -> RequestBuilder {
+        if key.is_empty() { builder } else { builder.bearer_auth(key) }
+    }
+
+    fn parse_response(&self, response: Value) -> Result<Reply> {
+        let message = &response["message"];
+        if !message.is_object() {
+            bail!("Unexpected response format");
+        }
+        Ok(Reply {
+            message: assistant(
+                message["content"].as_str().unwrap_or_default().to_string(),
+                message["thinking"].as_str().filter(|t| !t.is_empty()).map(String::from),
+                function_call(message),
+            ),
+            truncated: response["done_reason"] == "length",
+            usage: usage(&response),
+        })
+    }
+
+    fn parse_stream_line(&self, line: &str) -> Result<Option<Delta>> {
+        if line.trim().is_empty() {
+            return Ok(None);
+        }
+        let event: Value = serde_json::from_str(line).context("Malformed line in the response stream")?;
+        if event.get("error").is_some() {
+            return Ok(Some(Delta { error: Some(event), ..Delta::default() }));
+        }
+        let message = &event["message"];
+        let call = function_call(message);
+        let done = event["done"] == true;
+        Ok(Some(Delta {
+            content: message["content"].as_str().filter(|p| !p.is_empty()).map(String::from),
+            reasoning: message["thinking"].as_str().filter(|p| !p.is_empty()).map(String::from),
+            call_name: call.as_ref().map(|c| c.name.clone()),
+            call_arguments: call.map(|c| c.arguments),
+            truncated: event["done_reason"] == "length",
+            usage: done.then(|| usage(&event)),
+            done,
+            ..Delta::default()
+        }))
+    }
+
+    fn supports_tools(&self) -> bool {
+        true
+    }
+
+    fn supports_streaming(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,204 @@
+use anyhow::{Result, Context, bail};
+use reqwest::RequestBuilder;
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+use crate::{api_version::ApiVersions, ChatMessage, FunctionDefinition};
+use super::{call_index, ignore_parallel, sse_data, Delta, Provider, Reply, Request};
+
+/// OpenAI Chat Completions, also spoken by most compatible servers and proxies
+pub struct OpenAi;
+
+/// A conversation message as sent to the provider, borrowed so no turn clones the history
+#[derive(Serialize)]
+struct OutgoingMessage<'a> {
+    role: &'a str,
+    content: Content<'a>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    function_call: Option<OutgoingCall<'a>>,
+}
+
+/// A function call as Chat Completions takes it back: the name and arguments only
+#[derive(Serialize)]
+struct OutgoingCall<'a> {
+    name: &'a str,
+    arguments: &'a str,
+}
+
+/// Plain text, or text and uploaded files as content parts
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Content<'a> {
+    Text(&'a str),
+    Parts(Vec<Value>),
+}
+
+impl<'a> From<&'a ChatMessage> for OutgoingMessage<'a> {
+    fn from(m: &'a ChatMessage) -> Self {
+        let files: Vec<Value> = m.files.iter()
+            .filter(|f| f.provider == "openai")
+            .map(|f| json!({"type": "file", "file": {"file_id": f.id}}))
+            .collect();
+        let content = if files.is_empty() {
+            Content::Text(&m.content)
+        } else {
+            Content::Parts([json!({"type": "text", "text": m.content})].into_iter().chain(files).collect())
+        };
+        OutgoingMessage { role: &m.role, content, name: m.name.as_deref(), function_call: m.function_call.as_ref().map(|c| OutgoingCall { name: &c.name, arguments: &c.arguments }) }
+    }
+}
+
+#[derive(Serialize)]
+struct ChatCompletionRequest<'a> {
+    model: &'a str,
+    messages: Vec<OutgoingMessage<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    functions: Option<&'a [FunctionDefinition]>,
+    /// `"auto"`, or `{"name": ...}` to force a function
+    #[serde(skip_serializing_if = "Option::is_none")]
+    function_call: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u16>,
+    /// Replaces `max_tokens` for reasoning models, where it also covers hidden reasoning tokens
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_completion_tokens: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reasoning_effort: Option<&'a str>,
+    /// Deliver the answer as server-sent events
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<Value>,
+}
+
+#[derive(Deserialize)]
+struct ChatCompletionResponse {
+    choices: Vec<Choice>,
+}
+
+#[derive(Deserialize)]
+struct Choice {
+    message: ChatMessage,
+    finish_reason: Option<String>,
+}
+
+fn usage(response: &Value) -> (u64, u64) {
+    let usage = &response["usage"];
+    (usage["prompt_tokens"].as_u64().unwrap_or(0), usage["completion_tokens"].as_u64().unwrap_or(0))
+}
+
+impl Provider for OpenAi {
+    fn name(&self) -> &'static str {
+        "openai"
+    }
+
+    fn default_model(&self) -> &'static str {
+        "gpt-4o-mini"
+    }
+
+    fn build_request(&self, r: &Request) -> Result<(String, Vec<u8>)> {
+        let body = ChatCompletionRequest {
+            model: r.model,
+            messages: r.messages.iter().map(OutgoingMessage::from).collect(),
+            functions: r.functions,
+            function_call: r.functions.map(|_| r.force.map_or_else(|| json!("auto"), |name| json!({"name": name}))),
+            max_tokens: (!r.reasoning_model).then_some(r.max_tokens),
+            max_completion_tokens: r.reasoning_model.then_some(r.max_tokens),
+            temperature: r.temperature,
+            reasoning_effort: r.reasoning_effort,
+            stream: r.stream.then_some(true),
+            // Token usage is only reported for a stream when asked for
+            stream_options: r.stream.then(|| json!({"include_usage": true})),
+        };
+        Ok((format!("{}/{}/chat/completions", r.base("https://api.openai.com"), r.versions.openai), serde_json::to_vec(&body)?))
+    }
+
+    fn authorize(&self, builder: RequestBuilder, key: &str, _: &ApiVersions) -> RequestBuilder {
+        builder.bearer_auth(key)
+    }
+
+    fn parse_response(&self, response: Value) -> Result<Reply> {
+        if response.get("choices").is_none() {
+            bail!("Unexpected response format");
+        }
+        let usage = usage(&response);
+        // Newer compatible servers send `tool_calls` instead of the legacy `function_call`, which
+        // never holds more than one call
+        let tool_calls = &response["choices"][0]["message"]["tool_calls"];
+        ignore_parallel(tool_calls.as_array().into_iter().flatten().skip(1));
+        let tool_call = tool_calls[0]["function"].clone();
+        let parsed: ChatCompletionResponse = serde_json::from_value(response)?;
+        let choice = parsed.choices.into_iter().next().context("Empty choices in response")?;
+        let mut message = choice.message;
+        if message.function_call.is_none() {
+            message.function_call = serde_json::from_value(tool_call).ok();
+        }
+        Ok(Reply { message, truncated: choice.finish_reason.as_deref() == Some("length"), usage })
+    }
+
+    fn parse_stream_line(&self, line: &str) -> Result<Option<Delta>> {
+        let Some(data) = sse_data(line) else { return Ok(None) };
+        if data == "[DONE]" {
+            return Ok(Some(Delta { done: true, ..Delta::default() }));
+        }
+        let event: Value = serde_json::from_str(data).context("Malformed event in the response stream")?;
+        if event.get("error").is_some() {
+            return Ok(Some(Delta { error: Some(event), ..Delta::default() }));
+        }
+        let choice = &event["choices"][0];
+        let delta = &choice["delta"];
+        let tool_calls = delta["tool_calls"].as_array().map(Vec::as_slice).unwrap_or_default();
+        ignore_parallel(tool_calls.iter().filter(|c| call_index(c) > 0));
+        let first = tool_calls.iter().find(|c| call_index(c) == 0).map_or(&Value::Null, |c| &c["function"]);
+        let call = delta.get("function_call").unwrap_or(first);
+        Ok(Some(Delta {
+            content: delta["content"].as_str().filter(|p| !p.is_empty()).map(String::from),
+            reasoning: delta["reasoning_content"].as_str().or_else(|| delta["reasoning"].as_str()).map(String::from),
+            call_name: call["name"].as_str().map(String::from),
+            call_arguments: call["arguments"].as_str().map(String::from),
+            truncated: choice["finish_reason"] == "length",
+            usage: event["usage"].is_object().then(|| usage(&event)),
+            ..Delta::default()
+        }))
+    }
+
+    fn supports_tools(&self) -> bool {
+        true
+    }
+
+    fn supports_streaming(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use crate::{mock::MockProvider, turns::Turn};
+    use super::{OpenAi, Provider};
+
+    #[test]
+    fn tool_calls_past_the_first_are_left_out() {
+        let call = |id: &str, path: &str| json!({"id": id, "type": "function", "function": {"name": "list_dir", "arguments": json!({"path": path}).to_string()}});
+        let response = json!({"choices": [{"message": {"role": "assistant", "content": null, "tool_calls": [call("call_1", "src"), call("call_2", "tests")]}, "finish_reason": "tool_calls"}]});
+        let call = OpenAi.parse_response(response).unwrap().message.function_call.unwrap();
+        assert_eq!((call.name.as_str(), call.arguments.as_str()), ("list_dir", r#"{"path":"src"}"#));
+        let piece = |index: u64, name: &str| format!("data: {}", json!({"choices": [{"delta": {"tool_calls": [{"index": index, "function": {"name": name, "arguments": ""}}]}}]}));
+        assert_eq!(OpenAi.parse_stream_line(&piece(0, "list_dir")).unwrap().unwrap().call_name.as_deref(), Some("list_dir"));
+        assert!(OpenAi.parse_stream_line(&piece(1, "read_file")).unwrap().unwrap().call_name.is_none());
+    }
+
+    #[tokio::test]
+    async fn streamed_pieces_are_reassembled() {
+        let mock = MockProvider::start().await;
+        mock.stream(&["Entry point: main", ".rs, caf", "é included"]).await;
+        let mut agent = mock.agent();
+        agent.streaming = true;
+        let reply = agent.send_request(Turn::Answer, None).await.unwrap();
+        assert_eq!(reply.content, "Entry point: main.rs, café included");
+        assert_eq!(mock.requests().await[0]["stream"], true);
+    }
+}
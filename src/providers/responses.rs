@@ -0,0 +1,208 @@
+use anyhow::{Result, bail};
+use reqwest::RequestBuilder;
+use serde_json::{json, Value};
+use crate::{api_version::ApiVersions, ChatMessage, FunctionCall};
+use super::{assistant, sse_data, Delta, Provider, Reply, Request};
+
+/// OpenAI's Responses API, selected with `openai_api = "responses"`. Responses are stored on
+/// OpenAI's side, so each request names the last one and sends only what came after it, and the
+/// provider can run tools of its own (code interpreter, file search) within a turn.
+pub struct Responses;
+
+/// Id the result of the function call in `messages[at]` refers to: the provider's, or one made
+/// up for calls it never saw, which only has to match within the request
+pub(super) fn call_id(messages: &[ChatMessage], at: usize) -> String {
+    messages[at].function_call.as_ref().and_then(|c| c.call_id.clone()).unwrap_or_else(|| format!("call_{}", at))
+}
+
+/// Instructions from the first system message, and the conversation after the last stored
+/// response as input items, with that response's id when there is one
+fn input(messages: &[ChatMessage]) -> (String, Option<&str>, Vec<Value>) {
+    let anchor = messages.iter().rposition(|m| m.response_id.is_some());
+    let previous = anchor.and_then(|at| messages[at].response_id.as_deref());
+    let instructions = messages.first().filter(|m| m.role == "system").map(|m| m.content.clone()).unwrap_or_default();
+    let mut items = Vec::new();
+    let mut last_call = None;
+    for (at, m) in messages.iter().enumerate() {
+        if m.function_call.is_some() {
+            last_call = Some(at);
+        }
+        if anchor.is_some_and(|anchor| at <= anchor) || (at == 0 && m.role == "system") {
+            continue;
+        }
+        match m.role.as_str() {
+            "system" => items.push(json!({"role": "developer", "content": m.content})),
+            "assistant" => {
+                if !m.content.is_empty() {
+                    items.push(json!({"role": "assistant", "content": m.content}));
+                }
+                if let Some(call) = &m.function_call {
+                    items.push(json!({"type": "function_call", "call_id": call_id(messages, at), "name": call.name, "arguments": call.arguments}));
+                }
+            }
+            "function" => match last_call {
+                Some(call) => items.push(json!({"type": "function_call_output", "call_id": call_id(messages, call), "output": m.content})),
+                None => items.push(json!({"role": "user", "content": m.content})),
+            },
+            _ => {
+                let files: Vec<Value> = m.files.iter()
+                    .filter(|f| f.provider == "openai")
+                    .map(|f| json!({"type": "input_file", "file_id": f.id}))
+                    .collect();
+                if files.is_empty() {
+                    items.push(json!({"role": "user", "content": m.content}));
+                } else {
+                    let parts: Vec<Value> = [json!({"type": "input_text", "text": m.content})].into_iter().chain(files).collect();
+                    items.push(json!({"role": "user", "content": parts}));
+                }
+            }
+        }
+    }
+    (instructions, previous, items)
+}
+
+fn usage(response: &Value) -> (u64, u64) {
+    let usage = &response["usage"];
+    (usage["input_tokens"].as_u64().unwrap_or(0), usage["output_tokens"].as_u64().unwrap_or(0))
+}
+
+fn truncated(response: &Value) -> bool {
+    response["incomplete_details"]["reason"] == "max_output_tokens"
+}
+
+/// Answer, reasoning summary and the first function call among a response's output items.
+/// Hosted tool calls need nothing from the agent; the answer already reflects them.
+fn output(response: &Value) -> (String, Option<String>, Option<FunctionCall>) {
+    let mut content = String::new();
+    let mut reasoning: Option<String> = None;
+    let mut call = None;
+    for item in response["output"].as_array().into_iter().flatten() {
+        match item["type"].as_str() {
+            Some("message") => {
+                for part in item["content"].as_array().into_iter().flatten().filter(|p| p["type"] == "output_text") {
+                    content.push_str(part["text"].as_str().unwrap_or_default());
+                }
+            }
+            Some("reasoning") => {
+                for text in item["summary"].as_array().into_iter().flatten().filter_map(|s| s["text"].as_str()) {
+                    reasoning.get_or_insert_with(String::new).push_str(text);
+                }
+            }
+            Some("function_call") if call.is_none() => call = Some(FunctionCall {
+                name: item["name"].as_str().unwrap_or_default().to_string(),
+                arguments: item["arguments"].as_str().unwrap_or("{}").to_string(),
+                call_id: item["call_id"].as_str().map(String::from),
+            }),
+            _ => {}
+        }
+    }
+    (content, reasoning, call)
+}
+
+impl Provider for Responses {
+    fn name(&self) -> &'static str {
+        "openai"
+    }
+
+    fn default_model(&self) -> &'static str {
+        "gpt-4o-mini"
+    }
+
+    fn build_request(&self, r: &Request) -> Result<(String, Vec<u8>)> {
+        let (instructions, previous, input) = input(r.messages);
+        let mut body = json!({
+            "model": r.model,
+            "input": input,
+            "max_output_tokens": r.max_tokens,
+            "store": true,
+            // The stored conversation outgrows the context window; let the provider drop its oldest turns
+            "truncation": "auto",
+        });
+        if !instructions.is_empty() {
+            body["instructions"] = instructions.into();
+        }
+        if let Some(id) = previous {
+            body["previous_response_id"] = id.into();
+        }
+        if let Some(temperature) = r.temperature {
+            body["temperature"] = temperature.into();
+        }
+        if let Some(effort) = r.reasoning_effort {
+            body["reasoning"] = json!({"effort": effort, "summary": "auto"});
+        }
+        let mut tools: Vec<Value> = r.functions.into_iter().flatten()
+            .map(|f| json!({"type": "function", "name": f.name, "description": f.description, "parameters": f.parameters}))
+            .collect();
+        for tool in r.hosted_tools {
+            match tool.as_str() {
+                "code_interpreter" => tools.push(json!({"type": "code_interpreter", "container": {"type": "auto"}})),
+                "file_search" if !r.vector_stores.is_empty() => tools.push(json!({"type": "file_search", "vector_store_ids": r.vector_stores})),
+                _ => {}
+            }
+        }
+        if !tools.is_empty() {
+            body["tools"] = tools.into();
+        }
+        if r.functions.is_some() {
+            // Every call in a stored response needs its output in the next request; the agent runs one at a time
+            body["parallel_tool_calls"] = false.into();
+        }
+        if let Some(name) = r.force {
+            body["tool_choice"] = json!({"type": "function", "name": name});
+        }
+        if r.stream {
+            body["stream"] = true.into();
+        }
+        Ok((format!("{}/{}/responses", r.base("https://api.openai.com"), r.versions.openai), serde_json::to_vec(&body)?))
+    }
+
+    fn authorize(&self, builder: RequestBuilder, key: &str, _: &ApiVersions) -> RequestBuilder {
+        builder.bearer_auth(key)
+    }
+
+    fn parse_response(&self, response: Value) -> Result<Reply> {
+        if response.get("output").is_none() {
+            bail!("Unexpected response format");
+        }
+        let (content, reasoning, call) = output(&response);
+        let mut message = assistant(content, reasoning, call);
+        message.response_id = response["id"].as_str().map(String::from);
+        Ok(Reply { message, truncated: truncated(&response), usage: usage(&response) })
+    }
+
+    /// Typed events: text and argument deltas, the function call as its item is added, and the
+    /// whole response with its id and usage once it completes
+    fn parse_stream_line(&self, line: &str) -> Result<Option<Delta>> {
+        let Some(data) = sse_data(line) else { return Ok(None) };
+        let Ok(event) = serde_json::from_str::<Value>(data) else { return Ok(None) };
+        let text = |field: &str| event[field].as_str().map(String::from);
+        Ok(Some(match event["type"].as_str().unwrap_or_default() {
+            "response.output_text.delta" => Delta { content: text("delta"), ..Delta::default() },
+            "response.reasoning_summary_text.delta" => Delta { reasoning: text("delta"), ..Delta::default() },
+            "response.output_item.added" if event["item"]["type"] == "function_call" => Delta {
+                call_name: event["item"]["name"].as_str().map(String::from),
+                call_id: event["item"]["call_id"].as_str().map(String::from),
+                ..Delta::default()
+            },
+            "response.function_call_arguments.delta" => Delta { call_arguments: text("delta"), ..Delta::default() },
+            "response.completed" | "response.incomplete" => Delta {
+                response_id: event["response"]["id"].as_str().map(String::from),
+                truncated: truncated(&event["response"]),
+                usage: Some(usage(&event["response"])),
+                done: true,
+                ..Delta::default()
+            },
+            "response.failed" => Delta { error: Some(json!({"error": event["response"]["error"]})), ..Delta::default() },
+            "error" => Delta { error: Some(json!({"error": {"message": event["message"], "code": event["code"]}})), ..Delta::default() },
+            _ => return Ok(None),
+        }))
+    }
+
+    fn supports_tools(&self) -> bool {
+        true
+    }
+
+    fn supports_streaming(&self) -> bool {
+        true
+    }
+}
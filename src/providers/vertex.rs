@@ -0,0 +1,58 @@
+use anyhow::{Result, Context};
+use reqwest::RequestBuilder;
+use serde_json::Value;
+use crate::api_version::ApiVersions;
+use super::{gemini::{self, Gemini}, Delta, Provider, Reply, Request};
+
+/// Gemini on Vertex AI: the same request and response format as the Generative Language API,
+/// under a Google Cloud project and region, with OAuth access tokens in place of API keys
+pub struct Vertex {
+    /// Google Cloud project billed for the requests
+    pub project: Option<String>,
+    /// Region such as `us-central1`, or `global`
+    pub location: String,
+}
+
+impl Provider for Vertex {
+    fn name(&self) -> &'static str {
+        "vertex"
+    }
+
+    fn default_model(&self) -> &'static str {
+        "gemini-2.5-flash"
+    }
+
+    fn build_request(&self, r: &Request) -> Result<(String, Vec<u8>)> {
+        let project = self.project.as_deref().context("No Google Cloud project for Vertex AI; set `vertex.project` or GOOGLE_CLOUD_PROJECT")?;
+        // Regional endpoints carry the region in the host; the global one has none
+        let host = match self.location.as_str() {
+            "global" => "https://aiplatform.googleapis.com".to_string(),
+            region => format!("https://{}-aiplatform.googleapis.com", region),
+        };
+        let url = format!("{}/v1/projects/{}/locations/{}/publishers/google/models/{}:{}",
+            r.base(&host), project, self.location, r.model, gemini::method(r.stream));
+        Ok((url, serde_json::to_vec(&gemini::body(r))?))
+    }
+
+    /// The access token normally comes from the Application Default Credentials per request;
+    /// a key given here is taken as a token already minted
+    fn authorize(&self, builder: RequestBuilder, key: &str, _: &ApiVersions) -> RequestBuilder {
+        if key.is_empty() { builder } else { builder.bearer_auth(key) }
+    }
+
+    fn parse_response(&self, response: Value) -> Result<Reply> {
+        Gemini.parse_response(response)
+    }
+
+    fn parse_stream_line(&self, line: &str) -> Result<Option<Delta>> {
+        Gemini.parse_stream_line(line)
+    }
+
+    fn supports_tools(&self) -> bool {
+        true
+    }
+
+    fn supports_streaming(&self) -> bool {
+        true
+    }
+}
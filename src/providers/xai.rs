@@ -0,0 +1,49 @@
+use anyhow::Result;
+use reqwest::RequestBuilder;
+use serde_json::Value;
+use crate::api_version::ApiVersions;
+use super::{compat, Delta, Provider, Reply, Request};
+
+/// xAI's Chat Completions for Grok. Reasoning models return their thinking as
+/// `reasoning_content` and count its tokens apart from `completion_tokens`.
+pub struct Xai;
+
+const QUIRKS: compat::Quirks = compat::Quirks { replay_reasoning: false, reasoning_tokens_apart: true };
+
+impl Provider for Xai {
+    fn name(&self) -> &'static str {
+        "xai"
+    }
+
+    fn default_model(&self) -> &'static str {
+        "grok-3-mini"
+    }
+
+    fn key_var(&self) -> Option<&'static str> {
+        Some("XAI_API_KEY")
+    }
+
+    fn build_request(&self, r: &Request) -> Result<(String, Vec<u8>)> {
+        Ok((format!("{}/v1/chat/completions", r.base("https://api.x.ai")), serde_json::to_vec(&compat::body(r, QUIRKS))?))
+    }
+
+    fn authorize(&self, builder: RequestBuilder, key: &str, _: &ApiVersions) -> RequestBuilder {
+        builder.bearer_auth(key)
+    }
+
+    fn parse_response(&self, response: Value) -> Result<Reply> {
+        compat::parse_response(response, QUIRKS)
+    }
+
+    fn parse_stream_line(&self, line: &str) -> Result<Option<Delta>> {
+        compat::parse_stream_line(line, QUIRKS)
+    }
+
+    fn supports_tools(&self) -> bool {
+        true
+    }
+
+    fn supports_streaming(&self) -> bool {
+        true
+    }
+}
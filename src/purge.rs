@@ -0,0 +1,162 @@
+//! `rustaigent purge`: delete what the agent keeps about past sessions, for users with
+//! data-retention obligations. Files are overwritten with zeros before they are unlinked; on
+//! copy-on-write filesystems and SSDs that is best effort, so pair it with `encryption`.
+
+use std::{fs, io::Write, path::{Path, PathBuf}, time::Duration};
+use anyhow::{Result, Context, bail};
+use crate::{paths, stats, vault};
+
+/// Which stored data to delete
+pub enum Scope {
+    /// Everything under the data directory
+    All,
+    /// One autosaved session: its bundle, plan, notes and usage record
+    Session(String),
+    /// Sessions, checkpoints, paged-out turns, stored tool outputs, notes, answers, usage records and prompts older than this
+    OlderThan(Duration),
+}
+
+/// One change a purge makes
+enum Target {
+    /// Overwritten and removed
+    File(PathBuf),
+    /// JSONL log rewritten without `removed` of its entries
+    Entries { path: PathBuf, keep: Vec<u8>, removed: usize, sealed: bool },
+}
+
+/// Directories whose files are purged by age
+const DATED_DIRS: &[&str] = &["sessions", "fix", "spill", "queue", "artifacts", "notes", "logkeys"];
+
+/// Usage records, one session per line, dated by `started`
+const USAGE: (&str, &str, bool) = ("usage.jsonl", "started", false);
+/// Prompt history, one prompt per line, dated by `at`; sealed when encryption is on
+const PROMPTS: (&str, &str, bool) = ("prompts.jsonl", "at", true);
+
+fn files_under(dir: &Path, out: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else { return };
+    for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+        if path.is_dir() {
+            files_under(&path, out);
+        } else {
+            out.push(path);
+        }
+    }
+}
+
+fn modified(path: &Path) -> u64 {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Entries of a JSONL log to drop; `None` when the log is missing or nothing matches
+fn entries(log: (&str, &str, bool), drop: impl Fn(&serde_json::Value) -> bool) -> Result<Option<Target>> {
+    let (name, _, sealed) = log;
+    let path = paths::data_dir().join(name);
+    let Some(text) = vault::read(&path)? else { return Ok(None) };
+    let text = String::from_utf8(text).with_context(|| format!("{} is not text", path.display()))?;
+    let (mut keep, mut removed) = (String::new(), 0);
+    for line in text.lines() {
+        if serde_json::from_str(line).is_ok_and(|entry| drop(&entry)) {
+            removed += 1;
+        } else {
+            keep.push_str(line);
+            keep.push('\n');
+        }
+    }
+    Ok((removed > 0).then(|| Target::Entries { path, keep: keep.into_bytes(), removed, sealed }))
+}
+
+/// Everything `scope` would delete, without touching it
+fn plan(scope: &Scope) -> Result<Vec<Target>> {
+    let data = paths::data_dir();
+    let mut files = Vec::new();
+    let mut targets = Vec::new();
+    match scope {
+        Scope::All => files_under(&data, &mut files),
+        Scope::Session(id) => {
+            if id.is_empty() || id.contains(['/', '\\']) {
+                bail!("`{}` is not a session id", id);
+            }
+            files_under(&data.join("sessions"), &mut files);
+            files_under(&data.join("notes"), &mut files);
+            files.retain(|p| p.file_name().and_then(|n| n.to_str()).is_some_and(|n| n.starts_with(&format!("{}.", id))));
+            targets.extend(entries(USAGE, |e| e["id"].as_str() == Some(id))?);
+        }
+        Scope::OlderThan(age) => {
+            let cutoff = stats::now_secs().saturating_sub(age.as_secs());
+            for dir in DATED_DIRS {
+                files_under(&data.join(dir), &mut files);
+            }
+            files.retain(|p| modified(p) < cutoff);
+            for log in [USAGE, PROMPTS] {
+                targets.extend(entries(log, |e| e[log.1].as_u64().is_some_and(|t| t < cutoff))?);
+            }
+        }
+    }
+    files.sort();
+    targets.splice(0..0, files.into_iter().map(Target::File));
+    Ok(targets)
+}
+
+/// Overwrite a file with zeros, flush it to disk and unlink it. Read-only files (objects in the
+/// sync clone, which only ever hold ciphertext) are unlinked as they are.
+fn shred(path: &Path) -> Result<()> {
+    if let Ok(mut file) = fs::OpenOptions::new().write(true).open(path) {
+        let mut left = file.metadata()?.len();
+        let zeros = [0u8; 64 * 1024];
+        while left > 0 {
+            let n = left.min(zeros.len() as u64) as usize;
+            file.write_all(&zeros[..n])?;
+            left -= n as u64;
+        }
+        file.sync_all()?;
+    }
+    fs::remove_file(path).with_context(|| format!("Failed to delete {}", path.display()))
+}
+
+/// Delete what `scope` selects, or with `dry_run` only list it
+pub fn run(scope: &Scope, dry_run: bool) -> Result<()> {
+    let targets = plan(scope)?;
+    if targets.is_empty() {
+        if let Scope::Session(id) = scope {
+            bail!("No stored session `{}`", id);
+        }
+        println!("Nothing to purge");
+        return Ok(());
+    }
+    let (mut files, mut removed) = (0, 0);
+    for target in targets {
+        match target {
+            Target::File(path) => {
+                let size = fs::metadata(&path).map_or(0, |m| m.len());
+                println!("{} {} ({} bytes)", if dry_run { "Would delete" } else { "Deleting" }, path.display(), size);
+                if !dry_run {
+                    shred(&path)?;
+                }
+                files += 1;
+            }
+            Target::Entries { path, keep, removed: n, sealed } => {
+                println!("{} {} entr{} from {}", if dry_run { "Would remove" } else { "Removing" }, n, if n == 1 { "y" } else { "ies" }, path.display());
+                if !dry_run {
+                    shred(&path)?;
+                    if sealed {
+                        vault::write(&path, keep)?;
+                    } else {
+                        fs::write(&path, keep).with_context(|| format!("Failed to write {}", path.display()))?;
+                    }
+                }
+                removed += n;
+            }
+        }
+    }
+    if dry_run {
+        println!("Dry run: {} file(s) and {} log entr{} would be purged", files, removed, if removed == 1 { "y" } else { "ies" });
+        return Ok(());
+    }
+    if let Scope::All = scope {
+        fs::remove_dir_all(paths::data_dir()).ok();
+    }
+    println!("Purged {} file(s) and {} log entr{}", files, removed, if removed == 1 { "y" } else { "ies" });
+    Ok(())
+}
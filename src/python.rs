@@ -0,0 +1,198 @@
+//! Python bindings, for notebooks and Python orchestration: `import rustaigent`. Build the module
+//! with `maturin develop --release` (pyproject.toml turns on the `python` feature).
+
+use futures::future::BoxFuture;
+use pyo3::{exceptions::PyRuntimeError, prelude::*, types::{PyDict, PyList}};
+use serde_json::Value;
+use crate::{events::AgentEvent, handle::{runtime, AgentHandle}, tools::{Tool, ToolCall}, AgentBuilder, FunctionDefinition};
+
+/// The error chain as a Python exception, without the backtrace anyhow would add
+fn error(err: anyhow::Error) -> PyErr {
+    PyRuntimeError::new_err(format!("{:#}", err))
+}
+
+fn to_json(py: Python<'_>, value: &Bound<'_, PyAny>) -> PyResult<String> {
+    py.import("json")?.call_method1("dumps", (value,))?.extract()
+}
+
+fn from_json<'py>(py: Python<'py>, value: &Value) -> PyResult<Bound<'py, PyAny>> {
+    py.import("json")?.call_method1("loads", (value.to_string(),))
+}
+
+/// A Python function offered to the model as a tool; it is called with the model's arguments as
+/// keyword arguments and its result is passed back as text
+#[pyclass(name = "FunctionTool", frozen)]
+pub struct FunctionTool {
+    definition: FunctionDefinition,
+    func: Py<PyAny>,
+}
+
+#[pymethods]
+impl FunctionTool {
+    /// `parameters` is the JSON Schema of the keyword arguments, as a dict
+    #[new]
+    #[pyo3(signature = (name, description, parameters, func))]
+    fn new(py: Python<'_>, name: String, description: String, parameters: &Bound<'_, PyAny>, func: Py<PyAny>) -> PyResult<Self> {
+        let parameters = serde_json::from_str(&to_json(py, parameters)?).map_err(|err| PyRuntimeError::new_err(err.to_string()))?;
+        Ok(FunctionTool { definition: FunctionDefinition { name, description, parameters }, func })
+    }
+
+    #[getter]
+    fn name(&self) -> &str {
+        &self.definition.name
+    }
+}
+
+struct PythonTool {
+    definition: FunctionDefinition,
+    func: Py<PyAny>,
+}
+
+impl Tool for PythonTool {
+    fn definition(&self) -> FunctionDefinition {
+        self.definition.clone()
+    }
+
+    fn call(&self, args: Value) -> BoxFuture<'_, anyhow::Result<String>> {
+        Box::pin(async move {
+            tokio::task::block_in_place(|| Python::attach(|py| -> PyResult<String> {
+                let kwargs = from_json(py, &args)?;
+                let kwargs = kwargs.cast::<PyDict>().ok();
+                self.func.call(py, (), kwargs)?.bind(py).str()?.extract()
+            })).map_err(|err| anyhow::anyhow!("{}", err))
+        })
+    }
+}
+
+/// One agent session. Tool calls that would ask for approval go to `approve(question) -> bool`,
+/// and `ask_user` questions to `answer(question) -> str`; without `approve` they are declined.
+#[pyclass(name = "Agent", frozen)]
+pub struct PyAgent {
+    handle: AgentHandle,
+}
+
+#[pymethods]
+impl PyAgent {
+    #[new]
+    #[pyo3(signature = (provider=None, model=None, api_key=None, system_prompt=None, tools=Vec::new(), approve=None, answer=None, yolo=false))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        py: Python<'_>,
+        provider: Option<String>,
+        model: Option<String>,
+        api_key: Option<String>,
+        system_prompt: Option<String>,
+        tools: Vec<PyRef<'_, FunctionTool>>,
+        approve: Option<Py<PyAny>>,
+        answer: Option<Py<PyAny>>,
+        yolo: bool,
+    ) -> PyResult<Self> {
+        let mut builder = AgentBuilder::new();
+        if let Some(provider) = provider {
+            builder = builder.provider(provider);
+        }
+        if let Some(model) = model {
+            builder = builder.model(model);
+        }
+        if let Some(key) = api_key {
+            builder = builder.api_key(key);
+        }
+        if let Some(prompt) = system_prompt {
+            builder = builder.system_prompt(prompt);
+        }
+        for tool in tools {
+            builder = builder.tool(PythonTool { definition: tool.definition.clone(), func: tool.func.clone_ref(py) });
+        }
+        let mut agent = builder.build().map_err(error)?;
+        // Answers are returned, not printed
+        agent.enable_quiet();
+        if yolo {
+            agent.enable_yolo();
+        }
+        match approve {
+            Some(approve) => {
+                let events = agent.events();
+                runtime().spawn(relay(events, approve, answer));
+            }
+            None => agent.enable_unattended(),
+        }
+        Ok(PyAgent { handle: AgentHandle::new(agent) })
+    }
+
+    /// Run one turn and return the answer; `None` when the guardrails held the prompt back
+    fn ask(&self, py: Python<'_>, prompt: String) -> PyResult<Option<String>> {
+        let handle = self.handle.clone();
+        py.detach(|| runtime().block_on(handle.send(&prompt))).map_err(error)
+    }
+
+    /// The conversation so far as `{"role": ..., "content": ...}` dicts, system prompt first
+    fn history<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyList>> {
+        let handle = self.handle.clone();
+        let messages = py.detach(|| runtime().block_on(async { handle.history().await.load_all() })).map_err(error)?;
+        let list = PyList::empty(py);
+        for message in messages {
+            let dict = PyDict::new(py);
+            dict.set_item("role", message.role)?;
+            dict.set_item("content", message.content)?;
+            if let Some(call) = message.function_call {
+                dict.set_item("function_call", (call.name, call.arguments))?;
+            }
+            list.append(dict)?;
+        }
+        Ok(list)
+    }
+}
+
+/// Hand the session's prompts to the Python callbacks
+async fn relay(mut events: crate::events::Receiver, approve: Py<PyAny>, answer: Option<Py<PyAny>>) {
+    while let Some(event) = events.recv().await {
+        match event {
+            AgentEvent::ApprovalNeeded { question, respond } => {
+                let approved = tokio::task::block_in_place(|| Python::attach(|py| {
+                    approve.call1(py, (question,)).and_then(|r| r.bind(py).is_truthy())
+                }));
+                match approved {
+                    Ok(true) => respond.approve(),
+                    _ => respond.deny(),
+                }
+            }
+            AgentEvent::QuestionAsked { question, respond } => {
+                let Some(answer) = &answer else { continue };
+                let text = tokio::task::block_in_place(|| Python::attach(|py| {
+                    answer.call1(py, (question,)).and_then(|r| r.bind(py).str()?.extract::<String>())
+                }));
+                respond.answer(text.unwrap_or_default());
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Names of the supported providers
+#[pyfunction]
+fn providers() -> Vec<&'static str> {
+    crate::config::PROVIDERS.to_vec()
+}
+
+/// The built-in tools as `{"name", "description", "parameters"}` dicts
+#[pyfunction]
+fn tools<'py>(py: Python<'py>) -> PyResult<Bound<'py, PyList>> {
+    let list = PyList::empty(py);
+    for definition in ToolCall::definitions() {
+        let dict = PyDict::new(py);
+        dict.set_item("name", definition.name)?;
+        dict.set_item("description", definition.description)?;
+        dict.set_item("parameters", from_json(py, &definition.parameters)?)?;
+        list.append(dict)?;
+    }
+    Ok(list)
+}
+
+#[pymodule]
+fn rustaigent(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyAgent>()?;
+    module.add_class::<FunctionTool>()?;
+    module.add_function(wrap_pyfunction!(providers, module)?)?;
+    module.add_function(wrap_pyfunction!(tools, module)?)?;
+    Ok(())
+}
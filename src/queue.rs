@@ -0,0 +1,190 @@
+use std::{fs, path::PathBuf};
+use chrono::Timelike;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use anyhow::{Result, Context};
+use crate::{batch, paths, stats, turns::Turn, Agent};
+
+/// A prompt deferred to the Batch API
+#[derive(Serialize, Deserialize)]
+pub struct Job {
+    pub id: u64,
+    pub prompt: String,
+    pub model: String,
+    pub queued: u64,
+    /// Batch the job was submitted in; `None` while it waits for the off-peak window
+    pub batch: Option<String>,
+    /// Where the answer was saved once the batch completed
+    pub answer: Option<PathBuf>,
+    pub error: Option<String>,
+}
+
+/// A submitted batch as last reported by the provider
+#[derive(Serialize, Deserialize)]
+pub struct Batch {
+    pub id: String,
+    pub status: String,
+    pub submitted: u64,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct Queue {
+    pub jobs: Vec<Job>,
+    pub batches: Vec<Batch>,
+}
+
+fn queue_file() -> PathBuf {
+    paths::data_dir().join("queue.json")
+}
+
+fn answers_dir() -> PathBuf {
+    paths::data_dir().join("queue")
+}
+
+impl Queue {
+    fn load() -> Result<Self> {
+        let path = queue_file();
+        if !path.is_file() {
+            return Ok(Queue::default());
+        }
+        serde_json::from_str(&fs::read_to_string(&path)?).with_context(|| format!("Corrupt queue {}", path.display()))
+    }
+
+    fn save(&self) -> Result<()> {
+        let path = queue_file();
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(&path, serde_json::to_vec_pretty(self)?).with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+/// Daily local time window such as `22:00-06:00`, in minutes after midnight; may wrap past midnight
+#[derive(Clone, Copy)]
+pub struct Window {
+    start: u32,
+    end: u32,
+}
+
+fn parse_time(text: &str) -> Option<u32> {
+    let (h, m) = text.trim().split_once(':')?;
+    let (h, m): (u32, u32) = (h.parse().ok()?, m.parse().ok()?);
+    (h < 24 && m < 60).then_some(h * 60 + m)
+}
+
+impl Window {
+    pub fn parse(spec: &str) -> Result<Self> {
+        let (start, end) = spec.split_once('-')
+            .and_then(|(s, e)| Some((parse_time(s)?, parse_time(e)?)))
+            .with_context(|| format!("off_peak `{}` must look like 22:00-06:00", spec))?;
+        Ok(Window { start, end })
+    }
+
+    pub fn contains(&self, minute: u32) -> bool {
+        if self.start <= self.end { (self.start..self.end).contains(&minute) } else { minute >= self.start || minute < self.end }
+    }
+}
+
+/// Send pending jobs as one batch
+async fn submit(agent: &Agent, queue: &mut Queue) -> Result<()> {
+    let pending: Vec<usize> = (0..queue.jobs.len()).filter(|&i| queue.jobs[i].batch.is_none()).collect();
+    let model = queue.jobs[pending[0]].model.clone();
+    let requests: Vec<(String, String)> = pending.iter().map(|&i| (queue.jobs[i].id.to_string(), queue.jobs[i].prompt.clone())).collect();
+    let batch = batch::submit(agent, &requests, &model).await?;
+    let id = batch["id"].as_str().unwrap_or_default().to_string();
+    for &i in &pending {
+        queue.jobs[i].batch = Some(id.clone());
+    }
+    println!("Submitted {} job(s) as batch {}", pending.len(), id);
+    queue.batches.push(Batch { id, status: batch["status"].as_str().unwrap_or("validating").into(), submitted: stats::now_secs() });
+    queue.save()
+}
+
+/// Submit waiting jobs when inside the off-peak window (or always with `now`), otherwise say when they will go
+async fn flush(agent: &Agent, queue: &mut Queue, now: bool) -> Result<()> {
+    let waiting = queue.jobs.iter().filter(|j| j.batch.is_none()).count();
+    if waiting == 0 {
+        return Ok(());
+    }
+    if let Some(spec) = agent.config.off_peak.as_deref().filter(|_| !now) {
+        let local = chrono::Local::now();
+        if !Window::parse(spec)?.contains(local.hour() * 60 + local.minute()) {
+            println!("{} job(s) wait for the off-peak window {}; run `rustaigent queue` then (e.g. from cron), or pass --now", waiting, spec);
+            return Ok(());
+        }
+    }
+    submit(agent, queue).await
+}
+
+/// Save the answers of a finished batch next to the queue
+async fn collect(agent: &Agent, queue: &mut Queue, batch: &Value) -> Result<()> {
+    let lines = batch::results(agent, batch).await?;
+    fs::create_dir_all(answers_dir())?;
+    for line in lines {
+        let Some(job) = queue.jobs.iter_mut().find(|j| line["custom_id"].as_str() == Some(&j.id.to_string())) else {
+            continue;
+        };
+        match batch::outcome(&line) {
+            Ok(answer) => {
+                let path = answers_dir().join(format!("{}.md", job.id));
+                fs::write(&path, format!("# {}\n\n{}\n", job.prompt, answer))?;
+                job.answer = Some(path);
+            }
+            Err(err) => job.error = Some(err),
+        }
+    }
+    Ok(())
+}
+
+/// Queue `prompts` and submit whatever is due
+pub async fn add(agent: &Agent, prompts: Vec<String>, now: bool) -> Result<()> {
+    batch::require_openai(agent)?;
+    let mut queue = Queue::load()?;
+    let model = agent.turn_model(Turn::Answer);
+    for prompt in prompts.into_iter().map(|p| p.trim().to_string()).filter(|p| !p.is_empty()) {
+        let id = queue.jobs.iter().map(|j| j.id).max().unwrap_or(0) + 1;
+        println!("Queued job {}", id);
+        queue.jobs.push(Job { id, prompt, model: model.clone(), queued: stats::now_secs(), batch: None, answer: None, error: None });
+    }
+    queue.save()?;
+    flush(agent, &mut queue, now).await
+}
+
+/// Submit due jobs, poll unfinished batches and list every job
+pub async fn status(agent: &Agent) -> Result<()> {
+    let mut queue = Queue::load()?;
+    if agent.provider == "openai" {
+        flush(agent, &mut queue, false).await?;
+        for i in 0..queue.batches.len() {
+            if batch::finished(&queue.batches[i].status) {
+                continue;
+            }
+            let batch = batch::poll(agent, &queue.batches[i].id).await?;
+            queue.batches[i].status = batch["status"].as_str().unwrap_or("unknown").to_string();
+            if batch::finished(&queue.batches[i].status) {
+                collect(agent, &mut queue, &batch).await?;
+                let reason = batch::failure(&batch);
+                let id = &queue.batches[i].id;
+                for job in queue.jobs.iter_mut().filter(|j| j.batch.as_ref() == Some(id) && j.answer.is_none() && j.error.is_none()) {
+                    job.error = Some(reason.clone());
+                }
+            }
+            queue.save()?;
+        }
+    }
+    if queue.jobs.is_empty() {
+        println!("The queue is empty");
+    }
+    for job in &queue.jobs {
+        let state = match (&job.answer, &job.error, &job.batch) {
+            (Some(path), _, _) => format!("done        {}", path.display()),
+            (_, Some(err), _) => format!("failed      {}", err),
+            (_, _, Some(batch)) => {
+                let status = queue.batches.iter().find(|b| &b.id == batch).map_or("submitted", |b| b.status.as_str());
+                format!("{:<11} {}", status, batch)
+            }
+            _ => "waiting     for the off-peak window".into(),
+        };
+        let prompt: String = job.prompt.chars().take(40).collect();
+        println!("{:>4}  {:<40}  {}", job.id, prompt, state);
+    }
+    Ok(())
+}
@@ -0,0 +1,74 @@
+//! Racing providers: each request also goes to a second provider, and whichever answers first
+//! is kept while the other request is dropped mid-flight. Latency over cost, for the REPL.
+
+use std::future::Future;
+use anyhow::Result;
+use serde::{Serialize, Deserialize};
+
+/// `[race]` table: the provider each request is raced against
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct RaceConfig {
+    /// Provider raced against the session's
+    pub provider: String,
+    /// Its model; the provider's default when unset
+    pub model: Option<String>,
+    /// Scheme and host when it is not served from the provider's default endpoint
+    pub base_url: Option<String>,
+    /// Race from the start of the session; on by default, `/race on` turns it on later
+    pub enabled: Option<bool>,
+}
+
+/// Keys accepted in `[race]`, for config validation
+pub const KEYS: &[&str] = &["provider", "model", "base_url", "enabled"];
+
+/// Which of the two raced requests an answer came from
+#[derive(Debug, PartialEq)]
+pub enum Winner {
+    Session,
+    Rival,
+}
+
+/// The first of `session` and `rival` to succeed, the other dropped. A failure does not decide
+/// the race: the other request is awaited, and when both fail the session's error is returned.
+pub async fn first_ok<T>(session: impl Future<Output = Result<T>>, rival: impl Future<Output = Result<T>>) -> Result<(T, Winner)> {
+    tokio::pin!(session, rival);
+    tokio::select! {
+        answer = &mut session => match answer {
+            Ok(answer) => Ok((answer, Winner::Session)),
+            Err(err) => match rival.await {
+                Ok(answer) => Ok((answer, Winner::Rival)),
+                Err(_) => Err(err),
+            },
+        },
+        answer = &mut rival => match answer {
+            Ok(answer) => Ok((answer, Winner::Rival)),
+            Err(err) => {
+                tracing::debug!("raced provider failed: {:#}", err);
+                session.await.map(|answer| (answer, Winner::Session))
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use crate::{mock::MockProvider, turns::Turn};
+    use super::RaceConfig;
+
+    #[tokio::test]
+    async fn raced_requests_keep_the_first_answer() {
+        let (session, rival) = (MockProvider::start().await, MockProvider::start().await);
+        session.slow_reply("from the session's provider", std::time::Duration::from_secs(2)).await;
+        rival.reply("from the rival").await;
+        let mut agent = session.agent();
+        agent.enable_quiet();
+        agent.race = Some(Arc::new(RaceConfig { provider: "openai".into(), base_url: Some(rival.base_url()), ..Default::default() }));
+        let started = std::time::Instant::now();
+        assert_eq!(agent.send_request(Turn::Answer, None).await.unwrap().content, "from the rival");
+        assert!(started.elapsed() < std::time::Duration::from_secs(2));
+        assert_eq!(rival.requests().await.len(), 1);
+        agent.race = None;
+        assert_eq!(agent.send_request(Turn::Answer, None).await.unwrap().content, "from the session's provider");
+    }
+}
@@ -0,0 +1,68 @@
+use std::env;
+use crate::config::Config;
+
+pub const EFFORTS: &[&str] = &["low", "medium", "high"];
+
+/// Reasoning-model settings: how hard to think, and what to do with the thinking afterwards
+#[derive(Clone, Default)]
+pub struct Reasoning {
+    /// `reasoning_effort` for OpenAI o-series and compatible models
+    pub effort: Option<String>,
+    /// Extended-thinking budget in tokens, for Claude and Gemini
+    pub budget: Option<u32>,
+    /// Print reasoning before the answer
+    pub show: bool,
+    /// Keep reasoning in the conversation transcript
+    pub persist: bool,
+}
+
+fn flag(var: &str) -> Option<bool> {
+    env::var(var).ok().map(|v| v == "true" || v == "1")
+}
+
+impl Reasoning {
+    /// Resolve from REASONING_EFFORT / THINKING_BUDGET / SHOW_THINKING, then the config file
+    pub fn resolve(config: &Config) -> Self {
+        let effort = env::var("REASONING_EFFORT").ok().or_else(|| config.reasoning_effort.clone())
+            .filter(|e| {
+                let known = EFFORTS.contains(&e.as_str());
+                if !known {
+                    tracing::warn!("ignoring reasoning effort `{}` (expected one of {})", e, EFFORTS.join(", "));
+                }
+                known
+            });
+        Reasoning {
+            effort,
+            budget: env::var("THINKING_BUDGET").ok().and_then(|v| v.parse().ok()).or(config.thinking_budget),
+            show: flag("SHOW_THINKING").or(config.show_thinking).unwrap_or(false),
+            persist: config.persist_thinking.unwrap_or(false),
+        }
+    }
+}
+
+/// Split `<think>…</think>` blocks, as emitted inline by local reasoning models, from the answer
+pub fn split_think_tags(content: &str) -> (Option<String>, String) {
+    let mut thinking = Vec::new();
+    let mut answer = String::new();
+    let mut rest = content;
+    while let Some(start) = rest.find("<think>") {
+        answer.push_str(&rest[..start]);
+        let after = &rest[start + "<think>".len()..];
+        match after.find("</think>") {
+            Some(end) => {
+                thinking.push(after[..end].trim().to_string());
+                rest = &after[end + "</think>".len()..];
+            }
+            None => {
+                thinking.push(after.trim().to_string());
+                rest = "";
+            }
+        }
+    }
+    answer.push_str(rest);
+    if thinking.is_empty() {
+        (None, content.to_string())
+    } else {
+        (Some(thinking.join("\n\n")), answer.trim_start().to_string())
+    }
+}
@@ -0,0 +1,69 @@
+//! Live reload of the config files and the system prompt file. They are checked before each
+//! turn, and settings that are safe to change mid-session (sampling, tool permissions, the
+//! prompt) take effect without a restart. Every applied change is announced and recorded in the
+//! conversation, so the transcript shows what the rest of the session ran under.
+
+use std::{path::PathBuf, time::SystemTime};
+use crate::{config::Config, paths};
+
+/// `name` of the system messages recording a reload
+pub const NOTE: &str = "config_reload";
+
+/// Keys a running session picks up; changes to any other key wait for a restart
+pub const LIVE: &[&str] = &[
+    "temperature", "max_tokens", "system_prompt", "system_prompt_file",
+    "policy", "permissions", "role", "allowed_paths", "allowed_hosts", "allow_private_network",
+    "command_timeout_secs", "command_output_limit", "hot_reload", "prefetch", "actions_summary", "suggestions",
+];
+
+/// Config files in effect now, and the prompt file `config` names
+fn files(config: &Config) -> Vec<PathBuf> {
+    [paths::team_config_file(), Some(paths::user_config_file()), paths::project_config_file(), config.system_prompt_file.clone()]
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+fn stamps(config: &Config) -> Vec<(PathBuf, Option<SystemTime>)> {
+    files(config).into_iter().map(|path| {
+        let modified = std::fs::metadata(&path).and_then(|m| m.modified()).ok();
+        (path, modified)
+    }).collect()
+}
+
+/// Modification times of the watched files at the last look
+pub struct Watcher {
+    seen: Vec<(PathBuf, Option<SystemTime>)>,
+    /// Configured system prompt text the session runs with, before the additions made at startup
+    pub prompt: String,
+}
+
+impl Watcher {
+    pub fn new(config: &Config, prompt: String) -> Self {
+        Watcher { seen: stamps(config), prompt }
+    }
+
+    /// Whether a watched file was changed, created or removed since the last look
+    pub fn changed(&mut self, config: &Config) -> bool {
+        let now = stamps(config);
+        if now == self.seen {
+            return false;
+        }
+        self.seen = now;
+        true
+    }
+}
+
+/// Top-level keys whose values differ between `old` and `new`
+pub fn changed_keys(old: &Config, new: &Config) -> Vec<String> {
+    let (Ok(serde_json::Value::Object(old)), Ok(serde_json::Value::Object(new))) = (serde_json::to_value(old), serde_json::to_value(new)) else {
+        return Vec::new();
+    };
+    let mut keys: Vec<String> = old.keys().chain(new.keys())
+        .filter(|k| old.get(*k) != new.get(*k))
+        .cloned()
+        .collect();
+    keys.sort();
+    keys.dedup();
+    keys
+}
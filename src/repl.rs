@@ -0,0 +1,113 @@
+//! Line editing for the REPL: history across sessions, multi-line prompts and completion of
+//! slash commands.
+
+use std::{borrow::Cow, io::{IsTerminal, Write}};
+use anyhow::Result;
+use rustyline::{completion::{Completer, Pair}, error::ReadlineError, history::DefaultHistory, validate::{ValidationContext, ValidationResult, Validator}, Context, Editor, Helper, Highlighter, Hinter};
+
+/// Prompts recalled with the arrow keys, newest last
+const HISTORY_SIZE: usize = 1000;
+
+/// REPL commands with their arguments and what they do, for `/help` and completion
+pub const COMMANDS: &[(&str, &str, &str)] = &[
+    ("/help", "", "List these commands"),
+    ("/howdoi", "<thing>", "Look something up in the built-in documentation, offline"),
+    ("/model", "[name]", "Show the model, or switch to another for the rest of the session"),
+    ("/provider", "[name]", "Show the provider, or switch to openai, claude, ollama, google, vertex, huggingface, xai or deepseek"),
+    ("/race", "[on|off|provider]", "Send each request to a second provider too and keep the first answer"),
+    ("/tools", "", "List the tools the model may call"),
+    ("/clear", "", "Start over with only the system prompt"),
+    ("/retry", "", "Drop the last answer and send its prompt again"),
+    ("/upload", "<path>", "Upload a PDF or data file to the provider for the next prompt"),
+    ("/template", "[name]", "List conversation templates, or seed the session from one"),
+    ("/context", "[drop <items>]", "What fills the prompt, item by item, or drop some of it"),
+    ("/cost", "", "Requests, tokens and cost so far"),
+    ("/tag", "[name=value|-name]", "List the tags spend is attributed to, or set or drop one"),
+    ("/capabilities", "", "What the current model supports"),
+    ("/plan", "[task|done <n>]", "Show the plan, make one for a task or check a step off"),
+    ("/simulate", "<tool(args)>", "Describe what a tool call would do without running it"),
+    ("/editor", "[draft]", "Write the prompt in $EDITOR"),
+    ("/export", "<path>", "Save the session to a file"),
+    ("/sessions", "[id]", "List saved sessions, or replay one"),
+    ("/init", "", "Set up a provider and its key, and switch to it"),
+];
+
+#[derive(Helper, Highlighter, Hinter)]
+struct Input;
+
+/// Whether more lines belong to the prompt: inside a ``` fence until it is closed, and after a
+/// first line ending in `\` until a blank line
+fn incomplete(input: &str) -> bool {
+    if input.lines().filter(|l| l.trim_start().starts_with("```")).count() % 2 == 1 {
+        return true;
+    }
+    input.lines().next().is_some_and(|first| first.ends_with('\\')) && !input.ends_with('\n')
+}
+
+impl Validator for Input {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        Ok(if incomplete(ctx.input()) { ValidationResult::Incomplete } else { ValidationResult::Valid(None) })
+    }
+}
+
+impl Completer for Input {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let typed = &line[..pos];
+        if !typed.starts_with('/') || typed.contains(char::is_whitespace) {
+            return Ok((pos, Vec::new()));
+        }
+        let matches = COMMANDS.iter()
+            .filter(|(name, ..)| name.starts_with(typed))
+            .map(|(name, args, _)| Pair { display: format!("{} {}", name, args).trim_end().to_string(), replacement: name.to_string() })
+            .collect();
+        Ok((0, matches))
+    }
+}
+
+/// Reads prompts from the terminal with editing and history, or line by line from a pipe
+pub struct LineReader {
+    editor: Editor<Input, DefaultHistory>,
+}
+
+impl LineReader {
+    /// An editor primed with the prompt history of earlier sessions
+    pub fn new() -> Result<Self> {
+        let config = rustyline::Config::builder().max_history_size(HISTORY_SIZE)?.auto_add_history(true).build();
+        let mut editor = Editor::with_config(config)?;
+        editor.set_helper(Some(Input));
+        match crate::sync::prompts() {
+            Ok(prompts) => {
+                for prompt in prompts.iter().skip(prompts.len().saturating_sub(HISTORY_SIZE)) {
+                    editor.add_history_entry(prompt.as_str())?;
+                }
+            }
+            Err(err) => tracing::debug!("prompt history unavailable: {:#}", err),
+        }
+        Ok(LineReader { editor })
+    }
+
+    /// The next input, shown after `prompt` (`styled` is the same text with colors); `None` at
+    /// end of input. Ctrl-C discards the line being typed and reads another.
+    pub fn read(&mut self, prompt: &str, styled: &str) -> Result<Option<String>> {
+        if !std::io::stdin().is_terminal() {
+            print!("{}", styled);
+            std::io::stdout().flush()?;
+        }
+        match self.editor.readline(&(prompt, styled)) {
+            Ok(line) => Ok(Some(join(&line).into_owned())),
+            Err(ReadlineError::Interrupted) => Ok(Some(String::new())),
+            Err(ReadlineError::Eof) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+/// The prompt without the backslash that continued its first line
+fn join(input: &str) -> Cow<'_, str> {
+    match input.split_once('\n') {
+        Some((first, rest)) if first.ends_with('\\') => Cow::Owned(format!("{}\n{}", first.trim_end_matches('\\').trim_end(), rest)),
+        _ => Cow::Borrowed(input),
+    }
+}
@@ -0,0 +1,56 @@
+use std::io::{self, IsTerminal, Write};
+use anyhow::Result;
+use crate::{session::{Bundle, Step}, theme::{Role, Theme}, tools::diff};
+
+fn render(step: &Step, index: usize, total: usize, theme: &Theme) -> String {
+    let header = format!("── step {}/{} ", index + 1, total);
+    match step {
+        Step::Message { role, content } => {
+            let color = match *role {
+                "user" => Role::User,
+                "assistant" => Role::Assistant,
+                _ => Role::Thinking,
+            };
+            format!("{}{}\n{}", header, theme.paint(color, &format!("[{}]", role)), theme.reply(content))
+        }
+        Step::Tool(call) => {
+            let status = if call.ok { "ok" } else { "failed" };
+            let body = if diff::is_unified(&call.output) { theme.diff(&call.output) } else { call.output.clone() };
+            format!("{}{} {} -> {}\n{}", header, theme.paint(Role::Tool, &format!("[tool {}]", call.call.name())), call.call.args(), status, body)
+        }
+    }
+}
+
+/// Step through a session: Enter/`n` next, `p` previous, a number jumps, `q` quits.
+/// When stdin is not a terminal the whole session is printed in order.
+pub fn run(bundle: &Bundle, theme: &Theme) -> Result<()> {
+    let steps = bundle.steps();
+    println!("Session {} ({}), {} steps", bundle.id, bundle.provider, steps.len());
+    if !io::stdin().is_terminal() {
+        for (i, step) in steps.iter().enumerate() {
+            println!("{}\n", render(step, i, steps.len(), theme));
+        }
+        return Ok(());
+    }
+
+    let mut current = 0;
+    while let Some(step) = steps.get(current) {
+        println!("{}", render(step, current, steps.len(), theme));
+        print!("[n]ext [p]rev [number] [q]uit > ");
+        io::stdout().flush()?;
+        let mut line = String::new();
+        if io::stdin().read_line(&mut line)? == 0 {
+            break;
+        }
+        match line.trim() {
+            "" | "n" => current += 1,
+            "p" => current = current.saturating_sub(1),
+            "q" => break,
+            other => match other.parse::<usize>() {
+                Ok(n) if (1..=steps.len()).contains(&n) => current = n - 1,
+                _ => println!("Enter n, p, q or a step number between 1 and {}", steps.len()),
+            },
+        }
+    }
+    Ok(())
+}
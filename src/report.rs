@@ -0,0 +1,130 @@
+//! `rustaigent stats --html`: the usage summary as one standalone HTML file, with cost over time,
+//! usage per model and tool frequency drawn as inline SVG, so it can be mailed or put on a wiki
+//! for people without the CLI. Nothing is loaded from the network.
+
+use std::fmt::Write;
+use crate::stats::{ModelSummary, Summary, Tags};
+
+const TEMPLATE: &str = include_str!("web/report.html");
+
+/// Width of the charts' drawing area, before scaling to the page
+const WIDTH: f64 = 900.0;
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// The report for `sum`, covering sessions started within `since`, counting only spend under `filter`
+pub fn html(sum: &Summary, since: &str, filter: &Tags) -> String {
+    let mut subtitle = format!("Sessions started in the last {}, generated {}", since, chrono::Local::now().format("%Y-%m-%d %H:%M"));
+    if !filter.is_empty() {
+        let tags: Vec<String> = filter.iter().map(|(name, value)| format!("{}={}", name, value)).collect();
+        subtitle.push_str(&format!("; only spend tagged {}", tags.join(", ")));
+    }
+    let mut body = tiles(sum);
+    if sum.sessions == 0 {
+        body.push_str("<p class=\"muted\">No usage was recorded in this period.</p>");
+    } else {
+        body.push_str(&format!("<h2>Cost per day</h2><div class=\"chart\">{}</div>", cost_chart(sum)));
+        let models = bars(sum.models.iter().map(|(model, m)| (model.as_str(), m.cost_usd, 0.0, format!("${:.4} · {} requests · {} tokens", m.cost_usd, m.requests, m.prompt_tokens + m.completion_tokens))).collect());
+        body.push_str(&format!("<h2>Cost per model</h2><div class=\"chart\">{}</div>", models));
+        body.push_str(&table("Model", &sum.models));
+        if sum.providers.len() > 1 {
+            body.push_str(&format!("<h2>Providers</h2>{}", table("Provider", &sum.providers)));
+        }
+        if !sum.tags.is_empty() {
+            body.push_str(&format!("<h2>Tags</h2>{}", table("Tag", &sum.tags)));
+        }
+        if !sum.tool_calls.is_empty() {
+            let tools = bars(sum.tool_calls.iter().map(|(tool, &calls)| {
+                let failures = sum.tools.get(tool).map_or(0, |t| t.failures);
+                (tool.as_str(), calls as f64, failures as f64, format!("{} calls · {} failed", calls, failures))
+            }).collect());
+            body.push_str(&format!("<h2>Tool calls</h2><div class=\"chart\">{}</div>", tools));
+            body.push_str(&format!("<p class=\"muted\">Failed calls in red. Tools took {:.0}% of task time; the rest was spent on model requests.</p>", sum.tool_share * 100.0));
+        }
+    }
+    TEMPLATE.replace("{{title}}", "RustAIgent usage")
+        .replace("{{subtitle}}", &escape(&subtitle))
+        .replace("{{body}}", &body)
+}
+
+fn tiles(sum: &Summary) -> String {
+    let cost: f64 = sum.models.values().map(|m| m.cost_usd).sum();
+    let tokens: u64 = sum.models.values().map(|m| m.prompt_tokens + m.completion_tokens).sum();
+    let tiles = [
+        ("Total cost", format!("${:.2}", cost)),
+        ("Sessions", sum.sessions.to_string()),
+        ("Tasks", sum.tasks.to_string()),
+        ("Tokens", tokens.to_string()),
+        ("Average task", format!("{:.1}s", sum.avg_task_secs)),
+        ("Approved", format!("{:.0}%", sum.approval_rate * 100.0)),
+    ];
+    let mut out = String::from("<div class=\"tiles\">");
+    for (label, value) in tiles {
+        let _ = write!(out, "<div class=\"tile\"><span class=\"muted\">{}</span><b>{}</b></div>", label, value);
+    }
+    out.push_str("</div>");
+    out
+}
+
+/// Vertical bars of cost for every day from the first to the last with usage
+fn cost_chart(sum: &Summary) -> String {
+    let parse = |day: &String| chrono::NaiveDate::parse_from_str(day, "%Y-%m-%d").ok();
+    let (Some(first), Some(last)) = (sum.days.keys().next().and_then(parse), sum.days.keys().next_back().and_then(parse)) else {
+        return String::new();
+    };
+    let days: Vec<(String, f64)> = first.iter_days().take_while(|d| *d <= last)
+        .map(|d| d.format("%Y-%m-%d").to_string())
+        .map(|d| (d.clone(), sum.days.get(&d).map_or(0.0, |m| m.cost_usd)))
+        .collect();
+    let (height, left, bottom) = (220.0, 60.0, 24.0);
+    let max = days.iter().map(|(_, c)| *c).fold(0.0, f64::max).max(0.0001);
+    let slot = (WIDTH - left) / days.len() as f64;
+    let mut svg = format!("<svg viewBox=\"0 0 {} {}\" role=\"img\" aria-label=\"Cost per day\">", WIDTH, height);
+    let _ = write!(svg, "<line class=\"axis\" x1=\"{l}\" y1=\"{b}\" x2=\"{w}\" y2=\"{b}\"/>", l = left, b = height - bottom, w = WIDTH);
+    let _ = write!(svg, "<text x=\"{}\" y=\"12\" text-anchor=\"end\">${:.2}</text><text x=\"{}\" y=\"{}\" text-anchor=\"end\">$0</text>", left - 6.0, max, left - 6.0, height - bottom);
+    // About eight date labels, however long the period
+    let every = days.len().div_ceil(8).max(1);
+    for (i, (day, cost)) in days.iter().enumerate() {
+        let bar = (height - bottom - 16.0) * cost / max;
+        let x = left + slot * i as f64;
+        let _ = write!(svg, "<rect class=\"bar\" x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\"><title>{}: ${:.4}</title></rect>",
+            x + slot * 0.1, height - bottom - bar, slot * 0.8, bar, day, cost);
+        if i % every == 0 {
+            let _ = write!(svg, "<text x=\"{:.1}\" y=\"{}\" text-anchor=\"middle\">{}</text>", x + slot / 2.0, height - 6.0, &day[5..]);
+        }
+    }
+    svg.push_str("</svg>");
+    svg
+}
+
+/// Horizontal bars, largest first: label, value, the part of it drawn as failures, and a caption
+fn bars(mut rows: Vec<(&str, f64, f64, String)>) -> String {
+    rows.sort_by(|a, b| b.1.total_cmp(&a.1));
+    let (row, label) = (26.0, 200.0);
+    let max = rows.iter().map(|r| r.1).fold(0.0, f64::max).max(0.0001);
+    let span = WIDTH - label - 240.0;
+    let mut svg = format!("<svg viewBox=\"0 0 {} {}\" role=\"img\">", WIDTH, row * rows.len() as f64);
+    for (i, (name, value, failed, caption)) in rows.iter().enumerate() {
+        let y = row * i as f64;
+        let width = span * value / max;
+        let _ = write!(svg, "<text x=\"{}\" y=\"{:.1}\" text-anchor=\"end\">{}</text>", label - 8.0, y + 17.0, escape(name));
+        let _ = write!(svg, "<rect class=\"bar\" x=\"{}\" y=\"{:.1}\" width=\"{:.1}\" height=\"18\"/>", label, y + 4.0, width);
+        if *failed > 0.0 {
+            let _ = write!(svg, "<rect class=\"fail\" x=\"{}\" y=\"{:.1}\" width=\"{:.1}\" height=\"18\"/>", label, y + 4.0, span * failed / max);
+        }
+        let _ = write!(svg, "<text x=\"{:.1}\" y=\"{:.1}\">{}</text>", label + width + 8.0, y + 17.0, escape(caption));
+    }
+    svg.push_str("</svg>");
+    svg
+}
+
+fn table(heading: &str, rows: &std::collections::BTreeMap<String, ModelSummary>) -> String {
+    let mut out = format!("<table><tr><th>{}</th><th>Requests</th><th>Prompt tokens</th><th>Completion tokens</th><th>Cost $</th></tr>", heading);
+    for (name, m) in rows {
+        let _ = write!(out, "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{:.4}</td></tr>", escape(name), m.requests, m.prompt_tokens, m.completion_tokens, m.cost_usd);
+    }
+    out.push_str("</table>");
+    out
+}
@@ -0,0 +1,97 @@
+//! Retries shared by a turn: every request the turn makes, over all its tool rounds, draws from
+//! one budget and stops retrying at one deadline, so retries that each look reasonable cannot
+//! add up to minutes. Requests outside a turn retry `retry_count` times each.
+
+use std::{sync::Mutex, time::{Duration, Instant}};
+
+/// Retries one turn may make across all its requests, unless `turn_retries` says otherwise
+pub const DEFAULT_RETRIES: u32 = 4;
+/// Time from the start of a turn, tools included, after which nothing is retried
+pub const DEFAULT_DEADLINE: Duration = Duration::from_secs(90);
+
+struct Turn {
+    left: u32,
+    used: u32,
+    started: Instant,
+    deadline: Duration,
+}
+
+/// The running turn's retry budget; shared by the copies of the session racing or routing it
+#[derive(Default)]
+pub struct Budget(Mutex<Option<Turn>>);
+
+impl Budget {
+    /// Start a turn with `retries` to spend before `deadline` is up
+    pub fn begin(&self, retries: u32, deadline: Duration) {
+        *self.0.lock().unwrap() = Some(Turn { left: retries, used: 0, started: Instant::now(), deadline });
+    }
+
+    pub fn end(&self) {
+        *self.0.lock().unwrap() = None;
+    }
+
+    /// Take a retry made after waiting `pause`: `None` outside a turn, otherwise `Ok` or why the
+    /// turn cannot afford it
+    pub fn take(&self, pause: Duration) -> Option<Result<(), String>> {
+        let mut turn = self.0.lock().unwrap();
+        let turn = turn.as_mut()?;
+        let elapsed = turn.started.elapsed();
+        if turn.left == 0 {
+            return Some(Err(format!("the turn has used all {} of its retries", turn.used)));
+        }
+        if elapsed + pause > turn.deadline {
+            return Some(Err(format!("a retry in {}s would pass the turn's {}s retry deadline", pause.as_secs().max(1), turn.deadline.as_secs())));
+        }
+        turn.left -= 1;
+        turn.used += 1;
+        Some(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use crate::mock::MockProvider;
+
+    #[tokio::test]
+    async fn turns_share_one_retry_budget_and_deadline() {
+        let flaky = MockProvider::start().await;
+        flaky.unavailable(5).await;
+        flaky.reply("too late").await;
+        let mut session = flaky.agent();
+        session.enable_quiet();
+        session.config.turn_retries = Some(2);
+        let Err(err) = session.turn("hello").await else { panic!("the turn outlived its retries") };
+        assert!(format!("{:#}", err).contains("used all 2 of its retries"), "{:#}", err);
+        assert_eq!(flaky.requests().await.len(), 3);
+
+        let limited = MockProvider::start().await;
+        limited.rate_limited(1).await;
+        limited.reply("too late").await;
+        let mut late = limited.agent();
+        late.enable_quiet();
+        late.config.retry_deadline_secs = Some(1);
+        let Err(err) = late.turn("hello").await else { panic!("the retry passed the deadline") };
+        assert!(format!("{:#}", err).contains("would pass the turn's 1s retry deadline"), "{:#}", err);
+        assert_eq!(limited.requests().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn side_requests_retry_out_of_the_turns_budget() {
+        let mock = MockProvider::start().await;
+        mock.unavailable(2).await;
+        mock.tool_call("run_command", json!({"command": "echo hi"})).await;
+        mock.rate_limited(1).await;
+        mock.reply("done").await;
+        let mut agent = mock.tool_agent();
+        agent.config.turn_retries = Some(2);
+        agent.config.command_safety = Some(crate::risk::SafetyConfig { model: Some("judge".into()), ..Default::default() });
+        agent.enable_unattended();
+        let reply = agent.turn("say hi").await.unwrap().unwrap();
+        assert_eq!(reply.content, "done");
+        let requests = mock.requests().await;
+        // The judge's 429 is not retried once the turn's two retries are spent
+        assert_eq!(requests.len(), 5, "{:#?}", requests.iter().map(|r| r["model"].clone()).collect::<Vec<_>>());
+        assert_eq!(requests[3]["model"], "judge");
+    }
+}
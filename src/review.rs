@@ -0,0 +1,24 @@
+//! `rustaigent review [base]`: a code review of what the branch changed. Diffs larger than the
+//! context window are reviewed from per-file notes stacked up by [`digest`](crate::digest).
+
+use anyhow::Result;
+use crate::{digest, pr, Agent};
+
+/// What the notes on a diff too large for one prompt should capture
+const NOTES_FOCUS: &str = "For each file, note what changed and any bugs, risky changes, missing tests or unclear code you see, \
+    with line numbers from the hunk headers.";
+
+/// The review prompt for the branch's changes since it left `base`, uncommitted work included
+pub async fn prompt(agent: &Agent, base: Option<String>) -> Result<String> {
+    let base = match base {
+        Some(base) => base,
+        None => pr::default_base()?,
+    };
+    let changes = pr::changes(&base, true)?;
+    let diff = digest::condense(agent, &changes.diff, NOTES_FOCUS).await?;
+    Ok(format!(
+        "Review this change before it is merged into `{}`. Lead with bugs and behavior changes that look unintended, \
+         then security and performance risks, missing tests, and smaller suggestions; name the file and line for each. \
+         Skip what is fine. Finish with a verdict: approve, approve with changes, or request changes.\n\n{}\n\n{}",
+        base, changes.overview(), diff.render()))
+}
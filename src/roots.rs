@@ -0,0 +1,156 @@
+//! Extra project roots: directories besides the working directory that a session works across,
+//! such as a service and its client SDK, each under a name from `[roots]` or `--root`. Tool
+//! calls address their files as `@name/path`; the prefix is replaced by the root's directory
+//! before the policy sees the call, so every root is reachable and the rest stays sandboxed.
+
+use std::path::{Path, PathBuf};
+use anyhow::{Result, bail};
+use serde_json::Value;
+use crate::tools::{self, ToolCall};
+
+/// Named directories of the session, in the order they were added
+#[derive(Default)]
+pub struct Roots {
+    roots: Vec<(String, PathBuf)>,
+}
+
+/// Whether `name` can follow `@` in a path
+fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+impl Roots {
+    /// Add `dir` under `name`; relative directories are taken from the working directory
+    pub fn add(&mut self, name: &str, dir: &Path) -> Result<PathBuf> {
+        if !valid_name(name) {
+            bail!("`{}` is not a root name; use letters, digits, - and _", name);
+        }
+        if self.roots.iter().any(|(n, _)| n == name) {
+            bail!("There is already a root named `{}`", name);
+        }
+        let dir = std::env::current_dir()?.join(dir);
+        if !dir.is_dir() {
+            bail!("Root `{}` is not a directory: {}", name, dir.display());
+        }
+        let dir = dir.canonicalize()?;
+        self.roots.push((name.into(), dir.clone()));
+        Ok(dir)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.roots.is_empty()
+    }
+
+    /// `path` with a leading `@name` replaced by that root's directory; `None` when it has no
+    /// such prefix, or names something that exists as written
+    fn expand_path(&self, path: &str) -> Result<Option<String>> {
+        let Some(rest) = path.strip_prefix('@') else { return Ok(None) };
+        if self.roots.is_empty() || Path::new(path).exists() {
+            return Ok(None);
+        }
+        let (name, tail) = rest.split_once(['/', '\\']).unwrap_or((rest, ""));
+        let Some((_, dir)) = self.roots.iter().find(|(n, _)| n == name) else {
+            let hint = tools::hints::did_you_mean(name, self.roots.iter().map(|(n, _)| n.as_str()));
+            bail!("No root `@{}`; the roots are {}.{}", name, self.names(), hint);
+        };
+        Ok(Some(dir.join(tail).to_string_lossy().into_owned()))
+    }
+
+    /// `@sdk, @api`
+    fn names(&self) -> String {
+        self.roots.iter().map(|(n, _)| format!("@{}", n)).collect::<Vec<_>>().join(", ")
+    }
+
+    /// The call with each `@name/` path pointing into that root, `---`/`+++` lines of patches
+    /// included
+    pub fn expand(&self, call: ToolCall) -> Result<ToolCall> {
+        let mut replaced = Vec::new();
+        for path in call.paths() {
+            if let Some(full) = self.expand_path(path)? {
+                replaced.push((path.to_string(), full));
+            }
+        }
+        if replaced.is_empty() {
+            return Ok(call);
+        }
+        let mut args = call.args();
+        for value in args.as_object_mut().into_iter().flat_map(|o| o.values_mut()) {
+            match value {
+                Value::String(text) => *text = rewrite(text, &replaced),
+                Value::Array(items) => items.iter_mut().filter_map(|v| match v {
+                    Value::String(text) => Some(text),
+                    _ => None,
+                }).for_each(|text| *text = rewrite(text, &replaced)),
+                _ => {}
+            }
+        }
+        ToolCall::parse(call.name(), args)
+    }
+
+    /// Lines for the system prompt telling the model how to reach the roots
+    pub fn describe(&self) -> String {
+        let mut text = String::from("\n\nThis session spans several project roots. Paths without a prefix are in the working directory; address files of the other roots as `@name/path` in tool calls, e.g. `@name/src/lib.rs`. Commands run in the working directory, so `cd` into a root's directory first to build or test it there.");
+        for (name, dir) in &self.roots {
+            text.push_str(&format!("\n- @{}: {}", name, dir.display()));
+        }
+        text
+    }
+}
+
+/// `text` with a path argument replaced by its expansion: the whole value, or the path in a
+/// patch header line
+fn rewrite(text: &str, replaced: &[(String, String)]) -> String {
+    if let Some((_, full)) = replaced.iter().find(|(path, _)| path == text) {
+        return full.clone();
+    }
+    if !text.contains('\n') {
+        return text.to_string();
+    }
+    text.split_inclusive('\n').map(|line| {
+        // A SEARCH/REPLACE block names its file on a line of its own
+        if let Some((_, full)) = replaced.iter().find(|(path, _)| path == line.trim_end()) {
+            return format!("{}{}", full, &line[line.trim_end().len()..]);
+        }
+        let Some(header) = ["--- ", "+++ "].into_iter().find(|h| line.starts_with(h)) else { return line.to_string() };
+        let rest = &line[header.len()..];
+        let (side, path) = match rest.get(..2) {
+            Some(side @ ("a/" | "b/")) => (side, &rest[2..]),
+            _ => ("", rest),
+        };
+        let end = path.find(['\t', '\r', '\n']).unwrap_or(path.len());
+        match replaced.iter().find(|(p, _)| p == &path[..end]) {
+            // Absolute paths carry no a/ or b/ side
+            Some((_, full)) => format!("{}{}{}", header, full, &path[end..]),
+            None => format!("{}{}{}", header, side, path),
+        }
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use crate::mock::MockProvider;
+
+    #[tokio::test]
+    async fn other_roots_are_reached_by_prefix() {
+        let mock = MockProvider::start().await;
+        mock.tool_call("read_file", json!({"path": "@sdk/lib.rs"})).await;
+        mock.tool_call("read_file", json!({"path": "@skd/lib.rs"})).await;
+        mock.reply("done").await;
+        let dir = std::env::temp_dir().join(format!("rustaigent-root-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("lib.rs"), "pub fn client() {}").unwrap();
+        let mut agent = mock.tool_agent();
+        agent.add_root("sdk", &dir).unwrap();
+        agent.turn("Compare the client").await.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(agent.conversation[0].content.contains("\n- @sdk: "));
+        let requests = mock.requests().await;
+        let output = |n: usize| {
+            let messages = requests[n]["messages"].as_array().unwrap();
+            messages[messages.len() - 1]["content"].as_str().unwrap().to_string()
+        };
+        assert!(output(1).contains("pub fn client() {}"));
+        assert!(output(2).contains("No root `@skd`") && output(2).contains("sdk"));
+    }
+}
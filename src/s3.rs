@@ -0,0 +1,123 @@
+//! Minimal S3 client for the sync backend and the shared codebase index: GET, PUT and HEAD of
+//! objects under a prefix, signed with AWS Signature Version 4. Any S3-compatible store works,
+//! Google Cloud Storage included through its interoperability endpoint and HMAC keys.
+
+use std::env;
+use sha2::{Digest, Sha256};
+use hmac::{Hmac, KeyInit, Mac};
+use reqwest::{Client, Method, StatusCode};
+use anyhow::{Result, Context, bail};
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn sha256(data: &[u8]) -> String {
+    hex(&Sha256::digest(data))
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// A bucket and key prefix, with the credentials from AWS_ACCESS_KEY_ID, AWS_SECRET_ACCESS_KEY
+/// and AWS_SESSION_TOKEN
+pub struct Bucket {
+    client: Client,
+    endpoint: String,
+    bucket: String,
+    prefix: String,
+    region: String,
+    access_key: String,
+    secret_key: String,
+    token: Option<String>,
+}
+
+impl Bucket {
+    /// `url` is `s3://bucket/prefix`, or `gs://bucket/prefix` for Google Cloud Storage;
+    /// `setting` names the config key it came from, for errors
+    pub fn open(url: &str, endpoint: Option<&str>, region: Option<&str>, client: &Client, setting: &str) -> Result<Self> {
+        let (rest, google) = match (url.strip_prefix("s3://"), url.strip_prefix("gs://")) {
+            (Some(rest), _) => (rest, false),
+            (_, Some(rest)) => (rest, true),
+            _ => bail!("`{}` must look like s3://bucket/prefix or gs://bucket/prefix (found `{}`)", setting, url),
+        };
+        let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+        let region = region.map(String::from).or_else(|| env::var("AWS_REGION").ok())
+            .unwrap_or_else(|| if google { "auto" } else { "us-east-1" }.into());
+        let endpoint = match endpoint {
+            Some(endpoint) => endpoint.to_string(),
+            None if google => "https://storage.googleapis.com".into(),
+            None => format!("https://s3.{}.amazonaws.com", region),
+        };
+        let keys = if google { "the HMAC key of a Cloud Storage service account" } else { "S3 access" };
+        Ok(Bucket {
+            client: client.clone(),
+            endpoint: endpoint.trim_end_matches('/').into(),
+            bucket: bucket.into(),
+            prefix: prefix.trim_matches('/').into(),
+            region,
+            access_key: env::var("AWS_ACCESS_KEY_ID").with_context(|| format!("Set AWS_ACCESS_KEY_ID to {} for `{}`", keys, setting))?,
+            secret_key: env::var("AWS_SECRET_ACCESS_KEY").with_context(|| format!("Set AWS_SECRET_ACCESS_KEY to {} for `{}`", keys, setting))?,
+            token: env::var("AWS_SESSION_TOKEN").ok(),
+        })
+    }
+
+    /// Request for the object `name` under the prefix
+    fn request(&self, method: Method, name: &str, body: &[u8]) -> reqwest::RequestBuilder {
+        let key = if self.prefix.is_empty() { name.to_string() } else { format!("{}/{}", self.prefix, name) };
+        let path = format!("/{}/{}", self.bucket, key);
+        let host = self.endpoint.split_once("://").map_or(self.endpoint.as_str(), |(_, h)| h);
+        let now = chrono::Utc::now();
+        let (date, stamp) = (now.format("%Y%m%d").to_string(), now.format("%Y%m%dT%H%M%SZ").to_string());
+        let payload = sha256(body);
+        let mut headers = vec![("host", host.to_string()), ("x-amz-content-sha256", payload.clone()), ("x-amz-date", stamp.clone())];
+        if let Some(token) = &self.token {
+            headers.push(("x-amz-security-token", token.clone()));
+        }
+        let canonical_headers: String = headers.iter().map(|(k, v)| format!("{}:{}\n", k, v)).collect();
+        let signed = headers.iter().map(|(k, _)| *k).collect::<Vec<_>>().join(";");
+        let request = format!("{}\n{}\n\n{}\n{}\n{}", method, path, canonical_headers, signed, payload);
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}", stamp, scope, sha256(request.as_bytes()));
+        let signing_key = ["s3", "aws4_request"].iter()
+            .fold(hmac(&hmac(format!("AWS4{}", self.secret_key).as_bytes(), &date), &self.region), |key, part| hmac(&key, part));
+        let authorization = format!("AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}", self.access_key, scope, signed, hex(&hmac(&signing_key, &to_sign)));
+        let mut builder = self.client.request(method, format!("{}{}", self.endpoint, path)).header("authorization", authorization);
+        for (k, v) in headers.into_iter().filter(|(k, _)| *k != "host") {
+            builder = builder.header(k, v);
+        }
+        builder
+    }
+
+    /// The object's content, `None` when there is no such object
+    pub async fn get(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        let response = self.request(Method::GET, name, b"").send().await?;
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => Ok(Some(response.bytes().await?.to_vec())),
+            status => bail!("Fetching {} from bucket {} returned {}", name, self.bucket, status),
+        }
+    }
+
+    pub async fn put(&self, name: &str, data: Vec<u8>) -> Result<()> {
+        let status = self.request(Method::PUT, name, &data).body(data).send().await?.status();
+        if !status.is_success() {
+            bail!("Uploading {} to bucket {} returned {}", name, self.bucket, status);
+        }
+        Ok(())
+    }
+
+    /// Whether the object exists, without downloading it
+    pub async fn exists(&self, name: &str) -> Result<bool> {
+        match self.request(Method::HEAD, name, b"").send().await?.status() {
+            StatusCode::NOT_FOUND => Ok(false),
+            status if status.is_success() => Ok(true),
+            status => bail!("Looking up {} in bucket {} returned {}", name, self.bucket, status),
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use std::{env, fs, path::{Path, PathBuf}};
+use anyhow::{Result, Context, bail};
+use reqwest::Client;
+use serde_json::Value;
+use crate::tools::{archive, download};
+
+const REPO: &str = "makalin/RustAIgent";
+
+/// Minisign public key baked in at build time; when present, release signatures are mandatory
+const UPDATE_PUBKEY: Option<&str> = option_env!("RUSTAIGENT_UPDATE_PUBKEY");
+
+fn parse_version(v: &str) -> Vec<u64> {
+    v.trim_start_matches('v').split(['.', '-']).map_while(|p| p.parse().ok()).collect()
+}
+
+/// Release asset for this platform, e.g. `rustaigent-x86_64-linux.tar.gz`
+fn find_asset(assets: &[Value]) -> Option<&Value> {
+    let (arch, os) = (env::consts::ARCH, env::consts::OS);
+    assets.iter().find(|a| {
+        let name = a["name"].as_str().unwrap_or_default().to_lowercase();
+        name.contains(arch) && name.contains(os) && !name.ends_with(".sha256") && !name.ends_with(".minisig")
+    })
+}
+
+async fn fetch_text(client: &Client, url: &str) -> Result<String> {
+    Ok(client.get(url).send().await?.error_for_status()?.text().await?)
+}
+
+/// Expected SHA-256 from `<asset>.sha256` or a `SHA256SUMS` file in the release
+async fn expected_checksum(client: &Client, assets: &[Value], name: &str) -> Result<String> {
+    let url_of = |n: &str| assets.iter().find(|a| a["name"] == n).and_then(|a| a["browser_download_url"].as_str()).map(String::from);
+    if let Some(url) = url_of(&format!("{}.sha256", name)) {
+        return fetch_text(client, &url).await?.split_whitespace().next().map(String::from).context("Empty checksum file");
+    }
+    if let Some(url) = url_of("SHA256SUMS") {
+        return listed_checksum(&fetch_text(client, &url).await?, name);
+    }
+    bail!("Release has no checksum for {}; refusing to install an unverified binary", name)
+}
+
+/// `name`'s sum in `sha256sum` output, text (`  name`) or binary (` *name`) mode
+fn listed_checksum(sums: &str, name: &str) -> Result<String> {
+    sums.lines()
+        .filter_map(|l| l.split_once(char::is_whitespace))
+        .find(|(_, file)| file.trim().trim_start_matches('*') == name)
+        .map(|(sum, _)| sum.to_string())
+        .with_context(|| format!("{} is not listed in SHA256SUMS", name))
+}
+
+fn verify_signature(artifact: &Path, signature: &str, pubkey: &str) -> Result<()> {
+    let pk = minisign_verify::PublicKey::from_base64(pubkey).context("Invalid update public key")?;
+    let sig = minisign_verify::Signature::decode(signature).context("Invalid release signature")?;
+    pk.verify(&fs::read(artifact)?, &sig, false).context("Release signature does not match")?;
+    Ok(())
+}
+
+/// Locate the executable inside an extracted archive
+fn find_binary(dir: &Path) -> Option<PathBuf> {
+    let exe_name = if cfg!(windows) { "rustaigent.exe" } else { "rustaigent" };
+    for entry in fs::read_dir(dir).ok()?.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            if let Some(found) = find_binary(&path) {
+                return Some(found);
+            }
+        } else if path.file_name().is_some_and(|n| n == exe_name) {
+            return Some(path);
+        }
+    }
+    None
+}
+
+/// Swap the running executable for `new_bin`, keeping the old one until the rename succeeds
+fn replace_exe(new_bin: &Path) -> Result<PathBuf> {
+    let current = env::current_exe()?;
+    let staged = current.with_extension("new");
+    fs::copy(new_bin, &staged).with_context(|| format!("Cannot write next to {}", current.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&staged, fs::Permissions::from_mode(0o755))?;
+    }
+    let backup = current.with_extension("old");
+    fs::rename(&current, &backup)?;
+    if let Err(err) = fs::rename(&staged, &current) {
+        fs::rename(&backup, &current).ok();
+        return Err(err.into());
+    }
+    fs::remove_file(&backup).ok();
+    Ok(current)
+}
+
+/// Check GitHub for a newer release and, unless `check_only`, install it
+pub async fn run(check_only: bool) -> Result<()> {
+    let client = Client::builder().user_agent(concat!("rustaigent/", env!("CARGO_PKG_VERSION"))).build()?;
+    let release: Value = client.get(format!("https://api.github.com/repos/{}/releases/latest", REPO))
+        .send().await?
+        .error_for_status()
+        .context("Failed to query GitHub releases")?
+        .json().await?;
+    let tag = release["tag_name"].as_str().context("Release has no tag")?;
+    let current = env!("CARGO_PKG_VERSION");
+    if parse_version(tag) <= parse_version(current) {
+        println!("rustaigent {} is up to date (latest release: {})", current, tag);
+        return Ok(());
+    }
+    println!("Update available: {} -> {}", current, tag);
+    if check_only {
+        return Ok(());
+    }
+
+    let assets = release["assets"].as_array().cloned().unwrap_or_default();
+    let asset = find_asset(&assets)
+        .with_context(|| format!("Release {} has no build for {}-{}", tag, env::consts::ARCH, env::consts::OS))?;
+    let name = asset["name"].as_str().unwrap_or_default().to_string();
+    let url = asset["browser_download_url"].as_str().context("Asset has no download URL")?;
+    let checksum = expected_checksum(&client, &assets, &name).await?;
+
+    // A fresh private directory, so nobody can swap the artifact between verifying and installing it
+    let tmp = tempfile::Builder::new().prefix("rustaigent-update-").tempdir().context("Failed to create a download directory")?;
+    let workdir = tmp.path();
+    let result = async {
+        let artifact = workdir.join(&name);
+        download::download(&Default::default(), url, workdir, &name, Some(&checksum), download::Progress::Redraw).await?;
+
+        if let Some(pubkey) = UPDATE_PUBKEY {
+            let sig_url = assets.iter()
+                .find(|a| a["name"] == format!("{}.minisig", name))
+                .and_then(|a| a["browser_download_url"].as_str())
+                .context("Release is not signed; refusing to install")?;
+            verify_signature(&artifact, &fetch_text(&client, sig_url).await?, pubkey)?;
+            println!("Signature verified");
+        } else {
+            // The checksum comes from the same release as the binary, so it catches a corrupted
+            // download but not a tampered release
+            eprintln!("This build has no update key (RUSTAIGENT_UPDATE_PUBKEY), so {} is installed unauthenticated: only its checksum from the same release was checked", name);
+        }
+
+        let lower = name.to_lowercase();
+        let binary = if lower.ends_with(".zip") || lower.ends_with(".tar.gz") || lower.ends_with(".tgz") {
+            let out = workdir.join("extracted");
+            archive::extract(workdir, &name, "extracted")?;
+            find_binary(&out).context("Archive does not contain a rustaigent binary")?
+        } else {
+            artifact
+        };
+        replace_exe(&binary)
+    }.await;
+    drop(tmp);
+
+    println!("Installed {} to {}", tag, result?.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use serde_json::json;
+    use super::{find_asset, find_binary, listed_checksum, parse_version};
+
+    #[test]
+    fn versions_compare_numerically() {
+        assert_eq!(parse_version("v1.10.0"), [1, 10, 0]);
+        assert!(parse_version("v1.10.0") > parse_version("1.9.3"));
+        assert!(parse_version("0.5.0-rc.1") == parse_version("0.5.0"));
+        assert!(parse_version("v0.5.1") > parse_version("0.5"));
+    }
+
+    #[test]
+    fn checksums_are_found_by_exact_file_name() {
+        let sums = "aaa  rustaigent-x86_64-linux.tar.gz.minisig\nbbb *rustaigent-x86_64-linux.tar.gz\nccc  rustaigent-aarch64-macos.zip\n";
+        assert_eq!(listed_checksum(sums, "rustaigent-x86_64-linux.tar.gz").unwrap(), "bbb");
+        assert_eq!(listed_checksum(sums, "rustaigent-aarch64-macos.zip").unwrap(), "ccc");
+        assert!(listed_checksum(sums, "rustaigent-x86_64-windows.zip").unwrap_err().to_string().contains("not listed"));
+    }
+
+    #[test]
+    fn the_asset_for_this_platform_skips_checksums_and_signatures() {
+        let (arch, os) = (std::env::consts::ARCH, std::env::consts::OS);
+        let build = format!("rustaigent-{}-{}.tar.gz", arch, os);
+        let assets = vec![
+            json!({ "name": format!("{}.sha256", build) }),
+            json!({ "name": format!("{}.minisig", build) }),
+            json!({ "name": "rustaigent-other-platform.zip" }),
+            json!({ "name": build.to_uppercase() }),
+        ];
+        assert_eq!(find_asset(&assets).unwrap()["name"], build.to_uppercase());
+        assert!(find_asset(&assets[..3]).is_none());
+    }
+
+    #[test]
+    fn the_binary_is_found_in_nested_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        let exe = if cfg!(windows) { "rustaigent.exe" } else { "rustaigent" };
+        fs::create_dir_all(dir.path().join("release").join("bin")).unwrap();
+        fs::write(dir.path().join("release").join("README.md"), "").unwrap();
+        assert!(find_binary(dir.path()).is_none());
+        fs::write(dir.path().join("release").join("bin").join(exe), "").unwrap();
+        assert_eq!(find_binary(dir.path()).unwrap(), dir.path().join("release").join("bin").join(exe));
+    }
+}
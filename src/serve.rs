@@ -0,0 +1,591 @@
+//! `rustaigent serve`: a JSON API over HTTP that lets a small team share one deployment. Each
+//! user signs in with an API key or OIDC token and gets their own sessions, tool permissions,
+//! budget and workspace directory. A WebSocket per session carries its events and lets a browser
+//! answer approval prompts; the chat page served at `/` is built on both. `/v1/chat/completions`
+//! speaks OpenAI's API for editors and other clients of it.
+
+use std::{collections::HashMap, convert::Infallible, path::{Path, PathBuf}, sync::Arc};
+use anyhow::{Result, Context, anyhow};
+use bytes::Bytes;
+use http_body_util::{combinators::BoxBody, BodyExt, Full, Limited, StreamBody};
+use hyper::{body::{Frame, Incoming}, header, Method, Request, Response, StatusCode};
+use serde_json::{json, Value};
+use futures::{SinkExt, StreamExt};
+use hyper_util::rt::TokioIo;
+use tokio::{net::TcpListener, sync::Mutex};
+use tokio_tungstenite::{tungstenite::{handshake::derive_accept_key, protocol::Role, Message}, WebSocketStream};
+use tracing::{debug, warn};
+use crate::{compat, config, events::{AgentEvent, Pending}, handle::AgentHandle, http, paths, stats, tenants::{Ledger, ServeConfig, Tenants, User}, tools, Agent, AgentBuilder};
+
+const DEFAULT_BIND: &str = "127.0.0.1:8080";
+
+/// Sessions one user may keep open when `max_sessions` is unset
+const DEFAULT_MAX_SESSIONS: usize = 4;
+
+/// Largest request body accepted
+const MAX_BODY: usize = 1 << 20;
+
+/// Tools that start processes outside the path sandbox, offered to a user only when their
+/// `tools` list names them
+const UNCONFINED_TOOLS: &[&str] = &["run_command", "eval_code", "run_tests", "terraform_plan"];
+
+/// Server-sent events buffered for a streaming chat completion before the relay waits for the client
+const SSE_FRAMES: usize = 16;
+
+type Body = BoxBody<Bytes, Infallible>;
+
+/// The browser chat, a single page with its styles and script inline
+const WEB_UI: &str = include_str!("web/index.html");
+
+/// One open conversation of a user
+struct Session {
+    handle: AgentHandle,
+    stats: Arc<std::sync::Mutex<stats::SessionStats>>,
+    created: u64,
+}
+
+struct Server {
+    tenants: Tenants,
+    ledger: Arc<std::sync::Mutex<Ledger>>,
+    /// Sessions by user, then by id
+    sessions: Mutex<HashMap<String, HashMap<String, Session>>>,
+    workspaces: PathBuf,
+    max_sessions: usize,
+    /// Where the server was started; session agents read their config from here, never from a workspace
+    home: PathBuf,
+}
+
+/// Listen on `bind`, or the configured address, until interrupted; `port` replaces the address's port
+pub async fn run(bind: Option<String>, port: Option<u16>) -> Result<()> {
+    let config = config::Config::load();
+    let cfg = config.serve.clone().context("Add a [serve] table with at least one [serve.users.<name>] to the config")?;
+    cfg.check()?;
+    if cfg.users.as_ref().is_none_or(|u| u.is_empty()) {
+        anyhow::bail!("No users configured; add a [serve.users.<name>] table");
+    }
+    let server = Arc::new(Server::new(&cfg, http::client(config.http.as_ref()))?);
+    let mut bind = bind.or(cfg.bind).unwrap_or_else(|| DEFAULT_BIND.into());
+    if let Some(port) = port {
+        bind = format!("{}:{}", bind.rsplit_once(':').map_or(bind.as_str(), |(host, _)| host), port);
+    }
+    let listener = TcpListener::bind(&bind).await.with_context(|| format!("Cannot listen on {}", bind))?;
+    eprintln!("Serving {} user(s) on http://{}", server.tenants.users.len(), listener.local_addr()?);
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let server = Arc::clone(&server);
+        tokio::spawn(async move {
+            let service = hyper::service::service_fn(move |req| {
+                let server = Arc::clone(&server);
+                async move { Ok::<_, Infallible>(server.handle(req).await) }
+            });
+            let io = TokioIo::new(stream);
+            if let Err(err) = hyper::server::conn::http1::Builder::new().serve_connection(io, service).with_upgrades().await {
+                debug!("connection from {} ended: {}", peer, err);
+            }
+        });
+    }
+}
+
+/// An error answer: `{"error": "..."}` with the given status
+fn error(status: StatusCode, message: impl std::fmt::Display) -> Response<Body> {
+    reply(status, json!({ "error": message.to_string() }))
+}
+
+fn reply(status: StatusCode, body: Value) -> Response<Body> {
+    let mut resp = Response::new(Full::new(Bytes::from(body.to_string())).boxed());
+    *resp.status_mut() = status;
+    resp.headers_mut().insert(header::CONTENT_TYPE, header::HeaderValue::from_static("application/json"));
+    resp
+}
+
+fn session_id() -> Result<String> {
+    let mut bytes = [0u8; 12];
+    getrandom::fill(&mut bytes).map_err(|err| anyhow!("No OS randomness: {}", err))?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+impl Server {
+    fn new(cfg: &ServeConfig, client: reqwest::Client) -> Result<Self> {
+        Ok(Server {
+            tenants: Tenants::new(cfg, client),
+            ledger: Arc::new(std::sync::Mutex::new(Ledger::load()?)),
+            sessions: Mutex::new(HashMap::new()),
+            workspaces: cfg.workspaces.clone().unwrap_or_else(|| paths::data_dir().join("workspaces")),
+            max_sessions: cfg.max_sessions.unwrap_or(DEFAULT_MAX_SESSIONS),
+            home: std::env::current_dir()?,
+        })
+    }
+
+    async fn handle(&self, req: Request<Incoming>) -> Response<Body> {
+        let path: Vec<String> = req.uri().path().split('/').filter(|s| !s.is_empty()).map(String::from).collect();
+        let path: Vec<&str> = path.iter().map(String::as_str).collect();
+        if req.method() == Method::GET && path == ["health"] {
+            return reply(StatusCode::OK, json!({ "ok": true }));
+        }
+        if req.method() == Method::GET && path.is_empty() {
+            let mut resp = Response::new(Full::new(Bytes::from_static(WEB_UI.as_bytes())).boxed());
+            resp.headers_mut().insert(header::CONTENT_TYPE, header::HeaderValue::from_static("text/html; charset=utf-8"));
+            return resp;
+        }
+        // Browsers can't set headers on a WebSocket, so the token may come as `?access_token=`
+        let query = req.uri().query().unwrap_or_default();
+        let token = req.headers().get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .map(str::trim)
+            .map(String::from)
+            .or_else(|| url::form_urlencoded::parse(query.as_bytes()).find(|(k, _)| k == "access_token").map(|(_, v)| v.into_owned()));
+        let Some(token) = token else {
+            return error(StatusCode::UNAUTHORIZED, "send `Authorization: Bearer <key or token>`");
+        };
+        let user = match self.tenants.authenticate(&token).await {
+            Ok(Some(user)) => user,
+            Ok(None) => return error(StatusCode::UNAUTHORIZED, "unknown key or token"),
+            Err(err) => {
+                warn!("sign-in failed: {:#}", err);
+                return error(StatusCode::BAD_GATEWAY, "the identity provider could not be reached");
+            }
+        };
+        let result = match (req.method().clone(), path.as_slice()) {
+            (Method::GET, ["capabilities"]) => self.capabilities(&user).await,
+            (Method::GET, ["v1", "me"]) => Ok(self.me(&user).await),
+            (Method::GET, ["v1", "sessions"]) => Ok(self.list(&user).await),
+            (Method::POST, ["v1", "sessions"]) => self.open(&user).await,
+            (Method::POST, ["v1", "sessions", id, "messages"]) => {
+                let id = id.to_string();
+                match read_json(req).await {
+                    Ok(body) => self.message(&user, &id, &body).await,
+                    Err(err) => Ok(error(StatusCode::BAD_REQUEST, format!("{:#}", err))),
+                }
+            }
+            (Method::GET, ["v1", "sessions", id, "messages"]) => Ok(self.transcript(&user, id).await),
+            (Method::GET, ["v1", "sessions", id, "events"]) => self.subscribe(&user, id, req).await,
+            (Method::DELETE, ["v1", "sessions", id]) => Ok(self.close(&user, id).await),
+            (Method::GET, ["v1", "models"]) => Ok(reply(StatusCode::OK, json!({
+                "object": "list",
+                "data": [{ "id": compat::MODEL, "object": "model", "created": 0, "owned_by": "rustaigent" }],
+            }))),
+            (Method::POST, ["v1", "chat", "completions"]) => match read_json(req).await {
+                Ok(body) => self.chat(&user, &body).await,
+                Err(err) => Ok(reply(StatusCode::BAD_REQUEST, compat::error(&format!("{:#}", err), "invalid_request_error"))),
+            },
+            _ => Ok(error(StatusCode::NOT_FOUND, "no such endpoint")),
+        };
+        result.unwrap_or_else(|err| {
+            warn!("request of user {} failed: {:#}", user, err);
+            error(StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", err))
+        })
+    }
+
+    fn user(&self, name: &str) -> &User {
+        &self.tenants.users[name]
+    }
+
+    /// What is left of the user's monthly budget, so a turn cannot overrun it
+    fn remaining_budget(&self, name: &str) -> Option<f64> {
+        let spent = self.ledger.lock().unwrap().current(name).cost_usd;
+        self.user(name).monthly_budget_usd.map(|budget| (budget - spent).max(0.0))
+    }
+
+    async fn me(&self, name: &str) -> Response<Body> {
+        let user = self.user(name);
+        let usage = self.ledger.lock().unwrap().current(name);
+        let sessions = self.sessions.lock().await.get(name).map_or(0, HashMap::len);
+        reply(StatusCode::OK, json!({
+            "user": name,
+            "sessions": sessions,
+            "max_sessions": self.max_sessions,
+            "month": usage.month,
+            "cost_usd": usage.cost_usd,
+            "monthly_budget_usd": user.monthly_budget_usd,
+            "requests_today": usage.requests,
+            "daily_requests": user.daily_requests,
+        }))
+    }
+
+    async fn list(&self, name: &str) -> Response<Body> {
+        let sessions = self.sessions.lock().await;
+        let list: Vec<Value> = sessions.get(name).into_iter().flatten()
+            .map(|(id, s)| json!({ "id": id, "created": s.created, "cost_usd": s.stats.lock().unwrap().cost() }))
+            .collect();
+        reply(StatusCode::OK, json!({ "sessions": list }))
+    }
+
+    /// The user's workspace, created on first use
+    fn workspace(&self, name: &str) -> Result<PathBuf> {
+        let dir = self.workspaces.join(name);
+        std::fs::create_dir_all(&dir).with_context(|| format!("Failed to create workspace {}", dir.display()))?;
+        Ok(dir.canonicalize()?)
+    }
+
+    /// A fresh agent for the user, confined to their tools and workspace
+    async fn agent(&self, name: &str) -> Result<Agent> {
+        let workspace = self.workspace(name)?;
+        let mut agent = {
+            // Built from the server's directory so a workspace can't carry its own config
+            paths::in_dir(&self.home, || AgentBuilder::new().build())?
+        };
+        confine(&mut agent, self.user(name), &workspace);
+        Ok(agent)
+    }
+
+    /// The handshake for a session of the user: their tools, under their permissions
+    async fn capabilities(&self, name: &str) -> Result<Response<Body>> {
+        Ok(reply(StatusCode::OK, self.agent(name).await?.handshake()))
+    }
+
+    async fn open(&self, name: &str) -> Result<Response<Body>> {
+        if self.sessions.lock().await.get(name).is_some_and(|s| s.len() >= self.max_sessions) {
+            return Ok(error(StatusCode::TOO_MANY_REQUESTS, format!("at most {} open sessions; close one first", self.max_sessions)));
+        }
+        let agent = self.agent(name).await?;
+        let stats = Arc::clone(&agent.stats);
+        let id = session_id()?;
+        let session = Session { handle: AgentHandle::new(agent), stats, created: stats::now_secs() };
+        let mut sessions = self.sessions.lock().await;
+        let open = sessions.entry(name.to_string()).or_default();
+        if open.len() >= self.max_sessions {
+            return Ok(error(StatusCode::TOO_MANY_REQUESTS, format!("at most {} open sessions; close one first", self.max_sessions)));
+        }
+        open.insert(id.clone(), session);
+        Ok(reply(StatusCode::CREATED, json!({ "id": id })))
+    }
+
+    async fn message(&self, name: &str, id: &str, body: &Value) -> Result<Response<Body>> {
+        let Some(content) = body["content"].as_str() else {
+            return Ok(error(StatusCode::BAD_REQUEST, "expected {\"content\": \"...\"}"));
+        };
+        let (handle, stats) = match self.sessions.lock().await.get(name).and_then(|s| s.get(id)) {
+            Some(session) => (session.handle.clone(), Arc::clone(&session.stats)),
+            None => return Ok(error(StatusCode::NOT_FOUND, "no such session")),
+        };
+        if let Some(reason) = self.ledger.lock().unwrap().refusal(name, self.user(name)) {
+            return Ok(error(StatusCode::TOO_MANY_REQUESTS, reason));
+        }
+        handle.set_turn_budget(self.remaining_budget(name)).await;
+        let before = totals(&stats);
+        let answer = handle.send(content).await;
+        let after = totals(&stats);
+        let cost = after.2 - before.2;
+        self.ledger.lock().unwrap().record(name, cost);
+        let usage = json!({ "prompt_tokens": after.0 - before.0, "completion_tokens": after.1 - before.1, "cost_usd": cost });
+        Ok(match answer {
+            Ok(Some(answer)) => reply(StatusCode::OK, json!({ "answer": answer, "usage": usage })),
+            Ok(None) => error(StatusCode::UNPROCESSABLE_ENTITY, "the guardrails held the prompt back"),
+            Err(err) => error(StatusCode::BAD_GATEWAY, format!("{:#}", err)),
+        })
+    }
+
+    /// The prompts and answers of a session so far, for a client picking it up again
+    async fn transcript(&self, name: &str, id: &str) -> Response<Body> {
+        let Some(handle) = self.sessions.lock().await.get(name).and_then(|s| s.get(id)).map(|s| s.handle.clone()) else {
+            return error(StatusCode::NOT_FOUND, "no such session");
+        };
+        let history = handle.history().await;
+        let messages: Vec<Value> = history.iter()
+            .filter(|m| (m.role == "user" || m.role == "assistant") && m.name.is_none() && m.function_call.is_none() && !m.content.is_empty())
+            .map(|m| json!({ "role": m.role, "content": m.content }))
+            .collect();
+        reply(StatusCode::OK, json!({ "messages": messages }))
+    }
+
+    /// Switch the connection to a WebSocket that carries the session's events from now on
+    async fn subscribe(&self, name: &str, id: &str, req: Request<Incoming>) -> Result<Response<Body>> {
+        let Some(handle) = self.sessions.lock().await.get(name).and_then(|s| s.get(id)).map(|s| s.handle.clone()) else {
+            return Ok(error(StatusCode::NOT_FOUND, "no such session"));
+        };
+        let upgrade = req.headers().get(header::UPGRADE).and_then(|v| v.to_str().ok()).is_some_and(|v| v.eq_ignore_ascii_case("websocket"));
+        let Some(key) = req.headers().get(header::SEC_WEBSOCKET_KEY).filter(|_| upgrade) else {
+            return Ok(error(StatusCode::UPGRADE_REQUIRED, "connect with a WebSocket client"));
+        };
+        let accept = derive_accept_key(key.as_bytes());
+        let workspace = self.workspace(name)?;
+        tokio::spawn(async move {
+            match hyper::upgrade::on(req).await {
+                Ok(upgraded) => {
+                    let socket = WebSocketStream::from_raw_socket(TokioIo::new(upgraded), Role::Server, None).await;
+                    if let Err(err) = relay(handle, socket, workspace).await {
+                        debug!("event stream ended: {:#}", err);
+                    }
+                }
+                Err(err) => debug!("WebSocket upgrade failed: {}", err),
+            }
+        });
+        let mut resp = Response::new(Full::default().boxed());
+        *resp.status_mut() = StatusCode::SWITCHING_PROTOCOLS;
+        let headers = resp.headers_mut();
+        headers.insert(header::UPGRADE, header::HeaderValue::from_static("websocket"));
+        headers.insert(header::CONNECTION, header::HeaderValue::from_static("upgrade"));
+        if let Ok(accept) = header::HeaderValue::from_str(&accept) {
+            headers.insert(header::SEC_WEBSOCKET_ACCEPT, accept);
+        }
+        Ok(resp)
+    }
+
+    /// `/v1/chat/completions`: one turn, tools included, of a fresh agent that continues the
+    /// client's conversation. Nobody can approve calls here, so those that would ask are declined.
+    async fn chat(&self, name: &str, body: &Value) -> Result<Response<Body>> {
+        let request = match compat::Completion::parse(body) {
+            Ok(request) => request,
+            Err(err) => return Ok(reply(StatusCode::BAD_REQUEST, compat::error(&format!("{:#}", err), "invalid_request_error"))),
+        };
+        if let Some(reason) = self.ledger.lock().unwrap().refusal(name, self.user(name)) {
+            return Ok(reply(StatusCode::TOO_MANY_REQUESTS, compat::error(&reason, "rate_limit_error")));
+        }
+        let mut agent = self.agent(name).await?;
+        agent.set_turn_budget(self.remaining_budget(name));
+        if request.model.is_some() {
+            agent.model = request.model;
+        }
+        if let Some(temperature) = request.temperature {
+            agent.temperature = temperature;
+        }
+        if let Some(max_tokens) = request.max_tokens {
+            agent.max_tokens = max_tokens;
+        }
+        for message in request.history {
+            agent.conversation.push(message);
+        }
+        let (id, created, model) = (format!("chatcmpl-{}", session_id()?), stats::now_secs(), agent.model_name());
+        let stats = Arc::clone(&agent.stats);
+        let (ledger, name, prompt) = (Arc::clone(&self.ledger), name.to_string(), request.prompt);
+        if !request.stream {
+            let answer = agent.turn(&prompt).await;
+            let (prompt_tokens, completion_tokens, cost) = totals(&stats);
+            ledger.lock().unwrap().record(&name, cost);
+            return Ok(match answer {
+                Ok(Some(answer)) => reply(StatusCode::OK, compat::completion(&id, created, &model, &answer.content, compat::usage(prompt_tokens, completion_tokens))),
+                Ok(None) => reply(StatusCode::BAD_REQUEST, compat::error("the guardrails held the prompt back", "invalid_request_error")),
+                Err(err) => reply(StatusCode::BAD_GATEWAY, compat::error(&format!("{:#}", err), "api_error")),
+            });
+        }
+
+        agent.streaming = true;
+        let mut events = agent.events();
+        // The turn runs apart from the relay so that approvals, which block it, can be declined
+        let turn = tokio::spawn(async move { agent.turn(&prompt).await.map(|answer| answer.is_some()) });
+        // A client reading slowly holds the relay up, and the answer text waits in the agent's
+        // event queue, merged into one piece, rather than piling up here chunk by chunk
+        let (mut tx, rx) = futures::channel::mpsc::channel::<Result<Frame<Bytes>, Infallible>>(SSE_FRAMES);
+        let include_usage = request.include_usage;
+        tokio::spawn(async move {
+            send_event(&mut tx, compat::chunk(&id, created, &model, json!({ "role": "assistant", "content": "" }), None).to_string()).await;
+            // Ends when the turn is over and the agent, with its end of the channel, is dropped
+            while let Some(event) = events.recv().await {
+                match event {
+                    AgentEvent::Token(text) => send_event(&mut tx, compat::chunk(&id, created, &model, json!({ "content": text }), None).to_string()).await,
+                    AgentEvent::ApprovalNeeded { respond, .. } => respond.deny(),
+                    _ => {}
+                }
+            }
+            let (prompt_tokens, completion_tokens, cost) = totals(&stats);
+            ledger.lock().unwrap().record(&name, cost);
+            match turn.await.map_err(anyhow::Error::from).and_then(|result| result) {
+                Ok(true) => {
+                    send_event(&mut tx, compat::chunk(&id, created, &model, json!({}), Some("stop")).to_string()).await;
+                    if include_usage {
+                        send_event(&mut tx, json!({ "id": id, "object": "chat.completion.chunk", "created": created, "model": model, "choices": [], "usage": compat::usage(prompt_tokens, completion_tokens) }).to_string()).await;
+                    }
+                }
+                Ok(false) => send_event(&mut tx, compat::error("the guardrails held the prompt back", "invalid_request_error").to_string()).await,
+                Err(err) => send_event(&mut tx, compat::error(&format!("{:#}", err), "api_error").to_string()).await,
+            }
+            send_event(&mut tx, "[DONE]".into()).await;
+        });
+        let mut resp = Response::new(BodyExt::boxed(StreamBody::new(rx)));
+        resp.headers_mut().insert(header::CONTENT_TYPE, header::HeaderValue::from_static("text/event-stream"));
+        resp.headers_mut().insert(header::CACHE_CONTROL, header::HeaderValue::from_static("no-cache"));
+        Ok(resp)
+    }
+
+    async fn close(&self, name: &str, id: &str) -> Response<Body> {
+        let removed = self.sessions.lock().await.get_mut(name).and_then(|s| s.remove(id));
+        match removed {
+            Some(_) => reply(StatusCode::OK, json!({ "closed": id })),
+            None => error(StatusCode::NOT_FOUND, "no such session"),
+        }
+    }
+}
+
+/// Send one server-sent event, waiting while the client is behind; a client that left is ignored
+async fn send_event(tx: &mut futures::channel::mpsc::Sender<Result<Frame<Bytes>, Infallible>>, data: String) {
+    tx.send(Ok(Frame::data(Bytes::from(format!("data: {}\n\n", data))))).await.ok();
+}
+
+/// Prompt tokens, completion tokens and cost so far
+fn totals(stats: &std::sync::Mutex<stats::SessionStats>) -> (u64, u64, f64) {
+    let stats = stats.lock().unwrap();
+    let (prompt, completion) = stats.models.values().fold((0, 0), |(p, c), u| (p + u.prompt_tokens, c + u.completion_tokens));
+    (prompt, completion, stats.cost())
+}
+
+/// State of one event stream: prompts awaiting the client's reply and the change the next
+/// approval is about. The C API frames its events the same way.
+pub(crate) struct Stream {
+    workspace: PathBuf,
+    waiting: HashMap<u64, Pending>,
+    next: u64,
+    preview: Option<String>,
+}
+
+impl Stream {
+    pub(crate) fn new(workspace: PathBuf) -> Self {
+        Stream { workspace, waiting: HashMap::new(), next: 0, preview: None }
+    }
+
+    /// The event as a JSON frame; prompts get an id the client's reply refers to, and approvals of
+    /// file changes carry them as a unified diff
+    pub(crate) fn frame(&mut self, event: AgentEvent) -> Value {
+        match event {
+            AgentEvent::Token(text) => json!({ "type": "token", "text": text }),
+            AgentEvent::ToolStarted { call } => {
+                self.preview = preview(&call, &self.workspace);
+                json!({ "type": "tool_started", "name": call.name(), "args": call.args() })
+            }
+            AgentEvent::ToolFinished { name, ok, output } => json!({ "type": "tool_finished", "name": name, "ok": ok, "output": output }),
+            AgentEvent::ApprovalNeeded { question, respond } => {
+                self.next += 1;
+                self.waiting.insert(self.next, Pending::Approval(respond));
+                json!({ "type": "approval_needed", "id": self.next, "question": question, "diff": self.preview.take() })
+            }
+            AgentEvent::QuestionAsked { question, respond } => {
+                self.next += 1;
+                self.waiting.insert(self.next, Pending::Answer(respond));
+                json!({ "type": "question_asked", "id": self.next, "question": question })
+            }
+            AgentEvent::Done { content } => json!({ "type": "done", "content": content }),
+        }
+    }
+
+    /// Decline every prompt still waiting for a reply
+    pub(crate) fn decline_all(&mut self) {
+        self.waiting.clear();
+    }
+
+    /// Settle a waiting prompt from `{"type": "approval", "id": 1, "approve": true}` or
+    /// `{"type": "answer", "id": 2, "text": "..."}`
+    pub(crate) fn settle(&mut self, text: &str) -> Result<()> {
+        let reply: Value = serde_json::from_str(text).context("frames must be JSON")?;
+        let id = reply["id"].as_u64().context("the reply has no `id`")?;
+        match (reply["type"].as_str(), self.waiting.remove(&id)) {
+            (_, None) => anyhow::bail!("nothing is waiting for a reply with id {}", id),
+            (Some("approval"), Some(Pending::Approval(respond))) if reply["approve"].as_bool() == Some(true) => respond.approve(),
+            (Some("approval"), Some(Pending::Approval(respond))) => respond.deny(),
+            (Some("answer"), Some(Pending::Answer(respond))) => respond.answer(reply["text"].as_str().unwrap_or_default()),
+            (_, Some(prompt)) => {
+                let expected = match prompt {
+                    Pending::Approval(_) => "approval",
+                    Pending::Answer(_) => "answer",
+                };
+                self.waiting.insert(id, prompt);
+                anyhow::bail!("id {} expects a reply of type `{}`", id, expected);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The change a file-writing call would make, as a unified diff. Only files inside the
+/// workspace are read for it; the policy decides about the rest.
+fn preview(call: &tools::ToolCall, workspace: &Path) -> Option<String> {
+    match call {
+        tools::ToolCall::WriteFile { path, content } => {
+            let old = workspace.join(path).canonicalize().ok()
+                .filter(|full| full.starts_with(workspace))
+                .and_then(|full| std::fs::read_to_string(full).ok())
+                .unwrap_or_default();
+            Some(tools::diff::unified(&old, content, path, path))
+        }
+        tools::ToolCall::ApplyPatch { patch, .. } if tools::diff::is_unified(patch) => Some(patch.clone()),
+        _ => None,
+    }
+}
+
+/// Send the session's events to the socket and its replies back, until either side closes or a
+/// newer connection takes the session over. Prompts left unanswered are declined.
+async fn relay(handle: AgentHandle, socket: WebSocketStream<TokioIo<hyper::upgrade::Upgraded>>, workspace: PathBuf) -> Result<()> {
+    let mut events = handle.events().await;
+    let (mut outgoing, mut incoming) = socket.split();
+    let mut stream = Stream::new(workspace);
+    loop {
+        tokio::select! {
+            event = events.recv() => {
+                let Some(event) = event else { break };
+                outgoing.send(Message::text(stream.frame(event).to_string())).await?;
+            }
+            message = incoming.next() => match message {
+                Some(Ok(Message::Text(text))) if serde_json::from_str::<Value>(text.as_str()).is_ok_and(|v| v["type"] == "cancel") => {
+                    // Prompts the turn waits on would hold the cancellation up
+                    stream.decline_all();
+                    handle.cancel();
+                }
+                Some(Ok(Message::Text(text))) => {
+                    if let Err(err) = stream.settle(text.as_str()) {
+                        outgoing.send(Message::text(json!({ "type": "error", "error": format!("{:#}", err) }).to_string())).await?;
+                    }
+                }
+                Some(Ok(Message::Close(_))) | None => break,
+                Some(Ok(_)) => {}
+                Some(Err(err)) => return Err(err.into()),
+            }
+        }
+    }
+    outgoing.close().await.ok();
+    Ok(())
+}
+
+/// Apply the user's tools, permissions and role, and keep the session's tools in its workspace.
+/// Calls that would ask go to the session's WebSocket, and are declined while none is connected.
+fn confine(agent: &mut crate::Agent, user: &User, workspace: &Path) {
+    agent.enable_unattended();
+    agent.enable_quiet();
+    // The server's own repository is not the user's to search
+    agent.tool_ctx.index = None;
+    let functions: Vec<_> = agent.functions.iter()
+        .filter(|f| f.name != "search_codebase")
+        .filter(|f| match &user.tools {
+            Some(allowed) => allowed.contains(&f.name),
+            None => !UNCONFINED_TOOLS.contains(&f.name.as_str()),
+        })
+        .cloned()
+        .collect();
+    agent.functions = functions.into();
+    let policy = &mut agent.tool_ctx.policy;
+    policy.levels.extend(user.permissions.clone().unwrap_or_default());
+    if user.role.is_some() {
+        policy.role = user.role.clone();
+    }
+    policy.roots = Some(vec![workspace.to_path_buf()]);
+    agent.tool_ctx.set_workdir(workspace.to_path_buf());
+}
+
+async fn read_json(req: Request<Incoming>) -> Result<Value> {
+    let body = Limited::new(req.into_body(), MAX_BODY).collect().await
+        .map_err(|err| anyhow!("Failed to read the request body: {}", err))?
+        .to_bytes();
+    serde_json::from_slice(&body).context("The request body must be JSON")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{mock::MockProvider, tenants::User};
+    use super::confine;
+
+    #[tokio::test]
+    async fn tenants_get_process_tools_only_when_listed() {
+        let mock = MockProvider::start().await;
+        let workspace = std::env::temp_dir();
+        let offered = |user: &User| {
+            let mut agent = mock.tool_agent();
+            confine(&mut agent, user, &workspace);
+            agent.functions.iter().map(|f| f.name.clone()).collect::<Vec<_>>()
+        };
+        let default = offered(&User::default());
+        assert!(default.contains(&"write_file".to_string()));
+        for tool in ["run_command", "eval_code", "run_tests", "terraform_plan", "search_codebase"] {
+            assert!(!default.contains(&tool.to_string()), "{} offered by default", tool);
+        }
+        let listed = offered(&User { tools: Some(vec!["read_file".into(), "run_command".into()]), ..User::default() });
+        assert_eq!(listed, ["read_file", "run_command"]);
+    }
+}
@@ -0,0 +1,168 @@
+use std::{fs, path::Path};
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use anyhow::{Result, Context, bail};
+use crate::ChatMessage;
+
+/// Identifies the bundle layout; bump when fields change incompatibly
+pub const FORMAT: &str = "rustaigent-session/1";
+
+/// Files larger than this are listed in a bundle without their content
+const MAX_SNAPSHOT_BYTES: u64 = 1024 * 1024;
+
+/// One tool invocation and what it returned
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ToolCall {
+    /// Stored as `tool` and `args` fields
+    #[serde(flatten)]
+    pub call: crate::tools::ToolCall,
+    pub ok: bool,
+    pub output: String,
+    pub at: u64,
+    /// Number of conversation messages when the call ran, to place it in the transcript
+    #[serde(default)]
+    pub turn: usize,
+}
+
+/// A file touched during the session, as it was at export time
+#[derive(Serialize, Deserialize, Clone)]
+pub struct FileSnapshot {
+    pub path: String,
+    pub sha256: String,
+    /// `None` for binary or oversized files
+    pub content: Option<String>,
+}
+
+/// Portable session: everything a teammate needs to inspect or continue it elsewhere
+#[derive(Serialize, Deserialize)]
+pub struct Bundle {
+    pub format: String,
+    pub id: String,
+    pub exported: u64,
+    pub provider: String,
+    pub model: Option<String>,
+    pub messages: Vec<ChatMessage>,
+    pub tool_calls: Vec<ToolCall>,
+    pub files: Vec<FileSnapshot>,
+    /// SHA-256 of the effective configuration, to spot differing setups
+    pub config_digest: String,
+    /// Plan written before the work, with completed steps checked off
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub plan: Option<crate::plan::Plan>,
+    /// Tags the session's spend was attributed to when it was saved
+    #[serde(default, skip_serializing_if = "std::collections::BTreeMap::is_empty")]
+    pub tags: crate::stats::Tags,
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// One thing that happened in a recorded session
+pub enum Step<'a> {
+    Message { role: &'a str, content: &'a str },
+    Tool(&'a ToolCall),
+}
+
+pub fn config_digest(config: &crate::config::Config) -> String {
+    sha256_hex(toml::to_string(config).unwrap_or_default().as_bytes())
+}
+
+/// Snapshot every file named in the tool log that still exists
+pub fn snapshot_files(calls: &[ToolCall]) -> Vec<FileSnapshot> {
+    let mut paths: Vec<&str> = calls.iter()
+        .flat_map(|c| c.call.paths())
+        .collect();
+    paths.sort();
+    paths.dedup();
+    paths.into_iter().filter_map(|p| {
+        let meta = fs::metadata(p).ok().filter(|m| m.is_file())?;
+        let bytes = fs::read(p).ok()?;
+        let content = if meta.len() <= MAX_SNAPSHOT_BYTES { String::from_utf8(bytes.clone()).ok() } else { None };
+        Some(FileSnapshot { path: p.to_string(), sha256: sha256_hex(&bytes), content })
+    }).collect()
+}
+
+impl Bundle {
+    /// Write as JSON, zstd-compressed for `.zst` paths
+    pub fn save(&self, path: &Path) -> Result<()> {
+        crate::compress::write(path, &serde_json::to_vec_pretty(self)?)
+    }
+
+    /// Write an autosaved bundle, also encrypted when `encryption` is on; exports stay portable
+    pub fn save_private(&self, path: &Path) -> Result<()> {
+        crate::compress::write_private(path, &serde_json::to_vec_pretty(self)?)
+    }
+
+    /// Read a bundle, compressed, encrypted or not
+    pub fn load(path: &Path) -> Result<Self> {
+        let bytes = crate::compress::read(path)?;
+        let bundle: Bundle = serde_json::from_slice(&bytes).with_context(|| format!("{} is not a session bundle", path.display()))?;
+        if bundle.format != FORMAT {
+            bail!("Unsupported session format `{}` (expected `{}`)", bundle.format, FORMAT);
+        }
+        Ok(bundle)
+    }
+
+    /// Messages in order, with each tool call placed where it ran
+    pub fn steps(&self) -> Vec<Step<'_>> {
+        let mut out = Vec::new();
+        for (i, m) in self.messages.iter().enumerate() {
+            out.extend(self.tool_calls.iter().filter(|c| c.turn == i).map(Step::Tool));
+            // A function call and its result are shown by the tool step itself
+            if m.role != "function" && m.function_call.is_none() {
+                out.push(Step::Message { role: &m.role, content: &m.content });
+            }
+        }
+        let end = self.messages.len();
+        out.extend(self.tool_calls.iter().filter(|c| c.turn >= end).map(Step::Tool));
+        out
+    }
+
+    /// Human-readable transcript for inspection
+    pub fn render(&self) -> String {
+        let mut out = format!("Session {} ({}{})\n", self.id, self.provider, self.model.as_deref().map(|m| format!(", {}", m)).unwrap_or_default());
+        out.push_str(&format!("config digest {}\n", self.config_digest));
+        if let Some(plan) = &self.plan {
+            out.push_str(&format!("\n{}", plan.render()));
+        }
+        for m in &self.messages {
+            out.push_str(&format!("\n[{}]\n{}\n", m.role, m.content));
+        }
+        if !self.tool_calls.is_empty() {
+            out.push_str("\nTool calls:\n");
+            for c in &self.tool_calls {
+                out.push_str(&format!("  {} -> {}\n", c.call, if c.ok { "ok" } else { "failed" }));
+            }
+        }
+        if !self.files.is_empty() {
+            out.push_str("\nFiles:\n");
+            for f in &self.files {
+                out.push_str(&format!("  {} sha256:{}{}\n", f.path, &f.sha256[..12], if f.content.is_none() { " (content omitted)" } else { "" }));
+            }
+        }
+        out
+    }
+
+    /// Write snapshots back to disk, reporting files that already match
+    pub fn restore_files(&self) -> Result<Vec<String>> {
+        let mut written = Vec::new();
+        for f in &self.files {
+            let Some(content) = &f.content else { continue };
+            let rel = Path::new(&f.path);
+            if rel.is_absolute() || rel.components().any(|c| matches!(c, std::path::Component::ParentDir)) {
+                tracing::warn!("not restoring {}: only paths inside the current directory are restored", f.path);
+                continue;
+            }
+            if fs::read(&f.path).is_ok_and(|cur| sha256_hex(&cur) == f.sha256) {
+                continue;
+            }
+            if let Some(parent) = Path::new(&f.path).parent().filter(|p| !p.as_os_str().is_empty()) {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&f.path, content)?;
+            written.push(f.path.clone());
+        }
+        Ok(written)
+    }
+}
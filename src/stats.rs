@@ -0,0 +1,568 @@
+use std::{collections::BTreeMap, fs::{self, OpenOptions}, io::Write, time::{Duration, SystemTime, UNIX_EPOCH}};
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use anyhow::{Result, Context, bail};
+use crate::paths;
+
+const USAGE_FILE: &str = "usage.jsonl";
+
+/// USD per million tokens, from a `[pricing."<model prefix>"]` table
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+pub struct Price {
+    #[serde(default)]
+    pub prompt: f64,
+    #[serde(default)]
+    pub completion: f64,
+}
+
+/// Keys accepted in a `[pricing]` table, for config validation
+pub const KEYS: &[&str] = &["prompt", "completion"];
+
+/// Prices by model name prefix, overriding the built-in list prices
+pub type Pricing = BTreeMap<String, Price>;
+
+/// Labels attributing spend, such as `ticket`, `team` or `client`, by name
+pub type Tags = BTreeMap<String, String>;
+
+/// A `name=value` tag from the command line or the REPL
+pub fn parse_tag(text: &str) -> Result<(String, String)> {
+    let (name, value) = text.split_once('=').with_context(|| format!("Expected a tag as NAME=VALUE, not `{}`", text))?;
+    let (name, value) = (name.trim(), value.trim());
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        bail!("Tag names may only hold letters, digits, - and _ (found `{}`)", name);
+    }
+    if value.is_empty() || value.contains(',') {
+        bail!("The value of tag `{}` must be non-empty and free of commas", name);
+    }
+    Ok((name.to_string(), value.to_string()))
+}
+
+/// `name=value` pairs of `tags`, comma-separated, as usage under them is keyed
+fn tag_key(tags: &Tags) -> String {
+    tags.iter().map(|(name, value)| format!("{}={}", name, value)).collect::<Vec<_>>().join(",")
+}
+
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct ModelUsage {
+    pub requests: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+/// Outcomes and latency of one tool's calls
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct ToolUsage {
+    pub calls: u64,
+    pub failures: u64,
+    /// Calls stopped at a time limit, also counted as failures
+    pub timeouts: u64,
+    /// Calls timed, leaving out those that waited for approval
+    pub timed: u64,
+    pub millis: u64,
+    pub max_millis: u64,
+    /// The slow-tool warning was shown this session
+    #[serde(skip)]
+    pub warned: bool,
+}
+
+/// Fix loops run in a session and how they ended
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct FixRuns {
+    pub runs: u64,
+    /// Runs that ended with the check passing
+    pub green: u64,
+    /// Iterations the green runs took, together
+    pub iterations_to_green: u64,
+}
+
+impl FixRuns {
+    fn is_empty(&self) -> bool {
+        self.runs == 0
+    }
+}
+
+/// How a tool call ended, for [`SessionStats::record_tool`]
+#[derive(PartialEq, Clone, Copy)]
+pub enum ToolOutcome {
+    Ok,
+    Failed,
+    TimedOut,
+}
+
+/// Counters for one interactive session, appended to the usage log when the session ends
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SessionStats {
+    pub id: String,
+    pub started: u64,
+    pub ended: u64,
+    pub provider: String,
+    pub models: BTreeMap<String, ModelUsage>,
+    pub tool_calls: BTreeMap<String, u64>,
+    /// Outcomes and latency by tool; missing from sessions logged before they were tracked
+    #[serde(default)]
+    pub tools: BTreeMap<String, ToolUsage>,
+    pub approvals: u64,
+    pub denials: u64,
+    pub tasks: u64,
+    pub task_millis: u64,
+    /// Tags in effect when the session ended
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: Tags,
+    /// Requests made under tags, by their comma-separated `name=value` pairs and model
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tagged: BTreeMap<String, BTreeMap<String, ModelUsage>>,
+    /// Experiment variant the session ran
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub experiment: Option<crate::experiment::Assignment>,
+    #[serde(default, skip_serializing_if = "FixRuns::is_empty")]
+    pub fixes: FixRuns,
+    /// Set once appended to the usage log, so an interrupted session isn't counted twice
+    #[serde(skip)]
+    saved: bool,
+    /// Prices from the config
+    #[serde(skip)]
+    pricing: Option<Pricing>,
+}
+
+pub fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+impl SessionStats {
+    pub fn new(provider: &str, pricing: Option<Pricing>) -> Self {
+        let started = now_secs();
+        SessionStats {
+            id: format!("{:x}-{:x}", started, std::process::id()),
+            started,
+            ended: started,
+            provider: provider.into(),
+            models: BTreeMap::new(),
+            tool_calls: BTreeMap::new(),
+            tools: BTreeMap::new(),
+            approvals: 0,
+            denials: 0,
+            tasks: 0,
+            task_millis: 0,
+            tags: Tags::new(),
+            tagged: BTreeMap::new(),
+            experiment: None,
+            fixes: FixRuns::default(),
+            saved: false,
+            pricing,
+        }
+    }
+
+    /// Record one Chat Completions response, as the Batch API returns them
+    pub fn record_response(&mut self, model: &str, response: &Value) {
+        let usage = &response["usage"];
+        self.record_usage(model, usage["prompt_tokens"].as_u64().unwrap_or(0), usage["completion_tokens"].as_u64().unwrap_or(0));
+    }
+
+    /// Record one request and its token counts, under the current tags too
+    pub fn record_usage(&mut self, model: &str, prompt: u64, completion: u64) {
+        let mut entries = vec![self.models.entry(model.into()).or_default()];
+        if !self.tags.is_empty() {
+            entries.push(self.tagged.entry(tag_key(&self.tags)).or_default().entry(model.into()).or_default());
+        }
+        for entry in entries {
+            entry.requests += 1;
+            entry.prompt_tokens += prompt;
+            entry.completion_tokens += completion;
+        }
+    }
+
+    /// Record one tool call; `elapsed` is `None` when it waited for approval, which says nothing
+    /// about the tool's speed. Returns the tool's counters so far.
+    pub fn record_tool(&mut self, name: &str, elapsed: Option<Duration>, outcome: ToolOutcome) -> &mut ToolUsage {
+        *self.tool_calls.entry(name.into()).or_default() += 1;
+        let usage = self.tools.entry(name.into()).or_default();
+        usage.calls += 1;
+        usage.failures += u64::from(outcome != ToolOutcome::Ok);
+        usage.timeouts += u64::from(outcome == ToolOutcome::TimedOut);
+        if let Some(elapsed) = elapsed {
+            let millis = elapsed.as_millis() as u64;
+            usage.timed += 1;
+            usage.millis += millis;
+            usage.max_millis = usage.max_millis.max(millis);
+        }
+        usage
+    }
+
+    /// Record a fix loop that ended after `iterations`, with the check passing when `green`
+    pub fn record_fix(&mut self, iterations: usize, green: bool) {
+        self.fixes.runs += 1;
+        if green {
+            self.fixes.green += 1;
+            self.fixes.iterations_to_green += iterations as u64;
+        }
+    }
+
+    pub fn record_task(&mut self, elapsed: Duration) {
+        self.tasks += 1;
+        self.task_millis += elapsed.as_millis() as u64;
+    }
+
+    /// USD spent so far, at the configured or list prices
+    pub fn cost(&self) -> f64 {
+        self.models.iter().map(|(model, usage)| cost(model, usage, self.pricing.as_ref())).sum()
+    }
+
+    /// Requests, tokens and cost per model so far, with a total line when several models were used
+    pub fn render(&self) -> String {
+        let mut out = format!("{:<28} {:>9} {:>12} {:>12} {:>10}\n", "MODEL", "REQUESTS", "PROMPT TOK", "COMPL TOK", "COST $");
+        for (model, usage) in &self.models {
+            out.push_str(&format!("{:<28} {:>9} {:>12} {:>12} {:>10.4}\n", model, usage.requests, usage.prompt_tokens, usage.completion_tokens, cost(model, usage, self.pricing.as_ref())));
+        }
+        if self.models.len() > 1 {
+            let (requests, prompt, completion) = self.models.values().fold((0, 0, 0), |(r, p, c), u| (r + u.requests, p + u.prompt_tokens, c + u.completion_tokens));
+            out.push_str(&format!("{:<28} {:>9} {:>12} {:>12} {:>10.4}\n", "total", requests, prompt, completion, self.cost()));
+        }
+        if !self.tools.is_empty() {
+            let rows = self.tools.iter().map(|(tool, usage)| (tool.as_str(), ToolSummary::from(usage)));
+            out.push_str(&format!("\n{}", tool_table(rows)));
+        }
+        out
+    }
+
+    fn is_empty(&self) -> bool {
+        self.models.is_empty() && self.tool_calls.is_empty() && self.tasks == 0
+    }
+
+    /// Append this session to the usage log; sessions with no activity are skipped
+    pub fn save(&mut self) -> Result<()> {
+        if self.is_empty() || self.saved {
+            return Ok(());
+        }
+        self.ended = now_secs();
+        let dir = paths::data_dir();
+        fs::create_dir_all(&dir)?;
+        let mut file = OpenOptions::new().create(true).append(true).open(dir.join(USAGE_FILE))?;
+        writeln!(file, "{}", serde_json::to_string(self)?)?;
+        self.saved = true;
+        Ok(())
+    }
+}
+
+/// USD per million (prompt, completion) tokens: the longest matching `[pricing]` prefix, else the
+/// list price; unknown and local models are free
+pub fn price_per_million(model: &str, pricing: Option<&Pricing>) -> (f64, f64) {
+    let configured = pricing.into_iter().flatten()
+        .filter(|(prefix, _)| model.starts_with(prefix.as_str()))
+        .max_by_key(|(prefix, _)| prefix.len());
+    if let Some((_, price)) = configured {
+        return (price.prompt, price.completion);
+    }
+    match model {
+        m if m.starts_with("gpt-4o-mini") => (0.15, 0.60),
+        m if m.starts_with("gpt-4o") => (2.50, 10.00),
+        m if m.starts_with("gpt-4") => (30.00, 60.00),
+        m if m.starts_with("gpt-3.5") => (0.50, 1.50),
+        m if m.starts_with("claude-2") => (8.00, 24.00),
+        m if m.starts_with("claude-sonnet-4") => (3.00, 15.00),
+        m if m.starts_with("gemini-2.5-flash") => (0.30, 2.50),
+        _ => (0.0, 0.0),
+    }
+}
+
+pub fn cost(model: &str, usage: &ModelUsage, pricing: Option<&Pricing>) -> f64 {
+    let (p, c) = price_per_million(model, pricing);
+    (usage.prompt_tokens as f64 * p + usage.completion_tokens as f64 * c) / 1_000_000.0
+}
+
+/// Load sessions that started within `since` of now
+pub fn load(since: Duration) -> Result<Vec<SessionStats>> {
+    let path = paths::data_dir().join(USAGE_FILE);
+    let Ok(text) = fs::read_to_string(&path) else { return Ok(Vec::new()) };
+    let cutoff = now_secs().saturating_sub(since.as_secs());
+    Ok(text.lines()
+        .filter_map(|line| serde_json::from_str::<SessionStats>(line).ok())
+        .filter(|s| s.started >= cutoff)
+        .collect())
+}
+
+#[derive(Serialize, Default)]
+pub struct Summary {
+    pub sessions: usize,
+    pub tasks: u64,
+    pub avg_task_secs: f64,
+    pub approvals: u64,
+    pub denials: u64,
+    pub approval_rate: f64,
+    pub models: BTreeMap<String, ModelSummary>,
+    pub providers: BTreeMap<String, ModelSummary>,
+    /// Requests made under each `name=value` tag
+    pub tags: BTreeMap<String, ModelSummary>,
+    /// Requests by the UTC day (`YYYY-MM-DD`) their session started
+    pub days: BTreeMap<String, ModelSummary>,
+    pub tool_calls: BTreeMap<String, u64>,
+    pub tools: BTreeMap<String, ToolSummary>,
+    /// Part of the task time spent running tools rather than waiting for the model
+    pub tool_share: f64,
+    /// Outcomes by experiment name and variant
+    pub experiments: BTreeMap<String, BTreeMap<String, VariantSummary>>,
+}
+
+/// Outcomes of the sessions that ran one experiment variant
+#[derive(Serialize, Default)]
+pub struct VariantSummary {
+    pub sessions: usize,
+    pub approvals: u64,
+    pub denials: u64,
+    pub approval_rate: f64,
+    pub fix_runs: u64,
+    pub fix_green: u64,
+    /// Iterations a fix loop took to pass, on average over those that did
+    pub avg_iterations_to_green: f64,
+    pub cost_usd: f64,
+    pub cost_per_session: f64,
+    #[serde(skip)]
+    iterations_to_green: u64,
+}
+
+impl VariantSummary {
+    fn add(&mut self, session: &SessionStats, cost: f64) {
+        self.sessions += 1;
+        self.approvals += session.approvals;
+        self.denials += session.denials;
+        self.fix_runs += session.fixes.runs;
+        self.fix_green += session.fixes.green;
+        self.iterations_to_green += session.fixes.iterations_to_green;
+        self.cost_usd += cost;
+        self.approval_rate = self.approvals as f64 / (self.approvals + self.denials).max(1) as f64;
+        self.avg_iterations_to_green = self.iterations_to_green as f64 / self.fix_green.max(1) as f64;
+        self.cost_per_session = self.cost_usd / self.sessions as f64;
+    }
+}
+
+#[derive(Serialize, Default, Clone)]
+pub struct ToolSummary {
+    pub calls: u64,
+    pub failures: u64,
+    pub timeouts: u64,
+    pub failure_rate: f64,
+    pub avg_secs: f64,
+    pub max_secs: f64,
+    #[serde(skip)]
+    millis: u64,
+    #[serde(skip)]
+    timed: u64,
+}
+
+impl ToolSummary {
+    fn add(&mut self, usage: &ToolUsage) {
+        self.calls += usage.calls;
+        self.failures += usage.failures;
+        self.timeouts += usage.timeouts;
+        self.millis += usage.millis;
+        self.timed += usage.timed;
+        self.max_secs = self.max_secs.max(usage.max_millis as f64 / 1000.0);
+        self.failure_rate = self.failures as f64 / self.calls.max(1) as f64;
+        self.avg_secs = self.millis as f64 / self.timed.max(1) as f64 / 1000.0;
+    }
+}
+
+impl From<&ToolUsage> for ToolSummary {
+    fn from(usage: &ToolUsage) -> Self {
+        let mut summary = ToolSummary::default();
+        summary.add(usage);
+        summary
+    }
+}
+
+/// Tool rows: calls, failures, timeouts and latency
+fn tool_table<'a>(rows: impl Iterator<Item = (&'a str, ToolSummary)>) -> String {
+    let mut out = format!("{:<28} {:>9} {:>9} {:>9} {:>9} {:>9}\n", "TOOL", "CALLS", "FAILED", "TIMEOUTS", "AVG S", "MAX S");
+    for (tool, t) in rows {
+        out.push_str(&format!("{:<28} {:>9} {:>8.0}% {:>9} {:>9.2} {:>9.2}\n", tool, t.calls, t.failure_rate * 100.0, t.timeouts, t.avg_secs, t.max_secs));
+    }
+    out
+}
+
+#[derive(Serialize, Default)]
+pub struct ModelSummary {
+    pub requests: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub cost_usd: f64,
+}
+
+/// UTC day of a Unix timestamp, as `YYYY-MM-DD`
+fn day(secs: u64) -> String {
+    chrono::DateTime::from_timestamp(secs as i64, 0).unwrap_or_default().format("%Y-%m-%d").to_string()
+}
+
+impl ModelSummary {
+    fn add(&mut self, usage: &ModelUsage, cost: f64) {
+        self.requests += usage.requests;
+        self.prompt_tokens += usage.prompt_tokens;
+        self.completion_tokens += usage.completion_tokens;
+        self.cost_usd += cost;
+    }
+}
+
+/// Totals over `sessions`, priced with `pricing` over the list prices. With `filter` tags, only
+/// sessions that made requests under all of them count, and only those requests' tokens.
+pub fn summarize(sessions: &[SessionStats], pricing: Option<&Pricing>, filter: &Tags) -> Summary {
+    let mut sum = Summary::default();
+    let mut task_millis = 0;
+    let wanted: Vec<String> = filter.iter().map(|(name, value)| format!("{}={}", name, value)).collect();
+    for s in sessions {
+        let tagged: Vec<(&String, &BTreeMap<String, ModelUsage>)> = s.tagged.iter()
+            .filter(|(key, _)| wanted.iter().all(|w| key.split(',').any(|pair| pair == w)))
+            .collect();
+        if !wanted.is_empty() && tagged.is_empty() {
+            continue;
+        }
+        sum.sessions += 1;
+        sum.tasks += s.tasks;
+        task_millis += s.task_millis;
+        sum.approvals += s.approvals;
+        sum.denials += s.denials;
+        let counted: Vec<(&String, &ModelUsage)> = match wanted.is_empty() {
+            true => s.models.iter().collect(),
+            false => tagged.iter().flat_map(|(_, models)| models.iter()).collect(),
+        };
+        let day = day(s.started);
+        let mut session_cost = 0.0;
+        for (model, usage) in counted {
+            let cost = cost(model, usage, pricing);
+            session_cost += cost;
+            sum.models.entry(model.clone()).or_default().add(usage, cost);
+            sum.providers.entry(s.provider.clone()).or_default().add(usage, cost);
+            sum.days.entry(day.clone()).or_default().add(usage, cost);
+        }
+        for (key, models) in tagged {
+            for (model, usage) in models {
+                let cost = cost(model, usage, pricing);
+                for pair in key.split(',') {
+                    sum.tags.entry(pair.to_string()).or_default().add(usage, cost);
+                }
+            }
+        }
+        if let Some(assignment) = &s.experiment {
+            sum.experiments.entry(assignment.name.clone()).or_default().entry(assignment.variant.clone()).or_default().add(s, session_cost);
+        }
+        for (tool, n) in &s.tool_calls {
+            *sum.tool_calls.entry(tool.clone()).or_default() += n;
+        }
+        for (tool, usage) in &s.tools {
+            sum.tools.entry(tool.clone()).or_default().add(usage);
+        }
+    }
+    if sum.tasks > 0 {
+        sum.avg_task_secs = task_millis as f64 / sum.tasks as f64 / 1000.0;
+    }
+    if task_millis > 0 {
+        let tool_millis: u64 = sum.tools.values().map(|t| t.millis).sum();
+        sum.tool_share = (tool_millis as f64 / task_millis as f64).min(1.0);
+    }
+    let decisions = sum.approvals + sum.denials;
+    if decisions > 0 {
+        sum.approval_rate = sum.approvals as f64 / decisions as f64;
+    }
+    sum
+}
+
+/// Render a summary as an aligned terminal table
+pub fn render_table(sum: &Summary) -> String {
+    let mut out = format!(
+        "Sessions: {}   Tasks: {}   Avg task: {:.1}s   Approvals: {} / Denials: {} ({:.0}% approved)\n\n",
+        sum.sessions, sum.tasks, sum.avg_task_secs, sum.approvals, sum.denials, sum.approval_rate * 100.0
+    );
+    out.push_str(&format!("{:<28} {:>9} {:>14} {:>14} {:>10}\n", "MODEL", "REQUESTS", "PROMPT TOK", "COMPL TOK", "COST $"));
+    for (model, m) in &sum.models {
+        out.push_str(&format!("{:<28} {:>9} {:>14} {:>14} {:>10.4}\n", model, m.requests, m.prompt_tokens, m.completion_tokens, m.cost_usd));
+    }
+    if sum.providers.len() > 1 {
+        out.push_str(&format!("\n{:<28} {:>9} {:>14} {:>14} {:>10}\n", "PROVIDER", "REQUESTS", "PROMPT TOK", "COMPL TOK", "COST $"));
+        for (provider, m) in &sum.providers {
+            out.push_str(&format!("{:<28} {:>9} {:>14} {:>14} {:>10.4}\n", provider, m.requests, m.prompt_tokens, m.completion_tokens, m.cost_usd));
+        }
+    }
+    if !sum.tags.is_empty() {
+        out.push_str(&format!("\n{:<28} {:>9} {:>14} {:>14} {:>10}\n", "TAG", "REQUESTS", "PROMPT TOK", "COMPL TOK", "COST $"));
+        for (tag, m) in &sum.tags {
+            out.push_str(&format!("{:<28} {:>9} {:>14} {:>14} {:>10.4}\n", tag, m.requests, m.prompt_tokens, m.completion_tokens, m.cost_usd));
+        }
+    }
+    for (name, variants) in &sum.experiments {
+        out.push_str(&format!("\n{:<28} {:>9} {:>9} {:>12} {:>14} {:>10} {:>10}\n", format!("EXPERIMENT {}", name), "SESSIONS", "APPROVED", "FIXES GREEN", "ITER TO GREEN", "COST $", "$/SESSION"));
+        for (variant, v) in variants {
+            let green = format!("{}/{}", v.fix_green, v.fix_runs);
+            out.push_str(&format!("{:<28} {:>9} {:>8.0}% {:>12} {:>14.1} {:>10.4} {:>10.4}\n", variant, v.sessions, v.approval_rate * 100.0, green, v.avg_iterations_to_green, v.cost_usd, v.cost_per_session));
+        }
+    }
+    if !sum.tool_calls.is_empty() {
+        // Sessions logged before outcomes were tracked only have call counts
+        let rows = sum.tool_calls.iter().map(|(tool, &calls)| {
+            (tool.as_str(), ToolSummary { calls, ..sum.tools.get(tool).cloned().unwrap_or_default() })
+        });
+        out.push_str(&format!("\nTools took {:.0}% of task time; the rest was spent on model requests.\n", sum.tool_share * 100.0));
+        out.push_str(&tool_table(rows));
+    }
+    out
+}
+
+/// One CSV row per model, provider, tag and tool, so spreadsheets can pivot any way
+pub fn render_csv(sum: &Summary) -> String {
+    let mut out = String::from("kind,name,requests_or_calls,prompt_tokens,completion_tokens,cost_usd,failures,timeouts,avg_secs,max_secs\n");
+    for (model, m) in &sum.models {
+        out.push_str(&format!("model,{},{},{},{},{:.6},,,,\n", model, m.requests, m.prompt_tokens, m.completion_tokens, m.cost_usd));
+    }
+    for (provider, m) in &sum.providers {
+        out.push_str(&format!("provider,{},{},{},{},{:.6},,,,\n", provider, m.requests, m.prompt_tokens, m.completion_tokens, m.cost_usd));
+    }
+    for (tag, m) in &sum.tags {
+        out.push_str(&format!("tag,{},{},{},{},{:.6},,,,\n", tag, m.requests, m.prompt_tokens, m.completion_tokens, m.cost_usd));
+    }
+    for (tool, n) in &sum.tool_calls {
+        match sum.tools.get(tool) {
+            Some(t) => out.push_str(&format!("tool,{},{},,,,{},{},{:.3},{:.3}\n", tool, n, t.failures, t.timeouts, t.avg_secs, t.max_secs)),
+            None => out.push_str(&format!("tool,{},{},,,,,,,\n", tool, n)),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{mock::MockProvider, turns::Turn};
+    use super::{summarize, Price, SessionStats};
+
+    #[tokio::test]
+    async fn usage_is_counted_and_priced_per_model() {
+        let mock = MockProvider::start().await;
+        mock.reply("one").await;
+        mock.reply("two").await;
+        let agent = mock.agent();
+        let model = agent.model_name();
+        let pricing = [(model.clone(), Price { prompt: 2.0, completion: 8.0 })].into();
+        *agent.stats.lock().unwrap() = SessionStats::new("openai", Some(pricing));
+        agent.send_request(Turn::Tools, None).await.unwrap();
+        agent.send_request(Turn::Tools, None).await.unwrap();
+        let stats = agent.stats.lock().unwrap();
+        let usage = &stats.models[&model];
+        assert_eq!((usage.requests, usage.prompt_tokens, usage.completion_tokens), (2, 20, 10));
+        assert!((stats.cost() - (20.0 * 2.0 + 10.0 * 8.0) / 1e6).abs() < 1e-12);
+    }
+
+    #[tokio::test]
+    async fn spend_is_attributed_to_the_tags_of_each_request() {
+        let mock = MockProvider::start().await;
+        mock.reply("pong").await;
+        let mut agent = mock.agent();
+        agent.send_request(Turn::Tools, None).await.unwrap();
+        agent.tag("client".into(), "acme".into());
+        agent.tag("ticket".into(), "ENG-1".into());
+        agent.send_request(Turn::Tools, None).await.unwrap();
+        agent.tag("ticket".into(), "ENG-2".into());
+        agent.send_request(Turn::Tools, None).await.unwrap();
+        let sessions = [agent.stats.lock().unwrap().clone()];
+        let all = summarize(&sessions, None, &Default::default());
+        assert_eq!(all.models["gpt-4o-mini"].requests, 3);
+        assert_eq!((all.tags["client=acme"].requests, all.tags["ticket=ENG-1"].requests), (2, 1));
+        let filter = [("ticket".to_string(), "ENG-2".to_string())].into();
+        let one = summarize(&sessions, None, &filter);
+        assert_eq!((one.sessions, one.models["gpt-4o-mini"].prompt_tokens), (1, 10));
+    }
+}
@@ -0,0 +1,332 @@
+//! `rustaigent --stdio-editor`: the protocol editor plugins speak, one JSON object per line on
+//! stdin and stdout, so Vim, Emacs and VS Code integrations can stay thin wrappers around one
+//! interface. Requests carry an `id`, a `method` and `params`, and each gets one response with
+//! the same `id` holding `result` or `error`. While a turn runs, `event` notifications carry the
+//! server's event frames, and a fix arrives as a `receive_patch` notification before its response.
+//! `rustaigent daemon` speaks the same protocol on each connection to its socket.
+
+use std::{collections::VecDeque, future::Future, path::Path};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use anyhow::{Result, Context, anyhow, bail};
+use tokio::{io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader, Lines}, sync::mpsc};
+use crate::{cancel, events, oneshot, serve::Stream, Agent};
+
+/// Version `open_session` reports; raised only by incompatible changes
+pub const PROTOCOL: u32 = 1;
+
+#[derive(Deserialize)]
+struct Request {
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+/// Code selected in the editor, sent along with the prompts that follow until it is replaced
+#[derive(Deserialize)]
+struct Selection {
+    path: String,
+    start_line: Option<u64>,
+    end_line: Option<u64>,
+    text: String,
+    language: Option<String>,
+}
+
+impl Selection {
+    fn describe(&self) -> String {
+        let lines = match (self.start_line, self.end_line) {
+            (Some(start), Some(end)) => format!(", lines {}-{}", start, end),
+            (Some(start), None) => format!(", from line {}", start),
+            _ => String::new(),
+        };
+        let fence = if self.text.contains("```") { "````" } else { "```" };
+        format!("Selected in {}{}:\n{}{}\n{}\n{}", self.path, lines, fence, self.language.as_deref().unwrap_or_default(), self.text.trim_end(), fence)
+    }
+}
+
+/// The lines of `lines` as they arrive, after `first` when it was read already
+pub(crate) fn incoming<R: AsyncBufRead + Unpin + Send + 'static>(mut lines: Lines<R>, first: Option<String>) -> mpsc::UnboundedReceiver<String> {
+    let (sender, incoming) = mpsc::unbounded_channel();
+    if let Some(first) = first {
+        sender.send(first).ok();
+    }
+    tokio::spawn(async move {
+        while let Ok(Some(line)) = lines.next_line().await {
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    incoming
+}
+
+/// The connection: requests coming in, lines going out, and the frames of the turn's prompts
+struct Link {
+    events: events::Receiver,
+    incoming: mpsc::UnboundedReceiver<String>,
+    outgoing: mpsc::UnboundedSender<Value>,
+    /// Requests that arrived during a turn, handled once it is over
+    queued: VecDeque<String>,
+    /// The editor's side has ended
+    closed: bool,
+    stream: Stream,
+}
+
+impl Link {
+    /// Queue a line for the editor; if it has gone, the next read ends the session
+    fn send(&self, message: Value) {
+        self.outgoing.send(message).ok();
+    }
+
+    fn respond(&self, id: Value, result: Result<Value>) {
+        match result {
+            Ok(result) => self.send(json!({ "id": id, "result": result })),
+            Err(err) => self.send(json!({ "id": id, "error": { "message": format!("{:#}", err) } })),
+        }
+    }
+
+    fn relay_event(&mut self, event: events::AgentEvent) {
+        let frame = self.stream.frame(event);
+        self.send(json!({ "method": "event", "params": frame }));
+    }
+
+    /// The next request to handle, queued ones first
+    async fn next_line(&mut self) -> Option<String> {
+        if let Some(line) = self.queued.pop_front() {
+            return Some(line);
+        }
+        if self.closed {
+            return None;
+        }
+        self.incoming.recv().await
+    }
+
+    /// Run `turn`, relaying its events and taking `reply` and `cancel` while it runs; other
+    /// requests wait in line until it is over
+    async fn relay<T>(&mut self, canceller: cancel::Canceller, turn: impl Future<Output = Result<T>>) -> Result<T> {
+        tokio::pin!(turn);
+        let result = loop {
+            tokio::select! {
+                Some(event) = self.events.recv() => self.relay_event(event),
+                line = self.incoming.recv(), if !self.closed => {
+                    let Some(line) = line else {
+                        self.closed = true;
+                        continue;
+                    };
+                    let request = serde_json::from_str::<Request>(&line).ok().filter(|r| r.method == "reply" || r.method == "cancel");
+                    let Some(request) = request else {
+                        self.queued.push_back(line);
+                        continue;
+                    };
+                    let id = request.id.unwrap_or(Value::Null);
+                    if request.method == "reply" {
+                        let settled = self.stream.settle(&request.params.to_string()).map(|_| json!({}));
+                        self.respond(id, settled);
+                        continue;
+                    }
+                    // Prompts the turn waits on would hold the cancellation up
+                    self.stream.decline_all();
+                    canceller.cancel();
+                    self.respond(id, Ok(json!({})));
+                }
+                result = &mut turn => break result,
+            }
+        };
+        // Events of the turn's last moments go out before its response
+        while let Some(event) = self.events.try_recv() {
+            self.relay_event(event);
+        }
+        result
+    }
+}
+
+/// State of the session: the agent, the editor's selection and the connection
+struct Editor<'a> {
+    agent: &'a mut Agent,
+    link: Link,
+    selection: Option<Selection>,
+}
+
+/// Serve the protocol until stdin closes or the editor sends `shutdown`. Approvals and questions
+/// go to the editor as events; it answers them with `reply`.
+pub async fn run(agent: &mut Agent) -> Result<()> {
+    let lines = BufReader::new(tokio::io::stdin()).lines();
+    serve(agent, incoming(lines, None), tokio::io::stdout()).await
+}
+
+/// Serve the protocol on `incoming` lines, writing to `out`, until they end or `shutdown`
+pub(crate) async fn serve<W: AsyncWrite + Unpin + Send + 'static>(agent: &mut Agent, incoming: mpsc::UnboundedReceiver<String>, mut out: W) -> Result<()> {
+    agent.enable_quiet();
+    let (outgoing, mut lines) = mpsc::unbounded_channel::<Value>();
+    let writer = tokio::spawn(async move {
+        while let Some(line) = lines.recv().await {
+            if out.write_all(format!("{}\n", line).as_bytes()).await.is_err() || out.flush().await.is_err() {
+                break;
+            }
+        }
+    });
+    let events = agent.events();
+    let link = Link { events, incoming, outgoing, queued: VecDeque::new(), closed: false, stream: Stream::new(std::env::current_dir()?) };
+    let mut editor = Editor { agent, link, selection: None };
+    while let Some(line) = editor.link.next_line().await {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let request = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => request,
+            Err(err) => {
+                editor.link.respond(Value::Null, Err(anyhow!("Not a request: {}", err)));
+                continue;
+            }
+        };
+        let id = request.id.clone().unwrap_or(Value::Null);
+        if request.method == "shutdown" {
+            editor.link.respond(id, Ok(json!({})));
+            break;
+        }
+        let result = editor.handle(&id, request).await;
+        editor.link.respond(id, result);
+    }
+    // Every line sent is written before the session ends
+    drop(editor);
+    writer.await?;
+    Ok(())
+}
+
+impl Editor<'_> {
+    async fn handle(&mut self, id: &Value, request: Request) -> Result<Value> {
+        match request.method.as_str() {
+            "open_session" => self.open_session(&request.params),
+            "send_selection" => {
+                self.selection = Some(serde_json::from_value(request.params).context("A selection needs `path` and `text`")?);
+                Ok(json!({}))
+            }
+            "ask" => {
+                let prompt = request.params["prompt"].as_str().context("`ask` needs a `prompt`")?;
+                let answer = self.turn(self.with_selection(prompt)).await?;
+                Ok(json!({ "answer": answer }))
+            }
+            "capabilities" => Ok(self.agent.handshake()),
+            "prompt" => self.prompt(&request.params).await,
+            "request_fix" => self.request_fix(id, &request.params).await,
+            "reply" | "cancel" => bail!("No turn is running"),
+            other => bail!("Unknown method `{}`", other),
+        }
+    }
+
+    /// Start over, in `root` when given: the conversation and the selection are dropped
+    fn open_session(&mut self, params: &Value) -> Result<Value> {
+        if let Some(root) = params["root"].as_str() {
+            let root = Path::new(root).canonicalize().with_context(|| format!("No such directory {}", root))?;
+            if !root.is_dir() {
+                bail!("{} is not a directory", root.display());
+            }
+            self.agent.tool_ctx.policy.allow_path(&root);
+            self.agent.tool_ctx.owners = crate::owners::Owners::discover(self.agent.config.codeowners.as_ref(), &root).map(std::sync::Arc::new);
+            self.agent.tool_ctx.set_workdir(root.clone());
+            self.link.stream = Stream::new(root);
+        }
+        self.agent.clear();
+        self.selection = None;
+        let session = self.agent.stats.lock().unwrap().id.clone();
+        Ok(json!({
+            "protocol": PROTOCOL,
+            "session": session,
+            "provider": self.agent.provider,
+            "model": self.agent.model_name(),
+            "language": self.agent.lang.code(),
+            "root": self.agent.tool_ctx.dir(),
+        }))
+    }
+
+    /// Answer as `rustaigent -p` does, with `input` attached and verification and
+    /// post-processing applied, returning the part `output` asks for too
+    async fn prompt(&mut self, params: &Value) -> Result<Value> {
+        let prompt = params["prompt"].as_str().context("`prompt` needs a `prompt`")?;
+        let output = match params["output"].as_str().unwrap_or("text") {
+            "text" => oneshot::Output::Text,
+            "code" => oneshot::Output::Code,
+            "patch" => oneshot::Output::Patch,
+            other => bail!("Unknown output `{}`; use text, code or patch", other),
+        };
+        let canceller = self.agent.canceller();
+        let (answer, selected) = self.link.relay(canceller, oneshot::answer(self.agent, prompt, params["input"].as_str(), output)).await?;
+        Ok(json!({ "answer": answer, "output": selected }))
+    }
+
+    /// Ask for a fix of the selection, or of `path`, as a patch the editor applies
+    async fn request_fix(&mut self, id: &Value, params: &Value) -> Result<Value> {
+        let instruction = params["instruction"].as_str().unwrap_or("Fix the problems in this code.");
+        let prompt = match params["path"].as_str() {
+            Some(path) if self.selection.is_none() => format!("In {}: {}", path, instruction),
+            _ => self.with_selection(instruction),
+        };
+        let answer = self.turn(prompt + oneshot::PATCH_INSTRUCTIONS).await?;
+        let patch = oneshot::select(&answer, oneshot::Output::Patch)?;
+        let files: Vec<&str> = patch.lines()
+            .filter_map(|line| line.strip_prefix("+++ "))
+            .map(|name| name.split('\t').next().unwrap_or(name).trim())
+            .filter(|name| *name != "/dev/null")
+            .map(|name| name.strip_prefix("b/").unwrap_or(name))
+            .collect();
+        self.link.send(json!({ "method": "receive_patch", "params": { "id": id, "patch": patch, "files": files } }));
+        Ok(json!({ "answer": answer }))
+    }
+
+    fn with_selection(&self, prompt: &str) -> String {
+        match &self.selection {
+            Some(selection) => format!("{}\n\n{}", selection.describe(), prompt),
+            None => prompt.to_string(),
+        }
+    }
+
+    async fn turn(&mut self, prompt: String) -> Result<String> {
+        let canceller = self.agent.canceller();
+        match self.link.relay(canceller, self.agent.turn(&prompt)).await? {
+            Some(reply) => Ok(reply.content),
+            None => Err(anyhow!("The guardrails held the prompt back")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use crate::mock::MockProvider;
+    use super::{incoming, serve};
+
+    #[tokio::test]
+    async fn editor_requests_wait_for_the_running_turn() {
+        use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+        let mock = MockProvider::start().await;
+        mock.reply_once("Fixed.\n\n```diff\n--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -1 +1 @@\n-old\n+new\n```").await;
+        mock.reply("second").await;
+        let mut session = mock.agent();
+        let (mut editor, plugin) = tokio::io::duplex(1 << 16);
+        let requests = [
+            json!({"id": 1, "method": "open_session"}),
+            json!({"id": 2, "method": "request_fix", "params": {"path": "src/lib.rs"}}),
+            json!({"id": 3, "method": "ask", "params": {"prompt": "and now?"}}),
+            json!({"id": 4, "method": "shutdown"}),
+        ];
+        for request in requests {
+            editor.write_all(format!("{}\n", request).as_bytes()).await.unwrap();
+        }
+        let (read, write) = tokio::io::split(plugin);
+        let incoming = incoming(tokio::io::BufReader::new(read).lines(), None);
+        serve(&mut session, incoming, write).await.unwrap();
+        let mut out = String::new();
+        editor.shutdown().await.unwrap();
+        editor.read_to_string(&mut out).await.unwrap();
+        let lines: Vec<serde_json::Value> = out.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        let order: Vec<String> = lines.iter().map(|l| l["method"].as_str().map_or_else(|| l["id"].to_string(), String::from)).collect();
+        let patch = lines.iter().find(|l| l["method"] == "receive_patch").unwrap();
+        assert_eq!(patch["params"]["files"], json!(["src/lib.rs"]));
+        assert_eq!(patch["params"]["id"], 2);
+        let relevant: Vec<&str> = order.iter().map(String::as_str).filter(|m| *m != "event").collect();
+        assert_eq!(relevant, ["1", "receive_patch", "2", "3", "4"]);
+        assert_eq!(lines.iter().find(|l| l["id"] == 3).unwrap()["result"]["answer"], "second");
+        assert!(order.iter().filter(|m| *m == "event").count() >= 2);
+    }
+}
@@ -0,0 +1,82 @@
+//! Follow-up suggestions: with `[suggestions]` on, each answer in the REPL ends with two or three
+//! likely next requests, such as running the tests or applying a proposed patch, and typing a
+//! suggestion's number sends it as the next prompt.
+
+use serde::{Serialize, Deserialize};
+use anyhow::{Result, bail};
+use crate::{turns::Turn, Agent};
+
+/// `[suggestions]` table
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct SuggestConfig {
+    /// Offer follow-ups after each answer in the REPL
+    pub enabled: Option<bool>,
+    /// Model that writes them (default: the session's); one request per answer
+    pub model: Option<String>,
+}
+
+/// Keys accepted in `[suggestions]`, for config validation
+pub const KEYS: &[&str] = &["enabled", "model"];
+
+/// Follow-ups offered at most
+const MAX: usize = 3;
+
+/// Characters of the answer the suggestions are written from; its end says what is left to do
+const ANSWER_CHARS: usize = 4000;
+
+/// Two or three requests the user is likely to send after `answer` to `request`
+pub async fn follow_ups(agent: &Agent, request: &str, answer: &str) -> Result<Vec<String>> {
+    let cfg = agent.config.suggestions.clone().unwrap_or_default();
+    let answer = &answer[answer.floor_char_boundary(answer.len().saturating_sub(ANSWER_CHARS))..];
+    let actions = agent.last_actions().map(|actions| format!("\n\nWhat the tools did:\n{}", actions.note())).unwrap_or_default();
+    let prompt = format!(
+        "Suggest two or three follow-up requests the user is likely to send next, such as running the tests, applying a proposed patch or explaining an alternative. Write each as the user's next message to you, an imperative of at most eight words, and suggest only what you could do with your tools. Reply with a JSON array of strings only.\n\nRequest:\n{}\n\nAnswer:\n{}{}",
+        request, answer, actions
+    );
+    let mut writer = agent.clone_for_batch(prompt);
+    if let Some(model) = &cfg.model {
+        writer.model = Some(model.clone());
+    }
+    let reply = writer.send_request(Turn::Answer, None).await?.content;
+    let json = reply.find('[').zip(reply.rfind(']')).filter(|(start, end)| start < end).map(|(start, end)| &reply[start..=end]);
+    let Some(suggestions) = json.and_then(|json| serde_json::from_str::<Vec<String>>(json).ok()) else {
+        bail!("The model replied without suggestions: {}", reply.trim());
+    };
+    Ok(suggestions.into_iter()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty() && !s.contains('\n'))
+        .take(MAX)
+        .collect())
+}
+
+/// The suggestions as shown under the answer, numbered from 1
+pub fn render(suggestions: &[String], a11y: bool) -> String {
+    let numbered: Vec<String> = suggestions.iter().enumerate().map(|(i, s)| format!("[{}] {}", i + 1, s)).collect();
+    match a11y {
+        true => format!("Suggested next steps, type a number to send one: {}.", numbered.join(", ")),
+        false => format!("Next: {}", numbered.join("  ")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mock::{last_content, MockProvider};
+    use super::{follow_ups, SuggestConfig};
+
+    #[tokio::test]
+    async fn follow_ups_are_written_from_the_last_exchange() {
+        let mock = MockProvider::start().await;
+        mock.reply_once("Added `parse` to src/lib.rs.").await;
+        mock.reply("Here you go:\n[\"Run the tests\", \"Explain the alternative\", \" \", \"Add a doc comment\", \"Commit it\"]").await;
+        let mut agent = mock.agent();
+        agent.enable_quiet();
+        agent.config.suggestions = Some(SuggestConfig { enabled: Some(true), model: Some("mini".into()) });
+        let reply = agent.turn("Write parse").await.unwrap().unwrap();
+        let suggestions = follow_ups(&agent, "Write parse", &reply.content).await.unwrap();
+        assert_eq!(suggestions, ["Run the tests", "Explain the alternative", "Add a doc comment"]);
+        let requests = mock.requests().await;
+        assert_eq!(requests[1]["model"], "mini");
+        let prompt = last_content(&requests[1]);
+        assert!(prompt.contains("Request:\nWrite parse") && prompt.contains("Added `parse`"), "{}", prompt);
+    }
+}
@@ -0,0 +1,278 @@
+use std::{collections::BTreeMap, env, fs, io::IsTerminal, path::{Path, PathBuf}, process::Command};
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use reqwest::{Client, StatusCode};
+use anyhow::{Result, Context, bail};
+use crate::{credentials, paths, s3, stats, vault::{self, Cipher}};
+
+/// `[sync]` table in the config file: where prompt history and sessions are mirrored, encrypted
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct SyncConfig {
+    /// `git`, `s3` or `webdav`
+    pub backend: Option<String>,
+    /// Git remote, `s3://bucket/prefix` or WebDAV collection URL
+    pub url: Option<String>,
+    /// S3-compatible endpoint such as MinIO (default: AWS for `region`)
+    pub endpoint: Option<String>,
+    /// S3 region (default: AWS_REGION, then us-east-1)
+    pub region: Option<String>,
+    /// WebDAV user; the password comes from RUSTAIGENT_SYNC_PASSWORD
+    pub username: Option<String>,
+    /// Sync when the REPL exits
+    pub auto: Option<bool>,
+}
+
+/// Keys accepted in `[sync]`, for config validation
+pub const KEYS: &[&str] = &["backend", "url", "endpoint", "region", "username", "auto"];
+
+pub const BACKENDS: &[&str] = &["git", "s3", "webdav"];
+
+const MAGIC: &[u8] = b"RAIS1";
+const MANIFEST: &str = "manifest.enc";
+const PROMPTS: &str = "prompts.jsonl.enc";
+
+/// Local prompt history, one `{"at", "prompt"}` object per line
+fn prompts_file() -> PathBuf {
+    paths::data_dir().join("prompts.jsonl")
+}
+
+fn sessions_dir() -> PathBuf {
+    paths::data_dir().join("sessions")
+}
+
+/// Append a REPL prompt to the local history. The file is rewritten whole so it can be sealed,
+/// into a temporary file renamed over the old one so a crash mid-write keeps the history
+pub fn record_prompt(prompt: &str) -> Result<()> {
+    let path = prompts_file();
+    fs::create_dir_all(path.parent().unwrap())?;
+    let mut history = vault::read(&path)?.unwrap_or_default();
+    let line = serde_json::json!({"at": stats::now_secs(), "prompt": prompt});
+    history.extend_from_slice(format!("{}\n", line).as_bytes());
+    let tmp = path.with_extension("jsonl.tmp");
+    vault::write(&tmp, history)?;
+    fs::rename(&tmp, &path).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Prompts of the local history, oldest first
+pub fn prompts() -> Result<Vec<String>> {
+    let history = vault::read(&prompts_file())?.map(String::from_utf8).transpose()?.unwrap_or_default();
+    Ok(history.lines()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .filter_map(|entry| entry["prompt"].as_str().map(String::from))
+        .collect())
+}
+
+/// Synced files as last uploaded, by remote name
+#[derive(Serialize, Deserialize, Default)]
+struct Manifest {
+    files: BTreeMap<String, Entry>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Entry {
+    sha256: String,
+    /// Modification time of the uploaded copy, in seconds since the epoch
+    modified: u64,
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn sha256(data: &[u8]) -> String {
+    hex(&Sha256::digest(data))
+}
+
+fn modified(path: &Path) -> u64 {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Passphrase from RUSTAIGENT_SYNC_PASSPHRASE, the stored credentials, or a prompt
+fn passphrase() -> Result<String> {
+    if let Some(pass) = env::var("RUSTAIGENT_SYNC_PASSPHRASE").ok().or_else(|| credentials::load("sync")) {
+        return Ok(pass);
+    }
+    if !std::io::stdin().is_terminal() {
+        bail!("Set RUSTAIGENT_SYNC_PASSPHRASE to sync without a terminal");
+    }
+    Ok(rpassword::prompt_password("Sync passphrase: ")?)
+}
+
+/// Where the encrypted files live
+enum Store {
+    /// Working tree of a clone under the data directory, pushed when done
+    Git { dir: PathBuf },
+    S3(s3::Bucket),
+    WebDav { client: Client, url: String, username: Option<String>, password: Option<String> },
+}
+
+fn git(dir: &Path, args: &[&str]) -> Result<std::process::Output> {
+    Command::new("git").arg("-C").arg(dir).args(args).output().context("Failed to run git")
+}
+
+fn git_ok(dir: &Path, args: &[&str]) -> Result<()> {
+    let out = git(dir, args)?;
+    if !out.status.success() {
+        bail!("git {} failed: {}", args.join(" "), String::from_utf8_lossy(&out.stderr).trim());
+    }
+    Ok(())
+}
+
+impl Store {
+    fn open(cfg: &SyncConfig, client: &Client) -> Result<Self> {
+        let url = cfg.url.clone().context("`sync.url` is not set")?;
+        match cfg.backend.as_deref().unwrap_or("git") {
+            "git" => {
+                let dir = paths::data_dir().join("sync").join("git");
+                if !dir.join(".git").is_dir() {
+                    fs::create_dir_all(dir.parent().unwrap())?;
+                    let out = Command::new("git").args(["clone", "-q", &url]).arg(&dir).output().context("Failed to run git")?;
+                    if !out.status.success() {
+                        bail!("git clone {} failed: {}", url, String::from_utf8_lossy(&out.stderr).trim());
+                    }
+                } else if git(&dir, &["rev-parse", "-q", "--verify", "HEAD"])?.status.success() {
+                    git_ok(&dir, &["pull", "-q", "--ff-only"])?;
+                }
+                Ok(Store::Git { dir })
+            }
+            "s3" => Ok(Store::S3(s3::Bucket::open(&url, cfg.endpoint.as_deref(), cfg.region.as_deref(), client, "sync.url")?)),
+            "webdav" => Ok(Store::WebDav {
+                client: client.clone(),
+                url: format!("{}/", url.trim_end_matches('/')),
+                username: cfg.username.clone(),
+                password: env::var("RUSTAIGENT_SYNC_PASSWORD").ok(),
+            }),
+            other => bail!("`sync.backend` must be one of {} (found `{}`)", BACKENDS.join(", "), other),
+        }
+    }
+
+    fn dav_request(&self, method: reqwest::Method, name: &str) -> reqwest::RequestBuilder {
+        let Store::WebDav { client, url, username, password } = self else { unreachable!() };
+        let builder = client.request(method, format!("{}{}", url, name));
+        match username {
+            Some(user) => builder.basic_auth(user, password.as_deref()),
+            None => builder,
+        }
+    }
+
+    async fn get(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        let request = match self {
+            Store::Git { dir } => {
+                let path = dir.join(name);
+                return Ok(path.is_file().then(|| fs::read(&path)).transpose()?);
+            }
+            Store::S3(bucket) => return bucket.get(name).await,
+            Store::WebDav { .. } => self.dav_request(reqwest::Method::GET, name),
+        };
+        let response = request.send().await?;
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => Ok(Some(response.bytes().await?.to_vec())),
+            status => bail!("Fetching {} from the sync backend returned {}", name, status),
+        }
+    }
+
+    async fn put(&self, name: &str, data: Vec<u8>) -> Result<()> {
+        let request = match self {
+            Store::Git { dir } => return Ok(fs::write(dir.join(name), data)?),
+            Store::S3(bucket) => return bucket.put(name, data).await,
+            Store::WebDav { .. } => self.dav_request(reqwest::Method::PUT, name),
+        };
+        let status = request.body(data).send().await?.status();
+        if !status.is_success() {
+            bail!("Uploading {} to the sync backend returned {}", name, status);
+        }
+        Ok(())
+    }
+
+    /// Publish the changes; only git batches them
+    fn finish(&self) -> Result<()> {
+        let Store::Git { dir } = self else { return Ok(()) };
+        git_ok(dir, &["add", "-A"])?;
+        if git(dir, &["diff", "--cached", "--quiet"])?.status.success() {
+            return Ok(());
+        }
+        git_ok(dir, &["commit", "-q", "-m", "rustaigent sync"])?;
+        git_ok(dir, &["push", "-q", "origin", "HEAD"]).context("Push rejected; run `rustaigent sync` again to merge the other machine's changes")
+    }
+}
+
+/// Union of two prompt histories, ordered by time without duplicates
+fn merge_prompts(local: &str, remote: &str) -> String {
+    let mut lines: Vec<(u64, &str)> = local.lines().chain(remote.lines())
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| (serde_json::from_str::<serde_json::Value>(l).ok().and_then(|v| v["at"].as_u64()).unwrap_or(0), l))
+        .collect();
+    lines.sort();
+    lines.dedup();
+    lines.iter().map(|(_, l)| format!("{}\n", l)).collect()
+}
+
+/// Mirror prompt history and saved sessions with the `[sync]` backend, both ways
+pub async fn run(cfg: Option<&SyncConfig>, client: &Client) -> Result<()> {
+    let cfg = cfg.context("No `[sync]` table in the config; see the README's Syncing section")?;
+    let store = Store::open(cfg, client)?;
+    let salt = match store.get("salt").await? {
+        Some(salt) => salt,
+        None => {
+            let salt = vault::random::<16>()?.to_vec();
+            store.put("salt", salt.clone()).await?;
+            salt
+        }
+    };
+    let cipher = Cipher::from_passphrase(&passphrase()?, &salt, MAGIC)?;
+    let mut manifest: Manifest = match store.get(MANIFEST).await? {
+        Some(data) => serde_json::from_slice(&cipher.open(&format!("remote {}", MANIFEST), &data)?).context("Corrupt sync manifest")?,
+        None => Manifest::default(),
+    };
+    let listed = manifest.files.len();
+
+    // Local files are compared and uploaded as plaintext, whether or not they are stored encrypted
+    let local = vault::read(&prompts_file())?.map(String::from_utf8).transpose()?.unwrap_or_default();
+    let remote = match store.get(PROMPTS).await? {
+        Some(data) => String::from_utf8(cipher.open(&format!("remote {}", PROMPTS), &data)?)?,
+        None => String::new(),
+    };
+    let merged = merge_prompts(&local, &remote);
+    if merged != local {
+        fs::create_dir_all(paths::data_dir())?;
+        vault::write(&prompts_file(), merged.clone().into_bytes())?;
+    }
+    if merged != remote {
+        store.put(PROMPTS, cipher.seal(merged.as_bytes())?).await?;
+    }
+
+    let dir = sessions_dir();
+    fs::create_dir_all(&dir)?;
+    let (mut uploaded, mut downloaded) = (0, 0);
+    for file in fs::read_dir(&dir)?.filter_map(|e| e.ok()).map(|e| e.path()).filter(|p| p.is_file()) {
+        let Some(name) = file.file_name().and_then(|n| n.to_str()).map(|n| format!("session-{}.enc", n)) else { continue };
+        let data = vault::open(&file, fs::read(&file)?)?;
+        let entry = Entry { sha256: sha256(&data), modified: modified(&file) };
+        let newer = manifest.files.get(&name).is_none_or(|e| e.sha256 != entry.sha256 && e.modified <= entry.modified);
+        if newer {
+            store.put(&name, cipher.seal(&data)?).await?;
+            manifest.files.insert(name, entry);
+            uploaded += 1;
+        }
+    }
+    for (name, entry) in &manifest.files {
+        let Some(file) = name.strip_prefix("session-").and_then(|n| n.strip_suffix(".enc")).map(|n| dir.join(n)) else { continue };
+        let current = vault::read(&file).ok().flatten().map(|d| sha256(&d));
+        if current.as_ref() == Some(&entry.sha256) || (current.is_some() && modified(&file) > entry.modified) {
+            continue;
+        }
+        let data = store.get(name).await?.with_context(|| format!("The manifest lists {} but the backend has no such file", name))?;
+        vault::write(&file, cipher.open(&format!("remote {}", name), &data)?)?;
+        downloaded += 1;
+    }
+    // Every seal draws a fresh nonce, so only rewrite the manifest when it changed
+    if uploaded > 0 || manifest.files.len() != listed {
+        store.put(MANIFEST, cipher.seal(&serde_json::to_vec(&manifest)?)?).await?;
+    }
+    store.finish()?;
+    println!("Synced {} prompt(s); uploaded {} and downloaded {} session file(s)", merged.lines().count(), uploaded, downloaded);
+    Ok(())
+}
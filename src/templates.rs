@@ -0,0 +1,108 @@
+//! Conversation templates: named starting points that seed a session with synthetic earlier
+//! turns, such as a `cargo check` run and the project tree, so a common workflow starts with
+//! that context loaded instead of spending round trips on it. A template is a TOML file in
+//! `.rustaigent/templates/` (the nearest one up from the working directory) or in
+//! `~/.config/rustaigent/templates/`; the project's wins on a name clash.
+
+use std::{fs, path::PathBuf};
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use serde_json::Value;
+
+/// One `[[seed]]` entry: a tool run now whose call and output join the conversation, or a
+/// message added as written
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SeedEntry {
+    tool: Option<String>,
+    #[serde(default)]
+    args: Option<toml::Value>,
+    role: Option<String>,
+    content: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct File {
+    description: Option<String>,
+    prompt: Option<String>,
+    #[serde(default)]
+    seed: Vec<SeedEntry>,
+}
+
+pub enum Seed {
+    Tool { name: String, args: Value },
+    Message { role: String, content: String },
+}
+
+pub struct Template {
+    pub name: String,
+    pub description: Option<String>,
+    /// Instructions appended to the system prompt
+    pub prompt: Option<String>,
+    pub seeds: Vec<Seed>,
+}
+
+/// Template directories, most specific first
+fn dirs() -> Vec<PathBuf> {
+    let project = std::env::current_dir().ok().and_then(|cwd| {
+        cwd.ancestors().map(|dir| dir.join(".rustaigent").join("templates")).find(|d| d.is_dir())
+    });
+    let personal = crate::paths::user_config_file().parent().map(|dir| dir.join("templates"));
+    project.into_iter().chain(personal).collect()
+}
+
+fn parse(name: &str, text: &str) -> Result<Template> {
+    let file: File = toml::from_str(text)?;
+    let seeds = file.seed.into_iter().enumerate().map(|(i, entry)| match entry {
+        SeedEntry { tool: Some(name), args, role: None, content: None } => {
+            let args = match args {
+                Some(args) => serde_json::to_value(args)?,
+                None => Value::Object(Default::default()),
+            };
+            Ok(Seed::Tool { name, args })
+        }
+        SeedEntry { tool: None, args: None, role: Some(role), content: Some(content) } => {
+            if role != "user" && role != "assistant" {
+                bail!("seed {}: `role` must be `user` or `assistant` (found `{}`)", i + 1, role);
+            }
+            Ok(Seed::Message { role, content })
+        }
+        _ => bail!("seed {}: give either `tool` (with optional `args`) or `role` and `content`", i + 1),
+    }).collect::<Result<_>>()?;
+    Ok(Template { name: name.into(), description: file.description, prompt: file.prompt, seeds })
+}
+
+/// The template called `name`
+pub fn load(name: &str) -> Result<Template> {
+    if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+        bail!("`{}` is not a template name", name);
+    }
+    let Some(path) = dirs().into_iter().map(|dir| dir.join(format!("{}.toml", name))).find(|p| p.is_file()) else {
+        let known: Vec<String> = list().into_iter().map(|t| t.0).collect();
+        let hint = crate::tools::hints::did_you_mean(name, known.iter().map(String::as_str));
+        bail!("No template `{}`; /template lists them.{}", name, hint);
+    };
+    let text = fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+    parse(name, &text).with_context(|| format!("Invalid template {}", path.display()))
+}
+
+/// Names, descriptions and files of the available templates, sorted by name
+pub fn list() -> Vec<(String, Option<String>, PathBuf)> {
+    let mut found: Vec<(String, Option<String>, PathBuf)> = Vec::new();
+    for dir in dirs() {
+        let Ok(entries) = fs::read_dir(&dir) else { continue };
+        for path in entries.flatten().map(|e| e.path()).filter(|p| p.extension().is_some_and(|e| e == "toml")) {
+            let Some(name) = path.file_stem().and_then(|s| s.to_str()).map(String::from) else { continue };
+            if found.iter().any(|(n, ..)| *n == name) {
+                continue;
+            }
+            let description = fs::read_to_string(&path).ok()
+                .and_then(|text| parse(&name, &text).ok())
+                .and_then(|t| t.description);
+            found.push((name, description, path));
+        }
+    }
+    found.sort_by(|a, b| a.0.cmp(&b.0));
+    found
+}
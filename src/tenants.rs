@@ -0,0 +1,349 @@
+//! Users of `rustaigent serve`: who they are, what their sessions may do and how much they may
+//! spend. Callers present a static API key, stored as its SHA-256, or an OIDC access token issued to
+//! our client that the issuer's userinfo endpoint vouches for.
+
+use std::{collections::{BTreeMap, HashMap}, fs, path::PathBuf, sync::Mutex, time::{Duration, Instant}};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use anyhow::{Result, Context, bail};
+use tokio::sync::OnceCell;
+use crate::{paths, policy::Action};
+
+/// How long an OIDC token stays accepted after the issuer last confirmed it
+const OIDC_CACHE_TTL: Duration = Duration::from_secs(300);
+
+/// `[serve]` table: where the server listens and who may use it
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct ServeConfig {
+    /// Address and port to listen on; `127.0.0.1:8080` when unset
+    pub bind: Option<String>,
+    /// Directory holding one workspace per user; `workspaces` in the data directory when unset
+    pub workspaces: Option<PathBuf>,
+    /// Issuer whose access tokens are accepted, e.g. `https://accounts.example.com`
+    pub oidc_issuer: Option<String>,
+    /// Client ID that accepted tokens must be issued to, by their `aud` or `azp` claim
+    pub oidc_client_id: Option<String>,
+    /// Sessions one user may keep open; 4 when unset
+    pub max_sessions: Option<usize>,
+    pub users: Option<BTreeMap<String, User>>,
+}
+
+/// Keys accepted in `[serve]`, for config validation
+pub const KEYS: &[&str] = &["bind", "workspaces", "oidc_issuer", "oidc_client_id", "max_sessions", "users"];
+
+/// One `[serve.users.<name>]` table
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct User {
+    /// Hex SHA-256 of the user's API key, e.g. from `printf %s "$KEY" | sha256sum`
+    pub key_sha256: Option<String>,
+    /// OIDC `sub` claims, or `email` claims the issuer has verified, that sign in as this user
+    pub oidc: Option<Vec<String>>,
+    /// Tools offered in the user's sessions; when unset, the built-in tools except those that start processes
+    pub tools: Option<Vec<String>>,
+    /// `allow`, `ask` or `deny` per tool, over `[permissions]`; calls that would ask need a WebSocket client to approve them
+    pub permissions: Option<BTreeMap<String, Action>>,
+    /// Role the `[[policy]]` rules see for this user
+    pub role: Option<String>,
+    /// USD the user may spend per calendar month (UTC)
+    pub monthly_budget_usd: Option<f64>,
+    /// Turns the user may run per day (UTC)
+    pub daily_requests: Option<u64>,
+}
+
+/// Keys accepted in `[serve.users.<name>]`, for config validation
+pub const USER_KEYS: &[&str] = &["key_sha256", "oidc", "tools", "permissions", "role", "monthly_budget_usd", "daily_requests"];
+
+impl ServeConfig {
+    /// Users with a way to sign in, names usable as directory names, and OIDC only with an issuer
+    pub fn check(&self) -> Result<()> {
+        if let Some(issuer) = &self.oidc_issuer {
+            if !secure(issuer) {
+                bail!("`oidc_issuer` must start with https:// (found `{}`)", issuer);
+            }
+            if self.oidc_client_id.is_none() {
+                bail!("`oidc_issuer` needs `oidc_client_id`, so tokens issued to other applications are refused");
+            }
+        }
+        if self.max_sessions == Some(0) {
+            bail!("`max_sessions` must be at least 1");
+        }
+        for (name, user) in self.users.iter().flatten() {
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+                bail!("user `{}`: names may only hold letters, digits, `-` and `_`", name);
+            }
+            match &user.key_sha256 {
+                Some(hash) if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) => {
+                    bail!("user `{}`: `key_sha256` must be 64 hex digits", name);
+                }
+                None if user.oidc.is_none() => bail!("user `{}` needs `key_sha256` or `oidc` to sign in", name),
+                _ => {}
+            }
+            if user.oidc.is_some() && self.oidc_issuer.is_none() {
+                bail!("user `{}`: `oidc` needs `serve.oidc_issuer`", name);
+            }
+            if user.monthly_budget_usd.is_some_and(|b| b < 0.0) {
+                bail!("user `{}`: `monthly_budget_usd` must not be negative", name);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn sha256_hex(text: &str) -> String {
+    Sha256::digest(text.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// The configured users and the tokens recently confirmed for them
+pub struct Tenants {
+    pub users: BTreeMap<String, User>,
+    issuer: Option<String>,
+    client_id: String,
+    client: reqwest::Client,
+    userinfo: OnceCell<String>,
+    /// User by SHA-256 of an OIDC token, with when the issuer confirmed it
+    confirmed: Mutex<HashMap<String, (String, Instant)>>,
+}
+
+impl Tenants {
+    pub fn new(cfg: &ServeConfig, client: reqwest::Client) -> Self {
+        Tenants {
+            users: cfg.users.clone().unwrap_or_default(),
+            issuer: cfg.oidc_issuer.as_ref().map(|i| i.trim_end_matches('/').to_string()),
+            client_id: cfg.oidc_client_id.clone().unwrap_or_default(),
+            client,
+            userinfo: OnceCell::new(),
+            confirmed: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The user a bearer token belongs to; `None` when it is neither a known key nor a token the
+    /// issuer accepts, issued to our client, for a configured user
+    pub async fn authenticate(&self, token: &str) -> Result<Option<String>> {
+        let hash = sha256_hex(token);
+        let by_key = self.users.iter().find(|(_, u)| u.key_sha256.as_ref().is_some_and(|h| h.eq_ignore_ascii_case(&hash)));
+        if let Some((name, _)) = by_key {
+            return Ok(Some(name.clone()));
+        }
+        if self.issuer.is_none() {
+            return Ok(None);
+        }
+        if let Some((name, at)) = self.confirmed.lock().unwrap().get(&hash) {
+            if at.elapsed() < OIDC_CACHE_TTL {
+                return Ok(Some(name.clone()));
+            }
+        }
+        let Some(claims) = self.userinfo(token).await? else {
+            return Ok(None);
+        };
+        if !issued_to(&token_claims(token).unwrap_or(serde_json::Value::Null), &self.client_id) && !issued_to(&claims, &self.client_id) {
+            return Ok(None);
+        }
+        // An unverified email is whatever the account holder typed in
+        let email = claims["email"].as_str().filter(|_| claims["email_verified"] == serde_json::Value::Bool(true));
+        let ids: Vec<&str> = claims["sub"].as_str().into_iter().chain(email).collect();
+        let user = self.users.iter()
+            .find(|(_, u)| u.oidc.iter().flatten().any(|id| ids.contains(&id.as_str())))
+            .map(|(name, _)| name.clone());
+        if let Some(name) = &user {
+            let mut confirmed = self.confirmed.lock().unwrap();
+            confirmed.retain(|_, (_, at)| at.elapsed() < OIDC_CACHE_TTL);
+            confirmed.insert(hash, (name.clone(), Instant::now()));
+        }
+        Ok(user)
+    }
+
+    /// Claims of the token's subject, or `None` when the issuer rejects the token
+    async fn userinfo(&self, token: &str) -> Result<Option<serde_json::Value>> {
+        let issuer = self.issuer.as_deref().unwrap_or_default();
+        let endpoint = self.userinfo.get_or_try_init(|| async {
+            let url = format!("{}/.well-known/openid-configuration", issuer);
+            let discovery: serde_json::Value = self.client.get(&url).send().await?.error_for_status()?.json().await
+                .with_context(|| format!("OIDC discovery failed at {}", url))?;
+            let endpoint = discovery["userinfo_endpoint"].as_str().with_context(|| format!("{} names no userinfo_endpoint", url))?;
+            if !secure(endpoint) {
+                bail!("{} names a userinfo_endpoint without https: {}", url, endpoint);
+            }
+            Ok(endpoint.to_string())
+        }).await?;
+        let resp = self.client.get(endpoint).bearer_auth(token).send().await.context("OIDC userinfo request failed")?;
+        if matches!(resp.status(), reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN) {
+            return Ok(None);
+        }
+        Ok(Some(resp.error_for_status()?.json().await?))
+    }
+}
+
+/// Whether bearer tokens may be sent to `url`: https, or plain http to this machine
+fn secure(url: &str) -> bool {
+    let Ok(url) = url::Url::parse(url) else { return false };
+    match (url.scheme(), url.host()) {
+        ("https", Some(_)) => true,
+        ("http", Some(url::Host::Domain(host))) => host == "localhost",
+        ("http", Some(url::Host::Ipv4(ip))) => ip.is_loopback(),
+        ("http", Some(url::Host::Ipv6(ip))) => ip.is_loopback(),
+        _ => false,
+    }
+}
+
+/// Payload of a JWT access token; `None` for opaque tokens. The issuer's userinfo endpoint has
+/// already checked the signature by the time these are trusted
+fn token_claims(token: &str) -> Option<serde_json::Value> {
+    let payload = token.split('.').nth(1)?;
+    serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload.trim_end_matches('=')).ok()?).ok()
+}
+
+/// Whether the claims name `client_id` as their audience or authorized party
+fn issued_to(claims: &serde_json::Value, client_id: &str) -> bool {
+    let audience = match &claims["aud"] {
+        serde_json::Value::String(aud) => aud == client_id,
+        serde_json::Value::Array(aud) => aud.iter().any(|a| a.as_str() == Some(client_id)),
+        _ => false,
+    };
+    audience || claims["azp"].as_str() == Some(client_id)
+}
+
+/// What one user has used in the current month and day
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct Usage {
+    pub month: String,
+    pub cost_usd: f64,
+    pub day: String,
+    pub requests: u64,
+}
+
+/// Usage per user, kept in the data directory so restarts don't reset budgets
+#[derive(Default)]
+pub struct Ledger {
+    users: BTreeMap<String, Usage>,
+}
+
+fn ledger_file() -> PathBuf {
+    paths::data_dir().join("serve-usage.json")
+}
+
+impl Ledger {
+    pub fn load() -> Result<Self> {
+        let path = ledger_file();
+        if !path.is_file() {
+            return Ok(Ledger::default());
+        }
+        let users = serde_json::from_str(&fs::read_to_string(&path)?).with_context(|| format!("Corrupt usage ledger {}", path.display()))?;
+        Ok(Ledger { users })
+    }
+
+    fn save(&self) -> Result<()> {
+        let path = ledger_file();
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(&path, serde_json::to_vec_pretty(&self.users)?).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// The user's usage, with the month's cost and the day's requests reset when they have passed
+    pub fn current(&self, name: &str) -> Usage {
+        let now = chrono::Utc::now();
+        let (month, day) = (now.format("%Y-%m").to_string(), now.format("%Y-%m-%d").to_string());
+        let mut usage = self.users.get(name).cloned().unwrap_or_default();
+        if usage.month != month {
+            usage.month = month;
+            usage.cost_usd = 0.0;
+        }
+        if usage.day != day {
+            usage.day = day;
+            usage.requests = 0;
+        }
+        usage
+    }
+
+    /// Why the user may not start another turn, if they have reached a limit
+    pub fn refusal(&self, name: &str, user: &User) -> Option<String> {
+        let usage = self.current(name);
+        if let Some(budget) = user.monthly_budget_usd.filter(|b| usage.cost_usd >= *b) {
+            return Some(format!("monthly budget of ${:.2} reached (${:.4} spent)", budget, usage.cost_usd));
+        }
+        if let Some(limit) = user.daily_requests.filter(|l| usage.requests >= *l) {
+            return Some(format!("daily limit of {} requests reached", limit));
+        }
+        None
+    }
+
+    /// Count a finished turn and its cost
+    pub fn record(&mut self, name: &str, cost_usd: f64) {
+        let mut usage = self.current(name);
+        usage.cost_usd += cost_usd;
+        usage.requests += 1;
+        self.users.insert(name.to_string(), usage);
+        if let Err(err) = self.save() {
+            tracing::warn!("failed to save serve usage: {:#}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::Engine;
+    use serde_json::json;
+    use wiremock::{matchers::{header, method, path}, Mock, MockServer, ResponseTemplate};
+    use super::{issued_to, secure, sha256_hex, ServeConfig, Tenants, User};
+
+    fn tenants(issuer: Option<String>) -> Tenants {
+        let users = [
+            ("alice".to_string(), User { key_sha256: Some(sha256_hex("alice-key").to_uppercase()), ..Default::default() }),
+            ("bob".to_string(), User { oidc: Some(vec!["sub-bob".into()]), ..Default::default() }),
+            ("carol".to_string(), User { oidc: Some(vec!["carol@example.com".into()]), ..Default::default() }),
+        ];
+        let cfg = ServeConfig { oidc_issuer: issuer, oidc_client_id: Some("rustaigent".into()), users: Some(users.into()), ..Default::default() };
+        Tenants::new(&cfg, reqwest::Client::new())
+    }
+
+    #[tokio::test]
+    async fn api_keys_match_by_their_hash() {
+        let tenants = tenants(None);
+        assert_eq!(tenants.authenticate("alice-key").await.unwrap().as_deref(), Some("alice"));
+        assert_eq!(tenants.authenticate("alice-key ").await.unwrap(), None);
+        assert_eq!(tenants.authenticate(&sha256_hex("alice-key")).await.unwrap(), None);
+    }
+
+    #[test]
+    fn tokens_are_issued_to_the_client_by_audience_or_authorized_party() {
+        assert!(issued_to(&json!({"aud": "rustaigent"}), "rustaigent"));
+        assert!(issued_to(&json!({"aud": ["api", "rustaigent"]}), "rustaigent"));
+        assert!(issued_to(&json!({"aud": "api", "azp": "rustaigent"}), "rustaigent"));
+        assert!(!issued_to(&json!({"aud": ["api"], "azp": "other"}), "rustaigent"));
+        assert!(!issued_to(&json!({"sub": "sub-bob"}), "rustaigent"));
+    }
+
+    #[test]
+    fn tokens_go_to_the_issuer_over_https_or_to_this_machine() {
+        assert!(secure("https://accounts.example.com") && secure("http://127.0.0.1:8080") && secure("http://[::1]:9000") && secure("http://localhost/realm"));
+        assert!(!secure("http://accounts.example.com") && !secure("http://10.0.0.5") && !secure("accounts.example.com") && !secure("ftp://localhost"));
+        let config = ServeConfig { oidc_issuer: Some("http://accounts.example.com".into()), oidc_client_id: Some("rustaigent".into()), ..Default::default() };
+        assert!(config.check().unwrap_err().to_string().contains("must start with https://"));
+    }
+
+    #[tokio::test]
+    async fn oidc_tokens_need_our_audience_and_a_verified_email() {
+        let issuer = MockServer::start().await;
+        let discovery = json!({"userinfo_endpoint": format!("{}/userinfo", issuer.uri())});
+        Mock::given(method("GET")).and(path("/.well-known/openid-configuration")).respond_with(ResponseTemplate::new(200).set_body_json(discovery)).mount(&issuer).await;
+        let jwt = format!("header.{}.signature", base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(json!({"aud": "rustaigent"}).to_string()));
+        let claims = [
+            ("ours", json!({"sub": "sub-bob", "aud": "rustaigent"})),
+            ("other-app", json!({"sub": "sub-bob", "aud": "other-app"})),
+            (jwt.as_str(), json!({"sub": "sub-bob"})),
+            ("unverified", json!({"sub": "sub-x", "azp": "rustaigent", "email": "carol@example.com", "email_verified": false})),
+            ("verified", json!({"sub": "sub-x", "azp": "rustaigent", "email": "carol@example.com", "email_verified": true})),
+        ];
+        for (token, claims) in &claims {
+            Mock::given(method("GET")).and(path("/userinfo")).and(header("authorization", format!("Bearer {}", token).as_str()))
+                .respond_with(ResponseTemplate::new(200).set_body_json(claims)).mount(&issuer).await;
+        }
+        Mock::given(method("GET")).and(path("/userinfo")).respond_with(ResponseTemplate::new(401)).with_priority(10).mount(&issuer).await;
+        let tenants = tenants(Some(issuer.uri()));
+        let mut users = Vec::new();
+        for token in ["ours", "other-app", jwt.as_str(), "unverified", "verified", "revoked"] {
+            users.push(tenants.authenticate(token).await.unwrap());
+        }
+        let users: Vec<Option<&str>> = users.iter().map(Option::as_deref).collect();
+        assert_eq!(users, [Some("bob"), None, Some("bob"), None, Some("carol"), None]);
+    }
+}
@@ -0,0 +1,210 @@
+use std::{env, io::IsTerminal};
+use serde::{Serialize, Deserialize};
+use anyhow::{Result, bail};
+
+/// `[theme]` table in the config file; each value is a color spec such as
+/// `"bold green"`, `"208"` (256-color index) or `"#ff8800"`
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct ThemeConfig {
+    pub user: Option<String>,
+    pub assistant: Option<String>,
+    pub tool: Option<String>,
+    pub error: Option<String>,
+    /// Model reasoning shown with `show_thinking`
+    pub thinking: Option<String>,
+    pub diff_add: Option<String>,
+    pub diff_remove: Option<String>,
+    pub diff_hunk: Option<String>,
+    /// Background behind fenced code blocks in replies
+    pub code_bg: Option<String>,
+}
+
+/// Keys accepted in `[theme]`, for config validation
+pub const KEYS: &[&str] = &["user", "assistant", "tool", "error", "thinking", "diff_add", "diff_remove", "diff_hunk", "code_bg"];
+
+#[derive(Clone, Copy)]
+pub enum Role {
+    User,
+    Assistant,
+    Tool,
+    Error,
+    Thinking,
+}
+
+/// Resolved SGR sequences; painting is a no-op when colors are disabled
+#[derive(Clone)]
+pub struct Theme {
+    enabled: bool,
+    user: String,
+    assistant: String,
+    tool: String,
+    error: String,
+    thinking: String,
+    diff_add: String,
+    diff_remove: String,
+    diff_hunk: String,
+    code_bg: String,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            enabled: false,
+            user: "1;32".into(),
+            assistant: "1;34".into(),
+            tool: "33".into(),
+            error: "1;31".into(),
+            thinking: "2;3".into(),
+            diff_add: "32".into(),
+            diff_remove: "31".into(),
+            diff_hunk: "36".into(),
+            code_bg: "48;5;236".into(),
+        }
+    }
+}
+
+const NAMES: &[&str] = &["black", "red", "green", "yellow", "blue", "magenta", "cyan", "white"];
+
+/// Translate one color spec into SGR parameters
+pub fn parse_spec(spec: &str, background: bool) -> Result<String> {
+    let mut codes = Vec::new();
+    for word in spec.split_whitespace() {
+        let lower = word.to_lowercase();
+        let code = match lower.as_str() {
+            "bold" => "1".to_string(),
+            "dim" => "2".to_string(),
+            "italic" => "3".to_string(),
+            "underline" => "4".to_string(),
+            hex if hex.starts_with('#') && hex.len() == 7 => {
+                let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16);
+                match (channel(1), channel(3), channel(5)) {
+                    (Ok(r), Ok(g), Ok(b)) => format!("{};2;{};{};{}", if background { 48 } else { 38 }, r, g, b),
+                    _ => bail!("invalid hex color `{}`", word),
+                }
+            }
+            index if index.parse::<u8>().is_ok() => format!("{};5;{}", if background { 48 } else { 38 }, index),
+            name => {
+                let (bright, base) = match name.strip_prefix("bright_") {
+                    Some(base) => (true, base),
+                    None => (false, name),
+                };
+                let Some(n) = NAMES.iter().position(|c| *c == base) else {
+                    bail!("unknown color `{}` (use a name like `bright_blue`, a 0-255 index or `#rrggbb`)", word);
+                };
+                let offset = match (background, bright) {
+                    (false, false) => 30,
+                    (false, true) => 90,
+                    (true, false) => 40,
+                    (true, true) => 100,
+                };
+                (offset + n).to_string()
+            }
+        };
+        codes.push(code);
+    }
+    if codes.is_empty() {
+        bail!("empty color spec");
+    }
+    Ok(codes.join(";"))
+}
+
+/// Colors are used only on a terminal and when `NO_COLOR` is unset or empty
+pub fn colors_supported() -> bool {
+    colors_supported_on(std::io::stdout())
+}
+
+/// Whether `stream` may carry colors, as `colors_supported` decides for stdout
+pub fn colors_supported_on(stream: impl IsTerminal) -> bool {
+    env::var_os("NO_COLOR").is_none_or(|v| v.is_empty()) && stream.is_terminal()
+}
+
+impl Theme {
+    /// Apply config overrides on top of the default palette
+    pub fn from_config(cfg: Option<&ThemeConfig>, enabled: bool) -> Result<Self> {
+        let mut theme = Theme { enabled, ..Theme::default() };
+        let Some(cfg) = cfg else {
+            return Ok(theme);
+        };
+        let slots = [
+            (&cfg.user, &mut theme.user, false),
+            (&cfg.assistant, &mut theme.assistant, false),
+            (&cfg.tool, &mut theme.tool, false),
+            (&cfg.error, &mut theme.error, false),
+            (&cfg.thinking, &mut theme.thinking, false),
+            (&cfg.diff_add, &mut theme.diff_add, false),
+            (&cfg.diff_remove, &mut theme.diff_remove, false),
+            (&cfg.diff_hunk, &mut theme.diff_hunk, false),
+            (&cfg.code_bg, &mut theme.code_bg, true),
+        ];
+        for (spec, slot, background) in slots {
+            if let Some(spec) = spec {
+                *slot = parse_spec(spec, background)?;
+            }
+        }
+        Ok(theme)
+    }
+
+    /// Theme for this process: configured colors when the output supports them, plain otherwise
+    pub fn detect(cfg: Option<&ThemeConfig>) -> Self {
+        let enabled = colors_supported();
+        Theme::from_config(cfg, enabled).unwrap_or_else(|err| {
+            tracing::warn!("ignoring theme: {:#}", err);
+            Theme { enabled, ..Theme::default() }
+        })
+    }
+
+    fn sgr(&self, codes: &str, text: &str) -> String {
+        if self.enabled && !text.is_empty() {
+            format!("\x1b[{}m{}\x1b[0m", codes, text)
+        } else {
+            text.to_string()
+        }
+    }
+
+    pub fn paint(&self, role: Role, text: &str) -> String {
+        let codes = match role {
+            Role::User => &self.user,
+            Role::Assistant => &self.assistant,
+            Role::Tool => &self.tool,
+            Role::Error => &self.error,
+            Role::Thinking => &self.thinking,
+        };
+        self.sgr(codes, text)
+    }
+
+    /// Color added, removed and hunk-header lines of a unified diff
+    pub fn diff(&self, text: &str) -> String {
+        if !self.enabled {
+            return text.to_string();
+        }
+        text.lines().map(|line| {
+            if line.starts_with("+++") || line.starts_with("---") {
+                self.sgr("1", line)
+            } else if line.starts_with('+') {
+                self.sgr(&self.diff_add, line)
+            } else if line.starts_with('-') {
+                self.sgr(&self.diff_remove, line)
+            } else if line.starts_with("@@") {
+                self.sgr(&self.diff_hunk, line)
+            } else {
+                line.to_string()
+            }
+        }).collect::<Vec<_>>().join("\n")
+    }
+
+    /// Shade fenced code blocks in a reply with the code background
+    pub fn reply(&self, text: &str) -> String {
+        if !self.enabled {
+            return text.to_string();
+        }
+        let mut in_code = false;
+        text.lines().map(|line| {
+            let fence = line.trim_start().starts_with("```");
+            let shade = in_code || fence;
+            if fence {
+                in_code = !in_code;
+            }
+            if shade { self.sgr(&self.code_bg, line) } else { line.to_string() }
+        }).collect::<Vec<_>>().join("\n")
+    }
+}
@@ -0,0 +1,175 @@
+//! The Rust toolchain a project targets: its edition, minimum supported Rust version, pinned
+//! channel and enabled lints, read from `Cargo.toml`, `rust-toolchain.toml` and `clippy.toml`.
+//! They are described in the system prompt, so suggestions do not quietly need nightly or a newer
+//! Rust, and checked again where code is verified.
+
+use std::{fs, path::Path, process::Command};
+use crate::workspace;
+
+/// What the project's manifests say about the Rust it builds with
+#[derive(Default)]
+pub struct Toolchain {
+    pub edition: Option<String>,
+    /// `rust-version`, or clippy's `msrv` when the manifest has none
+    pub rust_version: Option<String>,
+    /// Channel pinned by `rust-toolchain.toml` or `rust-toolchain`
+    pub channel: Option<String>,
+    /// Lints raised above `allow`, as `name level`
+    pub lints: Vec<String>,
+    /// Settings from `clippy.toml` other than `msrv`
+    pub clippy: Vec<String>,
+}
+
+fn read(path: &Path) -> Option<toml::Table> {
+    toml::from_str(&fs::read_to_string(path).ok()?).ok()
+}
+
+/// `key` of `[package]`, following `key.workspace = true` to `[workspace.package]` of the root
+fn inherited(package: &toml::Table, root: Option<&toml::Table>, key: &str) -> Option<String> {
+    let value = package.get(key)?;
+    if let Some(value) = value.as_str() {
+        return Some(value.to_string());
+    }
+    if value.get("workspace").and_then(|w| w.as_bool()) == Some(true) {
+        return root?.get("workspace")?.get("package")?.get(key)?.as_str().map(String::from);
+    }
+    None
+}
+
+/// Lints of a `[lints]` table that are not `allow`, prefixed with their tool
+fn lints(table: &toml::Table) -> Vec<String> {
+    let mut found = Vec::new();
+    for (tool, lints) in table.iter().filter_map(|(tool, lints)| Some((tool, lints.as_table()?))) {
+        for (name, level) in lints {
+            // `name = "deny"` or `name = { level = "deny", priority = -1 }`
+            let Some(level) = level.as_str().or_else(|| level.get("level")?.as_str()) else { continue };
+            if level == "allow" {
+                continue;
+            }
+            let name = if tool == "rust" { name.clone() } else { format!("{}::{}", tool, name) };
+            found.push(format!("`{}` {}", name, level));
+        }
+    }
+    found
+}
+
+fn clippy_setting(key: &str, value: &toml::Value) -> String {
+    match value.as_array() {
+        // `disallowed-methods = ["std::env::set_var", { path = "..." , reason = "..." }]`
+        Some(items) => {
+            let items: Vec<String> = items.iter()
+                .filter_map(|item| item.as_str().or_else(|| item.get("path")?.as_str()))
+                .map(|item| format!("`{}`", item))
+                .collect();
+            format!("{}: {}", key, items.join(", "))
+        }
+        None => format!("`{} = {}`", key, value),
+    }
+}
+
+/// The channel a `rust-toolchain.toml` or legacy `rust-toolchain` file pins
+fn pinned(dir: &Path) -> Option<String> {
+    if let Some(table) = read(&dir.join("rust-toolchain.toml")) {
+        return table.get("toolchain")?.get("channel")?.as_str().map(String::from);
+    }
+    let text = fs::read_to_string(dir.join("rust-toolchain")).ok()?;
+    match toml::from_str::<toml::Table>(&text) {
+        Ok(table) => table.get("toolchain")?.get("channel")?.as_str().map(String::from),
+        Err(_) => Some(text.trim().to_string()).filter(|channel| !channel.is_empty()),
+    }
+}
+
+impl Toolchain {
+    /// The toolchain of the package around `dir`, or of its workspace from a virtual manifest;
+    /// `None` outside a cargo project
+    pub fn detect(dir: &Path) -> Option<Toolchain> {
+        let (manifest_dir, manifest) = dir.ancestors().find_map(|d| Some((d, read(&d.join("Cargo.toml"))?)))?;
+        let root = workspace::Workspace::detect(dir).and_then(|ws| read(&ws.root.join("Cargo.toml")));
+        let ws = |key: &str| root.as_ref()?.get("workspace")?.get(key)?.as_table();
+        let mut toolchain = Toolchain::default();
+        match manifest.get("package").and_then(|p| p.as_table()) {
+            Some(package) => {
+                // Cargo's default for manifests that name no edition
+                toolchain.edition = Some(inherited(package, root.as_ref(), "edition").unwrap_or_else(|| "2015".into()));
+                toolchain.rust_version = inherited(package, root.as_ref(), "rust-version");
+            }
+            None => {
+                let package = ws("package");
+                toolchain.edition = package.and_then(|p| p.get("edition")?.as_str()).map(String::from);
+                toolchain.rust_version = package.and_then(|p| p.get("rust-version")?.as_str()).map(String::from);
+            }
+        }
+        let lints = manifest.get("lints").and_then(|l| l.as_table());
+        toolchain.lints = match lints {
+            Some(lints) if lints.get("workspace").and_then(|w| w.as_bool()) == Some(true) => ws("lints").map(self::lints).unwrap_or_default(),
+            Some(lints) => self::lints(lints),
+            None if manifest.get("package").is_none() => ws("lints").map(self::lints).unwrap_or_default(),
+            None => Vec::new(),
+        };
+        // rustup and clippy look for their files in the ancestors of where they run
+        let mut dirs = manifest_dir.ancestors();
+        toolchain.channel = dirs.clone().find_map(pinned);
+        if let Some(clippy) = dirs.find_map(|d| read(&d.join("clippy.toml")).or_else(|| read(&d.join(".clippy.toml")))) {
+            for (key, value) in &clippy {
+                match key.as_str() {
+                    "msrv" => {
+                        if toolchain.rust_version.is_none() {
+                            toolchain.rust_version = value.as_str().map(String::from);
+                        }
+                    }
+                    _ => toolchain.clippy.push(clippy_setting(key, value)),
+                }
+            }
+        }
+        Some(toolchain)
+    }
+
+    /// Nightly features are allowed only on a pinned nightly channel
+    pub fn nightly(&self) -> bool {
+        self.channel.as_deref().is_some_and(|channel| channel.starts_with("nightly"))
+    }
+
+    /// System prompt paragraph on what the code has to build with
+    pub fn describe(&self) -> String {
+        let mut lines = Vec::new();
+        if let Some(edition) = &self.edition {
+            lines.push(format!("- edition {}", edition));
+        }
+        if let Some(msrv) = &self.rust_version {
+            lines.push(format!("- minimum supported Rust version {}: use no language features, standard library APIs or Cargo features stabilized after Rust {}", msrv, msrv));
+        }
+        if let Some(channel) = &self.channel {
+            lines.push(format!("- toolchain pinned to `{}` by rust-toolchain", channel));
+        }
+        if !self.nightly() {
+            lines.push("- stable Rust: no `#![feature(...)]` attributes or other nightly-only features".into());
+        }
+        if !self.lints.is_empty() {
+            lines.push(format!("- lints in Cargo.toml: {}", self.lints.join(", ")));
+        }
+        if !self.clippy.is_empty() {
+            lines.push(format!("- clippy.toml: {}", self.clippy.join("; ")));
+        }
+        let lints = if self.lints.is_empty() && self.clippy.is_empty() { "" } else { " without tripping these lints" };
+        format!("Rust toolchain of this project:\n{}\nWrite code that builds with it{}.", lines.join("\n"), lints)
+    }
+
+    /// Problems of a generated Rust block this toolchain would reject
+    pub fn check(&self, code: &str) -> Option<String> {
+        (!self.nightly() && code.contains("#![feature(")).then(|| {
+            "A Rust code block uses `#![feature(...)]`, which needs nightly; this project builds on stable Rust, so use stable APIs instead".to_string()
+        })
+    }
+
+    /// `cargo +<msrv> check --all-targets` when the project names a minimum Rust version and
+    /// rustup has that toolchain installed
+    pub fn msrv_check(&self) -> Option<String> {
+        let msrv = self.rust_version.as_deref()?;
+        let installed = Command::new("rustup").args(["run", msrv, "cargo", "--version"]).output().is_ok_and(|out| out.status.success());
+        if !installed {
+            tracing::info!("Rust {} is not installed, so code is not checked against the minimum supported version; `rustup toolchain install {}` adds it", msrv, msrv);
+            return None;
+        }
+        Some(format!("cargo +{} check --all-targets", msrv))
+    }
+}
@@ -0,0 +1,41 @@
+//! Approvals delegated to `approval_command`: the program gets the proposed action as a JSON
+//! argument and decides with its exit status, so a chat bot or an organization's policy service
+//! can approve calls in place of the terminal prompt.
+
+use std::process::{Command, Stdio};
+use serde_json::Value;
+
+/// Exit status of an approval command that leaves the decision to the usual prompt
+const UNDECIDED: i32 = 2;
+
+/// The command's decision on `action`, with the first line it printed as the reason; `None`
+/// when it exits with 2 to pass. Anything but 0, 1 or 2 counts as a denial, so a broken
+/// command fails closed.
+pub fn delegate(command: &str, action: &Value) -> Option<(bool, Option<String>)> {
+    let run = || Command::new("sh").arg("-c").arg(format!("{} \"$1\"", command)).arg("sh").arg(action.to_string())
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output();
+    // Chat approvals can take minutes; keep the runtime's other tasks moving meanwhile
+    let output = match tokio::runtime::Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == tokio::runtime::RuntimeFlavor::MultiThread => tokio::task::block_in_place(run),
+        _ => run(),
+    };
+    let output = match output {
+        Ok(output) => output,
+        Err(err) => {
+            tracing::warn!("declined: the approval command `{}` could not run: {}", command, err);
+            return Some((false, Some("the approval command could not run".into())));
+        }
+    };
+    let reason = String::from_utf8_lossy(&output.stdout).lines().map(str::trim).find(|l| !l.is_empty()).map(String::from);
+    match output.status.code() {
+        Some(0) => Some((true, reason)),
+        Some(1) => Some((false, reason)),
+        Some(UNDECIDED) => None,
+        _ => {
+            tracing::warn!("declined: the approval command `{}` failed ({})", command, output.status);
+            Some((false, reason.or_else(|| Some(format!("the approval command failed ({})", output.status)))))
+        }
+    }
+}
@@ -0,0 +1,298 @@
+use std::{fs::{self, File}, io::{self, Read}, path::{Component, Path, PathBuf}};
+use anyhow::{Result, Context, bail};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+
+/// Most bytes one extraction writes, so a zip bomb cannot fill the disk
+const MAX_EXTRACTED_BYTES: u64 = 1 << 30;
+/// Most entries, files and directories, one extraction creates
+const MAX_ENTRIES: usize = 20_000;
+
+enum Format {
+    Zip,
+    TarGz,
+    Tar,
+}
+
+fn detect_format(path: &str) -> Result<Format> {
+    let lower = path.to_lowercase();
+    if lower.ends_with(".zip") {
+        Ok(Format::Zip)
+    } else if lower.ends_with(".tar.gz") || lower.ends_with(".tgz") {
+        Ok(Format::TarGz)
+    } else if lower.ends_with(".tar") {
+        Ok(Format::Tar)
+    } else {
+        bail!("Unsupported archive format for `{}` (expected .zip, .tar.gz, .tgz or .tar)", path)
+    }
+}
+
+/// Reject entry names that would escape the destination directory
+fn safe_entry_path(name: &Path) -> Result<PathBuf> {
+    let mut out = PathBuf::new();
+    for comp in name.components() {
+        match comp {
+            Component::Normal(part) => out.push(part),
+            Component::CurDir => {}
+            _ => bail!("Refusing to extract `{}`: path escapes destination", name.display()),
+        }
+    }
+    Ok(out)
+}
+
+/// What an extraction may still write, per `MAX_EXTRACTED_BYTES` and `MAX_ENTRIES`
+struct Budget {
+    bytes: u64,
+    entries: usize,
+}
+
+impl Budget {
+    fn new() -> Self {
+        Budget { bytes: MAX_EXTRACTED_BYTES, entries: MAX_ENTRIES }
+    }
+
+    fn entry(&mut self) -> Result<()> {
+        self.entries = self.entries.checked_sub(1)
+            .with_context(|| format!("Refusing to extract more than {} entries", MAX_ENTRIES))?;
+        Ok(())
+    }
+
+    fn bytes(&mut self, len: u64) -> Result<()> {
+        self.bytes = self.bytes.checked_sub(len)
+            .with_context(|| format!("Refusing to extract more than {} MiB", MAX_EXTRACTED_BYTES >> 20))?;
+        Ok(())
+    }
+
+    /// Copy `from` into a new file at `to`, failing once the bytes run out; the sizes zip
+    /// entries declare are not trusted
+    fn copy(&mut self, from: impl Read, to: &Path) -> Result<()> {
+        let written = io::copy(&mut from.take(self.bytes + 1), &mut File::create(to)?)?;
+        if let Err(err) = self.bytes(written) {
+            fs::remove_file(to).ok();
+            return Err(err);
+        }
+        Ok(())
+    }
+}
+
+/// Extract a zip or tar(.gz) archive into `dest`, both relative to `dir`, returning a listing of
+/// extracted entries
+pub fn extract(dir: &Path, archive: &str, dest: &str) -> Result<String> {
+    extract_within(dir, archive, dest, Budget::new())
+}
+
+fn extract_within(dir: &Path, archive: &str, dest: &str, mut budget: Budget) -> Result<String> {
+    let shown = dest;
+    let dest = &dir.join(dest);
+    fs::create_dir_all(dest)?;
+    let file = File::open(dir.join(archive)).with_context(|| format!("Failed to open {}", archive))?;
+    let mut extracted = Vec::new();
+
+    match detect_format(archive)? {
+        Format::Zip => {
+            let mut zip = zip::ZipArchive::new(file)?;
+            for i in 0..zip.len() {
+                budget.entry()?;
+                let entry = zip.by_index(i)?;
+                let rel = entry.enclosed_name()
+                    .with_context(|| format!("Refusing to extract `{}`: path escapes destination", entry.name()))?;
+                let rel = safe_entry_path(&rel)?;
+                if entry.is_symlink() {
+                    bail!("Refusing to extract link entry `{}`", rel.display());
+                }
+                let target = dest.join(&rel);
+                if entry.is_dir() {
+                    fs::create_dir_all(&target)?;
+                    continue;
+                }
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                budget.copy(entry, &target)?;
+                extracted.push(rel.display().to_string());
+            }
+        }
+        Format::TarGz => extract_tar(tar::Archive::new(GzDecoder::new(file)), dest, &mut budget, &mut extracted)?,
+        Format::Tar => extract_tar(tar::Archive::new(file), dest, &mut budget, &mut extracted)?,
+    }
+
+    Ok(format!("Extracted {} files to {}:\n{}", extracted.len(), shown, extracted.join("\n")))
+}
+
+fn extract_tar<R: io::Read>(mut archive: tar::Archive<R>, dest: &Path, budget: &mut Budget, extracted: &mut Vec<String>) -> Result<()> {
+    for entry in archive.entries()? {
+        budget.entry()?;
+        let mut entry = entry?;
+        let kind = entry.header().entry_type();
+        if kind.is_symlink() || kind.is_hard_link() {
+            bail!("Refusing to extract link entry `{}`", entry.path()?.display());
+        }
+        let rel = safe_entry_path(&entry.path()?)?;
+        let target = dest.join(&rel);
+        if kind.is_dir() {
+            fs::create_dir_all(&target)?;
+            continue;
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // The size is what the entry takes up in the stream, so unlike a zip's it cannot lie
+        budget.bytes(entry.size())?;
+        entry.unpack(&target)?;
+        extracted.push(rel.display().to_string());
+    }
+    Ok(())
+}
+
+/// Create a zip or tar.gz archive from files and directories, stored relative to their parent;
+/// relative paths are taken from `dir`
+pub fn create(dir: &Path, archive: &str, paths: &[&str]) -> Result<String> {
+    let mut files = Vec::new();
+    for p in paths {
+        let path = dir.join(p);
+        let base = path.parent().unwrap_or(Path::new(""));
+        collect_files(&path, base, &mut files)?;
+    }
+
+    let out = File::create(dir.join(archive)).with_context(|| format!("Failed to create {}", archive))?;
+    match detect_format(archive)? {
+        Format::Zip => {
+            let mut zip = zip::ZipWriter::new(out);
+            let opts = zip::write::SimpleFileOptions::default();
+            for (src, name) in &files {
+                zip.start_file(name.to_string_lossy(), opts)?;
+                io::copy(&mut File::open(src)?, &mut zip)?;
+            }
+            zip.finish()?;
+        }
+        Format::TarGz => {
+            let mut tar = tar::Builder::new(GzEncoder::new(out, Compression::default()));
+            for (src, name) in &files {
+                tar.append_path_with_name(src, name)?;
+            }
+            tar.into_inner()?.finish()?;
+        }
+        Format::Tar => {
+            let mut tar = tar::Builder::new(out);
+            for (src, name) in &files {
+                tar.append_path_with_name(src, name)?;
+            }
+            tar.finish()?;
+        }
+    }
+
+    Ok(format!("Created {} with {} files", archive, files.len()))
+}
+
+fn collect_files(path: &Path, base: &Path, files: &mut Vec<(PathBuf, PathBuf)>) -> Result<()> {
+    let meta = fs::metadata(path).with_context(|| format!("Cannot read {}", path.display()))?;
+    if meta.is_dir() {
+        let mut entries = fs::read_dir(path)?.collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by_key(|e| e.file_name());
+        for entry in entries {
+            collect_files(&entry.path(), base, files)?;
+        }
+    } else {
+        let name = path.strip_prefix(base).unwrap_or(path).to_path_buf();
+        files.push((path.to_path_buf(), safe_entry_path(&name)?));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Write, path::PathBuf};
+    use super::{extract, extract_within, Budget};
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rustaigent-archive-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// A tar holding one entry named `name` byte for byte, which `tar::Builder` would refuse
+    fn tar_with(dir: &std::path::Path, name: &str, kind: tar::EntryType) {
+        let mut header = tar::Header::new_old();
+        header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+        header.set_entry_type(kind);
+        header.set_size(if kind.is_file() { 5 } else { 0 });
+        if kind.is_symlink() {
+            header.set_link_name("/etc/passwd").unwrap();
+        }
+        header.set_mode(0o644);
+        header.set_cksum();
+        let mut tar = tar::Builder::new(fs::File::create(dir.join("in.tar")).unwrap());
+        tar.append(&header, &b"owned"[..if kind.is_file() { 5 } else { 0 }]).unwrap();
+        tar.finish().unwrap();
+    }
+
+    #[test]
+    fn entries_leaving_the_destination_are_refused() {
+        let dir = scratch("escape");
+        let mut refusals = Vec::new();
+        for (name, kind) in [("../outside.txt", tar::EntryType::Regular), ("/tmp/absolute.txt", tar::EntryType::Regular), ("link", tar::EntryType::Symlink), ("hard", tar::EntryType::Link)] {
+            tar_with(&dir, name, kind);
+            refusals.push(extract(&dir, "in.tar", "out").map_err(|err| err.to_string()));
+        }
+        for name in ["../outside.txt", "/tmp/absolute.txt"] {
+            let mut zip = zip::ZipWriter::new(fs::File::create(dir.join("in.zip")).unwrap());
+            zip.start_file(name, zip::write::SimpleFileOptions::default()).unwrap();
+            zip.write_all(b"owned").unwrap();
+            zip.finish().unwrap();
+            refusals.push(extract(&dir, "in.zip", "out").map_err(|err| err.to_string()));
+        }
+        let mut zip = zip::ZipWriter::new(fs::File::create(dir.join("in.zip")).unwrap());
+        zip.add_symlink("link", "/etc/passwd", zip::write::SimpleFileOptions::default()).unwrap();
+        zip.finish().unwrap();
+        refusals.push(extract(&dir, "in.zip", "out").map_err(|err| err.to_string()));
+        let leaked = dir.join("outside.txt").exists() || dir.join("out").join("link").exists() || std::path::Path::new("/tmp/absolute.txt").exists();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(!leaked);
+        for refusal in &refusals {
+            let err = refusal.as_ref().unwrap_err();
+            assert!(err.starts_with("Refusing to extract"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn created_archives_extract_to_the_same_files() {
+        let dir = scratch("round-trip");
+        fs::create_dir_all(dir.join("src/nested")).unwrap();
+        fs::write(dir.join("src/main.rs"), "fn main() {}").unwrap();
+        fs::write(dir.join("src/nested/lib.rs"), "pub fn f() {}").unwrap();
+        let mut listings = Vec::new();
+        for archive in ["src.zip", "src.tar.gz"] {
+            super::create(&dir, archive, &["src"]).unwrap();
+            let dest = format!("{}.out", archive);
+            listings.push(extract(&dir, archive, &dest).unwrap());
+            assert_eq!(fs::read_to_string(dir.join(dest).join("src/nested/lib.rs")).unwrap_or_default(), "pub fn f() {}");
+        }
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(listings.iter().all(|l| l.starts_with("Extracted 2 files to ")), "{:?}", listings);
+    }
+
+    #[test]
+    fn extraction_stops_past_its_byte_and_entry_budget() {
+        let dir = scratch("budget");
+        let mut zip = zip::ZipWriter::new(fs::File::create(dir.join("bomb.zip")).unwrap());
+        let options = zip::write::SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+        zip.start_file("small.txt", options).unwrap();
+        zip.write_all(&[b'a'; 600]).unwrap();
+        zip.start_file("zeros.bin", options).unwrap();
+        zip.write_all(&[0; 600]).unwrap();
+        zip.finish().unwrap();
+        let bytes = extract_within(&dir, "bomb.zip", "bytes", Budget { bytes: 1000, entries: 10 }).unwrap_err().to_string();
+        let written = fs::read_dir(dir.join("bytes")).unwrap().count();
+        let entries = extract_within(&dir, "bomb.zip", "entries", Budget { bytes: 1 << 20, entries: 1 }).unwrap_err().to_string();
+        let mut tar = tar::Builder::new(fs::File::create(dir.join("big.tar")).unwrap());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(2000);
+        header.set_mode(0o644);
+        tar.append_data(&mut header, "big.bin", &[0u8; 2000][..]).unwrap();
+        tar.finish().unwrap();
+        let tarred = extract_within(&dir, "big.tar", "tar", Budget { bytes: 1000, entries: 10 }).unwrap_err().to_string();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(bytes.starts_with("Refusing to extract more than") && written == 1, "{} {}", bytes, written);
+        assert_eq!(entries, format!("Refusing to extract more than {} entries", super::MAX_ENTRIES));
+        assert!(tarred.starts_with("Refusing to extract more than"), "{}", tarred);
+    }
+}
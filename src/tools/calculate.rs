@@ -0,0 +1,23 @@
+use std::time::{Duration, Instant};
+use anyhow::{Result, bail};
+
+/// Give up on expressions like `10^10^10` instead of hanging the session
+const TIME_LIMIT: Duration = Duration::from_secs(2);
+
+struct Deadline(Instant);
+
+impl fend_core::Interrupt for Deadline {
+    fn should_interrupt(&self) -> bool {
+        Instant::now() >= self.0
+    }
+}
+
+/// Evaluate with exact rational arithmetic and unit conversion, e.g. `1.5 GiB / (40 MB/s) to s`
+pub fn calculate(expression: &str) -> Result<String> {
+    let mut context = fend_core::Context::new();
+    match fend_core::evaluate_with_interrupt(expression, &mut context, &Deadline(Instant::now() + TIME_LIMIT)) {
+        Ok(result) => Ok(format!("{} = {}", expression.trim(), result.get_main_result())),
+        Err(err) if err.eq_ignore_ascii_case("interrupted") => bail!("Calculation did not finish within {}s", TIME_LIMIT.as_secs()),
+        Err(err) => bail!("Cannot calculate `{}`: {}", expression.trim(), err),
+    }
+}
@@ -0,0 +1,315 @@
+use std::fmt;
+use serde::{Serialize, Deserialize, Deserializer};
+use serde_json::{json, Map, Value};
+use anyhow::{Result, anyhow};
+use crate::FunctionDefinition;
+
+/// How an argument type is described to the model and recorded in logs
+trait Param {
+    const REQUIRED: bool = true;
+    fn schema() -> Value;
+    fn is_absent(&self) -> bool {
+        false
+    }
+}
+
+impl Param for String {
+    fn schema() -> Value {
+        json!({"type": "string"})
+    }
+}
+
+impl Param for Option<String> {
+    const REQUIRED: bool = false;
+    fn schema() -> Value {
+        json!({"type": "string"})
+    }
+    fn is_absent(&self) -> bool {
+        self.is_none()
+    }
+}
+
+impl Param for Vec<String> {
+    fn schema() -> Value {
+        json!({"type": "array", "items": {"type": "string"}})
+    }
+}
+
+impl Param for Option<Vec<String>> {
+    const REQUIRED: bool = false;
+    fn schema() -> Value {
+        json!({"type": "array", "items": {"type": "string"}})
+    }
+    fn is_absent(&self) -> bool {
+        self.is_none()
+    }
+}
+
+/// Any JSON value
+impl Param for Option<Value> {
+    const REQUIRED: bool = false;
+    fn schema() -> Value {
+        json!({})
+    }
+    fn is_absent(&self) -> bool {
+        self.is_none()
+    }
+}
+
+impl Param for Option<u32> {
+    const REQUIRED: bool = false;
+    fn schema() -> Value {
+        json!({"type": "integer", "minimum": 1})
+    }
+    fn is_absent(&self) -> bool {
+        self.is_none()
+    }
+}
+
+impl Param for Option<bool> {
+    const REQUIRED: bool = false;
+    fn schema() -> Value {
+        json!({"type": "boolean"})
+    }
+    fn is_absent(&self) -> bool {
+        self.is_none()
+    }
+}
+
+/// `edit_structured` operation
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum EditOp {
+    Set,
+    Remove,
+}
+
+impl Param for EditOp {
+    fn schema() -> Value {
+        json!({"type": "string", "enum": ["set", "remove"]})
+    }
+}
+
+/// `generate_random` output
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum RandomKind {
+    Uuid,
+    Hex,
+    Base64,
+    Password,
+}
+
+impl Param for RandomKind {
+    fn schema() -> Value {
+        json!({"type": "string", "enum": ["uuid", "hex", "base64", "password"]})
+    }
+}
+
+/// `Some` for any value the caller sent, `null` included; missing stays `None` through `default`
+fn present<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Value>, D::Error> {
+    Value::deserialize(deserializer).map(Some)
+}
+
+/// The doc comment among an argument's attributes
+macro_rules! arg_doc {
+    () => { None::<&str> };
+    (#[doc = $doc:literal] $($rest:tt)*) => { Some($doc) };
+    (#[$($other:tt)*] $($rest:tt)*) => { arg_doc!($($rest)*) };
+}
+
+/// Declares every tool once: the `ToolCall` variant, its wire name, description and typed
+/// arguments. Function definitions for the model are generated from the same list.
+macro_rules! registry {
+    ($(
+        #[doc = $doc:literal]
+        $variant:ident $name:literal { $( $(#[$($attr:tt)*])* $arg:ident: $ty:ty ),* $(,)? }
+    )*) => {
+        /// A validated tool invocation, shared by the executor, approvals, policy and the session log.
+        /// Serializes as `{"tool": "<name>", "args": {...}}`.
+        #[derive(Serialize, Deserialize, Clone, Debug)]
+        #[serde(tag = "tool", content = "args")]
+        pub enum ToolCall {
+            $(
+                #[serde(rename = $name)]
+                $variant { $( $(#[$($attr)*])* #[serde(skip_serializing_if = "Param::is_absent")] $arg: $ty ),* },
+            )*
+        }
+
+        impl ToolCall {
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $( ToolCall::$variant { .. } => $name, )*
+                }
+            }
+
+            /// Function definitions for every tool, in registry order
+            pub(crate) fn definitions() -> Vec<FunctionDefinition> {
+                vec![$({
+                    let args: Vec<(&str, Value, bool, Option<&str>)> = vec![$(
+                        (stringify!($arg), <$ty as Param>::schema(), <$ty as Param>::REQUIRED, arg_doc!($(#[$($attr)*])*)),
+                    )*];
+                    let mut properties = Map::new();
+                    let mut required = Vec::new();
+                    for (arg, mut schema, is_required, doc) in args {
+                        if let Some(doc) = doc {
+                            schema["description"] = doc.trim().into();
+                        }
+                        properties.insert(arg.into(), schema);
+                        if is_required {
+                            required.push(arg);
+                        }
+                    }
+                    FunctionDefinition {
+                        name: $name.into(),
+                        description: $doc.trim().into(),
+                        parameters: json!({"type": "object", "properties": properties, "required": required}),
+                    }
+                }),*]
+            }
+        }
+    };
+}
+
+registry! {
+    /// Read a file from the filesystem
+    ReadFile "read_file" { path: String }
+    /// Write content to a file
+    WriteFile "write_file" { path: String, content: String }
+    /// Edit files with a unified diff (---/+++ headers, @@ hunks with a few lines of context) or SEARCH/REPLACE blocks; prefer it to write_file for changes to existing files. Nothing is written unless every hunk applies
+    ApplyPatch "apply_patch" { patch: String, /// File the patch is for when it has no ---/+++ headers or file name lines
+        path: Option<String> }
+    /// Regex find-and-replace across files matching a glob; shows a diff preview and applies only after user approval
+    ReplaceInFiles "replace_in_files" { pattern: String, replacement: String, /// Glob such as src/**/*.rs
+        scope: String }
+    /// Set or remove a value at a JSON pointer (e.g. /package/version) in a JSON, YAML or TOML file; JSON and TOML layout and TOML comments are preserved, and YAML files with comments are refused
+    EditStructured "edit_structured" { path: String, pointer: String, op: EditOp,
+        #[serde(default, deserialize_with = "present")] value: Option<Value> }
+    /// Delete a file from the filesystem
+    DeleteFile "delete_file" { path: String }
+    /// Unified diff between two files or directories, or between a file and provided content
+    Diff "diff" { left: String, right: Option<String>, content: Option<String> }
+    /// List files in a directory
+    ListDir "list_dir" { path: String }
+    /// Run a shell command without stdin; returns the exit code and output, long output cut to its start and end
+    RunCommand "run_command" { command: String, /// Wall-clock limit after which the command is killed, default 300
+        timeout_secs: Option<u32> }
+    /// Branch, staged, unstaged and untracked files of the git repository in the working directory
+    GitStatus "git_status" {}
+    /// Diff of the git working tree: unstaged changes by default, the index with staged, or everything since a commit or branch with base
+    GitDiff "git_diff" { staged: Option<bool>, /// Commit or branch to diff the working tree against, e.g. main
+        base: Option<String>, /// File or directory to limit the diff to
+        path: Option<String> }
+    /// Commit staged changes with a message; paths are staged first, all stages every change including untracked files
+    GitCommit "git_commit" { message: String, paths: Option<Vec<String>>, all: Option<bool> }
+    /// Create a git branch from HEAD or start and switch to it (checkout=false only creates it)
+    GitCreateBranch "git_create_branch" { name: String, start: Option<String>, checkout: Option<bool> }
+    /// Run the test suite of the detected project(s): cargo test, npm/pnpm/yarn test, pytest, go test, mvn/gradle test, ...
+    RunTests "run_tests" { /// Language to limit to, e.g. python
+        project: Option<String> }
+    /// Run `terraform plan -json` (or read a saved plan file) and summarize planned changes with risk levels
+    TerraformPlan "terraform_plan" { /// Terraform root module, default .
+        dir: Option<String>, /// Saved plan from `terraform plan -out`
+        plan_file: Option<String> }
+    /// Perform a GET request to a URL
+    FetchUrl "fetch_url" { url: String }
+    /// Download a URL to a local file, optionally verifying its SHA-256 checksum
+    DownloadFile "download_file" { url: String, path: String, sha256: Option<String> }
+    /// Build and run a Rust program (with fn main) as a temporary Cargo project; returns compiler errors, or the exit code with stdout and stderr. Declare crates in a `//! deps: serde = "1", rand@0.8` line or in dependencies
+    EvalCode "eval_code" { code: String,
+        /// Crates as `name`, `name@version` or `name = { version = "1", features = ["derive"] }`
+        dependencies: Option<Vec<String>>,
+        /// Wall-clock limit for building and running, default 120
+        timeout_secs: Option<u32> }
+    /// Extract a .zip, .tar.gz or .tar archive into a directory
+    ExtractArchive "extract_archive" { archive: String, destination: String }
+    /// Create a .zip or .tar.gz archive from files and directories
+    CreateArchive "create_archive" { archive: String, paths: Vec<String> }
+    /// Exact arithmetic with arbitrary precision and unit conversion; use it for any number in an answer (sizes, durations, rates, costs) instead of estimating
+    Calculate "calculate" { /// Expression such as `1.5 GiB / (40 MB/s) to seconds`, `3 days to hours` or `2^64 - 1`
+        expression: String }
+    /// Generate UUIDs, random hex or base64 tokens, or passwords from the OS random source; use it for every ID, key or secret instead of making one up
+    GenerateRandom "generate_random" { kind: RandomKind,
+        /// Bytes for hex and base64 (default 16 and 32), characters for passwords (default 24)
+        length: Option<u32>,
+        /// Number of values, one per line (default 1)
+        count: Option<u32>,
+        /// Include symbols in passwords (default true)
+        symbols: Option<bool>,
+        /// Leave out look-alike characters such as l, 1, O and 0
+        avoid_ambiguous: Option<bool> }
+    /// Compile a regular expression with Rust's `regex` crate and run it against sample inputs, listing matches and capture groups; use it to verify a pattern before putting it in code
+    TestRegex "test_regex" { /// Pattern in Rust regex syntax; inline flags such as (?i) and (?m) are supported
+        pattern: String, inputs: Vec<String> }
+    /// Current time and timezone, OS and architecture, Rust toolchain versions and working directory of the machine the agent runs on
+    GetEnvironment "get_environment" {}
+    /// Ask the user a clarifying question and wait for the answer; use it instead of guessing when requirements are ambiguous
+    AskUser "ask_user" { question: String }
+    /// Find the parts of the indexed codebase most related to a question or description, e.g. `where are retries configured`; returns file spans with line numbers. Use it before reading files when you don't know where something lives
+    SearchCodebase "search_codebase" { query: String, /// Number of matches, default 5
+        k: Option<u32> }
+    /// Read part of a large tool output that was stored as an artifact, by the id in its `[artifact ...]` header
+    ReadArtifact "read_artifact" { id: String, /// Byte offset to start at; leave it out to start at the beginning
+        offset: Option<u32>, /// Bytes to read, default and at most 16384
+        len: Option<u32> }
+    /// Save a finding to the session scratchpad, e.g. where something is defined or what a command showed; notes outlast summarized turns, so check them with read_notes before working something out again
+    WriteNote "write_note" { note: String, /// Number of an outdated note to replace instead of adding one
+        replaces: Option<u32> }
+    /// Read the notes saved with write_note, numbered
+    ReadNotes "read_notes" { /// Only notes containing this text
+        query: Option<String> }
+    /// Create an isolated temporary directory for experiments; it is deleted when the session ends
+    MakeScratchDir "make_scratch_dir" { label: Option<String> }
+}
+
+impl ToolCall {
+    /// Validate JSON arguments from the model or the REPL against the tool's parameters
+    /// Errors name the closest tool or arguments and list the tool's parameters.
+    pub fn parse(name: &str, args: Value) -> Result<Self> {
+        serde_json::from_value(json!({"tool": name, "args": args.clone()})).map_err(|err| {
+            let definitions = Self::definitions();
+            match definitions.iter().find(|d| d.name == name) {
+                Some(definition) => anyhow!("{}", super::hints::invalid_arguments(definition, &args, &err.to_string())),
+                None => anyhow!("Unknown tool `{}`.{}", name, super::hints::did_you_mean(name, definitions.iter().map(|d| d.name.as_str()))),
+            }
+        })
+    }
+
+    /// Arguments as a JSON object, as the model sent them
+    pub fn args(&self) -> Value {
+        serde_json::to_value(self).map(|mut v| v["args"].take()).unwrap_or_default()
+    }
+
+    /// Files, directories and globs the call reads or changes
+    pub fn paths(&self) -> Vec<&str> {
+        match self {
+            ToolCall::ReadFile { path } | ToolCall::WriteFile { path, .. } | ToolCall::DeleteFile { path }
+            | ToolCall::ListDir { path } | ToolCall::EditStructured { path, .. } | ToolCall::DownloadFile { path, .. } => vec![path],
+            ToolCall::Diff { left, right, .. } => std::iter::once(left.as_str()).chain(right.as_deref()).collect(),
+            ToolCall::ReplaceInFiles { scope, .. } => vec![scope],
+            ToolCall::ApplyPatch { patch, path } => super::patch::targets(patch, path.as_deref()),
+            ToolCall::ExtractArchive { archive, destination } => vec![archive, destination],
+            ToolCall::GitDiff { path, .. } => path.iter().map(String::as_str).collect(),
+            ToolCall::TerraformPlan { dir, plan_file } => dir.iter().chain(plan_file).map(String::as_str).collect(),
+            ToolCall::GitCommit { paths, .. } => paths.iter().flatten().map(String::as_str).collect(),
+            ToolCall::CreateArchive { archive, paths } => std::iter::once(archive.as_str()).chain(paths.iter().map(String::as_str)).collect(),
+            ToolCall::RunCommand { .. } | ToolCall::RunTests { .. } | ToolCall::GitStatus {} | ToolCall::GitCreateBranch { .. }
+            | ToolCall::FetchUrl { .. } | ToolCall::EvalCode { .. } | ToolCall::AskUser { .. } | ToolCall::GetEnvironment {} | ToolCall::Calculate { .. } | ToolCall::GenerateRandom { .. } | ToolCall::TestRegex { .. } | ToolCall::SearchCodebase { .. } | ToolCall::ReadArtifact { .. } | ToolCall::WriteNote { .. } | ToolCall::ReadNotes { .. } | ToolCall::MakeScratchDir { .. } => Vec::new(),
+        }
+    }
+}
+
+/// `name(arg, ...)` in the REPL's invocation syntax, long values shortened
+impl fmt::Display for ToolCall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let args = self.args();
+        let values: Vec<String> = args.as_object().into_iter().flatten().map(|(_, v)| {
+            let text = v.to_string();
+            match text.char_indices().nth(60) {
+                Some((cut, _)) => format!("{}…", &text[..cut]),
+                None => text,
+            }
+        }).collect();
+        write!(f, "{}({})", self.name(), values.join(", "))
+    }
+}
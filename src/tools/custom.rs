@@ -0,0 +1,26 @@
+use futures::future::BoxFuture;
+use serde_json::Value;
+use anyhow::Result;
+use crate::FunctionDefinition;
+
+/// A tool supplied by an application embedding the agent, offered to the model next to the
+/// built-in ones. Calls go through the same policy rules and approval prompts.
+pub trait Tool: Send + Sync {
+    /// Name, description and argument schema sent to the model
+    fn definition(&self) -> FunctionDefinition;
+
+    /// Run the tool with the arguments the model sent; the text is handed back to the model
+    fn call(&self, args: Value) -> BoxFuture<'_, Result<String>>;
+
+    /// What a call with these arguments would do, for approval prompts and dry runs; without it
+    /// the raw arguments are shown
+    fn simulate(&self, _args: &Value) -> Option<String> {
+        None
+    }
+
+    /// Whether the output comes from outside the user's control, such as web pages, mail or
+    /// issue trackers; it is then framed as untrusted content and calls repeating it need approval
+    fn untrusted(&self) -> bool {
+        false
+    }
+}
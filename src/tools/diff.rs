@@ -0,0 +1,197 @@
+use std::{collections::BTreeSet, fs, path::Path};
+use anyhow::{Result, Context, bail};
+use similar::TextDiff;
+
+/// Bytes of a `diff` result returned to the model; the rest is cut at a line boundary
+const MAX_OUTPUT: usize = 64 * 1024;
+
+/// Render a unified diff with three lines of context; empty when the inputs are identical
+pub fn unified(old: &str, new: &str, old_name: &str, new_name: &str) -> String {
+    if old == new {
+        return String::new();
+    }
+    TextDiff::from_lines(old, new)
+        .unified_diff()
+        .context_radius(3)
+        .header(old_name, new_name)
+        .to_string()
+}
+
+/// Rewrite a unified diff as plain sentences, one change per line, for screen readers
+pub fn linearize(diff: &str) -> String {
+    let mut out = String::new();
+    let (mut old_line, mut new_line) = (0usize, 0usize);
+    // Lines of the current hunk still to come on each side; until they are, `--- x` is a
+    // removed line reading `-- x` rather than the next file's header
+    let (mut old_left, mut new_left) = (0usize, 0usize);
+    for line in diff.lines() {
+        let in_hunk = old_left > 0 || new_left > 0;
+        if let Some(name) = line.strip_prefix("--- ").filter(|_| !in_hunk) {
+            out.push_str(&format!("Comparing {}", name));
+        } else if let Some(name) = line.strip_prefix("+++ ").filter(|_| !in_hunk) {
+            out.push_str(&format!(" with {}.\n", name));
+        } else if let Some(header) = line.strip_prefix("@@ ") {
+            // `-12,3 +12,4 @@` gives the starting line and length on each side
+            let mut parts = header.split_whitespace();
+            let range = |p: Option<&str>| {
+                let mut nums = p.unwrap_or_default().get(1..).unwrap_or_default().split(',').map(|n| n.parse().ok());
+                (nums.next().flatten().unwrap_or(1), nums.next().flatten().unwrap_or(1))
+            };
+            (old_line, old_left) = range(parts.next());
+            (new_line, new_left) = range(parts.next());
+            out.push_str(&format!("Change near line {}:\n", new_line));
+        } else if let Some(text) = line.strip_prefix('-') {
+            out.push_str(&format!("  Removed line {}: {}\n", old_line, text));
+            old_line += 1;
+            old_left = old_left.saturating_sub(1);
+        } else if let Some(text) = line.strip_prefix('+') {
+            out.push_str(&format!("  Added line {}: {}\n", new_line, text));
+            new_line += 1;
+            new_left = new_left.saturating_sub(1);
+        } else if line.starts_with(' ') {
+            old_line += 1;
+            new_line += 1;
+            old_left = old_left.saturating_sub(1);
+            new_left = new_left.saturating_sub(1);
+        } else if !line.is_empty() {
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// Whether text looks like a unified diff
+pub fn is_unified(text: &str) -> bool {
+    text.lines().any(|l| l.starts_with("@@ ")) && text.lines().any(|l| l.starts_with("--- "))
+}
+
+/// Diff two files, two directories, or a file against supplied content
+pub fn diff(dir: &Path, left: &str, right: Option<&str>, content: Option<&str>) -> Result<String> {
+    let out = match (right, content) {
+        (_, Some(content)) => {
+            let old = fs::read_to_string(dir.join(left)).with_context(|| format!("Failed to read {}", left))?;
+            unified(&old, content, left, "(provided content)")
+        }
+        (Some(right), None) => {
+            let (l, r) = (dir.join(left), dir.join(right));
+            if l.is_dir() && r.is_dir() {
+                diff_dirs(dir, Path::new(left), Path::new(right))?
+            } else {
+                let old = fs::read_to_string(l).with_context(|| format!("Failed to read {}", left))?;
+                let new = fs::read_to_string(r).with_context(|| format!("Failed to read {}", right))?;
+                unified(&old, &new, left, right)
+            }
+        }
+        (None, None) => bail!("Provide either `right` or `content` to compare against"),
+    };
+    Ok(if out.is_empty() { "No differences".into() } else { truncate(out) })
+}
+
+/// `out` cut to `MAX_OUTPUT` bytes at a line boundary, noting how much was left out
+fn truncate(mut out: String) -> String {
+    if out.len() <= MAX_OUTPUT {
+        return out;
+    }
+    let mut cut = MAX_OUTPUT;
+    while !out.is_char_boundary(cut) {
+        cut -= 1;
+    }
+    let cut = out[..cut].rfind('\n').map_or(cut, |i| i + 1);
+    let omitted = out.len() - cut;
+    out.truncate(cut);
+    out.push_str(&format!("… {} more bytes of diff omitted; compare narrower paths to see them\n", omitted));
+    out
+}
+
+/// Compare the trees at `left` and `right`, relative to `dir`, naming files as given
+fn diff_dirs(dir: &Path, left: &Path, right: &Path) -> Result<String> {
+    let mut l_files = BTreeSet::new();
+    let mut r_files = BTreeSet::new();
+    walk(&dir.join(left), &dir.join(left), &mut l_files)?;
+    walk(&dir.join(right), &dir.join(right), &mut r_files)?;
+
+    let mut out = String::new();
+    for rel in l_files.union(&r_files) {
+        let (lp, rp) = (left.join(rel), right.join(rel));
+        match (l_files.contains(rel), r_files.contains(rel)) {
+            (true, false) => out.push_str(&format!("Only in {}: {}\n", left.display(), rel)),
+            (false, true) => out.push_str(&format!("Only in {}: {}\n", right.display(), rel)),
+            // Links are compared by target, never followed out of the trees
+            _ if dir.join(&lp).is_symlink() || dir.join(&rp).is_symlink() => {
+                if fs::read_link(dir.join(&lp)).ok() != fs::read_link(dir.join(&rp)).ok() {
+                    out.push_str(&format!("Symbolic links {} and {} differ\n", lp.display(), rp.display()));
+                }
+            }
+            _ => {
+                let (old, new) = (fs::read(dir.join(&lp))?, fs::read(dir.join(&rp))?);
+                if old == new {
+                    continue;
+                }
+                match (String::from_utf8(old), String::from_utf8(new)) {
+                    (Ok(old), Ok(new)) => out.push_str(&unified(&old, &new, &lp.display().to_string(), &rp.display().to_string())),
+                    _ => out.push_str(&format!("Binary files {} and {} differ\n", lp.display(), rp.display())),
+                }
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Files and symlinks under `dir`, relative to `root`; symlinked directories are not entered, so
+/// a loop or a link out of the workspace is listed rather than followed
+fn walk(root: &Path, dir: &Path, files: &mut BTreeSet<String>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            walk(root, &path, files)?;
+        } else if let Ok(rel) = path.strip_prefix(root) {
+            files.insert(rel.to_string_lossy().into_owned());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use super::{diff, linearize, MAX_OUTPUT};
+
+    #[test]
+    fn changes_are_read_out_with_their_line_numbers() {
+        let diff = "diff --git a/src/lib.rs b/src/lib.rs\n--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -10,3 +10,3 @@ fn main() {\n context\n-old\n+new\n@@ -40 +40,2 @@\n+first\n+second\n";
+        assert_eq!(linearize(diff), "diff --git a/src/lib.rs b/src/lib.rs\nComparing a/src/lib.rs with b/src/lib.rs.\nChange near line 10:\n  Removed line 11: old\n  Added line 11: new\nChange near line 40:\n  Added line 40: first\n  Added line 41: second\n");
+    }
+
+    #[test]
+    fn lines_starting_with_dashes_inside_a_hunk_are_changes() {
+        let diff = "--- a/query.sql\n+++ b/query.sql\n@@ -1,2 +1,2 @@\n--- old comment\n+++ new comment\n select 1;\n--- a/b.sql\n+++ b/b.sql\n@@ -3 +3 @@\n-x\n+y\n";
+        assert_eq!(linearize(diff), "Comparing a/query.sql with b/query.sql.\nChange near line 1:\n  Removed line 1: -- old comment\n  Added line 1: ++ new comment\nComparing a/b.sql with b/b.sql.\nChange near line 3:\n  Removed line 3: x\n  Added line 3: y\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn directory_diffs_do_not_follow_symlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        for side in ["a", "b"] {
+            fs::create_dir_all(dir.path().join(side)).unwrap();
+            std::os::unix::fs::symlink(".", dir.path().join(side).join("loop")).unwrap();
+        }
+        std::os::unix::fs::symlink("/etc", dir.path().join("a").join("etc")).unwrap();
+        std::os::unix::fs::symlink("/usr", dir.path().join("b").join("etc")).unwrap();
+        let out = diff(dir.path(), "a", Some("b"), None).unwrap();
+        assert_eq!(out, "Symbolic links a/etc and b/etc differ\n");
+    }
+
+    #[test]
+    fn long_diffs_are_cut_at_a_line() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("old.txt"), "").unwrap();
+        let out = diff(dir.path(), "old.txt", None, Some(&"a long line of new content\n".repeat(10_000))).unwrap();
+        assert!(out.len() < MAX_OUTPUT + 100);
+        let (kept, note) = out.rsplit_once('…').unwrap();
+        assert!(kept.ends_with("+a long line of new content\n"));
+        assert!(note.contains("more bytes of diff omitted"));
+    }
+}
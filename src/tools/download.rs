@@ -0,0 +1,104 @@
+use std::{env, fs, io::Write, path::Path};
+use anyhow::{Result, Context, bail};
+use sha2::{Digest, Sha256};
+
+const DEFAULT_MAX_BYTES: u64 = 100 * 1024 * 1024;
+
+/// How download progress is shown on stderr
+#[derive(Clone, Copy, PartialEq)]
+pub enum Progress {
+    /// One line redrawn in place
+    Redraw,
+    /// Whole sentences, for screen readers
+    Lines,
+    Hidden,
+}
+
+/// Stream a URL `network` lets through to `path` in `dir`, enforcing DOWNLOAD_MAX_BYTES and an
+/// optional SHA-256 checksum
+pub async fn download(network: &super::network::Network, url: &str, dir: &Path, path: &str, expected_sha256: Option<&str>, progress: Progress) -> Result<String> {
+    let max_bytes = env::var("DOWNLOAD_MAX_BYTES").ok().and_then(|v| v.parse().ok()).unwrap_or(DEFAULT_MAX_BYTES);
+    let mut resp = network.get(url).await?.error_for_status()?;
+    if let Some(len) = resp.content_length() {
+        if len > max_bytes {
+            bail!("Refusing to download {}: {} bytes exceeds limit of {} bytes", url, len, max_bytes);
+        }
+    }
+
+    let dest = dir.join(path);
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let partial = dir.join(format!("{}.part", path));
+    let mut file = fs::File::create(&partial).with_context(|| format!("Failed to create {}", partial.display()))?;
+    let received = receive(&mut resp, &mut file, url, max_bytes, progress).await;
+    drop(file);
+    // Never leave a half-written or unverified `.part` file behind
+    let received = received.and_then(|(received, digest)| {
+        if let Some(expected) = expected_sha256 {
+            if !digest.eq_ignore_ascii_case(expected.trim()) {
+                bail!("Checksum mismatch for {}: expected {}, got {}", url, expected, digest);
+            }
+        }
+        fs::rename(&partial, &dest)?;
+        Ok((received, digest))
+    });
+    let (received, digest) = received.inspect_err(|_| { fs::remove_file(&partial).ok(); })?;
+
+    let verified = if expected_sha256.is_some() { " (checksum verified)" } else { "" };
+    Ok(format!("Downloaded {} bytes to {}{}\nsha256: {}", received, path, verified, digest))
+}
+
+/// Stream the response body into `file`, returning the byte count and SHA-256 hex digest
+async fn receive(resp: &mut reqwest::Response, file: &mut fs::File, url: &str, max_bytes: u64, progress: Progress) -> Result<(u64, String)> {
+    let total = resp.content_length();
+    let mut hasher = Sha256::new();
+    let mut received: u64 = 0;
+    let mut last_report = 0;
+
+    while let Some(chunk) = resp.chunk().await? {
+        received += chunk.len() as u64;
+        if received > max_bytes {
+            bail!("Download of {} aborted: exceeded limit of {} bytes", url, max_bytes);
+        }
+        hasher.update(&chunk);
+        file.write_all(&chunk)?;
+        // Report roughly every MiB so large downloads don't look hung
+        let step = if progress == Progress::Lines { 10 * 1024 * 1024 } else { 1024 * 1024 };
+        if progress != Progress::Hidden && received - last_report >= step {
+            last_report = received;
+            match (total, progress) {
+                (Some(t), Progress::Lines) => eprintln!("Downloaded {} of {} bytes, {} percent.", received, t, received * 100 / t.max(1)),
+                (None, Progress::Lines) => eprintln!("Downloaded {} bytes so far.", received),
+                (Some(t), _) => eprint!("\r[download] {} / {} bytes ({}%)", received, t, received * 100 / t.max(1)),
+                (None, _) => eprint!("\r[download] {} bytes", received),
+            }
+        }
+    }
+    if last_report > 0 && progress == Progress::Redraw {
+        eprintln!();
+    }
+    file.flush()?;
+    Ok((received, hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::{matchers::method, Mock, MockServer, ResponseTemplate};
+    use super::{download, Progress};
+    use crate::tools::network::Network;
+
+    #[tokio::test]
+    async fn failed_downloads_leave_no_partial_file() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET")).respond_with(ResponseTemplate::new(200).set_body_bytes(b"payload".to_vec())).mount(&server).await;
+        let dir = tempfile::tempdir().unwrap();
+        let network = Network::new(None, true, None);
+        let err = download(&network, &server.uri(), dir.path(), "file.bin", Some("00"), Progress::Hidden).await.unwrap_err();
+        assert!(err.to_string().contains("Checksum mismatch"));
+        assert!(std::fs::read_dir(dir.path()).unwrap().next().is_none());
+        download(&network, &server.uri(), dir.path(), "file.bin", None, Progress::Hidden).await.unwrap();
+        assert_eq!(std::fs::read(dir.path().join("file.bin")).unwrap(), b"payload");
+        assert!(!dir.path().join("file.bin.part").exists());
+    }
+}
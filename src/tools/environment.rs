@@ -0,0 +1,27 @@
+use std::{env, path::Path, process::Command};
+use anyhow::Result;
+
+/// First line of a version command's output in `dir`, or `not found`
+fn version(dir: &Path, program: &str, args: &[&str]) -> String {
+    Command::new(program).args(args).current_dir(dir).output().ok()
+        .filter(|out| out.status.success())
+        .and_then(|out| String::from_utf8_lossy(&out.stdout).lines().next().map(|l| l.trim().to_string()))
+        .unwrap_or_else(|| "not found".into())
+}
+
+/// Facts about the machine the agent runs on and the toolchain of `dir`, so the model need not
+/// assume them
+pub fn describe(dir: &Path) -> Result<String> {
+    let now = chrono::Local::now();
+    let timezone = iana_time_zone::get_timezone().unwrap_or_else(|_| "unknown".into());
+    let kernel = if cfg!(unix) { format!(" ({})", version(dir, "uname", &["-sr"])) } else { String::new() };
+    Ok([
+        format!("time: {}", now.format("%Y-%m-%d %H:%M:%S %:z (%A)")),
+        format!("timezone: {}", timezone),
+        format!("os: {} {}{}", env::consts::OS, env::consts::ARCH, kernel),
+        format!("rustc: {}", version(dir, "rustc", &["--version"])),
+        format!("cargo: {}", version(dir, "cargo", &["--version"])),
+        format!("toolchain: {}", version(dir, "rustup", &["show", "active-toolchain"])),
+        format!("cwd: {}", dir.display()),
+    ].join("\n"))
+}
@@ -0,0 +1,239 @@
+//! `eval_code`: a Rust program built as a throwaway Cargo project and run with a wall-clock
+//! limit, its output capped and handed back so the model can iterate on compiler errors.
+
+use std::{fs, io::Read, path::{Path, PathBuf}, process::{Child, Command, Stdio}, sync::{Arc, Mutex}, thread, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
+use anyhow::{Result, Context, bail};
+use tokio_util::sync::CancellationToken;
+
+/// Wall-clock limit for building and running together when the call sets none
+pub const DEFAULT_TIMEOUT_SECS: u64 = 120;
+
+/// Longest limit a call may ask for
+const MAX_TIMEOUT_SECS: u64 = 600;
+
+/// Bytes kept of stdout and of stderr each
+const MAX_OUTPUT: usize = 16 * 1024;
+
+/// How long output is still collected once the process has ended
+const OUTPUT_GRACE: Duration = Duration::from_secs(1);
+
+/// Header line declaring dependencies inside the code
+const DEPS_HEADER: &str = "//! deps:";
+
+/// Credentials the program has no business reading
+pub const HIDDEN_ENV: &[&str] = &[
+    "OPENAI_API_KEY", "ANTHROPIC_API_KEY", "GOOGLE_API_KEY", "GITHUB_TOKEN",
+    "RUSTAIGENT_PASSPHRASE", "RUSTAIGENT_SYNC_PASSPHRASE", "RUSTAIGENT_SYNC_PASSWORD",
+];
+
+/// Target directory shared by evaluations, so dependencies compile once
+fn target_dir() -> PathBuf {
+    dirs::cache_dir().unwrap_or_else(std::env::temp_dir).join("rustaigent").join("eval-target")
+}
+
+/// Split at commas outside quotes, brackets and braces
+fn split_entries(list: &str) -> Vec<String> {
+    let (mut entries, mut current, mut depth, mut quoted) = (Vec::new(), String::new(), 0i32, false);
+    for c in list.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            '{' | '[' if !quoted => depth += 1,
+            '}' | ']' if !quoted => depth -= 1,
+            ',' if !quoted && depth == 0 => {
+                entries.push(std::mem::take(&mut current));
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    entries.push(current);
+    entries.into_iter().map(|e| e.trim().to_string()).filter(|e| !e.is_empty()).collect()
+}
+
+/// `name`, `name@1.2` or `name = <TOML value>` as a `[dependencies]` line
+fn dependency_line(entry: &str) -> Result<String> {
+    let (name, value) = match entry.split_once('=') {
+        Some((name, value)) => (name.trim(), value.trim().to_string()),
+        None => match entry.split_once('@') {
+            Some((name, version)) => (name.trim(), format!("\"{}\"", version.trim())),
+            None => (entry.trim(), "\"*\"".to_string()),
+        },
+    };
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        bail!("`{}` is not a dependency; use `name`, `name@version` or `name = \"version\"`", entry);
+    }
+    let line = format!("{} = {}", name, value);
+    toml::from_str::<toml::Table>(&line).with_context(|| format!("Invalid dependency `{}`", entry))?;
+    Ok(line)
+}
+
+/// Dependencies from `//! deps:` lines of the code and the call's list
+pub fn dependencies(code: &str, declared: &[String]) -> Result<Vec<String>> {
+    let from_code = code.lines()
+        .filter_map(|line| line.trim().strip_prefix(DEPS_HEADER))
+        .flat_map(split_entries);
+    from_code.chain(declared.iter().cloned()).map(|entry| dependency_line(&entry)).collect()
+}
+
+fn manifest(package: &str, deps: &[String]) -> String {
+    format!("[package]\nname = \"{}\"\nversion = \"0.0.0\"\nedition = \"2021\"\npublish = false\n\n[dependencies]\n{}\n\n[workspace]\n", package, deps.join("\n"))
+}
+
+/// What a pipe delivered: the first `MAX_OUTPUT` bytes and the total count
+type Captured = Arc<Mutex<(Vec<u8>, usize)>>;
+
+/// Read a pipe to the end in the background, keeping the first `MAX_OUTPUT` bytes
+fn capture(mut pipe: impl Read + Send + 'static) -> (Captured, thread::JoinHandle<()>) {
+    let captured = Captured::default();
+    let sink = captured.clone();
+    let reader = thread::spawn(move || {
+        let mut buf = [0u8; 8192];
+        while let Ok(n) = pipe.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+            let mut sink = sink.lock().unwrap();
+            let room = MAX_OUTPUT.saturating_sub(sink.0.len());
+            sink.0.extend_from_slice(&buf[..n.min(room)]);
+            sink.1 += n;
+        }
+    });
+    (captured, reader)
+}
+
+fn render(label: &str, captured: &Captured) -> String {
+    let (kept, total) = &*captured.lock().unwrap();
+    if *total == 0 {
+        return String::new();
+    }
+    let mut text = format!("--- {} ---\n{}", label, String::from_utf8_lossy(kept).trim_end());
+    if *total > kept.len() {
+        text.push_str(&format!("\n… {} more bytes not shown", total - kept.len()));
+    }
+    text.push('\n');
+    text
+}
+
+/// Kill the process and everything it started; a build spawns rustc, a program its own children
+pub(super) fn kill(child: &mut Child) {
+    #[cfg(unix)]
+    {
+        Command::new("kill").args(["-KILL", "--", &format!("-{}", child.id())]).status().ok();
+    }
+    child.kill().ok();
+}
+
+/// How a process ended
+enum Outcome {
+    Exited(std::process::ExitStatus),
+    TimedOut,
+}
+
+/// Run `command` in its own process group until it exits, `deadline` passes or the turn is
+/// cancelled
+fn run(mut command: Command, deadline: Instant, cancel: &CancellationToken) -> Result<(Outcome, String, String)> {
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+    for var in HIDDEN_ENV {
+        command.env_remove(var);
+    }
+    let mut child = command.stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    let stdout = capture(child.stdout.take().context("no stdout pipe")?);
+    let stderr = capture(child.stderr.take().context("no stderr pipe")?);
+    let outcome = match super::shell::wait(&mut child, deadline, cancel)? {
+        Some(status) => Outcome::Exited(status),
+        None => Outcome::TimedOut,
+    };
+    // A background process that escaped the kill may hold the pipes open; take what arrived
+    let grace = Instant::now() + OUTPUT_GRACE;
+    while !(stdout.1.is_finished() && stderr.1.is_finished()) && Instant::now() < grace {
+        thread::sleep(Duration::from_millis(20));
+    }
+    Ok((outcome, render("stdout", &stdout.0), render("stderr", &stderr.0)))
+}
+
+/// A temp project directory and the binary it builds, removed when evaluation ends
+struct Project {
+    dir: PathBuf,
+    binary: PathBuf,
+}
+
+impl Drop for Project {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.dir).ok();
+        fs::remove_file(&self.binary).ok();
+    }
+}
+
+fn scaffold(code: &str, deps: &[String], target: &Path) -> Result<Project> {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH)?.subsec_nanos();
+    let package = format!("eval_{}_{:x}", std::process::id(), nanos);
+    let dir = std::env::temp_dir().join(format!("rustaigent-{}", package));
+    let binary = target.join("debug").join(format!("{}{}", package, std::env::consts::EXE_SUFFIX));
+    let project = Project { dir, binary };
+    fs::create_dir_all(project.dir.join("src"))?;
+    fs::write(project.dir.join("Cargo.toml"), manifest(&package, deps))?;
+    fs::write(project.dir.join("src").join("main.rs"), code)?;
+    Ok(project)
+}
+
+/// Build `code` with its dependencies and run it in `dir`, reporting build errors, output and
+/// exit status
+pub fn eval(dir: &Path, code: &str, dependencies: &[String], timeout_secs: Option<u64>, cancel: &CancellationToken) -> Result<String> {
+    let deps = self::dependencies(code, dependencies)?;
+    let limit = Duration::from_secs(timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS).clamp(1, MAX_TIMEOUT_SECS));
+    let started = Instant::now();
+    let deadline = started + limit;
+    let target = target_dir();
+    let project = scaffold(code, &deps, &target)?;
+
+    let mut build = Command::new("cargo");
+    build.args(["build", "--quiet", "--message-format", "short"]).current_dir(&project.dir).env("CARGO_TARGET_DIR", &target);
+    let (outcome, _, errors) = run(build, deadline, cancel).context("Failed to invoke cargo")?;
+    match outcome {
+        Outcome::TimedOut => return Ok(format!("Build timed out after {}; fewer or lighter dependencies may help\n{}", humantime::format_duration(limit), errors)),
+        Outcome::Exited(status) if !status.success() => return Ok(format!("Compilation failed:\n{}", errors.trim_start_matches("--- stderr ---\n"))),
+        Outcome::Exited(_) => {}
+    }
+
+    let mut program = Command::new(&project.binary);
+    program.current_dir(dir);
+    let (outcome, stdout, stderr) = run(program, deadline, cancel)?;
+    let elapsed = humantime::format_duration(Duration::from_millis(started.elapsed().as_millis() as u64));
+    let status = match outcome {
+        Outcome::TimedOut => format!("Killed after the {} limit", humantime::format_duration(limit)),
+        Outcome::Exited(status) => match status.code() {
+            Some(code) => format!("Exit code {} after {}", code, elapsed),
+            None => format!("Terminated by a signal after {}", elapsed),
+        },
+    };
+    let output = format!("{}{}", stdout, stderr);
+    Ok(match output.is_empty() {
+        true => format!("{}; no output", status),
+        false => format!("{}\n{}", status, output.trim_end()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dependencies, dependency_line, split_entries};
+
+    #[test]
+    fn entries_split_at_top_level_commas() {
+        assert_eq!(split_entries(r#" serde = { version = "1", features = ["derive", "rc"] }, rand@0.8 , "a,b", "#), [r#"serde = { version = "1", features = ["derive", "rc"] }"#, "rand@0.8", r#""a,b""#]);
+        assert!(split_entries(" , ").is_empty());
+    }
+
+    #[test]
+    fn dependencies_become_manifest_lines() {
+        assert_eq!(dependency_line("regex").unwrap(), r#"regex = "*""#);
+        assert_eq!(dependency_line(" rand @ 0.8 ").unwrap(), r#"rand = "0.8""#);
+        assert_eq!(dependency_line(r#"serde = { version = "1", features = ["derive"] }"#).unwrap(), r#"serde = { version = "1", features = ["derive"] }"#);
+        for bad in ["", "../evil", "a b", r#"x = { path = "#, "name = [unclosed"] {
+            assert!(dependency_line(bad).is_err(), "{}", bad);
+        }
+        let code = "//! deps: regex, itertools@0.12\nfn main() {}\n";
+        assert_eq!(dependencies(code, &["rand@0.8".into()]).unwrap(), [r#"regex = "*""#, r#"itertools = "0.12""#, r#"rand = "0.8""#]);
+    }
+}
@@ -0,0 +1,77 @@
+//! `git_status`, `git_diff`, `git_commit` and `git_create_branch`: the git steps a coding session
+//! ends with, run through the git CLI in the session's workspace.
+
+use std::path::Path;
+use anyhow::{Result, Context, bail};
+use crate::gitdiff::{self, git_in};
+
+/// Refuse values git would read as options, such as `--output=<file>`
+fn not_option<'a>(what: &str, value: &'a str) -> Result<&'a str> {
+    if value.starts_with('-') {
+        bail!("The {} `{}` looks like an option", what, value);
+    }
+    Ok(value)
+}
+
+pub fn status(dir: &Path) -> Result<String> {
+    let status = git_in(dir, &["status", "--short", "--branch"])?;
+    Ok(match status.lines().count() {
+        // Only the `## branch` line: nothing changed
+        0 | 1 => format!("{}\nWorking tree clean", status.trim_end()),
+        _ => status.trim_end().to_string(),
+    })
+}
+
+/// Unstaged changes, the index with `staged`, or everything since `base`; `path` limits the files
+pub fn diff(dir: &Path, staged: bool, base: Option<&str>, path: Option<&str>) -> Result<String> {
+    let mut args = vec!["diff"];
+    if staged {
+        args.push("--cached");
+    }
+    args.extend(base.map(|base| not_option("base", base)).transpose()?);
+    if let Some(path) = path {
+        args.extend(["--", path]);
+    }
+    let diff = git_in(dir, &args)?;
+    if diff.trim().is_empty() {
+        return Ok(format!("No changes (`git {}`)", args.join(" ")));
+    }
+    Ok(gitdiff::truncate(&diff))
+}
+
+/// Stage `paths` (every change with `all`) on top of what is already staged, then commit
+pub fn commit(dir: &Path, message: &str, paths: &[String], all: bool) -> Result<String> {
+    if message.trim().is_empty() {
+        bail!("The commit message is empty");
+    }
+    if all {
+        git_in(dir, &["add", "--all"])?;
+    } else if !paths.is_empty() {
+        let args: Vec<&str> = ["add", "--"].into_iter().chain(paths.iter().map(String::as_str)).collect();
+        git_in(dir, &args)?;
+    }
+    if git_in(dir, &["diff", "--cached", "--quiet"]).is_ok() {
+        bail!("Nothing is staged; pass the paths to commit or all=true");
+    }
+    git_in(dir, &["commit", "--quiet", "-m", message])?;
+    let branch = git_in(dir, &["rev-parse", "--abbrev-ref", "HEAD"])?;
+    let summary = git_in(dir, &["show", "--stat", "--format=%h %s", "HEAD"])?;
+    Ok(format!("Committed on {}: {}", branch.trim(), summary.trim_end()))
+}
+
+/// Create `name` from `start` (default HEAD) and switch to it unless `checkout` is false
+pub fn create_branch(dir: &Path, name: &str, start: Option<&str>, checkout: bool) -> Result<String> {
+    not_option("branch name", name)?;
+    git_in(dir, &["check-ref-format", "--branch", name]).with_context(|| format!("`{}` is not a valid branch name", name))?;
+    let mut args = match checkout {
+        true => vec!["switch", "--create", name],
+        false => vec!["branch", name],
+    };
+    args.extend(start.map(|start| not_option("start point", start)).transpose()?);
+    git_in(dir, &args)?;
+    let from = start.unwrap_or("HEAD");
+    Ok(match checkout {
+        true => format!("Created branch {} from {} and switched to it", name, from),
+        false => format!("Created branch {} from {}", name, from),
+    })
+}
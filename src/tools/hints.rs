@@ -0,0 +1,193 @@
+//! Hints appended to the errors a model gets back for a bad call: the closest tool, argument or
+//! path names and the parameters a tool takes, so the next call can be right without guessing.
+
+use std::{io, path::{Component, Path}};
+use serde_json::Value;
+use crate::FunctionDefinition;
+
+/// Suggestions offered at most
+const SUGGESTIONS: usize = 3;
+/// Entries of a directory listed when nothing in it is close
+const LISTED: usize = 20;
+
+/// Edit distance between `a` and `b`, ignoring case
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.to_lowercase().chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.to_lowercase().chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substituted = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substituted.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Candidates within a few edits of `target`, closest first
+pub fn closest<'a>(target: &str, candidates: impl IntoIterator<Item = &'a str>) -> Vec<&'a str> {
+    let limit = (target.chars().count() / 3).max(2);
+    let mut near: Vec<(usize, &str)> = candidates.into_iter()
+        .filter(|c| *c != target)
+        .map(|c| (distance(target, c), c))
+        .filter(|(d, _)| *d <= limit)
+        .collect();
+    near.sort();
+    near.dedup();
+    near.into_iter().take(SUGGESTIONS).map(|(_, c)| c).collect()
+}
+
+/// ` Did you mean `a` or `b`?`, or nothing when no candidate is close
+pub fn did_you_mean<'a>(target: &str, candidates: impl IntoIterator<Item = &'a str>) -> String {
+    let near = closest(target, candidates);
+    if near.is_empty() {
+        return String::new();
+    }
+    let quoted: Vec<String> = near.iter().map(|c| format!("`{}`", c)).collect();
+    format!(" Did you mean {}?", quoted.join(" or "))
+}
+
+/// One line per parameter: its type, allowed values or least value, and whether it is required
+fn parameters(definition: &FunctionDefinition) -> String {
+    let schema = &definition.parameters;
+    let required: Vec<&str> = schema["required"].as_array().into_iter().flatten().filter_map(Value::as_str).collect();
+    let mut out = format!("Parameters of `{}`:", definition.name);
+    let properties = schema["properties"].as_object().into_iter().flatten();
+    let mut any = false;
+    for (name, param) in properties {
+        any = true;
+        let mut kind = match (param["type"].as_str(), param["items"]["type"].as_str()) {
+            (Some("array"), Some(item)) => format!("array of {}", item),
+            (Some(kind), _) => kind.to_string(),
+            (None, _) => "any JSON value".into(),
+        };
+        if let Some(values) = param["enum"].as_array() {
+            let values: Vec<String> = values.iter().map(|v| format!("`{}`", v.as_str().unwrap_or_default())).collect();
+            kind = format!("one of {}", values.join(", "));
+        }
+        if let Some(min) = param["minimum"].as_u64() {
+            kind.push_str(&format!(", at least {}", min));
+        }
+        let need = if required.contains(&name.as_str()) { "required" } else { "optional" };
+        out.push_str(&format!("\n  {}: {}, {}", name, kind, need));
+    }
+    if !any {
+        out.push_str(" none; pass `{}`");
+    }
+    out
+}
+
+/// The error for arguments that did not fit the tool, with the parameters it takes and the
+/// closest names for a misspelled argument or value
+pub fn invalid_arguments(definition: &FunctionDefinition, args: &Value, error: &str) -> String {
+    let mut out = format!("Invalid arguments for `{}`: {}.", definition.name, error);
+    let known: Vec<&str> = definition.parameters["properties"].as_object().into_iter().flatten().map(|(k, _)| k.as_str()).collect();
+    // Argument names the tool does not have, each set against the parameter names
+    for name in args.as_object().into_iter().flatten().map(|(k, _)| k.as_str()).filter(|k| !known.contains(k)) {
+        out.push_str(&format!("\nThere is no argument `{}`.{}", name, did_you_mean(name, known.iter().copied())));
+    }
+    // `unknown variant `uid`, expected one of `uuid`, `hex`` names the value and the choices
+    if let Some(rest) = error.strip_prefix("unknown variant `") {
+        let mut quoted = rest.split('`').step_by(2);
+        if let Some(value) = quoted.next() {
+            out.push_str(&did_you_mean(value, quoted));
+        }
+    }
+    out.push('\n');
+    out.push_str(&parameters(definition));
+    out
+}
+
+/// Whether a tool failed because a file or directory does not exist
+pub fn not_found(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| cause.downcast_ref::<io::Error>().is_some_and(|e| e.kind() == io::ErrorKind::NotFound))
+}
+
+/// Where the model may have meant `path` to point, for a path under `root` that does not
+/// exist: close names in the deepest directory that does, and the project files of the same or
+/// a close name, offered as "did you mean" so the model can retry without another listing
+pub fn missing_path(root: &Path, path: &str) -> Option<String> {
+    let full = root.join(path);
+    if path.contains('*') || full.exists() {
+        return None;
+    }
+    let mut out = format!("`{}` does not exist.", path);
+    let mut existing = full.as_path();
+    while !existing.is_dir() {
+        existing = existing.parent()?;
+    }
+    let missing = full.strip_prefix(existing).ok()?.components().next().and_then(|c| match c {
+        Component::Normal(name) => name.to_str(),
+        _ => None,
+    })?;
+    let entries: Vec<String> = std::fs::read_dir(existing).ok()?.flatten()
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .filter(|name| !name.starts_with('.'))
+        .collect();
+    let shown = existing.strip_prefix(root).ok().filter(|p| !p.as_os_str().is_empty());
+    let shown = shown.map(|p| format!("{}/", p.display()));
+    let near = closest(missing, entries.iter().map(String::as_str));
+    let near: Vec<String> = near.iter().map(|n| format!("{}{}", shown.as_deref().unwrap_or_default(), n)).collect();
+    if !near.is_empty() {
+        let quoted: Vec<String> = near.iter().map(|n| format!("`{}`", n)).collect();
+        out.push_str(&format!(" Close names: {}.", quoted.join(", ")));
+    }
+    // The right file name in the wrong directory, or a close one: `src/agent.rs` for
+    // `src/core/agent.rs`, `src/agnet.rs` for either; same names first, then the closer path
+    let name = full.file_name()?.to_str()?;
+    // Tighter than `closest`: every file in the project is a candidate
+    let limit = (name.chars().count() / 4).max(1);
+    let wanted = path.trim_start_matches("./");
+    let mut ranked: Vec<(usize, usize, String)> = crate::index::candidates(root).unwrap_or_default().into_iter()
+        .filter_map(|rel| {
+            let file = Path::new(&rel).file_name()?.to_str()?;
+            let d = distance(name, file);
+            (d <= limit && !near.contains(&rel)).then(|| (d, distance(wanted, &rel), rel))
+        })
+        .collect();
+    ranked.sort();
+    let elsewhere: Vec<String> = ranked.into_iter().take(SUGGESTIONS).map(|(_, _, rel)| rel).collect();
+    if !elsewhere.is_empty() {
+        let elsewhere: Vec<String> = elsewhere.iter().map(|p| format!("`{}`", p)).collect();
+        out.push_str(&format!(" Did you mean {}? Call again with the right path.", elsewhere.join(" or ")));
+    }
+    if near.is_empty() && elsewhere.is_empty() {
+        let mut listed = entries;
+        listed.sort();
+        let more = listed.len().saturating_sub(LISTED);
+        listed.truncate(LISTED);
+        let place = shown.map(|p| format!("`{}`", p)).unwrap_or_else(|| "the working directory".into());
+        out.push_str(&format!(" {} holds: {}", place, listed.join(", ")));
+        if more > 0 {
+            out.push_str(&format!(" and {} more", more));
+        }
+        out.push('.');
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use crate::mock::{last_content, MockProvider};
+
+    #[tokio::test]
+    async fn bad_calls_come_back_with_close_matches() {
+        let mock = MockProvider::start().await;
+        mock.tool_call("list_dir", json!({"path": "src/tool"})).await;
+        mock.tool_call("list_dir", json!({"paht": "src"})).await;
+        mock.tool_call("list_dir", json!({"path": "src/hint.rs"})).await;
+        mock.reply("done").await;
+        let mut agent = mock.agent();
+        agent.enable_quiet();
+        agent.request_tools().await.unwrap();
+        let requests = mock.requests().await;
+        let last = |i: usize| last_content(&requests[i]);
+        assert!(last(1).contains("Close names: `src/tools`"), "{}", last(1));
+        assert!(last(2).contains("There is no argument `paht`. Did you mean `path`?"), "{}", last(2));
+        assert!(last(2).contains("path: string, required"), "{}", last(2));
+        assert!(last(3).contains("Did you mean `src/tools/hints.rs`?"), "{}", last(3));
+    }
+}
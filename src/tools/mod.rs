@@ -0,0 +1,510 @@
+use std::{fs, io::{self, Write}, path::{Path, PathBuf}, process::Command};
+use anyhow::{Result, Context, bail};
+use crate::policy::{Action, Call, Decision, Origin};
+
+mod approval;
+pub mod archive;
+mod calculate;
+mod call;
+mod custom;
+pub mod diff;
+pub mod download;
+mod environment;
+mod eval;
+mod git;
+pub mod hints;
+pub mod network;
+pub mod notes;
+pub mod patch;
+mod random;
+mod regex_test;
+mod replace;
+mod structured;
+mod scratch;
+mod shell;
+mod simulate;
+pub mod terraform;
+
+pub use call::{EditOp, RandomKind, ToolCall};
+pub use custom::Tool;
+pub(crate) use eval::HIDDEN_ENV;
+pub(crate) use shell::DEFAULT_OUTPUT_LIMIT;
+pub use scratch::cleanup as remove_scratch_dirs;
+pub use simulate::Simulation;
+
+/// Per-session state shared by tool invocations
+#[derive(Default)]
+pub struct ToolContext {
+    scratch: scratch::ScratchDirs,
+    /// The session scratchpad of `write_note` and `read_notes`
+    pub notes: notes::Notes,
+    /// Refuse to write `.rs` files that don't parse
+    pub lint_rust: bool,
+    pub lang: crate::i18n::Lang,
+    /// Screen-reader friendly output: no redrawn progress lines, diffs as sentences
+    pub a11y: bool,
+    /// Suppress progress output
+    pub quiet: bool,
+    pub theme: crate::theme::Theme,
+    pub policy: crate::policy::Policy,
+    /// Workspace member selected with `--package`; cargo commands are scoped to it
+    pub package: Option<String>,
+    /// Describe calls that would change something instead of running them
+    pub dry_run: bool,
+    /// Nobody can answer on the terminal (stdin carries input): approvals are declined and
+    /// questions go unanswered
+    pub unattended: bool,
+    /// Frontend subscribed to agent events; approvals go to it instead of the terminal
+    pub events: Option<crate::events::Sink>,
+    /// `approval_command`: program asked before anyone else, with the action as JSON
+    pub approval_command: Option<String>,
+    /// Decision overriding the policy for the next call that changes something, set when it may
+    /// act on untrusted content
+    pub caution: Option<crate::policy::Decision>,
+    /// Hosts `fetch_url` and `download_file` may reach
+    pub network: network::Network,
+    /// `command_timeout_secs`: seconds `run_command` may take when the call sets no limit, and `terraform_plan` always
+    pub command_timeout_secs: Option<u64>,
+    /// `command_output_limit`: bytes of `run_command` output returned to the model
+    pub command_output_limit: Option<usize>,
+    /// Binaries, minified bundles and generated files `read_file` and `replace_in_files` pass over
+    pub bulk: crate::bulk::Guard,
+    /// CODEOWNERS of the repository, consulted before files are changed
+    pub owners: Option<std::sync::Arc<crate::owners::Owners>>,
+    /// Embedded chunks of the repository, searched by `search_codebase`
+    pub index: Option<std::sync::Arc<crate::index::Retriever>>,
+    /// Workspace the tools run in instead of the process's working directory, set with
+    /// [`ToolContext::set_workdir`]
+    pub workdir: Option<PathBuf>,
+    /// Token of the running turn; commands are killed when it is cancelled
+    pub cancel: tokio_util::sync::CancellationToken,
+    pub approvals: u64,
+    pub denials: u64,
+}
+
+impl ToolContext {
+    /// Directory relative path arguments and commands resolve against: the workspace, else the
+    /// working directory
+    pub fn dir(&self) -> PathBuf {
+        self.workdir.clone().or_else(|| crate::paths::current_dir().ok()).unwrap_or_default()
+    }
+
+    /// Run the tools in `dir`, with their path arguments checked against it too
+    pub fn set_workdir(&mut self, dir: PathBuf) {
+        self.policy.workdir = Some(dir.clone());
+        self.workdir = Some(dir);
+    }
+
+    pub fn progress_style(&self) -> download::Progress {
+        match (self.quiet, self.a11y) {
+            (true, _) => download::Progress::Hidden,
+            (false, true) => download::Progress::Lines,
+            (false, false) => download::Progress::Redraw,
+        }
+    }
+
+    /// Whether running `call` would stop for an approval prompt
+    pub fn will_ask(&self, call: &ToolCall) -> bool {
+        !self.policy.yolo && self.policy.evaluate(&Call::now(call, self.policy.role.as_deref())).action == Action::Ask
+    }
+
+    /// What `call` would do, with the owners of the files it changes
+    pub fn effect(&self, call: &ToolCall) -> Simulation {
+        let dir = self.dir();
+        let mut effect = call.simulate(&dir, self.package.as_deref());
+        if let Some(owners) = self.owners.as_ref().filter(|_| !effect.read_only) {
+            effect.owners = owners.describe(&dir, &effect.files);
+        }
+        effect
+    }
+
+    /// Whether `call` only reads and the policy allows it outright, without asking or `--yolo`
+    pub fn runs_unasked(&self, call: &ToolCall) -> bool {
+        call.simulate(&self.dir(), self.package.as_deref()).read_only
+            && self.policy.evaluate(&Call::now(call, self.policy.role.as_deref())).action == Action::Allow
+    }
+
+    /// A context for running read-only calls away from the session: same policy, directory and
+    /// package, but quiet, and declining anything that would ask
+    pub fn detached(&self) -> ToolContext {
+        let mut policy = self.policy.clone();
+        policy.yolo = false;
+        ToolContext {
+            policy,
+            workdir: self.workdir.clone(),
+            package: self.package.clone(),
+            lang: self.lang.clone(),
+            bulk: self.bulk.clone(),
+            quiet: true,
+            unattended: true,
+            ..ToolContext::default()
+        }
+    }
+
+    /// Ask for approval, counting the outcome for usage stats
+    pub fn confirm(&mut self, question: &str) -> Result<bool> {
+        Ok(self.confirm_action(question, serde_json::json!({"kind": "confirmation"}))?.0)
+    }
+
+    /// Ask for approval of `action`, described for `approval_command`, which decides before the
+    /// frontend or terminal are asked; returns the answer and the reason the command gave
+    fn confirm_action(&mut self, question: &str, mut action: serde_json::Value) -> Result<(bool, Option<String>)> {
+        let delegated = self.approval_command.as_deref().and_then(|command| {
+            action["question"] = question.into();
+            approval::delegate(command, &action)
+        });
+        if let Some((approved, reason)) = delegated {
+            tracing::info!("approval command {}: {}{}", if approved { "approved" } else { "declined" }, question.lines().last().unwrap_or(question),
+                reason.as_deref().map(|r| format!(" ({})", r)).unwrap_or_default());
+            self.count(approved);
+            return Ok((approved, reason));
+        }
+        let approved = match self.events.as_ref().and_then(|sink| sink.ask(question)) {
+            Some(answer) => answer,
+            None if self.unattended => {
+                tracing::warn!("declined without asking (no terminal to answer on; --yolo approves): {}", question.lines().last().unwrap_or(question));
+                false
+            }
+            None => confirm(&self.lang, question)?,
+        };
+        self.count(approved);
+        Ok((approved, None))
+    }
+
+    fn count(&mut self, approved: bool) {
+        if approved {
+            self.approvals += 1;
+        } else {
+            self.denials += 1;
+        }
+    }
+
+    /// Ask before a step of a workflow, unless `--yolo` waives approvals
+    pub fn approve(&mut self, question: &str) -> Result<bool> {
+        if self.policy.yolo {
+            return Ok(true);
+        }
+        self.confirm(question)
+    }
+
+    /// Put a question from the model to the user: to the subscribed frontend, else on the terminal
+    pub fn ask_user(&mut self, question: &str) -> Result<String> {
+        if let Some(answer) = self.events.as_ref().and_then(|sink| sink.question(question)) {
+            return Ok(answer);
+        }
+        if self.unattended {
+            return Ok(String::new());
+        }
+        println!("{} {}", self.theme.paint(crate::theme::Role::Assistant, "RustAIgent asks:"), question);
+        print!("> ");
+        io::stdout().flush()?;
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer)?;
+        Ok(answer.trim().to_string())
+    }
+}
+
+/// Tools that prompt the user or draw their own progress, so no spinner is shown while they run
+pub const INTERACTIVE: &[&str] = &["download_file", "replace_in_files", "ask_user", "run_command"];
+
+/// Ask the user a yes/no question on the terminal; anything but an explicit yes declines
+pub fn confirm(lang: &crate::i18n::Lang, question: &str) -> Result<bool> {
+    print!("{} {} ", question, lang.msg(crate::i18n::Msg::ConfirmSuffix));
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(lang.is_yes(&answer))
+}
+
+/// Error of a call the policy or the user refused; it never ran, so it is no failure of the tool
+#[derive(Debug)]
+pub struct Refused(String);
+
+impl std::fmt::Display for Refused {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Refused {}
+
+/// Whether a call's result says it was stopped at a time limit: commands and builds report the
+/// kill in their output, MCP servers and HTTP requests as errors
+pub fn timed_out(result: &Result<String>) -> bool {
+    match result {
+        Ok(out) => out.starts_with("Killed after the ") || out.starts_with("Build timed out after "),
+        Err(err) => {
+            let err = format!("{:#}", err);
+            err.contains("did not answer") || err.contains("timed out")
+        }
+    }
+}
+
+/// Let a call through, refuse it or ask first, as the policy decides unless `caution` overrides
+/// an allow or ask; `effect` is what the approval prompt says the call would do. `--yolo`
+/// approves what would ask, except commands scored critical.
+fn authorize(ctx: &mut ToolContext, call: &Call, effect: &dyn std::fmt::Display, caution: Option<Decision>) -> Result<()> {
+    let mut decision = ctx.policy.evaluate(call);
+    if let Some(caution) = caution.filter(|_| decision.action != Action::Deny) {
+        decision = caution;
+    }
+    tracing::debug!("policy: {} {}", call.tool, decision);
+    let yolo = ctx.policy.yolo && !matches!(decision.origin, Origin::Risk(score) if score >= crate::risk::CRITICAL);
+    match decision.action {
+        Action::Allow => {}
+        Action::Ask if yolo && matches!(decision.origin, Origin::Untrusted | Origin::Risk(_) | Origin::Owners) => tracing::warn!("approved by --yolo: {} {}", call.tool, decision),
+        Action::Ask if yolo => tracing::debug!("approved by --yolo"),
+        Action::Deny => return Err(Refused(format!("Tool `{}` {}", call.tool, decision)).into()),
+        Action::Ask => {
+            let why = decision.reason.as_deref().unwrap_or("policy requires approval");
+            let workdir = ctx.dir();
+            let action = serde_json::json!({
+                "kind": "tool_call",
+                "tool": call.tool,
+                "args": call.args,
+                "paths": call.paths,
+                "role": call.role,
+                "effect": effect.to_string(),
+                "reason": why,
+                "workdir": workdir,
+            });
+            match ctx.confirm_action(&format!("{}\nAllow {} ({})?", effect, call.tool, why), action)? {
+                (true, _) => {}
+                (false, Some(reason)) => return Err(Refused(format!("Tool `{}` was not approved: {}", call.tool, reason)).into()),
+                (false, None) => return Err(Refused(format!("Tool `{}` was not approved", call.tool)).into()),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Result of a call skipped by a dry run; denials are still reported as errors
+fn dry_run(ctx: &ToolContext, call: &Call, effect: &dyn std::fmt::Display) -> Result<String> {
+    let decision = ctx.policy.evaluate(call);
+    if decision.action == Action::Deny {
+        return Err(Refused(format!("Tool `{}` {}", call.tool, decision)).into());
+    }
+    Ok(format!("Dry run: nothing was executed. The call {} and would do this:\n{}", decision, effect))
+}
+
+/// Execute a tool registered by the embedding application, under the same policy as built-in ones
+pub async fn execute_custom(ctx: &mut ToolContext, tool: &dyn Tool, args: serde_json::Value) -> Result<String> {
+    let name = tool.definition().name;
+    let effect = tool.simulate(&args).unwrap_or_else(|| format!("Calls {}({})", name, args));
+    let role = ctx.policy.role.clone();
+    let call = Call::custom(&name, args.clone(), role.as_deref());
+    if ctx.dry_run {
+        return dry_run(ctx, &call, &effect);
+    }
+    let caution = ctx.caution.take();
+    authorize(ctx, &call, &effect, caution)?;
+    tool.call(args).await
+}
+
+/// Execute a validated tool call, returning the text handed back to the model
+pub async fn execute(ctx: &mut ToolContext, call: &ToolCall) -> Result<String> {
+    let dir = ctx.dir();
+    let role = ctx.policy.role.clone();
+    let effect = ctx.effect(call);
+    if ctx.dry_run && !effect.read_only {
+        return dry_run(ctx, &Call::now(call, role.as_deref()), &effect);
+    }
+    let owned = ctx.owners.as_ref().filter(|_| !effect.read_only).and_then(|owners| owners.caution(&dir, &effect.files));
+    let caution = ctx.caution.take().filter(|_| !effect.read_only).or(owned);
+    authorize(ctx, &Call::now(call, role.as_deref()), &effect, caution)?;
+    match call {
+        ToolCall::ReadFile { path } => {
+            if let Some(skip) = ctx.bulk.skip(&dir, Path::new(path)) {
+                bail!("Not reading {}: it is {}. Look into it with run_command (head, grep, wc) instead, or ask the user to add it to `include_files`.", path, skip);
+            }
+            Ok(fs::read_to_string(dir.join(path))?)
+        }
+        ToolCall::WriteFile { path, content } => {
+            if ctx.lint_rust && path.ends_with(".rs") {
+                if let Some(err) = crate::lint::syntax_error(content) {
+                    bail!("Not writing {}: content does not parse as Rust ({}). Fix the syntax and retry.", path, err);
+                }
+            }
+            fs::write(dir.join(path), content)?;
+            Ok(format!("Wrote {} bytes to {}", content.len(), path))
+        }
+        ToolCall::ApplyPatch { patch, path } => patch::apply(ctx, patch, path.as_deref()),
+        ToolCall::DeleteFile { path } => {
+            fs::remove_file(dir.join(path))?;
+            Ok(format!("Deleted {}", path))
+        }
+        ToolCall::ListDir { path } => {
+            let mut names = fs::read_dir(dir.join(path))?
+                .map(|e| e.map(|e| e.file_name().to_string_lossy().into_owned()))
+                .collect::<Result<Vec<_>, _>>()?;
+            names.sort();
+            Ok(names.join("\n"))
+        }
+        ToolCall::RunCommand { command, timeout_secs } => {
+            let command = match &ctx.package {
+                Some(package) => crate::workspace::scope_command(command, package),
+                None => command.clone(),
+            };
+            let timeout = timeout_secs.map(u64::from).or(ctx.command_timeout_secs).unwrap_or(shell::DEFAULT_TIMEOUT_SECS);
+            let (limit, echo, cancel) = (ctx.command_output_limit.unwrap_or(shell::DEFAULT_OUTPUT_LIMIT), !ctx.quiet, ctx.cancel.clone());
+            blocking(move || shell::run(&dir, &command, timeout, limit, echo, &cancel)).await
+        }
+        ToolCall::GitStatus {} => git::status(&dir),
+        ToolCall::GitDiff { staged, base, path } => git::diff(&dir, staged.unwrap_or(false), base.as_deref(), path.as_deref()),
+        ToolCall::GitCommit { message, paths, all } => git::commit(&dir, message, paths.as_deref().unwrap_or_default(), all.unwrap_or(false)),
+        ToolCall::GitCreateBranch { name, start, checkout } => git::create_branch(&dir, name, start.as_deref(), checkout.unwrap_or(true)),
+        ToolCall::TerraformPlan { dir: module, plan_file } => {
+            let (module, plan_file, timeout, cancel) = (module.clone(), plan_file.clone(), ctx.command_timeout_secs, ctx.cancel.clone());
+            blocking(move || terraform::plan(&dir, module.as_deref().unwrap_or("."), plan_file.as_deref(), timeout, &cancel)).await
+        }
+        ToolCall::RunTests { project } => {
+            let (package, project) = (ctx.package.clone(), project.clone());
+            blocking(move || run_tests(package.as_deref(), &dir, project.as_deref())).await
+        }
+        ToolCall::FetchUrl { url } => ctx.network.fetch(url).await,
+        ToolCall::EvalCode { code, dependencies, timeout_secs } => {
+            let (code, dependencies, timeout, cancel) = (code.clone(), dependencies.clone().unwrap_or_default(), timeout_secs.map(u64::from), ctx.cancel.clone());
+            blocking(move || eval::eval(&dir, &code, &dependencies, timeout, &cancel)).await
+        }
+        ToolCall::ExtractArchive { archive, destination } => archive::extract(&dir, archive, destination),
+        ToolCall::CreateArchive { archive, paths } => archive::create(&dir, archive, &paths.iter().map(String::as_str).collect::<Vec<_>>()),
+        ToolCall::DownloadFile { url, path, sha256 } => download::download(&ctx.network, url, &dir, path, sha256.as_deref(), ctx.progress_style()).await,
+        ToolCall::Diff { left, right, content } => diff::diff(&dir, left, right.as_deref(), content.as_deref()),
+        ToolCall::ReplaceInFiles { pattern, replacement, scope } => replace::replace_in_files(ctx, &dir, pattern, replacement, scope),
+        ToolCall::EditStructured { path, pointer, op, value } => structured::edit_structured(&dir, path, pointer, *op, value.as_ref()),
+        ToolCall::Calculate { expression } => calculate::calculate(expression),
+        ToolCall::GenerateRandom { kind, length, count, symbols, avoid_ambiguous } => random::generate(*kind, *length, *count, *symbols, *avoid_ambiguous),
+        ToolCall::TestRegex { pattern, inputs } => regex_test::test_regex(pattern, inputs),
+        ToolCall::SearchCodebase { query, k } => match &ctx.index {
+            Some(index) => Ok(crate::index::render(&index.search(query, k.unwrap_or(5) as usize).await?)),
+            None => bail!("The codebase is not indexed; run `rustaigent index` first"),
+        },
+        ToolCall::ReadArtifact { id, offset, len } => crate::artifacts::read(id, offset.unwrap_or(0) as usize, len.map_or(crate::artifacts::MAX_READ, |n| n as usize)),
+        ToolCall::WriteNote { note, replaces } => ctx.notes.write(note, *replaces),
+        ToolCall::ReadNotes { query } => Ok(ctx.notes.read(query.as_deref())),
+        ToolCall::GetEnvironment {} => environment::describe(&dir),
+        ToolCall::AskUser { question } => match ctx.ask_user(question)?.as_str() {
+            "" => Ok("The user gave no answer; proceed with your best judgement and state what you assumed.".into()),
+            answer => Ok(format!("The user answered: {}", answer)),
+        },
+        ToolCall::MakeScratchDir { label } => {
+            let dir = ctx.scratch.create(label.as_deref())?;
+            ctx.policy.allow_scratch(&dir);
+            Ok(format!("Created scratch directory {} (removed at session end)", dir.display()))
+        }
+    }
+}
+
+/// Run `work`, which waits on a process, on the blocking pool so it holds no runtime worker;
+/// `serve` and the daemon run many sessions on one runtime
+async fn blocking<T: Send + 'static>(work: impl FnOnce() -> Result<T> + Send + 'static) -> Result<T> {
+    tokio::task::spawn_blocking(work).await.context("The tool's task failed")?
+}
+
+/// Paths matching `pattern`, relative to `dir` unless the pattern is absolute
+pub(crate) fn glob_in(dir: &Path, pattern: &str) -> Result<Vec<PathBuf>> {
+    let full = match Path::new(pattern).is_absolute() {
+        true => pattern.to_string(),
+        false => format!("{}/{}", glob::Pattern::escape(&dir.to_string_lossy()), pattern),
+    };
+    let matched = glob::glob(&full).with_context(|| format!("Invalid glob `{}`", pattern))?;
+    Ok(matched.flatten().map(|path| path.strip_prefix(dir).map(Path::to_path_buf).unwrap_or(path)).collect())
+}
+
+/// Run the test command of each project detected in `dir`, or only the one whose language
+/// matches `only`
+fn run_tests(package: Option<&str>, dir: &Path, only: Option<&str>) -> Result<String> {
+    let projects = crate::project::detect(dir);
+    let selected: Vec<_> = projects.iter()
+        .filter(|p| only.is_none_or(|o| p.language.to_lowercase().contains(&o.to_lowercase())))
+        .collect();
+    if selected.is_empty() {
+        bail!("No {}project manifest found in the current directory", only.map(|o| format!("{} ", o)).unwrap_or_default());
+    }
+    let mut report = String::new();
+    for p in selected {
+        let command = match package {
+            Some(package) => crate::workspace::scope_command(&p.test, package),
+            None => p.test.clone(),
+        };
+        let out = Command::new("sh").arg("-c").arg(&command).current_dir(dir).output()?;
+        report.push_str(&format!("$ {} ({})\n{}{}", command, out.status, String::from_utf8_lossy(&out.stdout), String::from_utf8_lossy(&out.stderr)));
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use serde_json::json;
+    use crate::mock::MockProvider;
+    use super::{execute, ToolCall, ToolContext};
+
+    #[tokio::test]
+    async fn tool_calls_run_until_the_model_answers() {
+        let mock = MockProvider::start().await;
+        mock.tool_call("list_dir", json!({"path": "src"})).await;
+        mock.reply("done").await;
+        let mut agent = mock.agent();
+        agent.enable_quiet();
+        let reply = agent.request_tools().await.unwrap();
+        assert_eq!(reply.content, "done");
+        let requests = mock.requests().await;
+        assert_eq!(requests.len(), 2);
+        let messages = requests[1]["messages"].as_array().unwrap();
+        assert_eq!(messages[messages.len() - 2]["function_call"]["name"], "list_dir");
+        assert_eq!(messages[messages.len() - 1]["role"], "function");
+        assert_eq!(agent.tool_log.len(), 1);
+    }
+
+    struct Echo;
+
+    impl crate::Tool for Echo {
+        fn definition(&self) -> crate::FunctionDefinition {
+            crate::FunctionDefinition {
+                name: "echo".into(),
+                description: "Repeat the text".into(),
+                parameters: json!({"type":"object","properties":{"text":{"type":"string"}},"required":["text"]}),
+            }
+        }
+
+        fn call(&self, args: serde_json::Value) -> futures::future::BoxFuture<'_, anyhow::Result<String>> {
+            Box::pin(async move { Ok(format!("echo: {}", args["text"].as_str().unwrap_or_default())) })
+        }
+    }
+
+    #[tokio::test]
+    async fn registered_tools_are_offered_and_run() {
+        let mock = MockProvider::start().await;
+        mock.tool_call("echo", json!({"text": "hi"})).await;
+        mock.reply("done").await;
+        let mut agent = crate::AgentBuilder::new().provider("openai").api_key("k1").tool(Echo).build().unwrap();
+        agent.base_url = Some(mock.base_url());
+        agent.streaming = false;
+        agent.config.turns = None;
+        agent.enable_quiet();
+        let reply = agent.request_tools().await.unwrap();
+        assert_eq!(reply.content, "done");
+        let requests = mock.requests().await;
+        assert!(requests[0]["functions"].as_array().unwrap().iter().any(|f| f["name"] == "echo"));
+        let messages = requests[1]["messages"].as_array().unwrap();
+        assert_eq!(messages[messages.len() - 1]["content"], "echo: hi");
+        let stats = agent.stats.lock().unwrap();
+        assert_eq!(stats.tool_calls["echo"], 1);
+        assert_eq!((stats.tools["echo"].calls, stats.tools["echo"].failures, stats.tools["echo"].timed), (1, 0, 1));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn commands_leave_the_runtime_free_while_they_run() {
+        let mut ctx = ToolContext { quiet: true, ..ToolContext::default() };
+        let ticker = tokio::spawn(async {
+            for _ in 0..5 {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        });
+        let call = ToolCall::parse("run_command", json!({ "command": "sleep 0.5" })).unwrap();
+        execute(&mut ctx, &call).await.unwrap();
+        assert!(ticker.is_finished(), "the single runtime thread was blocked by the command");
+    }
+}
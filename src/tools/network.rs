@@ -0,0 +1,238 @@
+//! Where `fetch_url` and `download_file` may connect. With `allowed_hosts` set only those hosts
+//! are reached, `*.example.com` standing for any subdomain. Loopback, private and link-local
+//! addresses are refused unless `allow_private_network` is on, so a model steered by a page
+//! cannot probe internal services or a cloud metadata endpoint. Names are checked up front, the
+//! session's client resolves them again through the same filter when it connects, and every
+//! redirect is checked again.
+
+use std::{net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr}, sync::{Arc, OnceLock}, time::Duration};
+use anyhow::{Context, Result, bail};
+use hyper014::client::connect::dns::Name;
+use reqwest::{Url, dns::{Addrs, Resolve, Resolving}, header::LOCATION, redirect};
+use crate::http::{self, HttpConfig};
+use super::Refused;
+
+/// Redirects followed before giving up
+const MAX_REDIRECTS: usize = 10;
+
+/// Seconds to establish a connection
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Seconds a fetch or download may take in all, body included
+const TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Bytes of a page `fetch_url` reads; the rest is left unread
+const MAX_FETCH_BYTES: usize = 1024 * 1024;
+
+/// `allowed_hosts` and `allow_private_network` of a session, with the client its fetches and
+/// downloads share
+#[derive(Default, Clone)]
+pub struct Network {
+    allowed: Option<Vec<String>>,
+    private: bool,
+    http: Option<HttpConfig>,
+    client: Arc<OnceLock<reqwest::Client>>,
+}
+
+/// Name resolution of the session's client: private and local addresses are dropped unless
+/// `allow_private_network` is on, so a name cannot be pointed at them after it was checked
+struct Resolver {
+    private: bool,
+}
+
+impl Resolve for Resolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let private = self.private;
+        Box::pin(async move {
+            let host = name.as_str();
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, 0)).await?
+                .filter(|addr| private || !local(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("`{}` resolves to no public address", host).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+impl Network {
+    pub fn new(allowed: Option<Vec<String>>, private: bool, http: Option<&HttpConfig>) -> Self {
+        let allowed = allowed.map(|hosts| hosts.iter().map(|h| h.trim().trim_end_matches('.').to_lowercase()).collect());
+        Network { allowed, private, http: http.cloned(), client: Arc::default() }
+    }
+
+    /// The client, built with the `[http]` tuning on first use; redirects are followed by `get`
+    fn client(&self) -> &reqwest::Client {
+        self.client.get_or_init(|| {
+            let build = |builder: reqwest::ClientBuilder| builder
+                .redirect(redirect::Policy::none())
+                .dns_resolver(Arc::new(Resolver { private: self.private }))
+                .connect_timeout(CONNECT_TIMEOUT)
+                .timeout(TIMEOUT)
+                .build();
+            build(http::builder(self.http.as_ref())).unwrap_or_else(|err| {
+                tracing::warn!("ignoring [http] settings: {}", err);
+                build(reqwest::Client::builder()).expect("HTTP client without [http] settings")
+            })
+        })
+    }
+
+    /// Refuse `url` when its scheme is not http(s), its host is off the allowlist or it is, or
+    /// resolves only to, a private address
+    async fn admit(&self, url: &Url) -> Result<()> {
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(Refused(format!("Only http and https URLs can be fetched, not `{}`", url.scheme())).into());
+        }
+        let Some(host) = url.host_str().map(|h| h.trim_end_matches('.').to_lowercase()) else {
+            bail!("`{}` names no host", url);
+        };
+        if let Some(allowed) = &self.allowed {
+            if !allowed.iter().any(|pattern| covers(pattern, &host)) {
+                return Err(Refused(format!("`{}` is not in `allowed_hosts` ({})", host, allowed.join(", "))).into());
+            }
+        }
+        if self.private {
+            return Ok(());
+        }
+        let literal = host.trim_start_matches('[').trim_end_matches(']');
+        if let Ok(ip) = literal.parse::<IpAddr>() {
+            if local(ip) {
+                return Err(Refused(format!("`{}` is a private or local address; set `allow_private_network = true` to reach it", host)).into());
+            }
+            return Ok(());
+        }
+        let port = url.port_or_known_default().unwrap_or(80);
+        let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), port)).await
+            .with_context(|| format!("Could not resolve `{}`", host))?
+            .collect();
+        if addrs.is_empty() {
+            bail!("`{}` has no addresses", host);
+        }
+        if addrs.iter().all(|a| local(a.ip())) {
+            return Err(Refused(format!("`{}` resolves to a private or local address; set `allow_private_network = true` to reach it", host)).into());
+        }
+        Ok(())
+    }
+
+    /// GET `url`, following redirects that pass the same checks
+    pub async fn get(&self, url: &str) -> Result<reqwest::Response> {
+        let mut url = Url::parse(url).with_context(|| format!("`{}` is not a valid URL", url))?;
+        for _ in 0..=MAX_REDIRECTS {
+            self.admit(&url).await?;
+            let resp = self.client().get(url.clone()).send().await?;
+            let location = resp.headers().get(LOCATION).and_then(|l| l.to_str().ok());
+            match location {
+                Some(location) if resp.status().is_redirection() => url = url.join(location)?,
+                _ => return Ok(resp),
+            }
+        }
+        bail!("Gave up on {} after {} redirects", url, MAX_REDIRECTS)
+    }
+
+    /// The body of `url` as text, cut after `MAX_FETCH_BYTES` with a note saying so
+    pub async fn fetch(&self, url: &str) -> Result<String> {
+        let mut resp = self.get(url).await?;
+        let mut body = Vec::new();
+        while let Some(chunk) = resp.chunk().await? {
+            body.extend_from_slice(&chunk);
+            if body.len() > MAX_FETCH_BYTES {
+                body.truncate(MAX_FETCH_BYTES);
+                let mut text = String::from_utf8_lossy(&body).into_owned();
+                text.push_str(&format!("\n… the page was cut after {} bytes; use download_file for the whole of it", MAX_FETCH_BYTES));
+                return Ok(text);
+            }
+        }
+        Ok(String::from_utf8_lossy(&body).into_owned())
+    }
+}
+
+/// Whether `host` is `pattern`, or a subdomain of it for `*.` patterns
+fn covers(pattern: &str, host: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host.strip_suffix(domain).is_some_and(|sub| sub.ends_with('.') && sub.len() > 1),
+        None => pattern == "*" || pattern == host,
+    }
+}
+
+/// Loopback, private, link-local, shared and unspecified addresses
+fn local(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => local_v4(v4),
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => local_v4(v4),
+            None => local_v6(v6),
+        },
+    }
+}
+
+fn local_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    ip.is_loopback() || ip.is_private() || ip.is_link_local() || ip.is_unspecified() || ip.is_broadcast()
+        // Carrier-grade NAT, 100.64.0.0/10
+        || (a == 100 && (64..128).contains(&b))
+        || a == 0
+}
+
+fn local_v6(ip: Ipv6Addr) -> bool {
+    let segments = ip.segments();
+    let first = segments[0];
+    // IPv4-compatible ::a.b.c.d and NAT64 64:ff9b::a.b.c.d reach the IPv4 address they carry
+    if segments[..6] == [0; 6] || segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+        let [.., a, b, c, d] = ip.octets();
+        return local_v4(Ipv4Addr::new(a, b, c, d));
+    }
+    ip.is_loopback() || ip.is_unspecified()
+        // Unique local fc00::/7 and link-local fe80::/10
+        || (first & 0xfe00) == 0xfc00
+        || (first & 0xffc0) == 0xfe80
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use crate::mock::{last_content, MockProvider};
+    use super::{covers, local, Network, MAX_FETCH_BYTES};
+
+    #[tokio::test]
+    async fn fetches_of_local_or_unlisted_hosts_are_refused() {
+        let mock = MockProvider::start().await;
+        mock.page("/admin", "secret").await;
+        mock.tool_call("fetch_url", json!({"url": format!("{}/admin", mock.base_url())})).await;
+        mock.tool_call("fetch_url", json!({"url": "http://metadata.example.com/latest"})).await;
+        mock.reply("done").await;
+        let mut agent = mock.tool_agent();
+        agent.tool_ctx.network = Network::new(Some(vec!["127.0.0.1".into(), "*.github.com".into()]), false, None);
+        agent.request_tools().await.unwrap();
+        let requests = mock.requests().await;
+        let last = |i: usize| last_content(&requests[i]);
+        assert!(last(1).contains("private or local address") && !last(1).contains("secret"), "{}", last(1));
+        assert!(last(2).contains("is not in `allowed_hosts`"), "{}", last(2));
+        assert!(agent.stats.lock().unwrap().tools.get("fetch_url").is_none_or(|t| t.failures == 0));
+    }
+
+    #[test]
+    fn wildcards_cover_subdomains_only() {
+        assert!(covers("*.github.com", "api.github.com") && covers("*.github.com", "a.b.github.com"));
+        assert!(!covers("*.github.com", "github.com") && !covers("*.github.com", "evilgithub.com") && !covers("*.github.com", ".github.com"));
+        assert!(covers("*", "example.com") && covers("example.com", "example.com") && !covers("example.com", "www.example.com"));
+    }
+
+    #[test]
+    fn private_and_local_addresses_are_recognized() {
+        let local_addrs = ["127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "169.254.169.254", "100.64.0.1", "0.0.0.0", "255.255.255.255", "::1", "::", "fd00::1", "fe80::1", "::ffff:127.0.0.1", "::127.0.0.1", "::10.0.0.1", "64:ff9b::169.254.169.254", "64:ff9b::10.1.2.3"];
+        let public = ["8.8.8.8", "100.128.0.1", "172.32.0.1", "2606:4700::1111", "::ffff:1.1.1.1", "::1.1.1.1", "64:ff9b::8.8.8.8"];
+        assert!(local_addrs.iter().all(|ip| local(ip.parse().unwrap())), "{:?}", local_addrs.iter().filter(|ip| !local(ip.parse().unwrap())).collect::<Vec<_>>());
+        assert!(public.iter().all(|ip| !local(ip.parse().unwrap())), "{:?}", public.iter().filter(|ip| local(ip.parse().unwrap())).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn fetched_pages_are_cut_at_the_byte_limit() {
+        let mock = MockProvider::start().await;
+        mock.page("/big", &"x".repeat(MAX_FETCH_BYTES * 2)).await;
+        let page = Network::new(None, true, None).fetch(&format!("{}/big", mock.base_url())).await.unwrap();
+        let (kept, note) = page.split_once('\n').unwrap();
+        assert_eq!(kept.len(), MAX_FETCH_BYTES);
+        assert!(note.contains("the page was cut"));
+    }
+}
@@ -0,0 +1,103 @@
+//! The session scratchpad behind `write_note` and `read_notes`: findings the model keeps as
+//! working memory across tool rounds, so facts are looked up in its notes instead of being
+//! derived again after older turns were summarized or paged out. Stored per session id under
+//! the data directory, sealed when `encryption` is on.
+
+use std::{fs, path::PathBuf};
+use anyhow::{Result, bail};
+use crate::{paths, vault};
+
+/// Characters one note may hold
+const MAX_NOTE: usize = 4000;
+/// Notes one session may keep
+const MAX_NOTES: usize = 200;
+
+fn file(session: &str) -> PathBuf {
+    paths::data_dir().join("notes").join(format!("{}.json", session))
+}
+
+fn load(session: &str) -> Vec<String> {
+    match vault::read(&file(session)) {
+        Ok(Some(data)) => serde_json::from_slice(&data).unwrap_or_default(),
+        Ok(None) => Vec::new(),
+        Err(err) => {
+            tracing::warn!("starting with an empty scratchpad: {:#}", err);
+            Vec::new()
+        }
+    }
+}
+
+/// Numbered notes of one session; kept in memory only until [`Notes::open`] names the session
+#[derive(Default)]
+pub struct Notes {
+    entries: Vec<String>,
+    session: Option<String>,
+}
+
+impl Notes {
+    pub fn open(session: &str) -> Self {
+        Notes { entries: load(session), session: Some(session.into()) }
+    }
+
+    /// Carry the notes of a resumed session over, ahead of any taken since
+    pub fn adopt(&mut self, session: &str) {
+        if self.session.as_deref() == Some(session) {
+            return;
+        }
+        let mut entries = load(session);
+        if entries.is_empty() {
+            return;
+        }
+        entries.append(&mut self.entries);
+        self.entries = entries;
+        if let Err(err) = self.save() {
+            tracing::warn!("keeping the resumed notes in memory: {:#}", err);
+        }
+    }
+
+    fn save(&self) -> Result<()> {
+        let Some(session) = &self.session else { return Ok(()) };
+        let path = file(session);
+        fs::create_dir_all(path.parent().unwrap_or(&path))?;
+        vault::write(&path, serde_json::to_vec(&self.entries)?)
+    }
+
+    /// Add a note, or replace note `replaces` (numbered from 1)
+    pub fn write(&mut self, note: &str, replaces: Option<u32>) -> Result<String> {
+        let note = note.trim();
+        if note.is_empty() {
+            bail!("The note is empty");
+        }
+        if note.chars().count() > MAX_NOTE {
+            bail!("A note may hold {} characters; split it or keep only the finding", MAX_NOTE);
+        }
+        match replaces {
+            Some(n) => {
+                let Some(entry) = self.entries.get_mut((n as usize).wrapping_sub(1)) else {
+                    bail!("There is no note {}; notes are numbered 1 to {}", n, self.entries.len());
+                };
+                *entry = note.into();
+                self.save()?;
+                return Ok(format!("Replaced note {}", n));
+            }
+            None if self.entries.len() >= MAX_NOTES => bail!("The scratchpad holds {} notes; replace an outdated one instead", MAX_NOTES),
+            None => self.entries.push(note.into()),
+        }
+        self.save()?;
+        Ok(format!("Saved note {}", self.entries.len()))
+    }
+
+    /// Every note, or those containing `query` (ignoring case), with their numbers
+    pub fn read(&self, query: Option<&str>) -> String {
+        let query = query.map(str::to_lowercase).filter(|q| !q.trim().is_empty());
+        let shown: Vec<String> = self.entries.iter().enumerate()
+            .filter(|(_, note)| query.as_ref().is_none_or(|q| note.to_lowercase().contains(q)))
+            .map(|(i, note)| format!("Note {}: {}", i + 1, note))
+            .collect();
+        match (shown.is_empty(), &query) {
+            (true, None) => "No notes yet; save findings with write_note".into(),
+            (true, Some(q)) => format!("No note mentions \"{}\" ({} notes in all)", q, self.entries.len()),
+            (false, _) => shown.join("\n"),
+        }
+    }
+}
@@ -0,0 +1,437 @@
+use std::{collections::BTreeMap, fs, path::Path};
+use anyhow::{Result, Context, bail};
+use super::ToolContext;
+
+const SEARCH: &str = "<<<<<<< SEARCH";
+const DIVIDER: &str = "=======";
+const REPLACE: &str = ">>>>>>> REPLACE";
+
+/// The change a patch makes to one file, worked out against its current content
+pub struct FileChange {
+    pub path: String,
+    /// `None` when the patch creates the file
+    pub old: Option<String>,
+    /// `None` when the patch deletes the file
+    pub new: Option<String>,
+    pub hunks: usize,
+    pub added: usize,
+    pub removed: usize,
+}
+
+/// One `@@` hunk of a unified diff
+struct Hunk {
+    header: String,
+    /// 1-based line the hunk starts at in the old file; 0 for an empty file
+    start: usize,
+    /// `(' ' | '-' | '+', text)`
+    lines: Vec<(char, String)>,
+    /// The new side ends without a trailing newline
+    no_newline: bool,
+}
+
+/// Diff sections per file, in patch order
+struct FileDiff {
+    old_path: Option<String>,
+    new_path: Option<String>,
+    hunks: Vec<Hunk>,
+}
+
+fn is_search_replace(patch: &str) -> bool {
+    patch.lines().any(|l| l.trim_end() == SEARCH)
+}
+
+/// Path from a `---`/`+++` header: timestamps dropped, `/dev/null` as `None`
+fn header_path(rest: &str, prefix: &str) -> Option<String> {
+    let path = rest.split('\t').next().unwrap_or_default().trim();
+    if path == "/dev/null" {
+        return None;
+    }
+    Some(path.strip_prefix(prefix).unwrap_or(path).to_string())
+}
+
+fn parse_diff(patch: &str, path: Option<&str>) -> Result<Vec<FileDiff>> {
+    let lines: Vec<&str> = patch.lines().collect();
+    let mut files: Vec<FileDiff> = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        if let (Some(old), Some(new)) = (line.strip_prefix("--- "), lines.get(i + 1).and_then(|l| l.strip_prefix("+++ "))) {
+            files.push(FileDiff { old_path: header_path(old, "a/"), new_path: header_path(new, "b/"), hunks: Vec::new() });
+            i += 2;
+            continue;
+        }
+        if let Some(rest) = line.strip_prefix("@@ ") {
+            if files.is_empty() {
+                let Some(path) = path else { bail!("The diff has no ---/+++ file headers; pass `path`") };
+                files.push(FileDiff { old_path: Some(path.into()), new_path: Some(path.into()), hunks: Vec::new() });
+            }
+            // `-12,3 +12,4 @@`: models often miscount, so only the start is used
+            let start = rest.split_whitespace().next()
+                .and_then(|old| old.strip_prefix('-')?.split(',').next()?.parse::<usize>().ok())
+                .with_context(|| format!("Malformed hunk header `{}`", line))?;
+            let mut hunk = Hunk { header: line.to_string(), start, lines: Vec::new(), no_newline: false };
+            i += 1;
+            while i < lines.len() {
+                let body = lines[i];
+                if body.starts_with("@@ ") || body.starts_with("diff --git ")
+                    || (body.starts_with("--- ") && lines.get(i + 1).is_some_and(|l| l.starts_with("+++ "))) {
+                    break;
+                }
+                match body.chars().next() {
+                    Some(kind @ (' ' | '-' | '+')) => hunk.lines.push((kind, body[1..].to_string())),
+                    Some('\\') => hunk.no_newline |= hunk.lines.last().is_some_and(|(kind, _)| *kind != '-'),
+                    // Editors and models drop the space of empty context lines
+                    None => hunk.lines.push((' ', String::new())),
+                    Some(_) => bail!("Unexpected line in hunk `{}`: `{}`", hunk.header, body),
+                }
+                i += 1;
+            }
+            while hunk.lines.last().is_some_and(|(kind, text)| *kind == ' ' && text.is_empty()) {
+                hunk.lines.pop();
+            }
+            files.last_mut().unwrap().hunks.push(hunk);
+            continue;
+        }
+        i += 1;
+    }
+    files.retain(|f| !f.hunks.is_empty() || f.new_path.is_none());
+    if files.is_empty() {
+        bail!("No hunks found; send a unified diff with @@ hunks or SEARCH/REPLACE blocks");
+    }
+    Ok(files)
+}
+
+fn strip_eol(line: &str) -> &str {
+    line.trim_end_matches(['\n', '\r'])
+}
+
+/// Where the hunk's old side occurs, trying the stated line first and then ever further away;
+/// exact matches win over ones that differ in trailing whitespace
+fn locate(lines: &[&str], old: &[&str], expected: usize, from: usize) -> Option<usize> {
+    let fits = |at: usize, loose: bool| {
+        at + old.len() <= lines.len() && old.iter().zip(&lines[at..]).all(|(want, have)| {
+            let have = strip_eol(have);
+            if loose { have.trim_end() == want.trim_end() } else { have == *want }
+        })
+    };
+    let expected = expected.max(from);
+    for loose in [false, true] {
+        for distance in 0..=lines.len().max(expected) {
+            let candidates = [expected.checked_add(distance), expected.checked_sub(distance).filter(|_| distance > 0)];
+            for at in candidates.into_iter().flatten().filter(|&at| at >= from) {
+                if fits(at, loose) {
+                    return Some(at);
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Why a hunk does not fit: the first line that differs where it says it applies
+fn mismatch(lines: &[&str], old: &[&str], at: usize) -> String {
+    if at >= lines.len() && !old.is_empty() {
+        return format!("the file has only {} lines", lines.len());
+    }
+    for (n, want) in old.iter().enumerate() {
+        match lines.get(at + n) {
+            Some(have) if strip_eol(have) == *want => continue,
+            Some(have) => return format!("line {} is `{}` but the hunk expects `{}`", at + n + 1, strip_eol(have), want),
+            None => return format!("the file ends before the hunk's line `{}`", want),
+        }
+    }
+    "its context occurs only before an earlier hunk".into()
+}
+
+fn apply_hunks(path: &str, content: &str, hunks: &[Hunk]) -> Result<String> {
+    let lines: Vec<&str> = content.split_inclusive('\n').collect();
+    let eol = if content.contains("\r\n") { "\r\n" } else { "\n" };
+    let mut out: Vec<&str> = Vec::new();
+    let mut cursor = 0;
+    let mut offset: isize = 0;
+    let mut newline_at_end = content.is_empty() || content.ends_with('\n');
+    for (n, hunk) in hunks.iter().enumerate() {
+        let old: Vec<&str> = hunk.lines.iter().filter(|(kind, _)| *kind != '+').map(|(_, text)| text.as_str()).collect();
+        let expected = (hunk.start.saturating_sub(1) as isize + offset).max(0) as usize;
+        let Some(at) = locate(&lines, &old, expected, cursor) else {
+            bail!("Hunk #{} ({}) does not apply to {}: {}. Nothing was changed; read the file and send a corrected patch",
+                n + 1, hunk.header, path, mismatch(&lines, &old, expected.max(cursor)));
+        };
+        out.extend(lines[cursor..at].iter().map(|l| strip_eol(l)));
+        let mut consumed = at;
+        for (kind, text) in &hunk.lines {
+            match kind {
+                '+' => out.push(text),
+                '-' => consumed += 1,
+                // The file's own line, so whitespace-insensitive matches keep their whitespace
+                _ => {
+                    out.push(strip_eol(lines[consumed]));
+                    consumed += 1;
+                }
+            }
+        }
+        if consumed == lines.len() && !hunk.lines.is_empty() {
+            // A hunk ending in context keeps the file's last line as it was
+            let ends_in_context = hunk.lines.last().is_some_and(|(kind, _)| *kind == ' ');
+            newline_at_end = !hunk.no_newline && (newline_at_end || !ends_in_context);
+        }
+        offset = at as isize - hunk.start.saturating_sub(1) as isize;
+        cursor = consumed;
+    }
+    out.extend(lines[cursor..].iter().map(|l| strip_eol(l)));
+    let mut text = out.join(eol);
+    if newline_at_end && !out.is_empty() {
+        text.push_str(eol);
+    }
+    Ok(text)
+}
+
+fn read(dir: &Path, path: &str) -> Result<Option<String>> {
+    match fs::read_to_string(dir.join(path)) {
+        Ok(text) => Ok(Some(text)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err).with_context(|| format!("Failed to read {}", path)),
+    }
+}
+
+/// Current content of `path`, as changed by earlier sections of the same patch
+fn current<'a>(dir: &Path, changes: &'a mut BTreeMap<String, FileChange>, path: &str) -> Result<&'a mut FileChange> {
+    if !changes.contains_key(path) {
+        let old = read(dir, path)?;
+        changes.insert(path.to_string(), FileChange { path: path.to_string(), new: old.clone(), old, hunks: 0, added: 0, removed: 0 });
+    }
+    Ok(changes.get_mut(path).unwrap())
+}
+
+fn prepare_diff(dir: &Path, patch: &str, path: Option<&str>) -> Result<Vec<FileChange>> {
+    let mut changes = BTreeMap::new();
+    let mut order = Vec::new();
+    for file in parse_diff(patch, path)? {
+        let target = file.new_path.clone().or(file.old_path.clone()).context("Both file headers are /dev/null")?;
+        let change = current(dir, &mut changes, &target)?;
+        if !order.contains(&target) {
+            order.push(target.clone());
+        }
+        match (&file.old_path, &file.new_path) {
+            (None, Some(_)) if change.new.is_some() => bail!("The patch creates {}, which already exists", target),
+            (Some(_), _) if change.new.is_none() => bail!("{} does not exist; create it with a /dev/null header or write_file", target),
+            _ => {}
+        }
+        if file.new_path.is_none() {
+            change.new = None;
+            change.removed += change.old.as_deref().map_or(0, |t| t.lines().count());
+            continue;
+        }
+        let content = change.new.as_deref().unwrap_or_default();
+        change.new = Some(apply_hunks(&target, content, &file.hunks)?);
+        change.hunks += file.hunks.len();
+        for hunk in &file.hunks {
+            change.added += hunk.lines.iter().filter(|(k, _)| *k == '+').count();
+            change.removed += hunk.lines.iter().filter(|(k, _)| *k == '-').count();
+        }
+    }
+    Ok(order.into_iter().filter_map(|p| changes.remove(&p)).collect())
+}
+
+/// SEARCH/REPLACE blocks with the file they apply to
+fn parse_blocks<'a>(patch: &'a str, path: Option<&'a str>) -> Result<Vec<(&'a str, String, String)>> {
+    let lines: Vec<&str> = patch.lines().collect();
+    let mut blocks = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        if lines[i].trim_end() != SEARCH {
+            i += 1;
+            continue;
+        }
+        let n = blocks.len() + 1;
+        // The file name goes on the line before the block, above an optional code fence
+        let named = lines[..i].iter().rev().map(|l| l.trim()).find(|l| !l.starts_with("```"))
+            .filter(|l| !l.is_empty() && *l != REPLACE && !l.contains(' '));
+        let Some(file) = path.or(named) else { bail!("SEARCH/REPLACE block #{} names no file; put the path on the line before it or pass `path`", n) };
+        let mut search = Vec::new();
+        let mut replace = Vec::new();
+        let mut in_replace = false;
+        i += 1;
+        loop {
+            let Some(line) = lines.get(i) else { bail!("SEARCH/REPLACE block #{} is not closed with `{}`", n, REPLACE) };
+            i += 1;
+            match line.trim_end() {
+                DIVIDER if !in_replace => in_replace = true,
+                REPLACE if in_replace => break,
+                _ if in_replace => replace.push(*line),
+                _ => search.push(*line),
+            }
+        }
+        let join = |lines: &[&str]| lines.iter().map(|l| format!("{}\n", l)).collect::<String>();
+        blocks.push((file, join(&search), join(&replace)));
+    }
+    Ok(blocks)
+}
+
+fn prepare_blocks(dir: &Path, patch: &str, path: Option<&str>) -> Result<Vec<FileChange>> {
+    let mut changes = BTreeMap::new();
+    let mut order = Vec::new();
+    for (n, (file, search, replace)) in parse_blocks(patch, path)?.into_iter().enumerate() {
+        let change = current(dir, &mut changes, file)?;
+        if !order.iter().any(|p| p == file) {
+            order.push(file.to_string());
+        }
+        let content = match (&change.new, search.is_empty()) {
+            (None, true) => String::new(),
+            (None, false) => bail!("{} does not exist; use an empty SEARCH section to create it", file),
+            (Some(content), _) => content.clone(),
+        };
+        let updated = if search.is_empty() {
+            if !content.is_empty() {
+                bail!("SEARCH/REPLACE block #{} has an empty SEARCH section, but {} is not empty", n + 1, file);
+            }
+            replace.clone()
+        } else {
+            // The last line of a file may lack its newline
+            let (search, replace) = match content.contains(&search) {
+                true => (search.as_str(), replace.as_str()),
+                false => (search.strip_suffix('\n').unwrap_or(&search), replace.strip_suffix('\n').unwrap_or(&replace)),
+            };
+            match content.matches(search).count() {
+                1 => content.replacen(search, replace, 1),
+                0 => bail!("SEARCH/REPLACE block #{} does not match {}; copy the lines exactly from the current file. Nothing was changed", n + 1, file),
+                count => bail!("SEARCH/REPLACE block #{} matches {} places in {}; include more surrounding lines. Nothing was changed", n + 1, count, file),
+            }
+        };
+        change.hunks += 1;
+        change.added += replace.lines().count();
+        change.removed += search.lines().count();
+        change.new = Some(updated);
+    }
+    Ok(order.into_iter().filter_map(|p| changes.remove(&p)).collect())
+}
+
+/// Check the patch against the files in `dir` as they are now and work out every change,
+/// writing nothing
+pub fn prepare(dir: &Path, patch: &str, path: Option<&str>) -> Result<Vec<FileChange>> {
+    if is_search_replace(patch) {
+        prepare_blocks(dir, patch, path)
+    } else {
+        prepare_diff(dir, patch, path)
+    }
+}
+
+/// Files the patch names, for policy checks and session snapshots
+pub fn targets<'a>(patch: &'a str, path: Option<&'a str>) -> Vec<&'a str> {
+    let mut found: Vec<&str> = if is_search_replace(patch) {
+        parse_blocks(patch, path).map(|blocks| blocks.into_iter().map(|(file, _, _)| file).collect()).unwrap_or_default()
+    } else {
+        let lines: Vec<&str> = patch.lines().collect();
+        lines.windows(2)
+            .filter_map(|w| Some((w[0].strip_prefix("--- ")?, w[1].strip_prefix("+++ ")?)))
+            .flat_map(|(old, new)| [(old, "a/"), (new, "b/")])
+            .filter_map(|(rest, prefix)| {
+                let path = rest.split('\t').next()?.trim();
+                (path != "/dev/null").then(|| path.strip_prefix(prefix).unwrap_or(path))
+            })
+            .chain(path)
+            .collect()
+    };
+    found.sort_unstable();
+    found.dedup();
+    found
+}
+
+impl FileChange {
+    /// `path (2 hunks, +3 −1 lines)`
+    pub fn summary(&self) -> String {
+        format!("{} ({} hunk{}, +{} −{} lines)", self.path, self.hunks, if self.hunks == 1 { "" } else { "s" }, self.added, self.removed)
+    }
+}
+
+/// Apply a unified diff or SEARCH/REPLACE blocks. Every hunk is checked before anything is
+/// written, and files are replaced through renames, so a failed patch leaves no file changed.
+pub fn apply(ctx: &ToolContext, patch: &str, path: Option<&str>) -> Result<String> {
+    let dir = ctx.dir();
+    let changes = prepare(&dir, patch, path)?;
+    for change in &changes {
+        if let (true, true, Some(new)) = (ctx.lint_rust, change.path.ends_with(".rs"), &change.new) {
+            if let Some(err) = crate::lint::syntax_error(new) {
+                bail!("Not patching {}: the result does not parse as Rust ({}). Nothing was changed; fix the patch and retry.", change.path, err);
+            }
+        }
+    }
+    let mut staged = Vec::new();
+    for change in &changes {
+        let Some(new) = &change.new else { continue };
+        let target = dir.join(&change.path);
+        if let Some(dir) = target.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let tmp = target.with_file_name(format!(".{}.patch-tmp", target.file_name().and_then(|n| n.to_str()).unwrap_or("file")));
+        // The replacement keeps the original's mode, e.g. a script's executable bit
+        let written = fs::write(&tmp, new).and_then(|_| match fs::metadata(&target) {
+            Ok(meta) => fs::set_permissions(&tmp, meta.permissions()),
+            Err(_) => Ok(()),
+        });
+        if let Err(err) = written {
+            fs::remove_file(&tmp).ok();
+            for (tmp, _) in &staged {
+                fs::remove_file(tmp).ok();
+            }
+            return Err(err).with_context(|| format!("Failed to write {}; nothing was changed", change.path));
+        }
+        staged.push((tmp, target));
+    }
+    for (i, (tmp, target)) in staged.iter().enumerate() {
+        if let Err(err) = fs::rename(tmp, target) {
+            for (tmp, _) in &staged[i..] {
+                fs::remove_file(tmp).ok();
+            }
+            return Err(err).with_context(|| format!("Failed to replace {}", target.display()));
+        }
+    }
+    for change in changes.iter().filter(|c| c.new.is_none()) {
+        fs::remove_file(dir.join(&change.path)).with_context(|| format!("Failed to delete {}", change.path))?;
+    }
+    Ok(changes.iter().map(|change| match (&change.old, &change.new) {
+        (None, _) => format!("Created {}", change.summary()),
+        (_, None) => format!("Deleted {}", change.path),
+        _ => format!("Patched {}", change.summary()),
+    }).collect::<Vec<_>>().join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::tools::ToolContext;
+    use super::apply;
+
+    fn context(dir: &std::path::Path) -> ToolContext {
+        let mut ctx = ToolContext::default();
+        ctx.set_workdir(dir.to_path_buf());
+        ctx
+    }
+
+    #[test]
+    fn a_hunk_that_does_not_apply_changes_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "one\ntwo\n").unwrap();
+        fs::write(dir.path().join("b.txt"), "three\n").unwrap();
+        let patch = "--- a/a.txt\n+++ b/a.txt\n@@ -1,2 +1,2 @@\n one\n-two\n+2\n--- a/b.txt\n+++ b/b.txt\n@@ -1 +1 @@\n-four\n+4\n";
+        assert!(apply(&context(dir.path()), patch, None).is_err());
+        assert_eq!(fs::read_to_string(dir.path().join("a.txt")).unwrap(), "one\ntwo\n");
+        assert_eq!(fs::read_to_string(dir.path().join("b.txt")).unwrap(), "three\n");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn patched_files_keep_their_mode() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("run.sh");
+        fs::write(&script, "#!/bin/sh\necho old\n").unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o750)).unwrap();
+        let patch = "--- a/run.sh\n+++ b/run.sh\n@@ -1,2 +1,2 @@\n #!/bin/sh\n-echo old\n+echo new\n";
+        apply(&context(dir.path()), patch, None).unwrap();
+        assert_eq!(fs::read_to_string(&script).unwrap(), "#!/bin/sh\necho new\n");
+        assert_eq!(fs::metadata(&script).unwrap().permissions().mode() & 0o777, 0o750);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,81 @@
+use anyhow::{Result, anyhow, bail};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use super::RandomKind;
+
+const LOWER: &str = "abcdefghijklmnopqrstuvwxyz";
+const UPPER: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const DIGITS: &str = "0123456789";
+const SYMBOLS: &str = "!#$%&()*+,-./:;<=>?@[]^_{|}~";
+/// Characters easily confused when read or retyped
+const AMBIGUOUS: &str = "Il1O0o";
+const MAX_LENGTH: u32 = 4096;
+const MAX_COUNT: u32 = 100;
+
+fn random_bytes(n: usize) -> Result<Vec<u8>> {
+    let mut buf = vec![0; n];
+    getrandom::fill(&mut buf).map_err(|err| anyhow!("OS random source failed: {}", err))?;
+    Ok(buf)
+}
+
+/// Uniform index below `n`, rejecting draws that would bias the modulo
+fn below(n: usize) -> Result<usize> {
+    let n = n as u32;
+    let zone = u32::MAX - u32::MAX % n;
+    loop {
+        let draw = getrandom::u32().map_err(|err| anyhow!("OS random source failed: {}", err))?;
+        if draw < zone {
+            return Ok((draw % n) as usize);
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Version 4 UUID
+fn uuid() -> Result<String> {
+    let mut b = random_bytes(16)?;
+    b[6] = (b[6] & 0x0f) | 0x40;
+    b[8] = (b[8] & 0x3f) | 0x80;
+    let h = hex(&b);
+    Ok(format!("{}-{}-{}-{}-{}", &h[..8], &h[8..12], &h[12..16], &h[16..20], &h[20..]))
+}
+
+/// At least one character from every enabled class, the rest from all of them, shuffled
+fn password(length: usize, symbols: bool, avoid_ambiguous: bool) -> Result<String> {
+    let classes: Vec<Vec<char>> = [LOWER, UPPER, DIGITS, if symbols { SYMBOLS } else { "" }].iter()
+        .filter(|class| !class.is_empty())
+        .map(|class| class.chars().filter(|c| !avoid_ambiguous || !AMBIGUOUS.contains(*c)).collect())
+        .collect();
+    if length < classes.len() {
+        bail!("A password with every character class needs at least {} characters", classes.len());
+    }
+    let all: Vec<char> = classes.concat();
+    let mut chars = classes.iter().map(|class| Ok(class[below(class.len())?])).collect::<Result<Vec<_>>>()?;
+    while chars.len() < length {
+        chars.push(all[below(all.len())?]);
+    }
+    for i in (1..chars.len()).rev() {
+        chars.swap(i, below(i + 1)?);
+    }
+    Ok(chars.into_iter().collect())
+}
+
+/// Values from the OS random source; `length` counts bytes for `hex`/`base64` and characters for passwords
+pub fn generate(kind: RandomKind, length: Option<u32>, count: Option<u32>, symbols: Option<bool>, avoid_ambiguous: Option<bool>) -> Result<String> {
+    let count = count.unwrap_or(1);
+    if !(1..=MAX_COUNT).contains(&count) {
+        bail!("`count` must be between 1 and {}", MAX_COUNT);
+    }
+    if length.is_some_and(|l| !(1..=MAX_LENGTH).contains(&l)) {
+        bail!("`length` must be between 1 and {}", MAX_LENGTH);
+    }
+    let values = (0..count).map(|_| match kind {
+        RandomKind::Uuid => uuid(),
+        RandomKind::Hex => Ok(hex(&random_bytes(length.unwrap_or(16) as usize)?)),
+        RandomKind::Base64 => Ok(URL_SAFE_NO_PAD.encode(random_bytes(length.unwrap_or(32) as usize)?)),
+        RandomKind::Password => password(length.unwrap_or(24) as usize, symbols.unwrap_or(true), avoid_ambiguous.unwrap_or(false)),
+    }).collect::<Result<Vec<_>>>()?;
+    Ok(values.join("\n"))
+}
@@ -0,0 +1,40 @@
+use anyhow::{Result, bail};
+use regex::Regex;
+
+/// Matches listed per input; the count is still reported in full
+const MAX_MATCHES: usize = 20;
+
+/// Compile `pattern` with the `regex` crate and list every match and capture group in each input
+pub fn test_regex(pattern: &str, inputs: &[String]) -> Result<String> {
+    let re = match Regex::new(pattern) {
+        Ok(re) => re,
+        Err(err) => bail!("Pattern does not compile with the Rust regex crate:\n{}", err),
+    };
+    let groups: Vec<String> = re.capture_names().enumerate().skip(1)
+        .map(|(i, name)| name.map_or_else(|| i.to_string(), str::to_string))
+        .collect();
+    let mut out = format!("`{}` compiles; {} capture group(s){}\n", pattern, groups.len(),
+        if groups.is_empty() { String::new() } else { format!(": {}", groups.join(", ")) });
+    for (n, input) in inputs.iter().enumerate() {
+        let matches: Vec<regex::Captures> = re.captures_iter(input).collect();
+        if matches.is_empty() {
+            out.push_str(&format!("\ninput {} {:?}: no match\n", n + 1, input));
+            continue;
+        }
+        out.push_str(&format!("\ninput {} {:?}: {} match(es)\n", n + 1, input, matches.len()));
+        for caps in matches.iter().take(MAX_MATCHES) {
+            let whole = caps.get(0).unwrap();
+            out.push_str(&format!("  {}..{} {:?}\n", whole.start(), whole.end(), whole.as_str()));
+            for (name, group) in groups.iter().zip(caps.iter().skip(1)) {
+                match group {
+                    Some(m) => out.push_str(&format!("    {} = {:?}\n", name, m.as_str())),
+                    None => out.push_str(&format!("    {} = (no match)\n", name)),
+                }
+            }
+        }
+        if matches.len() > MAX_MATCHES {
+            out.push_str(&format!("  … {} more\n", matches.len() - MAX_MATCHES));
+        }
+    }
+    Ok(out)
+}
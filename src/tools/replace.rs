@@ -0,0 +1,60 @@
+use std::{fs, path::Path};
+use anyhow::{Result, Context};
+use regex::Regex;
+use super::{diff, ToolContext};
+
+/// Regex find-and-replace across files matching `scope` in `dir`, applied only once the user
+/// approves the preview
+pub fn replace_in_files(ctx: &mut ToolContext, dir: &Path, pattern: &str, replacement: &str, scope: &str) -> Result<String> {
+    let re = Regex::new(pattern).with_context(|| format!("Invalid regex `{}`", pattern))?;
+    let mut changes = Vec::new();
+    let mut total = 0;
+    let mut skipped = 0;
+
+    for path in super::glob_in(dir, scope)? {
+        if !dir.join(&path).is_file() {
+            continue;
+        }
+        // Build output, bundles and lockfiles are not edited by pattern
+        if ctx.bulk.skip(dir, &path).is_some() {
+            skipped += 1;
+            continue;
+        }
+        // Skip binary and non-UTF-8 files rather than failing the whole run
+        let Ok(old) = fs::read_to_string(dir.join(&path)) else { continue };
+        let count = re.find_iter(&old).count();
+        if count == 0 {
+            continue;
+        }
+        let new = re.replace_all(&old, replacement).into_owned();
+        total += count;
+        changes.push((path, old, new, count));
+    }
+
+    let skipped = match skipped {
+        0 => String::new(),
+        n => format!(" ({} binary, minified or generated files skipped)", n),
+    };
+    if changes.is_empty() {
+        return Ok(format!("No matches for `{}` in `{}`{}", pattern, scope, skipped));
+    }
+
+    let mut preview = format!("{} replacements in {} files{}\n", total, changes.len(), skipped);
+    for (path, old, new, _) in &changes {
+        let name = path.display().to_string();
+        preview.push_str(&diff::unified(old, new, &name, &name));
+    }
+
+    if ctx.a11y {
+        println!("{}", diff::linearize(&preview));
+    } else {
+        println!("{}", ctx.theme.diff(&preview));
+    }
+    if !ctx.confirm(&format!("Apply {} replacements in {} files?", total, changes.len()))? {
+        return Ok(format!("User declined; no files were changed.\n{}", preview));
+    }
+    for (path, _, new, _) in &changes {
+        fs::write(dir.join(path), new)?;
+    }
+    Ok(format!("Applied. {}", preview))
+}
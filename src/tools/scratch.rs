@@ -0,0 +1,82 @@
+use std::{fs, path::{Path, PathBuf}, sync::Mutex};
+use anyhow::{Result, Context};
+use tempfile::TempDir;
+
+/// Every live scratch directory of the process, for exit paths that skip `Drop`
+static LIVE: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Temporary directories handed out to the agent, removed when the session ends
+#[derive(Default)]
+pub struct ScratchDirs {
+    dirs: Vec<TempDir>,
+}
+
+impl ScratchDirs {
+    /// Allocate a fresh, empty directory under the system temp dir, private to this user
+    pub fn create(&mut self, label: Option<&str>) -> Result<PathBuf> {
+        let label: String = label.unwrap_or("scratch").chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+            .collect();
+        let dir = tempfile::Builder::new().prefix(&format!("rustaigent-{}-", label)).tempdir()
+            .context("Failed to create a scratch directory")?;
+        let path = dir.path().to_path_buf();
+        if let Ok(mut live) = LIVE.lock() {
+            live.push(path.clone());
+        }
+        self.dirs.push(dir);
+        Ok(path)
+    }
+}
+
+impl Drop for ScratchDirs {
+    fn drop(&mut self) {
+        // The `TempDir`s remove themselves
+        if let Ok(mut live) = LIVE.lock() {
+            live.retain(|path| !self.dirs.iter().any(|dir| dir.path() == path));
+        }
+    }
+}
+
+/// Remove the live scratch directories `matching` selects
+fn remove(matching: impl Fn(&Path) -> bool) {
+    if let Ok(mut live) = LIVE.lock() {
+        live.retain(|dir| {
+            if !matching(dir) {
+                return true;
+            }
+            fs::remove_dir_all(dir).ok();
+            false
+        });
+    }
+}
+
+/// Remove every scratch directory still alive; call before `std::process::exit`
+pub fn cleanup() {
+    remove(|_| true);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{remove, ScratchDirs, LIVE};
+
+    #[test]
+    fn live_directories_can_be_removed_without_their_owner() {
+        let mut scratch = ScratchDirs::default();
+        let dir = scratch.create(Some("leak")).unwrap();
+        assert!(dir.is_dir());
+        remove(|live| live == dir);
+        assert!(!dir.exists());
+        assert!(!LIVE.lock().unwrap().contains(&dir));
+        std::mem::forget(scratch);
+    }
+
+    #[test]
+    fn dropping_the_owner_removes_and_forgets_its_directories() {
+        let mut scratch = ScratchDirs::default();
+        let dir = scratch.create(Some("a/b")).unwrap();
+        assert!(dir.file_name().unwrap().to_string_lossy().starts_with("rustaigent-a_b-"));
+        drop(scratch);
+        assert!(!dir.exists());
+        assert!(!LIVE.lock().unwrap().contains(&dir));
+    }
+}
@@ -0,0 +1,123 @@
+//! `run_command`: a shell command with a wall-clock limit, its output streamed to the terminal
+//! as it arrives and cut to its head and tail before it goes back to the model.
+
+use std::{collections::VecDeque, io::{Read, Write}, path::Path, process::{Child, Command, ExitStatus, Stdio}, sync::{Arc, Mutex}, thread, time::{Duration, Instant}};
+use anyhow::{Result, Context, bail};
+use tokio_util::sync::CancellationToken;
+
+/// Wall-clock limit when neither the call nor `command_timeout_secs` sets one
+pub const DEFAULT_TIMEOUT_SECS: u64 = 300;
+
+/// Longest limit a call may ask for
+const MAX_TIMEOUT_SECS: u64 = 3600;
+
+/// Bytes of output returned when `command_output_limit` is unset, split between head and tail
+pub const DEFAULT_OUTPUT_LIMIT: usize = 16 * 1024;
+
+/// How long output is still collected once the shell has ended
+const OUTPUT_GRACE: Duration = Duration::from_secs(1);
+
+/// Output kept for the model: the first and last half of the limit, and how much arrived
+struct Kept {
+    head: Vec<u8>,
+    tail: VecDeque<u8>,
+    total: usize,
+    half: usize,
+}
+
+impl Kept {
+    fn push(&mut self, bytes: &[u8]) {
+        self.total += bytes.len();
+        let room = self.half.saturating_sub(self.head.len());
+        let (head, rest) = bytes.split_at(room.min(bytes.len()));
+        self.head.extend_from_slice(head);
+        self.tail.extend(rest);
+        let excess = self.tail.len().saturating_sub(self.half);
+        self.tail.drain(..excess);
+    }
+
+    fn render(&self) -> String {
+        let head = String::from_utf8_lossy(&self.head);
+        let tail = String::from_utf8_lossy(self.tail.as_slices().0).into_owned() + &String::from_utf8_lossy(self.tail.as_slices().1);
+        let omitted = self.total - self.head.len() - self.tail.len();
+        match omitted {
+            0 => format!("{}{}", head, tail),
+            n => format!("{}\n… {} bytes omitted …\n{}", head, n, tail),
+        }
+    }
+}
+
+/// Copy a pipe into `kept`, echoing it to stderr when `echo` is set
+fn forward(mut pipe: impl Read + Send + 'static, kept: Arc<Mutex<Kept>>, echo: bool) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut buf = [0u8; 8192];
+        while let Ok(n) = pipe.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+            kept.lock().unwrap().push(&buf[..n]);
+            if echo {
+                let mut err = std::io::stderr().lock();
+                err.write_all(&buf[..n]).ok();
+                err.flush().ok();
+            }
+        }
+    })
+}
+
+/// Wait for `child` to exit, killing its process group once `deadline` passes (`None`) or the
+/// turn is cancelled (an error)
+pub(super) fn wait(child: &mut Child, deadline: Instant, cancel: &CancellationToken) -> Result<Option<ExitStatus>> {
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            super::eval::kill(child);
+            child.wait().ok();
+            return Ok(None);
+        }
+        if cancel.is_cancelled() {
+            super::eval::kill(child);
+            child.wait().ok();
+            bail!("Killed: the turn was cancelled");
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+/// Run `command` with `sh -c` in `dir`, in its own process group, without stdin so prompts fail
+/// instead of waiting; the group is killed when `timeout_secs` pass or the turn is cancelled
+pub fn run(dir: &Path, command: &str, timeout_secs: u64, output_limit: usize, echo: bool, cancel: &CancellationToken) -> Result<String> {
+    let limit = Duration::from_secs(timeout_secs.clamp(1, MAX_TIMEOUT_SECS));
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command).current_dir(dir);
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut shell, 0);
+    let started = Instant::now();
+    let mut child = shell.stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().context("Failed to start sh")?;
+    let kept = Arc::new(Mutex::new(Kept { head: Vec::new(), tail: VecDeque::new(), total: 0, half: output_limit.max(2) / 2 }));
+    let readers = [
+        forward(child.stdout.take().context("no stdout pipe")?, kept.clone(), echo),
+        forward(child.stderr.take().context("no stderr pipe")?, kept.clone(), echo),
+    ];
+    let status = wait(&mut child, started + limit, cancel)?;
+    // A background process may hold the pipes open after the shell is gone; take what arrived
+    let grace = Instant::now() + OUTPUT_GRACE;
+    while !readers.iter().all(|r| r.is_finished()) && Instant::now() < grace {
+        thread::sleep(Duration::from_millis(20));
+    }
+    let elapsed = humantime::format_duration(Duration::from_millis(started.elapsed().as_millis() as u64));
+    let status = match status {
+        None => format!("Killed after the {} limit; interactive commands get no input, so pass flags such as --yes instead", humantime::format_duration(limit)),
+        Some(status) => match status.code() {
+            Some(code) => format!("Exit code {} after {}", code, elapsed),
+            None => format!("Terminated by a signal after {}", elapsed),
+        },
+    };
+    let output = kept.lock().unwrap().render();
+    Ok(match output.trim_end() {
+        "" => format!("{}; no output", status),
+        output => format!("{}\n{}", status, output),
+    })
+}
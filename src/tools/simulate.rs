@@ -0,0 +1,266 @@
+use std::{fmt, fs, path::Path};
+use super::{RandomKind, ToolCall};
+
+/// What a call would do, worked out without doing it: shown in approval prompts and returned
+/// instead of the result in dry runs
+pub struct Simulation {
+    /// One sentence describing the effect
+    pub summary: String,
+    /// Files and directories read or changed
+    pub files: Vec<String>,
+    /// Commands that would run, as composed for the shell
+    pub commands: Vec<String>,
+    /// Network hosts that would be contacted
+    pub hosts: Vec<String>,
+    /// Files changed that CODEOWNERS assigns, with their owners
+    pub owners: Vec<String>,
+    /// Nothing outside the agent changes, so dry runs still execute the call
+    pub read_only: bool,
+}
+
+impl Simulation {
+    fn new(summary: String) -> Self {
+        Simulation { summary, files: Vec::new(), commands: Vec::new(), hosts: Vec::new(), owners: Vec::new(), read_only: false }
+    }
+
+    fn files<'a>(mut self, files: impl IntoIterator<Item = &'a str>) -> Self {
+        self.files.extend(files.into_iter().map(String::from));
+        self
+    }
+
+    fn command(mut self, command: impl Into<String>) -> Self {
+        self.commands.push(command.into());
+        self
+    }
+
+    fn host(mut self, url: &str) -> Self {
+        self.hosts.push(reqwest::Url::parse(url).ok().and_then(|u| u.host_str().map(String::from)).unwrap_or_else(|| url.into()));
+        self
+    }
+
+    fn read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+}
+
+/// The summary, then one indented line per non-empty list
+impl fmt::Display for Simulation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.summary)?;
+        for (label, items) in [("files", &self.files), ("runs", &self.commands), ("contacts", &self.hosts), ("owned by", &self.owners)] {
+            if !items.is_empty() {
+                write!(f, "\n  {}: {}", label, items.join(", "))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn size(path: &Path) -> Option<u64> {
+    fs::metadata(path).ok().filter(|m| m.is_file()).map(|m| m.len())
+}
+
+/// `(added, removed)` lines between the current file and `content`
+fn line_changes(old: &str, new: &str) -> (usize, usize) {
+    similar::TextDiff::from_lines(old, new).iter_all_changes().fold((0, 0), |(a, r), change| match change.tag() {
+        similar::ChangeTag::Insert => (a + 1, r),
+        similar::ChangeTag::Delete => (a, r + 1),
+        similar::ChangeTag::Equal => (a, r),
+    })
+}
+
+fn bytes(n: u64) -> String {
+    format!("{} byte{}", n, if n == 1 { "" } else { "s" })
+}
+
+fn plural(n: usize, word: &str) -> String {
+    format!("{} {}{}", n, word, if n == 1 { "" } else { "s" })
+}
+
+impl ToolCall {
+    /// Describe the effect of the call from the current state of the filesystem, with relative
+    /// paths taken from `dir`; `package` is the workspace member commands are scoped to
+    pub fn simulate(&self, dir: &Path, package: Option<&str>) -> Simulation {
+        let size = |path: &str| size(&dir.join(path));
+        let scoped = |command: &str| match package {
+            Some(package) => crate::workspace::scope_command(command, package),
+            None => command.to_string(),
+        };
+        match self {
+            ToolCall::ReadFile { path } => match size(path) {
+                Some(size) => Simulation::new(format!("Reads {} ({})", path, bytes(size))),
+                None => Simulation::new(format!("Reads {}, which does not exist; the call will fail", path)),
+            }.files([path.as_str()]).read_only(),
+            ToolCall::WriteFile { path, content } => {
+                let summary = match fs::read_to_string(dir.join(path)) {
+                    Ok(old) if old == *content => format!("Rewrites {} with identical content", path),
+                    Ok(old) => {
+                        let (added, removed) = line_changes(&old, content);
+                        format!("Overwrites {} ({} → {}, +{} −{} lines)", path, old.len(), bytes(content.len() as u64), added, removed)
+                    }
+                    Err(_) if size(path).is_some() => format!("Overwrites {} with {} of text", path, bytes(content.len() as u64)),
+                    Err(_) => match Path::new(path).parent().filter(|p| !p.as_os_str().is_empty() && !dir.join(p).is_dir()) {
+                        Some(dir) => format!("Creates {} ({}), but {} does not exist; the call will fail", path, bytes(content.len() as u64), dir.display()),
+                        None => format!("Creates {} ({})", path, bytes(content.len() as u64)),
+                    },
+                };
+                Simulation::new(summary).files([path.as_str()])
+            }
+            ToolCall::ApplyPatch { patch, path } => match super::patch::prepare(dir, patch, path.as_deref()) {
+                Ok(changes) => Simulation::new(changes.iter().map(|change| match (&change.old, &change.new) {
+                    (None, _) => format!("Creates {}", change.summary()),
+                    (_, None) => format!("Deletes {}", change.path),
+                    _ => format!("Patches {}", change.summary()),
+                }).collect::<Vec<_>>().join("; ")),
+                Err(err) => Simulation::new(format!("Applies a patch that does not apply; the call will fail: {:#}", err)),
+            }.files(self.paths()),
+            ToolCall::ReplaceInFiles { pattern, replacement, scope } => {
+                let matched: Vec<String> = super::glob_in(dir, scope).unwrap_or_default().into_iter()
+                    .filter(|p| dir.join(p).is_file())
+                    .map(|p| p.display().to_string())
+                    .collect();
+                Simulation::new(format!("Replaces `{}` with `{}` in {} matching {} (a preview is shown before anything changes)",
+                    pattern, replacement, plural(matched.len(), "file"), scope))
+                    .files(matched.iter().map(String::as_str))
+            }
+            ToolCall::EditStructured { path, pointer, op, value } => match op {
+                super::EditOp::Set => Simulation::new(format!("Sets {} in {} to {}", pointer, path, value.as_ref().map_or("null".into(), |v| v.to_string()))),
+                super::EditOp::Remove => Simulation::new(format!("Removes {} from {}", pointer, path)),
+            }.files([path.as_str()]),
+            ToolCall::DeleteFile { path } => match size(path) {
+                Some(size) => Simulation::new(format!("Deletes {} ({})", path, bytes(size))),
+                None => Simulation::new(format!("Deletes {}, which does not exist; the call will fail", path)),
+            }.files([path.as_str()]),
+            ToolCall::Diff { left, right, content } => {
+                let other = right.as_deref().or(content.as_ref().map(|_| "the given content")).unwrap_or("nothing");
+                Simulation::new(format!("Compares {} with {}", left, other)).files(self.paths()).read_only()
+            }
+            ToolCall::ListDir { path } => Simulation::new(format!("Lists the entries of {}", path)).files([path.as_str()]).read_only(),
+            ToolCall::RunCommand { command, timeout_secs } => {
+                let limit = timeout_secs.map(|s| format!(", killed after {}s", s)).unwrap_or_default();
+                Simulation::new(format!("Runs a shell command in {}{}", dir.display(), limit)).command(scoped(command))
+            }
+            ToolCall::RunTests { project } => {
+                let projects = crate::project::detect(dir);
+                let selected: Vec<_> = projects.iter()
+                    .filter(|p| project.as_deref().is_none_or(|o| p.language.to_lowercase().contains(&o.to_lowercase())))
+                    .collect();
+                let mut sim = Simulation::new(match selected.len() {
+                    0 => "Runs no tests: no matching project manifest was found".into(),
+                    n => format!("Runs the test suite of {}", plural(n, "detected project")),
+                });
+                for p in selected {
+                    sim = sim.command(scoped(&p.test));
+                }
+                sim
+            }
+            ToolCall::GitStatus {} => Simulation::new("Shows the git branch and changed files".into()).command("git status --short --branch").read_only(),
+            ToolCall::GitDiff { staged, base, path } => {
+                let what = match (staged.unwrap_or(false), base) {
+                    (true, _) => "staged changes".to_string(),
+                    (false, Some(base)) => format!("changes since {}", base),
+                    (false, None) => "unstaged changes".to_string(),
+                };
+                Simulation::new(format!("Shows the {}{}", what, path.as_ref().map(|p| format!(" in {}", p)).unwrap_or_default()))
+                    .files(self.paths()).read_only()
+            }
+            ToolCall::GitCommit { message, paths, all } => {
+                let staged = match (all.unwrap_or(false), paths.as_deref().unwrap_or_default()) {
+                    (true, _) => "every change, untracked files included".to_string(),
+                    (false, []) => "what is staged".to_string(),
+                    (false, paths) => format!("{} and what is already staged", plural(paths.len(), "path")),
+                };
+                let subject = message.lines().next().unwrap_or_default();
+                Simulation::new(format!("Commits \"{}\" with {}", subject, staged)).files(self.paths()).command("git commit")
+            }
+            ToolCall::GitCreateBranch { name, start, checkout } => {
+                let from = start.as_deref().unwrap_or("HEAD");
+                match checkout.unwrap_or(true) {
+                    true => Simulation::new(format!("Creates branch {} from {} and switches to it", name, from)).command(format!("git switch --create {}", name)),
+                    false => Simulation::new(format!("Creates branch {} from {}", name, from)).command(format!("git branch {}", name)),
+                }
+            }
+            ToolCall::TerraformPlan { dir: module, plan_file } => {
+                let module = module.as_deref().unwrap_or(".");
+                match plan_file {
+                    Some(file) => Simulation::new(format!("Summarizes the saved plan {} in {}", file, module)).command(format!("terraform show -json {}", file)),
+                    None => Simulation::new(format!("Plans the Terraform module in {}; providers may query remote state", module))
+                        .command("terraform plan -json -input=false -lock=false"),
+                }.files([module])
+            }
+            ToolCall::FetchUrl { url } => Simulation::new(format!("Sends a GET request to {}", url)).host(url),
+            ToolCall::DownloadFile { url, path, sha256 } => {
+                let target = match size(path) {
+                    Some(size) => format!("{}, replacing its {}", path, bytes(size)),
+                    None => path.clone(),
+                };
+                let check = if sha256.is_some() { " and checks its SHA-256" } else { "" };
+                Simulation::new(format!("Downloads {} to {}{}", url, target, check)).files([path.as_str()]).host(url)
+            }
+            ToolCall::EvalCode { code, dependencies, timeout_secs } => {
+                let deps = match super::eval::dependencies(code, dependencies.as_deref().unwrap_or_default()) {
+                    Ok(deps) if deps.is_empty() => String::new(),
+                    Ok(deps) => format!(" with {} ({})", plural(deps.len(), "dependency"), deps.iter().filter_map(|d| d.split(' ').next()).collect::<Vec<_>>().join(", ")),
+                    Err(err) => format!(" but fails: {:#}", err),
+                };
+                let limit = timeout_secs.map_or(super::eval::DEFAULT_TIMEOUT_SECS, u64::from);
+                Simulation::new(format!("Builds {} of Rust as a temporary Cargo project{} and runs it for up to {}s", plural(code.lines().count(), "line"), deps, limit))
+                    .command("cargo build")
+                    .command("./<program>")
+            }
+            ToolCall::ExtractArchive { archive, destination } => {
+                Simulation::new(format!("Extracts {} into {}, overwriting files of the same name", archive, destination)).files(self.paths())
+            }
+            ToolCall::CreateArchive { archive, paths } => {
+                let verb = if size(archive).is_some() { "Replaces" } else { "Creates" };
+                Simulation::new(format!("{} {} from {}", verb, archive, plural(paths.len(), "path"))).files(self.paths())
+            }
+            ToolCall::Calculate { expression } => Simulation::new(format!("Evaluates `{}`", expression)).read_only(),
+            ToolCall::GenerateRandom { kind, count, .. } => {
+                let kind = match kind { RandomKind::Uuid => "UUID", RandomKind::Hex => "hex token", RandomKind::Base64 => "base64 token", RandomKind::Password => "password" };
+                Simulation::new(format!("Generates {} from the OS random source", plural(count.unwrap_or(1) as usize, kind))).read_only()
+            }
+            ToolCall::TestRegex { pattern, inputs } => Simulation::new(format!("Matches `{}` against {}", pattern, plural(inputs.len(), "input"))).read_only(),
+            ToolCall::SearchCodebase { query, .. } => Simulation::new(format!("Searches the codebase index for \"{}\"", query)).read_only(),
+            ToolCall::ReadArtifact { id, .. } => Simulation::new(format!("Reads part of stored tool output {}", id)).read_only(),
+            ToolCall::WriteNote { replaces: Some(n), .. } => Simulation::new(format!("Replaces note {} in the session scratchpad", n)).read_only(),
+            ToolCall::WriteNote { .. } => Simulation::new("Adds a note to the session scratchpad".into()).read_only(),
+            ToolCall::ReadNotes { .. } => Simulation::new("Reads the session scratchpad".into()).read_only(),
+            ToolCall::GetEnvironment {} => Simulation::new("Reports the time, OS, Rust toolchain versions and working directory".into())
+                .command("rustc --version")
+                .command("cargo --version")
+                .command("rustup show active-toolchain")
+                .read_only(),
+            ToolCall::AskUser { question } => Simulation::new(format!("Asks you: {}", question)).read_only(),
+            ToolCall::MakeScratchDir { .. } => Simulation::new(format!("Creates an empty directory under {} that is removed when the session ends", std::env::temp_dir().display())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use crate::mock::MockProvider;
+
+    #[tokio::test]
+    async fn dry_runs_describe_changes_instead_of_making_them() {
+        let dir = std::env::temp_dir().join(format!("rustaigent-dry-run-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("keep.txt");
+        std::fs::write(&file, "kept").unwrap();
+        let mock = MockProvider::start().await;
+        mock.tool_call("delete_file", json!({"path": file.to_string_lossy()})).await;
+        mock.reply("done").await;
+        let mut agent = mock.tool_agent();
+        agent.tool_ctx.policy.allow_path(&dir);
+        agent.enable_dry_run();
+        agent.request_tools().await.unwrap();
+        assert!(file.exists());
+        let messages = mock.requests().await[1]["messages"].as_array().unwrap().clone();
+        let result = messages[messages.len() - 1]["content"].as_str().unwrap().to_string();
+        assert!(result.starts_with("Dry run"), "{}", result);
+        assert!(result.contains("Deletes") && result.contains("(4 bytes)"), "{}", result);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,486 @@
+use std::{fs, path::Path};
+use anyhow::{Result, Context, bail};
+use serde_json::Value;
+use toml_edit::{Array, DocumentMut, Item, TableLike};
+use super::EditOp;
+
+enum Edit {
+    Set(Value),
+    Remove,
+}
+
+/// Split a JSON pointer (`/a/b/0`) into unescaped tokens
+fn pointer_tokens(pointer: &str) -> Result<Vec<String>> {
+    let Some(rest) = pointer.strip_prefix('/') else {
+        bail!("JSON pointer `{}` must start with `/`", pointer)
+    };
+    Ok(rest.split('/').map(|t| t.replace("~1", "/").replace("~0", "~")).collect())
+}
+
+/// Apply a `set` or `remove` at a JSON pointer inside a JSON, YAML or TOML file
+pub fn edit_structured(dir: &Path, path: &str, pointer: &str, op: EditOp, value: Option<&Value>) -> Result<String> {
+    let edit = match op {
+        EditOp::Set => Edit::Set(value.cloned().context("`value` is required for op `set`")?),
+        EditOp::Remove => Edit::Remove,
+    };
+    let tokens = pointer_tokens(pointer)?;
+    let text = fs::read_to_string(dir.join(path)).with_context(|| format!("Failed to read {}", path))?;
+    let lower = path.to_lowercase();
+
+    let updated = if lower.ends_with(".toml") {
+        // toml_edit keeps comments, ordering and whitespace of untouched entries
+        let mut doc: DocumentMut = text.parse().with_context(|| format!("Failed to parse {} as TOML", path))?;
+        edit_table(doc.as_table_mut(), &tokens, &edit)?;
+        doc.to_string()
+    } else if lower.ends_with(".yaml") || lower.ends_with(".yml") {
+        // serde_yaml writes the document back in its own style and drops comments, which are
+        // often the point of a config file, so commented files are left to `apply_patch`
+        if let Some(line) = yaml_comment(&text) {
+            bail!("{} has comments (line {}), which this edit would drop; change it with `apply_patch` instead", path, line);
+        }
+        let mut root: Value = serde_yaml::from_str(&text).with_context(|| format!("Failed to parse {} as YAML", path))?;
+        edit_json(&mut root, &tokens, &edit)?;
+        serde_yaml::to_string(&root)?
+    } else if lower.ends_with(".json") {
+        let mut root: Value = serde_json::from_str(&text).with_context(|| format!("Failed to parse {} as JSON", path))?;
+        edit_json(&mut root, &tokens, &edit)?;
+        // The change is spliced into the text so the rest keeps its layout; a splice that does
+        // not read back as the edited document falls back to writing it out whole
+        match splice(&text, &tokens, &edit).filter(|spliced| serde_json::from_str::<Value>(spliced).is_ok_and(|v| v == root)) {
+            Some(spliced) => spliced,
+            None => {
+                let mut out = write_json_like(&root, &text)?;
+                if text.ends_with('\n') {
+                    out.push('\n');
+                }
+                out
+            }
+        }
+    } else {
+        bail!("Unsupported file type for `{}` (expected .json, .yaml, .yml or .toml)", path)
+    };
+
+    let preview = super::diff::unified(&text, &updated, path, path);
+    fs::write(dir.join(path), &updated)?;
+    Ok(if preview.is_empty() { format!("{} unchanged", path) } else { preview })
+}
+
+/// Indent of the original's second line, the first nested one, or two spaces
+fn indent_unit(original: &str) -> String {
+    let indent: String = original.lines().nth(1).unwrap_or("  ").chars().take_while(|c| c.is_whitespace()).collect();
+    if indent.is_empty() { "  ".to_string() } else { indent }
+}
+
+/// Serialize JSON matching the original's layout: compact stays compact, otherwise reuse its indent
+fn write_json_like(root: &Value, original: &str) -> Result<String> {
+    if !original.trim().contains('\n') {
+        return Ok(serde_json::to_string(root)?);
+    }
+    let indent = indent_unit(original);
+    let mut buf = Vec::new();
+    let mut ser = serde_json::Serializer::with_formatter(&mut buf, serde_json::ser::PrettyFormatter::with_indent(indent.as_bytes()));
+    serde::Serialize::serialize(root, &mut ser)?;
+    Ok(String::from_utf8(buf)?)
+}
+
+fn edit_json(root: &mut Value, tokens: &[String], edit: &Edit) -> Result<()> {
+    let (last, parents) = tokens.split_last().context("Empty JSON pointer")?;
+    let mut node = root;
+    for token in parents {
+        node = match node {
+            Value::Object(map) => map.entry(token.clone()).or_insert_with(|| Value::Object(Default::default())),
+            Value::Array(arr) => arr.get_mut(index(token)?).with_context(|| format!("Index {} out of bounds", token))?,
+            _ => bail!("Cannot descend into scalar at `{}`", token),
+        };
+    }
+    match (node, edit) {
+        (Value::Object(map), Edit::Set(v)) => {
+            map.insert(last.clone(), v.clone());
+        }
+        (Value::Object(map), Edit::Remove) => {
+            map.shift_remove(last).with_context(|| format!("Key `{}` not found", last))?;
+        }
+        (Value::Array(arr), Edit::Set(v)) if last == "-" => arr.push(v.clone()),
+        (Value::Array(arr), Edit::Set(v)) => {
+            *arr.get_mut(index(last)?).with_context(|| format!("Index {} out of bounds", last))? = v.clone();
+        }
+        (Value::Array(arr), Edit::Remove) => {
+            let i = index(last)?;
+            if i >= arr.len() {
+                bail!("Index {} out of bounds", last);
+            }
+            arr.remove(i);
+        }
+        _ => bail!("Cannot edit `{}` inside a scalar", last),
+    }
+    Ok(())
+}
+
+/// Line number of the first YAML comment: a `#` starting a line or following a space, outside quotes
+fn yaml_comment(text: &str) -> Option<usize> {
+    text.lines().position(|line| {
+        let mut quote = None;
+        let mut prev = ' ';
+        for c in line.chars() {
+            match (quote, c) {
+                (None, '#') if prev.is_whitespace() => return true,
+                (None, '"' | '\'') => quote = Some(c),
+                (Some(q), _) if c == q => quote = None,
+                _ => {}
+            }
+            prev = c;
+        }
+        false
+    }).map(|n| n + 1)
+}
+
+fn skip_ws(b: &[u8], mut i: usize) -> usize {
+    while b.get(i).is_some_and(u8::is_ascii_whitespace) {
+        i += 1;
+    }
+    i
+}
+
+/// End of the JSON string literal starting at `i`
+fn string_end(b: &[u8], i: usize) -> Option<usize> {
+    if b.get(i) != Some(&b'"') {
+        return None;
+    }
+    let mut j = i + 1;
+    while j < b.len() {
+        match b[j] {
+            b'\\' => j += 2,
+            b'"' => return Some(j + 1),
+            _ => j += 1,
+        }
+    }
+    None
+}
+
+/// End of the JSON value starting at `i`
+fn value_end(b: &[u8], i: usize) -> Option<usize> {
+    match *b.get(i)? {
+        b'"' => string_end(b, i),
+        b'{' | b'[' => {
+            let mut depth = 0;
+            let mut j = i;
+            while j < b.len() {
+                match b[j] {
+                    b'"' => {
+                        j = string_end(b, j)?;
+                        continue;
+                    }
+                    b'{' | b'[' => depth += 1,
+                    b'}' | b']' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Some(j + 1);
+                        }
+                    }
+                    _ => {}
+                }
+                j += 1;
+            }
+            None
+        }
+        _ => Some(b[i..].iter().position(|c| matches!(c, b',' | b'}' | b']') || c.is_ascii_whitespace()).map_or(b.len(), |n| i + n)),
+    }
+}
+
+/// A member of an object or an element of an array in the JSON text
+struct Entry {
+    key: Option<String>,
+    /// Where the member's key, or the element, starts
+    start: usize,
+    /// End of the member's key
+    key_end: usize,
+    value: (usize, usize),
+}
+
+/// Entries of the object or array whose bracket opens at `open`
+fn entries(text: &str, open: usize) -> Option<Vec<Entry>> {
+    let b = text.as_bytes();
+    let object = match b.get(open)? {
+        b'{' => true,
+        b'[' => false,
+        _ => return None,
+    };
+    let mut out = Vec::new();
+    let mut i = skip_ws(b, open + 1);
+    if matches!(b.get(i), Some(b'}' | b']')) {
+        return Some(out);
+    }
+    loop {
+        let start = i;
+        let (key, key_end) = if object {
+            let end = string_end(b, i)?;
+            let key: String = serde_json::from_str(&text[i..end]).ok()?;
+            i = skip_ws(b, end);
+            if b.get(i) != Some(&b':') {
+                return None;
+            }
+            i = skip_ws(b, i + 1);
+            (Some(key), end)
+        } else {
+            (None, start)
+        };
+        let end = value_end(b, i)?;
+        out.push(Entry { key, start, key_end, value: (i, end) });
+        i = skip_ws(b, end);
+        match b.get(i)? {
+            b',' => i = skip_ws(b, i + 1),
+            b'}' | b']' => return Some(out),
+            _ => return None,
+        }
+    }
+}
+
+/// Leading whitespace of the line holding byte `at`
+fn line_indent(text: &str, at: usize) -> &str {
+    let line = &text[text[..at].rfind('\n').map_or(0, |n| n + 1)..];
+    &line[..line.len() - line.trim_start_matches([' ', '\t']).len()]
+}
+
+/// `value` as JSON for a place indented by `indent`: compact in a one-line document, otherwise
+/// pretty-printed in the document's indent
+fn render(value: &Value, text: &str, indent: &str) -> Option<String> {
+    if !text.trim().contains('\n') {
+        return serde_json::to_string(value).ok();
+    }
+    let pretty = write_json_like(value, text).ok()?;
+    Some(pretty.replace('\n', &format!("\n{}", indent)))
+}
+
+/// `value` under the keys of `tokens`, for a set below members that are not there yet
+fn nest(tokens: &[String], value: &Value) -> Value {
+    tokens.iter().rev().fold(value.clone(), |inner, key| Value::Object([(key.clone(), inner)].into_iter().collect()))
+}
+
+/// `text` with `edit` made at `tokens` in place, every other byte left as it was; `None` when
+/// the change cannot be placed that way
+fn splice(text: &str, tokens: &[String], edit: &Edit) -> Option<String> {
+    let b = text.as_bytes();
+    let mut open = skip_ws(b, 0);
+    for (depth, token) in tokens.iter().enumerate() {
+        let found = entries(text, open)?;
+        let object = b[open] == b'{';
+        let hit = if object {
+            found.iter().rposition(|e| e.key.as_deref() == Some(token))
+        } else {
+            token.parse::<usize>().ok().filter(|&i| i < found.len())
+        };
+        let last = depth + 1 == tokens.len();
+        match (hit, edit) {
+            (Some(i), _) if !last => open = found[i].value.0,
+            (Some(i), Edit::Remove) => return Some(remove(text, open, &found, i)),
+            (Some(i), Edit::Set(value)) => {
+                let (start, end) = found[i].value;
+                let rendered = render(value, text, line_indent(text, found[i].start))?;
+                return Some(format!("{}{}{}", &text[..start], rendered, &text[end..]));
+            }
+            (None, Edit::Set(value)) if object => return insert(text, open, &found, Some(token), &nest(&tokens[depth + 1..], value)),
+            (None, Edit::Set(value)) if last && token == "-" => return insert(text, open, &found, None, value),
+            (None, _) => return None,
+        }
+    }
+    None
+}
+
+/// `text` with entry `i` of the container at `open` taken out, along with its comma
+fn remove(text: &str, open: usize, found: &[Entry], i: usize) -> String {
+    let (start, end) = match i {
+        _ if found.len() == 1 => (open + 1, value_end(text.as_bytes(), open).unwrap_or(text.len()) - 1),
+        _ if i + 1 < found.len() => (found[i].start, found[i + 1].start),
+        _ => (found[i - 1].value.1, found[i].value.1),
+    };
+    format!("{}{}", &text[..start], &text[end..])
+}
+
+/// `text` with a member `key` (or an element, for arrays) appended to the container at `open`,
+/// spaced like the entries already there
+fn insert(text: &str, open: usize, found: &[Entry], key: Option<&str>, value: &Value) -> Option<String> {
+    let b = text.as_bytes();
+    let key = key.map(|k| serde_json::to_string(k).ok()).unwrap_or(Some(String::new()))?;
+    let entry = |indent: &str| -> Option<String> {
+        let separator = match found.first() {
+            _ if key.is_empty() => "",
+            Some(e) => &text[e.key_end..e.value.0],
+            None if text.trim().contains('\n') => ": ",
+            None => ":",
+        };
+        Some(format!("{}{}{}", key, separator, render(value, text, indent)?))
+    };
+    Some(match found.last() {
+        Some(last) => {
+            let gap = match found.len() {
+                // Nothing to copy between a lone entry and its bracket; follow the rest of the document
+                1 if !text[open + 1..last.start].contains('\n') => if text.contains(", ") { " " } else { "" },
+                1 => &text[open + 1..last.start],
+                n => &text[skip_ws(b, found[n - 2].value.1) + 1..last.start],
+            };
+            let indent = match gap.rfind('\n') {
+                Some(newline) => &gap[newline + 1..],
+                None => line_indent(text, last.start),
+            };
+            format!("{},{}{}{}", &text[..last.value.1], gap, entry(indent)?, &text[last.value.1..])
+        }
+        None => {
+            let close = value_end(b, open)? - 1;
+            let outer = line_indent(text, open);
+            let body = if text.trim().contains('\n') {
+                let inner = format!("{}{}", outer, indent_unit(text));
+                format!("\n{}{}\n{}", inner, entry(&inner)?, outer)
+            } else {
+                entry(outer)?
+            };
+            format!("{}{}{}", &text[..open + 1], body, &text[close..])
+        }
+    })
+}
+
+fn index(token: &str) -> Result<usize> {
+    token.parse().with_context(|| format!("Expected array index, got `{}`", token))
+}
+
+fn to_toml(value: &Value) -> Result<toml_edit::Value> {
+    Ok(match value {
+        Value::String(s) => s.as_str().into(),
+        Value::Bool(b) => (*b).into(),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => i.into(),
+            None => n.as_f64().context("Unsupported number")?.into(),
+        },
+        Value::Array(items) => {
+            let mut arr = Array::new();
+            for item in items {
+                arr.push(to_toml(item)?);
+            }
+            arr.into()
+        }
+        Value::Object(map) => {
+            let mut table = toml_edit::InlineTable::new();
+            for (k, v) in map {
+                table.insert(k, to_toml(v)?);
+            }
+            table.into()
+        }
+        Value::Null => bail!("TOML has no null value; use op `remove` instead"),
+    })
+}
+
+fn edit_table(table: &mut dyn TableLike, tokens: &[String], edit: &Edit) -> Result<()> {
+    let (key, rest) = tokens.split_first().context("Empty JSON pointer")?;
+    if rest.is_empty() {
+        match edit {
+            Edit::Set(v) => {
+                table.insert(key, Item::Value(to_toml(v)?));
+            }
+            Edit::Remove => {
+                table.remove(key).with_context(|| format!("Key `{}` not found", key))?;
+            }
+        }
+        return Ok(());
+    }
+    if table.get(key).is_none() {
+        if matches!(edit, Edit::Remove) {
+            bail!("Key `{}` not found", key);
+        }
+        table.insert(key, Item::Table(toml_edit::Table::new()));
+    }
+    edit_item(table.get_mut(key).expect("inserted above"), rest, edit)
+}
+
+fn edit_item(item: &mut Item, tokens: &[String], edit: &Edit) -> Result<()> {
+    match item {
+        Item::Table(t) => edit_table(t, tokens, edit),
+        Item::Value(toml_edit::Value::InlineTable(t)) => edit_table(t, tokens, edit),
+        Item::Value(toml_edit::Value::Array(a)) => edit_array(a, tokens, edit),
+        Item::ArrayOfTables(aot) => {
+            let (first, rest) = tokens.split_first().context("Empty JSON pointer")?;
+            let i = index(first)?;
+            if rest.is_empty() {
+                match edit {
+                    Edit::Remove if i < aot.len() => {
+                        aot.remove(i);
+                    }
+                    Edit::Remove => bail!("Index {} out of bounds", first),
+                    Edit::Set(_) => bail!("Replacing a whole array-of-tables entry is not supported; edit its keys instead"),
+                }
+                return Ok(());
+            }
+            edit_table(aot.get_mut(i).with_context(|| format!("Index {} out of bounds", first))?, rest, edit)
+        }
+        _ => bail!("Cannot descend into scalar at `{}`", tokens[0]),
+    }
+}
+
+fn edit_array(arr: &mut Array, tokens: &[String], edit: &Edit) -> Result<()> {
+    let (first, rest) = tokens.split_first().context("Empty JSON pointer")?;
+    if rest.is_empty() {
+        match edit {
+            Edit::Set(v) if first == "-" => arr.push(to_toml(v)?),
+            Edit::Set(v) => {
+                let i = index(first)?;
+                if i >= arr.len() {
+                    bail!("Index {} out of bounds", first);
+                }
+                arr.replace(i, to_toml(v)?);
+            }
+            Edit::Remove => {
+                let i = index(first)?;
+                if i >= arr.len() {
+                    bail!("Index {} out of bounds", first);
+                }
+                arr.remove(i);
+            }
+        }
+        return Ok(());
+    }
+    match arr.get_mut(index(first)?) {
+        Some(toml_edit::Value::InlineTable(t)) => edit_table(t, rest, edit),
+        Some(toml_edit::Value::Array(inner)) => edit_array(inner, rest, edit),
+        Some(_) => bail!("Cannot descend into scalar at `{}`", first),
+        None => bail!("Index {} out of bounds", first),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use serde_json::json;
+    use crate::tools::EditOp;
+    use super::edit_structured;
+
+    fn edited(name: &str, text: &str, pointer: &str, op: EditOp, value: Option<serde_json::Value>) -> anyhow::Result<String> {
+        let dir = std::env::temp_dir().join(format!("rustaigent-structured-{}-{}", std::process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(name), text).unwrap();
+        let result = edit_structured(&dir, name, pointer, op, value.as_ref()).map(|_| fs::read_to_string(dir.join(name)).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+        result
+    }
+
+    #[test]
+    fn json_edits_keep_the_layout_of_everything_else() {
+        let text = "{\n    \"name\": \"demo\",\n    \"tags\": [\"a\", \"b\"],\n    \"deps\": {\"x\": 1}\n}\n";
+        let set = edited("set.json", text, "/name", EditOp::Set, Some(json!("renamed"))).unwrap();
+        assert_eq!(set, text.replace("\"demo\"", "\"renamed\""));
+        let pushed = edited("push.json", text, "/tags/-", EditOp::Set, Some(json!("c"))).unwrap();
+        assert_eq!(pushed, text.replace("\"b\"]", "\"b\", \"c\"]"));
+        let added = edited("add.json", text, "/deps/y", EditOp::Set, Some(json!(2))).unwrap();
+        assert_eq!(added, text.replace("{\"x\": 1}", "{\"x\": 1, \"y\": 2}"));
+        let nested = edited("nested.json", text, "/scripts/test", EditOp::Set, Some(json!("cargo test"))).unwrap();
+        assert_eq!(nested, text.replace("1}\n}", "1},\n    \"scripts\": {\n        \"test\": \"cargo test\"\n    }\n}"));
+        let removed = edited("remove.json", text, "/tags", EditOp::Remove, None).unwrap();
+        assert_eq!(removed, "{\n    \"name\": \"demo\",\n    \"deps\": {\"x\": 1}\n}\n");
+    }
+
+    #[test]
+    fn yaml_with_comments_is_refused() {
+        let text = "# deploy settings\nreplicas: 2\nimage: \"app:#1\"\n";
+        let err = edited("deploy.yaml", text, "/replicas", EditOp::Set, Some(json!(3))).unwrap_err();
+        assert!(err.to_string().contains("has comments (line 1)"), "{}", err);
+        let plain = edited("plain.yaml", "replicas: 2\nimage: \"app:#1\"\n", "/replicas", EditOp::Set, Some(json!(3))).unwrap();
+        assert!(plain.contains("replicas: 3"), "{}", plain);
+    }
+}
@@ -0,0 +1,143 @@
+use std::{collections::BTreeMap, io::Read, path::Path, process::{Command, Output, Stdio}, thread, time::{Duration, Instant}};
+use serde_json::Value;
+use anyhow::{Result, Context, anyhow, bail};
+use tokio_util::sync::CancellationToken;
+
+/// Resource types whose changes deserve a closer look: access, network exposure, data, keys
+const SENSITIVE_TYPES: &[&str] = &["iam", "security_group", "firewall", "role", "policy", "kms", "key", "secret", "db_instance", "rds", "database", "bucket", "dns", "route53", "vpc", "subnet", "network"];
+
+struct Change {
+    address: String,
+    resource_type: String,
+    action: String,
+}
+
+impl Change {
+    fn risk(&self) -> &'static str {
+        let sensitive = SENSITIVE_TYPES.iter().any(|t| self.resource_type.contains(t));
+        match (self.action.as_str(), sensitive) {
+            ("delete" | "replace", _) => "HIGH",
+            ("update", true) | ("create", true) => "MEDIUM",
+            _ => "LOW",
+        }
+    }
+}
+
+/// Normalize terraform's action lists (`["delete","create"]`) into one verb
+fn action_name(actions: &[Value]) -> String {
+    let names: Vec<&str> = actions.iter().filter_map(|a| a.as_str()).collect();
+    match names.as_slice() {
+        ["delete", "create"] | ["create", "delete"] => "replace".into(),
+        [one] => one.to_string(),
+        many => many.join("+"),
+    }
+}
+
+/// Planned changes from `terraform plan -json` (streamed UI events)
+fn from_events(output: &str) -> (Vec<Change>, Vec<String>) {
+    let mut changes = Vec::new();
+    let mut diagnostics = Vec::new();
+    for line in output.lines() {
+        let Ok(event) = serde_json::from_str::<Value>(line) else { continue };
+        match event["type"].as_str() {
+            Some("planned_change") => {
+                let change = &event["change"];
+                changes.push(Change {
+                    address: change["resource"]["addr"].as_str().unwrap_or_default().into(),
+                    resource_type: change["resource"]["resource_type"].as_str().unwrap_or_default().into(),
+                    action: change["action"].as_str().unwrap_or_default().into(),
+                });
+            }
+            Some("diagnostic") => diagnostics.push(format!("{}: {}", event["diagnostic"]["severity"].as_str().unwrap_or("info"), event["diagnostic"]["summary"].as_str().unwrap_or_default())),
+            _ => {}
+        }
+    }
+    (changes, diagnostics)
+}
+
+/// Planned changes from `terraform show -json <planfile>`
+fn from_plan_document(doc: &Value) -> Vec<Change> {
+    doc["resource_changes"].as_array().into_iter().flatten()
+        .map(|rc| Change {
+            address: rc["address"].as_str().unwrap_or_default().into(),
+            resource_type: rc["type"].as_str().unwrap_or_default().into(),
+            action: action_name(rc["change"]["actions"].as_array().map(Vec::as_slice).unwrap_or_default()),
+        })
+        .filter(|c| c.action != "no-op" && c.action != "read")
+        .collect()
+}
+
+fn render(changes: &[Change], diagnostics: &[String]) -> String {
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for c in changes {
+        *counts.entry(c.action.as_str()).or_default() += 1;
+    }
+    let summary: Vec<String> = counts.iter().map(|(a, n)| format!("{} {}", n, a)).collect();
+    let mut out = format!("Plan: {}\n", if summary.is_empty() { "no changes".into() } else { summary.join(", ") });
+    let mut sorted: Vec<&Change> = changes.iter().collect();
+    sorted.sort_by_key(|c| match c.risk() { "HIGH" => 0, "MEDIUM" => 1, _ => 2 });
+    for c in sorted {
+        out.push_str(&format!("[{}] {} {}\n", c.risk(), c.action, c.address));
+    }
+    for d in diagnostics {
+        out.push_str(&format!("diagnostic {}\n", d));
+    }
+    out
+}
+
+/// Run terraform with `args` in `dir` in its own process group, killed like `run_command` once
+/// `timeout_secs` pass or the turn is cancelled
+fn terraform(dir: &Path, args: &[&str], timeout_secs: u64, cancel: &CancellationToken) -> Result<Output> {
+    let mut command = Command::new("terraform");
+    command.args(args).current_dir(dir);
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+    let mut child = command.stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()
+        .context("Failed to run terraform; is it installed?")?;
+    let read = |mut pipe: Box<dyn Read + Send>| thread::spawn(move || {
+        let mut bytes = Vec::new();
+        pipe.read_to_end(&mut bytes).ok();
+        bytes
+    });
+    let stdout = read(Box::new(child.stdout.take().context("no stdout pipe")?));
+    let stderr = read(Box::new(child.stderr.take().context("no stderr pipe")?));
+    let deadline = Instant::now() + Duration::from_secs(timeout_secs);
+    let Some(status) = super::shell::wait(&mut child, deadline, cancel)? else {
+        bail!("terraform was killed after the {} limit", humantime::format_duration(Duration::from_secs(timeout_secs)));
+    };
+    let joined = |reader: thread::JoinHandle<Vec<u8>>| reader.join().map_err(|_| anyhow!("terraform output reader panicked"));
+    Ok(Output { status, stdout: joined(stdout)?, stderr: joined(stderr)? })
+}
+
+/// Run `terraform plan -json` in `module` under `dir`, or read an existing plan file, and
+/// summarize the changes with risk levels; terraform gets `timeout_secs`, `run_command`'s default
+/// when unset
+pub fn plan(dir: &Path, module: &str, plan_file: Option<&str>, timeout_secs: Option<u64>, cancel: &CancellationToken) -> Result<String> {
+    let dir = dir.join(module);
+    let timeout_secs = timeout_secs.unwrap_or(super::shell::DEFAULT_TIMEOUT_SECS);
+    if let Some(file) = plan_file {
+        let out = terraform(&dir, &["show", "-json", file], timeout_secs, cancel)?;
+        if !out.status.success() {
+            bail!("terraform show failed:\n{}", String::from_utf8_lossy(&out.stderr));
+        }
+        let doc: Value = serde_json::from_slice(&out.stdout).context("terraform show did not return JSON")?;
+        return Ok(render(&from_plan_document(&doc), &[]));
+    }
+    let out = terraform(&dir, &["plan", "-json", "-input=false", "-lock=false"], timeout_secs, cancel)?;
+    let (changes, diagnostics) = from_events(&String::from_utf8_lossy(&out.stdout));
+    if !out.status.success() && changes.is_empty() {
+        bail!("terraform plan failed:\n{}{}", diagnostics.join("\n"), String::from_utf8_lossy(&out.stderr));
+    }
+    Ok(render(&changes, &diagnostics))
+}
+
+/// Instructions for the plan review flow
+pub fn review_prompt(summary: &str) -> String {
+    format!(
+        "Review this Terraform plan before it is applied. Explain in plain language what will change, then assess the risk: \
+         call out destroyed or replaced resources, changes to access control, networking exposure, encryption and data stores, \
+         and anything that could cause downtime or data loss. Finish with a clear recommendation (apply, apply with care, or do not apply) \
+         and what to verify first.\n\n{}",
+        summary
+    )
+}
@@ -0,0 +1,37 @@
+use serde::{Serialize, Deserialize};
+
+/// What a request is for, so tool choices can be sampled more conservatively than prose
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Turn {
+    /// Tools are offered and the model picks one and its arguments, or answers
+    Tools,
+    /// Tools are left out and the model writes or rewrites an answer
+    Answer,
+}
+
+/// Generation settings for one kind of turn; unset fields fall back to the session's
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct TurnParams {
+    pub model: Option<String>,
+    pub temperature: Option<f32>,
+    pub max_tokens: Option<u16>,
+}
+
+/// The `[turns.tools]` and `[turns.answer]` tables
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct Turns {
+    pub tools: Option<TurnParams>,
+    pub answer: Option<TurnParams>,
+}
+
+/// Tables accepted under `[turns]`, for config validation
+pub const KINDS: &[&str] = &["tools", "answer"];
+
+impl Turns {
+    pub fn params(&self, turn: Turn) -> Option<&TurnParams> {
+        match turn {
+            Turn::Tools => self.tools.as_ref(),
+            Turn::Answer => self.answer.as_ref(),
+        }
+    }
+}
@@ -0,0 +1,137 @@
+//! Files uploaded to the provider's files API and referred to from the next prompt, so large PDFs
+//! and datasets are not inlined as text. OpenAI takes PDFs through its Files API; Gemini takes
+//! documents, data files and media through its own.
+
+use std::{path::Path, time::Duration};
+use serde_json::{json, Value};
+use anyhow::{Result, Context, bail};
+use crate::{Agent, FileRef};
+
+/// How often, and how many times, to check on a Gemini file still being processed
+const PROCESSING_POLL: Duration = Duration::from_secs(2);
+const PROCESSING_CHECKS: u32 = 30;
+
+/// MIME type by extension; `application/octet-stream` when the extension says nothing
+pub fn mime(path: &Path) -> &'static str {
+    let ext = path.extension().and_then(|e| e.to_str()).map(str::to_ascii_lowercase);
+    match ext.as_deref() {
+        Some("pdf") => "application/pdf",
+        Some("txt" | "log") => "text/plain",
+        Some("md") => "text/markdown",
+        Some("csv") => "text/csv",
+        Some("tsv") => "text/tab-separated-values",
+        Some("json") => "application/json",
+        Some("jsonl") => "application/jsonl",
+        Some("html" | "htm") => "text/html",
+        Some("xml") => "text/xml",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("webp") => "image/webp",
+        Some("mp3") => "audio/mpeg",
+        Some("wav") => "audio/wav",
+        Some("mp4") => "video/mp4",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Turn an error status into an error carrying the provider's message
+async fn checked(response: reqwest::Response) -> Result<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body: Value = response.json().await.unwrap_or_default();
+    bail!("File upload returned {}: {}", status, body["error"]["message"].as_str().unwrap_or("no details"));
+}
+
+/// Send `bytes` to the files API of the agent's provider
+pub async fn upload(agent: &Agent, name: &str, mime: &'static str, bytes: Vec<u8>) -> Result<FileRef> {
+    match agent.provider.as_str() {
+        "openai" => openai(agent, name, mime, bytes).await,
+        "google" => gemini(agent, name, mime, bytes).await,
+        other => bail!("Provider `{}` has no files API; uploads work with `openai` and `google`", other),
+    }
+}
+
+/// Chat Completions only reads PDFs from uploaded files
+async fn openai(agent: &Agent, name: &str, mime: &'static str, bytes: Vec<u8>) -> Result<FileRef> {
+    if mime != "application/pdf" {
+        bail!("OpenAI chat models only read uploaded PDFs; {} is {}", name, mime);
+    }
+    let base = agent.base_url.as_deref().unwrap_or("https://api.openai.com").trim_end_matches('/');
+    let (_, key) = agent.keys.select();
+    let file = reqwest::multipart::Part::bytes(bytes).file_name(name.to_string()).mime_str(mime)?;
+    let form = reqwest::multipart::Form::new().text("purpose", "user_data").part("file", file);
+    let url = format!("{}/{}/files", base, agent.api_versions.openai);
+    let uploaded: Value = checked(agent.client.post(url).bearer_auth(key).multipart(form).send().await?).await?.json().await?;
+    let id = uploaded["id"].as_str().context("The file upload returned no id")?;
+    Ok(FileRef { provider: "openai".into(), id: id.into(), name: name.into(), mime: mime.into() })
+}
+
+/// Resumable upload: a start request announcing the file, then its bytes to the URL it returns.
+/// Larger files are processed for a while before a prompt may use them.
+async fn gemini(agent: &Agent, name: &str, mime: &'static str, bytes: Vec<u8>) -> Result<FileRef> {
+    let base = agent.base_url.as_deref().unwrap_or("https://generativelanguage.googleapis.com").trim_end_matches('/');
+    let version = &agent.api_versions.google;
+    let (_, key) = agent.keys.select();
+    let start = agent.client.post(format!("{}/upload/{}/files", base, version))
+        .header("x-goog-api-key", &key)
+        .header("X-Goog-Upload-Protocol", "resumable")
+        .header("X-Goog-Upload-Command", "start")
+        .header("X-Goog-Upload-Header-Content-Length", bytes.len())
+        .header("X-Goog-Upload-Header-Content-Type", mime)
+        .json(&json!({"file": {"display_name": name}}))
+        .send().await?;
+    let target = checked(start).await?.headers().get("x-goog-upload-url")
+        .and_then(|url| url.to_str().ok()).map(String::from)
+        .context("The upload start returned no upload URL")?;
+    let finish = agent.client.post(target)
+        .header("X-Goog-Upload-Offset", 0)
+        .header("X-Goog-Upload-Command", "upload, finalize")
+        .body(bytes)
+        .send().await?;
+    let mut file = checked(finish).await?.json::<Value>().await?["file"].take();
+    for _ in 0..PROCESSING_CHECKS {
+        if file["state"] != "PROCESSING" {
+            break;
+        }
+        tokio::time::sleep(PROCESSING_POLL).await;
+        let resource = file["name"].as_str().context("The uploaded file has no name")?;
+        let url = format!("{}/{}/{}", base, version, resource);
+        file = checked(agent.client.get(url).header("x-goog-api-key", &key).send().await?).await?.json().await?;
+    }
+    match file["state"].as_str() {
+        Some("FAILED") => bail!("Gemini could not process {}", name),
+        Some("PROCESSING") => bail!("Gemini is still processing {}; try again in a minute", name),
+        _ => {}
+    }
+    let uri = file["uri"].as_str().context("The file upload returned no URI")?;
+    let mime = file["mimeType"].as_str().unwrap_or(mime);
+    Ok(FileRef { provider: "google".into(), id: uri.into(), name: name.into(), mime: mime.into() })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use crate::mock::MockProvider;
+
+    #[tokio::test]
+    async fn uploaded_files_go_with_the_next_prompt_only() {
+        let mock = MockProvider::start().await;
+        mock.files("file-mock").await;
+        mock.reply("a report").await;
+        let mut agent = mock.agent();
+        agent.enable_quiet();
+        let pdf = std::env::temp_dir().join(format!("rustaigent-upload-{}.pdf", std::process::id()));
+        std::fs::write(&pdf, b"%PDF-1.4").unwrap();
+        let file = agent.upload(&pdf).await.unwrap();
+        std::fs::remove_file(&pdf).ok();
+        assert_eq!(file.id, "file-mock");
+        agent.turn("What is this?").await.unwrap();
+        agent.turn("And now?").await.unwrap();
+        let requests = mock.requests().await;
+        let content = |n: usize| requests[n]["messages"].as_array().unwrap().last().unwrap()["content"].clone();
+        assert_eq!(content(0), json!([{"type": "text", "text": "What is this?"}, {"type": "file", "file": {"file_id": "file-mock"}}]));
+        assert_eq!(content(1), "And now?");
+    }
+}
@@ -0,0 +1,262 @@
+//! Encryption at rest for what the agent keeps about a session: autosaved bundles with their
+//! tool log, fix-loop checkpoints, plans, turns paged out of memory and the prompt history.
+//! Off unless `encryption` is set. Files are recognized by their header when read, so older
+//! plaintext files stay readable after it is turned on.
+
+use std::{env, fs, io::{IsTerminal, Write}, path::{Path, PathBuf}, sync::OnceLock};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::{aead::Aead, KeyInit, XChaCha20Poly1305, XNonce};
+use anyhow::{Result, Context, anyhow, bail};
+use crate::paths;
+
+/// Values accepted for `encryption`
+pub const MODES: &[&str] = &["keyring", "passphrase"];
+
+const MAGIC: &[u8] = b"RAIV1";
+const NONCE_LEN: usize = 24;
+const KEYRING_SERVICE: &str = "rustaigent";
+const KEYRING_USER: &str = "storage-key";
+/// Sealed with the passphrase key to tell a mistyped passphrase from a new one
+const CHECK: &[u8] = b"rustaigent storage key";
+
+/// XChaCha20-Poly1305 with a fresh random nonce per file, framed as magic, nonce, ciphertext
+pub struct Cipher {
+    aead: XChaCha20Poly1305,
+    magic: &'static [u8],
+}
+
+impl Cipher {
+    pub fn new(key: &[u8; 32], magic: &'static [u8]) -> Self {
+        Cipher { aead: XChaCha20Poly1305::new(key.into()), magic }
+    }
+
+    /// Key derived from a passphrase with Argon2id
+    pub fn from_passphrase(passphrase: &str, salt: &[u8], magic: &'static [u8]) -> Result<Self> {
+        let mut key = [0u8; 32];
+        argon2::Argon2::default().hash_password_into(passphrase.as_bytes(), salt, &mut key)
+            .map_err(|err| anyhow!("Failed to derive the key: {}", err))?;
+        Ok(Cipher::new(&key, magic))
+    }
+
+    pub fn seal(&self, plain: &[u8]) -> Result<Vec<u8>> {
+        let nonce: [u8; NONCE_LEN] = random()?;
+        let sealed = self.aead.encrypt(&XNonce::from(nonce), plain).map_err(|_| anyhow!("Encryption failed"))?;
+        Ok([self.magic, &nonce, &sealed].concat())
+    }
+
+    /// Decrypt sealed data; `what` names it in errors
+    pub fn open(&self, what: &str, data: &[u8]) -> Result<Vec<u8>> {
+        let body = data.strip_prefix(self.magic).filter(|b| b.len() > NONCE_LEN).with_context(|| format!("{} is not an encrypted file", what))?;
+        let (nonce, sealed) = body.split_at(NONCE_LEN);
+        let nonce: [u8; NONCE_LEN] = nonce.try_into()?;
+        self.aead.decrypt(&XNonce::from(nonce), sealed)
+            .map_err(|_| anyhow!("Cannot decrypt {}: wrong key or passphrase, or a tampered file", what))
+    }
+}
+
+/// Bytes from the OS random source
+pub fn random<const N: usize>() -> Result<[u8; N]> {
+    let mut bytes = [0u8; N];
+    getrandom::fill(&mut bytes).map_err(|err| anyhow!("No OS randomness: {}", err))?;
+    Ok(bytes)
+}
+
+/// Salt and passphrase check for `passphrase` mode
+fn check_file() -> PathBuf {
+    paths::data_dir().join("vault.check")
+}
+
+/// Random key kept in the OS keyring (Keychain, Credential Manager, Secret Service), created on first use
+fn keyring_key() -> Result<[u8; 32]> {
+    let entry = keyring::Entry::new(KEYRING_SERVICE, KEYRING_USER).context("The OS keyring is unavailable")?;
+    match entry.get_password() {
+        Ok(text) => STANDARD.decode(text).ok().and_then(|k| k.try_into().ok()).context("The storage key in the OS keyring is malformed"),
+        Err(keyring::Error::NoEntry) => {
+            let key: [u8; 32] = random()?;
+            entry.set_password(&STANDARD.encode(key)).context("Failed to store a new key in the OS keyring")?;
+            Ok(key)
+        }
+        Err(err) => Err(anyhow!(err)).context("The OS keyring is unavailable; set `encryption = \"passphrase\"` instead"),
+    }
+}
+
+/// Passphrase from RUSTAIGENT_PASSPHRASE or a prompt, checked against the one chosen first
+fn passphrase_cipher() -> Result<Cipher> {
+    let passphrase = match env::var("RUSTAIGENT_PASSPHRASE") {
+        Ok(pass) => pass,
+        Err(_) if std::io::stdin().is_terminal() => rpassword::prompt_password("Storage passphrase: ")?,
+        Err(_) => bail!("Set RUSTAIGENT_PASSPHRASE to read and write encrypted sessions without a terminal"),
+    };
+    passphrase_check(&passphrase, &check_file())
+}
+
+/// Cipher for `passphrase` with the salt in the check file at `path`, which is created only when
+/// missing: a new salt would leave every session sealed with the old one unreadable
+fn passphrase_check(passphrase: &str, path: &Path) -> Result<Cipher> {
+    let damaged = || anyhow!("{} is damaged; restore it from a backup, since sessions sealed with its salt cannot be read without it", path.display());
+    match fs::read(path) {
+        Ok(data) => {
+            if data.len() <= 16 {
+                return Err(damaged());
+            }
+            let (salt, check) = data.split_at(16);
+            let cipher = Cipher::from_passphrase(passphrase, salt, MAGIC)?;
+            match cipher.open("the passphrase check", check) {
+                Ok(plain) if plain == CHECK => Ok(cipher),
+                Err(_) if check.starts_with(MAGIC) && check.len() > MAGIC.len() + NONCE_LEN => bail!("Wrong storage passphrase"),
+                _ => Err(damaged()),
+            }
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            let salt: [u8; 16] = random()?;
+            let cipher = Cipher::from_passphrase(passphrase, &salt, MAGIC)?;
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let mut file = fs::OpenOptions::new().write(true).create_new(true).open(path)
+                .with_context(|| format!("Failed to create {}", path.display()))?;
+            file.write_all(&[&salt[..], &cipher.seal(CHECK)?].concat()).with_context(|| format!("Failed to write {}", path.display()))?;
+            Ok(cipher)
+        }
+        Err(err) => Err(err).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+/// Mode from RUSTAIGENT_ENCRYPTION or the config
+fn mode() -> Option<String> {
+    env::var("RUSTAIGENT_ENCRYPTION").ok().or_else(|| crate::config::Config::load().encryption).filter(|m| !m.is_empty())
+}
+
+/// The storage cipher, resolved once per process; `None` when encryption is off
+fn cipher() -> Result<Option<&'static Cipher>> {
+    static CIPHER: OnceLock<std::result::Result<Option<Cipher>, String>> = OnceLock::new();
+    let resolved = CIPHER.get_or_init(|| {
+        let cipher = match mode().as_deref() {
+            None => Ok(None),
+            Some("keyring") => keyring_key().map(|key| Some(Cipher::new(&key, MAGIC))),
+            Some("passphrase") => passphrase_cipher().map(Some),
+            Some(other) => Err(anyhow!("`encryption` must be one of {} (found `{}`)", MODES.join(", "), other)),
+        };
+        cipher.map_err(|err| format!("{:#}", err))
+    });
+    resolved.as_ref().map(Option::as_ref).map_err(|err| anyhow!("{}", err))
+}
+
+/// Data as it should be stored: sealed when encryption is on
+pub fn seal(plain: Vec<u8>) -> Result<Vec<u8>> {
+    match cipher()? {
+        Some(cipher) => cipher.seal(&plain),
+        None => Ok(plain),
+    }
+}
+
+/// Plain contents of stored data, decrypting it when it was sealed
+pub fn open(path: &Path, data: Vec<u8>) -> Result<Vec<u8>> {
+    if !data.starts_with(MAGIC) {
+        return Ok(data);
+    }
+    let cipher = cipher()?.with_context(|| format!("{} is encrypted; set `encryption` as when it was written", path.display()))?;
+    cipher.open(&path.display().to_string(), &data)
+}
+
+/// Read a stored file; `None` when it does not exist
+pub fn read(path: &Path) -> Result<Option<Vec<u8>>> {
+    match fs::read(path) {
+        Ok(data) => open(path, data).map(Some),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+/// Write a stored file, sealed when encryption is on
+pub fn write(path: &Path, plain: Vec<u8>) -> Result<()> {
+    fs::write(path, seal(plain)?).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Create `path` afresh, readable and writable by its owner only from the first byte, for keys
+/// and credentials
+pub fn create_private(path: &Path) -> Result<fs::File> {
+    if let Err(err) = fs::remove_file(path) {
+        if err.kind() != std::io::ErrorKind::NotFound {
+            return Err(err).with_context(|| format!("Failed to replace {}", path.display()));
+        }
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path).with_context(|| format!("Failed to create {}", path.display()))
+}
+
+/// Encrypt stored files written before encryption was turned on; returns how many changed
+pub fn seal_existing() -> Result<usize> {
+    if cipher()?.is_none() {
+        bail!("Encryption is off; set `encryption = \"keyring\"` or `\"passphrase\"` first");
+    }
+    let data = paths::data_dir();
+    let mut files = vec![data.join("prompts.jsonl")];
+    for dir in ["sessions", "fix", "artifacts"] {
+        if let Ok(entries) = fs::read_dir(data.join(dir)) {
+            files.extend(entries.filter_map(|e| e.ok()).map(|e| e.path()).filter(|p| p.is_file()));
+        }
+    }
+    let mut sealed = 0;
+    for path in files {
+        let Ok(bytes) = fs::read(&path) else { continue };
+        if !bytes.starts_with(MAGIC) {
+            write(&path, bytes)?;
+            sealed += 1;
+        }
+    }
+    Ok(sealed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use super::{passphrase_check, random, Cipher, MAGIC};
+
+    #[test]
+    fn sealed_data_opens_with_the_same_passphrase_only() {
+        let salt: [u8; 16] = random().unwrap();
+        let cipher = Cipher::from_passphrase("correct horse", &salt, MAGIC).unwrap();
+        let sealed = cipher.seal(b"session bundle").unwrap();
+        assert!(sealed.starts_with(MAGIC) && !sealed.windows(14).any(|w| w == b"session bundle"));
+        assert_ne!(sealed, cipher.seal(b"session bundle").unwrap(), "each seal needs a fresh nonce");
+        assert_eq!(cipher.open("bundle", &sealed).unwrap(), b"session bundle");
+        let again = Cipher::from_passphrase("correct horse", &salt, MAGIC).unwrap();
+        assert_eq!(again.open("bundle", &sealed).unwrap(), b"session bundle");
+        let wrong = Cipher::from_passphrase("battery staple", &salt, MAGIC).unwrap();
+        assert!(wrong.open("bundle", &sealed).unwrap_err().to_string().contains("wrong key or passphrase"));
+    }
+
+    #[test]
+    fn tampered_or_foreign_data_is_refused() {
+        let cipher = Cipher::new(&[7; 32], MAGIC);
+        let mut sealed = cipher.seal(b"plan").unwrap();
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+        assert!(cipher.open("plan", &sealed).is_err());
+        assert!(cipher.open("plan", b"{\"plain\": true}").unwrap_err().to_string().contains("is not an encrypted file"));
+        let other = Cipher::new(&[7; 32], b"RAIL1").seal(b"plan").unwrap();
+        assert!(cipher.open("plan", &other).is_err());
+    }
+
+    #[test]
+    fn the_passphrase_check_is_created_once_and_never_replaced() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("vault.check");
+        let sealed = passphrase_check("correct horse", &path).unwrap().seal(b"session").unwrap();
+        let check = fs::read(&path).unwrap();
+        assert_eq!(passphrase_check("correct horse", &path).unwrap().open("session", &sealed).unwrap(), b"session");
+        assert_eq!(passphrase_check("battery staple", &path).err().unwrap().to_string(), "Wrong storage passphrase");
+        fs::write(&path, &check[..10]).unwrap();
+        assert!(passphrase_check("correct horse", &path).err().unwrap().to_string().contains("is damaged"));
+        fs::write(&path, [&check[..16], b"garbage".as_slice()].concat()).unwrap();
+        assert!(passphrase_check("correct horse", &path).err().unwrap().to_string().contains("is damaged"));
+        fs::remove_file(&path).unwrap();
+        fs::create_dir(&path).unwrap();
+        assert!(passphrase_check("correct horse", &path).err().unwrap().to_string().contains("Failed to read"));
+        assert!(path.is_dir());
+    }
+}
@@ -0,0 +1,180 @@
+//! Verification pass: before a final answer is printed, it is checked against a rubric, first
+//! mechanically (Rust, JSON, TOML or YAML blocks that do not parse, placeholder markers) and then
+//! by a second model call. An answer that fails goes back to the model with the problems found, until it passes or
+//! the retries are used up, trading tokens for reliability in unattended runs.
+
+use regex::Regex;
+use serde::{Serialize, Deserialize};
+use anyhow::{Result, bail};
+use crate::{fences, toolchain::Toolchain, turns::Turn, Agent, ChatMessage};
+
+/// `[verify]` table: when answers are checked, by which model and against what
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct VerifyConfig {
+    /// Check every final answer; `--verify` turns it on for one run
+    pub enabled: Option<bool>,
+    /// Model that grades answers (default: the session's)
+    pub model: Option<String>,
+    /// Criteria on top of the built-in ones
+    pub rubric: Option<Vec<String>>,
+    /// Times a failing answer is regenerated (default 2)
+    pub retries: Option<u32>,
+    /// Fail the run when the last answer still fails, instead of printing it with a warning
+    pub strict: Option<bool>,
+}
+
+/// Keys accepted in `[verify]`, for config validation
+pub const KEYS: &[&str] = &["enabled", "model", "rubric", "retries", "strict"];
+
+/// What every answer is held to
+const RUBRIC: &[&str] = &[
+    "It does what the request asked for, completely, and answers the question that was asked.",
+    "Code and patches are complete: no TODO or FIXME markers, `todo!()`, `unimplemented!()`, elided bodies or \"rest unchanged\" comments in place of real code.",
+    "Code would compile as far as the answer shows: no syntax errors, undefined names or mismatched types.",
+    "Patches are well-formed unified diffs or SEARCH/REPLACE blocks that apply to the files named.",
+];
+
+/// Markers of code left unwritten
+const PLACEHOLDERS: &str = r"\b(TODO|FIXME)\b|\btodo!\(|\bunimplemented!\(|(//|#)\s*\.\.\.|(?i:rest of (the )?(code|file|function) (is )?(unchanged|remains|stays))";
+
+/// Language and body of each fenced block
+fn blocks(text: &str) -> Vec<(String, String)> {
+    let mut found = Vec::new();
+    let mut current: Option<(String, String)> = None;
+    for line in text.lines() {
+        let fence = line.trim_start().strip_prefix("```");
+        match (current.take(), fence) {
+            (Some(block), Some(_)) => found.push(block),
+            (Some((lang, mut body)), None) => {
+                body.push_str(line);
+                body.push('\n');
+                current = Some((lang, body));
+            }
+            (None, Some(info)) => {
+                let lang = info.split_whitespace().next().unwrap_or("");
+                current = Some((if lang == "rs" { "rust" } else { lang }.to_string(), String::new()));
+            }
+            (None, None) => {}
+        }
+    }
+    found
+}
+
+/// Problems visible without a model: blocks of a checkable language that do not parse,
+/// placeholders in code, and Rust the project's toolchain would reject
+fn mechanical(answer: &str, toolchain: Option<&Toolchain>) -> Vec<String> {
+    let blocks = blocks(answer);
+    let mut problems: Vec<String> = blocks.iter()
+        .filter_map(|(lang, body)| fences::syntax_error(lang, body).map(|err| format!("A {} code block does not parse: {}", lang, err)))
+        .collect();
+    let placeholder = Regex::new(PLACEHOLDERS).unwrap();
+    if let Some(found) = blocks.iter().find_map(|(_, body)| placeholder.find(body)) {
+        problems.push(format!("The code contains the placeholder `{}`; write the real code instead", found.as_str()));
+    }
+    if let Some(toolchain) = toolchain {
+        problems.extend(blocks.iter().filter(|(lang, _)| lang == "rust").find_map(|(_, body)| toolchain.check(body)));
+    }
+    problems
+}
+
+#[derive(Deserialize)]
+struct Grade {
+    pass: bool,
+    #[serde(default)]
+    problems: Vec<String>,
+}
+
+/// The grading model's verdict on `answer`: the problems it found, none when it passes
+async fn grade(agent: &Agent, cfg: &VerifyConfig, toolchain: Option<&Toolchain>, request: &str, answer: &str) -> Result<Vec<String>> {
+    let msrv = toolchain.and_then(|t| t.rust_version.as_ref().map(|msrv| (msrv, t.edition.as_deref().unwrap_or("2021"))))
+        .map(|(msrv, edition)| format!("Rust code builds on Rust {} with edition {}: it uses no language features or standard library APIs stabilized later.", msrv, edition));
+    let rubric: Vec<String> = RUBRIC.iter().map(|r| r.to_string()).chain(msrv).chain(cfg.rubric.iter().flatten().cloned()).collect();
+    let prompt = format!(
+        "Check the answer below against the request it replies to and each criterion. Reply with JSON only: {{\"pass\": true}} when every criterion holds, else {{\"pass\": false, \"problems\": [\"...\"]}} with one specific, actionable sentence per problem.\n\nCriteria:\n{}\n\nRequest:\n{}\n\nAnswer:\n{}",
+        rubric.iter().map(|r| format!("- {}", r)).collect::<Vec<_>>().join("\n"), request, answer
+    );
+    let mut grader = agent.clone_for_batch(prompt);
+    if let Some(model) = &cfg.model {
+        grader.model = Some(model.clone());
+    }
+    let reply = grader.send_request(Turn::Answer, None).await?.content;
+    let json = reply.find('{').zip(reply.rfind('}')).filter(|(start, end)| start < end).map(|(start, end)| &reply[start..=end]);
+    let Some(grade) = json.and_then(|json| serde_json::from_str::<Grade>(json).ok()) else {
+        bail!("The grading model replied without a verdict: {}", reply.trim());
+    };
+    Ok(match grade.pass {
+        true => Vec::new(),
+        false if grade.problems.is_empty() => vec!["The answer does not meet the criteria".into()],
+        false => grade.problems,
+    })
+}
+
+/// A turn whose answer is checked before it is returned, when `[verify]` or `--verify` is on.
+/// A failing answer is sent back with its problems as the next prompt, up to `retries` times.
+pub async fn turn(agent: &mut Agent, input: &str) -> Result<Option<ChatMessage>> {
+    let reply = agent.turn(input).await?;
+    let cfg = agent.config.verify.clone().unwrap_or_default();
+    if cfg.enabled != Some(true) {
+        return Ok(reply);
+    }
+    let retries = cfg.retries.unwrap_or(2);
+    let toolchain = Toolchain::detect(&agent.tool_ctx.dir());
+    let mut reply = reply;
+    for attempt in 0..=retries {
+        let Some(answer) = &reply else { return Ok(None) };
+        // Mechanical problems are certain, so the grading call is saved until they are fixed
+        let mut problems = mechanical(&answer.content, toolchain.as_ref());
+        if problems.is_empty() {
+            problems = match grade(agent, &cfg, toolchain.as_ref(), input, &answer.content).await {
+                Ok(problems) => problems,
+                Err(err) => {
+                    agent.report(format!("[verify] skipped: {:#}", err));
+                    return Ok(reply);
+                }
+            };
+        }
+        if problems.is_empty() {
+            agent.report(format!("[verify] passed{}", if attempt > 0 { format!(" after {} regeneration(s)", attempt) } else { String::new() }));
+            return Ok(reply);
+        }
+        agent.report(format!("[verify] failed: {}", problems.join("; ")));
+        if attempt == retries {
+            if cfg.strict == Some(true) {
+                bail!("The answer still failed verification after {} regeneration(s): {}", retries, problems.join("; "));
+            }
+            agent.report("[verify] keeping the last answer; it did not pass".into());
+            return Ok(reply);
+        }
+        let feedback = format!(
+            "A check of your answer found these problems:\n{}\nFix them and give the complete answer again, not just the changes.",
+            problems.iter().map(|p| format!("- {}", p)).collect::<Vec<_>>().join("\n")
+        );
+        reply = agent.turn(&feedback).await?;
+    }
+    Ok(reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mock::{last_content, MockProvider};
+    use super::turn;
+
+    #[tokio::test]
+    async fn failing_answers_are_regenerated_until_verified() {
+        let mock = MockProvider::start().await;
+        mock.reply_once("```rust\nfn parse() -> u32 {\n    todo!()\n}\n```").await;
+        mock.reply_once("```rust\nfn parse() -> u32 {\n    42\n}\n```").await;
+        mock.reply("{\"pass\": true}").await;
+        let mut agent = mock.agent();
+        agent.enable_quiet();
+        agent.enable_verify();
+        let reply = turn(&mut agent, "Write parse").await.unwrap().unwrap();
+        assert!(reply.content.contains("42"), "{}", reply.content);
+        let requests = mock.requests().await;
+        assert_eq!(requests.len(), 3);
+        let feedback = last_content(&requests[1]);
+        assert!(feedback.contains("placeholder `todo!(`"));
+        let grading = last_content(&requests[2]);
+        assert!(grading.contains("Request:\nWrite parse") && grading.contains("42"));
+    }
+}
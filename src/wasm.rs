@@ -0,0 +1,32 @@
+//! JavaScript entry point of the wasm32 build, for browser playgrounds and edge functions:
+//! `await complete("claude", key, model, JSON.stringify(messages))`.
+
+use wasm_bindgen::prelude::*;
+use crate::{api_version::ApiVersions, chat, providers::Request, ChatMessage};
+
+/// Run one request and return the assistant message as JSON (`role`, `content` and, when the
+/// model asked for one, `function_call`); `messages` is a JSON array of `{role, content}`, and
+/// `base_url` replaces the provider's scheme and host, e.g. with a proxy that holds the key
+#[wasm_bindgen]
+pub async fn complete(provider: String, api_key: String, model: String, messages: String, max_tokens: u16, temperature: Option<f32>, base_url: Option<String>) -> Result<String, JsError> {
+    let messages: Vec<ChatMessage> = serde_json::from_str(&messages)?;
+    let versions = ApiVersions::default();
+    let request = Request {
+        model: &model,
+        messages: &messages,
+        functions: None,
+        force: None,
+        max_tokens,
+        temperature,
+        reasoning_model: false,
+        reasoning_effort: None,
+        thinking_budget: None,
+        stream: false,
+        base_url: base_url.as_deref(),
+        versions: &versions,
+        hosted_tools: &[],
+        vector_stores: &[],
+    };
+    let reply = chat::complete(&reqwest::Client::new(), &provider, &api_key, &request).await.map_err(|err| JsError::new(&format!("{:#}", err)))?;
+    Ok(serde_json::to_string(&reply.message)?)
+}
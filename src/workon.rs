@@ -0,0 +1,214 @@
+//! `rustaigent work-on <issue-url>`: fetch a GitHub issue, then branch, plan, implement, test and
+//! describe the change, asking before each stage.
+
+use std::fs;
+use serde::Deserialize;
+use anyhow::{Result, Context, bail};
+use reqwest::Method;
+use crate::{fix, gitdiff, github, paths, pr, Agent};
+
+/// Comments fetched with the issue; later ones rarely change what is asked for
+const MAX_COMMENTS: usize = 20;
+
+/// Characters of the title kept in the branch name
+const MAX_SLUG: usize = 40;
+
+/// Where an issue lives, parsed from its web URL
+pub struct IssueRef {
+    repo: github::Repo,
+    pub number: u64,
+}
+
+#[derive(Deserialize)]
+struct Label {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct User {
+    login: String,
+}
+
+#[derive(Deserialize)]
+struct Comment {
+    user: User,
+    body: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct Issue {
+    pub number: u64,
+    pub title: String,
+    body: Option<String>,
+    html_url: String,
+    #[serde(default)]
+    labels: Vec<Label>,
+    #[serde(default)]
+    comments: usize,
+    /// Set when the number belongs to a pull request, which the issues API also serves
+    pull_request: Option<serde_json::Value>,
+    #[serde(skip)]
+    thread: Vec<Comment>,
+}
+
+impl IssueRef {
+    /// `https://github.com/<owner>/<repo>/issues/<n>`
+    pub fn parse(url: &str) -> Result<Self> {
+        let (repo, rest) = github::Repo::from_web_url(url)?;
+        let [kind, number] = &rest[..] else {
+            bail!("`{}` is not a GitHub issue URL (https://github.com/<owner>/<repo>/issues/<number>)", url);
+        };
+        if kind != "issues" {
+            bail!("`{}` is not a GitHub issue URL (https://github.com/<owner>/<repo>/issues/<number>)", url);
+        }
+        let number = number.parse().with_context(|| format!("`{}` is not an issue number", number))?;
+        Ok(IssueRef { repo, number })
+    }
+
+    /// The issue and its first comments; `GITHUB_TOKEN` is used when set, for private repositories
+    pub async fn fetch(&self, agent: &Agent) -> Result<Issue> {
+        let url = self.repo.url(&format!("issues/{}", self.number));
+        let issue = github::send(github::request(agent, Method::GET, &url)).await
+            .with_context(|| format!("Failed to fetch {}#{}", self.repo, self.number))?;
+        let mut issue: Issue = serde_json::from_value(issue)?;
+        if issue.pull_request.is_some() {
+            bail!("{}#{} is a pull request, not an issue", self.repo, self.number);
+        }
+        if issue.comments > 0 {
+            let comments = github::send(github::request(agent, Method::GET, &format!("{}/comments?per_page={}", url, MAX_COMMENTS))).await
+                .context("Failed to fetch the issue's comments")?;
+            issue.thread = serde_json::from_value(comments)?;
+        }
+        Ok(issue)
+    }
+}
+
+impl Issue {
+    /// Everything the model needs to know about the issue; what its authors wrote goes in an
+    /// untrusted-content frame
+    fn task(&self, issue: &IssueRef, untrusted: &mut crate::untrusted::Guard) -> String {
+        let mut text = format!("Title: {}\n", self.title);
+        if !self.labels.is_empty() {
+            text.push_str(&format!("Labels: {}\n", self.labels.iter().map(|l| l.name.as_str()).collect::<Vec<_>>().join(", ")));
+        }
+        text.push_str(&format!("\n{}\n", self.body.as_deref().unwrap_or("(no description)").trim()));
+        for comment in &self.thread {
+            text.push_str(&format!("\nComment by {}:\n{}\n", comment.user.login, comment.body.as_deref().unwrap_or("").trim()));
+        }
+        format!("Resolve GitHub issue #{} of {}, as described below.\n{}", self.number, issue.repo, untrusted.frame(&self.html_url, text.trim_end()))
+    }
+
+    /// `issue-<n>-<title words>`
+    pub fn branch(&self) -> String {
+        let words: Vec<String> = self.title.split(|c: char| !c.is_ascii_alphanumeric())
+            .filter(|w| !w.is_empty())
+            .map(str::to_ascii_lowercase)
+            .collect();
+        let mut slug = String::new();
+        for word in words {
+            if !slug.is_empty() && slug.len() + word.len() + 1 > MAX_SLUG {
+                break;
+            }
+            if !slug.is_empty() {
+                slug.push('-');
+            }
+            slug.push_str(&word);
+        }
+        slug.truncate(MAX_SLUG);
+        match slug.is_empty() {
+            true => format!("issue-{}", self.number),
+            false => format!("issue-{}-{}", self.number, slug),
+        }
+    }
+}
+
+/// Switch to the issue's branch, creating it from the current HEAD unless it exists
+fn checkout(branch: &str) -> Result<()> {
+    let exists = gitdiff::git(&["rev-parse", "--verify", "-q", &format!("refs/heads/{}", branch)]).is_ok();
+    if exists {
+        gitdiff::git(&["switch", branch])?;
+        println!("Switched to the existing branch {}", branch);
+    } else {
+        gitdiff::git(&["switch", "-c", branch])?;
+        println!("Created branch {}", branch);
+    }
+    Ok(())
+}
+
+fn implement_prompt(task: &str) -> String {
+    format!(
+        "{}\n\nImplement the change now with your tools, following the plan. Match the code style around your changes \
+         and add or update tests where the project has them. Finish with a short summary of what you changed.",
+        task)
+}
+
+/// Run the issue-to-PR workflow; `max_iterations` bounds the test fixing stage
+pub async fn run(agent: &mut Agent, url: &str, max_iterations: Option<usize>) -> Result<()> {
+    let issue_ref = IssueRef::parse(url)?;
+    if gitdiff::git(&["rev-parse", "--is-inside-work-tree"]).is_err() {
+        bail!("work-on needs a git repository; run it from a clone of {}", issue_ref.repo);
+    }
+    let issue = issue_ref.fetch(agent).await?;
+    println!("#{} {}\n{}\n", issue.number, issue.title, issue.html_url);
+    let task = issue.task(&issue_ref, &mut agent.untrusted);
+
+    let branch = issue.branch();
+    // The pull request goes back into the branch work starts from
+    let base = match gitdiff::git(&["rev-parse", "--abbrev-ref", "HEAD"]).unwrap_or_default().trim() {
+        "" | "HEAD" => None,
+        current if current == branch => None,
+        current => Some(current.to_string()),
+    };
+    if !agent.tool_ctx.approve(&format!("1/5 Create branch {} for this issue?", branch))? {
+        println!("Stopped before creating a branch");
+        return Ok(());
+    }
+    if agent.tool_ctx.dry_run {
+        println!("Dry run: would switch to branch {}", branch);
+    } else {
+        checkout(&branch)?;
+    }
+
+    if !agent.tool_ctx.approve("2/5 Have the model plan the change?")? {
+        println!("Stopped before planning; the branch is ready for manual work");
+        return Ok(());
+    }
+    match agent.make_plan(&task).await {
+        Ok(Some(plan)) => println!("{}", plan.render()),
+        Ok(None) => bail!("The guardrails held the issue back; work-on stopped"),
+        Err(err) => println!("Continuing without a plan: {:#}", err),
+    }
+
+    if !agent.tool_ctx.approve("3/5 Implement the change? Tool calls still ask as the policy says")? {
+        println!("Stopped before implementing; the plan is saved with the session");
+        return Ok(());
+    }
+    agent.ask(&implement_prompt(&task)).await?;
+
+    match fix::test_command(agent, &std::env::current_dir()?) {
+        Some(command) if agent.tool_ctx.approve(&format!("4/5 Run `{}` and fix failures?", command))? => {
+            fix::run(agent, Some(command), max_iterations, fix::StopConditions::default(), false, false).await?;
+        }
+        Some(_) => println!("Tests skipped"),
+        None => println!("4/5 No project manifest found; tests skipped"),
+    }
+
+    if !agent.tool_ctx.approve("5/5 Write a pull request description?")? {
+        println!("Stopped before the pull request description");
+        return Ok(());
+    }
+    let base = match base {
+        Some(base) => base,
+        None => pr::default_base()?,
+    };
+    let mut description = pr::describe(agent, &base, true).await?;
+    description.closes = Some(issue.number);
+    let dir = paths::data_dir().join("work-on");
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(format!("{}-{}-{}.md", issue_ref.repo.owner, issue_ref.repo.name, issue.number));
+    fs::write(&path, description.body())?;
+    println!("# {}\n\n{}\n", description.title, description.body());
+    println!("Saved to {}; once the changes are committed and pushed:", path.display());
+    println!("  gh pr create --base {} --head {} --title {:?} --body-file {}", base, branch, description.title, path.display());
+    Ok(())
+}
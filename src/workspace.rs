@@ -0,0 +1,143 @@
+use std::{fs, path::{Path, PathBuf}};
+use anyhow::{Result, Context, bail};
+
+/// A package in a cargo workspace
+#[derive(Clone)]
+pub struct Member {
+    pub name: String,
+    /// Directory containing the member's Cargo.toml
+    pub path: PathBuf,
+}
+
+/// The cargo workspace (or lone package) enclosing a directory
+pub struct Workspace {
+    pub root: PathBuf,
+    pub members: Vec<Member>,
+}
+
+/// Cargo subcommands that accept `-p <package>`
+const PACKAGE_SUBCOMMANDS: &[&str] = &["build", "b", "check", "c", "test", "t", "clippy", "run", "r", "bench", "doc", "d", "fmt", "tree", "clean", "fix"];
+
+fn read_manifest(dir: &Path) -> Option<toml::Table> {
+    let text = fs::read_to_string(dir.join("Cargo.toml")).ok()?;
+    toml::from_str(&text).ok()
+}
+
+fn package_name(manifest: &toml::Table) -> Option<String> {
+    manifest.get("package")?.get("name")?.as_str().map(String::from)
+}
+
+impl Workspace {
+    /// Find the outermost manifest with `[workspace]` above `start`, else the nearest package
+    pub fn detect(start: &Path) -> Option<Self> {
+        let mut package = None;
+        for dir in start.ancestors() {
+            let Some(manifest) = read_manifest(dir) else { continue };
+            if let Some(ws) = manifest.get("workspace").and_then(|w| w.as_table()) {
+                return Some(Workspace::from_table(dir, &manifest, ws));
+            }
+            if package.is_none() {
+                package = package_name(&manifest).map(|name| Workspace {
+                    root: dir.to_path_buf(),
+                    members: vec![Member { name, path: dir.to_path_buf() }],
+                });
+            }
+        }
+        package
+    }
+
+    fn from_table(root: &Path, manifest: &toml::Table, ws: &toml::Table) -> Self {
+        let patterns = |key: &str| -> Vec<String> {
+            ws.get(key).and_then(|v| v.as_array()).into_iter().flatten()
+                .filter_map(|v| v.as_str().map(String::from))
+                .collect()
+        };
+        let excluded: Vec<PathBuf> = patterns("exclude").iter().map(|p| root.join(p)).collect();
+        let mut dirs = Vec::new();
+        for pattern in patterns("members") {
+            let full = root.join(&pattern).to_string_lossy().into_owned();
+            match glob::glob(&full) {
+                Ok(paths) => dirs.extend(paths.flatten()),
+                Err(_) => dirs.push(root.join(&pattern)),
+            }
+        }
+        // A root manifest can be both a workspace and a package
+        if manifest.contains_key("package") {
+            dirs.insert(0, root.to_path_buf());
+        }
+        let mut members: Vec<Member> = dirs.into_iter()
+            .filter(|d| !excluded.contains(d))
+            .filter_map(|d| read_manifest(&d).and_then(|m| package_name(&m)).map(|name| Member { name, path: d }))
+            .collect();
+        members.dedup_by(|a, b| a.name == b.name);
+        Workspace { root: root.to_path_buf(), members }
+    }
+
+    pub fn member(&self, name: &str) -> Result<&Member> {
+        self.members.iter().find(|m| m.name == name).with_context(|| {
+            let names: Vec<_> = self.members.iter().map(|m| m.name.as_str()).collect();
+            format!("No package `{}` in {} (members: {})", name, self.root.display(), names.join(", "))
+        })
+    }
+
+    /// Member list, one `name (relative path)` per line
+    pub fn listing(&self) -> String {
+        self.members.iter()
+            .map(|m| format!("{} ({})", m.name, m.path.strip_prefix(&self.root).unwrap_or(&m.path).display()))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Workspace layout for the system prompt
+    pub fn describe(&self) -> String {
+        let list: Vec<String> = self.listing().lines().map(|l| format!("- {}", l)).collect();
+        format!("Cargo workspace at {} with members:\n{}", self.root.display(), list.join("\n"))
+    }
+}
+
+impl Member {
+    /// System prompt addition when the session is scoped with `--package`
+    pub fn scope_instruction(&self) -> String {
+        format!("The user is working on package `{}` only. Keep file changes inside {} and pass `-p {}` to cargo.", self.name, self.path.display(), self.name)
+    }
+}
+
+/// Add `-p <package>` to a cargo invocation that doesn't already select packages
+pub fn scope_command(command: &str, package: &str) -> String {
+    let words: Vec<&str> = command.split_whitespace().collect();
+    let selects = words.iter().any(|w| matches!(*w, "-p" | "--package" | "--workspace" | "--all") || w.starts_with("--package="));
+    match words.as_slice() {
+        ["cargo", sub, ..] if PACKAGE_SUBCOMMANDS.contains(sub) && !selects => {
+            // Insert after the subcommand so trailing `-- args` stay intact
+            let after_cargo = command.find("cargo").unwrap_or(0) + "cargo".len();
+            let at = command[after_cargo..].find(sub).map(|i| after_cargo + i + sub.len()).unwrap_or(command.len());
+            format!("{} -p {}{}", &command[..at], package, &command[at..])
+        }
+        _ => command.to_string(),
+    }
+}
+
+/// Fail early when `--package` names something that isn't a workspace member
+pub fn resolve(package: &str) -> Result<(Workspace, Member)> {
+    let cwd = std::env::current_dir()?;
+    let Some(ws) = Workspace::detect(&cwd) else {
+        bail!("--package {} given, but {} is not inside a cargo project", package, cwd.display());
+    };
+    let member = ws.member(package)?.clone();
+    Ok((ws, member))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::scope_command;
+
+    #[test]
+    fn cargo_commands_are_scoped_to_the_package() {
+        assert_eq!(scope_command("cargo test", "core"), "cargo test -p core");
+        assert_eq!(scope_command("cargo t --release -- --nocapture", "core"), "cargo t -p core --release -- --nocapture");
+        assert_eq!(scope_command("cargo run -- test", "cli"), "cargo run -p cli -- test");
+        for unchanged in ["cargo test -p other", "cargo test --package=other", "cargo build --workspace", "cargo publish", "make test", "cargo"] {
+            assert_eq!(scope_command(unchanged, "core"), unchanged);
+        }
+    }
+}